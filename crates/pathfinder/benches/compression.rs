@@ -0,0 +1,95 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use pathfinder_lib::core::GasPrice;
+use pathfinder_lib::rpc::v01::types::reply::StateUpdate;
+use pathfinder_lib::sequencer::reply::{Block, StateUpdate as SequencerStateUpdate};
+use pathfinder_lib::storage::{
+    CanonicalBlocksTable, CompressionConfig, StarknetBlock, StarknetBlocksTable,
+    StarknetStateUpdatesTable, StarknetTransactionsTable, Storage,
+};
+
+/// Inserts a block's transactions and a state update the same way L2 sync does, sharing a
+/// single compressor between them.
+fn insert_block(
+    tx: &rusqlite::Transaction<'_>,
+    compression: CompressionConfig,
+    block: &Block,
+    state_update: &StateUpdate,
+) {
+    let block_hash = state_update.block_hash.unwrap();
+    let starknet_block = StarknetBlock {
+        number: block.block_number,
+        hash: block_hash,
+        parent_hash: block.parent_block_hash,
+        root: block.state_root,
+        timestamp: block.timestamp,
+        gas_price: block.gas_price.unwrap_or(GasPrice::ZERO),
+        sequencer_address: block.sequencer_address,
+        transaction_count: block.transactions.len(),
+        event_count: block
+            .transaction_receipts
+            .iter()
+            .map(|receipt| receipt.events.len())
+            .sum(),
+    };
+    StarknetBlocksTable::insert(tx, &starknet_block, None).unwrap();
+    CanonicalBlocksTable::insert(tx, block.block_number, block_hash).unwrap();
+
+    let mut compressor = compression.compressor().unwrap();
+    StarknetStateUpdatesTable::insert_with_compressor(
+        tx,
+        &mut compressor,
+        block_hash,
+        state_update,
+    )
+    .unwrap();
+
+    let transaction_data = block
+        .transactions
+        .iter()
+        .cloned()
+        .zip(block.transaction_receipts.iter().cloned())
+        .collect::<Vec<_>>();
+    StarknetTransactionsTable::upsert_with_compressor(
+        tx,
+        &mut compressor,
+        block_hash,
+        block.block_number,
+        &transaction_data,
+    )
+    .unwrap();
+}
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let block: Block = serde_json::from_str(include_str!(
+        "../fixtures/sequencer/0.9.0/block/231579.json"
+    ))
+    .unwrap();
+    let state_update: SequencerStateUpdate = serde_json::from_str(include_str!(
+        "../fixtures/sequencer/0.9.1/state_update/315700.json"
+    ))
+    .unwrap();
+    let state_update: StateUpdate = state_update.into();
+
+    let s = Storage::in_memory().unwrap();
+    let mut connection = s.writer().unwrap();
+
+    let mut group = c.benchmark_group("sync insert");
+    group.throughput(Throughput::Elements(block.transactions.len() as u64));
+    for level in [3, 10] {
+        let compression = CompressionConfig {
+            level,
+            ..Default::default()
+        };
+        group.bench_with_input(BenchmarkId::new("level", level), &compression, |b, c| {
+            b.iter(|| {
+                // Dropping the transaction rolls the inserts back for the next iteration.
+                let tx = connection.transaction().unwrap();
+                insert_block(&tx, *c, black_box(&block), black_box(&state_update));
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,96 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use pathfinder_lib::core::GasPrice;
+use pathfinder_lib::sequencer::reply::Block;
+use pathfinder_lib::storage::{
+    CanonicalBlocksTable, CompressionConfig, StarknetBlock, StarknetBlocksTable,
+    StarknetEventFilter, StarknetEventsTable, StarknetTransactionsTable, Storage,
+};
+
+/// Inserts the block with its transactions and events.
+fn insert_block(tx: &rusqlite::Transaction<'_>, block: &Block) {
+    let block_hash = block.block_hash;
+    let starknet_block = StarknetBlock {
+        number: block.block_number,
+        hash: block_hash,
+        parent_hash: block.parent_block_hash,
+        root: block.state_root,
+        timestamp: block.timestamp,
+        gas_price: block.gas_price.unwrap_or(GasPrice::ZERO),
+        sequencer_address: block.sequencer_address,
+        transaction_count: block.transactions.len(),
+        event_count: block
+            .transaction_receipts
+            .iter()
+            .map(|receipt| receipt.events.len())
+            .sum(),
+    };
+    StarknetBlocksTable::insert(tx, &starknet_block, None).unwrap();
+    CanonicalBlocksTable::insert(tx, block.block_number, block_hash).unwrap();
+
+    let transaction_data = block
+        .transactions
+        .iter()
+        .cloned()
+        .zip(block.transaction_receipts.iter().cloned())
+        .collect::<Vec<_>>();
+    StarknetTransactionsTable::upsert_with_compressor(
+        tx,
+        &mut CompressionConfig::default().compressor().unwrap(),
+        block_hash,
+        block.block_number,
+        &transaction_data,
+    )
+    .unwrap();
+}
+
+/// Reads all events of a block, with keys decoded from the `key_bytes` column and from the
+/// base64 encoded `keys` of events stored before it.
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let block: Block = serde_json::from_str(include_str!(
+        "../fixtures/sequencer/0.9.0/block/231579.json"
+    ))
+    .unwrap();
+    let events = block
+        .transaction_receipts
+        .iter()
+        .map(|receipt| receipt.events.len())
+        .sum::<usize>();
+
+    let s = Storage::in_memory().unwrap();
+    let mut connection = s.writer().unwrap();
+    let tx = connection.transaction().unwrap();
+    insert_block(&tx, &block);
+
+    let filter = StarknetEventFilter {
+        from_block: None,
+        to_block: None,
+        contract_address: None,
+        keys: vec![],
+        data_filter: vec![],
+        page_size: 1024,
+        page_number: 0,
+        detect_last_page: false,
+        count_pages: false,
+        only_accepted: false,
+    };
+
+    let mut group = c.benchmark_group("get_events");
+    group.throughput(Throughput::Elements(events as u64));
+    for format in ["key_bytes", "base64"] {
+        if format == "base64" {
+            tx.execute("UPDATE starknet_events SET key_bytes = NULL", [])
+                .unwrap();
+        }
+
+        group.bench_with_input(BenchmarkId::new("keys", format), &filter, |b, filter| {
+            b.iter(|| {
+                let page = StarknetEventsTable::get_events(&tx, black_box(filter)).unwrap();
+                assert_eq!(page.events.len(), events);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -46,7 +46,7 @@ pub fn chunked_inserts(
 
 pub fn criterion_benchmark(c: &mut Criterion) {
     let s = pathfinder_lib::storage::Storage::in_memory().unwrap();
-    let mut connection = s.connection().unwrap();
+    let mut connection = s.writer().unwrap();
 
     c.bench_function("merkle_tree of 1000", |b| {
         b.iter_batched_ref(
@@ -26,7 +26,7 @@ async fn main() {
     use pathfinder_lib::storage::{JournalMode, StarknetBlocksTable, Storage};
 
     let storage = Storage::migrate(path, JournalMode::WAL).unwrap();
-    let mut connection = storage.connection().unwrap();
+    let mut connection = storage.writer().unwrap();
 
     let (chain, work_todo) = {
         let tx = connection.transaction().unwrap();
@@ -39,7 +39,7 @@ fn main() {
 
     // in general one does not want to do the full vacuum because it's going to take a long time
     if false {
-        let conn = storage.connection().unwrap();
+        let conn = storage.writer().unwrap();
 
         let vacuum_started = std::time::Instant::now();
         let vacuum_ret = conn.execute("VACUUM", []).expect("vacuum failed");
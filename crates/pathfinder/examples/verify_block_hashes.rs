@@ -57,7 +57,7 @@ fn main() -> anyhow::Result<()> {
             block_number: block.number,
             gas_price: Some(block.gas_price),
             parent_block_hash,
-            sequencer_address: Some(block.sequencer_address),
+            sequencer_address: block.sequencer_address,
             state_root: block.root,
             status: Status::AcceptedOnL1,
             timestamp: block.timestamp,
@@ -19,6 +19,10 @@ async fn main() -> anyhow::Result<()> {
         std::env::set_var("RUST_LOG", "info");
     }
 
+    if std::env::args_os().nth(1).as_deref() == Some("export-events".as_ref()) {
+        return export_events(std::env::args_os().skip(1));
+    }
+
     setup_tracing();
 
     let config =
@@ -74,9 +78,18 @@ Hint: Make sure the provided ethereum.url and ethereum.password are good.",
         false => JournalMode::Rollback,
         true => JournalMode::WAL,
     };
-    let storage = Storage::migrate(database_path.clone(), journal_mode).unwrap();
+    let storage = Storage::migrate(database_path.clone(), journal_mode)
+        .unwrap()
+        .with_compression(config.compression)
+        .with_readers(config.database_readers)
+        .context("Creating database reader pool")?;
+    pathfinder_lib::storage::set_slow_query_threshold(config.log_slow_queries);
     info!(location=?database_path, "Database migrated.");
-    verify_database_chain(&storage, starknet_chain).context("Verifying database")?;
+    storage
+        .bind_chain(starknet_chain)
+        .context("Verifying database")?;
+    check_database_consistency(&storage, config.consistency_check)
+        .context("Checking database consistency")?;
 
     let sequencer = match config.sequencer_url {
         Some(url) => {
@@ -91,6 +104,21 @@ Hint: Make sure the provided ethereum.url and ethereum.password are good.",
         }
         None => sequencer::Client::new(starknet_chain).unwrap(),
     };
+    run_database_maintenance(&storage, &sequencer, config.database_maintenance)
+        .await
+        .context("Running database maintenance")?;
+    let (sequencer, archive_handle) = match config.raw_block_archive {
+        config::RawBlockArchive::Off => (sequencer, None),
+        retention => {
+            let retain = match retention {
+                config::RawBlockArchive::Latest(count) => Some(count),
+                _ => None,
+            };
+            let (sender, receiver) = tokio::sync::mpsc::channel(16);
+            let handle = tokio::spawn(state::archive::archive(storage.clone(), receiver, retain));
+            (sequencer.with_raw_block_archive(sender), Some(handle))
+        }
+    };
     let sync_state = Arc::new(state::SyncState::default());
     let pending_state = state::PendingData::default();
     let pending_interval = match config.poll_pending {
@@ -127,7 +155,9 @@ Hint: Make sure the provided ethereum.url and ethereum.password are good.",
 
     let api = rpc::v01::api::RpcApi::new(storage, sequencer, starknet_chain, sync_state)
         .with_call_handling(call_handle)
-        .with_eth_gas_price(shared);
+        .with_eth_gas_price(shared)
+        .with_max_event_keys(config.rpc_max_event_keys)
+        .with_max_event_offset(config.rpc_max_event_offset);
     let api = match config.poll_pending {
         true => api.with_pending_data(pending_state),
         false => api,
@@ -135,6 +165,7 @@ Hint: Make sure the provided ethereum.url and ethereum.password are good.",
 
     let (rpc_handle, local_addr) = rpc::RpcServer::new(config.http_rpc_addr, api)
         .with_middleware(RpcMetricsMiddleware)
+        .with_max_batch_size(config.rpc_max_batch_size)
         .run()
         .await
         .context("Starting the RPC server")?;
@@ -166,6 +197,15 @@ Hint: Make sure the provided ethereum.url and ethereum.password are good.",
             // This handle returns () so its not very useful.
             tracing::error!("RPC server process ended unexpected");
         }
+        Some(result) = async { match archive_handle {
+            Some(handle) => Some(handle.await),
+            None => None,
+        } } => {
+            match result {
+                Ok(task_result) => tracing::error!("Raw block archive ended unexpected with: {:?}", task_result),
+                Err(err) => tracing::error!("Raw block archive ended unexpected; failed to join task handle: {:?}", err),
+            }
+        }
         result = update_handle => {
             match result {
                 Ok(_) => tracing::error!("Release monitoring process ended unexpectedly"),
@@ -178,27 +218,263 @@ Hint: Make sure the provided ethereum.url and ethereum.password are good.",
 }
 
 /// Verifies that the database matches the expected chain; throws an error if it does not.
-fn verify_database_chain(storage: &Storage, expected: core::Chain) -> anyhow::Result<()> {
-    use pathfinder_lib::storage::StarknetBlocksTable;
+/// Cross-checks the database tables, logging any inconsistencies and truncating the database back
+/// to its last consistent block if repair is requested.
+fn check_database_consistency(
+    storage: &Storage,
+    mode: config::ConsistencyCheck,
+) -> anyhow::Result<()> {
+    if mode == config::ConsistencyCheck::Off {
+        return Ok(());
+    }
 
-    let mut connection = storage.connection().context("Create database connection")?;
+    let mut connection = storage.writer().context("Create database connection")?;
     let transaction = connection
         .transaction()
         .context("Create database transaction")?;
 
-    let db_chain = match StarknetBlocksTable::get_chain(&transaction)
-        .context("Get chain from genesis block in the DB")?
-    {
-        Some(chain) => chain,
+    let report = pathfinder_lib::storage::consistency_check(&transaction)
+        .context("Run consistency check")?;
+    if report.is_consistent() {
+        info!("Database is consistent.");
+        return Ok(());
+    }
+
+    tracing::warn!(?report, "Database is inconsistent.");
+    if mode == config::ConsistencyCheck::Repair {
+        report.repair(&transaction).context("Repair database")?;
+        transaction.commit().context("Commit database repair")?;
+        info!(
+            first_inconsistent_block=?report.first_inconsistent_block(),
+            "Database repaired."
+        );
+    }
+
+    Ok(())
+}
+
+/// Runs the requested maintenance task. The version backfill commits every batch of blocks, so
+/// that it resumes where it stopped if interrupted.
+async fn run_database_maintenance(
+    storage: &Storage,
+    sequencer: &sequencer::Client,
+    mode: config::DatabaseMaintenance,
+) -> anyhow::Result<()> {
+    use pathfinder_lib::storage::StarknetVersionsTable;
+
+    match mode {
+        config::DatabaseMaintenance::Off => Ok(()),
+        config::DatabaseMaintenance::PruneVersions => {
+            let mut connection = storage.writer().context("Create database connection")?;
+            let transaction = connection
+                .transaction()
+                .context("Create database transaction")?;
+            let pruned = StarknetVersionsTable::prune_unreferenced(&transaction)
+                .context("Prune versions")?;
+            transaction.commit().context("Commit pruned versions")?;
+            info!(%pruned, "Pruned unreferenced versions.");
+            Ok(())
+        }
+        config::DatabaseMaintenance::BackfillVersions => {
+            let storage = storage.clone();
+            let sequencer = sequencer.clone();
+            let runtime = tokio::runtime::Handle::current();
+            tokio::task::spawn_blocking(move || backfill_versions(&storage, &sequencer, &runtime))
+                .await
+                .context("Version backfill panicked")?
+        }
+    }
+}
+
+fn backfill_versions(
+    storage: &Storage,
+    sequencer: &sequencer::Client,
+    runtime: &tokio::runtime::Handle,
+) -> anyhow::Result<()> {
+    use pathfinder_lib::sequencer::{reply::MaybePendingBlock, ClientApi};
+    use pathfinder_lib::storage::{backfill_block_versions, StarknetBlocksTable};
+
+    const BATCH_SIZE: u64 = 1000;
+
+    let mut connection = storage.writer().context("Create database connection")?;
+    let latest = {
+        let transaction = connection
+            .transaction()
+            .context("Create database transaction")?;
+        StarknetBlocksTable::get_latest_number(&transaction).context("Read latest block")?
+    };
+    let latest = match latest {
+        Some(latest) => latest,
         None => return Ok(()),
     };
 
+    let fetch = |number| match runtime.block_on(sequencer.block(core::BlockId::Number(number))) {
+        Ok(MaybePendingBlock::Block(block)) => block.starknet_version,
+        Ok(MaybePendingBlock::Pending(_)) => None,
+        Err(error) => {
+            tracing::warn!(block=%number, %error, "Fetching block version failed");
+            None
+        }
+    };
+
+    let (mut updated, mut unavailable) = (0, 0);
+    let mut from = core::StarknetBlockNumber::GENESIS;
+    while from <= latest {
+        let to = from + (BATCH_SIZE - 1);
+        let to = if to > latest { latest } else { to };
+
+        let transaction = connection
+            .transaction()
+            .context("Create database transaction")?;
+        let backfill = backfill_block_versions(&transaction, fetch, from..=to)
+            .context("Backfill block versions")?;
+        transaction.commit().context("Commit block versions")?;
+
+        updated += backfill.updated;
+        unavailable += backfill.unavailable;
+        info!(
+            "Backfilling block versions {:.1}% complete",
+            100.0 * (to.get() + 1) as f64 / (latest.get() + 1) as f64
+        );
+        from = to + 1;
+    }
+
+    info!(%updated, %unavailable, "Block versions backfilled.");
+    Ok(())
+}
+
+/// Exports the events of an existing database, without starting the node.
+///
+/// Usage: `pathfinder export-events --database FILE [--from N] [--to N] [--contract ADDRESS]
+/// [--format csv|jsonl] [--out FILE]`
+/// Runs the `export-events` command.
+///
+/// The database is opened read-only, so the command can run next to a node using it. Logs are
+/// written to stderr, so that they do not mix with events exported to stdout.
+fn export_events(args: impl Iterator<Item = std::ffi::OsString>) -> anyhow::Result<()> {
+    use clap::Arg;
+    use pathfinder_lib::core::{ContractAddress, StarknetBlockNumber};
+    use pathfinder_lib::storage::{ExportFormat, StarknetEventFilter, StarknetEventsTable};
+    use stark_hash::StarkHash;
+
+    let args = clap::Command::new("export-events")
+        .about("Writes the events of the database to a CSV or JSON lines file")
+        .arg(
+            Arg::new("database")
+                .long("database")
+                .help("Path to the database file")
+                .value_name("FILE")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("from")
+                .long("from")
+                .help("First block to export")
+                .value_name("BLOCK NUMBER")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("to")
+                .long("to")
+                .help("Last block to export")
+                .value_name("BLOCK NUMBER")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("contract")
+                .long("contract")
+                .help("Only export events emitted by this contract")
+                .value_name("ADDRESS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .help("Output format")
+                .takes_value(true)
+                .possible_values(["csv", "jsonl"])
+                .default_value("csv"),
+        )
+        .arg(
+            Arg::new("out")
+                .long("out")
+                .help("Output file, standard output if not set")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .get_matches_from(args);
+
+    let block = |name| -> anyhow::Result<Option<StarknetBlockNumber>> {
+        args.value_of(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .ok()
+                    .and_then(StarknetBlockNumber::new)
+                    .with_context(|| format!("Invalid block number for --{name}: {value}"))
+            })
+            .transpose()
+    };
+    let contract_address = args
+        .value_of("contract")
+        .map(|value| {
+            StarkHash::from_hex_str(value)
+                .ok()
+                .and_then(ContractAddress::new)
+                .with_context(|| format!("Invalid contract address: {value}"))
+        })
+        .transpose()?;
+    let filter = StarknetEventFilter {
+        from_block: block("from")?,
+        to_block: block("to")?,
+        contract_address,
+        keys: vec![],
+        data_filter: vec![],
+        page_size: 0,
+        page_number: 0,
+        detect_last_page: false,
+        count_pages: false,
+        only_accepted: false,
+    };
+    let format = match args.value_of("format") {
+        Some("jsonl") => ExportFormat::JsonLines,
+        _ => ExportFormat::Csv,
+    };
+
+    let database_path = std::path::PathBuf::from(args.value_of("database").unwrap());
     anyhow::ensure!(
-        db_chain == expected,
-        "Database ({}) does not much the expected network ({})",
-        db_chain,
-        expected
+        database_path.exists(),
+        "Database {} does not exist",
+        database_path.display()
     );
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_target(false)
+        .with_writer(std::io::stderr)
+        .compact()
+        .init();
+
+    let mut connection =
+        pathfinder_lib::storage::open_read_only(&database_path).context("Opening database")?;
+    let tx = connection.transaction().context("Creating transaction")?;
+
+    let count = match args.value_of("out") {
+        Some(path) => {
+            let file = std::fs::File::create(path)
+                .with_context(|| format!("Creating output file {path}"))?;
+            StarknetEventsTable::export(&tx, &filter, std::io::BufWriter::new(file), format)
+        }
+        None => StarknetEventsTable::export(
+            &tx,
+            &filter,
+            std::io::BufWriter::new(std::io::stdout().lock()),
+            format,
+        ),
+    }
+    .context("Exporting events")?;
+
+    info!(%count, "Exported events.");
 
     Ok(())
 }
@@ -29,6 +29,26 @@ pub enum ConfigOption {
     EnableSQLiteWriteAheadLogging,
     /// Enable pending polling.
     PollPending,
+    /// The zstd compression level used for database writes.
+    CompressionLevel,
+    /// Enables long distance matching for database writes.
+    CompressionLongWindow,
+    /// Whether to check the database for consistency at startup.
+    ConsistencyCheck,
+    /// Database maintenance performed at startup.
+    DatabaseMaintenance,
+    /// Which blocks to archive as raw sequencer JSON.
+    RawBlockArchive,
+    /// The number of database connections used to serve queries.
+    DatabaseReaders,
+    /// Storage writes slower than this many milliseconds are logged.
+    LogSlowQueries,
+    /// The maximum number of requests in a v0.2 JSON-RPC batch.
+    RpcMaxBatchSize,
+    /// The maximum number of keys in a `starknet_getEvents` filter.
+    RpcMaxEventKeys,
+    /// The maximum number of events `starknet_getEvents` skips to reach the requested page.
+    RpcMaxEventOffset,
     /// Enables and sets the monitoring endpoint
     MonitorAddress,
     /// Chooses Integration network instead of testnet.
@@ -48,6 +68,16 @@ impl Display for ConfigOption {
                 f.write_str("Enable SQLite write-ahead logging")
             }
             ConfigOption::PollPending => f.write_str("Enable pending block polling"),
+            ConfigOption::CompressionLevel => f.write_str("Database compression level"),
+            ConfigOption::CompressionLongWindow => f.write_str("Database compression long window"),
+            ConfigOption::ConsistencyCheck => f.write_str("Database consistency check"),
+            ConfigOption::DatabaseMaintenance => f.write_str("Database maintenance"),
+            ConfigOption::RawBlockArchive => f.write_str("Raw block archive"),
+            ConfigOption::DatabaseReaders => f.write_str("Database reader connections"),
+            ConfigOption::LogSlowQueries => f.write_str("Slow database write threshold"),
+            ConfigOption::RpcMaxBatchSize => f.write_str("Maximum JSON-RPC batch size"),
+            ConfigOption::RpcMaxEventKeys => f.write_str("Maximum event filter keys"),
+            ConfigOption::RpcMaxEventOffset => f.write_str("Maximum events page offset"),
             ConfigOption::MonitorAddress => f.write_str("Pathfinder monitoring address"),
             ConfigOption::Integration => f.write_str("Select integration network"),
         }
@@ -63,6 +93,42 @@ pub struct EthereumConfig {
     pub password: Option<String>,
 }
 
+/// The database consistency check performed at startup, see [crate::storage::consistency_check].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsistencyCheck {
+    /// The database is not checked.
+    Off,
+    /// Inconsistencies are logged.
+    Check,
+    /// The database is truncated back to its last consistent block.
+    Repair,
+}
+
+/// The database maintenance performed at startup, before syncing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatabaseMaintenance {
+    /// No maintenance is performed.
+    Off,
+    /// Fetches the sequencer version of blocks stored without one, see
+    /// [crate::storage::backfill_block_versions].
+    BackfillVersions,
+    /// Deletes the versions no block references, see
+    /// [crate::storage::StarknetVersionsTable::prune_unreferenced].
+    PruneVersions,
+}
+
+/// Which blocks are archived as the raw JSON served by the sequencer, see
+/// [crate::storage::RawBlocksTable].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawBlockArchive {
+    /// No blocks are archived.
+    Off,
+    /// All blocks are archived.
+    All,
+    /// Only the given number of latest blocks are kept.
+    Latest(std::num::NonZeroU64),
+}
+
 /// Node configuration options.
 #[derive(Debug, PartialEq, Eq)]
 pub struct Configuration {
@@ -80,6 +146,24 @@ pub struct Configuration {
     pub sqlite_wal: bool,
     /// Enable pending polling.
     pub poll_pending: bool,
+    /// Compression settings used for database writes.
+    pub compression: crate::storage::CompressionConfig,
+    /// The database consistency check performed at startup.
+    pub consistency_check: ConsistencyCheck,
+    /// The database maintenance performed at startup.
+    pub database_maintenance: DatabaseMaintenance,
+    /// Which blocks to archive as raw sequencer JSON.
+    pub raw_block_archive: RawBlockArchive,
+    /// The number of database connections used to serve queries.
+    pub database_readers: std::num::NonZeroU32,
+    /// Storage writes slower than this are logged, see [crate::storage::set_slow_query_threshold].
+    pub log_slow_queries: Option<std::time::Duration>,
+    /// The maximum number of requests in a v0.2 JSON-RPC batch.
+    pub rpc_max_batch_size: std::num::NonZeroUsize,
+    /// The maximum number of keys in a `starknet_getEvents` filter.
+    pub rpc_max_event_keys: usize,
+    /// The maximum number of events `starknet_getEvents` skips to reach the requested page.
+    pub rpc_max_event_offset: usize,
     /// The node's monitoring address and port.
     pub monitoring_addr: Option<SocketAddr>,
     /// Select integration network.
@@ -1,7 +1,10 @@
 //! Provides [ConfigBuilder] which is a convenient and safe way of collecting
 //! configuration parameters from various sources and combining them into one.
 
-use crate::config::{ConfigOption, Configuration, EthereumConfig};
+use crate::config::{
+    ConfigOption, Configuration, ConsistencyCheck, DatabaseMaintenance, EthereumConfig,
+    RawBlockArchive,
+};
 use reqwest::Url;
 use std::{collections::HashMap, net::SocketAddr, path::PathBuf, str::FromStr};
 
@@ -163,6 +166,177 @@ Hint: Register your own account or run your own Ethereum node and put the real U
             None => Ok(false),
         }?;
 
+        let compression_level = match self.take(ConfigOption::CompressionLevel) {
+            Some(level) => {
+                let parsed = level
+                    .parse::<i32>()
+                    .ok()
+                    .filter(|level| zstd::compression_level_range().contains(level));
+                match parsed {
+                    Some(level) => Ok(level),
+                    None => Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid value '{}' for compression level option, must be an integer in {:?}",
+                            level,
+                            zstd::compression_level_range()
+                        ),
+                    )),
+                }
+            }
+            None => Ok(crate::storage::CompressionConfig::default().level),
+        }?;
+
+        let compression_long_window = match self.take(ConfigOption::CompressionLongWindow) {
+            Some(enable) => {
+                let enable = enable.to_lowercase();
+                match enable.as_str() {
+                    "true" => Ok(true),
+                    "false" => Ok(false),
+                    _ => Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid value '{}' for compression long window option, must be true|false",
+                            enable
+                        ),
+                    )),
+                }
+            }
+            None => Ok(crate::storage::CompressionConfig::default().long_window),
+        }?;
+
+        let compression = crate::storage::CompressionConfig {
+            level: compression_level,
+            long_window: compression_long_window,
+        };
+
+        let consistency_check = match self.take(ConfigOption::ConsistencyCheck) {
+            Some(mode) => {
+                let mode = mode.to_lowercase();
+                match mode.as_str() {
+                    "off" => Ok(ConsistencyCheck::Off),
+                    "check" => Ok(ConsistencyCheck::Check),
+                    "repair" => Ok(ConsistencyCheck::Repair),
+                    _ => Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid value '{}' for consistency check option, must be off|check|repair",
+                            mode
+                        ),
+                    )),
+                }
+            }
+            None => Ok(ConsistencyCheck::Off),
+        }?;
+
+        let database_maintenance = match self.take(ConfigOption::DatabaseMaintenance) {
+            Some(mode) => {
+                let mode = mode.to_lowercase();
+                match mode.as_str() {
+                    "off" => Ok(DatabaseMaintenance::Off),
+                    "backfill-versions" => Ok(DatabaseMaintenance::BackfillVersions),
+                    "prune-versions" => Ok(DatabaseMaintenance::PruneVersions),
+                    _ => Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid value '{}' for database maintenance option, must be off|backfill-versions|prune-versions",
+                            mode
+                        ),
+                    )),
+                }
+            }
+            None => Ok(DatabaseMaintenance::Off),
+        }?;
+
+        let raw_block_archive = match self.take(ConfigOption::RawBlockArchive) {
+            Some(mode) => {
+                let mode = mode.to_lowercase();
+                match mode.as_str() {
+                    "off" => Ok(RawBlockArchive::Off),
+                    "all" => Ok(RawBlockArchive::All),
+                    other => match other.parse::<std::num::NonZeroU64>() {
+                        Ok(count) => Ok(RawBlockArchive::Latest(count)),
+                        Err(_) => Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            format!(
+                                "Invalid value '{}' for raw block archive option, must be off|all or a positive number of blocks",
+                                mode
+                            ),
+                        )),
+                    },
+                }
+            }
+            None => Ok(RawBlockArchive::Off),
+        }?;
+
+        let database_readers = match self.take(ConfigOption::DatabaseReaders) {
+            Some(count) => count.parse::<std::num::NonZeroU32>().map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "Invalid value '{}' for database readers option, must be a positive integer",
+                        count
+                    ),
+                )
+            }),
+            None => Ok(crate::storage::Storage::DEFAULT_READERS),
+        }?;
+
+        let log_slow_queries = match self.take(ConfigOption::LogSlowQueries) {
+            Some(millis) => millis
+                .parse::<u64>()
+                .map(|millis| Some(std::time::Duration::from_millis(millis)))
+                .map_err(|_| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid value '{}' for log slow queries option, must be a number of milliseconds",
+                            millis
+                        ),
+                    )
+                }),
+            None => Ok(None),
+        }?;
+
+        let rpc_max_batch_size = match self.take(ConfigOption::RpcMaxBatchSize) {
+            Some(size) => size.parse::<std::num::NonZeroUsize>().map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "Invalid value '{}' for RPC max batch size option, must be a positive integer",
+                        size
+                    ),
+                )
+            }),
+            None => Ok(crate::rpc::RpcServer::DEFAULT_MAX_BATCH_SIZE),
+        }?;
+
+        let rpc_max_event_keys = match self.take(ConfigOption::RpcMaxEventKeys) {
+            Some(count) => count.parse::<usize>().map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "Invalid value '{}' for RPC max event keys option, must be a number",
+                        count
+                    ),
+                )
+            }),
+            None => Ok(crate::storage::StarknetEventsTable::DEFAULT_MAX_KEYS),
+        }?;
+
+        let rpc_max_event_offset = match self.take(ConfigOption::RpcMaxEventOffset) {
+            Some(offset) => offset.parse::<usize>().map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "Invalid value '{}' for RPC max event offset option, must be a number",
+                        offset
+                    ),
+                )
+            }),
+            None => Ok(crate::storage::StarknetEventsTable::DEFAULT_MAX_OFFSET),
+        }?;
+
         Ok(Configuration {
             ethereum: EthereumConfig {
                 url: eth_url,
@@ -174,6 +348,15 @@ Hint: Register your own account or run your own Ethereum node and put the real U
             python_subprocesses,
             sqlite_wal,
             poll_pending,
+            compression,
+            consistency_check,
+            database_maintenance,
+            raw_block_archive,
+            database_readers,
+            log_slow_queries,
+            rpc_max_batch_size,
+            rpc_max_event_keys,
+            rpc_max_event_offset,
             monitoring_addr,
             integration,
         })
@@ -305,6 +488,16 @@ mod tests {
             match option {
                 ConfigOption::EthereumHttpUrl => "http://localhost",
                 ConfigOption::EnableSQLiteWriteAheadLogging => "true",
+                ConfigOption::CompressionLevel => "3",
+                ConfigOption::CompressionLongWindow => "true",
+                ConfigOption::ConsistencyCheck => "check",
+                ConfigOption::DatabaseMaintenance => "prune-versions",
+                ConfigOption::RawBlockArchive => "1000",
+                ConfigOption::DatabaseReaders => "16",
+                ConfigOption::LogSlowQueries => "100",
+                ConfigOption::RpcMaxBatchSize => "50",
+                ConfigOption::RpcMaxEventKeys => "16",
+                ConfigOption::RpcMaxEventOffset => "10000",
                 _ => "value",
             }
             .to_owned()
@@ -373,6 +566,68 @@ mod tests {
                 let config = builder_with_all_required().try_build().unwrap();
                 assert_eq!(config.sqlite_wal, expected);
             }
+
+            #[test]
+            fn compression() {
+                let expected = crate::storage::CompressionConfig::default();
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(config.compression, expected);
+            }
+
+            #[test]
+            fn consistency_check() {
+                let expected = crate::config::ConsistencyCheck::Off;
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(config.consistency_check, expected);
+            }
+
+            #[test]
+            fn database_maintenance() {
+                let expected = crate::config::DatabaseMaintenance::Off;
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(config.database_maintenance, expected);
+            }
+
+            #[test]
+            fn raw_block_archive() {
+                let expected = crate::config::RawBlockArchive::Off;
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(config.raw_block_archive, expected);
+            }
+
+            #[test]
+            fn database_readers() {
+                let expected = crate::storage::Storage::DEFAULT_READERS;
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(config.database_readers, expected);
+            }
+
+            #[test]
+            fn log_slow_queries() {
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(config.log_slow_queries, None);
+            }
+
+            #[test]
+            fn rpc_max_batch_size() {
+                let expected = crate::rpc::RpcServer::DEFAULT_MAX_BATCH_SIZE;
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(config.rpc_max_batch_size, expected);
+            }
+
+            #[test]
+            fn rpc_max_event_keys() {
+                let expected = crate::storage::StarknetEventsTable::DEFAULT_MAX_KEYS;
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(config.rpc_max_event_keys, expected);
+            }
+
+            #[test]
+            fn rpc_max_event_offset() {
+                let expected = crate::storage::StarknetEventsTable::DEFAULT_MAX_OFFSET;
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(config.rpc_max_event_offset, expected);
+            }
         }
     }
 }
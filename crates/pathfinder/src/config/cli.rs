@@ -15,6 +15,16 @@ const SEQ_URL_KEY: &str = "sequencer-url";
 const PYTHON_SUBPROCESSES_KEY: &str = "python-subprocesses";
 const SQLITE_WAL: &str = "sqlite-wal";
 const POLL_PENDING: &str = "poll-pending";
+const COMPRESSION_LEVEL: &str = "compression-level";
+const COMPRESSION_LONG_WINDOW: &str = "compression-long-window";
+const CONSISTENCY_CHECK: &str = "consistency-check";
+const DATABASE_MAINTENANCE: &str = "database-maintenance";
+const RAW_BLOCK_ARCHIVE: &str = "raw-block-archive";
+const DATABASE_READERS: &str = "database-readers";
+const LOG_SLOW_QUERIES: &str = "log-slow-queries";
+const RPC_MAX_BATCH_SIZE: &str = "rpc-max-batch-size";
+const RPC_MAX_EVENT_KEYS: &str = "rpc-max-event-keys";
+const RPC_MAX_EVENT_OFFSET: &str = "rpc-max-event-offset";
 const MONITOR_ADDRESS: &str = "monitor-address";
 const INTEGRATION: &str = "integration";
 
@@ -50,6 +60,16 @@ where
     let python_subprocesses = args.value_of(PYTHON_SUBPROCESSES_KEY).map(|s| s.to_owned());
     let sqlite_wal = args.value_of(SQLITE_WAL).map(|s| s.to_owned());
     let poll_pending = args.value_of(POLL_PENDING).map(|s| s.to_owned());
+    let compression_level = args.value_of(COMPRESSION_LEVEL).map(|s| s.to_owned());
+    let compression_long_window = args.value_of(COMPRESSION_LONG_WINDOW).map(|s| s.to_owned());
+    let consistency_check = args.value_of(CONSISTENCY_CHECK).map(|s| s.to_owned());
+    let database_maintenance = args.value_of(DATABASE_MAINTENANCE).map(|s| s.to_owned());
+    let raw_block_archive = args.value_of(RAW_BLOCK_ARCHIVE).map(|s| s.to_owned());
+    let database_readers = args.value_of(DATABASE_READERS).map(|s| s.to_owned());
+    let log_slow_queries = args.value_of(LOG_SLOW_QUERIES).map(|s| s.to_owned());
+    let rpc_max_batch_size = args.value_of(RPC_MAX_BATCH_SIZE).map(|s| s.to_owned());
+    let rpc_max_event_keys = args.value_of(RPC_MAX_EVENT_KEYS).map(|s| s.to_owned());
+    let rpc_max_event_offset = args.value_of(RPC_MAX_EVENT_OFFSET).map(|s| s.to_owned());
     let monitor_address = args.value_of(MONITOR_ADDRESS).map(|s| s.to_owned());
     // Hack around our builder requiring Strings, but this arg just needs to be present.
     let integration = args.is_present(INTEGRATION).then_some(String::new());
@@ -63,6 +83,16 @@ where
         .with(ConfigOption::PythonSubprocesses, python_subprocesses)
         .with(ConfigOption::EnableSQLiteWriteAheadLogging, sqlite_wal)
         .with(ConfigOption::PollPending, poll_pending)
+        .with(ConfigOption::CompressionLevel, compression_level)
+        .with(ConfigOption::CompressionLongWindow, compression_long_window)
+        .with(ConfigOption::ConsistencyCheck, consistency_check)
+        .with(ConfigOption::DatabaseMaintenance, database_maintenance)
+        .with(ConfigOption::RawBlockArchive, raw_block_archive)
+        .with(ConfigOption::DatabaseReaders, database_readers)
+        .with(ConfigOption::LogSlowQueries, log_slow_queries)
+        .with(ConfigOption::RpcMaxBatchSize, rpc_max_batch_size)
+        .with(ConfigOption::RpcMaxEventKeys, rpc_max_event_keys)
+        .with(ConfigOption::RpcMaxEventOffset, rpc_max_event_offset)
         .with(ConfigOption::MonitorAddress, monitor_address)
         .with(ConfigOption::Integration, integration);
 
@@ -159,6 +189,96 @@ Examples:
                 .value_name("TRUE/FALSE")
                 .env("PATHFINDER_POLL_PENDING")
         )
+        .arg(
+            Arg::new(COMPRESSION_LEVEL)
+                .long(COMPRESSION_LEVEL)
+                .help("zstd compression level used when writing to the database [default: 10]")
+                .long_help("The zstd compression level used for transactions, state updates, classes and raw blocks written to the database. Lower levels speed up syncing, higher levels reduce the database size. Changing it does not affect previously written data.")
+                .takes_value(true)
+                .value_name("LEVEL")
+                .env("PATHFINDER_COMPRESSION_LEVEL")
+        )
+        .arg(
+            Arg::new(COMPRESSION_LONG_WINDOW)
+                .long(COMPRESSION_LONG_WINDOW)
+                .help("Enable zstd long distance matching when writing to the database [default: false]")
+                .long_help("Enables zstd's long distance matching with a 128 MiB window for blobs written to the database. This shrinks large blobs such as class definitions further, at the cost of compression speed and memory, which suits archival nodes. Changing it does not affect previously written data.")
+                .takes_value(true)
+                .value_name("TRUE/FALSE")
+                .env("PATHFINDER_COMPRESSION_LONG_WINDOW")
+        )
+        .arg(
+            Arg::new(CONSISTENCY_CHECK)
+                .long(CONSISTENCY_CHECK)
+                .help("Check the database for consistency at startup [default: off]")
+                .long_help("Cross-checks the stored blocks, canonical chain, transactions and L1-L2 head at startup. 'check' logs any inconsistencies, 'repair' additionally truncates the database back to its last consistent block, from where sync continues.")
+                .takes_value(true)
+                .value_name("OFF/CHECK/REPAIR")
+                .env("PATHFINDER_CONSISTENCY_CHECK")
+        )
+        .arg(
+            Arg::new(DATABASE_MAINTENANCE)
+                .long(DATABASE_MAINTENANCE)
+                .help("Database maintenance to perform at startup [default: off]")
+                .long_help("Performs a maintenance task at startup, before syncing. 'backfill-versions' fetches the sequencer version of blocks synced before versions were stored, committing progress in batches so that an interrupted backfill resumes. 'prune-versions' deletes the stored versions no block references anymore.")
+                .takes_value(true)
+                .value_name("OFF/BACKFILL-VERSIONS/PRUNE-VERSIONS")
+                .env("PATHFINDER_DATABASE_MAINTENANCE")
+        )
+        .arg(
+            Arg::new(RAW_BLOCK_ARCHIVE)
+                .long(RAW_BLOCK_ARCHIVE)
+                .help("Archive the sequencer's raw block JSON [default: off]")
+                .long_help("Stores the compressed JSON of each block exactly as served by the sequencer, for replaying or debugging blocks. 'all' keeps every block, a number N keeps only the latest N blocks.")
+                .takes_value(true)
+                .value_name("OFF/ALL/N")
+                .env("PATHFINDER_RAW_BLOCK_ARCHIVE")
+        )
+        .arg(
+            Arg::new(DATABASE_READERS)
+                .long(DATABASE_READERS)
+                .help("Number of database connections used to serve queries [default: 10]")
+                .long_help("The number of database connections used to serve RPC queries concurrently. Writes always use a single dedicated connection.")
+                .takes_value(true)
+                .value_name("COUNT")
+                .env("PATHFINDER_DATABASE_READERS")
+        )
+        .arg(
+            Arg::new(LOG_SLOW_QUERIES)
+                .long(LOG_SLOW_QUERIES)
+                .help("Log database writes slower than this many milliseconds [default: disabled]")
+                .long_help("Database writes which take at least this many milliseconds are logged as warnings, along with their parameters. This helps diagnosing slow syncing.")
+                .takes_value(true)
+                .value_name("MILLISECONDS")
+                .env("PATHFINDER_LOG_SLOW_QUERIES")
+        )
+        .arg(
+            Arg::new(RPC_MAX_BATCH_SIZE)
+                .long(RPC_MAX_BATCH_SIZE)
+                .help("The maximum number of requests in a v0.2 JSON-RPC batch [default: 100]")
+                .long_help("JSON-RPC batches sent to the v0.2 API with more requests than this are rejected with an invalid request error.")
+                .takes_value(true)
+                .value_name("COUNT")
+                .env("PATHFINDER_RPC_MAX_BATCH_SIZE")
+        )
+        .arg(
+            Arg::new(RPC_MAX_EVENT_KEYS)
+                .long(RPC_MAX_EVENT_KEYS)
+                .help("The maximum number of keys in a starknet_getEvents filter [default: 256]")
+                .long_help("starknet_getEvents requests whose filter has more keys than this are rejected. Each key makes the events query slower.")
+                .takes_value(true)
+                .value_name("COUNT")
+                .env("PATHFINDER_RPC_MAX_EVENT_KEYS")
+        )
+        .arg(
+            Arg::new(RPC_MAX_EVENT_OFFSET)
+                .long(RPC_MAX_EVENT_OFFSET)
+                .help("The maximum number of events starknet_getEvents skips to reach the requested page [default: 1000000]")
+                .long_help("starknet_getEvents requests for a page further than this many events into the results are rejected. The database steps through every skipped event, so large offsets are slow.")
+                .takes_value(true)
+                .value_name("COUNT")
+                .env("PATHFINDER_RPC_MAX_EVENT_OFFSET")
+        )
         .arg(
             Arg::new(MONITOR_ADDRESS)
                 .long(MONITOR_ADDRESS)
@@ -197,6 +317,16 @@ mod tests {
         env::remove_var("PATHFINDER_PYTHON_SUBPROCESSES");
         env::remove_var("PATHFINDER_SQLITE_WAL");
         env::remove_var("PATHFINDER_POLL_PENDING");
+        env::remove_var("PATHFINDER_COMPRESSION_LEVEL");
+        env::remove_var("PATHFINDER_COMPRESSION_LONG_WINDOW");
+        env::remove_var("PATHFINDER_CONSISTENCY_CHECK");
+        env::remove_var("PATHFINDER_DATABASE_MAINTENANCE");
+        env::remove_var("PATHFINDER_RAW_BLOCK_ARCHIVE");
+        env::remove_var("PATHFINDER_DATABASE_READERS");
+        env::remove_var("PATHFINDER_LOG_SLOW_QUERIES");
+        env::remove_var("PATHFINDER_RPC_MAX_BATCH_SIZE");
+        env::remove_var("PATHFINDER_RPC_MAX_EVENT_KEYS");
+        env::remove_var("PATHFINDER_RPC_MAX_EVENT_OFFSET");
         env::remove_var("PATHFINDER_MONITOR_ADDRESS");
     }
 
@@ -394,6 +524,217 @@ mod tests {
         assert_eq!(cfg.take(ConfigOption::PollPending), Some(value));
     }
 
+    #[test]
+    fn compression_level_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "value".to_owned();
+        let (_, mut cfg) = parse_args(vec!["bin name", "--compression-level", &value]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::CompressionLevel), Some(value));
+    }
+
+    #[test]
+    fn compression_level_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "value".to_owned();
+        env::set_var("PATHFINDER_COMPRESSION_LEVEL", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::CompressionLevel), Some(value));
+    }
+
+    #[test]
+    fn compression_long_window_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "value".to_owned();
+        let (_, mut cfg) =
+            parse_args(vec!["bin name", "--compression-long-window", &value]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::CompressionLongWindow), Some(value));
+    }
+
+    #[test]
+    fn compression_long_window_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "value".to_owned();
+        env::set_var("PATHFINDER_COMPRESSION_LONG_WINDOW", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::CompressionLongWindow), Some(value));
+    }
+
+    #[test]
+    fn consistency_check_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "value".to_owned();
+        let (_, mut cfg) = parse_args(vec!["bin name", "--consistency-check", &value]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::ConsistencyCheck), Some(value));
+    }
+
+    #[test]
+    fn consistency_check_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "value".to_owned();
+        env::set_var("PATHFINDER_CONSISTENCY_CHECK", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::ConsistencyCheck), Some(value));
+    }
+
+    #[test]
+    fn database_maintenance_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "value".to_owned();
+        let (_, mut cfg) = parse_args(vec!["bin name", "--database-maintenance", &value]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::DatabaseMaintenance), Some(value));
+    }
+
+    #[test]
+    fn database_maintenance_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "value".to_owned();
+        env::set_var("PATHFINDER_DATABASE_MAINTENANCE", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::DatabaseMaintenance), Some(value));
+    }
+
+    #[test]
+    fn raw_block_archive_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "value".to_owned();
+        let (_, mut cfg) = parse_args(vec!["bin name", "--raw-block-archive", &value]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::RawBlockArchive), Some(value));
+    }
+
+    #[test]
+    fn raw_block_archive_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "value".to_owned();
+        env::set_var("PATHFINDER_RAW_BLOCK_ARCHIVE", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::RawBlockArchive), Some(value));
+    }
+
+    #[test]
+    fn database_readers_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "value".to_owned();
+        let (_, mut cfg) = parse_args(vec!["bin name", "--database-readers", &value]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::DatabaseReaders), Some(value));
+    }
+
+    #[test]
+    fn database_readers_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "value".to_owned();
+        env::set_var("PATHFINDER_DATABASE_READERS", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::DatabaseReaders), Some(value));
+    }
+
+    #[test]
+    fn log_slow_queries_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "value".to_owned();
+        let (_, mut cfg) = parse_args(vec!["bin name", "--log-slow-queries", &value]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::LogSlowQueries), Some(value));
+    }
+
+    #[test]
+    fn log_slow_queries_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "value".to_owned();
+        env::set_var("PATHFINDER_LOG_SLOW_QUERIES", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::LogSlowQueries), Some(value));
+    }
+
+    #[test]
+    fn rpc_max_batch_size_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "value".to_owned();
+        let (_, mut cfg) = parse_args(vec!["bin name", "--rpc-max-batch-size", &value]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::RpcMaxBatchSize), Some(value));
+    }
+
+    #[test]
+    fn rpc_max_batch_size_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "value".to_owned();
+        env::set_var("PATHFINDER_RPC_MAX_BATCH_SIZE", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::RpcMaxBatchSize), Some(value));
+    }
+
+    #[test]
+    fn rpc_max_event_keys_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "value".to_owned();
+        let (_, mut cfg) = parse_args(vec!["bin name", "--rpc-max-event-keys", &value]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::RpcMaxEventKeys), Some(value));
+    }
+
+    #[test]
+    fn rpc_max_event_keys_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "value".to_owned();
+        env::set_var("PATHFINDER_RPC_MAX_EVENT_KEYS", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::RpcMaxEventKeys), Some(value));
+    }
+
+    #[test]
+    fn rpc_max_event_offset_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "value".to_owned();
+        let (_, mut cfg) = parse_args(vec!["bin name", "--rpc-max-event-offset", &value]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::RpcMaxEventOffset), Some(value));
+    }
+
+    #[test]
+    fn rpc_max_event_offset_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "value".to_owned();
+        env::set_var("PATHFINDER_RPC_MAX_EVENT_OFFSET", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::RpcMaxEventOffset), Some(value));
+    }
+
     #[test]
     fn monitor_address_long() {
         let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
@@ -24,6 +24,26 @@ struct FileConfig {
     sqlite_wal: Option<String>,
     #[serde(rename = "poll-pending")]
     poll_pending: Option<String>,
+    #[serde(rename = "compression-level")]
+    compression_level: Option<String>,
+    #[serde(rename = "compression-long-window")]
+    compression_long_window: Option<String>,
+    #[serde(rename = "consistency-check")]
+    consistency_check: Option<String>,
+    #[serde(rename = "database-maintenance")]
+    database_maintenance: Option<String>,
+    #[serde(rename = "raw-block-archive")]
+    raw_block_archive: Option<String>,
+    #[serde(rename = "database-readers")]
+    database_readers: Option<String>,
+    #[serde(rename = "log-slow-queries")]
+    log_slow_queries: Option<String>,
+    #[serde(rename = "rpc-max-batch-size")]
+    rpc_max_batch_size: Option<String>,
+    #[serde(rename = "rpc-max-event-keys")]
+    rpc_max_event_keys: Option<String>,
+    #[serde(rename = "rpc-max-event-offset")]
+    rpc_max_event_offset: Option<String>,
     #[serde(rename = "monitor-address")]
     monitor_address: Option<String>,
 }
@@ -43,6 +63,19 @@ impl FileConfig {
         .with(ConfigOption::PythonSubprocesses, self.python_subprocesses)
         .with(ConfigOption::EnableSQLiteWriteAheadLogging, self.sqlite_wal)
         .with(ConfigOption::PollPending, self.poll_pending)
+        .with(ConfigOption::CompressionLevel, self.compression_level)
+        .with(
+            ConfigOption::CompressionLongWindow,
+            self.compression_long_window,
+        )
+        .with(ConfigOption::ConsistencyCheck, self.consistency_check)
+        .with(ConfigOption::DatabaseMaintenance, self.database_maintenance)
+        .with(ConfigOption::RawBlockArchive, self.raw_block_archive)
+        .with(ConfigOption::DatabaseReaders, self.database_readers)
+        .with(ConfigOption::LogSlowQueries, self.log_slow_queries)
+        .with(ConfigOption::RpcMaxBatchSize, self.rpc_max_batch_size)
+        .with(ConfigOption::RpcMaxEventKeys, self.rpc_max_event_keys)
+        .with(ConfigOption::RpcMaxEventOffset, self.rpc_max_event_offset)
         .with(ConfigOption::MonitorAddress, self.monitor_address)
     }
 }
@@ -148,6 +181,86 @@ password = "{}""#,
         assert_eq!(cfg.take(ConfigOption::PollPending), Some(value));
     }
 
+    #[test]
+    fn compression_level() {
+        let value = "3".to_owned();
+        let toml = format!(r#"compression-level = "{}""#, value);
+        let mut cfg = config_from_str(&toml).unwrap();
+        assert_eq!(cfg.take(ConfigOption::CompressionLevel), Some(value));
+    }
+
+    #[test]
+    fn compression_long_window() {
+        let value = "true".to_owned();
+        let toml = format!(r#"compression-long-window = "{}""#, value);
+        let mut cfg = config_from_str(&toml).unwrap();
+        assert_eq!(cfg.take(ConfigOption::CompressionLongWindow), Some(value));
+    }
+
+    #[test]
+    fn consistency_check() {
+        let value = "repair".to_owned();
+        let toml = format!(r#"consistency-check = "{}""#, value);
+        let mut cfg = config_from_str(&toml).unwrap();
+        assert_eq!(cfg.take(ConfigOption::ConsistencyCheck), Some(value));
+    }
+
+    #[test]
+    fn database_maintenance() {
+        let value = "backfill-versions".to_owned();
+        let toml = format!(r#"database-maintenance = "{}""#, value);
+        let mut cfg = config_from_str(&toml).unwrap();
+        assert_eq!(cfg.take(ConfigOption::DatabaseMaintenance), Some(value));
+    }
+
+    #[test]
+    fn raw_block_archive() {
+        let value = "1000".to_owned();
+        let toml = format!(r#"raw-block-archive = "{}""#, value);
+        let mut cfg = config_from_str(&toml).unwrap();
+        assert_eq!(cfg.take(ConfigOption::RawBlockArchive), Some(value));
+    }
+
+    #[test]
+    fn database_readers() {
+        let value = "16".to_owned();
+        let toml = format!(r#"database-readers = "{}""#, value);
+        let mut cfg = config_from_str(&toml).unwrap();
+        assert_eq!(cfg.take(ConfigOption::DatabaseReaders), Some(value));
+    }
+
+    #[test]
+    fn log_slow_queries() {
+        let value = "250".to_owned();
+        let toml = format!(r#"log-slow-queries = "{}""#, value);
+        let mut cfg = config_from_str(&toml).unwrap();
+        assert_eq!(cfg.take(ConfigOption::LogSlowQueries), Some(value));
+    }
+
+    #[test]
+    fn rpc_max_batch_size() {
+        let value = "50".to_owned();
+        let toml = format!(r#"rpc-max-batch-size = "{}""#, value);
+        let mut cfg = config_from_str(&toml).unwrap();
+        assert_eq!(cfg.take(ConfigOption::RpcMaxBatchSize), Some(value));
+    }
+
+    #[test]
+    fn rpc_max_event_keys() {
+        let value = "16".to_owned();
+        let toml = format!(r#"rpc-max-event-keys = "{}""#, value);
+        let mut cfg = config_from_str(&toml).unwrap();
+        assert_eq!(cfg.take(ConfigOption::RpcMaxEventKeys), Some(value));
+    }
+
+    #[test]
+    fn rpc_max_event_offset() {
+        let value = "10000".to_owned();
+        let toml = format!(r#"rpc-max-event-offset = "{}""#, value);
+        let mut cfg = config_from_str(&toml).unwrap();
+        assert_eq!(cfg.take(ConfigOption::RpcMaxEventOffset), Some(value));
+    }
+
     #[test]
     fn monitor_address() {
         let value = "address".to_owned();
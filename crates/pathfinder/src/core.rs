@@ -496,5 +496,18 @@ mod tests {
                 BlockId::Hash(StarknetBlockHash(starkhash!("deadbeef")))
             );
         }
+
+        #[test]
+        fn malformed() {
+            for input in [
+                r#""earliest""#,
+                r#"{"block_number": -1}"#,
+                r#"{"block_hash": "not hex"}"#,
+                r#"{"block_number": 1, "block_hash": "0x1"}"#,
+                r#"{"block_index": 1}"#,
+            ] {
+                serde_json::from_str::<BlockId>(input).unwrap_err();
+            }
+        }
     }
 }
@@ -79,6 +79,8 @@ lazy_static::lazy_static!(
             .expect("LogStateUpdate event not found in core contract ABI").to_owned();
     pub static ref STATE_TRANSITION_FACT_EVENT: Event = core_contract().event("LogStateTransitionFact")
             .expect("LogStateTransitionFact event not found in core contract ABI").to_owned();
+    pub static ref MESSAGE_TO_L2_EVENT: Event = core_contract().event("LogMessageToL2")
+            .expect("LogMessageToL2 event not found in core contract ABI").to_owned();
     pub static ref MEMORY_PAGE_HASHES_EVENT: Event = gps_contract().event("LogMemoryPagesHashes")
             .expect("LogMemoryPagesHashes event not found in GPS contract ABI").to_owned();
     pub static ref MEMORY_PAGE_FACT_CONTINUOUS_EVENT: Event = mempage_contract().event("LogMemoryPageFactContinuous")
@@ -6,7 +6,7 @@ pub use fetch::*;
 use web3::types::H256;
 
 use crate::{
-    core::{GlobalRoot, StarknetBlockNumber},
+    core::{GlobalRoot, L1ToL2MessageNonce, StarknetBlockNumber},
     ethereum::EthOrigin,
 };
 
@@ -53,3 +53,13 @@ pub struct MemoryPageFactContinuousLog {
     pub origin: EthOrigin,
     pub hash: H256,
 }
+
+/// An L1 to L2 message log event. The message is consumed on L2 by an L1 handler
+/// transaction.
+///
+/// This is emitted by the Starknet core contract.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct L1ToL2MessageLog {
+    pub origin: EthOrigin,
+    pub nonce: L1ToL2MessageNonce,
+}
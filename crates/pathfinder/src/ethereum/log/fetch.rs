@@ -3,11 +3,12 @@ use web3::types::{H160, H256};
 use crate::core::Chain;
 use crate::ethereum::{
     contract::{
-        MEMORY_PAGE_FACT_CONTINUOUS_EVENT, MEMORY_PAGE_HASHES_EVENT, STATE_TRANSITION_FACT_EVENT,
-        STATE_UPDATE_EVENT,
+        MEMORY_PAGE_FACT_CONTINUOUS_EVENT, MEMORY_PAGE_HASHES_EVENT, MESSAGE_TO_L2_EVENT,
+        STATE_TRANSITION_FACT_EVENT, STATE_UPDATE_EVENT,
     },
     log::{
-        MemoryPageFactContinuousLog, MemoryPagesHashesLog, StateTransitionFactLog, StateUpdateLog,
+        L1ToL2MessageLog, MemoryPageFactContinuousLog, MemoryPagesHashesLog,
+        StateTransitionFactLog, StateUpdateLog,
     },
     EthOrigin,
 };
@@ -36,11 +37,12 @@ where
 /// Contains metadata for a log such as its point-of-origin on L1 and it's
 /// emitting contract and event signature.
 ///
-/// Implemented for the four Starknet log types,
+/// Implemented for the five Starknet log types,
 ///     - [StateUpdateLog]
 ///     - [StateTransitionFactLog]
 ///     - [MemoryPagesHashesLog]
 ///     - [MemoryPageFactContinuousLog]
+///     - [L1ToL2MessageLog]
 pub trait MetaLog: TryFrom<web3::types::Log, Error = anyhow::Error> {
     fn contract_address(chain: Chain) -> H160;
 
@@ -105,6 +107,20 @@ impl MetaLog for MemoryPageFactContinuousLog {
     }
 }
 
+impl MetaLog for L1ToL2MessageLog {
+    fn contract_address(chain: Chain) -> web3::types::H160 {
+        crate::ethereum::contract::addresses(chain).core
+    }
+
+    fn signature() -> H256 {
+        MESSAGE_TO_L2_EVENT.signature()
+    }
+
+    fn origin(&self) -> &EthOrigin {
+        &self.origin
+    }
+}
+
 impl<L, R> TryFrom<web3::types::Log> for EitherMetaLog<L, R>
 where
     L: MetaLog + PartialEq + std::fmt::Debug + Clone,
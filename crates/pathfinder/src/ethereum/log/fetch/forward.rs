@@ -191,6 +191,60 @@ where
     }
 }
 
+/// Fetches all logs of type T emitted in the L1 blocks `from..=to`.
+///
+/// Unlike [LogFetcher] this does not track a head, so the caller must ensure that `to` is
+/// still part of the L1 chain. An unknown block is reported as [FetchError::Reorg].
+pub async fn fetch_range<T>(
+    transport: impl EthereumTransport,
+    chain: Chain,
+    from: EthereumBlockNumber,
+    to: EthereumBlockNumber,
+) -> Result<Vec<T>, FetchError>
+where
+    T: MetaLog + PartialEq + std::fmt::Debug + Clone,
+{
+    let base_filter = FilterBuilder::default()
+        .address(vec![T::contract_address(chain)])
+        .topics(Some(vec![T::signature()]), None, None, None);
+
+    let mut logs = Vec::new();
+    let mut from_block = from.0;
+    // Start with the whole range, and halve it whenever we hit the query result limit.
+    let mut stride = to.0.saturating_sub(from_block);
+
+    while from_block <= to.0 {
+        let to_block = from_block.saturating_add(stride).min(to.0);
+        let filter = base_filter
+            .clone()
+            .from_block(BlockNumber::Number(from_block.into()))
+            .to_block(BlockNumber::Number(to_block.into()))
+            .build();
+
+        match transport.logs(filter).await {
+            Ok(batch) => {
+                for log in batch {
+                    logs.push(T::try_from(log)?);
+                }
+                from_block = to_block + 1;
+            }
+            Err(LogsError::QueryLimit) if stride > 0 => stride /= 2,
+            Err(LogsError::QueryLimit) => {
+                return Err(FetchError::Other(anyhow::anyhow!(
+                    "Query limit exceeded for a single block {}",
+                    from_block
+                )))
+            }
+            Err(LogsError::UnknownBlock) => return Err(FetchError::Reorg),
+            Err(LogsError::Other(other)) => {
+                return Err(FetchError::Other(anyhow::Error::new(other)))
+            }
+        }
+    }
+
+    Ok(logs)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -7,15 +7,15 @@ use web3::{
 };
 
 use crate::{
-    core::{GlobalRoot, StarknetBlockNumber},
+    core::{GlobalRoot, L1ToL2MessageNonce, StarknetBlockNumber},
     ethereum::{
         contract::{
-            MEMORY_PAGE_FACT_CONTINUOUS_EVENT, MEMORY_PAGE_HASHES_EVENT,
+            MEMORY_PAGE_FACT_CONTINUOUS_EVENT, MEMORY_PAGE_HASHES_EVENT, MESSAGE_TO_L2_EVENT,
             STATE_TRANSITION_FACT_EVENT, STATE_UPDATE_EVENT,
         },
         log::{
-            MemoryPageFactContinuousLog, MemoryPagesHashesLog, StateTransitionFactLog,
-            StateUpdateLog,
+            L1ToL2MessageLog, MemoryPageFactContinuousLog, MemoryPagesHashesLog,
+            StateTransitionFactLog, StateUpdateLog,
         },
         EthOrigin,
     },
@@ -118,6 +118,27 @@ impl TryFrom<web3::types::Log> for MemoryPageFactContinuousLog {
     }
 }
 
+impl TryFrom<web3::types::Log> for L1ToL2MessageLog {
+    type Error = anyhow::Error;
+
+    fn try_from(value: web3::types::Log) -> Result<Self, Self::Error> {
+        let (origin, raw_log) = parse_web3_log(value)?;
+
+        let log = MESSAGE_TO_L2_EVENT.parse_log(raw_log)?;
+
+        let nonce = get_log_param(&log, "nonce")?
+            .value
+            .into_uint()
+            .context("nonce could not be parsed")?;
+        let mut buf = [0u8; 32];
+        nonce.to_big_endian(&mut buf);
+        let nonce = StarkHash::from_be_bytes(buf).context("nonce could not be parsed")?;
+        let nonce = L1ToL2MessageNonce(nonce);
+
+        Ok(Self { origin, nonce })
+    }
+}
+
 /// Utility which extracts the [EthOrigin] and log index, and then converts to a [RawLog].
 fn parse_web3_log(log: web3::types::Log) -> anyhow::Result<(EthOrigin, RawLog)> {
     let origin = EthOrigin::try_from(&log)?;
@@ -495,4 +516,53 @@ mod tests {
             MemoryPageFactContinuousLog::try_from(log).unwrap_err();
         }
     }
+
+    mod message_to_l2 {
+        use super::*;
+        use pretty_assertions::assert_eq;
+        use web3::ethabi::Token;
+
+        /// Creates a valid web3 log containing a [L1ToL2MessageLog]. Also returns the
+        /// log's `nonce`.
+        fn test_data() -> (web3::types::Log, L1ToL2MessageNonce) {
+            let data = web3::ethabi::encode(&[
+                Token::Array(vec![Token::Uint(U256::from(1)), Token::Uint(U256::from(2))]),
+                Token::Uint(U256::from(0x1234)),
+                Token::Uint(U256::from(0)),
+            ]);
+            let mut log = create_test_log(MESSAGE_TO_L2_EVENT.signature(), data);
+            // The sender, recipient and selector are indexed.
+            log.topics.extend([
+                H256::from_low_u64_be(1),
+                H256::from_low_u64_be(2),
+                H256::from_low_u64_be(3),
+            ]);
+
+            (log, L1ToL2MessageNonce(crate::starkhash!("1234")))
+        }
+
+        #[test]
+        fn ok() {
+            let (log, nonce) = test_data();
+            let origin = EthOrigin::try_from(&log).unwrap();
+
+            let result = L1ToL2MessageLog::try_from(log).unwrap();
+            assert_eq!(result.origin, origin);
+            assert_eq!(result.nonce, nonce);
+        }
+
+        #[test]
+        fn bad_data() {
+            let (mut log, _) = test_data();
+            log.data.0.pop();
+            L1ToL2MessageLog::try_from(log).unwrap_err();
+        }
+
+        #[test]
+        fn missing_topics() {
+            let (mut log, _) = test_data();
+            log.topics.truncate(1);
+            L1ToL2MessageLog::try_from(log).unwrap_err();
+        }
+    }
 }
@@ -16,14 +16,17 @@ const INTEGRATION_GENESIS: EthereumBlockNumber = EthereumBlockNumber(5_986_835);
 
 impl StateRootFetcher {
     pub fn new(head: Option<StateUpdateLog>, chain: Chain) -> Self {
-        let genesis = match chain {
+        let inner = LogFetcher::<StateUpdateLog>::new(head, chain, Self::genesis(chain));
+        Self(inner)
+    }
+
+    /// The Ethereum block containing the Starknet genesis [StateUpdateLog] for the given chain.
+    pub fn genesis(chain: Chain) -> EthereumBlockNumber {
+        match chain {
             Chain::Mainnet => MAINNET_GENESIS,
             Chain::Testnet => TESTNET_GENESIS,
             Chain::Integration => INTEGRATION_GENESIS,
-        };
-
-        let inner = LogFetcher::<StateUpdateLog>::new(head, chain, genesis);
-        Self(inner)
+        }
     }
 }
 
@@ -92,12 +92,14 @@ pub mod test {
         ///
         /// # Panics
         ///
-        /// If the internal locking for write or setting the global recorder fails.
+        /// If setting the global recorder fails.
         pub fn lock<R>(recorder: R) -> Self
         where
             R: Recorder + 'static,
         {
-            let guard = RECORDER_LOCK.write().unwrap();
+            // A test which panics still drops its guard, clearing its recorder, so a poisoned lock
+            // is safe to reuse.
+            let guard = RECORDER_LOCK.write().unwrap_or_else(|e| e.into_inner());
 
             metrics::set_boxed_recorder(Box::new(recorder)).unwrap();
 
@@ -114,11 +116,8 @@ pub mod test {
         /// The internal RwLock is locked for reading which means that all tests
         /// that `lock_as_noop()` concurrently don't wait for each other.
         ///
-        /// # Panics
-        ///
-        /// If internal locking for read fails.
         pub fn lock_as_noop() -> Self {
-            let guard = RECORDER_LOCK.read().unwrap();
+            let guard = RECORDER_LOCK.read().unwrap_or_else(|e| e.into_inner());
 
             Self(GuardType::CommonNoop(guard))
         }
@@ -188,11 +187,13 @@ pub mod test {
             }
         }
 
+        /// Gauges and histograms are not tracked, but may be recorded by code running in
+        /// other tests concurrently.
         fn register_gauge(&self, _: &Key) -> Gauge {
-            unimplemented!()
+            Gauge::noop()
         }
         fn register_histogram(&self, _: &Key) -> Histogram {
-            unimplemented!()
+            Histogram::noop()
         }
     }
 
@@ -14,21 +14,37 @@ use jsonrpsee::{
     http_server::{HttpServerBuilder, HttpServerHandle, RpcModule},
 };
 
-use std::{net::SocketAddr, result::Result};
+use std::{net::SocketAddr, num::NonZeroUsize, result::Result};
 use v01::api::RpcApi;
 
 pub struct RpcServer {
     addr: SocketAddr,
     api: RpcApi,
     middleware: MaybeRpcMetricsMiddleware,
+    max_batch_size: NonZeroUsize,
 }
 
 impl RpcServer {
+    /// The default maximum number of requests in a single v0.2 batch.
+    pub const DEFAULT_MAX_BATCH_SIZE: NonZeroUsize = match NonZeroUsize::new(100) {
+        Some(size) => size,
+        None => unreachable!(),
+    };
+
     pub fn new(addr: SocketAddr, api: RpcApi) -> Self {
         Self {
             addr,
             api,
             middleware: MaybeRpcMetricsMiddleware::NoOp,
+            max_batch_size: Self::DEFAULT_MAX_BATCH_SIZE,
+        }
+    }
+
+    /// Rejects v0.2 batches of more than `max_batch_size` requests, see [batch_limit].
+    pub fn with_max_batch_size(self, max_batch_size: NonZeroUsize) -> Self {
+        Self {
+            max_batch_size,
+            ..self
         }
     }
 
@@ -40,53 +56,160 @@ impl RpcServer {
     }
 
     /// Starts the HTTP-RPC server.
+    ///
+    /// The JSON-RPC server itself listens on an ephemeral localhost port, behind a proxy
+    /// on `addr` which enforces the [batch limit](batch_limit).
     pub async fn run(self) -> Result<(HttpServerHandle, SocketAddr), anyhow::Error> {
         let server = HttpServerBuilder::default()
             .set_middleware(self.middleware)
-            .build(self.addr)
-            .await
-            .map_err(|e| match e {
-                jsonrpsee::core::Error::Transport(_) => {
-                    use std::error::Error;
+            .build(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await?;
+        let server_addr = server.local_addr()?;
+
+        let context_v02 = (&self.api).into();
+
+        let mut module_v01 = v01::RpcModuleWrapper::new(RpcModule::new(self.api));
+        v01::register_all_methods(&mut module_v01)?;
+        let module_v01: Methods = module_v01.into_inner().into();
 
-                    if let Some(inner) = e.source().and_then(|inner| inner.downcast_ref::<std::io::Error>()) {
-                        if let std::io::ErrorKind::AddrInUse = inner.kind() {
-                            return anyhow::Error::new(e)
-                                .context(format!("RPC address is already in use: {}.
+        let mut module_v02 = RpcModule::new(context_v02);
+        v02::register_all_methods(&mut module_v02)?;
+        let module_v02 = module_v02.into();
+
+        let handle = server.start_with_paths([
+            (vec!["/", "/rpc/v0.1"], module_v01),
+            (vec!["/rpc/v0.2"], module_v02),
+        ])?;
+
+        let (local_addr, proxy) = warp::serve(batch_limit(server_addr, self.max_batch_size))
+            .try_bind_ephemeral(self.addr)
+            .map_err(|e| {
+                let e = anyhow::Error::new(e);
+                let addr_in_use = e
+                    .chain()
+                    .filter_map(|inner| inner.downcast_ref::<std::io::Error>())
+                    .any(|inner| inner.kind() == std::io::ErrorKind::AddrInUse);
+                if addr_in_use {
+                    return e.context(format!("RPC address is already in use: {}.
 
 Hint: This usually means you are already running another instance of pathfinder.
 Hint: If this happens when upgrading, make sure to shut down the first one first.
 Hint: If you are looking to run two instances of pathfinder, you must configure them with different http rpc addresses.", self.addr));
+                }
+
+                e
+            })?;
+        tokio::spawn(proxy);
+
+        Ok((handle, local_addr))
+    }
+}
+
+/// Forwards requests to the JSON-RPC server at `server_addr`, except for v0.2 batches of more
+/// than `max_batch_size` requests, which are answered with an invalid request error.
+///
+/// The JSON-RPC server accepts batches of any size, and dispatches each of their requests
+/// concurrently.
+fn batch_limit(
+    server_addr: SocketAddr,
+    max_batch_size: NonZeroUsize,
+) -> impl warp::Filter<Extract = (warp::http::Response<bytes::Bytes>,), Error = warp::Rejection> + Clone
+{
+    use warp::Filter;
+
+    let client = reqwest::Client::new();
+
+    warp::any()
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::bytes())
+        .and_then(
+            move |method: warp::http::Method,
+                  path: warp::path::FullPath,
+                  headers: warp::http::HeaderMap,
+                  body: bytes::Bytes| {
+                let client = client.clone();
+                async move {
+                    if path.as_str().trim_end_matches('/') == "/rpc/v0.2" {
+                        if let Some(size) = batch_size(&body) {
+                            if size > max_batch_size.get() {
+                                let error = serde_json::json!({
+                                    "jsonrpc": "2.0",
+                                    "error": {
+                                        "code": -32600,
+                                        "message": format!(
+                                            "Batch of {size} requests exceeds the maximum of {max_batch_size}"
+                                        ),
+                                    },
+                                    "id": null,
+                                });
+                                return Ok(warp::http::Response::builder()
+                                    .header(warp::http::header::CONTENT_TYPE, "application/json")
+                                    .body(bytes::Bytes::from(error.to_string()))
+                                    .expect("Valid response"));
+                            }
                         }
                     }
 
-                    anyhow::Error::new(e)
+                    forward(&client, server_addr, method, path, headers, body)
+                        .await
+                        .map_err(|e| {
+                            tracing::warn!(error=?e, "Forwarding RPC request failed");
+                            warp::reject()
+                        })
                 }
-                _ => anyhow::Error::new(e),
-            })?;
-        let local_addr = server.local_addr()?;
+            },
+        )
+}
 
-        let context_v02 = (&self.api).into();
+/// Returns the number of requests in `body` if it is a JSON-RPC batch.
+fn batch_size(body: &[u8]) -> Option<usize> {
+    let first = body.iter().find(|b| !b.is_ascii_whitespace())?;
+    if *first != b'[' {
+        return None;
+    }
 
-        let mut module_v01 = v01::RpcModuleWrapper::new(RpcModule::new(self.api));
-        v01::register_all_methods(&mut module_v01)?;
-        let module_v01: Methods = module_v01.into_inner().into();
+    serde_json::from_slice::<Vec<::serde::de::IgnoredAny>>(body)
+        .ok()
+        .map(|batch| batch.len())
+}
 
-        let mut module_v02 = RpcModule::new(context_v02);
-        v02::register_all_methods(&mut module_v02)?;
-        let module_v02 = module_v02.into();
+async fn forward(
+    client: &reqwest::Client,
+    server_addr: SocketAddr,
+    method: warp::http::Method,
+    path: warp::path::FullPath,
+    mut headers: warp::http::HeaderMap,
+    body: bytes::Bytes,
+) -> anyhow::Result<warp::http::Response<bytes::Bytes>> {
+    use warp::http::header::{CONNECTION, CONTENT_LENGTH, HOST, TRANSFER_ENCODING};
+
+    // These describe the incoming connection, the forwarded request sets its own.
+    for name in [CONNECTION, CONTENT_LENGTH, HOST, TRANSFER_ENCODING] {
+        headers.remove(name);
+    }
 
-        Ok(server
-            .start_with_paths([
-                (vec!["/", "/rpc/v0.1"], module_v01),
-                (vec!["/rpc/v0.2"], module_v02),
-            ])
-            .map(|handle| (handle, local_addr))?)
+    let response = client
+        .request(method, format!("http://{server_addr}{}", path.as_str()))
+        .headers(headers)
+        .body(body)
+        .send()
+        .await?;
+
+    let mut builder = warp::http::Response::builder().status(response.status());
+    if let Some(headers) = builder.headers_mut() {
+        *headers = response.headers().clone();
+        for name in [CONNECTION, CONTENT_LENGTH, TRANSFER_ENCODING] {
+            headers.remove(name);
+        }
     }
+
+    Ok(builder.body(response.bytes().await?)?)
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use crate::{
         core::{
             ClassHash, ContractAddress, ContractAddressSalt, EntryPoint, EventData, EventKey,
@@ -99,8 +222,8 @@ mod tests {
             state_update::StorageDiff,
             transaction::{
                 execution_resources::{BuiltinInstanceCounter, EmptyBuiltinInstanceCounter},
-                EntryPointType, Event, ExecutionResources, InvokeTransaction, InvokeTransactionV0,
-                Receipt,
+                EntryPointType, Event, ExecutionResources, ExecutionStatus, InvokeTransaction,
+                InvokeTransactionV0, Receipt,
             },
         },
         starkhash, starkhash_bytes,
@@ -149,7 +272,7 @@ mod tests {
         use web3::types::H128;
 
         let storage = Storage::in_memory().unwrap();
-        let mut connection = storage.connection().unwrap();
+        let mut connection = storage.writer().unwrap();
         let db_txn = connection.transaction().unwrap();
 
         let contract0_addr = ContractAddress::new_or_panic(starkhash_bytes!(b"contract 0"));
@@ -249,28 +372,37 @@ mod tests {
         let block0 = StarknetBlock {
             number: StarknetBlockNumber::GENESIS,
             hash: genesis_hash,
+            parent_hash: StarknetBlockHash(StarkHash::ZERO),
             root: global_root0,
             timestamp: StarknetBlockTimestamp::new_or_panic(0),
             gas_price: GasPrice::ZERO,
-            sequencer_address: SequencerAddress(StarkHash::ZERO),
+            sequencer_address: Some(SequencerAddress(StarkHash::ZERO)),
+            transaction_count: 1,
+            event_count: 1,
         };
         let block1_hash = StarknetBlockHash(starkhash_bytes!(b"block 1"));
         let block1 = StarknetBlock {
             number: StarknetBlockNumber::new_or_panic(1),
             hash: block1_hash,
+            parent_hash: genesis_hash,
             root: global_root1,
             timestamp: StarknetBlockTimestamp::new_or_panic(1),
             gas_price: GasPrice::from(1),
-            sequencer_address: SequencerAddress(starkhash_bytes!(&[1u8])),
+            sequencer_address: Some(SequencerAddress(starkhash_bytes!(&[1u8]))),
+            transaction_count: 2,
+            event_count: 0,
         };
         let latest_hash = StarknetBlockHash(starkhash_bytes!(b"latest"));
         let block2 = StarknetBlock {
             number: StarknetBlockNumber::new_or_panic(2),
             hash: latest_hash,
+            parent_hash: block1_hash,
             root: global_root2,
             timestamp: StarknetBlockTimestamp::new_or_panic(2),
             gas_price: GasPrice::from(2),
-            sequencer_address: SequencerAddress(starkhash_bytes!(&[2u8])),
+            sequencer_address: Some(SequencerAddress(starkhash_bytes!(&[2u8]))),
+            transaction_count: 3,
+            event_count: 0,
         };
         StarknetBlocksTable::insert(&db_txn, &block0, None).unwrap();
         StarknetBlocksTable::insert(&db_txn, &block1, None).unwrap();
@@ -301,6 +433,7 @@ mod tests {
                 n_memory_holes: 0,
                 n_steps: 0,
             }),
+            execution_status: ExecutionStatus::Succeeded,
             l1_to_l2_consumed_message: None,
             l2_to_l1_messages: vec![],
             transaction_hash: txn0_hash,
@@ -434,6 +567,7 @@ mod tests {
                     n_memory_holes: 0,
                     n_steps: 0,
                 }),
+                execution_status: ExecutionStatus::Succeeded,
                 l1_to_l2_consumed_message: None,
                 l2_to_l1_messages: vec![],
                 transaction_hash: transactions[0].hash(),
@@ -449,6 +583,7 @@ mod tests {
                     n_memory_holes: 0,
                     n_steps: 0,
                 }),
+                execution_status: ExecutionStatus::Succeeded,
                 l1_to_l2_consumed_message: None,
                 l2_to_l1_messages: vec![],
                 transaction_hash: transactions[1].hash(),
@@ -509,7 +644,7 @@ mod tests {
         let deployed_contracts = state_diff.deployed_contracts.clone();
         let deploy_storage = storage.clone();
         tokio::task::spawn_blocking(move || {
-            let mut db = deploy_storage.connection().unwrap();
+            let mut db = deploy_storage.writer().unwrap();
             let tx = db.transaction().unwrap();
             let compressed_definition = include_bytes!("../fixtures/contract_definition.json.zst");
             for deployed in deployed_contracts {
@@ -536,7 +671,7 @@ mod tests {
         // Load from latest block in storage's root.
         let state_diff2 = state_diff.clone();
         let pending_root = tokio::task::spawn_blocking(move || {
-            let mut db = storage.connection().unwrap();
+            let mut db = storage.writer().unwrap();
             let tmp_tx = db.transaction().unwrap();
             let mut global_tree = GlobalStateTree::load(&tmp_tx, latest.root).unwrap();
             for deployed in state_diff2.deployed_contracts {
@@ -575,4 +710,81 @@ mod tests {
             .await;
         pending_data
     }
+
+    /// Batches are dispatched by the HTTP server itself, each element going through the
+    /// regular method handlers. A failing element must not fail the whole batch.
+    #[tokio::test]
+    async fn v02_batch_request() {
+        use crate::{core::Chain, rpc::v01::api::RpcApi, sequencer, state::SyncState};
+        use serde_json::json;
+
+        let storage = setup_storage();
+        let sequencer = sequencer::Client::new(Chain::Testnet).unwrap();
+        let sync_state = Arc::new(SyncState::default());
+        let api = RpcApi::new(storage, sequencer, Chain::Testnet, sync_state);
+        let (__handle, addr) = run_server(*LOCALHOST, api).await.unwrap();
+
+        let batch = json!([
+            {"jsonrpc": "2.0", "id": 0, "method": "starknet_chainId"},
+            {
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "starknet_getTransactionByHash",
+                "params": {"transaction_hash": "0xdeadbeef"}
+            },
+        ]);
+
+        let response: serde_json::Value = reqwest::Client::new()
+            .post(format!("http://{addr}/rpc/v0.2"))
+            .json(&batch)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let mut responses = response.as_array().unwrap().clone();
+        responses.sort_by_key(|r| r["id"].as_u64());
+        assert_eq!(responses.len(), 2);
+        assert_eq!(
+            responses[0]["result"],
+            json!(Chain::Testnet.starknet_chain_id().to_hex_str())
+        );
+        assert_eq!(responses[1]["error"]["code"], json!(25));
+    }
+
+    #[tokio::test]
+    async fn v02_batch_over_limit() {
+        use serde_json::json;
+        use std::num::NonZeroUsize;
+
+        // Oversized batches are answered by the proxy, so no JSON-RPC server is needed.
+        let filter = super::batch_limit(*LOCALHOST, NonZeroUsize::new(1).unwrap());
+
+        let batch = json!([
+            {"jsonrpc": "2.0", "id": 0, "method": "starknet_chainId"},
+            {"jsonrpc": "2.0", "id": 1, "method": "starknet_chainId"},
+        ]);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/rpc/v0.2")
+            .json(&batch)
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), warp::http::StatusCode::OK);
+        let response: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(response["error"]["code"], json!(-32600));
+        assert_eq!(response["id"], json!(null));
+    }
+
+    #[test]
+    fn batch_size() {
+        assert_eq!(super::batch_size(br#" [{"id": 0}, {"id": 1}]"#), Some(2));
+        assert_eq!(super::batch_size(b"[]"), Some(0));
+        assert_eq!(super::batch_size(br#"{"id": 0}"#), None);
+        assert_eq!(super::batch_size(b"[invalid"), None);
+    }
 }
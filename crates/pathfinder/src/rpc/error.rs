@@ -29,8 +29,23 @@ pub enum RpcError {
     NoBlocks,
     #[error("The supplied continuation token is invalid or unknown")]
     InvalidContinuationToken,
+    /// Not part of this version of the specification, uses the code later versions assign to it.
+    #[error("Too many keys provided in a filter")]
+    TooManyKeysInFilter,
     #[error("Contract error")]
     ContractError,
+    /// Not part of the specification. The database was locked by another connection, and the
+    /// request may succeed if retried.
+    #[error("Storage is busy, please retry")]
+    StorageBusy,
+    /// Not part of the specification. The event filter was never registered, or was removed
+    /// or expired.
+    #[error("Filter not found")]
+    FilterNotFound,
+    /// Not part of the specification. The node's limit on the number of registered event
+    /// filters is reached.
+    #[error("Too many event filters")]
+    TooManyFilters,
     #[error(transparent)]
     Internal(anyhow::Error),
 }
@@ -49,12 +64,25 @@ impl RpcError {
             RpcError::PageSizeTooBig => 31,
             RpcError::NoBlocks => 32,
             RpcError::InvalidContinuationToken => 33,
+            RpcError::TooManyKeysInFilter => 34,
             RpcError::ContractError => 40,
+            RpcError::StorageBusy => jsonrpsee::types::error::ErrorCode::ServerIsBusy.code(),
+            RpcError::FilterNotFound => -32000,
+            RpcError::TooManyFilters => -32005,
             RpcError::Internal(_) => jsonrpsee::types::error::ErrorCode::InternalError.code(),
         }
     }
 }
 
+impl From<crate::storage::StorageError> for RpcError {
+    fn from(e: crate::storage::StorageError) -> Self {
+        match e {
+            crate::storage::StorageError::Busy => Self::StorageBusy,
+            other => Self::Internal(other.into()),
+        }
+    }
+}
+
 impl From<RpcError> for jsonrpsee::core::error::Error {
     fn from(err: RpcError) -> Self {
         use jsonrpsee::types::error::{CallError, ErrorObject};
@@ -74,7 +102,8 @@ impl From<RpcError> for jsonrpsee::core::error::Error {
 /// generate_rpc_error_subset!(<enum_name>: <variant a>, <variant b>, <variant N>);
 /// ```
 /// Note that the variants __must__ match the [RpcError] variant names and that [RpcError::Internal]
-/// is always included by default (and therefore should not be part of macro input).
+/// and [RpcError::StorageBusy] are always included by default (and therefore should not be part of
+/// macro input).
 ///
 /// An `Internal` only variant can be generated using `generate_rpc_error_subset!(<enum_name>)`.
 ///
@@ -84,8 +113,14 @@ impl From<RpcError> for jsonrpsee::core::error::Error {
 /// 1. New enum definition with `#[derive(Debug)]`
 /// 2. `impl From<NewEnum> for RpcError`
 /// 3. `impl From<anyhow::Error> for NewEnum`
+/// 4. `impl From<StorageError> for NewEnum`, which maps [StorageError::Busy](crate::storage::StorageError::Busy)
+///    to `StorageBusy` and everything else to `Internal`
+/// 5. `impl MaybeBusy for NewEnum`, so that [Storage::read](crate::storage::Storage::read) retries
+///    reads which fail with it
+/// 6. `impl From<RpcError> for NewEnum`, which keeps the variants of the subset and maps everything
+///    else to `Internal`, for use with shared helpers such as [BlockId::resolve](crate::core::BlockId::resolve)
 ///
-/// It always includes the `Internal(anyhow::Error)` variant.
+/// It always includes the `Internal(anyhow::Error)` and `StorageBusy` variants.
 ///
 /// ## Example with expansion
 /// This macro invocation:
@@ -99,6 +134,7 @@ impl From<RpcError> for jsonrpsee::core::error::Error {
 ///     BlockNotFound,
 ///     NoBlocks,
 ///     Internal(anyhow::Error),
+///     StorageBusy,
 /// }
 ///
 /// impl From<MyError> for RpcError {
@@ -107,6 +143,7 @@ impl From<RpcError> for jsonrpsee::core::error::Error {
 ///             MyError::BlockNotFound => Self::BlockNotFound,
 ///             MyError::NoBlocks => Self::NoBlocks,
 ///             MyError::Internal(internal) => Self::Internal(internal),
+///             MyError::StorageBusy => Self::StorageBusy,
 ///         }
 ///     }
 /// }
@@ -116,6 +153,37 @@ impl From<RpcError> for jsonrpsee::core::error::Error {
 ///         Self::Internal(e)
 ///     }
 /// }
+///
+/// impl From<StorageError> for MyError {
+///     fn from(e: StorageError) -> Self {
+///         match e {
+///             StorageError::Busy => Self::StorageBusy,
+///             other => Self::Internal(other.into()),
+///         }
+///     }
+/// }
+///
+/// impl MaybeBusy for MyError {
+///     fn is_busy(&self) -> bool {
+///         match self {
+///             Self::StorageBusy => true,
+///             Self::Internal(e) => e.is_busy(),
+///             _ => false,
+///         }
+///     }
+/// }
+///
+/// impl From<RpcError> for MyError {
+///     fn from(e: RpcError) -> Self {
+///         match e {
+///             RpcError::BlockNotFound => Self::BlockNotFound,
+///             RpcError::NoBlocks => Self::NoBlocks,
+///             RpcError::StorageBusy => Self::StorageBusy,
+///             RpcError::Internal(internal) => Self::Internal(internal),
+///             other => Self::Internal(other.into()),
+///         }
+///     }
+/// }
 /// ```
 #[allow(unused_macros)]
 macro_rules! generate_rpc_error_subset {
@@ -136,12 +204,18 @@ macro_rules! generate_rpc_error_subset {
     ($enum_name:ident) => {
         generate_rpc_error_subset!(@enum_def, $enum_name,);
         generate_rpc_error_subset!(@from_anyhow, $enum_name);
+        generate_rpc_error_subset!(@from_storage, $enum_name);
+        generate_rpc_error_subset!(@maybe_busy, $enum_name);
+        generate_rpc_error_subset!(@from_rpc, $enum_name,);
         generate_rpc_error_subset!(@from_def, $enum_name,);
     };
     // Main entry-point for the macro
     ($enum_name:ident: $($subset:tt),+) => {
         generate_rpc_error_subset!(@enum_def, $enum_name, $($subset),+);
         generate_rpc_error_subset!(@from_anyhow, $enum_name);
+        generate_rpc_error_subset!(@from_storage, $enum_name);
+        generate_rpc_error_subset!(@maybe_busy, $enum_name);
+        generate_rpc_error_subset!(@from_rpc, $enum_name, $($subset),+);
         generate_rpc_error_subset!(@from_def, $enum_name, $($subset),+);
     };
     // Generates the enum definition, nothing tricky here.
@@ -149,6 +223,7 @@ macro_rules! generate_rpc_error_subset {
         #[derive(Debug)]
         pub enum $enum_name {
             Internal(anyhow::Error),
+            StorageBusy,
             $($subset),*
         }
     };
@@ -160,6 +235,45 @@ macro_rules! generate_rpc_error_subset {
             }
         }
     };
+    // Generates From<StorageError>, nothing tricky here.
+    (@from_storage, $enum_name:ident) => {
+        impl From<crate::storage::StorageError> for $enum_name {
+            fn from(e: crate::storage::StorageError) -> Self {
+                match e {
+                    crate::storage::StorageError::Busy => Self::StorageBusy,
+                    other => Self::Internal(other.into()),
+                }
+            }
+        }
+    };
+    // Generates MaybeBusy, so that reads failing with this error are retried by `Storage::read`.
+    (@maybe_busy, $enum_name:ident) => {
+        impl crate::storage::MaybeBusy for $enum_name {
+            fn is_busy(&self) -> bool {
+                match self {
+                    Self::StorageBusy => true,
+                    Self::Internal(e) => crate::storage::MaybeBusy::is_busy(e),
+                    #[allow(unreachable_patterns)]
+                    _ => false,
+                }
+            }
+        }
+    };
+    // Generates From<RpcError>, the subset's variants are unit variants and can be matched
+    // with a plain repetition.
+    (@from_rpc, $enum_name:ident, $($variants:ident),*) => {
+        impl From<crate::rpc::error::RpcError> for $enum_name {
+            fn from(e: crate::rpc::error::RpcError) -> Self {
+                match e {
+                    $(crate::rpc::error::RpcError::$variants => Self::$variants,)*
+                    crate::rpc::error::RpcError::StorageBusy => Self::StorageBusy,
+                    crate::rpc::error::RpcError::Internal(internal) => Self::Internal(internal),
+                    #[allow(unreachable_patterns)]
+                    other => Self::Internal(other.into()),
+                }
+            }
+        }
+    };
     // Generates From<$enum_name> for RpcError, this macro arm itself is not tricky,
     // however its child calls are.
     //
@@ -186,6 +300,7 @@ macro_rules! generate_rpc_error_subset {
         match $var {
             $($arms)*
             $enum_name::Internal(internal) => Self::Internal(internal),
+            $enum_name::StorageBusy => Self::StorageBusy,
         }
     };
     // Special case for single variant. This could probably be folded into one of the other
@@ -227,6 +342,18 @@ mod tests {
             generate_rpc_error_subset!(EmptyNoColon);
         }
 
+        #[test]
+        fn maybe_busy() {
+            use crate::storage::{MaybeBusy, StorageError};
+
+            generate_rpc_error_subset!(Subset: ContractNotFound);
+
+            assert!(Subset::StorageBusy.is_busy());
+            assert!(Subset::Internal(StorageError::Busy.into()).is_busy());
+            assert!(!Subset::Internal(anyhow::anyhow!("other")).is_busy());
+            assert!(!Subset::ContractNotFound.is_busy());
+        }
+
         #[test]
         fn single_variant() {
             generate_rpc_error_subset!(SINGLE: ContractNotFound);
@@ -248,5 +375,41 @@ mod tests {
             assert_matches!(no_blocks, RpcError::NoBlocks);
             assert_matches!(contract_error, RpcError::ContractError);
         }
+
+        #[test]
+        fn storage_errors() {
+            use crate::storage::StorageError;
+
+            generate_rpc_error_subset!(WithStorage: BlockNotFound);
+
+            let busy = RpcError::from(WithStorage::from(StorageError::Busy));
+            assert_matches!(busy, RpcError::StorageBusy);
+            assert_eq!(
+                busy.code(),
+                jsonrpsee::types::error::ErrorCode::ServerIsBusy.code()
+            );
+
+            let corruption = RpcError::from(WithStorage::from(StorageError::corruption(
+                "starknet_transactions",
+                "Deserializing transaction",
+            )));
+            assert_matches!(corruption, RpcError::Internal(_));
+            assert_eq!(
+                corruption.code(),
+                jsonrpsee::types::error::ErrorCode::InternalError.code()
+            );
+        }
+
+        #[test]
+        fn from_rpc_error() {
+            generate_rpc_error_subset!(Subset: BlockNotFound);
+
+            assert_matches!(Subset::from(RpcError::BlockNotFound), Subset::BlockNotFound);
+            assert_matches!(Subset::from(RpcError::StorageBusy), Subset::StorageBusy);
+            assert_matches!(
+                Subset::from(RpcError::ContractNotFound),
+                Subset::Internal(_)
+            );
+        }
     }
 }
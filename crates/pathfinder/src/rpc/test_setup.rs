@@ -29,7 +29,7 @@ impl<'a> Test<'a> {
         StorageInitIntoIterator: IntoIterator<Item = StorageInitItem>,
         StorageInitFn: FnOnce(&Transaction<'_>) -> StorageInitIntoIterator,
     {
-        let mut connection = self.storage.connection().unwrap();
+        let mut connection = self.storage.writer().unwrap();
         let tx = connection.transaction().unwrap();
         let storage_init = f(&tx);
         tx.commit().unwrap();
@@ -198,9 +198,16 @@ pub fn register_all_methods(
         #[derive(Debug, Deserialize)]
         struct NamedArgs {
             contract_address: ContractAddress,
+            // Optional for backwards compatibility, the nonce used to only be served from latest.
+            #[serde(default)]
+            block_id: Option<BlockId>,
         }
+        let params = params.parse::<NamedArgs>()?;
         context
-            .get_nonce(params.parse::<NamedArgs>()?.contract_address)
+            .get_nonce(
+                params.contract_address,
+                params.block_id.unwrap_or(BlockId::Latest),
+            )
             .await
     })?;
     module.register_async_method("starknet_call", |params, context| async move {
@@ -1087,7 +1094,7 @@ mod tests {
             async fn returns_program_and_entry_points_for_known_class() {
                 let storage = setup_storage();
 
-                let mut conn = storage.connection().unwrap();
+                let mut conn = storage.writer().unwrap();
                 let transaction = conn.transaction().unwrap();
                 let (_contract_address, class_hash, program, entry_points) =
                     setup_class_and_contract(&transaction).unwrap();
@@ -1117,7 +1124,7 @@ mod tests {
             async fn returns_program_and_entry_points_for_known_class() {
                 let storage = setup_storage();
 
-                let mut conn = storage.connection().unwrap();
+                let mut conn = storage.writer().unwrap();
                 let transaction = conn.transaction().unwrap();
                 let (_contract_address, class_hash, program, entry_points) =
                     setup_class_and_contract(&transaction).unwrap();
@@ -1303,7 +1310,7 @@ mod tests {
             use futures::stream::TryStreamExt;
 
             let storage = setup_storage();
-            let mut conn = storage.connection().unwrap();
+            let mut conn = storage.writer().unwrap();
             let transaction = conn.transaction().unwrap();
             let (contract_address, _class_hash, program, entry_points) =
                 setup_class_and_contract(&transaction).unwrap();
@@ -1343,7 +1350,7 @@ mod tests {
             use crate::core::ContractClass;
 
             let storage = setup_storage();
-            let mut conn = storage.connection().unwrap();
+            let mut conn = storage.writer().unwrap();
             let transaction = conn.transaction().unwrap();
             let (contract_address, _class_hash, _program, _entry_points) =
                 setup_class_and_contract(&transaction).unwrap();
@@ -1469,10 +1476,13 @@ mod tests {
             let block3 = StarknetBlock {
                 number: StarknetBlockNumber::new_or_panic(3),
                 hash: StarknetBlockHash(starkhash_bytes!(b"block 3 hash")),
+                parent_hash: StarknetBlockHash(starkhash_bytes!(b"latest")),
                 root: global_tree.apply().unwrap(),
                 timestamp: StarknetBlockTimestamp::new_or_panic(3),
                 gas_price: GasPrice::from(3),
-                sequencer_address: SequencerAddress(starkhash_bytes!(&[3u8])),
+                sequencer_address: Some(SequencerAddress(starkhash_bytes!(&[3u8]))),
+                transaction_count: 0,
+                event_count: 0,
             };
 
             StarknetBlocksTable::insert(transaction, &block3, None).unwrap();
@@ -1682,6 +1692,57 @@ mod tests {
         assert_eq!(expected, error);
     }
 
+    #[tokio::test]
+    async fn get_nonce_at_block() {
+        use crate::core::ContractNonce;
+        use crate::rpc::v01::types::reply::ErrorCode;
+
+        let storage = setup_storage();
+        let sequencer = Client::new(Chain::Testnet).unwrap();
+        let sync_state = Arc::new(SyncState::default());
+        let api = RpcApi::new(storage, sequencer, Chain::Testnet, sync_state);
+
+        // This contract is deployed in block 1 and has its nonce bumped to 0x10 in block 2.
+        let contract = ContractAddress::new_or_panic(starkhash_bytes!(b"contract 1"));
+
+        let nonce = api
+            .get_nonce(
+                contract,
+                BlockId::Number(StarknetBlockNumber::new_or_panic(1)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(nonce, ContractNonce::ZERO);
+
+        let nonce = api
+            .get_nonce(
+                contract,
+                BlockId::Number(StarknetBlockNumber::new_or_panic(2)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(nonce, ContractNonce(starkhash!("10")));
+
+        let nonce = api.get_nonce(contract, BlockId::Latest).await.unwrap();
+        assert_eq!(nonce, ContractNonce(starkhash!("10")));
+
+        // Not yet deployed in the genesis block.
+        let error = api
+            .get_nonce(contract, BlockId::Number(StarknetBlockNumber::GENESIS))
+            .await
+            .unwrap_err();
+        assert_eq!(ErrorCode::ContractNotFound, error);
+
+        let error = api
+            .get_nonce(
+                contract,
+                BlockId::Hash(StarknetBlockHash(starkhash_bytes!(b"invalid"))),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(ErrorCode::InvalidBlockId, error);
+    }
+
     // FIXME: these tests are largely defunct because they have never used ext_py, and handle
     // parsing issues.
     mod call {
@@ -2086,6 +2147,70 @@ mod tests {
             (storage, events)
         }
 
+        #[tokio::test]
+        async fn query_timeout() {
+            use crate::rpc::v01::types::request::EventFilter;
+
+            let (storage, _events) = setup();
+            let sequencer = Client::new(Chain::Testnet).unwrap();
+            let sync_state = Arc::new(SyncState::default());
+            let api = RpcApi::new(storage, sequencer, Chain::Testnet, sync_state)
+                .with_event_query_timeout(std::time::Duration::ZERO);
+
+            let error = api
+                .get_events(EventFilter {
+                    from_block: None,
+                    to_block: None,
+                    address: None,
+                    keys: vec![],
+                    page_size: test_utils::NUM_EVENTS,
+                    page_number: 0,
+                })
+                .await
+                .unwrap_err();
+
+            assert!(
+                error
+                    .to_string()
+                    .contains("query exceeded the maximum allowed time"),
+                "{error}"
+            );
+        }
+
+        #[tokio::test]
+        async fn too_many_keys() {
+            use crate::rpc::v01::types::request::EventFilter;
+            use jsonrpsee::types::error::CallError;
+
+            let (storage, events) = setup();
+            let sequencer = Client::new(Chain::Testnet).unwrap();
+            let sync_state = Arc::new(SyncState::default());
+            let api =
+                RpcApi::new(storage, sequencer, Chain::Testnet, sync_state).with_max_event_keys(2);
+
+            let error = api
+                .get_events(EventFilter {
+                    from_block: None,
+                    to_block: None,
+                    address: None,
+                    keys: events[..3].iter().map(|e| e.keys[0]).collect(),
+                    page_size: test_utils::NUM_EVENTS,
+                    page_number: 0,
+                })
+                .await
+                .unwrap_err();
+
+            assert_matches::assert_matches!(
+                error,
+                jsonrpsee::core::Error::Call(CallError::InvalidParams(e)) => {
+                    assert_eq!(
+                        e.downcast_ref::<crate::storage::EventFilterError>(),
+                        Some(&crate::storage::EventFilterError::TooManyKeys(2))
+                    );
+                }
+            );
+        }
+
         mod positional_args {
             use super::*;
             use crate::{rpc::v01::types::request::EventFilter, starkhash};
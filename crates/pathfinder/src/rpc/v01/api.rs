@@ -1,8 +1,8 @@
 //! Implementation of JSON-RPC endpoints.
 use crate::rpc::v01::types::{
     reply::{
-        Block, BlockHashAndNumber, BlockStatus, EmittedEvent, ErrorCode, FeeEstimate,
-        GetEventsResult, StateUpdate, Syncing, Transaction, TransactionReceipt,
+        Block, BlockHashAndNumber, BlockStatus, ErrorCode, FeeEstimate, GetEventsResult,
+        StateUpdate, Syncing, Transaction, TransactionReceipt,
     },
     request::{Call, ContractCall, EventFilter},
 };
@@ -15,11 +15,13 @@ use crate::{
         StarknetBlockTimestamp, StarknetTransactionHash, StarknetTransactionIndex, StorageAddress,
         StorageValue, TransactionNonce, TransactionVersion,
     },
+    rpc::v02::pending::append_pending_events,
     sequencer::{self, request::add_transaction::ContractDefinition, ClientApi},
     state::{state_tree::GlobalStateTree, PendingData, SyncState},
     storage::{
         ContractsTable, EventFilterError, RefsTable, StarknetBlocksBlockId, StarknetBlocksTable,
         StarknetEventsTable, StarknetStateUpdatesTable, StarknetTransactionsTable, Storage,
+        StorageError,
     },
 };
 use anyhow::Context;
@@ -44,6 +46,16 @@ pub struct RpcApi {
     pub shared_gas_price: Option<Cached>,
     pub sync_state: Arc<SyncState>,
     pub pending_data: Option<PendingData>,
+    /// See [StarknetEventsTable::get_events_with_max_offset].
+    pub max_event_offset: usize,
+    /// See [StarknetEventsTable::get_events_with_limits].
+    pub max_event_keys: usize,
+    /// See [StarknetEventsTable::with_timeout].
+    pub event_query_timeout: std::time::Duration,
+    /// See [EventFiltersTable::expire](crate::storage::EventFiltersTable::expire).
+    pub event_filter_timeout: std::time::Duration,
+    /// See [EventFiltersTable::register](crate::storage::EventFiltersTable::register).
+    pub max_event_filters: usize,
 }
 
 #[derive(Debug)]
@@ -82,6 +94,11 @@ impl RpcApi {
             shared_gas_price: None,
             sync_state,
             pending_data: None,
+            max_event_offset: StarknetEventsTable::DEFAULT_MAX_OFFSET,
+            max_event_keys: StarknetEventsTable::DEFAULT_MAX_KEYS,
+            event_query_timeout: StarknetEventsTable::DEFAULT_QUERY_TIMEOUT,
+            event_filter_timeout: crate::storage::EventFiltersTable::DEFAULT_MAX_IDLE,
+            max_event_filters: crate::storage::EventFiltersTable::DEFAULT_MAX_FILTERS,
         }
     }
 
@@ -99,6 +116,48 @@ impl RpcApi {
         }
     }
 
+    /// Limits how many events `starknet_getEvents` may skip to reach the requested page.
+    pub fn with_max_event_offset(self, max_event_offset: usize) -> Self {
+        Self {
+            max_event_offset,
+            ..self
+        }
+    }
+
+    /// Limits how many keys a `starknet_getEvents` filter may contain.
+    pub fn with_max_event_keys(self, max_event_keys: usize) -> Self {
+        Self {
+            max_event_keys,
+            ..self
+        }
+    }
+
+    /// Limits how long the database queries of a single `starknet_getEvents` request may run.
+    pub fn with_event_query_timeout(self, event_query_timeout: std::time::Duration) -> Self {
+        Self {
+            event_query_timeout,
+            ..self
+        }
+    }
+
+    /// Removes event filters registered with `pathfinder_newEventFilter` which have not been
+    /// polled for this long.
+    pub fn with_event_filter_timeout(self, event_filter_timeout: std::time::Duration) -> Self {
+        Self {
+            event_filter_timeout,
+            ..self
+        }
+    }
+
+    /// Limits how many event filters may be registered with `pathfinder_newEventFilter` at the
+    /// same time.
+    pub fn with_max_event_filters(self, max_event_filters: usize) -> Self {
+        Self {
+            max_event_filters,
+            ..self
+        }
+    }
+
     pub fn with_pending_data(self, pending_data: PendingData) -> Self {
         Self {
             pending_data: Some(pending_data),
@@ -143,7 +202,7 @@ impl RpcApi {
         tokio::task::spawn_blocking(move || {
             let _g = span.enter();
             let mut connection = storage
-                .connection()
+                .reader()
                 .context("Opening database connection")
                 .map_err(internal_server_error)?;
 
@@ -246,7 +305,10 @@ impl RpcApi {
             timestamp: block.timestamp,
             status: block_status,
             gas_price: block.gas_price,
-            sequencer: block.sequencer_address,
+            // Default value for cairo <0.8.0 is 0
+            sequencer: block
+                .sequencer_address
+                .unwrap_or(SequencerAddress(StarkHash::ZERO)),
         };
 
         Ok(block)
@@ -271,7 +333,7 @@ impl RpcApi {
         let jh = tokio::task::spawn_blocking(move || {
             let _g = span.enter();
             let mut db = storage
-                .connection()
+                .reader()
                 .context("Opening database connection")
                 .map_err(internal_server_error)?;
 
@@ -293,10 +355,11 @@ impl RpcApi {
                 }
             };
 
-            let state_update = StarknetStateUpdatesTable::get(&tx, block_hash)
+            let mut state_update = StarknetStateUpdatesTable::get(&tx, block_hash)
                 .context("Read state update from database")
                 .map_err(internal_server_error)?
                 .ok_or_else(|| Error::from(ErrorCode::InvalidBlockId))?;
+            state_update.state_diff = state_update.state_diff.without_nonces();
 
             Ok(state_update)
         });
@@ -357,7 +420,7 @@ impl RpcApi {
         let jh = tokio::task::spawn_blocking(move || {
             let _g = span.enter();
             let mut db = storage
-                .connection()
+                .reader()
                 .context("Opening database connection")
                 .map_err(internal_server_error)?;
 
@@ -445,7 +508,7 @@ impl RpcApi {
         let jh = tokio::task::spawn_blocking(move || {
             let _g = span.enter();
             let mut db = storage
-                .connection()
+                .reader()
                 .context("Opening database connection")
                 .map_err(internal_server_error)?;
 
@@ -502,7 +565,7 @@ impl RpcApi {
         let jh = tokio::task::spawn_blocking(move || {
             let _g = span.enter();
             let mut db = storage
-                .connection()
+                .reader()
                 .context("Opening database connection")
                 .map_err(internal_server_error)?;
 
@@ -566,7 +629,7 @@ impl RpcApi {
         let jh = tokio::task::spawn_blocking(move || {
             let _g = span.enter();
             let mut db = storage
-                .connection()
+                .reader()
                 .context("Opening database connection")
                 .map_err(internal_server_error)?;
 
@@ -624,7 +687,7 @@ impl RpcApi {
         let jh = tokio::task::spawn_blocking(move || {
             let _g = span.enter();
             let mut db = storage
-                .connection()
+                .reader()
                 .context("Opening database connection")
                 .map_err(internal_server_error)?;
             let tx = db
@@ -685,7 +748,7 @@ impl RpcApi {
         let jh = tokio::task::spawn_blocking(move || {
             let _g = span.enter();
             let mut db = storage
-                .connection()
+                .reader()
                 .context("Opening database connection")
                 .map_err(internal_server_error)?;
             let tx = db
@@ -765,7 +828,7 @@ impl RpcApi {
                             let code = tokio::task::spawn_blocking(move || {
                                 let _g = span.enter();
                                 let mut db = storage
-                                    .connection()
+                                    .reader()
                                     .context("Opening database connection")
                                     .map_err(internal_server_error)?;
                                 let tx = db
@@ -800,7 +863,7 @@ impl RpcApi {
         let jh = tokio::task::spawn_blocking(move || {
             let _g = span.enter();
             let mut db = storage
-                .connection()
+                .reader()
                 .context("Opening database connection")
                 .map_err(internal_server_error)?;
             let tx = db
@@ -863,7 +926,7 @@ impl RpcApi {
         let jh = tokio::task::spawn_blocking(move || {
             let _g = span.enter();
             let mut db = storage
-                .connection()
+                .reader()
                 .context("Opening database connection")
                 .map_err(internal_server_error)?;
             let tx = db
@@ -875,19 +938,8 @@ impl RpcApi {
                 .context("Reading transaction count from database")
                 .map_err(internal_server_error)?
             {
-                0 => {
-                    // We need to check if the value was 0 because there were no transactions, or because the block hash
-                    // is invalid.
-                    //
-                    // get_root is cheaper than querying the full block.
-                    match StarknetBlocksTable::get_root(&tx, block_id)
-                        .context("Reading block from database")?
-                    {
-                        Some(_) => Ok(0),
-                        None => Err(ErrorCode::InvalidBlockId.into()),
-                    }
-                }
-                other => Ok(other as u64),
+                Some(count) => Ok(count as u64),
+                None => Err(ErrorCode::InvalidBlockId.into()),
             }
         });
 
@@ -919,7 +971,7 @@ impl RpcApi {
         let jh = tokio::task::spawn_blocking(move || {
             let _g = span.enter();
             let mut db = storage
-                .connection()
+                .reader()
                 .context("Opening database connection")
                 .map_err(internal_server_error)?;
             let tx = db
@@ -948,7 +1000,7 @@ impl RpcApi {
         let jh = tokio::task::spawn_blocking(move || {
             let _g = span.enter();
             let mut db = storage
-                .connection()
+                .reader()
                 .context("Opening database connection")
                 .map_err(internal_server_error)?;
             let tx = db
@@ -989,7 +1041,7 @@ impl RpcApi {
                 let jh = tokio::task::spawn_blocking(move || {
                     let _g = span.enter();
                     let mut db = storage
-                        .connection()
+                        .reader()
                         .context("Opening database connection")
                         .map_err(internal_server_error)?;
 
@@ -1015,46 +1067,46 @@ impl RpcApi {
         }
     }
 
-    /// Returns the contract's latest nonce.
-    pub async fn get_nonce(&self, contract: ContractAddress) -> RpcResult<ContractNonce> {
+    /// Returns the contract's nonce as of the given block.
+    pub async fn get_nonce(
+        &self,
+        contract: ContractAddress,
+        block_id: BlockId,
+    ) -> RpcResult<ContractNonce> {
+        use crate::rpc::v02::pending::{PendingStateReader, StateReader, StorageStateReader};
+
+        let (block_id, pending) = match block_id {
+            BlockId::Hash(hash) => (hash.into(), None),
+            BlockId::Number(number) => (number.into(), None),
+            BlockId::Latest => (StarknetBlocksBlockId::Latest, None),
+            // Pending is served as latest, overlaid with the pending state diff.
+            BlockId::Pending => (
+                StarknetBlocksBlockId::Latest,
+                self.pending_data()?.state_update().await,
+            ),
+        };
+
         let storage = self.storage.clone();
         let span = tracing::Span::current();
         let jh = tokio::task::spawn_blocking(move || {
             let _g = span.enter();
-            let mut db = storage
-                .connection()
-                .context("Opening database connection")?;
+            let mut db = storage.reader().context("Opening database connection")?;
             let tx = db.transaction().context("Creating database transaction")?;
 
-            // FIXME: There is considerable overlap between this and `get_storage_at` which could be refactored.
-
             // Use internal_server_error to indicate that the process of querying for a particular block failed,
             // which is not the same as being sure that the block is not in the db.
-            let global_root = StarknetBlocksTable::get_root(&tx, StarknetBlocksBlockId::Latest)
+            let state = StorageStateReader::new(&tx, block_id)
                 .map_err(internal_server_error)?
-                .context("No global root found")
-                .map_err(internal_server_error)?;
-
-            let global_state_tree = GlobalStateTree::load(&tx, global_root)
-                .context("Loading global state tree")
-                .map_err(internal_server_error)?;
-
-            let state_hash = global_state_tree
-                .get(contract)
-                .context("Get contract state hash from global state tree")
-                .map_err(internal_server_error)?;
-
-            // There is a dedicated error code for a non-existent contract in the RPC API spec, so use it.
-            if state_hash.0 == StarkHash::ZERO {
-                return Err(Error::from(ErrorCode::ContractNotFound));
-            }
+                // Since the db query succeeded in execution, we can now report if the block was indeed not found
+                // by using a dedicated error code from the RPC API spec
+                .ok_or_else(|| Error::from(ErrorCode::InvalidBlockId))?;
+            let state = PendingStateReader::new(pending, state);
 
-            let nonce = crate::storage::ContractsStateTable::get_nonce(&tx, state_hash)
-                .context("Reading contract nonce")
+            let nonce = state
+                .nonce(contract)
                 .map_err(internal_server_error)?
-                // Since the contract does exist, the nonce should not be missing.
-                .context("Contract nonce is missing")
-                .map_err(internal_server_error)?;
+                // There is a dedicated error code for a non-existent contract in the RPC API spec, so use it.
+                .ok_or_else(|| Error::from(ErrorCode::ContractNotFound))?;
 
             Ok(nonce)
         });
@@ -1070,74 +1122,6 @@ impl RpcApi {
         Ok(value)
     }
 
-    /// Append's pending events to `dst` based on the filter requirements and returns
-    /// true if this was the last pending data i.e. `is_last_page`.
-    async fn append_pending_events(
-        &self,
-        dst: &mut Vec<EmittedEvent>,
-        skip: usize,
-        amount: usize,
-        address: Option<ContractAddress>,
-        keys: std::collections::HashSet<crate::core::EventKey>,
-    ) -> bool {
-        let pending_block = match self.pending_data.as_ref() {
-            Some(data) => match data.block().await {
-                Some(block) => block,
-                None => return true,
-            },
-            None => return true,
-        };
-
-        let original_len = dst.len();
-
-        let pending_events = pending_block
-            .transaction_receipts
-            .iter()
-            .flat_map(|receipt| {
-                receipt
-                    .events
-                    .iter()
-                    .zip(std::iter::repeat(receipt.transaction_hash))
-            })
-            .filter(|(event, _)| match address {
-                Some(address) => event.from_address == address,
-                None => true,
-            })
-            .filter(|(event, _)| {
-                if keys.is_empty() {
-                    return true;
-                }
-
-                for ek in &event.keys {
-                    if keys.contains(ek) {
-                        return true;
-                    }
-                }
-                false
-            })
-            .skip(skip)
-            // We need to take an extra event to determine is_last_page.
-            .take(amount + 1)
-            .map(
-                |(event, tx_hash)| crate::rpc::v01::types::reply::EmittedEvent {
-                    data: event.data.clone(),
-                    keys: event.keys.clone(),
-                    from_address: event.from_address,
-                    block_hash: None,
-                    block_number: None,
-                    transaction_hash: tx_hash,
-                },
-            );
-
-        dst.extend(pending_events);
-        let is_last_page = dst.len() <= (original_len + amount);
-        if !is_last_page {
-            dst.pop();
-        }
-
-        is_last_page
-    }
-
     /// Returns events matching the specified filter
     pub async fn get_events(&self, request: EventFilter) -> RpcResult<GetEventsResult> {
         // The [Block::Pending] in ranges makes things quite complicated. This implementation splits
@@ -1161,6 +1145,9 @@ impl RpcApi {
         use BlockId::*;
 
         let storage = self.storage.clone();
+        let max_event_offset = self.max_event_offset;
+        let max_event_keys = self.max_event_keys;
+        let event_query_timeout = self.event_query_timeout;
 
         // Handle the trivial (1) and (2) cases.
         match (request.from_block, request.to_block) {
@@ -1174,15 +1161,16 @@ impl RpcApi {
             }
             (Some(Pending), Some(Pending)) => {
                 let mut events = Vec::new();
-                let is_last_page = self
-                    .append_pending_events(
-                        &mut events,
-                        request.page_number * request.page_size,
-                        request.page_size,
-                        request.address,
-                        request.keys.into_iter().collect(),
-                    )
-                    .await;
+                let is_last_page = append_pending_events(
+                    &self.pending_data,
+                    &mut events,
+                    request.page_number * request.page_size,
+                    request.page_size,
+                    request.address,
+                    &request.keys,
+                    false,
+                )
+                .await;
                 return Ok(GetEventsResult {
                     events,
                     page_number: request.page_number,
@@ -1199,7 +1187,7 @@ impl RpcApi {
         let db_events = tokio::task::spawn_blocking(move || {
             let _g = span.enter();
             let mut connection = storage
-                .connection()
+                .reader()
                 .context("Opening database connection")
                 .map_err(internal_server_error)?;
 
@@ -1234,32 +1222,38 @@ impl RpcApi {
                 to_block,
                 contract_address: request.address,
                 keys: keys.clone(),
+                data_filter: vec![],
                 page_size: request.page_size,
                 page_number: request.page_number,
+                detect_last_page: true,
+                count_pages: false,
+                only_accepted: false,
             };
             // We don't add context here, because [StarknetEventsTable::get_events] adds its
             // own context to the errors. This way we get meaningful error information
             // for errors related to query parameters.
-            let page = StarknetEventsTable::get_events(&transaction, &filter).map_err(|e| {
-                if let Some(e) = e.downcast_ref::<EventFilterError>() {
-                    Error::from(*e)
-                } else {
-                    internal_server_error(e)
-                }
+            let page = StarknetEventsTable::with_timeout(&transaction, event_query_timeout, || {
+                StarknetEventsTable::get_events_with_limits(
+                    &transaction,
+                    &filter,
+                    max_event_offset,
+                    max_event_keys,
+                )
+            })
+            .map_err(|e| match e {
+                StorageError::InvalidFilter(e) => Error::from(e),
+                e => internal_server_error(e),
             })?;
 
             // Additional information is required if we need to append pending events.
             // More specifically, we need some database event count in order to page through
             // the pending events properly.
             let event_count = if request.to_block == Some(Pending) && page.events.is_empty() {
-                let count = StarknetEventsTable::event_count(
-                    &transaction,
-                    from_block,
-                    to_block,
-                    request.address,
-                    keys,
-                )
-                .map_err(internal_server_error)?;
+                let count =
+                    StarknetEventsTable::with_timeout(&transaction, event_query_timeout, || {
+                        StarknetEventsTable::event_count(&transaction, &filter, max_event_keys)
+                    })
+                    .map_err(internal_server_error)?;
 
                 Some(count)
             } else {
@@ -1285,19 +1279,21 @@ impl RpcApi {
 
         // Append pending data if required.
         if matches!(request.to_block, Some(Pending)) && events.events.len() < request.page_size {
-            let keys = request
-                .keys
-                .into_iter()
-                .collect::<std::collections::HashSet<_>>();
-
             let amount = request.page_size - events.events.len();
             let skip = match count {
                 Some(count) => request.page_number * request.page_size - count,
                 None => 0,
             };
-            events.is_last_page = self
-                .append_pending_events(&mut events.events, skip, amount, request.address, keys)
-                .await;
+            events.is_last_page = append_pending_events(
+                &self.pending_data,
+                &mut events.events,
+                skip,
+                amount,
+                request.address,
+                &request.keys,
+                false,
+            )
+            .await;
         }
 
         Ok(events)
@@ -1487,6 +1483,15 @@ impl From<EventFilterError> for jsonrpsee::core::Error {
                     Some(serde_json::json!({ "max_page_size": max_size })),
                 )))
             }
+            EventFilterError::PageNumberTooBig { .. }
+            | EventFilterError::TooManyKeys(_)
+            | EventFilterError::TooManyDataFilters(_)
+            | EventFilterError::InvalidBlockRange { .. } => {
+                Error::Call(CallError::InvalidParams(anyhow::Error::new(e)))
+            }
+            EventFilterError::QueryTimeout | EventFilterError::TooManyFilters(_) => {
+                internal_server_error(e)
+            }
         }
     }
 }
@@ -471,6 +471,17 @@ pub mod reply {
         }
     }
 
+    impl From<StateUpdate> for sequencer::reply::StateUpdate {
+        fn from(x: StateUpdate) -> Self {
+            Self {
+                block_hash: x.block_hash,
+                new_root: x.new_root,
+                old_root: x.old_root,
+                state_diff: x.state_diff.into(),
+            }
+        }
+    }
+
     /// State update related substructures.
     ///
     /// # Serialization
@@ -495,6 +506,8 @@ pub mod reply {
             pub nonces: Vec<Nonce>,
         }
 
+        /// Keeps the nonces, as stored state updates are replayed by snapshot import. The v0.1
+        /// API does not return them, see [StateDiff::without_nonces].
         impl From<sequencer::reply::state_update::StateDiff> for StateDiff {
             fn from(x: sequencer::reply::state_update::StateDiff) -> Self {
                 Self {
@@ -522,8 +535,63 @@ pub mod reply {
                             class_hash: deployed_contract.class_hash,
                         })
                         .collect(),
-                    // FIXME once the sequencer API provides the nonces
-                    nonces: vec![],
+                    nonces: x
+                        .nonces
+                        .into_iter()
+                        .map(|(contract_address, nonce)| Nonce {
+                            contract_address,
+                            nonce,
+                        })
+                        .collect(),
+                }
+            }
+        }
+
+        impl StateDiff {
+            /// The state diff as returned by the v0.1 API, which has never included nonces.
+            pub fn without_nonces(self) -> Self {
+                Self {
+                    nonces: Vec::new(),
+                    ..self
+                }
+            }
+        }
+
+        impl From<StateDiff> for sequencer::reply::state_update::StateDiff {
+            fn from(x: StateDiff) -> Self {
+                use sequencer::reply::state_update as seq;
+
+                let mut storage_diffs = std::collections::HashMap::<_, Vec<_>>::new();
+                for diff in x.storage_diffs {
+                    storage_diffs
+                        .entry(diff.address)
+                        .or_default()
+                        .push(seq::StorageDiff {
+                            key: diff.key,
+                            value: diff.value,
+                        });
+                }
+
+                Self {
+                    storage_diffs,
+                    deployed_contracts: x
+                        .deployed_contracts
+                        .into_iter()
+                        .map(|contract| seq::DeployedContract {
+                            address: contract.address,
+                            class_hash: contract.class_hash,
+                        })
+                        .collect(),
+                    declared_contracts: x
+                        .declared_contracts
+                        .into_iter()
+                        .map(|class| class.class_hash)
+                        .collect(),
+                    nonces: x
+                        .nonces
+                        .into_iter()
+                        .map(|nonce| (nonce.contract_address, nonce.nonce))
+                        .collect(),
                 }
             }
         }
@@ -1203,6 +1271,19 @@ pub mod reply {
         }
     }
 
+    impl From<crate::rpc::v02::pending::PendingEvent> for EmittedEvent {
+        fn from(event: crate::rpc::v02::pending::PendingEvent) -> Self {
+            Self {
+                data: event.data,
+                keys: event.keys,
+                from_address: event.from_address,
+                block_hash: None,
+                block_number: None,
+                transaction_hash: event.transaction_hash,
+            }
+        }
+    }
+
     // Result type for starknet_getEvents
     #[derive(Clone, Debug, Serialize, PartialEq, Eq)]
     #[cfg_attr(any(test, feature = "rpc-full-serde"), derive(serde::Deserialize))]
@@ -1,10 +1,12 @@
 use std::sync::Arc;
 
 use super::error::RpcError;
-use crate::{core::Chain, state::SyncState};
-use crate::{state::PendingData, storage::Storage};
+use crate::core::{BlockId, Chain, StarknetBlockHash};
+use crate::state::{PendingData, SyncState};
+use crate::storage::{StarknetBlocksBlockId, StarknetBlocksTable, Storage};
 
 pub mod method;
+pub mod pending;
 pub mod types;
 
 #[derive(Clone)]
@@ -13,6 +15,14 @@ pub struct RpcContext {
     pub pending_data: Option<PendingData>,
     pub sync_status: Arc<SyncState>,
     pub chain: Chain,
+    /// See [EventFiltersTable::expire](crate::storage::EventFiltersTable::expire).
+    pub event_filter_timeout: std::time::Duration,
+    /// See [EventFiltersTable::register](crate::storage::EventFiltersTable::register).
+    pub max_event_filters: usize,
+    /// See [StarknetEventsTable::get_events_with_limits](crate::storage::StarknetEventsTable::get_events_with_limits).
+    pub max_event_keys: usize,
+    /// See [StarknetEventsTable::get_events_with_limits](crate::storage::StarknetEventsTable::get_events_with_limits).
+    pub max_event_offset: usize,
 }
 
 impl RpcContext {
@@ -22,6 +32,10 @@ impl RpcContext {
             sync_status,
             chain,
             pending_data: None,
+            event_filter_timeout: crate::storage::EventFiltersTable::DEFAULT_MAX_IDLE,
+            max_event_filters: crate::storage::EventFiltersTable::DEFAULT_MAX_FILTERS,
+            max_event_keys: crate::storage::StarknetEventsTable::DEFAULT_MAX_KEYS,
+            max_event_offset: crate::storage::StarknetEventsTable::DEFAULT_MAX_OFFSET,
         }
     }
 
@@ -59,10 +73,46 @@ impl From<&super::v01::api::RpcApi> for RpcContext {
             pending_data: v01.pending_data.clone(),
             sync_status: v01.sync_state.clone(),
             chain: v01.chain,
+            event_filter_timeout: v01.event_filter_timeout,
+            max_event_filters: v01.max_event_filters,
+            max_event_keys: v01.max_event_keys,
+            max_event_offset: v01.max_event_offset,
         }
     }
 }
 
+impl BlockId {
+    /// Resolves to the referenced block in storage, or [RpcError::BlockNotFound] if there is none.
+    ///
+    /// The result always identifies the block by hash, so that later queries in the same
+    /// transaction refer to the same block even for [BlockId::Latest].
+    ///
+    /// [BlockId::Pending] resolves to the latest block, methods which serve pending data must
+    /// handle it beforehand.
+    pub fn resolve(
+        &self,
+        tx: &rusqlite::Transaction<'_>,
+    ) -> Result<StarknetBlocksBlockId, RpcError> {
+        self.resolve_hash(tx).map(StarknetBlocksBlockId::Hash)
+    }
+
+    /// Same as [BlockId::resolve], but returns the block's hash.
+    pub fn resolve_hash(
+        &self,
+        tx: &rusqlite::Transaction<'_>,
+    ) -> Result<StarknetBlockHash, RpcError> {
+        let block = match *self {
+            BlockId::Number(number) => number.into(),
+            BlockId::Hash(hash) => hash.into(),
+            BlockId::Latest | BlockId::Pending => StarknetBlocksBlockId::Latest,
+        };
+
+        let header = StarknetBlocksTable::get_header(tx, block)?.ok_or(RpcError::BlockNotFound)?;
+
+        Ok(header.hash)
+    }
+}
+
 /// Registers a JSON-RPC method with the [RpcModule<RpcContext>](jsonrpsee::RpcModule).
 ///
 /// An example signature for `method` is:
@@ -151,11 +201,13 @@ where
 // Registers all methods for the v0.2 API
 pub fn register_all_methods(module: &mut jsonrpsee::RpcModule<RpcContext>) -> anyhow::Result<()> {
     register_method_with_no_input(module, "starknet_chainId", method::chain_id::chain_id)?;
+    register_method(module, "starknet_getClass", method::get_class::get_class)?;
     register_method(
         module,
         "starknet_getClassHashAt",
         method::get_class_hash_at::get_class_hash_at,
     )?;
+    register_method(module, "starknet_getEvents", method::get_events::get_events)?;
     register_method(module, "starknet_getNonce", method::get_nonce::get_nonce)?;
     register_method(
         module,
@@ -178,6 +230,97 @@ pub fn register_all_methods(module: &mut jsonrpsee::RpcModule<RpcContext>) -> an
         method::get_transaction_receipt::get_transaction_receipt,
     )?;
     register_method_with_no_input(module, "starknet_syncing", method::syncing::syncing)?;
+    register_method(
+        module,
+        "pathfinder_getRawBlock",
+        method::get_raw_block::get_raw_block,
+    )?;
+    register_method(
+        module,
+        "pathfinder_getBlockWithReceipts",
+        method::get_block_with_receipts::get_block_with_receipts,
+    )?;
+    register_method(
+        module,
+        "pathfinder_newEventFilter",
+        method::event_filters::new_event_filter,
+    )?;
+    register_method(
+        module,
+        "pathfinder_getEventFilterChanges",
+        method::event_filters::get_event_filter_changes,
+    )?;
+    register_method(
+        module,
+        "pathfinder_removeEventFilter",
+        method::event_filters::remove_event_filter,
+    )?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    mod block_id_resolve {
+        use crate::core::{BlockId, StarknetBlockHash, StarknetBlockNumber};
+        use crate::rpc::error::RpcError;
+        use crate::storage::StarknetBlocksBlockId;
+        use crate::{starkhash, starkhash_bytes};
+
+        fn resolve(block_id: BlockId) -> Result<StarknetBlocksBlockId, RpcError> {
+            let storage = crate::rpc::tests::setup_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+            block_id.resolve(&tx)
+        }
+
+        #[test]
+        fn number() {
+            let resolved = resolve(StarknetBlockNumber::new_or_panic(1).into()).unwrap();
+            let expected = StarknetBlockHash(starkhash_bytes!(b"block 1"));
+            assert_eq!(resolved, StarknetBlocksBlockId::Hash(expected));
+        }
+
+        #[test]
+        fn hash() {
+            let hash = StarknetBlockHash(starkhash_bytes!(b"block 1"));
+            let resolved = resolve(hash.into()).unwrap();
+            assert_eq!(resolved, StarknetBlocksBlockId::Hash(hash));
+        }
+
+        #[test]
+        fn latest() {
+            let resolved = resolve(BlockId::Latest).unwrap();
+            let expected = StarknetBlockHash(starkhash_bytes!(b"latest"));
+            assert_eq!(resolved, StarknetBlocksBlockId::Hash(expected));
+        }
+
+        #[test]
+        fn pending_is_latest() {
+            let resolved = resolve(BlockId::Pending).unwrap();
+            assert_eq!(resolved, resolve(BlockId::Latest).unwrap());
+        }
+
+        #[test]
+        fn unknown_number() {
+            let error = resolve(StarknetBlockNumber::new_or_panic(123).into()).unwrap_err();
+            assert!(matches!(error, RpcError::BlockNotFound));
+        }
+
+        #[test]
+        fn unknown_hash() {
+            let error = resolve(StarknetBlockHash(starkhash!("0badc0de")).into()).unwrap_err();
+            assert!(matches!(error, RpcError::BlockNotFound));
+        }
+
+        #[test]
+        fn no_blocks() {
+            let storage = crate::storage::Storage::in_memory().unwrap();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let error = BlockId::Latest.resolve(&tx).unwrap_err();
+            assert!(matches!(error, RpcError::BlockNotFound));
+        }
+    }
+}
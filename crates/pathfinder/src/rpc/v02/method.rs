@@ -1,6 +1,11 @@
 pub(super) mod chain_id;
+pub(super) mod event_filters;
+pub(super) mod get_block_with_receipts;
+pub(super) mod get_class;
 pub(super) mod get_class_hash_at;
+pub(super) mod get_events;
 pub(super) mod get_nonce;
+pub(super) mod get_raw_block;
 pub(super) mod get_state_update;
 pub(super) mod get_transaction_by_block_id_and_index;
 pub(super) mod get_transaction_by_hash;
@@ -1,23 +1,61 @@
+use anyhow::Context;
+
 use crate::rpc::v02::RpcContext;
+use crate::storage::StarknetBlocksTable;
 
-crate::rpc::error::generate_rpc_error_subset!(ChainIdError);
+crate::rpc::error::generate_rpc_error_subset!(ChainIdError: NoBlocks);
 
+/// Returns the chain id of the chain whose genesis block is stored, rather than of the configured
+/// chain, so that a database of another chain is not misreported.
+///
+/// Fails with [ChainIdError::NoBlocks] until the genesis block has been synced.
 #[allow(dead_code)]
 pub async fn chain_id(context: RpcContext) -> Result<String, ChainIdError> {
-    Ok(context.chain.starknet_chain_id().to_hex_str().into_owned())
+    let storage = context.storage.clone();
+    let span = tracing::Span::current();
+    let jh = tokio::task::spawn_blocking(move || -> Result<String, ChainIdError> {
+        let _g = span.enter();
+        storage.read(|tx| {
+            let chain = StarknetBlocksTable::get_chain(tx)
+                .context("Reading chain from genesis block")?
+                .ok_or(ChainIdError::NoBlocks)?;
+
+            Ok(chain.starknet_chain_id().to_hex_str().into_owned())
+        })
+    });
+    jh.await.context("Database read panic or shutting down")?
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::core::Chain;
+    use crate::consts::{MAINNET_GENESIS_HASH, TESTNET_GENESIS_HASH};
+    use crate::core::{Chain, StarknetBlockHash, StarknetBlockNumber};
     use crate::rpc::v02::RpcContext;
+    use crate::state::SyncState;
+    use crate::storage::{test_utils, StarknetBlocksTable, Storage};
+    use std::sync::Arc;
+
+    use super::{chain_id, ChainIdError};
+
+    /// A context configured for `configured`, whose database holds the genesis block `genesis`.
+    fn context(configured: Chain, genesis: Option<StarknetBlockHash>) -> RpcContext {
+        let storage = Storage::in_memory().unwrap();
+        if let Some(genesis) = genesis {
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+            let mut block = test_utils::create_blocks()[0].clone();
+            block.number = StarknetBlockNumber::GENESIS;
+            block.hash = genesis;
+            StarknetBlocksTable::insert(&tx, &block, None).unwrap();
+            tx.commit().unwrap();
+        }
 
-    use super::chain_id;
+        RpcContext::new(storage, Arc::new(SyncState::default()), configured)
+    }
 
     #[tokio::test]
     async fn mainnet() {
-        let mut context = RpcContext::for_tests();
-        context.chain = Chain::Mainnet;
+        let context = context(Chain::Mainnet, Some(MAINNET_GENESIS_HASH));
 
         let result = chain_id(context).await.unwrap();
         let expected = format!("0x{}", hex::encode("SN_MAIN"));
@@ -26,11 +64,27 @@ mod tests {
 
     #[tokio::test]
     async fn testnet() {
-        let mut context = RpcContext::for_tests();
-        context.chain = Chain::Testnet;
+        let context = context(Chain::Testnet, Some(TESTNET_GENESIS_HASH));
 
         let result = chain_id(context).await.unwrap();
         let expected = format!("0x{}", hex::encode("SN_GOERLI"));
         assert_eq!(result, expected);
     }
+
+    #[tokio::test]
+    async fn stored_chain_wins_over_configuration() {
+        let context = context(Chain::Testnet, Some(MAINNET_GENESIS_HASH));
+
+        let result = chain_id(context).await.unwrap();
+        let expected = format!("0x{}", hex::encode("SN_MAIN"));
+        assert_eq!(result, expected);
+    }
+
+    #[tokio::test]
+    async fn no_genesis() {
+        let context = context(Chain::Testnet, None);
+
+        let error = chain_id(context).await.unwrap_err();
+        assert_matches::assert_matches!(error, ChainIdError::NoBlocks);
+    }
 }
@@ -0,0 +1,336 @@
+//! `pathfinder_newEventFilter`, `pathfinder_getEventFilterChanges` and
+//! `pathfinder_removeEventFilter`, which let clients poll for new events in the style of
+//! Ethereum's installed filters.
+//!
+//! Each call first removes the filters which were not polled for
+//! [RpcContext::event_filter_timeout]. At most [RpcContext::max_event_filters] filters are
+//! registered at the same time.
+//!
+//! Filters are written by the [auxiliary writer](crate::storage::Storage::auxiliary_writer), as
+//! the sync process holds on to the [writer](crate::storage::Storage::writer). These calls only
+//! wait for the sync process' current transaction to complete.
+
+use anyhow::Context;
+use rusqlite::TransactionBehavior;
+
+use crate::core::{BlockId, ContractAddress, EventKey, StarknetBlockNumber};
+use crate::rpc::v02::method::get_events::types::EmittedEvent;
+use crate::rpc::v02::RpcContext;
+use crate::storage::{
+    EventFilterError, EventFiltersTable, FilterId, StarknetBlocksTable, StarknetEventsTable,
+    StorageError,
+};
+
+#[derive(serde::Deserialize, Debug, PartialEq, Eq)]
+pub struct NewEventFilterInput {
+    filter: EventFilter,
+}
+
+/// Contains the parameters passed to `pathfinder_newEventFilter`.
+#[derive(Clone, Debug, serde::Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct EventFilter {
+    /// [BlockId::Latest] starts at the current head and [BlockId::Pending] after it.
+    #[serde(default)]
+    pub from_block: Option<BlockId>,
+    /// [BlockId::Latest] and [BlockId::Pending] keep following the chain, as does no block.
+    #[serde(default)]
+    pub to_block: Option<BlockId>,
+    #[serde(default)]
+    pub address: Option<ContractAddress>,
+    #[serde(default)]
+    pub keys: Vec<EventKey>,
+    /// The maximum number of events returned by each `pathfinder_getEventFilterChanges` call.
+    pub chunk_size: usize,
+}
+
+#[derive(serde::Deserialize, Debug, PartialEq, Eq)]
+pub struct EventFilterIdInput {
+    filter_id: FilterId,
+}
+
+crate::rpc::error::generate_rpc_error_subset!(NewEventFilterError: BlockNotFound, PageSizeTooBig, TooManyFilters);
+crate::rpc::error::generate_rpc_error_subset!(GetEventFilterChangesError: FilterNotFound);
+crate::rpc::error::generate_rpc_error_subset!(RemoveEventFilterError);
+
+/// Registers an event filter, whose new events are returned by
+/// [get_event_filter_changes].
+///
+/// Events of the pending block are not delivered.
+pub async fn new_event_filter(
+    context: RpcContext,
+    input: NewEventFilterInput,
+) -> Result<FilterId, NewEventFilterError> {
+    let request = input.filter;
+
+    if request.chunk_size > StarknetEventsTable::PAGE_SIZE_LIMIT {
+        return Err(NewEventFilterError::PageSizeTooBig);
+    }
+
+    let storage = context.storage.clone();
+    let max_idle = context.event_filter_timeout;
+    let max_filters = context.max_event_filters;
+    let span = tracing::Span::current();
+    let jh = tokio::task::spawn_blocking(move || -> Result<_, NewEventFilterError> {
+        let _g = span.enter();
+        let mut db = storage
+            .auxiliary_writer()
+            .context("Opening database connection")?;
+        let tx = db
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .context("Creating database transaction")?;
+
+        expire(&tx, max_idle)?;
+
+        let from_block = match request.from_block {
+            Some(BlockId::Latest) => StarknetBlocksTable::get_latest_number(&tx)?,
+            Some(BlockId::Pending) => Some(
+                StarknetBlocksTable::get_latest_number(&tx)?
+                    .map(|latest| latest + 1)
+                    .unwrap_or(StarknetBlockNumber::GENESIS),
+            ),
+            other => map_to_number(&tx, other)?,
+        };
+        let to_block = map_to_number(&tx, request.to_block)?;
+
+        let filter = crate::storage::StarknetEventFilter {
+            from_block,
+            to_block,
+            contract_address: request.address,
+            keys: request.keys,
+            data_filter: vec![],
+            page_size: request.chunk_size,
+            page_number: 0,
+            detect_last_page: false,
+            count_pages: false,
+            only_accepted: false,
+        };
+
+        let id = EventFiltersTable::register(&tx, &filter, max_filters).map_err(|e| match e {
+            StorageError::InvalidFilter(EventFilterError::PageSizeTooBig(_)) => {
+                NewEventFilterError::PageSizeTooBig
+            }
+            StorageError::InvalidFilter(EventFilterError::TooManyFilters(_)) => {
+                NewEventFilterError::TooManyFilters
+            }
+            other => other.into(),
+        })?;
+
+        tx.commit().context("Committing database transaction")?;
+
+        Ok(id)
+    });
+
+    jh.await.context("Database read panic or shutting down")?
+}
+
+/// Returns the events matching the filter which were not returned by a previous call, up to the
+/// filter's chunk size.
+///
+/// Events of blocks which were reorged away are returned again for their replacements.
+pub async fn get_event_filter_changes(
+    context: RpcContext,
+    input: EventFilterIdInput,
+) -> Result<Vec<EmittedEvent>, GetEventFilterChangesError> {
+    let storage = context.storage.clone();
+    let max_idle = context.event_filter_timeout;
+    let span = tracing::Span::current();
+    let jh = tokio::task::spawn_blocking(move || -> Result<_, GetEventFilterChangesError> {
+        let _g = span.enter();
+        let mut db = storage
+            .auxiliary_writer()
+            .context("Opening database connection")?;
+        let tx = db
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .context("Creating database transaction")?;
+
+        expire(&tx, max_idle)?;
+
+        let events = EventFiltersTable::poll(&tx, input.filter_id)?
+            .ok_or(GetEventFilterChangesError::FilterNotFound)?;
+
+        tx.commit().context("Committing database transaction")?;
+
+        Ok(events.into_iter().map(EmittedEvent::from).collect())
+    });
+
+    jh.await.context("Database read panic or shutting down")?
+}
+
+/// Removes the filter, returning `false` if there was no such filter.
+pub async fn remove_event_filter(
+    context: RpcContext,
+    input: EventFilterIdInput,
+) -> Result<bool, RemoveEventFilterError> {
+    let storage = context.storage.clone();
+    let max_idle = context.event_filter_timeout;
+    let span = tracing::Span::current();
+    let jh = tokio::task::spawn_blocking(move || -> Result<_, RemoveEventFilterError> {
+        let _g = span.enter();
+        let mut db = storage
+            .auxiliary_writer()
+            .context("Opening database connection")?;
+        let tx = db
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .context("Creating database transaction")?;
+
+        expire(&tx, max_idle)?;
+
+        let removed = EventFiltersTable::remove(&tx, input.filter_id)?;
+
+        tx.commit().context("Committing database transaction")?;
+
+        Ok(removed)
+    });
+
+    jh.await.context("Database read panic or shutting down")?
+}
+
+fn expire(tx: &rusqlite::Transaction<'_>, max_idle: std::time::Duration) -> anyhow::Result<()> {
+    let expired = EventFiltersTable::expire(tx, max_idle)?;
+    if expired > 0 {
+        tracing::debug!(expired, "Removed idle event filters");
+    }
+
+    Ok(())
+}
+
+/// Maps a [BlockId] to the block number stored for the filter, [None] meaning the filter
+/// follows the chain.
+fn map_to_number(
+    tx: &rusqlite::Transaction<'_>,
+    block: Option<BlockId>,
+) -> Result<Option<StarknetBlockNumber>, NewEventFilterError> {
+    match block {
+        Some(BlockId::Hash(hash)) => {
+            let number = StarknetBlocksTable::get_number(tx, hash)
+                .context("Reading block number")?
+                .ok_or(NewEventFilterError::BlockNotFound)?;
+
+            Ok(Some(number))
+        }
+        Some(BlockId::Number(number)) => Ok(Some(number)),
+        Some(BlockId::Latest) | Some(BlockId::Pending) | None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Chain;
+    use crate::state::SyncState;
+    use crate::storage::test_utils;
+    use std::sync::Arc;
+
+    fn setup() -> (RpcContext, Vec<EmittedEvent>) {
+        let (storage, events) = test_utils::setup_test_storage();
+        let events = events.into_iter().map(EmittedEvent::from).collect();
+        let context = RpcContext::new(storage, Arc::new(SyncState::default()), Chain::Testnet);
+        (context, events)
+    }
+
+    fn filter(chunk_size: usize) -> NewEventFilterInput {
+        NewEventFilterInput {
+            filter: EventFilter {
+                from_block: None,
+                to_block: None,
+                address: None,
+                keys: vec![],
+                chunk_size,
+            },
+        }
+    }
+
+    async fn changes(
+        context: &RpcContext,
+        filter_id: FilterId,
+    ) -> Result<Vec<EmittedEvent>, GetEventFilterChangesError> {
+        get_event_filter_changes(context.clone(), EventFilterIdInput { filter_id }).await
+    }
+
+    #[tokio::test]
+    async fn lifecycle() {
+        let (context, events) = setup();
+
+        let id = new_event_filter(context.clone(), filter(30)).await.unwrap();
+        assert_eq!(changes(&context, id).await.unwrap(), events[..30]);
+        assert_eq!(changes(&context, id).await.unwrap(), events[30..]);
+        assert_eq!(changes(&context, id).await.unwrap(), vec![]);
+
+        let removed = remove_event_filter(context.clone(), EventFilterIdInput { filter_id: id })
+            .await
+            .unwrap();
+        assert!(removed);
+        let error = changes(&context, id).await.unwrap_err();
+        assert_matches::assert_matches!(error, GetEventFilterChangesError::FilterNotFound);
+    }
+
+    #[tokio::test]
+    async fn while_syncing() {
+        let (context, events) = setup();
+        // The sync process holds on to the writer while it runs.
+        let _writer = context.storage.writer().unwrap();
+
+        let id = new_event_filter(context.clone(), filter(30)).await.unwrap();
+        assert_eq!(changes(&context, id).await.unwrap(), events[..30]);
+        let removed = remove_event_filter(context.clone(), EventFilterIdInput { filter_id: id })
+            .await
+            .unwrap();
+        assert!(removed);
+    }
+
+    #[tokio::test]
+    async fn from_latest() {
+        let (context, events) = setup();
+
+        let mut input = filter(30);
+        input.filter.from_block = Some(BlockId::Latest);
+        let id = new_event_filter(context.clone(), input).await.unwrap();
+        let latest = events.last().unwrap().block_number;
+        let expected = events
+            .iter()
+            .filter(|event| event.block_number == latest)
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(changes(&context, id).await.unwrap(), expected);
+
+        let mut input = filter(30);
+        input.filter.from_block = Some(BlockId::Pending);
+        let id = new_event_filter(context.clone(), input).await.unwrap();
+        assert_eq!(changes(&context, id).await.unwrap(), vec![]);
+    }
+
+    #[tokio::test]
+    async fn too_many_filters() {
+        let (mut context, _) = setup();
+        context.max_event_filters = 1;
+
+        let id = new_event_filter(context.clone(), filter(30)).await.unwrap();
+        let error = new_event_filter(context.clone(), filter(30))
+            .await
+            .unwrap_err();
+        assert_matches::assert_matches!(error, NewEventFilterError::TooManyFilters);
+
+        remove_event_filter(context.clone(), EventFilterIdInput { filter_id: id })
+            .await
+            .unwrap();
+        new_event_filter(context, filter(30)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn expired() {
+        let (mut context, _) = setup();
+        context.event_filter_timeout = std::time::Duration::ZERO;
+
+        let id = new_event_filter(context.clone(), filter(30)).await.unwrap();
+        context
+            .storage
+            .writer()
+            .unwrap()
+            .execute("UPDATE event_filters SET last_used = last_used - 1", [])
+            .unwrap();
+
+        let error = changes(&context, id).await.unwrap_err();
+        assert_matches::assert_matches!(error, GetEventFilterChangesError::FilterNotFound);
+    }
+}
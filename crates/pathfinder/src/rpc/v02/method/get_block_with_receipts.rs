@@ -0,0 +1,221 @@
+use anyhow::Context;
+
+use crate::{core::BlockId, rpc::v02::RpcContext, storage::StarknetBlocksBlockId};
+
+#[derive(serde::Deserialize, Debug, PartialEq, Eq)]
+pub struct GetBlockWithReceiptsInput {
+    block_id: BlockId,
+}
+
+crate::rpc::error::generate_rpc_error_subset!(GetBlockWithReceiptsError: BlockNotFound);
+
+/// Returns a block together with all of its transactions and their receipts.
+///
+/// This is a preview of the upcoming `starknet_getBlockWithReceipts` method.
+pub async fn get_block_with_receipts(
+    context: RpcContext,
+    input: GetBlockWithReceiptsInput,
+) -> Result<types::BlockWithReceipts, GetBlockWithReceiptsError> {
+    let block_id = match input.block_id {
+        BlockId::Number(number) => StarknetBlocksBlockId::Number(number),
+        BlockId::Hash(hash) => StarknetBlocksBlockId::Hash(hash),
+        BlockId::Latest => StarknetBlocksBlockId::Latest,
+        // Pending blocks are not supported yet.
+        BlockId::Pending => return Err(GetBlockWithReceiptsError::BlockNotFound),
+    };
+
+    let storage = context.storage.clone();
+    let span = tracing::Span::current();
+    let jh = tokio::task::spawn_blocking(move || {
+        let _g = span.enter();
+        storage.read(|tx| {
+            let block = crate::storage::get_block_with_receipts(tx, block_id)
+                .context("Reading block with receipts from database")?
+                .ok_or(GetBlockWithReceiptsError::BlockNotFound)?;
+
+            Ok(types::BlockWithReceipts::from(block))
+        })
+    });
+    jh.await.context("Database read panic or shutting down")?
+}
+
+mod types {
+    use serde::Serialize;
+
+    use crate::core::{
+        GlobalRoot, SequencerAddress, StarknetBlockHash, StarknetBlockNumber,
+        StarknetBlockTimestamp,
+    };
+    use crate::rpc::v02::method::get_transaction_receipt::types::TransactionReceipt;
+    use crate::rpc::v02::types::reply::{BlockStatus, Transaction};
+
+    /// A block with all of its transactions and their receipts, as returned by the RPC API.
+    #[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+    #[cfg_attr(any(test, feature = "rpc-full-serde"), derive(serde::Deserialize))]
+    pub struct BlockWithReceipts {
+        pub status: BlockStatus,
+        pub block_hash: StarknetBlockHash,
+        pub parent_hash: StarknetBlockHash,
+        pub block_number: StarknetBlockNumber,
+        pub new_root: GlobalRoot,
+        pub timestamp: StarknetBlockTimestamp,
+        pub sequencer_address: SequencerAddress,
+        /// Only stored since starknet 0.9.1.
+        pub starknet_version: Option<String>,
+        pub transactions: Vec<TransactionWithReceipt>,
+    }
+
+    #[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+    #[cfg_attr(any(test, feature = "rpc-full-serde"), derive(serde::Deserialize))]
+    pub struct TransactionWithReceipt {
+        pub transaction: Transaction,
+        pub receipt: TransactionReceipt,
+    }
+
+    impl From<crate::storage::BlockWithReceipts> for BlockWithReceipts {
+        fn from(data: crate::storage::BlockWithReceipts) -> Self {
+            let block = data.block;
+            let status = if data.accepted_on_l1 {
+                BlockStatus::AcceptedOnL1
+            } else {
+                BlockStatus::AcceptedOnL2
+            };
+
+            // The index is implied by the ordering.
+            let transactions = data
+                .transactions
+                .into_iter()
+                .map(|(transaction, receipt, _idx)| TransactionWithReceipt {
+                    receipt: TransactionReceipt::with_block_data(
+                        receipt,
+                        status,
+                        block.hash,
+                        block.number,
+                        &transaction,
+                    ),
+                    transaction: Transaction::from(transaction),
+                })
+                .collect();
+
+            Self {
+                status,
+                block_hash: block.hash,
+                parent_hash: block.parent_hash,
+                block_number: block.number,
+                new_root: block.root,
+                timestamp: block.timestamp,
+                // Default value for cairo <0.8.0 is 0
+                sequencer_address: block
+                    .sequencer_address
+                    .unwrap_or(SequencerAddress(stark_hash::StarkHash::ZERO)),
+                starknet_version: data.version,
+                transactions,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_block_with_receipts, GetBlockWithReceiptsError, GetBlockWithReceiptsInput};
+
+    use crate::core::{BlockId, StarknetBlockHash, StarknetBlockNumber, StarknetTransactionHash};
+    use crate::rpc::v02::types::reply::BlockStatus;
+    use crate::rpc::v02::RpcContext;
+    use crate::starkhash_bytes;
+    use crate::storage::RefsTable;
+
+    #[test]
+    fn parsing() {
+        use jsonrpsee::types::Params;
+
+        let positional = Params::new(Some(r#"[{ "block_number": 1 }]"#));
+        let input = positional.parse::<GetBlockWithReceiptsInput>().unwrap();
+        let expected = GetBlockWithReceiptsInput {
+            block_id: StarknetBlockNumber::new_or_panic(1).into(),
+        };
+        assert_eq!(input, expected);
+
+        let named = Params::new(Some(r#"{"block_id": "latest"}"#));
+        let input = named.parse::<GetBlockWithReceiptsInput>().unwrap();
+        assert_eq!(
+            input,
+            GetBlockWithReceiptsInput {
+                block_id: BlockId::Latest
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn transactions_and_receipts() {
+        let context = RpcContext::for_tests();
+
+        let input = GetBlockWithReceiptsInput {
+            block_id: StarknetBlockHash(starkhash_bytes!(b"block 1")).into(),
+        };
+        let block = get_block_with_receipts(context, input).await.unwrap();
+
+        assert_eq!(block.status, BlockStatus::AcceptedOnL2);
+        assert_eq!(block.block_number, StarknetBlockNumber::new_or_panic(1));
+
+        let hashes = block
+            .transactions
+            .iter()
+            .map(|tx| tx.transaction.hash())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            hashes,
+            vec![
+                StarknetTransactionHash(starkhash_bytes!(b"txn 1")),
+                StarknetTransactionHash(starkhash_bytes!(b"txn 2")),
+            ]
+        );
+
+        // Each receipt belongs to the transaction it is paired with.
+        for tx in block.transactions {
+            let receipt = serde_json::to_value(&tx.receipt).unwrap();
+            let hash = serde_json::to_value(tx.transaction.hash()).unwrap();
+            assert_eq!(receipt["transaction_hash"], hash);
+        }
+    }
+
+    #[tokio::test]
+    async fn accepted_on_l1() {
+        let context = RpcContext::for_tests();
+        {
+            let mut connection = context.storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+            RefsTable::set_l1_l2_head(&tx, Some(StarknetBlockNumber::new_or_panic(1))).unwrap();
+            tx.commit().unwrap();
+        }
+
+        let input = GetBlockWithReceiptsInput {
+            block_id: StarknetBlockNumber::GENESIS.into(),
+        };
+        let block = get_block_with_receipts(context.clone(), input)
+            .await
+            .unwrap();
+        assert_eq!(block.status, BlockStatus::AcceptedOnL1);
+
+        let input = GetBlockWithReceiptsInput {
+            block_id: BlockId::Latest,
+        };
+        let block = get_block_with_receipts(context, input).await.unwrap();
+        assert_eq!(block.status, BlockStatus::AcceptedOnL2);
+    }
+
+    #[tokio::test]
+    async fn block_not_found() {
+        let context = RpcContext::for_tests();
+
+        for block_id in [
+            BlockId::Pending,
+            StarknetBlockNumber::new_or_panic(100).into(),
+            StarknetBlockHash(starkhash_bytes!(b"invalid")).into(),
+        ] {
+            let input = GetBlockWithReceiptsInput { block_id };
+            let result = get_block_with_receipts(context.clone(), input).await;
+            assert_matches::assert_matches!(result, Err(GetBlockWithReceiptsError::BlockNotFound));
+        }
+    }
+}
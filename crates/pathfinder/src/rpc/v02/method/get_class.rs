@@ -0,0 +1,122 @@
+use anyhow::Context;
+
+use crate::core::{ClassHash, ContractClass};
+use crate::rpc::v02::RpcContext;
+use crate::storage::ContractCodeTable;
+
+crate::rpc::error::generate_rpc_error_subset!(GetClassError: ClassHashNotFound);
+
+#[derive(serde::Deserialize, Debug, PartialEq, Eq)]
+pub struct GetClassInput {
+    class_hash: ClassHash,
+}
+
+pub async fn get_class(
+    context: RpcContext,
+    input: GetClassInput,
+) -> Result<ContractClass, GetClassError> {
+    let span = tracing::Span::current();
+    let jh = tokio::task::spawn_blocking(move || -> Result<ContractClass, GetClassError> {
+        let _g = span.enter();
+        context.storage.read(|tx| {
+            ContractCodeTable::get_class(tx, input.class_hash)
+                .context("Fetching class from database")?
+                .ok_or(GetClassError::ClassHashNotFound)
+        })
+    });
+
+    jh.await.context("Database read panic or shutting down")?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_class, GetClassError, GetClassInput};
+
+    use crate::core::ClassHash;
+    use crate::rpc::v02::RpcContext;
+    use crate::starkhash_bytes;
+
+    mod parsing {
+        use super::*;
+        use crate::starkhash;
+
+        #[test]
+        fn positional_args() {
+            use jsonrpsee::types::Params;
+
+            let positional = r#"["0xabcde"]"#;
+            let positional = Params::new(Some(positional));
+
+            let input = positional.parse::<GetClassInput>().unwrap();
+            let expected = GetClassInput {
+                class_hash: ClassHash(starkhash!("0abcde")),
+            };
+            assert_eq!(input, expected);
+        }
+
+        #[test]
+        fn named_args() {
+            use jsonrpsee::types::Params;
+
+            let named = r#"{"class_hash": "0xabcde"}"#;
+            let named = Params::new(Some(named));
+
+            let input = named.parse::<GetClassInput>().unwrap();
+            let expected = GetClassInput {
+                class_hash: ClassHash(starkhash!("0abcde")),
+            };
+            assert_eq!(input, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        use crate::state::class_hash::{
+            extract_abi_code_hash, extract_program_and_entry_points_by_type,
+        };
+        use crate::storage::ContractCodeTable;
+        use std::io::Read;
+
+        let context = RpcContext::for_tests();
+
+        let definition = zstd::decode_all(
+            &include_bytes!("../../../../fixtures/contract_definition.json.zst")[..],
+        )
+        .unwrap();
+        let (abi, bytecode, class_hash) = extract_abi_code_hash(&definition).unwrap();
+        let (program, entry_points) =
+            extract_program_and_entry_points_by_type(&definition).unwrap();
+
+        let mut db = context.storage.writer().unwrap();
+        let tx = db.transaction().unwrap();
+        ContractCodeTable::insert(&tx, class_hash, &abi, &bytecode, &definition).unwrap();
+        tx.commit().unwrap();
+
+        let class = get_class(context, GetClassInput { class_hash })
+            .await
+            .unwrap();
+
+        assert_eq!(class.entry_points_by_type, entry_points);
+
+        // The program is a base64 encoding of the gzip-compressed JSON.
+        let compressed = base64::decode(&class.program).unwrap();
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        let decompressed: serde_json::Value = serde_json::from_slice(&decompressed).unwrap();
+        assert_eq!(decompressed, program);
+    }
+
+    #[tokio::test]
+    async fn class_hash_not_found() {
+        let context = RpcContext::for_tests();
+
+        let input = GetClassInput {
+            class_hash: ClassHash(starkhash_bytes!(b"invalid")),
+        };
+        let result = get_class(context, input).await;
+
+        assert_matches::assert_matches!(result, Err(GetClassError::ClassHashNotFound));
+    }
+}
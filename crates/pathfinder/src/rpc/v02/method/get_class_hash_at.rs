@@ -1,9 +1,10 @@
 use anyhow::Context;
 
-use crate::core::{BlockId, ClassHash, ContractAddress, ContractStateHash};
+use crate::core::{BlockId, ClassHash, ContractAddress};
+use crate::rpc::v02::pending::{
+    pending_state_update, PendingStateReader, StateReader, StorageStateReader,
+};
 use crate::rpc::v02::RpcContext;
-use crate::state::state_tree::GlobalStateTree;
-use crate::storage::{StarknetBlocksBlockId, StarknetBlocksTable};
 
 crate::rpc::error::generate_rpc_error_subset!(GetClassHashAtError: BlockNotFound, ContractNotFound);
 
@@ -17,91 +18,31 @@ pub async fn get_class_hash_at(
     context: RpcContext,
     input: GetClassHashAtInput,
 ) -> Result<ClassHash, GetClassHashAtError> {
-    let block_id = match input.block_id {
-        BlockId::Hash(hash) => hash.into(),
-        BlockId::Number(number) => number.into(),
-        BlockId::Latest => StarknetBlocksBlockId::Latest,
-        BlockId::Pending => {
-            match get_pending_class_hash(context.pending_data, input.contract_address).await {
-                Some(class_hash) => return Ok(class_hash),
-                None => StarknetBlocksBlockId::Latest,
-            }
-        }
+    // Pending is served as latest, overlaid with the pending state diff.
+    let pending = match input.block_id {
+        BlockId::Pending => pending_state_update(&context.pending_data).await,
+        _ => None,
     };
 
     let span = tracing::Span::current();
     let jh = tokio::task::spawn_blocking(move || {
         let _g = span.enter();
-        let mut db = context
-            .storage
-            .connection()
-            .context("Opening database connection")?;
-
-        let tx = db.transaction().context("Creating database transaction")?;
-
-        // Read the class hash via the state tree. This involves:
-        //  1. Reading the state_hash for this contract from the global tree
-        //  2. Fetching the class hash from the `contract_states` table
-        //
-        // (2) can also be achieved by fetching it directly from the `contracts` table,
-        // but it felt more "correct" to continue using the global state mechanism.
-        let global_root = StarknetBlocksTable::get_root(&tx, block_id)
-            .context("Reading global root from database")?
-            .ok_or(GetClassHashAtError::BlockNotFound)?;
-
-        let tree = GlobalStateTree::load(&tx, global_root).context("Loading global state tree")?;
-        let state_hash = match tree
-            .get(input.contract_address)
-            .context("Fetching contract leaf in global tree")?
-        {
-            zero if zero.0 == stark_hash::StarkHash::ZERO => {
-                return Err(GetClassHashAtError::ContractNotFound)
-            }
-            non_zero => non_zero,
-        };
+        context.storage.read(|tx| {
+            let block_id = input.block_id.resolve(tx)?;
 
-        read_class_hash(&tx, state_hash)
-            .context("Reading class hash from state table")?
-            // Class hash should not be None at this stage since we have a valid block and non-zero contract state_hash.
-            .ok_or_else(|| {
-                tracing::error!(%state_hash, "Class hash is missing in `contract_states` table");
-                anyhow::anyhow!("State table missing row for state_hash={}", state_hash).into()
-            })
+            let state =
+                StorageStateReader::new(tx, block_id)?.ok_or(GetClassHashAtError::BlockNotFound)?;
+            let state = PendingStateReader::new(pending.clone(), state);
+
+            state
+                .class_hash(input.contract_address)?
+                .ok_or(GetClassHashAtError::ContractNotFound)
+        })
     });
 
     jh.await.context("Database read panic or shutting down")?
 }
 
-/// Returns the [ClassHash] of the given [ContractAddress] if any is defined in the pending data.
-async fn get_pending_class_hash(
-    pending: Option<crate::state::PendingData>,
-    address: ContractAddress,
-) -> Option<ClassHash> {
-    pending?.state_update().await.and_then(|state_update| {
-        state_update
-            .state_diff
-            .deployed_contracts
-            .iter()
-            .find_map(|contract| (contract.address == address).then_some(contract.class_hash))
-    })
-}
-
-/// Returns the [ClassHash] for the given [ContractStateHash] from the database.
-fn read_class_hash(
-    tx: &rusqlite::Transaction<'_>,
-    state_hash: ContractStateHash,
-) -> anyhow::Result<Option<ClassHash>> {
-    use rusqlite::OptionalExtension;
-
-    tx.query_row(
-        "SELECT hash FROM contract_states WHERE state_hash=?",
-        [state_hash],
-        |row| row.get(0),
-    )
-    .optional()
-    .map_err(|e| e.into())
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
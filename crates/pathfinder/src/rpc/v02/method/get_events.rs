@@ -0,0 +1,674 @@
+use anyhow::Context;
+
+use crate::core::{BlockId, ContractAddress, EventKey, StarknetBlockNumber};
+use crate::rpc::v02::pending::append_pending_events;
+use crate::rpc::v02::RpcContext;
+use crate::storage::{EventFilterError, StarknetBlocksTable, StarknetEventsTable, StorageError};
+
+#[derive(serde::Deserialize, Debug, PartialEq, Eq)]
+pub struct GetEventsInput {
+    filter: EventFilter,
+}
+
+/// Contains event filter parameters passed to `starknet_getEvents`.
+#[derive(Clone, Debug, serde::Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct EventFilter {
+    #[serde(default)]
+    pub from_block: Option<BlockId>,
+    #[serde(default)]
+    pub to_block: Option<BlockId>,
+    #[serde(default)]
+    pub address: Option<ContractAddress>,
+    #[serde(default)]
+    pub keys: Vec<EventKey>,
+    /// Skips the events of reverted transactions.
+    #[serde(default)]
+    pub only_accepted: bool,
+
+    // These are inlined here because serde flatten and deny_unknown_fields
+    // don't work together.
+    pub chunk_size: usize,
+    /// Offset, measured in chunks, which was returned by the previous request.
+    #[serde(default)]
+    pub continuation_token: Option<String>,
+}
+
+crate::rpc::error::generate_rpc_error_subset!(
+    GetEventsError: BlockNotFound,
+    PageSizeTooBig,
+    InvalidContinuationToken,
+    TooManyKeysInFilter
+);
+
+/// Returns the events matching the filter, one chunk at a time.
+///
+/// The continuation token is the number of the next chunk, so the chunk size must not change while
+/// paging. Events of the pending block are paged after all events from the database if `to_block`
+/// is pending.
+pub async fn get_events(
+    context: RpcContext,
+    input: GetEventsInput,
+) -> Result<types::GetEventsResult, GetEventsError> {
+    // The [BlockId::Pending] in ranges makes things quite complicated. This implementation splits
+    // the ranges into the following buckets:
+    //
+    // 1. pending     :     pending -> query pending only
+    // 2. pending     : non-pending -> return empty result
+    // 3. non-pending : non-pending -> query db only
+    // 4. non-pending :     pending -> query db and potentially append pending events
+    //
+    // The database query for 3 and 4 is combined into one step.
+    //
+    // 4 requires some additional logic to handle some edge cases:
+    //  a) Query database
+    //  b) if full page           -> return page
+    //  c) else if partially full -> append events from start of pending
+    //  d) else (page is empty):
+    //      i) query database for event count
+    //     ii) query pending data using count for paging into pending events
+
+    let request = input.filter;
+
+    if request.chunk_size > StarknetEventsTable::PAGE_SIZE_LIMIT {
+        return Err(GetEventsError::PageSizeTooBig);
+    }
+
+    // Also checked by the database query, but pending events are filtered without it.
+    if request.keys.len() > context.max_event_keys {
+        return Err(GetEventsError::TooManyKeysInFilter);
+    }
+
+    let page_number = match &request.continuation_token {
+        Some(token) => token
+            .parse::<usize>()
+            .map_err(|_| GetEventsError::InvalidContinuationToken)?,
+        None => 0,
+    };
+
+    // Handle the trivial (1) and (2) cases.
+    match (request.from_block, request.to_block) {
+        (Some(BlockId::Pending), Some(BlockId::Pending)) => {
+            let skip = page_number
+                .checked_mul(request.chunk_size)
+                .ok_or(GetEventsError::InvalidContinuationToken)?;
+            let mut events = Vec::new();
+            let is_last_page = append_pending_events(
+                &context.pending_data,
+                &mut events,
+                skip,
+                request.chunk_size,
+                request.address,
+                &request.keys,
+                request.only_accepted,
+            )
+            .await;
+            return Ok(types::GetEventsResult::new(
+                events,
+                page_number,
+                is_last_page,
+            ));
+        }
+        (Some(BlockId::Pending), _) => {
+            return Ok(types::GetEventsResult::new(Vec::new(), page_number, true));
+        }
+        _ => {}
+    }
+
+    let storage = context.storage.clone();
+    let max_event_keys = context.max_event_keys;
+    let max_event_offset = context.max_event_offset;
+    let keys = request.keys.clone();
+    // blocking task to perform database event query and optionally, the event count
+    // required for (4d).
+    let span = tracing::Span::current();
+    let jh = tokio::task::spawn_blocking(move || -> Result<_, GetEventsError> {
+        let _g = span.enter();
+        storage.read(|transaction| {
+            let from_block = map_to_number(transaction, request.from_block)?;
+            let to_block = map_to_number(transaction, request.to_block)?;
+
+            // The specification has no error for an inverted range, it simply matches no events.
+            if let (Some(from), Some(to)) = (from_block, to_block) {
+                if from > to {
+                    let page = crate::storage::PageOfEvents {
+                        events: vec![],
+                        is_last_page: true,
+                        total_pages: None,
+                    };
+                    return Ok((page, None));
+                }
+            }
+
+            let filter = crate::storage::StarknetEventFilter {
+                from_block,
+                to_block,
+                contract_address: request.address,
+                keys: keys.clone(),
+                data_filter: vec![],
+                page_size: request.chunk_size,
+                page_number,
+                detect_last_page: true,
+                count_pages: false,
+                only_accepted: request.only_accepted,
+            };
+
+            let page = StarknetEventsTable::with_timeout(
+                transaction,
+                StarknetEventsTable::DEFAULT_QUERY_TIMEOUT,
+                || {
+                    StarknetEventsTable::get_events_with_limits(
+                        transaction,
+                        &filter,
+                        max_event_offset,
+                        max_event_keys,
+                    )
+                },
+            )
+            .map_err(|e| match e {
+                StorageError::InvalidFilter(EventFilterError::PageSizeTooBig(_)) => {
+                    GetEventsError::PageSizeTooBig
+                }
+                StorageError::InvalidFilter(EventFilterError::PageNumberTooBig { .. }) => {
+                    GetEventsError::InvalidContinuationToken
+                }
+                StorageError::InvalidFilter(EventFilterError::TooManyKeys(_)) => {
+                    GetEventsError::TooManyKeysInFilter
+                }
+                other => other.into(),
+            })?;
+
+            // Additional information is required if we need to append pending events.
+            // More specifically, we need some database event count in order to page through
+            // the pending events properly.
+            let event_count =
+                if request.to_block == Some(BlockId::Pending) && page.events.is_empty() {
+                    let count = StarknetEventsTable::with_timeout(
+                        transaction,
+                        StarknetEventsTable::DEFAULT_QUERY_TIMEOUT,
+                        || StarknetEventsTable::event_count(transaction, &filter, max_event_keys),
+                    )?;
+
+                    Some(count)
+                } else {
+                    None
+                };
+
+            Ok((page, event_count))
+        })
+    });
+
+    let (page, count) = jh.await.context("Database read panic or shutting down")??;
+
+    let mut events = page
+        .events
+        .into_iter()
+        .map(types::EmittedEvent::from)
+        .collect::<Vec<_>>();
+    let mut is_last_page = page.is_last_page;
+
+    // Append pending data if required.
+    if request.to_block == Some(BlockId::Pending) && events.len() < request.chunk_size {
+        let amount = request.chunk_size - events.len();
+        let skip = match count {
+            Some(count) => (page_number * request.chunk_size).saturating_sub(count),
+            None => 0,
+        };
+        is_last_page = append_pending_events(
+            &context.pending_data,
+            &mut events,
+            skip,
+            amount,
+            request.address,
+            &request.keys,
+            request.only_accepted,
+        )
+        .await;
+    }
+
+    Ok(types::GetEventsResult::new(
+        events,
+        page_number,
+        is_last_page,
+    ))
+}
+
+/// Maps a [BlockId] to a block number which can be used by the events query.
+fn map_to_number(
+    tx: &rusqlite::Transaction<'_>,
+    block: Option<BlockId>,
+) -> Result<Option<StarknetBlockNumber>, GetEventsError> {
+    match block {
+        Some(BlockId::Hash(hash)) => {
+            let number = StarknetBlocksTable::get_number(tx, hash)
+                .context("Reading block number")?
+                .ok_or(GetEventsError::BlockNotFound)?;
+
+            Ok(Some(number))
+        }
+        Some(BlockId::Number(number)) => Ok(Some(number)),
+        Some(BlockId::Latest) => Ok(StarknetBlocksTable::get_latest_number(tx)?),
+        Some(BlockId::Pending) | None => Ok(None),
+    }
+}
+
+pub(super) mod types {
+    use crate::core::{
+        ContractAddress, EventData, EventKey, StarknetBlockHash, StarknetBlockNumber,
+        StarknetTransactionHash,
+    };
+    use serde::Serialize;
+
+    /// Describes an emitted event returned by starknet_getEvents
+    #[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+    #[serde(deny_unknown_fields)]
+    pub struct EmittedEvent {
+        pub data: Vec<EventData>,
+        pub keys: Vec<EventKey>,
+        pub from_address: ContractAddress,
+        /// [None] for pending events.
+        pub block_hash: Option<StarknetBlockHash>,
+        /// [None] for pending events.
+        pub block_number: Option<StarknetBlockNumber>,
+        pub transaction_hash: StarknetTransactionHash,
+    }
+
+    impl From<crate::storage::StarknetEmittedEvent> for EmittedEvent {
+        fn from(event: crate::storage::StarknetEmittedEvent) -> Self {
+            Self {
+                data: event.data,
+                keys: event.keys,
+                from_address: event.from_address,
+                block_hash: Some(event.block_hash),
+                block_number: Some(event.block_number),
+                transaction_hash: event.transaction_hash,
+            }
+        }
+    }
+
+    impl From<crate::rpc::v02::pending::PendingEvent> for EmittedEvent {
+        fn from(event: crate::rpc::v02::pending::PendingEvent) -> Self {
+            Self {
+                data: event.data,
+                keys: event.keys,
+                from_address: event.from_address,
+                block_hash: None,
+                block_number: None,
+                transaction_hash: event.transaction_hash,
+            }
+        }
+    }
+
+    // Result type for starknet_getEvents
+    #[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+    #[serde(deny_unknown_fields)]
+    pub struct GetEventsResult {
+        pub events: Vec<EmittedEvent>,
+        /// Absent on the last chunk.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub continuation_token: Option<String>,
+    }
+
+    impl GetEventsResult {
+        pub(super) fn new(
+            events: Vec<EmittedEvent>,
+            page_number: usize,
+            is_last_page: bool,
+        ) -> Self {
+            Self {
+                events,
+                continuation_token: (!is_last_page).then(|| (page_number + 1).to_string()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::types::{EmittedEvent, GetEventsResult};
+    use super::*;
+    use crate::core::{Chain, StarknetBlockHash};
+    use crate::state::SyncState;
+    use crate::storage::test_utils;
+    use crate::{starkhash, starkhash_bytes};
+    use std::sync::Arc;
+
+    fn setup() -> (RpcContext, Vec<EmittedEvent>) {
+        let (storage, events) = test_utils::setup_test_storage();
+        let events = events.into_iter().map(EmittedEvent::from).collect();
+        let context = RpcContext::new(storage, Arc::new(SyncState::default()), Chain::Testnet);
+        (context, events)
+    }
+
+    fn filter(chunk_size: usize) -> EventFilter {
+        EventFilter {
+            from_block: None,
+            to_block: None,
+            address: None,
+            keys: vec![],
+            only_accepted: false,
+            chunk_size,
+            continuation_token: None,
+        }
+    }
+
+    /// Requests all chunks by following the continuation tokens.
+    async fn get_all_chunks(context: &RpcContext, mut filter: EventFilter) -> Vec<GetEventsResult> {
+        let mut chunks = Vec::new();
+        loop {
+            let chunk = get_events(
+                context.clone(),
+                GetEventsInput {
+                    filter: filter.clone(),
+                },
+            )
+            .await
+            .unwrap();
+            filter.continuation_token = chunk.continuation_token.clone();
+            chunks.push(chunk);
+            if filter.continuation_token.is_none() {
+                return chunks;
+            }
+        }
+    }
+
+    #[test]
+    fn parsing() {
+        use jsonrpsee::types::Params;
+
+        let named = r#"{"filter": {
+            "from_block": {"block_number": 1},
+            "to_block": "pending",
+            "address": "0x1",
+            "keys": ["0x2"],
+            "only_accepted": true,
+            "chunk_size": 10,
+            "continuation_token": "3"
+        }}"#;
+        let input = Params::new(Some(named)).parse::<GetEventsInput>().unwrap();
+        let expected = GetEventsInput {
+            filter: EventFilter {
+                from_block: Some(StarknetBlockNumber::new_or_panic(1).into()),
+                to_block: Some(BlockId::Pending),
+                address: Some(ContractAddress::new_or_panic(starkhash!("01"))),
+                keys: vec![EventKey(starkhash!("02"))],
+                only_accepted: true,
+                chunk_size: 10,
+                continuation_token: Some("3".to_owned()),
+            },
+        };
+        assert_eq!(input, expected);
+
+        let positional = r#"[{"chunk_size": 10}]"#;
+        let input = Params::new(Some(positional))
+            .parse::<GetEventsInput>()
+            .unwrap();
+        assert_eq!(input, GetEventsInput { filter: filter(10) });
+    }
+
+    #[tokio::test]
+    async fn paging_across_blocks() {
+        let (context, events) = setup();
+
+        // Chunks which do not line up with block boundaries.
+        let chunk_size = test_utils::EVENTS_PER_BLOCK + 1;
+        let chunks = get_all_chunks(&context, filter(chunk_size)).await;
+
+        assert_eq!(chunks.len(), (events.len() + chunk_size - 1) / chunk_size);
+        for (i, chunk) in chunks.iter().enumerate() {
+            let expected_token = (i + 1 < chunks.len()).then(|| (i + 1).to_string());
+            assert_eq!(chunk.continuation_token, expected_token);
+        }
+        let paged = chunks
+            .into_iter()
+            .flat_map(|chunk| chunk.events)
+            .collect::<Vec<_>>();
+        assert_eq!(paged, events);
+    }
+
+    #[tokio::test]
+    async fn block_range() {
+        let (context, events) = setup();
+        let blocks = test_utils::create_blocks();
+
+        let filter = EventFilter {
+            // The hash is resolved to a block number.
+            from_block: Some(blocks[1].hash.into()),
+            to_block: Some(blocks[2].number.into()),
+            ..filter(test_utils::NUM_EVENTS)
+        };
+        let result = get_events(context, GetEventsInput { filter })
+            .await
+            .unwrap();
+
+        let expected = &events[test_utils::EVENTS_PER_BLOCK..test_utils::EVENTS_PER_BLOCK * 3];
+        assert_eq!(result.events, expected);
+        assert_eq!(result.continuation_token, None);
+    }
+
+    #[tokio::test]
+    async fn latest() {
+        let (context, events) = setup();
+
+        let filter = EventFilter {
+            from_block: Some(BlockId::Latest),
+            to_block: Some(BlockId::Latest),
+            ..filter(test_utils::NUM_EVENTS)
+        };
+        let result = get_events(context, GetEventsInput { filter })
+            .await
+            .unwrap();
+
+        let expected = &events[events.len() - test_utils::EVENTS_PER_BLOCK..];
+        assert_eq!(result.events, expected);
+    }
+
+    #[tokio::test]
+    async fn inverted_block_range() {
+        let (context, _) = setup();
+
+        let filter = EventFilter {
+            from_block: Some(BlockId::Latest),
+            to_block: Some(BlockId::Number(StarknetBlockNumber::GENESIS)),
+            ..filter(test_utils::NUM_EVENTS)
+        };
+        let result = get_events(context, GetEventsInput { filter })
+            .await
+            .unwrap();
+
+        assert_eq!(result, GetEventsResult::new(vec![], 0, true));
+    }
+
+    #[tokio::test]
+    async fn only_accepted() {
+        use crate::sequencer::reply::transaction::ExecutionStatus;
+        use crate::storage::{
+            CanonicalBlocksTable, StarknetBlocksTable, StarknetTransactionsTable, Storage,
+        };
+
+        let storage = Storage::in_memory().unwrap();
+        let mut connection = storage.writer().unwrap();
+        let tx = connection.transaction().unwrap();
+        let block = test_utils::create_blocks()[0].clone();
+        StarknetBlocksTable::insert(&tx, &block, None).unwrap();
+        CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
+        let mut transactions = test_utils::create_transactions_and_receipts()[..2].to_vec();
+        transactions[0].1.execution_status = ExecutionStatus::Reverted;
+        StarknetTransactionsTable::upsert(&tx, block.hash, block.number, &transactions).unwrap();
+        tx.commit().unwrap();
+        drop(connection);
+
+        let events = test_utils::extract_events(&[block], &transactions)
+            .into_iter()
+            .map(EmittedEvent::from)
+            .collect::<Vec<_>>();
+        let context = RpcContext::new(storage, Arc::new(SyncState::default()), Chain::Testnet);
+
+        let result = get_events(context.clone(), GetEventsInput { filter: filter(10) })
+            .await
+            .unwrap();
+        assert_eq!(result.events, events);
+
+        let filter = EventFilter {
+            only_accepted: true,
+            ..filter(10)
+        };
+        let result = get_events(context, GetEventsInput { filter })
+            .await
+            .unwrap();
+        assert_eq!(result.events, events[1..]);
+    }
+
+    #[tokio::test]
+    async fn pending_appended_to_database_events() {
+        let context = RpcContext::for_tests_with_pending().await;
+
+        let stored = EventFilter {
+            to_block: Some(BlockId::Latest),
+            ..filter(StarknetEventsTable::PAGE_SIZE_LIMIT)
+        };
+        let stored = get_events(context.clone(), GetEventsInput { filter: stored })
+            .await
+            .unwrap()
+            .events;
+
+        let filter = EventFilter {
+            to_block: Some(BlockId::Pending),
+            ..filter(2)
+        };
+        let paged = get_all_chunks(&context, filter)
+            .await
+            .into_iter()
+            .flat_map(|chunk| chunk.events)
+            .collect::<Vec<_>>();
+
+        let (paged_stored, pending) = paged.split_at(stored.len());
+        assert_eq!(paged_stored, stored);
+        assert_eq!(pending.len(), 3);
+        assert!(pending
+            .iter()
+            .all(|event| event.block_hash.is_none() && event.block_number.is_none()));
+    }
+
+    #[tokio::test]
+    async fn pending_only() {
+        let context = RpcContext::for_tests_with_pending().await;
+
+        let filter = EventFilter {
+            from_block: Some(BlockId::Pending),
+            to_block: Some(BlockId::Pending),
+            keys: vec![EventKey(starkhash_bytes!(b"pending key"))],
+            ..filter(1)
+        };
+        let chunks = get_all_chunks(&context, filter).await;
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].continuation_token, Some("1".to_owned()));
+        assert!(chunks
+            .iter()
+            .flat_map(|chunk| &chunk.events)
+            .all(|event| event.keys == vec![EventKey(starkhash_bytes!(b"pending key"))]));
+    }
+
+    #[tokio::test]
+    async fn pending_to_non_pending_is_empty() {
+        let context = RpcContext::for_tests_with_pending().await;
+
+        let filter = EventFilter {
+            from_block: Some(BlockId::Pending),
+            to_block: Some(BlockId::Latest),
+            ..filter(10)
+        };
+        let result = get_events(context, GetEventsInput { filter })
+            .await
+            .unwrap();
+        assert_eq!(result.events, vec![]);
+        assert_eq!(result.continuation_token, None);
+    }
+
+    mod errors {
+        use super::*;
+
+        #[tokio::test]
+        async fn block_not_found() {
+            let (context, _) = setup();
+
+            let filter = EventFilter {
+                from_block: Some(StarknetBlockHash(starkhash_bytes!(b"unknown")).into()),
+                ..filter(10)
+            };
+            let result = get_events(context, GetEventsInput { filter }).await;
+            assert_matches::assert_matches!(result, Err(GetEventsError::BlockNotFound));
+        }
+
+        #[tokio::test]
+        async fn page_size_too_big() {
+            let (context, _) = setup();
+
+            let filter = filter(StarknetEventsTable::PAGE_SIZE_LIMIT + 1);
+            let result = get_events(context, GetEventsInput { filter }).await;
+            assert_matches::assert_matches!(result, Err(GetEventsError::PageSizeTooBig));
+        }
+
+        #[tokio::test]
+        async fn too_many_keys() {
+            let (context, _) = setup();
+            let context = RpcContext {
+                max_event_keys: 1,
+                ..context
+            };
+
+            for to_block in [None, Some(BlockId::Pending)] {
+                let filter = EventFilter {
+                    to_block,
+                    keys: vec![EventKey(starkhash!("01")), EventKey(starkhash!("02"))],
+                    ..filter(10)
+                };
+                let result = get_events(context.clone(), GetEventsInput { filter }).await;
+                assert_matches::assert_matches!(result, Err(GetEventsError::TooManyKeysInFilter));
+            }
+        }
+
+        #[tokio::test]
+        async fn max_event_offset() {
+            let (context, _) = setup();
+            let context = RpcContext {
+                max_event_offset: 10,
+                ..context
+            };
+
+            let within = EventFilter {
+                continuation_token: Some("1".to_owned()),
+                ..filter(10)
+            };
+            get_events(context.clone(), GetEventsInput { filter: within })
+                .await
+                .unwrap();
+
+            let beyond = EventFilter {
+                continuation_token: Some("2".to_owned()),
+                ..filter(10)
+            };
+            let result = get_events(context, GetEventsInput { filter: beyond }).await;
+            assert_matches::assert_matches!(result, Err(GetEventsError::InvalidContinuationToken));
+        }
+
+        #[tokio::test]
+        async fn invalid_continuation_token() {
+            let (context, _) = setup();
+
+            for token in ["invalid", "-1", &usize::MAX.to_string()] {
+                let filter = EventFilter {
+                    continuation_token: Some(token.to_owned()),
+                    ..filter(10)
+                };
+                let result = get_events(context.clone(), GetEventsInput { filter }).await;
+                assert_matches::assert_matches!(
+                    result,
+                    Err(GetEventsError::InvalidContinuationToken),
+                    "{token}"
+                );
+            }
+        }
+    }
+}
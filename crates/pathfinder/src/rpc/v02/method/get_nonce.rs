@@ -18,71 +18,36 @@ pub async fn get_nonce(
     context: RpcContext,
     input: GetNonceInput,
 ) -> Result<ContractNonce, GetNonceError> {
-    use crate::state::state_tree::GlobalStateTree;
-    use crate::storage::{StarknetBlocksBlockId, StarknetBlocksTable};
-
-    // We can potentially read the nonce from pending without having to reach out to the database.
-    let block_id = match input.block_id {
-        BlockId::Pending => {
-            match get_pending_nonce(&context.pending_data, input.contract_address).await {
-                Some(nonce) => return Ok(nonce),
-                None => StarknetBlocksBlockId::Latest,
-            }
-        }
-        BlockId::Latest => StarknetBlocksBlockId::Latest,
-        BlockId::Hash(hash) => hash.into(),
-        BlockId::Number(number) => number.into(),
+    use crate::rpc::v02::pending::{
+        pending_state_update, PendingStateReader, StateReader, StorageStateReader,
+    };
+
+    // Pending is served as latest, overlaid with the pending state diff.
+    let pending = match input.block_id {
+        BlockId::Pending => pending_state_update(&context.pending_data).await,
+        _ => None,
     };
 
     let storage = context.storage.clone();
     let span = tracing::Span::current();
     let jh = tokio::task::spawn_blocking(move || -> Result<ContractNonce, GetNonceError> {
         let _g = span.enter();
-        let mut db = storage
-            .connection()
-            .context("Opening database connection")?;
-        let tx = db.transaction().context("Creating database transaction")?;
-
-        let global_root = StarknetBlocksTable::get_root(&tx, block_id)
-            .context("Fetching global root")?
-            .ok_or(GetNonceError::BlockNotFound)?;
-
-        let global_state_tree =
-            GlobalStateTree::load(&tx, global_root).context("Loading global state tree")?;
-
-        let state_hash = global_state_tree
-            .get(input.contract_address)
-            .context("Get contract state hash from global state tree")?;
-
-        // There is a dedicated error code for a non-existent contract in the RPC API spec, so use it.
-        if state_hash.0 == stark_hash::StarkHash::ZERO {
-            return Err(GetNonceError::ContractNotFound);
-        }
-
-        let nonce = crate::storage::ContractsStateTable::get_nonce(&tx, state_hash)
-            .context("Reading contract nonce")?
-            // Since the contract does exist, the nonce should not be missing.
-            .context("Contract nonce is missing from database")?;
-
-        Ok(nonce)
+        storage.read(|tx| {
+            let block_id = input.block_id.resolve(tx)?;
+
+            let state =
+                StorageStateReader::new(tx, block_id)?.ok_or(GetNonceError::BlockNotFound)?;
+            let state = PendingStateReader::new(pending.clone(), state);
+
+            // There is a dedicated error code for a non-existent contract in the RPC API spec, so use it.
+            state
+                .nonce(input.contract_address)?
+                .ok_or(GetNonceError::ContractNotFound)
+        })
     });
     jh.await.context("Database read panic or shutting down")?
 }
 
-/// Returns the contract's pending nonce.
-async fn get_pending_nonce(
-    pending: &Option<crate::state::PendingData>,
-    contract_address: ContractAddress,
-) -> Option<ContractNonce> {
-    match pending {
-        Some(pending) => pending
-            .state_update()
-            .await
-            .and_then(|update| update.state_diff.nonces.get(&contract_address).copied()),
-        None => None,
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::{get_nonce, GetNonceError, GetNonceInput};
@@ -223,7 +188,6 @@ mod tests {
 
     #[tokio::test]
     async fn pending() {
-        use super::get_pending_nonce;
         use std::sync::Arc;
 
         // The data this test actually cares about
@@ -264,18 +228,46 @@ mod tests {
 
         let pending_data = crate::state::PendingData::default();
         pending_data.set(block, state_update).await;
-        let pending_data = Some(pending_data);
+        let context = RpcContext::for_tests().with_pending_data(pending_data);
+
+        let nonce_at = |contract_address| {
+            get_nonce(
+                context.clone(),
+                GetNonceInput {
+                    block_id: BlockId::Pending,
+                    contract_address,
+                },
+            )
+        };
 
-        let result = get_pending_nonce(&pending_data, valid_1).await;
-        assert_eq!(result, Some(nonce_1));
+        assert_eq!(nonce_at(valid_1).await.unwrap(), nonce_1);
+        assert_eq!(nonce_at(valid_2).await.unwrap(), nonce_2);
+        assert_matches::assert_matches!(
+            nonce_at(invalid).await,
+            Err(GetNonceError::ContractNotFound)
+        );
 
-        let result = get_pending_nonce(&pending_data, valid_2).await;
-        assert_eq!(result, Some(nonce_2));
+        // Without pending data, the pending nonces are not visible.
+        let input = GetNonceInput {
+            block_id: BlockId::Pending,
+            contract_address: valid_1,
+        };
+        let result = get_nonce(RpcContext::for_tests(), input).await;
+        assert_matches::assert_matches!(result, Err(GetNonceError::ContractNotFound));
+    }
 
-        let result = get_pending_nonce(&pending_data, invalid).await;
-        assert_eq!(result, None);
+    #[tokio::test]
+    async fn deployed_in_pending() {
+        let context = RpcContext::for_tests_with_pending().await;
 
-        let result = get_pending_nonce(&None, valid_1).await;
-        assert_eq!(result, None);
+        // This contract only exists in the pending block, without an explicit nonce.
+        let input = GetNonceInput {
+            block_id: BlockId::Pending,
+            contract_address: ContractAddress::new_or_panic(starkhash_bytes!(
+                b"pending contract 0 address"
+            )),
+        };
+        let nonce = get_nonce(context, input).await.unwrap();
+        assert_eq!(nonce, ContractNonce::ZERO);
     }
 }
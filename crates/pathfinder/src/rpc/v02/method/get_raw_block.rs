@@ -0,0 +1,135 @@
+use anyhow::Context;
+use serde_json::value::RawValue;
+
+use crate::{core::BlockId, rpc::v02::RpcContext, storage::StarknetBlocksBlockId};
+
+#[derive(serde::Deserialize, Debug, PartialEq, Eq)]
+pub struct GetRawBlockInput {
+    block_id: BlockId,
+}
+
+crate::rpc::error::generate_rpc_error_subset!(GetRawBlockError: BlockNotFound);
+
+/// Returns a block exactly as it was served by the sequencer.
+///
+/// This is a debugging aid which is only useful if the raw block archive is enabled. Blocks are
+/// looked up in the archive only, so that blocks which failed to sync can be inspected as well.
+pub async fn get_raw_block(
+    context: RpcContext,
+    input: GetRawBlockInput,
+) -> Result<Box<RawValue>, GetRawBlockError> {
+    use crate::storage::RawBlocksTable;
+
+    let block_id = match input.block_id {
+        BlockId::Number(number) => StarknetBlocksBlockId::Number(number),
+        BlockId::Hash(hash) => StarknetBlocksBlockId::Hash(hash),
+        BlockId::Latest => StarknetBlocksBlockId::Latest,
+        // Pending blocks are never archived.
+        BlockId::Pending => return Err(GetRawBlockError::BlockNotFound),
+    };
+
+    let storage = context.storage.clone();
+    let span = tracing::Span::current();
+    let jh = tokio::task::spawn_blocking(move || -> Result<Box<RawValue>, GetRawBlockError> {
+        let _g = span.enter();
+        storage.read(|tx| {
+            let raw = RawBlocksTable::get(tx, block_id)
+                .context("Reading raw block")?
+                .ok_or(GetRawBlockError::BlockNotFound)?;
+            let raw = String::from_utf8(raw).context("Raw block is not valid UTF-8")?;
+            let raw = RawValue::from_string(raw).context("Raw block is not valid JSON")?;
+
+            Ok(raw)
+        })
+    });
+    jh.await.context("Database read panic or shutting down")?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_raw_block, GetRawBlockError, GetRawBlockInput};
+
+    use crate::core::{BlockId, StarknetBlockHash, StarknetBlockNumber};
+    use crate::rpc::v02::RpcContext;
+    use crate::starkhash;
+    use crate::storage::{CompressionConfig, RawBlocksTable};
+
+    const RAW_BLOCK: &str = r#"{"block_hash":"0x0abc","block_number":7,"status":"ACCEPTED_ON_L2"}"#;
+
+    fn context_with_raw_block() -> RpcContext {
+        let context = RpcContext::for_tests();
+        let mut connection = context.storage.writer().unwrap();
+        let tx = connection.transaction().unwrap();
+        let mut compressor = CompressionConfig::default().compressor().unwrap();
+        RawBlocksTable::insert(
+            &tx,
+            StarknetBlockNumber::new_or_panic(7),
+            StarknetBlockHash(starkhash!("0abc")),
+            RAW_BLOCK.as_bytes(),
+            &mut compressor,
+        )
+        .unwrap();
+        tx.commit().unwrap();
+        drop(connection);
+
+        context
+    }
+
+    #[test]
+    fn parsing() {
+        use jsonrpsee::types::Params;
+
+        let positional = Params::new(Some(r#"[{ "block_number": 7 }]"#));
+        let input = positional.parse::<GetRawBlockInput>().unwrap();
+        let expected = GetRawBlockInput {
+            block_id: StarknetBlockNumber::new_or_panic(7).into(),
+        };
+        assert_eq!(input, expected);
+    }
+
+    #[tokio::test]
+    async fn verbatim() {
+        let context = context_with_raw_block();
+
+        for block_id in [
+            BlockId::Latest,
+            StarknetBlockNumber::new_or_panic(7).into(),
+            StarknetBlockHash(starkhash!("0abc")).into(),
+        ] {
+            let input = GetRawBlockInput { block_id };
+            let raw = get_raw_block(context.clone(), input).await.unwrap();
+            assert_eq!(raw.get(), RAW_BLOCK);
+        }
+    }
+
+    #[tokio::test]
+    async fn block_not_found() {
+        let context = context_with_raw_block();
+
+        for block_id in [BlockId::Pending, StarknetBlockNumber::GENESIS.into()] {
+            let input = GetRawBlockInput { block_id };
+            let result = get_raw_block(context.clone(), input).await;
+            assert_matches::assert_matches!(result, Err(GetRawBlockError::BlockNotFound));
+        }
+    }
+
+    #[tokio::test]
+    async fn archive_disabled() {
+        // Nothing is archived unless enabled, which is the case for the test storage.
+        let context = RpcContext::for_tests();
+
+        let archived: usize = context
+            .storage
+            .connection()
+            .unwrap()
+            .query_row("SELECT COUNT(1) FROM raw_blocks", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(archived, 0);
+
+        let input = GetRawBlockInput {
+            block_id: BlockId::Latest,
+        };
+        let result = get_raw_block(context, input).await;
+        assert_matches::assert_matches!(result, Err(GetRawBlockError::BlockNotFound));
+    }
+}
@@ -1,10 +1,6 @@
 use anyhow::Context;
 
-use crate::{
-    core::BlockId,
-    rpc::v02::RpcContext,
-    storage::{StarknetBlocksBlockId, StarknetBlocksTable, StarknetStateUpdatesTable},
-};
+use crate::{core::BlockId, rpc::v02::RpcContext, storage::StarknetStateUpdatesTable};
 
 #[derive(serde::Deserialize, Debug, PartialEq, Eq)]
 pub struct GetStateUpdateInput {
@@ -17,53 +13,31 @@ pub async fn get_state_update(
     context: RpcContext,
     input: GetStateUpdateInput,
 ) -> Result<types::StateUpdate, GetStateUpdateError> {
-    let block_id = match input.block_id {
-        BlockId::Pending => {
-            let update = match &context.pending_data {
-                Some(pending) => pending.state_update().await,
-                None => None,
-            };
-            match update {
-                Some(update) => {
-                    let update = update.as_ref().clone().into();
-                    return Ok(update);
-                }
-                None => return Err(GetStateUpdateError::BlockNotFound),
-            }
-        }
-        BlockId::Latest => StarknetBlocksBlockId::Latest,
-        BlockId::Hash(hash) => hash.into(),
-        BlockId::Number(number) => number.into(),
-    };
+    if input.block_id == BlockId::Pending {
+        let update = match &context.pending_data {
+            Some(pending) => pending.state_update().await,
+            None => None,
+        };
+        return match update {
+            Some(update) => Ok(update.as_ref().clone().into()),
+            None => Err(GetStateUpdateError::BlockNotFound),
+        };
+    }
 
     let storage = context.storage.clone();
     let span = tracing::Span::current();
 
     let jh = tokio::task::spawn_blocking(move || {
         let _g = span.enter();
-        let mut db = storage
-            .connection()
-            .context("Opening database connection")?;
-
-        let tx = db.transaction().context("Creating database transaction")?;
-
-        let block_hash = match block_id {
-            StarknetBlocksBlockId::Hash(h) => h,
-            StarknetBlocksBlockId::Number(_) | StarknetBlocksBlockId::Latest => {
-                StarknetBlocksTable::get_hash(
-                    &tx,
-                    block_id.try_into().expect("block_id is not a hash"),
-                )
-                .context("Read block from database")?
-                .ok_or(GetStateUpdateError::BlockNotFound)?
-            }
-        };
+        storage.read(|tx| {
+            let block_hash = input.block_id.resolve_hash(tx)?;
 
-        let state_update = StarknetStateUpdatesTable::get(&tx, block_hash)
-            .context("Read state update from database")?
-            .ok_or(GetStateUpdateError::BlockNotFound)?;
+            let state_update = StarknetStateUpdatesTable::get(tx, block_hash)
+                .context("Read state update from database")?
+                .ok_or(GetStateUpdateError::BlockNotFound)?;
 
-        Ok(state_update.into())
+            Ok(state_update.into())
+        })
     });
 
     jh.await.context("Database read panic or shutting down")?
@@ -401,7 +375,7 @@ mod tests {
 
     fn context_with_state_updates() -> (Vec<StateUpdate>, RpcContext) {
         let storage = crate::storage::Storage::in_memory().unwrap();
-        let mut connection = storage.connection().unwrap();
+        let mut connection = storage.writer().unwrap();
         let tx = connection.transaction().unwrap();
         let state_updates = crate::storage::fixtures::init::with_n_state_updates(&tx, 1);
         tx.commit().unwrap();
@@ -27,13 +27,17 @@ pub async fn get_transaction_by_block_id_and_index(
         .try_into()
         .map_err(|_| GetTransactionByBlockIdAndIndexError::InvalidTxnIndex)?;
 
+    if input.block_id == BlockId::Pending {
+        if let Some(result) = get_transaction_from_pending(context.pending_data, index).await {
+            return result;
+        }
+    }
+
     let block_id = match input.block_id {
         BlockId::Hash(hash) => hash.into(),
         BlockId::Number(number) => number.into(),
-        BlockId::Latest => StarknetBlocksBlockId::Latest,
-        BlockId::Pending => {
-            return get_transaction_from_pending(&context.pending_data, index).await
-        }
+        // Without a pending block, pending is the same as latest.
+        BlockId::Latest | BlockId::Pending => StarknetBlocksBlockId::Latest,
     };
 
     let storage = context.storage.clone();
@@ -41,54 +45,45 @@ pub async fn get_transaction_by_block_id_and_index(
 
     let jh = tokio::task::spawn_blocking(move || {
         let _g = span.enter();
-        let mut db = storage
-            .connection()
-            .context("Opening database connection")?;
-
-        let db_tx = db.transaction().context("Creating database transaction")?;
-
-        // Get the transaction from storage.
-        match StarknetTransactionsTable::get_transaction_at_block(&db_tx, block_id, index)
-            .context("Reading transaction from database")?
-        {
-            Some(transaction) => Ok(transaction.into()),
-            None => {
-                // We now need to check whether it was the block hash or transaction index which were invalid. We do this by checking if the block exists
-                // at all. If no, then the block hash is invalid. If yes, then the index is invalid.
-                //
-                // get_root is cheaper than querying the full block.
-                match StarknetBlocksTable::get_root(&db_tx, block_id)
-                    .context("Reading block from database")?
-                {
-                    Some(_) => Err(GetTransactionByBlockIdAndIndexError::InvalidTxnIndex),
-                    None => Err(GetTransactionByBlockIdAndIndexError::BlockNotFound),
+        storage.read(|db_tx| {
+            // Get the transaction from storage.
+            match StarknetTransactionsTable::get_transaction_at_block(db_tx, block_id, index)
+                .context("Reading transaction from database")?
+            {
+                Some(transaction) => Ok(transaction.into()),
+                None => {
+                    // We now need to check whether it was the block hash or transaction index which were invalid. We do this by checking if the block exists
+                    // at all. If no, then the block hash is invalid. If yes, then the index is invalid.
+                    //
+                    // get_root is cheaper than querying the full block.
+                    match StarknetBlocksTable::get_root(db_tx, block_id)
+                        .context("Reading block from database")?
+                    {
+                        Some(_) => Err(GetTransactionByBlockIdAndIndexError::InvalidTxnIndex),
+                        None => Err(GetTransactionByBlockIdAndIndexError::BlockNotFound),
+                    }
                 }
             }
-        }
+        })
     });
 
     jh.await.context("Database read panic or shutting down")?
 }
 
+/// Returns the transaction at `index` in the pending block, or [None] if there is no pending block.
 async fn get_transaction_from_pending(
-    pending: &Option<crate::state::PendingData>,
+    pending: Option<crate::state::PendingData>,
     index: usize,
-) -> Result<Transaction, GetTransactionByBlockIdAndIndexError> {
-    // We return InvalidTxnIndex even if the pending block is technically missing.
-    // The absence of the pending block should be transparent to the end-user so
-    // we effectively handle it as an empty pending block.
-    match pending {
-        Some(pending) => pending.block().await.map_or_else(
-            || Err(GetTransactionByBlockIdAndIndexError::InvalidTxnIndex),
-            |block| {
-                block.transactions.get(index).map_or(
-                    Err(GetTransactionByBlockIdAndIndexError::InvalidTxnIndex),
-                    |txn| Ok(txn.into()),
-                )
-            },
-        ),
-        None => Err(GetTransactionByBlockIdAndIndexError::InvalidTxnIndex),
-    }
+) -> Option<Result<Transaction, GetTransactionByBlockIdAndIndexError>> {
+    let block = pending?.block().await?;
+
+    let result = block
+        .transactions
+        .get(index)
+        .map(Into::into)
+        .ok_or(GetTransactionByBlockIdAndIndexError::InvalidTxnIndex);
+
+    Some(result)
 }
 
 #[cfg(test)]
@@ -256,4 +251,69 @@ mod tests {
             .unwrap();
         assert_eq!(result, expected);
     }
+
+    mod pending {
+        use super::*;
+
+        #[tokio::test]
+        async fn invalid_index() {
+            let context = RpcContext::for_tests_with_pending().await;
+            let count = context
+                .pending_data
+                .as_ref()
+                .unwrap()
+                .block()
+                .await
+                .unwrap()
+                .transactions
+                .len();
+
+            let input = GetTransactionByBlockIdAndIndexInput {
+                block_id: BlockId::Pending,
+                index: StarknetTransactionIndex::new_or_panic(count as u64),
+            };
+
+            let result = get_transaction_by_block_id_and_index(context, input).await;
+
+            assert_matches::assert_matches!(
+                result,
+                Err(GetTransactionByBlockIdAndIndexError::InvalidTxnIndex)
+            );
+        }
+
+        #[tokio::test]
+        async fn no_pending_data_is_latest() {
+            let context = RpcContext::for_tests();
+            let input = GetTransactionByBlockIdAndIndexInput {
+                block_id: BlockId::Pending,
+                index: StarknetTransactionIndex::new_or_panic(0),
+            };
+
+            let result = get_transaction_by_block_id_and_index(context, input)
+                .await
+                .unwrap();
+            assert_eq!(
+                result.hash(),
+                StarknetTransactionHash(starkhash_bytes!(b"txn 3"))
+            );
+        }
+
+        #[tokio::test]
+        async fn no_pending_block_is_latest() {
+            let context =
+                RpcContext::for_tests().with_pending_data(crate::state::PendingData::default());
+            let input = GetTransactionByBlockIdAndIndexInput {
+                block_id: BlockId::Pending,
+                index: StarknetTransactionIndex::new_or_panic(0),
+            };
+
+            let result = get_transaction_by_block_id_and_index(context, input)
+                .await
+                .unwrap();
+            assert_eq!(
+                result.hash(),
+                StarknetTransactionHash(starkhash_bytes!(b"txn 3"))
+            );
+        }
+    }
 }
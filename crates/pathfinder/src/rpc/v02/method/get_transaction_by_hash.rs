@@ -36,17 +36,13 @@ pub async fn get_transaction_by_hash(
 
     let jh = tokio::task::spawn_blocking(move || {
         let _g = span.enter();
-        let mut db = storage
-            .connection()
-            .context("Opening database connection")?;
-
-        let db_tx = db.transaction().context("Creating database transaction")?;
-
-        // Get the transaction from storage.
-        StarknetTransactionsTable::get_transaction(&db_tx, input.transaction_hash)
-            .context("Reading transaction from database")?
-            .ok_or(GetTransactionByHashError::TxnHashNotFound)
-            .map(|tx| tx.into())
+        storage.read(|db_tx| {
+            // Get the transaction from storage.
+            StarknetTransactionsTable::get_transaction(db_tx, input.transaction_hash)
+                .context("Reading transaction from database")?
+                .ok_or(GetTransactionByHashError::TxnHashNotFound)
+                .map(|tx| tx.into())
+        })
     });
 
     jh.await.context("Database read panic or shutting down")?
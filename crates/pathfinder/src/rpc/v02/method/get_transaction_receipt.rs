@@ -42,41 +42,37 @@ pub async fn get_transaction_receipt(
 
     let jh = tokio::task::spawn_blocking(move || {
         let _g = span.enter();
-        let mut db = storage
-            .connection()
-            .context("Opening database connection")?;
-
-        let db_tx = db.transaction().context("Creating database transaction")?;
-
-        match StarknetTransactionsTable::get_receipt(&db_tx, input.transaction_hash)
-            .context("Reading transaction receipt from database")?
-        {
-            Some((receipt, block_hash)) => {
-                // We require the block status here as well..
-                let block = StarknetBlocksTable::get(&db_tx, block_hash.into())
-                    .context("Reading block from database")?
-                    .context("Block missing from database")?;
-
-                let block_status = get_block_status(&db_tx, block.number)?;
-
-                // We require the transaction so that we can return the right RPC type for the receipt.
-                match StarknetTransactionsTable::get_transaction(&db_tx, input.transaction_hash)
-                    .context("Reading transaction from database")?
-                {
-                    Some(transaction) => Ok(types::MaybePendingTransactionReceipt::Normal(
-                        types::TransactionReceipt::with_block_data(
-                            receipt,
-                            block_status,
-                            block.hash,
-                            block.number,
-                            &transaction,
-                        ),
-                    )),
-                    None => Err(GetTransactionReceiptError::TxnHashNotFound),
+        storage.read(|db_tx| {
+            match StarknetTransactionsTable::get_receipt(db_tx, input.transaction_hash)
+                .context("Reading transaction receipt from database")?
+            {
+                Some((receipt, block_hash)) => {
+                    // We require the block status here as well..
+                    let block = StarknetBlocksTable::get(db_tx, block_hash.into())
+                        .context("Reading block from database")?
+                        .context("Block missing from database")?;
+
+                    let block_status = get_block_status(db_tx, block.number)?;
+
+                    // We require the transaction so that we can return the right RPC type for the receipt.
+                    match StarknetTransactionsTable::get_transaction(db_tx, input.transaction_hash)
+                        .context("Reading transaction from database")?
+                    {
+                        Some(transaction) => Ok(types::MaybePendingTransactionReceipt::Normal(
+                            types::TransactionReceipt::with_block_data(
+                                receipt,
+                                block_status,
+                                block.hash,
+                                block.number,
+                                &transaction,
+                            ),
+                        )),
+                        None => Err(GetTransactionReceiptError::TxnHashNotFound),
+                    }
                 }
+                None => Err(GetTransactionReceiptError::TxnHashNotFound),
             }
-            None => Err(GetTransactionReceiptError::TxnHashNotFound),
-        }
+        })
     });
 
     jh.await.context("Database read panic or shutting down")?
@@ -98,7 +94,7 @@ fn get_block_status(
     Ok(block_status)
 }
 
-mod types {
+pub(super) mod types {
     use serde::Serialize;
     use serde_with::serde_as;
 
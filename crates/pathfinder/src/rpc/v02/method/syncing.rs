@@ -1,9 +1,13 @@
+use anyhow::Context;
+
 use crate::{
-    core::{StarknetBlockHash, StarknetBlockNumber},
+    core::{GlobalRoot, StarknetBlockHash, StarknetBlockNumber},
     rpc::{serde::StarknetBlockNumberAsHexStr, v02::RpcContext},
+    storage::L1ConfirmedHead,
 };
 
 use serde::Serialize;
+use web3::types::H256;
 
 crate::rpc::error::generate_rpc_error_subset!(SyncingError);
 
@@ -16,6 +20,7 @@ pub async fn syncing(context: RpcContext) -> Result<SyncingOuput, SyncingError>
     let value = match value {
         Syncing::False(_) => SyncingOuput::False,
         Syncing::Status(status) => {
+            let l1_confirmed = l1_confirmed_head(&context).await?;
             let status = SyncingStatus {
                 starting_block_num: status.starting.number,
                 current_block_num: status.current.number,
@@ -23,6 +28,7 @@ pub async fn syncing(context: RpcContext) -> Result<SyncingOuput, SyncingError>
                 starting_block_hash: status.starting.hash,
                 current_block_hash: status.current.hash,
                 highest_block_hash: status.highest.hash,
+                l1_confirmed: l1_confirmed.map(|head| Box::new(head.into())),
             };
             SyncingOuput::Status(status)
         }
@@ -31,7 +37,22 @@ pub async fn syncing(context: RpcContext) -> Result<SyncingOuput, SyncingError>
     Ok(value)
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+async fn l1_confirmed_head(context: &RpcContext) -> Result<Option<L1ConfirmedHead>, SyncingError> {
+    let storage = context.storage.clone();
+    let span = tracing::Span::current();
+    let jh = tokio::task::spawn_blocking(move || {
+        let _g = span.enter();
+        storage.read(|tx| {
+            let head = crate::storage::get_l1_confirmed_head(tx)
+                .context("Reading L1 confirmed head from database")?;
+
+            Ok(head)
+        })
+    });
+    jh.await.context("Database read panic or shutting down")?
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(any(test, feature = "rpc-full-serde"), derive(serde::Deserialize))]
 pub enum SyncingOuput {
     False,
@@ -51,7 +72,7 @@ impl Serialize for SyncingOuput {
 }
 
 #[serde_with::serde_as]
-#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
 #[cfg_attr(any(test, feature = "rpc-full-serde"), derive(serde::Deserialize))]
 pub struct SyncingStatus {
     #[serde_as(as = "StarknetBlockNumberAsHexStr")]
@@ -63,6 +84,32 @@ pub struct SyncingStatus {
     starting_block_hash: StarknetBlockHash,
     current_block_hash: StarknetBlockHash,
     highest_block_hash: StarknetBlockHash,
+    /// The highest block confirmed on L1, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    l1_confirmed: Option<Box<L1ConfirmedBlock>>,
+}
+
+/// The highest block confirmed on L1, along with the Ethereum transaction which confirmed it.
+#[serde_with::serde_as]
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[cfg_attr(any(test, feature = "rpc-full-serde"), derive(serde::Deserialize))]
+pub struct L1ConfirmedBlock {
+    #[serde_as(as = "StarknetBlockNumberAsHexStr")]
+    block_num: StarknetBlockNumber,
+    block_hash: StarknetBlockHash,
+    global_root: GlobalRoot,
+    ethereum_transaction_hash: H256,
+}
+
+impl From<L1ConfirmedHead> for L1ConfirmedBlock {
+    fn from(head: L1ConfirmedHead) -> Self {
+        Self {
+            block_num: head.block.number,
+            block_hash: head.block.hash,
+            global_root: head.block.root,
+            ethereum_transaction_hash: head.proof.origin.transaction.hash.0,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -90,6 +137,7 @@ mod tests {
                 starting_block_hash: StarknetBlockHash(starkhash!("abcdef")),
                 current_block_hash: StarknetBlockHash(starkhash!("12345677")),
                 highest_block_hash: StarknetBlockHash(starkhash!("1144ffaacc")),
+                l1_confirmed: None,
             };
             let value = SyncingOuput::Status(status);
             let json = serde_json::to_value(value).unwrap();
@@ -105,6 +153,48 @@ mod tests {
 
             assert_eq!(json, expected);
         }
+
+        #[test]
+        fn syncing_with_l1_confirmed() {
+            use super::super::L1ConfirmedBlock;
+            use crate::core::{GlobalRoot, StarknetBlockHash, StarknetBlockNumber};
+            use crate::starkhash;
+            use web3::types::H256;
+
+            let status = SyncingStatus {
+                starting_block_num: StarknetBlockNumber::new_or_panic(0x12),
+                current_block_num: StarknetBlockNumber::new_or_panic(0x45),
+                highest_block_num: StarknetBlockNumber::new_or_panic(0x772),
+                starting_block_hash: StarknetBlockHash(starkhash!("abcdef")),
+                current_block_hash: StarknetBlockHash(starkhash!("12345677")),
+                highest_block_hash: StarknetBlockHash(starkhash!("1144ffaacc")),
+                l1_confirmed: Some(Box::new(L1ConfirmedBlock {
+                    block_num: StarknetBlockNumber::new_or_panic(0x40),
+                    block_hash: StarknetBlockHash(starkhash!("bbccdd")),
+                    global_root: GlobalRoot(starkhash!("eeff")),
+                    ethereum_transaction_hash: H256::from_low_u64_be(0x999),
+                })),
+            };
+            let value = SyncingOuput::Status(status);
+            let json = serde_json::to_value(value).unwrap();
+
+            let expected = serde_json::json!( {
+                "starting_block_num": "0x12",
+                "current_block_num": "0x45",
+                "highest_block_num": "0x772",
+                "starting_block_hash": "0xabcdef",
+                "current_block_hash": "0x12345677",
+                "highest_block_hash": "0x1144ffaacc",
+                "l1_confirmed": {
+                    "block_num": "0x40",
+                    "block_hash": "0xbbccdd",
+                    "global_root": "0xeeff",
+                    "ethereum_transaction_hash": "0x0000000000000000000000000000000000000000000000000000000000000999",
+                },
+            });
+
+            assert_eq!(json, expected);
+        }
     }
 
     #[tokio::test]
@@ -127,6 +217,7 @@ mod tests {
             starting_block_hash: StarknetBlockHash(crate::starkhash!("aabb")),
             current_block_hash: StarknetBlockHash(crate::starkhash!("ccddee")),
             highest_block_hash: StarknetBlockHash(crate::starkhash!("eeffaacc")),
+            l1_confirmed: None,
         };
         let expected = SyncingOuput::Status(expected);
 
@@ -138,6 +229,65 @@ mod tests {
         assert_eq!(result, expected);
     }
 
+    #[tokio::test]
+    async fn syncing_with_l1_confirmed_head() {
+        use crate::ethereum::{log::StateUpdateLog, BlockOrigin, EthOrigin, TransactionOrigin};
+        use crate::rpc::v01::types::reply::syncing::NumberedBlock;
+        use crate::rpc::v01::types::reply::syncing::Status as V1Status;
+        use crate::rpc::v01::types::reply::Syncing as V1Syncing;
+        use crate::storage::{L1StateTable, RefsTable, StarknetBlocksTable};
+        use web3::types::H256;
+
+        let context = RpcContext::for_tests();
+
+        let mut db = context.storage.writer().unwrap();
+        let tx = db.transaction().unwrap();
+        let block = StarknetBlocksTable::get(&tx, crate::storage::StarknetBlocksBlockId::Latest)
+            .unwrap()
+            .unwrap();
+        let ethereum_transaction_hash = H256::from_low_u64_be(0x999);
+        let update = StateUpdateLog {
+            origin: EthOrigin {
+                block: BlockOrigin {
+                    hash: crate::core::EthereumBlockHash(H256::from_low_u64_be(0x33)),
+                    number: crate::core::EthereumBlockNumber(100),
+                },
+                transaction: TransactionOrigin {
+                    hash: crate::core::EthereumTransactionHash(ethereum_transaction_hash),
+                    index: crate::core::EthereumTransactionIndex(2),
+                },
+                log_index: crate::core::EthereumLogIndex(5),
+            },
+            global_root: block.root,
+            block_number: block.number,
+        };
+        L1StateTable::upsert(&tx, &update).unwrap();
+        RefsTable::set_l1_l2_head(&tx, Some(block.number)).unwrap();
+        tx.commit().unwrap();
+
+        let status = V1Syncing::Status(V1Status {
+            starting: NumberedBlock::from(("aabb", 1)),
+            current: NumberedBlock::from(("ccddee", 2)),
+            highest: NumberedBlock::from(("eeffaacc", 3)),
+        });
+        *context.sync_status.status.write().await = status;
+
+        let result = super::syncing(context).await.unwrap();
+
+        let expected = super::L1ConfirmedBlock {
+            block_num: block.number,
+            block_hash: block.hash,
+            global_root: block.root,
+            ethereum_transaction_hash,
+        };
+        match result {
+            SyncingOuput::Status(status) => {
+                assert_eq!(status.l1_confirmed, Some(Box::new(expected)))
+            }
+            SyncingOuput::False => panic!("Expected a syncing status"),
+        }
+    }
+
     #[tokio::test]
     async fn not_syncing() {
         let status = crate::rpc::v01::types::reply::Syncing::False(false);
@@ -0,0 +1,432 @@
+//! Reading contract state and events as of the pending block.
+//!
+//! The pending block only carries a state diff, so any query it does not answer has to be
+//! served from the latest block in storage instead.
+use std::sync::Arc;
+
+use anyhow::Context;
+use rusqlite::{OptionalExtension, Transaction};
+use stark_hash::StarkHash;
+
+use crate::core::{
+    ClassHash, ContractAddress, ContractNonce, ContractStateHash, EventData, EventKey,
+    StarknetBlockHash, StarknetBlockNumber, StarknetTransactionHash, StorageAddress, StorageValue,
+};
+use crate::sequencer::reply::StateUpdate;
+use crate::state::state_tree::{ContractsStateTree, GlobalStateTree};
+use crate::state::PendingData;
+use crate::storage::{
+    ContractsStateTable, StarknetBlocksBlockId, StarknetBlocksTable, StarknetEmittedEvent,
+    StarknetEventFilter, StarknetEventsTable,
+};
+
+/// Answers contract state queries as of a specific block.
+///
+/// All queries return `None` if the contract does not exist at that block.
+pub trait StateReader {
+    fn nonce(&self, contract: ContractAddress) -> anyhow::Result<Option<ContractNonce>>;
+
+    fn class_hash(&self, contract: ContractAddress) -> anyhow::Result<Option<ClassHash>>;
+
+    fn storage(
+        &self,
+        contract: ContractAddress,
+        key: StorageAddress,
+    ) -> anyhow::Result<Option<StorageValue>>;
+}
+
+/// A [StateReader] backed by the global state tree of a block in storage.
+pub struct StorageStateReader<'tx> {
+    tx: &'tx Transaction<'tx>,
+    tree: GlobalStateTree<'tx, 'tx>,
+}
+
+impl<'tx> StorageStateReader<'tx> {
+    /// Returns `None` if the block does not exist.
+    pub fn new(
+        tx: &'tx Transaction<'tx>,
+        block: StarknetBlocksBlockId,
+    ) -> anyhow::Result<Option<Self>> {
+        let global_root = match StarknetBlocksTable::get_root(tx, block)
+            .context("Reading global root from database")?
+        {
+            Some(root) => root,
+            None => return Ok(None),
+        };
+
+        let tree = GlobalStateTree::load(tx, global_root).context("Loading global state tree")?;
+
+        Ok(Some(Self { tx, tree }))
+    }
+
+    /// Returns the contract's state hash, or `None` if it does not exist in this block.
+    fn state_hash(&self, contract: ContractAddress) -> anyhow::Result<Option<ContractStateHash>> {
+        let state_hash = self
+            .tree
+            .get(contract)
+            .context("Fetching contract leaf in global tree")?;
+
+        Ok((state_hash.0 != StarkHash::ZERO).then_some(state_hash))
+    }
+}
+
+impl StateReader for StorageStateReader<'_> {
+    fn nonce(&self, contract: ContractAddress) -> anyhow::Result<Option<ContractNonce>> {
+        let state_hash = match self.state_hash(contract)? {
+            Some(state_hash) => state_hash,
+            None => return Ok(None),
+        };
+
+        ContractsStateTable::get_nonce(self.tx, state_hash)
+            .context("Reading contract nonce")?
+            // Since the contract does exist, the nonce should not be missing.
+            .context("Contract nonce is missing from database")
+            .map(Some)
+    }
+
+    fn class_hash(&self, contract: ContractAddress) -> anyhow::Result<Option<ClassHash>> {
+        let state_hash = match self.state_hash(contract)? {
+            Some(state_hash) => state_hash,
+            None => return Ok(None),
+        };
+
+        // The class hash could also be read from the `contracts` table, but going through the
+        // state tree keeps this consistent with the block being queried.
+        self.tx
+            .query_row(
+                "SELECT hash FROM contract_states WHERE state_hash=?",
+                [state_hash],
+                |row| row.get(0),
+            )
+            .optional()
+            .context("Reading class hash from state table")?
+            // Class hash should not be None at this stage since we have a valid block and non-zero contract state_hash.
+            .with_context(|| format!("State table missing row for state_hash={}", state_hash))
+            .map(Some)
+    }
+
+    fn storage(
+        &self,
+        contract: ContractAddress,
+        key: StorageAddress,
+    ) -> anyhow::Result<Option<StorageValue>> {
+        let state_hash = match self.state_hash(contract)? {
+            Some(state_hash) => state_hash,
+            None => return Ok(None),
+        };
+
+        let contract_root = ContractsStateTable::get_root(self.tx, state_hash)
+            .context("Reading contract state root")?
+            .with_context(|| {
+                format!("Contract state root missing for state_hash={}", state_hash)
+            })?;
+
+        let tree = ContractsStateTree::load(self.tx, contract_root)
+            .context("Loading contract state tree")?;
+
+        tree.get(key).context("Reading storage value").map(Some)
+    }
+}
+
+/// A [StateReader] which overlays the pending state diff on top of another [StateReader],
+/// typically a [StorageStateReader] for the latest block.
+///
+/// Contracts deployed in the pending block are served entirely from the pending diff, since they
+/// are not yet known to storage.
+pub struct PendingStateReader<R> {
+    state_update: Option<Arc<StateUpdate>>,
+    fallback: R,
+}
+
+impl<R: StateReader> PendingStateReader<R> {
+    /// Without a pending `state_update` all queries are answered by `fallback`.
+    pub fn new(state_update: Option<Arc<StateUpdate>>, fallback: R) -> Self {
+        Self {
+            state_update,
+            fallback,
+        }
+    }
+
+    /// Returns the class hash of the contract if it was deployed in the pending block.
+    fn pending_deployment(&self, contract: ContractAddress) -> Option<ClassHash> {
+        self.state_update.as_ref().and_then(|update| {
+            update
+                .state_diff
+                .deployed_contracts
+                .iter()
+                .find_map(|deployed| (deployed.address == contract).then_some(deployed.class_hash))
+        })
+    }
+}
+
+impl<R: StateReader> StateReader for PendingStateReader<R> {
+    fn nonce(&self, contract: ContractAddress) -> anyhow::Result<Option<ContractNonce>> {
+        let pending = self
+            .state_update
+            .as_ref()
+            .and_then(|update| update.state_diff.nonces.get(&contract).copied());
+
+        match pending {
+            Some(nonce) => Ok(Some(nonce)),
+            None if self.pending_deployment(contract).is_some() => Ok(Some(ContractNonce::ZERO)),
+            None => self.fallback.nonce(contract),
+        }
+    }
+
+    fn class_hash(&self, contract: ContractAddress) -> anyhow::Result<Option<ClassHash>> {
+        match self.pending_deployment(contract) {
+            Some(class_hash) => Ok(Some(class_hash)),
+            None => self.fallback.class_hash(contract),
+        }
+    }
+
+    fn storage(
+        &self,
+        contract: ContractAddress,
+        key: StorageAddress,
+    ) -> anyhow::Result<Option<StorageValue>> {
+        let pending = self.state_update.as_ref().and_then(|update| {
+            update
+                .state_diff
+                .storage_diffs
+                .get(&contract)
+                .and_then(|diffs| diffs.iter().find_map(|d| (d.key == key).then_some(d.value)))
+        });
+
+        match pending {
+            Some(value) => Ok(Some(value)),
+            None if self.pending_deployment(contract).is_some() => {
+                Ok(Some(StorageValue(StarkHash::ZERO)))
+            }
+            None => self.fallback.storage(contract, key),
+        }
+    }
+}
+
+/// Returns the pending state update, if pending data is available.
+pub async fn pending_state_update(pending: &Option<PendingData>) -> Option<Arc<StateUpdate>> {
+    match pending {
+        Some(pending) => pending.state_update().await,
+        None => None,
+    }
+}
+
+/// An event of the pending block, which has neither a hash nor a number yet.
+pub struct PendingEvent {
+    pub data: Vec<EventData>,
+    pub keys: Vec<EventKey>,
+    pub from_address: ContractAddress,
+    pub transaction_hash: StarknetTransactionHash,
+}
+
+/// Appends the events of the pending block emitted by `address` with any of the `keys` to `dst`,
+/// after skipping the first `skip` of them. At most `amount` events are appended.
+///
+/// The events are matched by [StarknetEventsTable::filter_pending], as the database query would.
+/// If `only_accepted` is set, the events of reverted transactions are skipped.
+///
+/// Returns true if there are no more matching events, i.e. this is the last page.
+pub async fn append_pending_events<T: From<PendingEvent>>(
+    pending: &Option<PendingData>,
+    dst: &mut Vec<T>,
+    skip: usize,
+    amount: usize,
+    address: Option<ContractAddress>,
+    keys: &[EventKey],
+    only_accepted: bool,
+) -> bool {
+    let pending_block = match pending {
+        Some(data) => match data.block().await {
+            Some(block) => block,
+            None => return true,
+        },
+        None => return true,
+    };
+
+    let filter = StarknetEventFilter {
+        from_block: None,
+        to_block: None,
+        contract_address: address,
+        keys: keys.to_vec(),
+        data_filter: vec![],
+        page_size: amount,
+        page_number: 0,
+        detect_last_page: true,
+        count_pages: false,
+        only_accepted: false,
+    };
+    // The filter has no block range, so the block these are attributed to does not matter.
+    let events = StarknetEmittedEvent::from_receipts(
+        StarknetBlockHash(StarkHash::ZERO),
+        StarknetBlockNumber::GENESIS,
+        pending_block
+            .transaction_receipts
+            .iter()
+            .filter(|receipt| !only_accepted || receipt.execution_status.is_succeeded()),
+    );
+
+    let original_len = dst.len();
+
+    let pending_events = StarknetEventsTable::filter_pending(&events, &filter)
+        .into_iter()
+        .skip(skip)
+        // We need to take an extra event to determine is_last_page.
+        .take(amount + 1)
+        .map(|event| {
+            T::from(PendingEvent {
+                data: event.data,
+                keys: event.keys,
+                from_address: event.from_address,
+                transaction_hash: event.transaction_hash,
+            })
+        });
+
+    dst.extend(pending_events);
+    let is_last_page = dst.len() <= (amount + original_len);
+    if !is_last_page {
+        dst.pop();
+    }
+
+    is_last_page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequencer::reply::state_update::{DeployedContract, StateDiff, StorageDiff};
+    use crate::starkhash_bytes;
+    use std::collections::HashMap;
+
+    fn contract0() -> ContractAddress {
+        ContractAddress::new_or_panic(starkhash_bytes!(b"contract 0"))
+    }
+
+    fn contract1() -> ContractAddress {
+        ContractAddress::new_or_panic(starkhash_bytes!(b"contract 1"))
+    }
+
+    fn storage_key() -> StorageAddress {
+        StorageAddress::new_or_panic(starkhash_bytes!(b"storage addr 0"))
+    }
+
+    fn deployed() -> DeployedContract {
+        DeployedContract {
+            address: ContractAddress::new_or_panic(starkhash_bytes!(b"pending contract")),
+            class_hash: ClassHash(starkhash_bytes!(b"pending class")),
+        }
+    }
+
+    /// Overrides contract 0's nonce and contract 1's storage, and deploys [deployed].
+    fn pending_update() -> Arc<StateUpdate> {
+        let storage_diffs = HashMap::from([(
+            contract1(),
+            vec![StorageDiff {
+                key: storage_key(),
+                value: StorageValue(starkhash_bytes!(b"pending value")),
+            }],
+        )]);
+
+        Arc::new(StateUpdate {
+            block_hash: None,
+            new_root: crate::core::GlobalRoot(starkhash_bytes!(b"dont care")),
+            old_root: crate::core::GlobalRoot(starkhash_bytes!(b"dont care")),
+            state_diff: StateDiff {
+                storage_diffs,
+                deployed_contracts: vec![deployed()],
+                declared_contracts: Vec::new(),
+                nonces: HashMap::from([(contract0(), ContractNonce(starkhash_bytes!(b"nonce")))]),
+            },
+        })
+    }
+
+    #[test]
+    fn overridden_in_pending() {
+        let storage = crate::rpc::tests::setup_storage();
+        let mut db = storage.connection().unwrap();
+        let tx = db.transaction().unwrap();
+
+        let latest = StorageStateReader::new(&tx, StarknetBlocksBlockId::Latest)
+            .unwrap()
+            .unwrap();
+        let reader = PendingStateReader::new(Some(pending_update()), latest);
+
+        assert_eq!(
+            reader.nonce(contract0()).unwrap(),
+            Some(ContractNonce(starkhash_bytes!(b"nonce")))
+        );
+        assert_eq!(
+            reader.storage(contract1(), storage_key()).unwrap(),
+            Some(StorageValue(starkhash_bytes!(b"pending value")))
+        );
+    }
+
+    #[test]
+    fn absent_in_pending_falls_through_to_latest() {
+        let storage = crate::rpc::tests::setup_storage();
+        let mut db = storage.connection().unwrap();
+        let tx = db.transaction().unwrap();
+
+        let latest = StorageStateReader::new(&tx, StarknetBlocksBlockId::Latest)
+            .unwrap()
+            .unwrap();
+        let reader = PendingStateReader::new(Some(pending_update()), latest);
+
+        assert_eq!(
+            reader.nonce(contract1()).unwrap(),
+            Some(ContractNonce(crate::starkhash!("10")))
+        );
+        assert_eq!(
+            reader.class_hash(contract0()).unwrap(),
+            Some(ClassHash(starkhash_bytes!(b"class 0 hash")))
+        );
+        assert_eq!(
+            reader
+                .storage(
+                    contract0(),
+                    StorageAddress::new_or_panic(starkhash_bytes!(b"unset"))
+                )
+                .unwrap(),
+            Some(StorageValue(StarkHash::ZERO))
+        );
+
+        let invalid = ContractAddress::new_or_panic(starkhash_bytes!(b"invalid"));
+        assert_eq!(reader.nonce(invalid).unwrap(), None);
+        assert_eq!(reader.class_hash(invalid).unwrap(), None);
+        assert_eq!(reader.storage(invalid, storage_key()).unwrap(), None);
+
+        // Without pending data everything comes from latest.
+        let latest = StorageStateReader::new(&tx, StarknetBlocksBlockId::Latest)
+            .unwrap()
+            .unwrap();
+        let reader = PendingStateReader::new(None, latest);
+        assert_eq!(
+            reader.storage(contract1(), storage_key()).unwrap(),
+            Some(StorageValue(starkhash_bytes!(b"storage value 2")))
+        );
+    }
+
+    #[test]
+    fn deployed_only_in_pending() {
+        let storage = crate::rpc::tests::setup_storage();
+        let mut db = storage.connection().unwrap();
+        let tx = db.transaction().unwrap();
+
+        let latest = StorageStateReader::new(&tx, StarknetBlocksBlockId::Latest)
+            .unwrap()
+            .unwrap();
+        assert_eq!(latest.class_hash(deployed().address).unwrap(), None);
+
+        let reader = PendingStateReader::new(Some(pending_update()), latest);
+        let contract = deployed().address;
+
+        assert_eq!(
+            reader.class_hash(contract).unwrap(),
+            Some(deployed().class_hash)
+        );
+        assert_eq!(reader.nonce(contract).unwrap(), Some(ContractNonce::ZERO));
+        assert_eq!(
+            reader.storage(contract, storage_key()).unwrap(),
+            Some(StorageValue(StarkHash::ZERO))
+        );
+    }
+}
@@ -107,6 +107,8 @@ pub struct Client {
     inner: reqwest::Client,
     /// StarkNet sequencer URL.
     sequencer_url: Url,
+    /// Receives the unparsed JSON of every block fetched, if raw block archiving is enabled.
+    raw_blocks: Option<tokio::sync::mpsc::Sender<bytes::Bytes>>,
 }
 
 impl Client {
@@ -136,9 +138,23 @@ impl Client {
                 .user_agent(crate::consts::USER_AGENT)
                 .build()?,
             sequencer_url: url,
+            raw_blocks: None,
         })
     }
 
+    /// Forwards the original JSON of every block fetched by [ClientApi::block] to `sink`,
+    /// before it gets parsed.
+    ///
+    /// Blocks are dropped instead of waiting if `sink` is full.
+    ///
+    /// Used to archive the sequencer's blocks verbatim, see [crate::state::archive].
+    pub fn with_raw_block_archive(self, sink: tokio::sync::mpsc::Sender<bytes::Bytes>) -> Self {
+        Self {
+            raw_blocks: Some(sink),
+            ..self
+        }
+    }
+
     fn request(&self) -> builder::Request<'_, builder::stage::Gateway> {
         builder::Request::builder(&self.inner, self.sequencer_url.clone())
     }
@@ -169,13 +185,27 @@ impl Client {
 impl ClientApi for Client {
     #[tracing::instrument(skip(self))]
     async fn block(&self, block: BlockId) -> Result<reply::MaybePendingBlock, SequencerError> {
-        self.request()
+        let request = self
+            .request()
             .feeder_gateway()
             .get_block()
             .with_block(block)
-            .with_retry(Self::RETRY)
-            .get()
-            .await
+            .with_retry(Self::RETRY);
+
+        match &self.raw_blocks {
+            Some(sink) => {
+                let raw = request.get_as_bytes().await?;
+                // Archiving is best effort, and must neither fail nor stall the request. A full
+                // sink means the archive is lagging behind, so the block is dropped from it.
+                if let Err(tokio::sync::mpsc::error::TrySendError::Full(_)) =
+                    sink.try_send(raw.clone())
+                {
+                    tracing::warn!(?block, "Raw block archive is full, dropping block");
+                }
+                Ok(serde_json::from_slice(&raw)?)
+            }
+            None => request.get().await,
+        }
     }
 
     /// Performs a `call` on contract's function. Call result is not stored in L2, as opposed to `invoke`.
@@ -826,6 +856,50 @@ mod tests {
             client.block(BlockId::Pending).await.unwrap();
         }
 
+        #[tokio::test]
+        async fn raw_block_archive() {
+            use crate::core::BlockId;
+
+            let _guard = RecorderGuard::lock_as_noop();
+            let (_jh, client) = setup([(
+                "/feeder_gateway/get_block?blockNumber=latest",
+                response!("0.9.0/block/231579.json"),
+            )]);
+            let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+            let client = client.with_raw_block_archive(sender);
+
+            client.block(BlockId::Latest).await.unwrap();
+
+            let raw = receiver.try_recv().unwrap();
+            assert_eq!(
+                raw,
+                include_bytes!("../fixtures/sequencer/0.9.0/block/231579.json").as_slice()
+            );
+        }
+
+        #[tokio::test]
+        async fn full_raw_block_archive() {
+            use crate::core::BlockId;
+            use tokio::sync::mpsc::error::TryRecvError;
+
+            let _guard = RecorderGuard::lock_as_noop();
+            let (_jh, client) = setup([(
+                "/feeder_gateway/get_block?blockNumber=latest",
+                response!("0.9.0/block/231579.json"),
+            )]);
+            let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+            sender
+                .try_send(bytes::Bytes::from_static(b"queued"))
+                .unwrap();
+            let client = client.with_raw_block_archive(sender);
+
+            // Must not wait for the archive to catch up.
+            client.block(BlockId::Latest).await.unwrap();
+
+            assert_eq!(receiver.try_recv().unwrap(), b"queued".as_slice());
+            assert_matches!(receiver.try_recv(), Err(TryRecvError::Empty));
+        }
+
         #[test_log::test(tokio::test)]
         async fn invalid_hash() {
             let _guard = RecorderGuard::lock_as_noop();
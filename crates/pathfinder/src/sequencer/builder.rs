@@ -456,6 +456,7 @@ fn retry_condition(e: &SequencerError) -> bool {
             true
         }
         SequencerError::StarknetError(_) => false,
+        SequencerError::InvalidResponse(_) => false,
     }
 }
 
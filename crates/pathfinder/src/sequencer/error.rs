@@ -12,12 +12,16 @@ pub enum SequencerError {
     /// All other kinds of errors
     #[error(transparent)]
     ReqwestError(#[from] reqwest::Error),
+    /// A response body which was received in full but could not be deserialized.
+    #[error(transparent)]
+    InvalidResponse(#[from] serde_json::Error),
 }
 
 impl From<SequencerError> for Error {
     fn from(e: SequencerError) -> Self {
         match e {
             SequencerError::ReqwestError(e) => Error::Call(CallError::Failed(e.into())),
+            SequencerError::InvalidResponse(e) => Error::Call(CallError::Failed(e.into())),
             SequencerError::StarknetError(e) => match e.code {
                 StarknetErrorCode::OutOfRangeBlockHash | StarknetErrorCode::BlockNotFound
                     if e.message.contains("Block hash") =>
@@ -153,6 +153,9 @@ pub async fn with_metrics<T>(
             SequencerError::ReqwestError(e) if e.is_decode() => {
                 increment_failed(meta, REASON_DECODE);
             }
+            SequencerError::InvalidResponse(_) => {
+                increment_failed(meta, REASON_DECODE);
+            }
             SequencerError::ReqwestError(e)
                 if e.is_status()
                     && e.status().expect("error kind should be status")
@@ -282,6 +282,22 @@ pub mod transaction {
         pub to_address: EthereumAddress,
     }
 
+    /// Whether a transaction's execution succeeded, see [Receipt::execution_status].
+    #[derive(Copy, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+    #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+    pub enum ExecutionStatus {
+        #[default]
+        Succeeded,
+        /// The transaction is included in its block, but its state changes were reverted.
+        Reverted,
+    }
+
+    impl ExecutionStatus {
+        pub fn is_succeeded(&self) -> bool {
+            matches!(self, ExecutionStatus::Succeeded)
+        }
+    }
+
     /// Represents deserialized L2 transaction receipt data.
     #[serde_as]
     #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
@@ -293,6 +309,9 @@ pub mod transaction {
         pub events: Vec<Event>,
         #[serde(skip_serializing_if = "Option::is_none")]
         pub execution_resources: Option<ExecutionResources>,
+        /// Missing from receipts of sequencer versions without reverted transactions.
+        #[serde(default, skip_serializing_if = "ExecutionStatus::is_succeeded")]
+        pub execution_status: ExecutionStatus,
         pub l1_to_l2_consumed_message: Option<L1ToL2Message>,
         pub l2_to_l1_messages: Vec<L2ToL1Message>,
         pub transaction_hash: StarknetTransactionHash,
@@ -351,6 +370,15 @@ pub mod transaction {
                 Transaction::L1Handler(t) => t.contract_address,
             }
         }
+
+        /// Returns the class declared, or instantiated, by a declare or deploy transaction.
+        pub fn class_hash(&self) -> Option<ClassHash> {
+            match self {
+                Transaction::Declare(t) => Some(t.class_hash),
+                Transaction::Deploy(t) => Some(t.class_hash),
+                Transaction::Invoke(_) | Transaction::L1Handler(_) => None,
+            }
+        }
     }
 
     /// Represents deserialized L2 declare transaction data.
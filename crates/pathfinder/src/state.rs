@@ -17,7 +17,10 @@ pub mod state_tree;
 mod sync;
 
 pub use class_hash::compute_class_hash;
-pub use sync::{l1, l2, sync, PendingData, State as SyncState};
+#[cfg(test)]
+pub(crate) use sync::update_starknet_state;
+pub use sync::{archive, l1, l2, sync, PendingData, State as SyncState};
+pub(crate) use sync::{insert_block, track_l1_l2_head};
 
 #[derive(Clone, PartialEq, Eq)]
 pub struct CompressedContract {
@@ -38,10 +41,9 @@ impl std::fmt::Debug for CompressedContract {
     }
 }
 
-/// Updates a contract's state with the given [`StorageDiff`]. It returns the
-/// [ContractStateHash] of the new state.
-///
-/// Specifically, it updates the [ContractsStateTree] and [ContractsStateTable].
+/// Same as [contract_state_update], but also inserts the new state's preimage into the
+/// [ContractsStateTable]. It returns the [ContractStateHash] of the new state.
+#[cfg(test)]
 pub(crate) fn update_contract_state(
     contract_address: ContractAddress,
     updates: &[StorageDiff],
@@ -49,6 +51,28 @@ pub(crate) fn update_contract_state(
     global_tree: &GlobalStateTree<'_, '_>,
     db: &Transaction<'_>,
 ) -> anyhow::Result<ContractStateHash> {
+    let (contract_state_hash, class_hash, new_root, new_nonce) =
+        contract_state_update(contract_address, updates, new_nonce, global_tree, db)?;
+
+    ContractsStateTable::upsert(db, contract_state_hash, class_hash, new_root, new_nonce)
+        .context("Insert constract state hash into contracts state table")?;
+
+    Ok(contract_state_hash)
+}
+
+/// Updates a contract's state with the given [`StorageDiff`]. It returns the
+/// [ContractStateHash] of the new state along with its preimage.
+///
+/// Specifically, it updates the [ContractsStateTree]. The preimage is not inserted into the
+/// [ContractsStateTable], so that sync can insert those of a whole block at once with
+/// [ContractsStateTable::upsert_many].
+pub(crate) fn contract_state_update(
+    contract_address: ContractAddress,
+    updates: &[StorageDiff],
+    new_nonce: Option<ContractNonce>,
+    global_tree: &GlobalStateTree<'_, '_>,
+    db: &Transaction<'_>,
+) -> anyhow::Result<(ContractStateHash, ClassHash, ContractRoot, ContractNonce)> {
     // Update the contract state tree.
     let state_hash = global_tree
         .get(contract_address)
@@ -86,14 +110,11 @@ pub(crate) fn update_contract_state(
         .context("Class hash is missing from contracts table")?;
     let contract_state_hash = calculate_contract_state_hash(class_hash, new_root, new_nonce);
 
-    ContractsStateTable::upsert(db, contract_state_hash, class_hash, new_root, new_nonce)
-        .context("Insert constract state hash into contracts state table")?;
-
-    Ok(contract_state_hash)
+    Ok((contract_state_hash, class_hash, new_root, new_nonce))
 }
 
 /// Calculates the contract state hash from its preimage.
-fn calculate_contract_state_hash(
+pub(crate) fn calculate_contract_state_hash(
     hash: ClassHash,
     root: ContractRoot,
     nonce: ContractNonce,
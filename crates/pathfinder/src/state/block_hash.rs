@@ -10,7 +10,7 @@ use crate::sequencer::reply::{
     transaction::{Event, Receipt, Transaction},
     Block,
 };
-use crate::state::merkle_tree::MerkleTree;
+use crate::state::merkle_tree::{MerkleTree, ProofNode};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum VerifyResult {
@@ -274,7 +274,7 @@ fn compute_final_hash(
 /// of the index of the transaction / event within the block.
 ///
 /// The tree height is 64 in our case since our set operation takes u64 index values.
-struct CommitmentTree {
+pub(crate) struct CommitmentTree {
     tree: MerkleTree<()>,
 }
 
@@ -295,6 +295,16 @@ impl CommitmentTree {
     pub fn commit(self) -> Result<StarkHash> {
         self.tree.commit()
     }
+
+    pub fn commit_mut(&mut self) -> Result<StarkHash> {
+        self.tree.commit_mut()
+    }
+
+    /// Returns the proof of the value at `index`, see [MerkleTree::get_proof].
+    pub fn get_proof(&self, index: u64) -> Result<Vec<ProofNode>> {
+        let key = index.to_be_bytes();
+        self.tree.get_proof(key.view_bits())
+    }
 }
 
 /// Calculate transaction commitment hash value.
@@ -303,6 +313,11 @@ impl CommitmentTree {
 /// constructed by adding the (transaction_index, transaction_hash_with_signature)
 /// key-value pairs to the tree and computing the root hash.
 fn calculate_transaction_commitment(transactions: &[Transaction]) -> Result<StarkHash> {
+    transaction_commitment_tree(transactions)?.commit()
+}
+
+/// Builds the uncommitted tree of the transaction commitment, see [calculate_transaction_commitment].
+pub(crate) fn transaction_commitment_tree(transactions: &[Transaction]) -> Result<CommitmentTree> {
     let mut tree = CommitmentTree::default();
 
     transactions
@@ -318,7 +333,7 @@ fn calculate_transaction_commitment(transactions: &[Transaction]) -> Result<Star
         })
         .context("Failed to create transaction commitment tree")?;
 
-    tree.commit()
+    Ok(tree)
 }
 
 /// Compute the combined hash of the transaction hash and the signature.
@@ -330,7 +345,7 @@ fn calculate_transaction_commitment(transactions: &[Transaction]) -> Result<Star
 /// Note that for non-invoke transactions we don't actually have signatures. The
 /// cairo-lang uses an empty list (whose hash is not the ZERO value!) in that
 /// case.
-fn calculate_transaction_hash_with_signature(tx: &Transaction) -> StarkHash {
+pub(crate) fn calculate_transaction_hash_with_signature(tx: &Transaction) -> StarkHash {
     lazy_static::lazy_static!(
         static ref HASH_OF_EMPTY_LIST: StarkHash = HashChain::default().finalize();
     );
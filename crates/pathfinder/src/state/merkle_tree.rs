@@ -463,6 +463,37 @@ impl<T: NodeStorage> MerkleTree<T> {
         Ok(val)
     }
 
+    /// Returns the nodes along the path from the root towards `key`, which prove that the leaf
+    /// at `key` is part of the tree.
+    ///
+    /// The tree must have been committed, as the proof relies on the node hashes. The proof is
+    /// empty for an empty tree, and does not reach `key` if it has no leaf in the tree.
+    pub fn get_proof(&self, key: &BitSlice<Msb0, u8>) -> anyhow::Result<Vec<ProofNode>> {
+        let hash_of = |node: &Rc<RefCell<Node>>| {
+            node.borrow()
+                .hash()
+                .context("Proof requires a committed tree")
+        };
+
+        let mut proof = Vec::new();
+        for node in self.traverse(key)? {
+            hash_of(&node)?;
+            match &*node.borrow() {
+                Node::Binary(binary) => proof.push(ProofNode::Binary {
+                    left: hash_of(&binary.left)?,
+                    right: hash_of(&binary.right)?,
+                }),
+                Node::Edge(edge) => proof.push(ProofNode::Edge {
+                    child: hash_of(&edge.child)?,
+                    path: edge.path.clone(),
+                }),
+                Node::Leaf(_) | Node::Unresolved(_) => {}
+            }
+        }
+
+        Ok(proof)
+    }
+
     /// Traverses from the current root towards the destination [Leaf](Node::Leaf) node.
     /// Returns the list of nodes along the path.
     ///
@@ -681,6 +712,74 @@ impl<T: NodeStorage> MerkleTree<T> {
     }
 }
 
+/// A node along the path to a leaf, as returned by [`MerkleTree::get_proof`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProofNode {
+    Binary {
+        left: StarkHash,
+        right: StarkHash,
+    },
+    Edge {
+        child: StarkHash,
+        path: BitVec<Msb0, u8>,
+    },
+}
+
+impl ProofNode {
+    /// Calculates the hash of the node, in the same way as [BinaryNode] and [EdgeNode] do.
+    pub fn hash(&self) -> StarkHash {
+        match self {
+            ProofNode::Binary { left, right } => stark_hash::stark_hash(*left, *right),
+            ProofNode::Edge { child, path } => {
+                let path_hash = StarkHash::from_bits(path).unwrap();
+                let mut length = [0; 32];
+                // Safe as len() is guaranteed to be <= 251
+                length[31] = path.len() as u8;
+                let length = StarkHash::from_be_bytes(length).unwrap();
+
+                stark_hash::stark_hash(*child, path_hash) + length
+            }
+        }
+    }
+
+    /// Checks that `proof` links `root` to the leaf at `key` with the given `value`.
+    pub fn verify(
+        proof: &[ProofNode],
+        root: StarkHash,
+        key: &BitSlice<Msb0, u8>,
+        value: StarkHash,
+    ) -> bool {
+        let mut expected = root;
+        let mut height = 0;
+
+        for node in proof {
+            if node.hash() != expected {
+                return false;
+            }
+
+            match node {
+                ProofNode::Binary { left, right } => {
+                    expected = match key.get(height).map(|bit| Direction::from(*bit)) {
+                        Some(Direction::Left) => *left,
+                        Some(Direction::Right) => *right,
+                        None => return false,
+                    };
+                    height += 1;
+                }
+                ProofNode::Edge { child, path } => {
+                    if key.get(height..height + path.len()) != Some(path.as_bitslice()) {
+                        return false;
+                    }
+                    expected = *child;
+                    height += path.len();
+                }
+            }
+        }
+
+        height == key.len() && expected == value
+    }
+}
+
 /// Direction for the [`MerkleTree::dfs`] as the return value of the visitor function.
 #[derive(Default)]
 pub enum Visit {
@@ -1449,6 +1548,54 @@ mod tests {
         }
     }
 
+    mod proof {
+        use super::*;
+
+        #[test]
+        fn verifies_every_leaf() {
+            let mut uut = MerkleTree::empty((), 251);
+
+            let leaves = [
+                (starkhash!("99cadc82"), starkhash!("891127cbaf")),
+                (starkhash!("901823"), starkhash!("82233127cbaf")),
+                (starkhash!("8975"), starkhash!("0891124667aacde7cbaf")),
+            ];
+            for (key, value) in &leaves {
+                uut.set(key.view_bits(), *value).unwrap();
+            }
+            let root = uut.commit_mut().unwrap();
+
+            for (key, value) in &leaves {
+                let proof = uut.get_proof(key.view_bits()).unwrap();
+                assert!(ProofNode::verify(&proof, root, key.view_bits(), *value));
+                assert!(!ProofNode::verify(
+                    &proof,
+                    root,
+                    key.view_bits(),
+                    starkhash!("0bad")
+                ));
+            }
+
+            // A proof does not hold for another leaf.
+            let proof = uut.get_proof(leaves[0].0.view_bits()).unwrap();
+            assert!(!ProofNode::verify(
+                &proof,
+                root,
+                leaves[1].0.view_bits(),
+                leaves[1].1
+            ));
+        }
+
+        #[test]
+        fn uncommitted_tree() {
+            let mut uut = MerkleTree::empty((), 251);
+            let key = starkhash!("01");
+            uut.set(key.view_bits(), starkhash!("02")).unwrap();
+
+            uut.get_proof(key.view_bits()).unwrap_err();
+        }
+    }
+
     mod dfs {
         use super::{BinaryNode, EdgeNode, MerkleTree, Node, Visit};
         use crate::starkhash;
@@ -1,3 +1,4 @@
+pub mod archive;
 pub mod l1;
 pub mod l2;
 mod pending;
@@ -7,20 +8,25 @@ use std::sync::Arc;
 
 use crate::{
     core::{
-        Chain, ClassHash, ContractRoot, GasPrice, GlobalRoot, SequencerAddress, StarknetBlockHash,
-        StarknetBlockNumber,
+        Chain, ClassHash, ContractNonce, ContractRoot, ContractStateHash, EthereumBlockNumber,
+        GasPrice, GlobalRoot, StarknetBlockHash, StarknetBlockNumber,
+    },
+    ethereum::{
+        log::{L1ToL2MessageLog, StateUpdateLog},
+        transport::EthereumTransport,
     },
-    ethereum::{log::StateUpdateLog, transport::EthereumTransport},
     rpc::v01::types::reply::{syncing, syncing::NumberedBlock, Syncing as SyncStatus},
     sequencer::{
         self,
         reply::{Block, MaybePendingBlock, PendingBlock, StateUpdate},
     },
-    state::{calculate_contract_state_hash, state_tree::GlobalStateTree, update_contract_state},
+    state::{calculate_contract_state_hash, contract_state_update, state_tree::GlobalStateTree},
     storage::{
-        ContractCodeTable, ContractsStateTable, ContractsTable, L1StateTable, L1TableBlockId,
-        RefsTable, StarknetBlock, StarknetBlocksBlockId, StarknetBlocksTable,
-        StarknetStateUpdatesTable, StarknetTransactionsTable, Storage,
+        CompressionConfig, ContractClassIndexTable, ContractCodeTable, ContractSummariesTable,
+        ContractsStateTable, ContractsTable, EventSender, L1HandlerOriginsTable, L1StateTable,
+        L1TableBlockId, RefsTable, ReorgHistoryTable, ReorgSource, StarknetBlock,
+        StarknetBlocksBlockId, StarknetBlocksTable, StarknetEmittedEvent,
+        StarknetStateUpdatesTable, StarknetTransactionsTable, Storage, StorageUpdatesTable,
     },
 };
 
@@ -118,13 +124,16 @@ where
             Option<(StarknetBlockNumber, StarknetBlockHash, GlobalRoot)>,
             Chain,
             Option<std::time::Duration>,
+            CompressionConfig,
         ) -> F2
         + Copy,
 {
     // TODO: should this be owning a Storage, or just take in a Connection?
-    let mut db_conn = storage
-        .connection()
-        .context("Creating database connection")?;
+    let mut db_conn = storage.writer().context("Creating database connection")?;
+
+    let compression = storage.compression();
+    let events_tx = storage.event_sender().cloned();
+    let head_tx = storage.head_sender();
 
     let (tx_l1, mut rx_l1) = mpsc::channel(1);
     let (tx_l2, mut rx_l2) = mpsc::channel(1);
@@ -133,7 +142,7 @@ where
         let tx = db_conn.transaction()?;
         let l1_head = L1StateTable::get(&tx, L1TableBlockId::Latest)
             .context("Query L1 head from database")?;
-        let l2_head = StarknetBlocksTable::get(&tx, StarknetBlocksBlockId::Latest)
+        let l2_head = StarknetBlocksTable::get_header(&tx, StarknetBlocksBlockId::Latest)
             .context("Query L2 head from database")?
             .map(|block| (block.number, block.hash, block.root));
         Ok((l1_head, l2_head))
@@ -162,6 +171,7 @@ where
         l2_head,
         chain,
         pending_poll_interval,
+        compression,
     ));
 
     let mut existed = (0, 0);
@@ -199,6 +209,17 @@ where
                         _ => {}
                     }
                 }
+                Some(l1::Event::Messages(messages)) => {
+                    let consumed = l1_messages(&mut db_conn, &messages)
+                        .await
+                        .context("Attach Ethereum origins of L1 to L2 messages")?;
+
+                    tracing::debug!(
+                        count=%messages.len(),
+                        %consumed,
+                        "L1 sync stored L1 to L2 messages"
+                    );
+                }
                 Some(l1::Event::Reorg(reorg_tail)) => {
                     l1_reorg(&mut db_conn, reorg_tail)
                         .await
@@ -265,9 +286,11 @@ where
                     let block_hash = block.block_hash;
                     let storage_updates: usize = state_update.state_diff.storage_diffs.iter().map(|(_, storage_diffs)| storage_diffs.len()).sum();
                     let update_t = std::time::Instant::now();
-                    l2_update(&mut db_conn, *block, *state_update)
+                    l2_update(&mut db_conn, *block, *state_update, compression, events_tx.clone())
                         .await
                         .with_context(|| format!("Update L2 state to {}", block_number))?;
+                    // Only once committed, so that subscribers can read the block.
+                    head_tx.send_replace(Some((block_number, block_hash)));
                     let block_time = last_block_start.elapsed();
                     let update_t = update_t.elapsed();
                     last_block_start = std::time::Instant::now();
@@ -316,9 +339,10 @@ where
                 Some(l2::Event::Reorg(reorg_tail)) => {
                     pending_data.clear().await;
 
-                    l2_reorg(&mut db_conn, reorg_tail)
+                    let head = l2_reorg(&mut db_conn, reorg_tail)
                         .await
                         .with_context(|| format!("Reorg L2 state to {:?}", reorg_tail))?;
+                    head_tx.send_replace(head);
 
                     let new_head = match reorg_tail {
                         StarknetBlockNumber::GENESIS => None,
@@ -341,16 +365,15 @@ where
 
                     tracing::trace!("Inserted new contract {}", contract.hash.0.to_hex_str());
                 }
-                Some(l2::Event::QueryBlock(number, tx)) => {
-                    let block = tokio::task::block_in_place(|| {
+                Some(l2::Event::QueryParentHash(number, tx)) => {
+                    let parent_hash = tokio::task::block_in_place(|| {
                         let tx = db_conn.transaction()?;
-                        StarknetBlocksTable::get(&tx, number.into())
+                        StarknetBlocksTable::get_parent_hash(&tx, number).map_err(anyhow::Error::from)
                     })
-                    .with_context(|| format!("Query L2 block hash for block {number}"))?
-                    .map(|block| (block.hash, block.root));
-                    let _ = tx.send(block);
+                    .with_context(|| format!("Query L2 parent hash for block {number}"))?;
+                    let _ = tx.send(parent_hash);
 
-                    tracing::trace!(%number, "Query hash for L2 block");
+                    tracing::trace!(%number, "Query parent hash for L2 block");
                 }
                 Some(l2::Event::QueryContractExistance(contracts, tx)) => {
                     let exists =
@@ -387,7 +410,7 @@ where
                     let classes = deployed_classes
                         .chain(declared_classes)
                         .chain(declared_classes_block);
-                    download_verify_and_insert_missing_classes(sequencer.clone(), &mut db_conn, classes)
+                    download_verify_and_insert_missing_classes(sequencer.clone(), &mut db_conn, classes, compression)
                         .await
                         .context("Downloading missing classes for pending block")?;
 
@@ -441,7 +464,7 @@ where
                     let (new_tx, new_rx) = mpsc::channel(1);
                     rx_l2 = new_rx;
 
-                    let fut = l2_sync(new_tx, sequencer.clone(), l2_head, chain, pending_poll_interval);
+                    let fut = l2_sync(new_tx, sequencer.clone(), l2_head, chain, pending_poll_interval, compression);
 
                     l2_handle = tokio::spawn(async move {
                         #[cfg(not(test))]
@@ -560,6 +583,36 @@ async fn l1_update(connection: &mut Connection, updates: &[StateUpdateLog]) -> a
     })
 }
 
+/// Stores the Ethereum origins of the messages, and returns how many of them have already been
+/// consumed by a stored L1 handler transaction.
+async fn l1_messages(
+    connection: &mut Connection,
+    messages: &[L1ToL2MessageLog],
+) -> anyhow::Result<usize> {
+    tokio::task::block_in_place(move || {
+        let transaction = connection
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .context("Create database transaction")?;
+
+        let mut consumed = 0;
+        for message in messages {
+            let attached = L1HandlerOriginsTable::attach_ethereum_origin(
+                &transaction,
+                message.nonce,
+                message.origin.transaction.hash,
+                message.origin.block.number,
+            )
+            .context("Insert message")?;
+            consumed += attached as usize;
+        }
+
+        transaction
+            .commit()
+            .context("Commit database transaction")?;
+        Ok(consumed)
+    })
+}
+
 async fn l1_reorg(
     connection: &mut Connection,
     reorg_tail: StarknetBlockNumber,
@@ -569,6 +622,37 @@ async fn l1_reorg(
             .transaction_with_behavior(TransactionBehavior::Immediate)
             .context("Create database transaction")?;
 
+        let old_head = L1StateTable::get(&transaction, L1TableBlockId::Latest)
+            .context("Query L1 head")?
+            .map(|update| update.block_number);
+        if let Some(old_head) = old_head.filter(|head| *head >= reorg_tail) {
+            let old_head_hash = StarknetBlocksTable::get(&transaction, old_head.into())
+                .context("Query L1 head block")?
+                .map(|block| block.hash);
+            ReorgHistoryTable::record_reorg(
+                &transaction,
+                reorg_tail,
+                old_head,
+                old_head_hash,
+                unix_timestamp(),
+                ReorgSource::L1,
+            )
+            .context("Record L1 reorg")?;
+        }
+
+        // The messages of the L1 blocks after the new L1 head are gone with it.
+        let new_head = match reorg_tail {
+            StarknetBlockNumber::GENESIS => None,
+            other => {
+                L1StateTable::get(&transaction, (other - 1).into()).context("Query new L1 head")?
+            }
+        };
+        let ethereum_tail = new_head
+            .map(|head| EthereumBlockNumber(head.origin.block.number.0 + 1))
+            .unwrap_or(EthereumBlockNumber(0));
+        L1HandlerOriginsTable::ethereum_reorg(&transaction, ethereum_tail)
+            .context("Delete L1 to L2 messages from database")?;
+
         L1StateTable::reorg(&transaction, reorg_tail).context("Delete L1 state from database")?;
 
         // Track combined L1 and L2 state.
@@ -593,141 +677,248 @@ async fn l2_update(
     connection: &mut Connection,
     block: Block,
     state_update: StateUpdate,
+    compression: CompressionConfig,
+    events_tx: Option<EventSender>,
 ) -> anyhow::Result<()> {
-    use crate::storage::CanonicalBlocksTable;
-
     tokio::task::block_in_place(move || {
         let transaction = connection
             .transaction_with_behavior(TransactionBehavior::Immediate)
             .context("Create database transaction")?;
 
-        let new_root = update_starknet_state(&transaction, &state_update)
-            .context("Updating Starknet state")?;
-
-        // Ensure that roots match.. what should we do if it doesn't? For now the whole sync process ends..
-        anyhow::ensure!(new_root == block.state_root, "State root mismatch");
-
-        // Update L2 database. These types shouldn't be options at this level,
-        // but for now the unwraps are "safe" in that these should only ever be
-        // None for pending queries to the sequencer, but we aren't using those here.
-        let starknet_block = StarknetBlock {
-            number: block.block_number,
-            hash: block.block_hash,
-            root: block.state_root,
-            timestamp: block.timestamp,
-            // Default value for cairo <0.8.2 is 0
-            gas_price: block.gas_price.unwrap_or(GasPrice::ZERO),
-            sequencer_address: block
-                .sequencer_address
-                .unwrap_or(SequencerAddress(StarkHash::ZERO)),
-        };
-        StarknetBlocksTable::insert(
-            &transaction,
-            &starknet_block,
-            block.starknet_version.as_deref(),
-        )
-        .context("Insert block into database")?;
-
-        let rpc_state_update = state_update.into();
-        StarknetStateUpdatesTable::insert(&transaction, block.block_hash, &rpc_state_update)
-            .context("Insert state update into database")?;
-
-        CanonicalBlocksTable::insert(&transaction, block.block_number, block.block_hash)
-            .context("Inserting canonical block into database")?;
+        // Shared by all the compressed writes of this block.
+        let mut compressor = compression.compressor()?;
 
-        for class in rpc_state_update.state_diff.declared_contracts {
-            ContractCodeTable::update_declared_on_if_null(
-                &transaction,
-                class.class_hash,
+        let events = events_tx.as_ref().map(|_| {
+            StarknetEmittedEvent::from_receipts(
                 block.block_hash,
+                block.block_number,
+                &block.transaction_receipts,
             )
-            .with_context(|| format!("Setting declared_on for class={:?}", class.class_hash))?;
+        });
+
+        let starknet_block = insert_block(&transaction, &mut compressor, block, state_update)?;
+
+        track_l1_l2_head(&transaction, &starknet_block)?;
+
+        transaction
+            .commit()
+            .context("Commit database transaction")?;
+
+        // Only once committed, so that receivers never see events of a rolled back block.
+        if let (Some(events_tx), Some(events)) = (events_tx, events) {
+            StarknetEmittedEvent::broadcast(&events_tx, events);
         }
-        for class in rpc_state_update.state_diff.deployed_contracts {
-            ContractCodeTable::update_declared_on_if_null(
-                &transaction,
-                class.class_hash,
-                block.block_hash,
-            )
-            .with_context(|| format!("Setting declared_on for class={:?}", class.class_hash))?;
+
+        Ok(())
+    })
+}
+
+/// Advances the L1-L2 head to the newly inserted `block` if it directly follows the head and
+/// L1 agrees on its state root.
+pub(crate) fn track_l1_l2_head(
+    transaction: &Transaction<'_>,
+    block: &StarknetBlock,
+) -> anyhow::Result<()> {
+    let l1_l2_head = RefsTable::get_l1_l2_head(transaction).context("Query L1-L2 head")?;
+    let expected_next = l1_l2_head
+        .map(|head| head + 1)
+        .unwrap_or(StarknetBlockNumber::GENESIS);
+
+    if expected_next == block.number {
+        let l1_root =
+            L1StateTable::get_root(transaction, block.number.into()).context("Query L1 root")?;
+        if l1_root == Some(block.root) {
+            RefsTable::set_l1_l2_head(transaction, Some(block.number))
+                .context("Update L1-L2 head")?;
         }
+    }
 
-        // Insert the transactions.
-        anyhow::ensure!(
-            block.transactions.len() == block.transaction_receipts.len(),
-            "Transactions and receipts mismatch. There were {} transactions and {} receipts.",
-            block.transactions.len(),
-            block.transaction_receipts.len()
-        );
-        let transaction_data = block
-            .transactions
-            .into_iter()
-            .zip(block.transaction_receipts.into_iter())
-            .collect::<Vec<_>>();
-        StarknetTransactionsTable::upsert(
-            &transaction,
-            starknet_block.hash,
-            starknet_block.number,
-            &transaction_data,
+    Ok(())
+}
+
+/// Applies the block's state update to the global state, checks the resulting state root
+/// and inserts the block along with its state update and transactions.
+///
+/// This is the insert path shared by L2 sync and [snapshot imports](crate::storage::import).
+/// It does not track the L1-L2 head.
+pub(crate) fn insert_block(
+    transaction: &Transaction<'_>,
+    compressor: &mut zstd::bulk::Compressor<'_>,
+    block: Block,
+    state_update: StateUpdate,
+) -> anyhow::Result<StarknetBlock> {
+    use crate::storage::metrics::{WriteTimer, INSERT_BLOCK};
+    use crate::storage::CanonicalBlocksTable;
+
+    let timer = WriteTimer::start(INSERT_BLOCK);
+    let _span = tracing::debug_span!(
+        "insert_block",
+        block_number = %block.block_number,
+        transactions = block.transactions.len(),
+    )
+    .entered();
+
+    let new_root =
+        update_starknet_state(transaction, &state_update).context("Updating Starknet state")?;
+
+    // Ensure that roots match.. what should we do if it doesn't? For now the whole sync process ends..
+    anyhow::ensure!(new_root == block.state_root, "State root mismatch");
+
+    // Update L2 database. These types shouldn't be options at this level,
+    // but for now the unwraps are "safe" in that these should only ever be
+    // None for pending queries to the sequencer, but we aren't using those here.
+    let starknet_block = StarknetBlock {
+        number: block.block_number,
+        hash: block.block_hash,
+        parent_hash: block.parent_block_hash,
+        root: block.state_root,
+        timestamp: block.timestamp,
+        // Default value for cairo <0.8.2 is 0
+        gas_price: block.gas_price.unwrap_or(GasPrice::ZERO),
+        sequencer_address: block.sequencer_address,
+        transaction_count: block.transactions.len(),
+        event_count: block
+            .transaction_receipts
+            .iter()
+            .map(|receipt| receipt.events.len())
+            .sum(),
+    };
+    StarknetBlocksTable::insert(
+        transaction,
+        &starknet_block,
+        block.starknet_version.as_deref(),
+    )
+    .context("Insert block into database")?;
+
+    let rpc_state_update = state_update.into();
+    StarknetStateUpdatesTable::insert_with_compressor(
+        transaction,
+        compressor,
+        block.block_hash,
+        &rpc_state_update,
+    )
+    .context("Insert state update into database")?;
+
+    CanonicalBlocksTable::insert(transaction, block.block_number, block.block_hash)
+        .context("Inserting canonical block into database")?;
+
+    for class in rpc_state_update.state_diff.declared_contracts {
+        ContractCodeTable::update_declared_on_if_null(
+            transaction,
+            class.class_hash,
+            block.block_hash,
+        )
+        .with_context(|| format!("Setting declared_on for class={:?}", class.class_hash))?;
+    }
+    for contract in &rpc_state_update.state_diff.deployed_contracts {
+        ContractClassIndexTable::insert(
+            transaction,
+            block.block_number,
+            contract.address,
+            contract.class_hash,
+        )
+        .with_context(|| format!("Indexing class of contract={:?}", contract.address))?;
+    }
+    ContractSummariesTable::apply(
+        transaction,
+        block.block_number,
+        &rpc_state_update.state_diff.deployed_contracts,
+        &rpc_state_update.state_diff.nonces,
+    )
+    .context("Updating contract summaries")?;
+    StorageUpdatesTable::insert(
+        transaction,
+        block.block_number,
+        &rpc_state_update.state_diff.storage_diffs,
+    )
+    .context("Recording storage updates")?;
+    for class in rpc_state_update.state_diff.deployed_contracts {
+        ContractCodeTable::update_declared_on_if_null(
+            transaction,
+            class.class_hash,
+            block.block_hash,
         )
-        .context("Insert transaction data into database")?;
+        .with_context(|| format!("Setting declared_on for class={:?}", class.class_hash))?;
+    }
 
-        // Track combined L1 and L2 state.
-        let l1_l2_head = RefsTable::get_l1_l2_head(&transaction).context("Query L1-L2 head")?;
-        let expected_next = l1_l2_head
-            .map(|head| head + 1)
-            .unwrap_or(StarknetBlockNumber::GENESIS);
+    // Insert the transactions.
+    anyhow::ensure!(
+        block.transactions.len() == block.transaction_receipts.len(),
+        "Transactions and receipts mismatch. There were {} transactions and {} receipts.",
+        block.transactions.len(),
+        block.transaction_receipts.len()
+    );
+    let transaction_data = block
+        .transactions
+        .into_iter()
+        .zip(block.transaction_receipts.into_iter())
+        .collect::<Vec<_>>();
+    StarknetTransactionsTable::upsert_with_compressor(
+        transaction,
+        compressor,
+        starknet_block.hash,
+        starknet_block.number,
+        &transaction_data,
+    )
+    .context("Insert transaction data into database")?;
 
-        if expected_next == starknet_block.number {
-            let l1_root = L1StateTable::get_root(&transaction, starknet_block.number.into())
-                .context("Query L1 root")?;
-            if l1_root == Some(starknet_block.root) {
-                RefsTable::set_l1_l2_head(&transaction, Some(starknet_block.number))
-                    .context("Update L1-L2 head")?;
-            }
-        }
+    // The rows and bytes written are recorded by each table.
+    timer.finish(1, 0);
 
-        transaction.commit().context("Commit database transaction")
-    })
+    Ok(starknet_block)
 }
 
+/// Returns the number and hash of the new head.
 async fn l2_reorg(
     connection: &mut Connection,
     reorg_tail: StarknetBlockNumber,
-) -> anyhow::Result<()> {
-    use crate::storage::CanonicalBlocksTable;
-
+) -> anyhow::Result<Option<(StarknetBlockNumber, StarknetBlockHash)>> {
     tokio::task::block_in_place(move || {
         let transaction = connection
             .transaction_with_behavior(TransactionBehavior::Immediate)
             .context("Create database transaction")?;
 
-        // TODO: clean up state tree's as well...
+        let old_head = StarknetBlocksTable::get(&transaction, StarknetBlocksBlockId::Latest)
+            .context("Query L2 head")?;
+        if let Some(old_head) = old_head.filter(|head| head.number >= reorg_tail) {
+            ReorgHistoryTable::record_reorg(
+                &transaction,
+                reorg_tail,
+                old_head.number,
+                Some(old_head.hash),
+                unix_timestamp(),
+                ReorgSource::L2,
+            )
+            .context("Record L2 reorg")?;
+        }
 
-        CanonicalBlocksTable::reorg(&transaction, reorg_tail)
-            .context("Delete canonical blocks from database")?;
+        let preview = crate::storage::reorg_preview(&transaction, reorg_tail)
+            .context("Count rows affected by L2 reorg")?;
+        tracing::debug!(%reorg_tail, ?preview, "Deleting L2 data");
 
-        StarknetBlocksTable::reorg(&transaction, reorg_tail)
-            .context("Delete L2 blocks from database")?;
+        // TODO: clean up state tree's as well...
 
-        // Track combined L1 and L2 state.
-        let l1_l2_head = RefsTable::get_l1_l2_head(&transaction).context("Query L1-L2 head")?;
-        match l1_l2_head {
-            Some(head) if head >= reorg_tail => {
-                let new_head = match reorg_tail {
-                    StarknetBlockNumber::GENESIS => None,
-                    other => Some(other - 1),
-                };
-                RefsTable::set_l1_l2_head(&transaction, new_head).context("Update L1-L2 head")?;
-            }
-            _ => {}
-        }
+        // The L1 state is kept, as it only changes with L1 reorgs.
+        Storage::reorg_l2(&transaction, reorg_tail).context("Delete L2 data from database")?;
 
-        transaction.commit().context("Commit database transaction")
+        let head = crate::storage::latest_head(&transaction)?;
+        transaction
+            .commit()
+            .context("Commit database transaction")?;
+
+        Ok(head)
     })
 }
 
-fn update_starknet_state(
+/// Seconds since the unix epoch, for [ReorgHistoryTable] records.
+fn unix_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+pub(crate) fn update_starknet_state(
     transaction: &Transaction<'_>,
     state_update: &StateUpdate,
 ) -> anyhow::Result<GlobalRoot> {
@@ -738,8 +929,13 @@ fn update_starknet_state(
     let mut global_tree =
         GlobalStateTree::load(transaction, global_root).context("Loading global state tree")?;
 
+    // The preimages of the new contract states, inserted all at once at the end.
+    let mut contract_states = Vec::new();
+
     for contract in &state_update.state_diff.deployed_contracts {
-        deploy_contract(transaction, &mut global_tree, contract).context("Deploying contract")?;
+        let contract_state = deploy_contract(transaction, &mut global_tree, contract)
+            .context("Deploying contract")?;
+        contract_states.push(contract_state);
     }
 
     // Copied so we can mutate the map. This lets us remove used nonces from the list.
@@ -750,19 +946,20 @@ fn update_starknet_state(
         // Remove the nonce so we don't update it again in the next stage.
         let nonce = nonces.remove(contract_address);
 
-        let contract_state_hash =
-            update_contract_state(*contract_address, updates, nonce, &global_tree, transaction)
+        let contract_state =
+            contract_state_update(*contract_address, updates, nonce, &global_tree, transaction)
                 .context("Update contract state")?;
 
         // Update the global state tree.
         global_tree
-            .set(*contract_address, contract_state_hash)
+            .set(*contract_address, contract_state.0)
             .context("Updating global state tree")?;
+        contract_states.push(contract_state);
     }
 
     // Apply all remaining nonces (without storage updates).
     for (contract_address, nonce) in nonces {
-        let contract_state_hash = update_contract_state(
+        let contract_state = contract_state_update(
             contract_address,
             &[],
             Some(nonce),
@@ -773,25 +970,31 @@ fn update_starknet_state(
 
         // Update the global state tree.
         global_tree
-            .set(contract_address, contract_state_hash)
+            .set(contract_address, contract_state.0)
             .context("Updating global state tree")?;
+        contract_states.push(contract_state);
     }
 
+    ContractsStateTable::upsert_many(transaction, &contract_states)
+        .context("Insert contract state hashes into contracts state table")?;
+
     // Apply all global tree changes.
     global_tree
         .apply()
         .context("Apply global state tree updates")
 }
 
+/// Adds the contract to the global state tree and the [ContractsTable], and returns the preimage
+/// of its initial state for the [ContractsStateTable].
 fn deploy_contract(
     transaction: &Transaction<'_>,
     global_tree: &mut GlobalStateTree<'_, '_>,
     contract: &sequencer::reply::state_update::DeployedContract,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<(ContractStateHash, ClassHash, ContractRoot, ContractNonce)> {
     // Add a new contract to global tree, the contract root is initialized to ZERO.
     let contract_root = ContractRoot::ZERO;
     // The initial value of a contract nonce is ZERO.
-    let contract_nonce = crate::core::ContractNonce::ZERO;
+    let contract_nonce = ContractNonce::ZERO;
     // sequencer::reply::state_update::Contract::contract_hash is the old (pre cairo 0.9.0)
     // name for `class_hash`.
     let class_hash = contract.class_hash;
@@ -799,16 +1002,10 @@ fn deploy_contract(
     global_tree
         .set(contract.address, state_hash)
         .context("Adding deployed contract to global state tree")?;
-    ContractsStateTable::upsert(
-        transaction,
-        state_hash,
-        class_hash,
-        contract_root,
-        contract_nonce,
-    )
-    .context("Insert constract state hash into contracts state table")?;
     ContractsTable::upsert(transaction, contract.address, class_hash)
-        .context("Inserting class hash into contracts table")
+        .context("Inserting class hash into contracts table")?;
+
+    Ok((state_hash, class_hash, contract_root, contract_nonce))
 }
 
 /// Downloads and inserts class definitions for any classes in the
@@ -820,6 +1017,7 @@ async fn download_verify_and_insert_missing_classes<
     sequencer: SequencerClient,
     connection: &mut Connection,
     classes: ClassIter,
+    compression: CompressionConfig,
 ) -> anyhow::Result<()> {
     use crate::state::class_hash::extract_abi_code_hash;
 
@@ -872,8 +1070,7 @@ async fn download_verify_and_insert_missing_classes<
         );
 
         let compress = tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
-            let mut compressor =
-                zstd::bulk::Compressor::new(10).context("Create zstd compressor")?;
+            let mut compressor = compression.compressor()?;
 
             let abi = compressor.compress(&abi).context("Compress ABI")?;
             let bytecode = compressor
@@ -934,11 +1131,11 @@ mod tests {
             CallSignatureElem, Chain, ClassHash, ConstructorParam, ContractAddress,
             ContractAddressSalt, EthereumBlockHash, EthereumBlockNumber, EthereumLogIndex,
             EthereumTransactionHash, EthereumTransactionIndex, Fee, GasPrice, GlobalRoot,
-            SequencerAddress, StarknetBlockHash, StarknetBlockNumber, StarknetBlockTimestamp,
-            StarknetTransactionHash, StorageAddress, StorageValue, TransactionNonce,
-            TransactionVersion,
+            L1ToL2MessageNonce, SequencerAddress, StarknetBlockHash, StarknetBlockNumber,
+            StarknetBlockTimestamp, StarknetTransactionHash, StorageAddress, StorageValue,
+            TransactionNonce, TransactionVersion,
         },
-        ethereum,
+        ethereum::{self, log::L1ToL2MessageLog},
         rpc::v01::types::BlockHashOrTag,
         sequencer::{
             self,
@@ -947,7 +1144,7 @@ mod tests {
             request::{self, add_transaction::ContractDefinition},
         },
         state::{self, sync::PendingData},
-        storage::{self, L1StateTable, RefsTable, StarknetBlocksTable, Storage},
+        storage::{self, CompressionConfig, L1StateTable, RefsTable, StarknetBlocksTable, Storage},
     };
     use futures::stream::{StreamExt, TryStreamExt};
     use stark_hash::StarkHash;
@@ -1120,6 +1317,7 @@ mod tests {
         _: Option<(StarknetBlockNumber, StarknetBlockHash, GlobalRoot)>,
         _: Chain,
         _: Option<std::time::Duration>,
+        _: CompressionConfig,
     ) -> anyhow::Result<()> {
         // Avoid being restarted all the time by the outer sync() loop
         std::future::pending::<()>().await;
@@ -1180,18 +1378,24 @@ mod tests {
         pub static ref STORAGE_BLOCK0: storage::StarknetBlock = storage::StarknetBlock {
             number: StarknetBlockNumber::GENESIS,
             hash: StarknetBlockHash(*A),
+            parent_hash: StarknetBlockHash(StarkHash::ZERO),
             root: GlobalRoot(StarkHash::ZERO),
             timestamp: StarknetBlockTimestamp::new_or_panic(0),
             gas_price: GasPrice::ZERO,
-            sequencer_address: SequencerAddress(StarkHash::ZERO),
+            sequencer_address: Some(SequencerAddress(StarkHash::ZERO)),
+            transaction_count: 0,
+            event_count: 0,
         };
         pub static ref STORAGE_BLOCK1: storage::StarknetBlock = storage::StarknetBlock {
             number: StarknetBlockNumber::new_or_panic(1),
             hash: StarknetBlockHash(*B),
+            parent_hash: StarknetBlockHash(*A),
             root: GlobalRoot(*B),
             timestamp: StarknetBlockTimestamp::new_or_panic(1),
             gas_price: GasPrice::from(1),
-            sequencer_address: SequencerAddress(StarkHash::from_be_bytes([1u8; 32]).unwrap()),
+            sequencer_address: Some(SequencerAddress(StarkHash::from_be_bytes([1u8; 32]).unwrap())),
+            transaction_count: 0,
+            event_count: 0,
         };
         // Causes root to remain 0
         pub static ref STATE_UPDATE0: sequencer::reply::StateUpdate = sequencer::reply::StateUpdate {
@@ -1242,7 +1446,7 @@ mod tests {
         .into_iter()
         .map(|blocks| async {
             let storage = Storage::in_memory().unwrap();
-            let mut connection = storage.connection().unwrap();
+            let mut connection = storage.writer().unwrap();
             let tx = connection.transaction().unwrap();
 
             blocks
@@ -1251,6 +1455,7 @@ mod tests {
                 .for_each(|block| StarknetBlocksTable::insert(&tx, block, None).unwrap());
 
             tx.commit().unwrap();
+            drop(connection);
             drop(blocks);
 
             // UUT
@@ -1269,6 +1474,7 @@ mod tests {
             // TODO Find a better way to figure out that the DB update has already been performed
             tokio::time::sleep(Duration::from_millis(300)).await;
 
+            let mut connection = storage.connection().unwrap();
             let tx = connection.transaction().unwrap();
             RefsTable::get_l1_l2_head(&tx)
         })
@@ -1290,6 +1496,51 @@ mod tests {
         );
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn l1_reorg_removes_messages() {
+        let storage = Storage::in_memory().unwrap();
+        let mut connection = storage.writer().unwrap();
+
+        // Block #1 is in a later L1 block than genesis.
+        let mut update1 = STATE_UPDATE_LOG1.clone();
+        update1.origin.block.number = EthereumBlockNumber(5);
+        let tx = connection.transaction().unwrap();
+        L1StateTable::upsert(&tx, &STATE_UPDATE_LOG0).unwrap();
+        L1StateTable::upsert(&tx, &update1).unwrap();
+        tx.commit().unwrap();
+
+        let message = |block| L1ToL2MessageLog {
+            origin: ethereum::EthOrigin {
+                block: ethereum::BlockOrigin {
+                    hash: ETH_ORIG.block.hash,
+                    number: EthereumBlockNumber(block),
+                },
+                ..ETH_ORIG.clone()
+            },
+            nonce: L1ToL2MessageNonce(StarkHash::from_u64(block)),
+        };
+        let consumed = super::l1_messages(&mut connection, &[message(0), message(1), message(5)])
+            .await
+            .unwrap();
+        assert_eq!(consumed, 0);
+
+        super::l1_reorg(&mut connection, StarknetBlockNumber::new_or_panic(1))
+            .await
+            .unwrap();
+
+        // Only the messages up to the L1 block of the new L1 head remain, the others are fetched
+        // again along with the updates after it.
+        let tx = connection.transaction().unwrap();
+        let nonces = tx
+            .prepare("SELECT message_nonce FROM l1_to_l2_messages")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<Vec<L1ToL2MessageNonce>, _>>()
+            .unwrap();
+        assert_eq!(nonces, vec![L1ToL2MessageNonce(StarkHash::from_u64(0))]);
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
     async fn l1_reorg() {
         let results = [
@@ -1304,7 +1555,7 @@ mod tests {
         .into_iter()
         .map(|(updates, reorg_on_block)| async move {
             let storage = Storage::in_memory().unwrap();
-            let mut connection = storage.connection().unwrap();
+            let mut connection = storage.writer().unwrap();
             let tx = connection.transaction().unwrap();
 
             // A simple L1 sync task
@@ -1325,6 +1576,7 @@ mod tests {
                 .for_each(|update| L1StateTable::upsert(&tx, &update).unwrap());
 
             tx.commit().unwrap();
+            drop(connection);
 
             // UUT
             let _jh = tokio::spawn(state::sync(
@@ -1342,6 +1594,7 @@ mod tests {
             // TODO Find a better way to figure out that the DB update has already been performed
             tokio::time::sleep(Duration::from_millis(10)).await;
 
+            let mut connection = storage.connection().unwrap();
             let tx = connection.transaction().unwrap();
 
             let latest_block_number = L1StateTable::get(&tx, storage::L1TableBlockId::Latest)
@@ -1371,13 +1624,14 @@ mod tests {
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
     async fn l1_query_update() {
         let storage = Storage::in_memory().unwrap();
-        let mut connection = storage.connection().unwrap();
+        let mut connection = storage.writer().unwrap();
         let tx = connection.transaction().unwrap();
 
         // This is what we're asking for
         L1StateTable::upsert(&tx, &*STATE_UPDATE_LOG0).unwrap();
 
         tx.commit().unwrap();
+        drop(connection);
 
         // A simple L1 sync task which does the request and checks he result
         let l1 = |tx: mpsc::Sender<l1::Event>, _, _, _| async move {
@@ -1474,7 +1728,7 @@ mod tests {
         };
 
         // A simple L2 sync task
-        let l2 = move |tx: mpsc::Sender<l2::Event>, _, _, _, _| async move {
+        let l2 = move |tx: mpsc::Sender<l2::Event>, _, _, _, _, _| async move {
             tx.send(l2::Event::Update(
                 Box::new(block()),
                 Box::new(state_update()),
@@ -1495,7 +1749,7 @@ mod tests {
         .into_iter()
         .map(|update_log| async {
             let storage = Storage::in_memory().unwrap();
-            let mut connection = storage.connection().unwrap();
+            let mut connection = storage.writer().unwrap();
             let tx = connection.transaction().unwrap();
 
             if let Some(some_update_log) = update_log {
@@ -1503,6 +1757,7 @@ mod tests {
             }
 
             tx.commit().unwrap();
+            drop(connection);
 
             // UUT
             let _jh = tokio::spawn(state::sync(
@@ -1520,6 +1775,7 @@ mod tests {
             // TODO Find a better way to figure out that the DB update has already been performed
             tokio::time::sleep(Duration::from_millis(100)).await;
 
+            let mut connection = storage.connection().unwrap();
             let tx = connection.transaction().unwrap();
             RefsTable::get_l1_l2_head(&tx)
         })
@@ -1550,11 +1806,11 @@ mod tests {
         .into_iter()
         .map(|(updates, reorg_on_block)| async move {
             let storage = Storage::in_memory().unwrap();
-            let mut connection = storage.connection().unwrap();
+            let mut connection = storage.writer().unwrap();
             let tx = connection.transaction().unwrap();
 
             // A simple L2 sync task
-            let l2 = move |tx: mpsc::Sender<l2::Event>, _, _, _, _| async move {
+            let l2 = move |tx: mpsc::Sender<l2::Event>, _, _, _, _, _| async move {
                 tx.send(l2::Event::Reorg(StarknetBlockNumber::new_or_panic(
                     reorg_on_block,
                 )))
@@ -1571,6 +1827,7 @@ mod tests {
                 .for_each(|block| StarknetBlocksTable::insert(&tx, &block, None).unwrap());
 
             tx.commit().unwrap();
+            drop(connection);
 
             // UUT
             let _jh = tokio::spawn(state::sync(
@@ -1588,6 +1845,7 @@ mod tests {
             // TODO Find a better way to figure out that the DB update has already been performed
             tokio::time::sleep(Duration::from_millis(100)).await;
 
+            let mut connection = storage.connection().unwrap();
             let tx = connection.transaction().unwrap();
             let latest_block_number =
                 StarknetBlocksTable::get(&tx, storage::StarknetBlocksBlockId::Latest)
@@ -1614,13 +1872,123 @@ mod tests {
         );
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn l2_reorg_is_recorded() {
+        let storage = Storage::in_memory().unwrap();
+        let mut connection = storage.writer().unwrap();
+
+        let tx = connection.transaction().unwrap();
+        for block in [&*STORAGE_BLOCK0, &*STORAGE_BLOCK1] {
+            StarknetBlocksTable::insert(&tx, block, None).unwrap();
+        }
+        tx.commit().unwrap();
+
+        super::l2_reorg(&mut connection, StarknetBlockNumber::GENESIS)
+            .await
+            .unwrap();
+
+        let tx = connection.transaction().unwrap();
+        let reorgs = storage::ReorgHistoryTable::get_recent_reorgs(&tx, 10).unwrap();
+        assert_eq!(reorgs.len(), 1);
+        assert_eq!(reorgs[0].tail, StarknetBlockNumber::GENESIS);
+        assert_eq!(reorgs[0].old_head, STORAGE_BLOCK1.number);
+        assert_eq!(reorgs[0].old_head_hash, Some(STORAGE_BLOCK1.hash));
+        assert_eq!(reorgs[0].source, storage::ReorgSource::L2);
+        assert_eq!(reorgs[0].depth(), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn l2_head_watcher() {
+        lazy_static::lazy_static! {
+            // Lets the L2 task wait for each head to be seen before sending the next event, so
+            // that no update is coalesced.
+            static ref SEEN: tokio::sync::Semaphore = tokio::sync::Semaphore::new(0);
+        }
+
+        let storage = Storage::in_memory().unwrap();
+        let mut head = storage.head_watcher();
+        assert_eq!(*head.borrow(), None);
+
+        let l2 = |tx: mpsc::Sender<l2::Event>, _, _, _, _, _| async move {
+            // Roots remain 0, as for block 0.
+            let block1 = reply::Block {
+                block_hash: STORAGE_BLOCK1.hash,
+                block_number: STORAGE_BLOCK1.number,
+                parent_block_hash: BLOCK0.block_hash,
+                state_root: GlobalRoot(StarkHash::ZERO),
+                ..BLOCK0.clone()
+            };
+            let state_update1 = sequencer::reply::StateUpdate {
+                block_hash: Some(block1.block_hash),
+                ..STATE_UPDATE0.clone()
+            };
+            let timings = l2::Timings {
+                block_download: Duration::default(),
+                state_diff_download: Duration::default(),
+                contract_deployment: Duration::default(),
+                class_declaration: Duration::default(),
+            };
+            let events = [
+                l2::Event::Update(
+                    Box::new(BLOCK0.clone()),
+                    Box::new(STATE_UPDATE0.clone()),
+                    timings,
+                ),
+                l2::Event::Update(Box::new(block1), Box::new(state_update1), timings),
+                l2::Event::Reorg(STORAGE_BLOCK1.number),
+            ];
+
+            for event in events {
+                tx.send(event).await.unwrap();
+                SEEN.acquire().await.unwrap().forget();
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Ok(())
+        };
+
+        // UUT
+        let _jh = tokio::spawn(state::sync(
+            storage.clone(),
+            FakeTransport,
+            Chain::Testnet,
+            FakeSequencer,
+            Arc::new(state::SyncState::default()),
+            l1_noop,
+            l2,
+            PendingData::default(),
+            None,
+        ));
+
+        let mut connection = storage.connection().unwrap();
+        let mut seen = Vec::new();
+        for _ in 0..3 {
+            tokio::time::timeout(Duration::from_secs(1), head.changed())
+                .await
+                .unwrap()
+                .unwrap();
+            let current = *head.borrow_and_update();
+
+            // The head is only published once readable.
+            let tx = connection.transaction().unwrap();
+            assert_eq!(crate::storage::latest_head(&tx).unwrap(), current);
+            drop(tx);
+
+            seen.push(current);
+            SEEN.add_permits(1);
+        }
+
+        let head0 = Some((STORAGE_BLOCK0.number, STORAGE_BLOCK0.hash));
+        let head1 = Some((STORAGE_BLOCK1.number, STORAGE_BLOCK1.hash));
+        assert_eq!(seen, vec![head0, head1, head0]);
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
     async fn l2_new_contract() {
         let storage = Storage::in_memory().unwrap();
         let connection = storage.connection().unwrap();
 
         // A simple L2 sync task
-        let l2 = |tx: mpsc::Sender<l2::Event>, _, _, _, _| async move {
+        let l2 = |tx: mpsc::Sender<l2::Event>, _, _, _, _, _| async move {
             let zstd_magic = vec![0x28, 0xb5, 0x2f, 0xfd];
             tx.send(l2::Event::NewContract(state::CompressedContract {
                 abi: zstd_magic.clone(),
@@ -1660,23 +2028,27 @@ mod tests {
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
     async fn l2_query_hash() {
         let storage = Storage::in_memory().unwrap();
-        let mut connection = storage.connection().unwrap();
+        let mut connection = storage.writer().unwrap();
         let tx = connection.transaction().unwrap();
 
         // This is what we're asking for
         StarknetBlocksTable::insert(&tx, &STORAGE_BLOCK0, None).unwrap();
+        StarknetBlocksTable::insert(&tx, &STORAGE_BLOCK1, None).unwrap();
+
+        tx.commit().unwrap();
+        drop(connection);
 
         // A simple L2 sync task which does the request and checks he result
-        let l2 = |tx: mpsc::Sender<l2::Event>, _, _, _, _| async move {
+        let l2 = |tx: mpsc::Sender<l2::Event>, _, _, _, _, _| async move {
             let (tx1, rx1) = tokio::sync::oneshot::channel();
 
-            tx.send(l2::Event::QueryBlock(StarknetBlockNumber::GENESIS, tx1))
+            tx.send(l2::Event::QueryParentHash(STORAGE_BLOCK1.number, tx1))
                 .await
                 .unwrap();
 
             // Check the result straight away ¯\_(ツ)_/¯
             let result = rx1.await.unwrap().unwrap();
-            assert_eq!(result, (STORAGE_BLOCK0.hash, STORAGE_BLOCK0.root));
+            assert_eq!(result, STORAGE_BLOCK1.parent_hash);
 
             tokio::time::sleep(Duration::from_secs(1)).await;
             Ok(())
@@ -1699,7 +2071,7 @@ mod tests {
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
     async fn l2_query_contract_existance() {
         let storage = Storage::in_memory().unwrap();
-        let connection = storage.connection().unwrap();
+        let connection = storage.writer().unwrap();
         let zstd_magic = vec![0x28, 0xb5, 0x2f, 0xfd];
 
         // This is what we're asking for
@@ -1713,9 +2085,10 @@ mod tests {
             },
         )
         .unwrap();
+        drop(connection);
 
         // A simple L2 sync task which does the request and checks he result
-        let l2 = |tx: mpsc::Sender<l2::Event>, _, _, _, _| async move {
+        let l2 = |tx: mpsc::Sender<l2::Event>, _, _, _, _, _| async move {
             let (tx1, rx1) = tokio::sync::oneshot::channel::<Vec<bool>>();
 
             tx.send(l2::Event::QueryContractExistance(vec![ClassHash(*A)], tx1))
@@ -1752,7 +2125,7 @@ mod tests {
         static CNT: AtomicUsize = AtomicUsize::new(0);
 
         // A simple L2 sync task
-        let l2 = move |_, _, _, _, _| async move {
+        let l2 = move |_, _, _, _, _, _| async move {
             CNT.fetch_add(1, Ordering::Relaxed);
             Ok(())
         };
@@ -0,0 +1,131 @@
+//! Archives the original JSON of the blocks fetched from the sequencer, see
+//! [RawBlocksTable](crate::storage::RawBlocksTable).
+use std::num::NonZeroU64;
+
+use anyhow::Context;
+use tokio::sync::mpsc;
+
+use crate::{
+    core::{StarknetBlockHash, StarknetBlockNumber},
+    storage::{RawBlocksTable, Storage},
+};
+
+/// The fields required to archive a block, everything else is stored verbatim.
+#[derive(serde::Deserialize)]
+struct BlockId {
+    block_number: Option<StarknetBlockNumber>,
+    block_hash: Option<StarknetBlockHash>,
+}
+
+/// Archives the raw blocks received from `blocks`, which is fed by
+/// [sequencer::Client::with_raw_block_archive](crate::sequencer::Client::with_raw_block_archive).
+///
+/// Pending blocks are skipped. If `retain` is set, only that many of the latest blocks are kept.
+///
+/// This writes using the [Storage::auxiliary_writer], as the sync holds on to the
+/// [Storage::writer]. Failing to archive a block is logged but otherwise ignored.
+pub async fn archive(
+    storage: Storage,
+    mut blocks: mpsc::Receiver<bytes::Bytes>,
+    retain: Option<NonZeroU64>,
+) -> anyhow::Result<()> {
+    let mut compressor = storage.compression().compressor()?;
+
+    while let Some(raw) = blocks.recv().await {
+        let (number, hash) = match serde_json::from_slice::<BlockId>(&raw) {
+            Ok(BlockId {
+                block_number: Some(number),
+                block_hash: Some(hash),
+            }) => (number, hash),
+            Ok(_) => continue,
+            Err(e) => {
+                tracing::warn!(reason=%e, "Skipping unidentifiable raw block");
+                continue;
+            }
+        };
+
+        let result = tokio::task::block_in_place(|| -> anyhow::Result<()> {
+            let mut connection = storage
+                .auxiliary_writer()
+                .context("Create database connection")?;
+            let tx = connection
+                .transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)
+                .context("Create database transaction")?;
+
+            if !RawBlocksTable::insert(&tx, number, hash, &raw, &mut compressor)? {
+                return Ok(());
+            }
+            if let Some(retain) = retain {
+                let oldest = number.get().saturating_sub(retain.get() - 1);
+                RawBlocksTable::prune(&tx, StarknetBlockNumber::new_or_panic(oldest))?;
+            }
+
+            tx.commit().context("Commit database transaction")
+        });
+        // The archive is a debugging aid, failing to write it must not stop the node.
+        if let Err(e) = result {
+            tracing::warn!(block=%number.get(), reason=?e, "Failed to archive raw block");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::StarknetBlocksBlockId;
+
+    fn raw_block(number: u64) -> bytes::Bytes {
+        format!(
+            r#"{{"block_number":{number},"block_hash":"0x{number:x}","status":"ACCEPTED_ON_L2"}}"#
+        )
+        .into()
+    }
+
+    fn archived(storage: &Storage, number: u64) -> Option<Vec<u8>> {
+        let mut connection = storage.connection().unwrap();
+        let tx = connection.transaction().unwrap();
+        let number = StarknetBlockNumber::new_or_panic(number);
+        RawBlocksTable::get(&tx, StarknetBlocksBlockId::Number(number)).unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn archives_verbatim_and_skips_pending() {
+        let storage = Storage::in_memory().unwrap();
+        let (sender, receiver) = mpsc::channel(8);
+
+        sender.send(raw_block(0)).await.unwrap();
+        sender.send(raw_block(1)).await.unwrap();
+        sender
+            .send(r#"{"parent_block_hash":"0x1","status":"PENDING"}"#.into())
+            .await
+            .unwrap();
+        drop(sender);
+
+        archive(storage.clone(), receiver, None).await.unwrap();
+
+        assert_eq!(archived(&storage, 0), Some(raw_block(0).to_vec()));
+        assert_eq!(archived(&storage, 1), Some(raw_block(1).to_vec()));
+        assert_eq!(archived(&storage, 2), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn prunes_beyond_retention() {
+        let storage = Storage::in_memory().unwrap();
+        let (sender, receiver) = mpsc::channel(8);
+
+        for i in 0..5 {
+            sender.send(raw_block(i)).await.unwrap();
+        }
+        drop(sender);
+
+        archive(storage.clone(), receiver, NonZeroU64::new(2))
+            .await
+            .unwrap();
+
+        for i in 0..5 {
+            assert_eq!(archived(&storage, i).is_some(), i >= 3, "block {i}");
+        }
+    }
+}
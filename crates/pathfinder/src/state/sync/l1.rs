@@ -7,7 +7,7 @@ use tokio::sync::{mpsc, oneshot, RwLock};
 use crate::{
     core::{Chain, EthereumBlockHash, EthereumBlockNumber, StarknetBlockNumber},
     ethereum::{
-        log::{FetchError, StateUpdateLog},
+        log::{fetch_range, FetchError, L1ToL2MessageLog, StateUpdateLog},
         state_update::state_root::StateRootFetcher,
         transport::EthereumTransport,
     },
@@ -19,6 +19,9 @@ use crate::{
 pub enum Event {
     /// New L1 [update logs](StateUpdateLog) found.
     Update(Vec<StateUpdateLog>),
+    /// New L1 to L2 [message logs](L1ToL2MessageLog) found. These are sent before the
+    /// [Event::Update] of the updates they precede.
+    Messages(Vec<L1ToL2MessageLog>),
     /// An L1 reorg was detected, contains the reorg-tail which
     /// indicates the oldest block which is now invalid
     /// i.e. reorg-tail + 1 should be the new head.
@@ -42,8 +45,10 @@ where
     T: EthereumTransport + Send + Sync + Clone,
 {
     let eth_api = EthereumImpl {
+        message_head: Arc::new(RwLock::new(head.clone())),
         logs: Arc::new(RwLock::new(StateRootFetcher::new(head, chain))),
         transport,
+        chain,
     };
 
     // The core sync logic implementation.
@@ -55,6 +60,13 @@ where
 trait EthereumApi {
     async fn fetch_logs(&mut self) -> Result<Vec<StateUpdateLog>, FetchError>;
 
+    /// Fetches the messages sent since the previous call, up to the L1 block of the
+    /// [log head](EthereumApi::log_head).
+    ///
+    /// On [FetchError::Reorg] the log head is rolled back to the previous call's, so that the
+    /// next [EthereumApi::fetch_logs] detects the reorg.
+    async fn fetch_messages(&mut self) -> Result<Vec<L1ToL2MessageLog>, FetchError>;
+
     async fn set_log_head(&mut self, head: Option<StateUpdateLog>);
 
     async fn log_head(&self) -> Option<StateUpdateLog>;
@@ -85,7 +97,10 @@ where
 #[derive(Clone)]
 struct EthereumImpl<T: EthereumTransport + Send + Sync> {
     logs: Arc<RwLock<StateRootFetcher>>,
+    /// The update log up to whose L1 block the messages have been fetched.
+    message_head: Arc<RwLock<Option<StateUpdateLog>>>,
     transport: T,
+    chain: Chain,
 }
 
 #[async_trait::async_trait]
@@ -108,7 +123,43 @@ impl<T: EthereumTransport + Send + Sync + Clone> EthereumApi for EthereumImpl<T>
         Ok(logs)
     }
 
+    async fn fetch_messages(&mut self) -> Result<Vec<L1ToL2MessageLog>, FetchError> {
+        let from = match self.message_head.read().await.as_ref() {
+            Some(head) => EthereumBlockNumber(head.origin.block.number.0 + 1),
+            None => StateRootFetcher::genesis(self.chain),
+        };
+        let head = self.logs.read().await.head().clone();
+        let to = match head.as_ref() {
+            Some(head) => head.origin.block.number,
+            None => return Ok(Vec::new()),
+        };
+
+        let ff = || fetch_range(self.transport.clone(), self.chain, from, to);
+        let messages = retry(ff, |error| match error {
+            FetchError::Other(other) => {
+                tracing::warn!(reason=%other, "Failed fetching L1 messages, retrying");
+                true
+            }
+            FetchError::Reorg => false,
+        })
+        .await;
+
+        match messages {
+            Ok(messages) => {
+                *self.message_head.write().await = head;
+                Ok(messages)
+            }
+            Err(FetchError::Reorg) => {
+                let message_head = self.message_head.read().await.clone();
+                self.logs.write().await.set_head(message_head);
+                Err(FetchError::Reorg)
+            }
+            Err(other) => Err(other),
+        }
+    }
+
     async fn set_log_head(&mut self, head: Option<StateUpdateLog>) {
+        *self.message_head.write().await = head.clone();
         self.logs.write().await.set_head(head);
     }
 
@@ -153,6 +204,14 @@ impl EventSender {
         rx.await.map_err(|_recv_err| ChannelClosedError)
     }
 
+    /// Sends [Event::Messages] on its channel.
+    async fn messages(&self, messages: Vec<L1ToL2MessageLog>) -> Result<(), ChannelClosedError> {
+        self.0
+            .send(Event::Messages(messages))
+            .await
+            .map_err(|_send_err| ChannelClosedError)
+    }
+
     /// Sends [Event::Update] on its channel.
     async fn updates(&self, updates: Vec<StateUpdateLog>) -> Result<(), ChannelClosedError> {
         self.0
@@ -189,6 +248,20 @@ async fn sync_impl(
                     continue;
                 }
 
+                // Messages consumed by these updates' blocks were sent before the last update.
+                let messages = match eth_api.fetch_messages().await {
+                    Ok(messages) => messages,
+                    // The updates are dropped, and fetched again from before the reorg.
+                    Err(FetchError::Reorg) => continue,
+                    // Unreachable provided that `eth_api` implements a retry policy.
+                    Err(FetchError::Other(other)) => anyhow::bail!(other),
+                };
+                if !messages.is_empty() {
+                    if let Err(_exit) = event_sender.messages(messages).await {
+                        return Ok(());
+                    }
+                }
+
                 // There were log updates, send the event!
                 if let Err(_exit) = event_sender.updates(logs).await {
                     return Ok(());
@@ -272,6 +345,7 @@ mod tests {
         use crate::{
             core::{
                 EthereumLogIndex, EthereumTransactionHash, EthereumTransactionIndex, GlobalRoot,
+                L1ToL2MessageNonce,
             },
             ethereum::{BlockOrigin, EthOrigin, TransactionOrigin},
             starkhash,
@@ -327,6 +401,9 @@ mod tests {
 
             // Create a mocker which expects
             let mut mock_fetcher = MockEthereumApi::new();
+            mock_fetcher
+                .expect_fetch_messages()
+                .returning(|| Ok(Vec::new()));
             let mut seq = mockall::Sequence::new();
             let mock_output = Ok(logs1.clone());
             mock_fetcher
@@ -386,6 +463,9 @@ mod tests {
             // Closing the event's channel should trigger the sync to exit after the first send.
             rx_event.close();
             let mut mock_fetcher = MockEthereumApi::new();
+            mock_fetcher
+                .expect_fetch_messages()
+                .returning(|| Ok(Vec::new()));
             mock_fetcher
                 .expect_fetch_logs()
                 .return_once(move || Ok(logs));
@@ -399,6 +479,101 @@ mod tests {
                 .unwrap();
         }
 
+        /// Creates a log with the given Starknet block number, in the L1 block of the same
+        /// number.
+        fn update_log(block: u64) -> StateUpdateLog {
+            StateUpdateLog {
+                origin: EthOrigin {
+                    block: BlockOrigin {
+                        hash: EthereumBlockHash(H256::from_low_u64_be(block + 100)),
+                        number: EthereumBlockNumber(block),
+                    },
+                    transaction: TransactionOrigin {
+                        hash: EthereumTransactionHash(H256::from_low_u64_be(block + 200)),
+                        index: EthereumTransactionIndex(1),
+                    },
+                    log_index: EthereumLogIndex(1),
+                },
+                global_root: GlobalRoot(StarkHash::from_u64(block)),
+                block_number: StarknetBlockNumber::new_or_panic(block),
+            }
+        }
+
+        #[tokio::test]
+        async fn messages_before_update() {
+            let (tx_event, mut rx_event) = mpsc::channel(1);
+
+            let logs = vec![update_log(0)];
+            let messages = vec![L1ToL2MessageLog {
+                origin: logs[0].origin.clone(),
+                nonce: L1ToL2MessageNonce(starkhash!("07")),
+            }];
+
+            let mut mock_fetcher = MockEthereumApi::new();
+            let mut seq = mockall::Sequence::new();
+            let mock_output = Ok(logs.clone());
+            mock_fetcher
+                .expect_fetch_logs()
+                .times(1)
+                .in_sequence(&mut seq)
+                .return_once(move || mock_output);
+            let mock_output = Ok(messages.clone());
+            mock_fetcher
+                .expect_fetch_messages()
+                .times(1)
+                .in_sequence(&mut seq)
+                .return_once(move || mock_output);
+            // Accept further calls, progress is blocked by not receiving the events.
+            mock_fetcher
+                .expect_fetch_logs()
+                .returning(|| Ok(vec![update_log(1)]));
+            mock_fetcher
+                .expect_fetch_messages()
+                .returning(|| Ok(Vec::new()));
+
+            tokio::spawn(sync_impl(mock_fetcher, tx_event, Chain::Testnet));
+
+            match rx_event.recv().await.unwrap() {
+                Event::Messages(recv) => assert_eq!(recv, messages),
+                _other => panic!("Expected Messages event"),
+            }
+            match rx_event.recv().await.unwrap() {
+                Event::Update(recv) => assert_eq!(recv, logs),
+                _other => panic!("Expected Updates event"),
+            }
+        }
+
+        #[tokio::test]
+        async fn updates_dropped_on_message_reorg() {
+            let (tx_event, mut rx_event) = mpsc::channel(1);
+
+            let mut mock_fetcher = MockEthereumApi::new();
+            let mut seq = mockall::Sequence::new();
+            mock_fetcher
+                .expect_fetch_logs()
+                .times(1)
+                .in_sequence(&mut seq)
+                .returning(|| Ok(vec![update_log(1)]));
+            mock_fetcher
+                .expect_fetch_messages()
+                .times(1)
+                .in_sequence(&mut seq)
+                .returning(|| Err(FetchError::Reorg));
+            mock_fetcher
+                .expect_fetch_logs()
+                .returning(|| Ok(vec![update_log(0)]));
+            mock_fetcher
+                .expect_fetch_messages()
+                .returning(|| Ok(Vec::new()));
+
+            tokio::spawn(sync_impl(mock_fetcher, tx_event, Chain::Testnet));
+
+            match rx_event.recv().await.unwrap() {
+                Event::Update(recv) => assert_eq!(recv, vec![update_log(0)]),
+                _other => panic!("Expected Updates event"),
+            }
+        }
+
         mod reorg {
             use super::*;
 
@@ -433,6 +608,9 @@ mod tests {
                 let expected_head = logs.iter().rev().nth(REORG_COUNT + 1).unwrap().clone();
 
                 let mut mock_fetcher = MockEthereumApi::new();
+                mock_fetcher
+                    .expect_fetch_messages()
+                    .returning(|| Ok(Vec::new()));
                 let mut seq = mockall::Sequence::new();
                 let mock_output = Ok(logs.clone());
                 mock_fetcher
@@ -528,6 +706,9 @@ mod tests {
                     .collect::<Vec<_>>();
 
                 let mut mock_fetcher = MockEthereumApi::new();
+                mock_fetcher
+                    .expect_fetch_messages()
+                    .returning(|| Ok(Vec::new()));
                 let mut seq = mockall::Sequence::new();
                 let mock_output = Ok(logs.clone());
                 mock_fetcher
@@ -616,6 +797,9 @@ mod tests {
                     .collect::<Vec<_>>();
 
                 let mut mock_fetcher = MockEthereumApi::new();
+                mock_fetcher
+                    .expect_fetch_messages()
+                    .returning(|| Ok(Vec::new()));
                 let mut seq = mockall::Sequence::new();
                 let mock_output = Ok(logs.clone());
                 mock_fetcher
@@ -12,6 +12,7 @@ use crate::sequencer::reply::{Block, Status};
 use crate::state::block_hash::verify_block_hash;
 use crate::state::class_hash::extract_abi_code_hash;
 use crate::state::CompressedContract;
+use crate::storage::CompressionConfig;
 use crate::{
     core::{Chain, ClassHash, StarknetBlockHash, StarknetBlockNumber},
     sequencer::reply::{PendingBlock, StateUpdate},
@@ -36,12 +37,12 @@ pub enum Event {
     Reorg(StarknetBlockNumber),
     /// A new unique L2 [contract](CompressedContract) was found.
     NewContract(CompressedContract),
-    /// Query for the [block hash](StarknetBlockHash) and [root](GlobalRoot) of the given block.
+    /// Query for the stored [parent hash](StarknetBlockHash) of the given block.
     ///
     /// The receiver should return the data using the [oneshot::channel].
-    QueryBlock(
+    QueryParentHash(
         StarknetBlockNumber,
-        oneshot::Sender<Option<(StarknetBlockHash, GlobalRoot)>>,
+        oneshot::Sender<Option<StarknetBlockHash>>,
     ),
     /// Query for the existance of the the given [contracts](ClassHash) in storage.
     ///
@@ -58,6 +59,7 @@ pub async fn sync(
     mut head: Option<(StarknetBlockNumber, StarknetBlockHash, GlobalRoot)>,
     chain: Chain,
     pending_poll_interval: Option<Duration>,
+    compression: CompressionConfig,
 ) -> anyhow::Result<()> {
     use crate::state::sync::head_poll_interval;
 
@@ -136,14 +138,14 @@ pub async fn sync(
 
         // Download and emit newly declared classes.
         let t_declare = std::time::Instant::now();
-        declare_classes(&block, &sequencer, &tx_event)
+        declare_classes(&block, &sequencer, &tx_event, compression)
             .await
             .with_context(|| format!("Handling newly declared classes for block {:?}", next))?;
         let t_declare = t_declare.elapsed();
 
         // Download and emit any newly deployed (but undeclared) classes.
         let t_deploy = std::time::Instant::now();
-        deploy_contracts(&tx_event, &sequencer, &state_update.state_diff)
+        deploy_contracts(&tx_event, &sequencer, &state_update.state_diff, compression)
             .await
             .with_context(|| format!("Deploying new contracts for block {:?}", next))?;
         let t_deploy = t_deploy.elapsed();
@@ -172,6 +174,7 @@ async fn declare_classes(
     block: &Block,
     sequencer: &impl sequencer::ClientApi,
     tx_event: &mpsc::Sender<Event>,
+    compression: CompressionConfig,
 ) -> Result<(), anyhow::Error> {
     let declared_classes = block
         .transactions
@@ -217,7 +220,7 @@ async fn declare_classes(
         .collect::<Vec<_>>();
 
     for class_hash in require_downloading {
-        let class = download_and_compress_class(class_hash, sequencer)
+        let class = download_and_compress_class(class_hash, sequencer, compression)
             .await
             .with_context(|| format!("Downloading class {}", class_hash.0))?;
 
@@ -333,26 +336,26 @@ async fn reorg(
 
         let (tx, rx) = oneshot::channel();
         tx_event
-            .send(Event::QueryBlock(previous_block_number, tx))
+            .send(Event::QueryParentHash(reorg_tail.0, tx))
             .await
             .context("Event channel closed")?;
 
-        let previous = match rx.await.context("Oneshot channel closed")? {
+        let previous_hash = match rx.await.context("Oneshot channel closed")? {
             Some(hash) => hash,
             None => break None,
         };
 
-        match download_block(previous_block_number, chain, Some(previous.0), sequencer)
+        match download_block(previous_block_number, chain, Some(previous_hash), sequencer)
             .await
             .with_context(|| format!("Download block {} from sequencer", previous_block_number))?
         {
-            DownloadBlock::Block(block) if block.block_hash == previous.0 => {
-                break Some((previous_block_number, previous.0, previous.1));
+            DownloadBlock::Block(block) if block.block_hash == previous_hash => {
+                break Some((previous_block_number, previous_hash, block.state_root));
             }
             _ => {}
         };
 
-        reorg_tail = (previous_block_number, previous.0, previous.1);
+        reorg_tail = (previous_block_number, previous_hash, reorg_tail.2);
     };
 
     let reorg_tail = new_head
@@ -371,6 +374,7 @@ async fn deploy_contracts(
     tx_event: &mpsc::Sender<Event>,
     sequencer: &impl sequencer::ClientApi,
     state_diff: &StateDiff,
+    compression: CompressionConfig,
 ) -> anyhow::Result<()> {
     let unique_contracts = state_diff
         .deployed_contracts
@@ -416,7 +420,7 @@ async fn deploy_contracts(
             .find(|contract| contract.class_hash == contract_hash)
             .unwrap();
 
-        let contract = download_and_compress_contract(contract, sequencer)
+        let contract = download_and_compress_contract(contract, sequencer, compression)
             .await
             .with_context(|| format!("Download and compress contract {:?}", contract.address))?;
 
@@ -435,6 +439,7 @@ async fn deploy_contracts(
 async fn download_and_compress_class(
     class_hash: ClassHash,
     sequencer: &impl sequencer::ClientApi,
+    compression: CompressionConfig,
 ) -> anyhow::Result<CompressedContract> {
     let definition = sequencer
         .class_by_hash(class_hash)
@@ -460,7 +465,7 @@ async fn download_and_compress_class(
     );
 
     let compress = tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
-        let mut compressor = zstd::bulk::Compressor::new(10).context("Create zstd compressor")?;
+        let mut compressor = compression.compressor()?;
 
         let abi = compressor.compress(&abi).context("Compress ABI")?;
         let bytecode = compressor
@@ -485,6 +490,7 @@ async fn download_and_compress_class(
 async fn download_and_compress_contract(
     contract: &DeployedContract,
     sequencer: &impl sequencer::ClientApi,
+    compression: CompressionConfig,
 ) -> anyhow::Result<CompressedContract> {
     let contract_definition = sequencer
         .full_contract(contract.address)
@@ -509,7 +515,7 @@ async fn download_and_compress_contract(
     );
 
     let compress = tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
-        let mut compressor = zstd::bulk::Compressor::new(10).context("Create zstd compressor")?;
+        let mut compressor = compression.compressor()?;
 
         let abi = compressor.compress(&abi).context("Compress ABI")?;
         let bytecode = compressor
@@ -535,6 +541,7 @@ async fn download_and_compress_contract(
 mod tests {
     mod sync {
         use super::super::{sync, Event};
+        use crate::storage::CompressionConfig;
         use crate::{
             core::{
                 BlockId, ClassHash, ContractAddress, GasPrice, GlobalRoot, SequencerAddress,
@@ -903,7 +910,14 @@ mod tests {
                 );
 
                 // Let's run the UUT
-                let _jh = tokio::spawn(sync(tx_event, mock, None, Chain::Testnet, None));
+                let _jh = tokio::spawn(sync(
+                    tx_event,
+                    mock,
+                    None,
+                    Chain::Testnet,
+                    None,
+                    CompressionConfig::default(),
+                ));
 
                 let zstd_magic = vec![0x28, 0xb5, 0x2f, 0xfd];
 
@@ -988,6 +1002,7 @@ mod tests {
                     Some((BLOCK0_NUMBER, *BLOCK0_HASH, *GLOBAL_ROOT0)),
                     Chain::Testnet,
                     None,
+                    CompressionConfig::default(),
                 ));
 
                 let zstd_magic = vec![0x28, 0xb5, 0x2f, 0xfd];
@@ -1027,7 +1042,14 @@ mod tests {
                 block.status = Status::Reverted;
                 expect_block(&mut mock, &mut seq, BLOCK0_NUMBER.into(), Ok(block.into()));
 
-                let jh = tokio::spawn(sync(tx_event, mock, None, Chain::Testnet, None));
+                let jh = tokio::spawn(sync(
+                    tx_event,
+                    mock,
+                    None,
+                    Chain::Testnet,
+                    None,
+                    CompressionConfig::default(),
+                ));
                 let error = jh.await.unwrap().unwrap_err();
                 assert_eq!(
                     &error.to_string(),
@@ -1130,7 +1152,14 @@ mod tests {
                 );
 
                 // Let's run the UUT
-                let _jh = tokio::spawn(sync(tx_event, mock, None, Chain::Testnet, None));
+                let _jh = tokio::spawn(sync(
+                    tx_event,
+                    mock,
+                    None,
+                    Chain::Testnet,
+                    None,
+                    CompressionConfig::default(),
+                ));
 
                 let zstd_magic = vec![0x28, 0xb5, 0x2f, 0xfd];
 
@@ -1335,7 +1364,14 @@ mod tests {
                 );
 
                 // Run the UUT
-                let _jh = tokio::spawn(sync(tx_event, mock, None, Chain::Testnet, None));
+                let _jh = tokio::spawn(sync(
+                    tx_event,
+                    mock,
+                    None,
+                    Chain::Testnet,
+                    None,
+                    CompressionConfig::default(),
+                ));
 
                 let zstd_magic = vec![0x28, 0xb5, 0x2f, 0xfd];
 
@@ -1375,13 +1411,13 @@ mod tests {
                     assert_eq!(*block, *BLOCK2);
                     assert_eq!(*state_update, *STATE_UPDATE2);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::QueryBlock(block_number, sender) => {
-                    assert_eq!(block_number, BLOCK1_NUMBER);
-                    sender.send(Some((*BLOCK1_HASH, *GLOBAL_ROOT1))).unwrap();
+                assert_matches!(rx_event.recv().await.unwrap(), Event::QueryParentHash(block_number, sender) => {
+                    assert_eq!(block_number, BLOCK2_NUMBER);
+                    sender.send(Some(*BLOCK1_HASH)).unwrap();
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::QueryBlock(block_number, sender) => {
-                    assert_eq!(block_number, BLOCK0_NUMBER);
-                    sender.send(Some((*BLOCK0_HASH, *GLOBAL_ROOT0))).unwrap();
+                assert_matches!(rx_event.recv().await.unwrap(), Event::QueryParentHash(block_number, sender) => {
+                    assert_eq!(block_number, BLOCK1_NUMBER);
+                    sender.send(Some(*BLOCK0_HASH)).unwrap();
                 });
                 // Reorg started at the genesis block
                 assert_matches!(rx_event.recv().await.unwrap(), Event::Reorg(tail) => {
@@ -1615,7 +1651,14 @@ mod tests {
                 );
 
                 // Run the UUT
-                let _jh = tokio::spawn(sync(tx_event, mock, None, Chain::Testnet, None));
+                let _jh = tokio::spawn(sync(
+                    tx_event,
+                    mock,
+                    None,
+                    Chain::Testnet,
+                    None,
+                    CompressionConfig::default(),
+                ));
 
                 let zstd_magic = vec![0x28, 0xb5, 0x2f, 0xfd];
 
@@ -1659,17 +1702,17 @@ mod tests {
                     assert_eq!(*block, block3);
                     assert_eq!(*state_update, *STATE_UPDATE3);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::QueryBlock(block_number, sender) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::QueryParentHash(block_number, sender) => {
+                    assert_eq!(block_number, BLOCK3_NUMBER);
+                    sender.send(Some(*BLOCK0_HASH)).unwrap();
+                });
+                assert_matches!(rx_event.recv().await.unwrap(), Event::QueryParentHash(block_number, sender) => {
                     assert_eq!(block_number, BLOCK2_NUMBER);
-                    sender.send(Some((*BLOCK0_HASH, *GLOBAL_ROOT2))).unwrap();
+                    sender.send(Some(*BLOCK1_HASH)).unwrap();
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::QueryBlock(block_number, sender) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::QueryParentHash(block_number, sender) => {
                     assert_eq!(block_number, BLOCK1_NUMBER);
-                    sender.send(Some((*BLOCK1_HASH, *GLOBAL_ROOT1))).unwrap();
-                });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::QueryBlock(block_number, sender) => {
-                    assert_eq!(block_number, BLOCK0_NUMBER);
-                    sender.send(Some((*BLOCK0_HASH, *GLOBAL_ROOT0))).unwrap();
+                    sender.send(Some(*BLOCK0_HASH)).unwrap();
                 });
                 // Reorg started from block #1
                 assert_matches!(rx_event.recv().await.unwrap(), Event::Reorg(tail) => {
@@ -1822,7 +1865,14 @@ mod tests {
                 );
 
                 // Run the UUT
-                let _jh = tokio::spawn(sync(tx_event, mock, None, Chain::Testnet, None));
+                let _jh = tokio::spawn(sync(
+                    tx_event,
+                    mock,
+                    None,
+                    Chain::Testnet,
+                    None,
+                    CompressionConfig::default(),
+                ));
 
                 let zstd_magic = vec![0x28, 0xb5, 0x2f, 0xfd];
 
@@ -1862,9 +1912,9 @@ mod tests {
                     assert_eq!(*block, *BLOCK2);
                     assert_eq!(*state_update, *STATE_UPDATE2);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::QueryBlock(block_number, sender) => {
-                    assert_eq!(block_number, BLOCK1_NUMBER);
-                    sender.send(Some((*BLOCK1_HASH, *GLOBAL_ROOT1))).unwrap();
+                assert_matches!(rx_event.recv().await.unwrap(), Event::QueryParentHash(block_number, sender) => {
+                    assert_eq!(block_number, BLOCK2_NUMBER);
+                    sender.send(Some(*BLOCK1_HASH)).unwrap();
                 });
                 // Reorg started from block #2
                 assert_matches!(rx_event.recv().await.unwrap(), Event::Reorg(tail) => {
@@ -2018,7 +2068,14 @@ mod tests {
                 );
 
                 // Run the UUT
-                let _jh = tokio::spawn(sync(tx_event, mock, None, Chain::Testnet, None));
+                let _jh = tokio::spawn(sync(
+                    tx_event,
+                    mock,
+                    None,
+                    Chain::Testnet,
+                    None,
+                    CompressionConfig::default(),
+                ));
 
                 let zstd_magic = vec![0x28, 0xb5, 0x2f, 0xfd];
 
@@ -2054,9 +2111,9 @@ mod tests {
                     assert_eq!(*block, *BLOCK1);
                     assert_eq!(*state_update, *STATE_UPDATE1);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::QueryBlock(block_number, sender) => {
-                    assert_eq!(block_number, BLOCK0_NUMBER);
-                    sender.send(Some((*BLOCK0_HASH, *GLOBAL_ROOT0))).unwrap();
+                assert_matches!(rx_event.recv().await.unwrap(), Event::QueryParentHash(block_number, sender) => {
+                    assert_eq!(block_number, BLOCK1_NUMBER);
+                    sender.send(Some(*BLOCK0_HASH)).unwrap();
                 });
                 // Reorg started from block #1
                 assert_matches!(rx_event.recv().await.unwrap(), Event::Reorg(tail) => {
@@ -2095,7 +2152,14 @@ mod tests {
                 );
 
                 // Run the UUT
-                let jh = tokio::spawn(sync(tx_event, mock, None, Chain::Testnet, None));
+                let jh = tokio::spawn(sync(
+                    tx_event,
+                    mock,
+                    None,
+                    Chain::Testnet,
+                    None,
+                    CompressionConfig::default(),
+                ));
 
                 // Wrap this in a timeout so we don't wait forever in case of test failure.
                 // Right now closing the channel causes an error.
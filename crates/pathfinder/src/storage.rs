@@ -3,28 +3,51 @@
 //! Currently this consists of a Sqlite backend implementation.
 
 mod contract;
+mod error;
 mod ethereum;
+mod event_bloom;
+mod event_filters;
 #[cfg(test)]
 pub(crate) mod fixtures;
 pub mod merkle_tree;
+pub(crate) mod metrics;
 mod schema;
+mod snapshot;
+mod staged;
 mod state;
 
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 pub use contract::{ContractCodeTable, ContractsTable};
+pub use error::StorageError;
 pub use ethereum::{EthereumBlocksTable, EthereumTransactionsTable};
+pub use event_filters::{EventFiltersTable, FilterId};
+pub use metrics::set_slow_query_threshold;
+pub use snapshot::{export, export_range, import, import_range};
+pub use staged::StagedBlocksTable;
 pub use state::{
-    CanonicalBlocksTable, ContractsStateTable, EventFilterError, L1StateTable, L1TableBlockId,
-    RefsTable, StarknetBlock, StarknetBlocksBlockId, StarknetBlocksTable, StarknetEmittedEvent,
-    StarknetEventFilter, StarknetEventsTable, StarknetStateUpdatesTable, StarknetTransactionsTable,
+    backfill_block_versions, consistency_check, get_block_with_receipts, get_l1_confirmed_head,
+    reorg_preview, roots_agree, verify_block_root, BlockListEntry, BlockWithReceipts,
+    CanonicalBlocksTable, ChainMismatch, ConsistencyReport, ContractClassIndexTable,
+    ContractSummariesTable, ContractSummary, ContractsStateTable, DeployedContractsTable,
+    EventCursor, EventFilterError, EventStats, ExportFormat, FeeStats, L1ConfirmedHead,
+    L1HandlerOrigin, L1HandlerOriginsTable, L1StateTable, L1TableBlockId, MetaTable,
+    NonMonotonicTimestamp, PageOfEvents, RawBlocksTable, RefsTable, ReorgHistoryTable,
+    ReorgPreview, ReorgRecord, ReorgSource, StarknetBlock, StarknetBlockHeader,
+    StarknetBlocksBlockId, StarknetBlocksTable, StarknetEmittedEvent, StarknetEventFilter,
+    StarknetEventsTable, StarknetStateUpdatesTable, StarknetTransactionsTable,
+    StarknetVersionsTable, StorageIntegrity, StorageUpdatesTable, TimestampPolicy,
+    TimestampRegression, TransactionBlockConflict, TransactionInclusionProof, VersionBackfill,
 };
 
+use crate::core::{Chain, StarknetBlockHash, StarknetBlockNumber};
+
 use anyhow::Context;
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::Connection;
+use stark_hash::StarkHash;
 
 /// Sqlite key used for the PRAGMA user version.
 const VERSION_KEY: &str = "user_version";
@@ -33,18 +56,64 @@ type PooledConnection = r2d2::PooledConnection<SqliteConnectionManager>;
 
 /// Specifies the [journal mode](https://sqlite.org/pragma.html#pragma_journal_mode)
 /// of the [Storage].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum JournalMode {
     Rollback,
     WAL,
 }
 
+/// Specifies the [checkpoint mode](https://sqlite.org/pragma.html#pragma_wal_checkpoint)
+/// used by [Storage::wal_checkpoint].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CheckpointMode {
+    /// Checkpoints as many frames as possible without waiting on readers or writers.
+    Passive,
+    /// Waits for writers, then checkpoints all frames.
+    Full,
+    /// Like [CheckpointMode::Full], but also truncates the WAL file to zero bytes.
+    Truncate,
+}
+
+/// The outcome of a [Storage::wal_checkpoint], as reported by Sqlite.
+///
+/// `log` and `checkpointed` are -1 if the database is not in WAL mode, and 0 after a
+/// successful [CheckpointMode::Truncate] as the WAL file is then reset.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WalCheckpoint {
+    /// Whether the checkpoint could not complete because of other readers or writers.
+    pub busy: bool,
+    /// The number of frames in the WAL file.
+    pub log: i64,
+    /// The number of frames which were checkpointed into the database.
+    pub checkpointed: i64,
+}
+
+/// The heads of the chain data in storage, see [Storage::tips].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ChainTips {
+    /// The hash and number of the latest block.
+    pub latest: Option<(StarknetBlockHash, StarknetBlockNumber)>,
+    /// The latest block which has been verified against L1.
+    pub l1_l2_head: Option<StarknetBlockNumber>,
+    /// The highest block with stored transactions.
+    pub highest_with_transactions: Option<StarknetBlockNumber>,
+    /// The highest block with a stored state update.
+    pub highest_with_state_update: Option<StarknetBlockNumber>,
+    /// The block of the latest L1 state update log.
+    pub l1_state: Option<StarknetBlockNumber>,
+}
+
 /// Used to create [Connection's](Connection) to the pathfinder database.
 ///
 /// Intended usage:
 /// - Use [Storage::migrate] to create the app's database.
 /// - Pass the [Storage] (or clones thereof) to components which require database access.
 /// - Use [Storage::connection] to create connection's to the database, which can in turn
-///   be used to interact with the various [tables](self).
+///   be used to interact with the various [tables](self). These connections cannot modify the
+///   database.
+/// - Use [Storage::reader] for queries, and leave [Storage::writer] to the sync process.
+/// - Write only through [Storage::writer], or [Storage::auxiliary_writer] for the few writes
+///   which must not wait for the sync process.
 #[derive(Clone)]
 pub struct Storage(Inner);
 
@@ -52,7 +121,227 @@ pub struct Storage(Inner);
 struct Inner {
     /// Uses [`Arc`] to allow _shallow_ [Storage] cloning
     database_path: Arc<PathBuf>,
+    /// Connections which have the `query_only` pragma set.
     pool: Pool<SqliteConnectionManager>,
+    /// Connections which have the `query_only` pragma set, limited to serve a bounded number
+    /// of queries concurrently.
+    readers: Pool<SqliteConnectionManager>,
+    /// Holds a single connection, so that writers don't compete for the database lock.
+    writer: Pool<SqliteConnectionManager>,
+    /// Holds a single connection for the writes listed at [Storage::auxiliary_writer].
+    auxiliary: Pool<SqliteConnectionManager>,
+    journal_mode: JournalMode,
+    compression: CompressionConfig,
+    events: Option<EventSender>,
+    head: Arc<HeadSender>,
+}
+
+/// How long a connection waits for a database lock before failing with `SQLITE_BUSY`.
+const BUSY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// The number of times [Storage::read] retries a query which failed because the database was
+/// busy, and the delay before the first retry. The delay doubles on each retry.
+const READ_RETRIES: u32 = 5;
+const READ_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(10);
+
+/// The WAL file is truncated to this size after checkpoints.
+const JOURNAL_SIZE_LIMIT: usize = 1024 * 1024 * 1024;
+
+/// Configures each new pooled connection, so that all pools share the same per-connection
+/// pragmas.
+#[derive(Debug)]
+struct ConnectionSetup {
+    query_only: bool,
+    journal_mode: JournalMode,
+}
+
+impl r2d2::CustomizeConnection<Connection, rusqlite::Error> for ConnectionSetup {
+    fn on_acquire(&self, conn: &mut Connection) -> Result<(), rusqlite::Error> {
+        use rusqlite::config::DbConfig::SQLITE_DBCONFIG_ENABLE_FKEY;
+
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.set_db_config(SQLITE_DBCONFIG_ENABLE_FKEY, true)?;
+        if self.journal_mode == JournalMode::WAL {
+            // Persistent once set, but this makes every connection agree on it even if the
+            // database was switched back to rollback mode by another process.
+            conn.pragma_update(None, "journal_mode", "WAL")?;
+            conn.pragma_update(None, "journal_size_limit", JOURNAL_SIZE_LIMIT.to_string())?;
+        }
+        if self.query_only {
+            conn.pragma_update(None, "query_only", true)?;
+        }
+        Ok(())
+    }
+}
+
+/// Creates a pool of up to `size` connections to the database at `path`.
+fn connection_pool(
+    path: &Path,
+    size: u32,
+    query_only: bool,
+    journal_mode: JournalMode,
+) -> anyhow::Result<Pool<SqliteConnectionManager>> {
+    let pool = Pool::builder()
+        .max_size(size)
+        .connection_customizer(Box::new(ConnectionSetup {
+            query_only,
+            journal_mode,
+        }))
+        .build(SqliteConnectionManager::file(path))?;
+    Ok(pool)
+}
+
+/// Reads the latest block in the form published by the [HeadSender].
+pub(crate) fn latest_head(
+    tx: &rusqlite::Transaction<'_>,
+) -> anyhow::Result<Option<(StarknetBlockNumber, StarknetBlockHash)>> {
+    let head = StarknetBlocksTable::get_latest_hash_and_number(tx)
+        .context("Read latest block")?
+        .map(|(hash, number)| (number, hash));
+    Ok(head)
+}
+
+/// An error which may be caused by the database being locked by another connection, in which
+/// case [Storage::read] retries the failed read.
+pub trait MaybeBusy {
+    fn is_busy(&self) -> bool;
+}
+
+impl MaybeBusy for anyhow::Error {
+    fn is_busy(&self) -> bool {
+        is_busy(self)
+    }
+}
+
+impl MaybeBusy for StorageError {
+    fn is_busy(&self) -> bool {
+        matches!(self, StorageError::Busy)
+    }
+}
+
+/// Returns true if the error was caused by another connection holding a database lock.
+fn is_busy(error: &anyhow::Error) -> bool {
+    if let Some(StorageError::Busy) = error.downcast_ref::<StorageError>() {
+        return true;
+    }
+
+    matches!(
+        error.downcast_ref::<rusqlite::Error>(),
+        Some(rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error {
+                code: rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked,
+                ..
+            },
+            _,
+        ))
+    )
+}
+
+/// Broadcasts [StarknetEmittedEvent]s once the blocks which insert them are committed.
+///
+/// Sending never blocks: receivers which fall behind by more than the channel's capacity
+/// miss the oldest events and get a [lagged](tokio::sync::broadcast::error::RecvError::Lagged)
+/// error instead.
+pub type EventSender = tokio::sync::broadcast::Sender<StarknetEmittedEvent>;
+
+/// Publishes the number and hash of the latest canonical block, see [Storage::head_watcher].
+///
+/// Writers update it only once the block has been committed, so that the head a receiver sees
+/// is always visible to new read connections.
+pub type HeadSender = tokio::sync::watch::Sender<Option<(StarknetBlockNumber, StarknetBlockHash)>>;
+
+/// Specifies how blobs such as transactions and state updates are compressed
+/// when written to the database.
+///
+/// Reading is independent of these settings, so they may be changed between runs.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CompressionConfig {
+    /// The zstd compression level.
+    pub level: i32,
+    /// Enables zstd's long distance matching with a larger window, which shrinks large blobs
+    /// further at the cost of compression speed and memory.
+    pub long_window: bool,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            level: 10,
+            long_window: false,
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// The window log used for [CompressionConfig::long_window]. This is the largest window
+    /// zstd decompresses by default, so reads don't need to know the setting.
+    const LONG_WINDOW_LOG: u32 = 27;
+
+    /// Creates a zstd compressor using these settings.
+    ///
+    /// The compressor may be reused for multiple blobs.
+    pub fn compressor(&self) -> anyhow::Result<zstd::bulk::Compressor<'static>> {
+        use zstd::stream::raw::CParameter;
+
+        let mut compressor =
+            zstd::bulk::Compressor::new(self.level).context("Create zstd compressor")?;
+        if self.long_window {
+            compressor
+                .set_parameter(CParameter::EnableLongDistanceMatching(true))
+                .context("Enable long distance matching")?;
+            compressor
+                .set_parameter(CParameter::WindowLog(Self::LONG_WINDOW_LOG))
+                .context("Set compression window")?;
+        }
+        Ok(compressor)
+    }
+}
+
+/// A zstd decompression context which may be reused for multiple blobs, unlike
+/// [zstd::decode_all] which creates a new one for every blob.
+pub struct Decompressor {
+    decoder: zstd::stream::raw::Decoder<'static>,
+    chunk: Vec<u8>,
+}
+
+impl Decompressor {
+    /// The size of the intermediate buffer blobs are decompressed through.
+    const CHUNK_SIZE: usize = 64 * 1024;
+
+    pub fn new() -> anyhow::Result<Self> {
+        Ok(Self {
+            decoder: zstd::stream::raw::Decoder::new().context("Create zstd decompressor")?,
+            chunk: vec![0; Self::CHUNK_SIZE],
+        })
+    }
+
+    /// Decompresses a blob consisting of a single zstd frame.
+    pub fn decompress(&mut self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        use zstd::stream::raw::{InBuffer, Operation, OutBuffer};
+
+        self.decoder.reinit()?;
+
+        let mut input = InBuffer::around(data);
+        let mut decompressed = Vec::with_capacity(data.len() * 4);
+        loop {
+            let mut output = OutBuffer::around(self.chunk.as_mut_slice());
+            let remaining = self.decoder.run(&mut input, &mut output)?;
+            let written = output.pos();
+            decompressed.extend_from_slice(&self.chunk[..written]);
+
+            // The frame has been decoded and flushed.
+            if remaining == 0 {
+                return Ok(decompressed);
+            }
+
+            if written == 0 && input.pos() == data.len() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "Incomplete zstd frame",
+                ));
+            }
+        }
+    }
 }
 
 impl Storage {
@@ -63,31 +352,49 @@ impl Storage {
     ///
     /// May be cloned safely.
     pub fn migrate(database_path: PathBuf, journal_mode: JournalMode) -> anyhow::Result<Self> {
-        let manager = SqliteConnectionManager::file(&database_path);
-        let pool = Pool::builder().build(manager)?;
+        metrics::register();
 
-        let mut conn = pool.get()?;
+        let writer = connection_pool(&database_path, 1, false, journal_mode)
+            .context("Creating writer connection pool")?;
+
+        let mut conn = writer.get()?;
         match journal_mode {
             JournalMode::Rollback => conn
                 .pragma_update(None, "journal_mode", "DELETE")
                 .context("Disabling WAL journal mode")?,
-            JournalMode::WAL => {
-                conn.pragma_update(None, "journal_mode", "WAL")
-                    .context("Enabling WAL journal mode")?;
-                // set journal size limit to 1 GB
-                conn.pragma_update(
-                    None,
-                    "journal_size_limit",
-                    (1024usize * 1024 * 1024).to_string(),
-                )
-                .context("Set journal size limit")?;
-            }
+            JournalMode::WAL => conn
+                .pragma_update(None, "journal_mode", "WAL")
+                .context("Enabling WAL journal mode")?,
         }
         migrate_database(&mut conn).context("Migrate database")?;
 
+        let head = latest_head(&conn.transaction().context("Create database transaction")?)?;
+        let (head, _) = tokio::sync::watch::channel(head);
+        drop(conn);
+
+        let pool = connection_pool(&database_path, 10, true, journal_mode)
+            .context("Creating connection pool")?;
+
+        let readers = connection_pool(
+            &database_path,
+            Self::DEFAULT_READERS.get(),
+            true,
+            journal_mode,
+        )
+        .context("Creating reader connection pool")?;
+        let auxiliary = connection_pool(&database_path, 1, false, journal_mode)
+            .context("Creating auxiliary writer connection pool")?;
+
         let inner = Inner {
             database_path: Arc::new(database_path),
             pool,
+            readers,
+            writer,
+            auxiliary,
+            journal_mode,
+            compression: CompressionConfig::default(),
+            events: None,
+            head: Arc::new(head),
         };
 
         let storage = Storage(inner);
@@ -95,12 +402,115 @@ impl Storage {
         Ok(storage)
     }
 
-    /// Returns a new Sqlite [Connection] to the database.
+    /// The default number of [reader](Storage::reader) connections.
+    pub const DEFAULT_READERS: std::num::NonZeroU32 = match std::num::NonZeroU32::new(10) {
+        Some(readers) => readers,
+        None => unreachable!(),
+    };
+
+    /// Replaces the [reader](Storage::reader) pool with one of `size` connections, which limits
+    /// the number of queries served concurrently. Defaults to [Storage::DEFAULT_READERS].
+    pub fn with_readers(mut self, size: std::num::NonZeroU32) -> anyhow::Result<Self> {
+        self.0.readers =
+            connection_pool(&self.0.database_path, size.get(), true, self.0.journal_mode)
+                .context("Creating reader connection pool")?;
+        Ok(self)
+    }
+
+    /// Returns a new Sqlite [Connection] to the database, which cannot modify it.
+    ///
+    /// Use [Storage::writer] to write.
     pub fn connection(&self) -> anyhow::Result<PooledConnection> {
         let conn = self.0.pool.get()?;
         Ok(conn)
     }
 
+    /// Returns a pooled connection which cannot modify the database, for serving queries.
+    pub fn reader(&self) -> anyhow::Result<PooledConnection> {
+        let conn = self.0.readers.get()?;
+        Ok(conn)
+    }
+
+    /// Returns the connection dedicated to writing, which is used by the sync process.
+    ///
+    /// There is only one such connection, so this blocks while it is in use.
+    pub fn writer(&self) -> anyhow::Result<PooledConnection> {
+        let conn = self.0.writer.get()?;
+        Ok(conn)
+    }
+
+    /// Returns the only other connection which may write, for small writes which must not wait
+    /// for the sync process to release the [writer](Storage::writer):
+    /// - the archive of raw blocks fetched by the sync process, see [RawBlocksTable], and
+    /// - the cursors of [event filters](EventFiltersTable).
+    ///
+    /// Its transactions compete with the writer's for the database lock, which they wait for
+    /// up to the busy timeout. They should therefore be kept short.
+    pub fn auxiliary_writer(&self) -> anyhow::Result<PooledConnection> {
+        let conn = self.0.auxiliary.get()?;
+        Ok(conn)
+    }
+
+    /// Runs `f` in a transaction on a [reader](Storage::reader) connection.
+    ///
+    /// If `f` fails because the database is locked by another connection, it is retried a few
+    /// times with an increasing delay.
+    pub fn read<T, E>(
+        &self,
+        mut f: impl FnMut(&rusqlite::Transaction<'_>) -> Result<T, E>,
+    ) -> Result<T, E>
+    where
+        E: From<anyhow::Error> + MaybeBusy,
+    {
+        let mut conn = self.reader().context("Opening database connection")?;
+        let mut delay = READ_RETRY_DELAY;
+        let mut retries = 0;
+
+        loop {
+            let result = match conn.transaction().context("Creating database transaction") {
+                Ok(tx) => f(&tx),
+                Err(e) => Err(e.into()),
+            };
+
+            match result {
+                Err(e) if retries < READ_RETRIES && e.is_busy() => {
+                    tracing::debug!(?delay, "Database is busy, retrying read");
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Opens a new connection which cannot modify the database, e.g. for serving queries.
+    ///
+    /// Database files are opened with `SQLITE_OPEN_READ_ONLY`, which also lets Sqlite skip
+    /// acquiring write locks. In-memory databases cannot be opened read-only, so the
+    /// `query_only` pragma is set on their connections instead.
+    pub fn read_only_connection(&self) -> anyhow::Result<Connection> {
+        use rusqlite::OpenFlags;
+
+        let flags = OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        let in_memory = self
+            .path()
+            .to_str()
+            .map_or(false, |path| path.contains("mode=memory"));
+
+        if in_memory {
+            let conn =
+                Connection::open_with_flags(self.path(), flags | OpenFlags::SQLITE_OPEN_READ_WRITE)
+                    .context("Opening in-memory database")?;
+            conn.pragma_update(None, "query_only", true)
+                .context("Enabling query only mode")?;
+            Ok(conn)
+        } else {
+            Connection::open_with_flags(self.path(), flags | OpenFlags::SQLITE_OPEN_READ_ONLY)
+                .context("Opening database in read-only mode")
+        }
+    }
+
     /// Convenience function for tests to create an in-memory database.
     /// Equivalent to [Storage::migrate] with an in-memory backed database.
     // No longer cfg(test) because needed in benchmarks
@@ -126,6 +536,467 @@ impl Storage {
     pub fn path(&self) -> &Path {
         &self.0.database_path
     }
+
+    /// Sets the [CompressionConfig] used when writing to the database.
+    pub fn with_compression(mut self, compression: CompressionConfig) -> Self {
+        self.0.compression = compression;
+        self
+    }
+
+    /// The [CompressionConfig] used when writing to the database.
+    pub fn compression(&self) -> CompressionConfig {
+        self.0.compression
+    }
+
+    /// Enables broadcasting of inserted events, see [EventSender].
+    ///
+    /// `capacity` is the number of events a receiver may fall behind by before it starts
+    /// missing events.
+    pub fn with_event_broadcast(mut self, capacity: usize) -> Self {
+        let (sender, _) = tokio::sync::broadcast::channel(capacity);
+        self.0.events = Some(sender);
+        self
+    }
+
+    /// The [EventSender] writers should pass on when inserting events, if broadcasting
+    /// is enabled.
+    pub fn event_sender(&self) -> Option<&EventSender> {
+        self.0.events.as_ref()
+    }
+
+    /// Binds the database to `chain` if it is not bound yet, see [MetaTable::bind_chain].
+    ///
+    /// This should be called at startup, before any other component uses the database, so that
+    /// a database holding another chain is never served or written to.
+    pub fn bind_chain(&self, chain: Chain) -> anyhow::Result<()> {
+        let mut conn = self.writer().context("Create database connection")?;
+        let tx = conn.transaction().context("Create database transaction")?;
+        MetaTable::bind_chain(&tx, chain)?;
+        tx.commit().context("Commit database transaction")
+    }
+
+    /// The chain the database is bound to, see [Storage::bind_chain].
+    pub fn stored_chain(&self) -> anyhow::Result<Option<Chain>> {
+        let mut conn = self.connection().context("Create database connection")?;
+        let tx = conn.transaction().context("Create database transaction")?;
+        let chain = MetaTable::get_chain(&tx)?;
+        Ok(chain)
+    }
+
+    /// Returns a receiver of the number and hash of the latest canonical block, updated as blocks
+    /// are committed or reorged away.
+    pub fn head_watcher(
+        &self,
+    ) -> tokio::sync::watch::Receiver<Option<(StarknetBlockNumber, StarknetBlockHash)>> {
+        self.0.head.subscribe()
+    }
+
+    /// The [HeadSender] writers should update after committing a new head.
+    pub fn head_sender(&self) -> &HeadSender {
+        &self.0.head
+    }
+
+    /// Copies the blocks `from..=to` from `other` into this database, along with their
+    /// transactions, events and state updates.
+    ///
+    /// Rows which already exist are skipped. The merge is aborted without changes if `other`
+    /// is missing a block in the range, or if a block conflicts with this database: either a
+    /// different block is stored at the same height, or it does not link to its stored
+    /// neighbours.
+    ///
+    /// Contract classes, contract state and L1 data are not copied. The blocks are written
+    /// through the [writer](Storage::writer), so this waits for it to be released.
+    pub fn merge_from(
+        &self,
+        other: &Storage,
+        from: StarknetBlockNumber,
+        to: StarknetBlockNumber,
+    ) -> anyhow::Result<()> {
+        let mut source = other
+            .connection()
+            .context("Opening source database connection")?;
+        let source = source
+            .transaction()
+            .context("Creating source database transaction")?;
+        let mut target = self.writer().context("Opening database connection")?;
+        let target = target
+            .transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)
+            .context("Creating database transaction")?;
+
+        let mut compressor = self.compression().compressor()?;
+        let mut events = Vec::new();
+
+        let mut number = from;
+        while number <= to {
+            let block = StarknetBlocksTable::get(&source, number.into())
+                .context("Reading block from source")?
+                .with_context(|| format!("Block {number} is missing from the source database"))?;
+
+            match StarknetBlocksTable::get_header(&target, number.into())
+                .context("Reading existing block")?
+            {
+                Some(existing) => anyhow::ensure!(
+                    existing.hash == block.hash,
+                    "Block {number} conflicts: {} is stored, but the source has {}",
+                    existing.hash.0,
+                    block.hash.0
+                ),
+                None => {
+                    if number != StarknetBlockNumber::GENESIS {
+                        if let Some(parent) =
+                            StarknetBlocksTable::get_header(&target, (number - 1).into())
+                                .context("Reading parent block")?
+                        {
+                            anyhow::ensure!(
+                                parent.hash == block.parent_hash,
+                                "Block {number} does not extend the stored parent block"
+                            );
+                        }
+                    }
+                    if let Some(child) =
+                        StarknetBlocksTable::get_header(&target, (number + 1).into())
+                            .context("Reading child block")?
+                    {
+                        // Blocks stored before parent hashes were tracked may not know theirs.
+                        anyhow::ensure!(
+                            child.parent_hash == block.hash
+                                || child.parent_hash.0 == StarkHash::ZERO,
+                            "Block {number} is not the parent of the stored child block"
+                        );
+                    }
+
+                    let version = StarknetBlocksTable::get_version(&source, number)
+                        .context("Reading block version from source")?;
+                    StarknetBlocksTable::insert(&target, &block, version.as_deref())
+                        .context("Inserting block")?;
+                }
+            }
+
+            if CanonicalBlocksTable::get(&target, number)
+                .context("Reading canonical block")?
+                .is_none()
+            {
+                CanonicalBlocksTable::insert(&target, number, block.hash)
+                    .context("Inserting canonical block")?;
+            }
+
+            if StarknetTransactionsTable::get_transaction_count(&target, block.hash.into())
+                .context("Counting existing transactions")?
+                .unwrap_or_default()
+                == 0
+            {
+                let transactions = StarknetTransactionsTable::get_transaction_data_for_block(
+                    &source,
+                    block.hash.into(),
+                )
+                .context("Reading transactions from source")?;
+                if self.event_sender().is_some() {
+                    events.extend(StarknetEmittedEvent::from_receipts(
+                        block.hash,
+                        number,
+                        transactions.iter().map(|(_, receipt)| receipt),
+                    ));
+                }
+                StarknetTransactionsTable::upsert_with_compressor(
+                    &target,
+                    &mut compressor,
+                    block.hash,
+                    number,
+                    &transactions,
+                )
+                .context("Inserting transactions")?;
+            }
+
+            if StarknetStateUpdatesTable::get(&target, block.hash)
+                .context("Reading existing state update")?
+                .is_none()
+            {
+                if let Some(state_update) = StarknetStateUpdatesTable::get(&source, block.hash)
+                    .context("Reading state update from source")?
+                {
+                    StarknetStateUpdatesTable::insert_with_compressor(
+                        &target,
+                        &mut compressor,
+                        block.hash,
+                        &state_update,
+                    )
+                    .context("Inserting state update")?;
+                }
+            }
+
+            number += 1;
+        }
+
+        let head = latest_head(&target)?;
+        target.commit().context("Committing merged blocks")?;
+        self.head_sender().send_replace(head);
+        if let Some(sender) = self.event_sender() {
+            StarknetEmittedEvent::broadcast(sender, events);
+        }
+
+        Ok(())
+    }
+
+    /// Checkpoints the WAL file into the database.
+    ///
+    /// Sqlite already checkpoints automatically, but this lets a long-running node bound the
+    /// size of its WAL file.
+    pub fn wal_checkpoint(&self, mode: CheckpointMode) -> anyhow::Result<WalCheckpoint> {
+        let mode = match mode {
+            CheckpointMode::Passive => "PASSIVE",
+            CheckpointMode::Full => "FULL",
+            CheckpointMode::Truncate => "TRUNCATE",
+        };
+
+        let conn = self.connection().context("Create database connection")?;
+        conn.query_row(&format!("PRAGMA wal_checkpoint({mode})"), [], |row| {
+            Ok(WalCheckpoint {
+                busy: row.get::<_, i64>(0)? != 0,
+                log: row.get(1)?,
+                checkpointed: row.get(2)?,
+            })
+        })
+        .context("Checkpointing WAL")
+    }
+
+    /// Runs Sqlite's `PRAGMA integrity_check`, returning the problems it reports. No problems
+    /// means the database is intact.
+    pub fn integrity_check(&self) -> anyhow::Result<Vec<String>> {
+        self.run_check("integrity_check")
+    }
+
+    /// Like [Storage::integrity_check], but skips the costlier checks such as verifying that
+    /// indices match their tables.
+    pub fn quick_check(&self) -> anyhow::Result<Vec<String>> {
+        self.run_check("quick_check")
+    }
+
+    fn run_check(&self, pragma: &str) -> anyhow::Result<Vec<String>> {
+        let conn = self.connection().context("Create database connection")?;
+        let mut stmt = conn
+            .prepare(&format!("PRAGMA {pragma}"))
+            .context("Preparing statement")?;
+        let mut problems = stmt
+            .query_map([], |row| row.get::<_, String>(0))
+            .with_context(|| format!("Running {pragma}"))?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Iterating over rows")?;
+
+        // Sqlite reports a single "ok" row if there are no problems.
+        if problems == ["ok"] {
+            problems.clear();
+        }
+
+        Ok(problems)
+    }
+
+    /// Returns the approximate number of bytes used by each table, largest first, e.g. to decide
+    /// what to prune.
+    ///
+    /// A table's size includes its indices. The full-text index of event keys is reported as the
+    /// virtual table `starknet_events_keys`, which includes its shadow tables. Free pages are not
+    /// attributed to any table.
+    pub fn table_sizes(&self) -> anyhow::Result<Vec<(String, u64)>> {
+        let conn = self.connection().context("Create database connection")?;
+        let mut stmt = conn
+            .prepare(
+                r"SELECT COALESCE(
+        (SELECT virtual.name FROM sqlite_schema AS virtual
+         WHERE virtual.sql LIKE 'CREATE VIRTUAL TABLE%'
+           AND schema.tbl_name LIKE virtual.name || '\_%' ESCAPE '\'),
+        schema.tbl_name,
+        dbstat.name
+    ) AS owner, SUM(dbstat.pgsize) AS size
+    FROM dbstat
+    LEFT JOIN sqlite_schema AS schema ON schema.name = dbstat.name
+    GROUP BY owner
+    ORDER BY size DESC, owner",
+            )
+            .context("Preparing statement")?;
+
+        let sizes = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .context("Executing query")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Iterating over rows")?;
+
+        Ok(sizes)
+    }
+
+    /// Reads the [ChainTips], e.g. for reporting status. Using a single transaction ensures
+    /// that the tips are consistent with each other.
+    pub fn tips(tx: &rusqlite::Transaction<'_>) -> anyhow::Result<ChainTips> {
+        let latest =
+            StarknetBlocksTable::get_latest_hash_and_number(tx).context("Query latest block")?;
+        let l1_l2_head = RefsTable::get_l1_l2_head(tx).context("Query L1-L2 head")?;
+        let highest_with_transactions = StarknetTransactionsTable::get_highest_block_number(tx)
+            .context("Query highest block with transactions")?;
+        let highest_with_state_update = StarknetStateUpdatesTable::get_highest_block_number(tx)
+            .context("Query highest block with a state update")?;
+        let l1_state = L1StateTable::get(tx, L1TableBlockId::Latest)
+            .context("Query latest L1 state")?
+            .map(|update| update.block_number);
+
+        Ok(ChainTips {
+            latest,
+            l1_l2_head,
+            highest_with_transactions,
+            highest_with_state_update,
+            l1_state,
+        })
+    }
+
+    /// Deletes all data of blocks `>= reorg_tail` from every table, and moves the L1-L2 head
+    /// down to the new head if it was past it.
+    ///
+    /// Unlike [Storage::reorg_l2], this also deletes the [L1 state](L1StateTable) of these
+    /// blocks, i.e. it rewinds the whole database to before `reorg_tail`.
+    pub fn reorg(
+        tx: &rusqlite::Transaction<'_>,
+        reorg_tail: StarknetBlockNumber,
+    ) -> anyhow::Result<()> {
+        Self::reorg_l2(tx, reorg_tail)?;
+        L1StateTable::reorg(tx, reorg_tail).context("Delete L1 state")?;
+
+        Ok(())
+    }
+
+    /// Deletes all L2 data of blocks `>= reorg_tail`, and moves the L1-L2 head down to the new
+    /// head if it was past it.
+    ///
+    /// This is what sync does when the sequencer's chain is reorganized. The [L1
+    /// state](L1StateTable) is kept, as it is only reorganized by L1 reorgs.
+    pub fn reorg_l2(
+        tx: &rusqlite::Transaction<'_>,
+        reorg_tail: StarknetBlockNumber,
+    ) -> anyhow::Result<()> {
+        StarknetEventsTable::reorg(tx, reorg_tail).context("Delete events")?;
+        CanonicalBlocksTable::reorg(tx, reorg_tail).context("Delete canonical blocks")?;
+        ContractClassIndexTable::reorg(tx, reorg_tail).context("Delete contract classes")?;
+        ContractSummariesTable::reorg(tx, reorg_tail).context("Delete contract summaries")?;
+        StorageUpdatesTable::reorg(tx, reorg_tail).context("Delete storage updates")?;
+        // L1 handler origins are found by their transaction's hash, transactions and state
+        // updates by their block's hash, so they have to be deleted in this order.
+        L1HandlerOriginsTable::reorg(tx, reorg_tail).context("Delete L1 handler origins")?;
+        StarknetTransactionsTable::reorg(tx, reorg_tail).context("Delete transactions")?;
+        StarknetStateUpdatesTable::reorg(tx, reorg_tail).context("Delete state updates")?;
+        StarknetBlocksTable::reorg(tx, reorg_tail).context("Delete blocks")?;
+        RawBlocksTable::reorg(tx, reorg_tail).context("Delete raw blocks")?;
+        StagedBlocksTable::reorg(tx, reorg_tail).context("Delete staged blocks")?;
+        EventFiltersTable::reorg(tx, reorg_tail).context("Rewind event filters")?;
+
+        match RefsTable::get_l1_l2_head(tx).context("Query L1-L2 head")? {
+            Some(head) if head >= reorg_tail => {
+                let new_head = match reorg_tail {
+                    StarknetBlockNumber::GENESIS => None,
+                    other => Some(other - 1),
+                };
+                RefsTable::set_l1_l2_head(tx, new_head).context("Update L1-L2 head")?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Subscribes to events inserted from now on. Returns [None] if broadcasting is not enabled.
+    pub fn subscribe_events(
+        &self,
+    ) -> Option<tokio::sync::broadcast::Receiver<StarknetEmittedEvent>> {
+        self.0.events.as_ref().map(EventSender::subscribe)
+    }
+}
+
+/// Reads block `number` from storage in the sequencer's format. The status is derived from the
+/// L1-L2 head.
+fn read_sequencer_block(
+    tx: &rusqlite::Transaction<'_>,
+    number: StarknetBlockNumber,
+    l1_l2_head: Option<StarknetBlockNumber>,
+) -> anyhow::Result<crate::sequencer::reply::Block> {
+    use crate::sequencer::reply::{Block, Status};
+
+    let block = StarknetBlocksTable::get(tx, number.into())
+        .context("Reading block")?
+        .with_context(|| format!("Block {number} is missing"))?;
+    let version = StarknetBlocksTable::get_version(tx, number).context("Reading block version")?;
+    let (transactions, transaction_receipts) =
+        StarknetTransactionsTable::get_transaction_data_for_block(tx, block.hash.into())
+            .context("Reading transactions")?
+            .into_iter()
+            .unzip();
+
+    let status = match l1_l2_head {
+        Some(head) if head >= number => Status::AcceptedOnL1,
+        _ => Status::AcceptedOnL2,
+    };
+
+    Ok(Block {
+        block_hash: block.hash,
+        block_number: block.number,
+        gas_price: Some(block.gas_price),
+        parent_block_hash: block.parent_hash,
+        sequencer_address: block.sequencer_address,
+        state_root: block.root,
+        status,
+        timestamp: block.timestamp,
+        transaction_receipts,
+        transactions,
+        starknet_version: version,
+    })
+}
+
+/// Writes `value` as a big endian `u32` length followed by a zstd frame of that length,
+/// containing the value as JSON.
+fn write_frame(
+    writer: &mut impl std::io::Write,
+    compressor: &mut zstd::bulk::Compressor<'_>,
+    value: &impl serde::Serialize,
+) -> anyhow::Result<()> {
+    let json = serde_json::to_vec(value).context("Serializing frame")?;
+    let frame = compressor.compress(&json).context("Compressing frame")?;
+    let length = u32::try_from(frame.len()).context("Frame is too large")?;
+
+    writer
+        .write_all(&length.to_be_bytes())
+        .and_then(|_| writer.write_all(&frame))
+        .context("Writing frame")
+}
+
+/// Reads the next frame written by [write_frame], or [None] at the end of the stream.
+fn read_frame<T: serde::de::DeserializeOwned>(
+    reader: &mut impl std::io::Read,
+) -> anyhow::Result<Option<T>> {
+    let length = match read_frame_length(reader)? {
+        Some(length) => length,
+        None => return Ok(None),
+    };
+
+    let mut frame = vec![0; length as usize];
+    reader.read_exact(&mut frame).context("Reading frame")?;
+
+    let json = zstd::decode_all(frame.as_slice()).context("Decompressing frame")?;
+    let value = serde_json::from_slice(&json).context("Deserializing frame")?;
+
+    Ok(Some(value))
+}
+
+/// Reads the length prefix of the next [write_frame] frame, or [None] at the end of
+/// the stream.
+fn read_frame_length(reader: &mut impl std::io::Read) -> anyhow::Result<Option<u32>> {
+    let mut length = [0u8; 4];
+    let mut read = 0;
+    while read < length.len() {
+        match reader.read(&mut length[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => anyhow::bail!("Export ends within a frame length"),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e).context("Reading frame length"),
+        }
+    }
+
+    Ok(Some(u32::from_be_bytes(length)))
 }
 
 /// Migrates the database to the latest version. This __MUST__ be called
@@ -169,6 +1040,7 @@ fn migrate_database(connection: &mut Connection) -> anyhow::Result<()> {
 
     Ok(())
 }
+
 /// Returns the current schema version of the existing database,
 /// or `0` if database does not yet exist.
 fn schema_version(connection: &Connection) -> anyhow::Result<usize> {
@@ -182,6 +1054,31 @@ fn schema_version(connection: &Connection) -> anyhow::Result<usize> {
     Ok(version)
 }
 
+/// Opens the database at `database_path` without modifying it, e.g. for offline tooling which
+/// may run next to a node using the same database.
+///
+/// Unlike [Storage::migrate], this never migrates the database or changes its journal mode, so
+/// it fails if the database's schema is not the one this application expects.
+pub fn open_read_only(database_path: &std::path::Path) -> anyhow::Result<Connection> {
+    use rusqlite::OpenFlags;
+
+    let connection = Connection::open_with_flags(
+        database_path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
+    .context("Opening database in read-only mode")?;
+
+    let version = schema_version(&connection)?;
+    let expected = schema::migrations().len();
+    anyhow::ensure!(
+        version == expected,
+        "Database schema version {version} does not match this application's {expected}, \
+        run the node to migrate it first"
+    );
+
+    Ok(connection)
+}
+
 /// Enables foreign key support for the database.
 fn enable_foreign_keys(connection: &Connection) -> anyhow::Result<()> {
     use rusqlite::config::DbConfig::SQLITE_DBCONFIG_ENABLE_FKEY;
@@ -232,10 +1129,18 @@ pub(crate) mod test_utils {
             .map(|i| StarknetBlock {
                 number: StarknetBlockNumber::GENESIS + i as u64,
                 hash: StarknetBlockHash(StarkHash::from_hex_str(&"a".repeat(i + 3)).unwrap()),
+                parent_hash: match i {
+                    0 => StarknetBlockHash(StarkHash::ZERO),
+                    i => StarknetBlockHash(StarkHash::from_hex_str(&"a".repeat(i + 2)).unwrap()),
+                },
                 root: GlobalRoot(StarkHash::from_hex_str(&"f".repeat(i + 3)).unwrap()),
                 timestamp: StarknetBlockTimestamp::new_or_panic(i as u64 + 500),
                 gas_price: GasPrice::from(i as u64),
-                sequencer_address: SequencerAddress(StarkHash::from_be_slice(&[i as u8]).unwrap()),
+                // The genesis block has no sequencer address, as for early blocks.
+                sequencer_address: (i > 0)
+                    .then(|| SequencerAddress(StarkHash::from_be_slice(&[i as u8]).unwrap())),
+                transaction_count: TRANSACTIONS_PER_BLOCK,
+                event_count: EVENTS_PER_BLOCK,
             })
             .collect::<Vec<_>>()
             .try_into()
@@ -336,6 +1241,7 @@ pub(crate) mod test_utils {
                     n_steps: i as u64 + 987,
                     n_memory_holes: i as u64 + 1177,
                 }),
+                execution_status: transaction::ExecutionStatus::Succeeded,
                 l1_to_l2_consumed_message: None,
                 l2_to_l1_messages: Vec::new(),
                 transaction_hash: tx.hash(),
@@ -378,10 +1284,18 @@ pub(crate) mod test_utils {
 
     /// Creates a storage instance in memory with a set of expected emitted events
     pub(crate) fn setup_test_storage() -> (Storage, Vec<StarknetEmittedEvent>) {
+        let storage = Storage::in_memory().unwrap();
+        let events = insert_test_data(&storage);
+
+        (storage, events)
+    }
+
+    /// Inserts the test blocks, transactions and receipts into the storage, and returns the
+    /// expected emitted events.
+    pub(crate) fn insert_test_data(storage: &Storage) -> Vec<StarknetEmittedEvent> {
         use crate::storage::CanonicalBlocksTable;
 
-        let storage = Storage::in_memory().unwrap();
-        let mut connection = storage.connection().unwrap();
+        let mut connection = storage.writer().unwrap();
         let tx = connection.transaction().unwrap();
 
         let blocks = create_blocks();
@@ -402,9 +1316,7 @@ pub(crate) mod test_utils {
 
         tx.commit().unwrap();
 
-        let events = extract_events(&blocks, &transactions_and_receipts);
-
-        (storage, events)
+        extract_events(&blocks, &transactions_and_receipts)
     }
 }
 
@@ -412,6 +1324,43 @@ pub(crate) mod test_utils {
 mod tests {
     use super::*;
 
+    #[test]
+    fn decompressor_is_reusable() {
+        let mut decompressor = Decompressor::new().unwrap();
+
+        // Larger than the decompressor's intermediate buffer.
+        let large = (0..3 * Decompressor::CHUNK_SIZE)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        for blob in [b"small".to_vec(), large, Vec::new(), b"small".to_vec()] {
+            let compressed = zstd::encode_all(blob.as_slice(), 10).unwrap();
+            assert_eq!(decompressor.decompress(&compressed).unwrap(), blob);
+        }
+
+        let compressed = zstd::encode_all(b"truncated".as_slice(), 10).unwrap();
+        decompressor
+            .decompress(&compressed[..compressed.len() - 1])
+            .unwrap_err();
+        // A failure must not leak into the next blob.
+        assert_eq!(decompressor.decompress(&compressed).unwrap(), b"truncated");
+    }
+
+    #[test]
+    fn long_window_blobs_decompress_with_default_settings() {
+        let compression = CompressionConfig {
+            long_window: true,
+            ..Default::default()
+        };
+        let blob = (0..3 * Decompressor::CHUNK_SIZE)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        let compressed = compression.compressor().unwrap().compress(&blob).unwrap();
+
+        assert_eq!(zstd::decode_all(compressed.as_slice()).unwrap(), blob);
+        let mut decompressor = Decompressor::new().unwrap();
+        assert_eq!(decompressor.decompress(&compressed).unwrap(), blob);
+    }
+
     #[test]
     fn schema_version_defaults_to_zero() {
         let mut conn = rusqlite::Connection::open_in_memory().unwrap();
@@ -421,6 +1370,29 @@ mod tests {
         assert_eq!(version, 0);
     }
 
+    #[test]
+    fn open_read_only_checks_schema_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("read_only.sqlite");
+
+        {
+            let mut conn = rusqlite::Connection::open(&path).unwrap();
+            migrate_database(&mut conn).unwrap();
+        }
+        let connection = open_read_only(&path).unwrap();
+        connection
+            .execute("DELETE FROM starknet_blocks", [])
+            .unwrap_err();
+        drop(connection);
+
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .pragma_update(None, VERSION_KEY, 1)
+            .unwrap();
+        let error = open_read_only(&path).unwrap_err();
+        assert!(error.to_string().contains("does not match"), "{error}");
+    }
+
     #[test]
     fn full_migration() {
         let mut conn = rusqlite::Connection::open_in_memory().unwrap();
@@ -480,4 +1452,867 @@ mod tests {
         conn.execute("INSERT INTO child (id, parent_id) VALUES (1, 1)", [])
             .unwrap_err();
     }
+
+    #[test]
+    fn wal_checkpoint() {
+        let db_dir = tempfile::TempDir::new().unwrap();
+        let storage = Storage::migrate(db_dir.path().join("wal.sqlite"), JournalMode::WAL).unwrap();
+
+        let mut connection = storage.writer().unwrap();
+        let tx = connection.transaction().unwrap();
+        for block in test_utils::create_blocks() {
+            StarknetBlocksTable::insert(&tx, &block, None).unwrap();
+        }
+        tx.commit().unwrap();
+        drop(connection);
+
+        let result = storage.wal_checkpoint(CheckpointMode::Full).unwrap();
+        assert!(!result.busy);
+        assert!(result.checkpointed > 0);
+        assert_eq!(result.checkpointed, result.log);
+
+        // Truncation resets the WAL, so Sqlite reports zero frames.
+        let result = storage.wal_checkpoint(CheckpointMode::Truncate).unwrap();
+        assert_eq!(
+            result,
+            WalCheckpoint {
+                busy: false,
+                log: 0,
+                checkpointed: 0
+            }
+        );
+        let wal_size = std::fs::metadata(db_dir.path().join("wal.sqlite-wal"))
+            .unwrap()
+            .len();
+        assert_eq!(wal_size, 0);
+    }
+
+    #[test]
+    fn head_watcher_starts_at_stored_head() {
+        let db_dir = tempfile::TempDir::new().unwrap();
+        let path = db_dir.path().join("head.sqlite");
+        let storage = Storage::migrate(path.clone(), JournalMode::WAL).unwrap();
+        assert_eq!(*storage.head_watcher().borrow(), None);
+
+        let mut connection = storage.writer().unwrap();
+        let tx = connection.transaction().unwrap();
+        let blocks = test_utils::create_blocks();
+        for block in &blocks {
+            StarknetBlocksTable::insert(&tx, block, None).unwrap();
+        }
+        tx.commit().unwrap();
+        drop(connection);
+
+        let storage = Storage::migrate(path, JournalMode::WAL).unwrap();
+        let latest = blocks.last().unwrap();
+        assert_eq!(
+            *storage.head_watcher().borrow(),
+            Some((latest.number, latest.hash))
+        );
+    }
+
+    mod bind_chain {
+        use super::*;
+
+        #[test]
+        fn fresh_database() {
+            let storage = Storage::in_memory().unwrap();
+            assert_eq!(storage.stored_chain().unwrap(), None);
+
+            storage.bind_chain(Chain::Testnet).unwrap();
+            assert_eq!(storage.stored_chain().unwrap(), Some(Chain::Testnet));
+
+            // Binding again to the same chain is a no-op.
+            storage.bind_chain(Chain::Testnet).unwrap();
+            assert_eq!(storage.stored_chain().unwrap(), Some(Chain::Testnet));
+        }
+
+        #[test]
+        fn mismatch() {
+            let storage = Storage::in_memory().unwrap();
+            storage.bind_chain(Chain::Mainnet).unwrap();
+
+            let error = storage.bind_chain(Chain::Testnet).unwrap_err();
+            assert_eq!(
+                error.downcast_ref::<ChainMismatch>(),
+                Some(&ChainMismatch {
+                    stored: Chain::Mainnet,
+                    configured: Chain::Testnet,
+                })
+            );
+            assert_eq!(storage.stored_chain().unwrap(), Some(Chain::Mainnet));
+        }
+
+        #[test]
+        fn genesis_of_other_chain() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+            let mut genesis = test_utils::create_blocks()[0].clone();
+            genesis.hash = crate::consts::MAINNET_GENESIS_HASH;
+            StarknetBlocksTable::insert(&tx, &genesis, None).unwrap();
+            tx.commit().unwrap();
+            drop(connection);
+
+            let error = storage.bind_chain(Chain::Testnet).unwrap_err();
+            assert!(error.downcast_ref::<ChainMismatch>().is_some(), "{error:?}");
+            assert_eq!(storage.stored_chain().unwrap(), None);
+        }
+
+        #[test]
+        fn round_trip() {
+            for chain in [Chain::Mainnet, Chain::Testnet, Chain::Integration] {
+                let storage = Storage::in_memory().unwrap();
+                storage.bind_chain(chain).unwrap();
+                assert_eq!(storage.stored_chain().unwrap(), Some(chain));
+            }
+        }
+    }
+
+    #[test]
+    fn integrity_check() {
+        let storage = Storage::in_memory().unwrap();
+        assert_eq!(storage.integrity_check().unwrap(), Vec::<String>::new());
+        assert_eq!(storage.quick_check().unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn table_sizes() {
+        let db_dir = tempfile::TempDir::new().unwrap();
+        let path = db_dir.path().join("sizes.sqlite");
+        let storage = Storage::migrate(path.clone(), JournalMode::Rollback).unwrap();
+        test_utils::insert_test_data(&storage);
+
+        let sizes = storage.table_sizes().unwrap();
+        for table in [
+            "starknet_blocks",
+            "starknet_transactions",
+            "starknet_events",
+            "starknet_events_keys",
+        ] {
+            assert!(
+                sizes.iter().any(|(name, size)| name == table && *size > 0),
+                "{table} missing from {sizes:?}"
+            );
+        }
+        // Shadow tables of the full-text index are part of it.
+        assert!(
+            !sizes
+                .iter()
+                .any(|(name, _)| name.starts_with("starknet_events_keys_")),
+            "{sizes:?}"
+        );
+
+        let connection = storage.connection().unwrap();
+        let free_pages: u64 = connection
+            .pragma_query_value(None, "freelist_count", |row| row.get(0))
+            .unwrap();
+        let page_size: u64 = connection
+            .pragma_query_value(None, "page_size", |row| row.get(0))
+            .unwrap();
+        let file_size = std::fs::metadata(&path).unwrap().len();
+        let total = sizes.iter().map(|(_, size)| size).sum::<u64>();
+        assert_eq!(total + free_pages * page_size, file_size);
+    }
+
+    #[test]
+    fn tips() {
+        use crate::core::{
+            EthereumBlockHash, EthereumBlockNumber, EthereumLogIndex, EthereumTransactionHash,
+            EthereumTransactionIndex, GlobalRoot,
+        };
+        use crate::ethereum::{log::StateUpdateLog, BlockOrigin, EthOrigin, TransactionOrigin};
+        use crate::rpc::v01::types::reply::StateUpdate;
+        use web3::types::H256;
+
+        let (storage, _) = test_utils::setup_test_storage();
+        let mut connection = storage.writer().unwrap();
+        let tx = connection.transaction().unwrap();
+
+        let empty = ChainTips {
+            latest: None,
+            l1_l2_head: None,
+            highest_with_transactions: None,
+            highest_with_state_update: None,
+            l1_state: None,
+        };
+
+        // Each tip is at a different block, so that they can't be mixed up.
+        let blocks = test_utils::create_blocks();
+        let mut state_update = StateUpdate::with_block_hash(0);
+        state_update.block_hash = Some(blocks[1].hash);
+        StarknetStateUpdatesTable::insert(&tx, blocks[1].hash, &state_update).unwrap();
+        RefsTable::set_l1_l2_head(&tx, Some(blocks[2].number)).unwrap();
+        L1StateTable::upsert(
+            &tx,
+            &StateUpdateLog {
+                origin: EthOrigin {
+                    block: BlockOrigin {
+                        hash: EthereumBlockHash(H256::from_low_u64_le(33)),
+                        number: EthereumBlockNumber(12_000),
+                    },
+                    transaction: TransactionOrigin {
+                        hash: EthereumTransactionHash(H256::from_low_u64_le(999)),
+                        index: EthereumTransactionIndex(20_000),
+                    },
+                    log_index: EthereumLogIndex(500),
+                },
+                global_root: GlobalRoot(blocks[0].root.0),
+                block_number: blocks[0].number,
+            },
+        )
+        .unwrap();
+        tx.execute(
+            "DELETE FROM starknet_transactions WHERE block_hash = ?",
+            [blocks[3].hash],
+        )
+        .unwrap();
+
+        let tips = Storage::tips(&tx).unwrap();
+        assert_eq!(
+            tips,
+            ChainTips {
+                latest: StarknetBlocksTable::get_latest_hash_and_number(&tx).unwrap(),
+                l1_l2_head: RefsTable::get_l1_l2_head(&tx).unwrap(),
+                highest_with_transactions: StarknetTransactionsTable::get_highest_block_number(&tx)
+                    .unwrap(),
+                highest_with_state_update: StarknetStateUpdatesTable::get_highest_block_number(&tx)
+                    .unwrap(),
+                l1_state: L1StateTable::get(&tx, L1TableBlockId::Latest)
+                    .unwrap()
+                    .map(|update| update.block_number),
+            }
+        );
+        assert_eq!(
+            tips,
+            ChainTips {
+                latest: Some((blocks[3].hash, blocks[3].number)),
+                l1_l2_head: Some(blocks[2].number),
+                highest_with_transactions: Some(blocks[2].number),
+                highest_with_state_update: Some(blocks[1].number),
+                l1_state: Some(blocks[0].number),
+            }
+        );
+
+        let storage = Storage::in_memory().unwrap();
+        let mut connection = storage.connection().unwrap();
+        let tx = connection.transaction().unwrap();
+        assert_eq!(Storage::tips(&tx).unwrap(), empty);
+    }
+
+    #[test]
+    fn reorg() {
+        use crate::core::{
+            ClassHash, ContractAddress, L1ToL2MessageNonce, StorageAddress, StorageValue,
+        };
+        use crate::core::{
+            EthereumBlockHash, EthereumBlockNumber, EthereumLogIndex, EthereumTransactionHash,
+            EthereumTransactionIndex, GlobalRoot,
+        };
+        use crate::ethereum::{log::StateUpdateLog, BlockOrigin, EthOrigin, TransactionOrigin};
+        use crate::rpc::v01::types::reply::state_update::{DeployedContract, StorageDiff};
+        use crate::rpc::v01::types::reply::StateUpdate;
+        use crate::sequencer::reply::state_update::StateDiff;
+        use web3::types::H256;
+
+        let (storage, _) = test_utils::setup_test_storage();
+        let mut connection = storage.writer().unwrap();
+        let tx = connection.transaction().unwrap();
+
+        let blocks = test_utils::create_blocks();
+        for (i, block) in blocks.iter().enumerate() {
+            let mut state_update = StateUpdate::with_block_hash(i as u8);
+            state_update.block_hash = Some(block.hash);
+            StarknetStateUpdatesTable::insert(&tx, block.hash, &state_update).unwrap();
+            L1StateTable::upsert(
+                &tx,
+                &StateUpdateLog {
+                    origin: EthOrigin {
+                        block: BlockOrigin {
+                            hash: EthereumBlockHash(H256::from_low_u64_le(i as u64)),
+                            number: EthereumBlockNumber(i as u64),
+                        },
+                        transaction: TransactionOrigin {
+                            hash: EthereumTransactionHash(H256::from_low_u64_le(i as u64)),
+                            index: EthereumTransactionIndex(0),
+                        },
+                        log_index: EthereumLogIndex(0),
+                    },
+                    global_root: GlobalRoot(block.root.0),
+                    block_number: block.number,
+                },
+            )
+            .unwrap();
+        }
+        RefsTable::set_l1_l2_head(&tx, Some(blocks[3].number)).unwrap();
+
+        // Fill every other table which refers to these blocks.
+        let mut compressor = storage.compression().compressor().unwrap();
+        for (i, block) in blocks.iter().enumerate() {
+            let n = i as u64;
+            let contract = DeployedContract {
+                address: ContractAddress::new_or_panic(StarkHash::from_u64(0xa0 + n)),
+                class_hash: ClassHash(StarkHash::from_u64(0xc0 + n)),
+            };
+            ContractClassIndexTable::insert(
+                &tx,
+                block.number,
+                contract.address,
+                contract.class_hash,
+            )
+            .unwrap();
+            ContractSummariesTable::apply(&tx, block.number, std::slice::from_ref(&contract), &[])
+                .unwrap();
+            StorageUpdatesTable::insert(
+                &tx,
+                block.number,
+                &[StorageDiff {
+                    address: contract.address,
+                    key: StorageAddress::new_or_panic(StarkHash::from_u64(0x10)),
+                    value: StorageValue(StarkHash::from_u64(n)),
+                }],
+            )
+            .unwrap();
+            RawBlocksTable::insert(&tx, block.number, block.hash, b"{}", &mut compressor).unwrap();
+
+            let transactions =
+                StarknetTransactionsTable::get_transaction_data_for_block(&tx, block.hash.into())
+                    .unwrap();
+            L1HandlerOriginsTable::insert(
+                &tx,
+                transactions[0].0.hash(),
+                L1ToL2MessageNonce(StarkHash::from_u64(n)),
+            )
+            .unwrap();
+        }
+
+        // A block staged above the head.
+        let mut staged = read_sequencer_block(&tx, blocks[3].number, None).unwrap();
+        staged.block_number = blocks[3].number + 2;
+        let staged_state_update = crate::sequencer::reply::StateUpdate {
+            block_hash: Some(staged.block_hash),
+            new_root: staged.state_root,
+            old_root: staged.state_root,
+            state_diff: StateDiff {
+                storage_diffs: Default::default(),
+                deployed_contracts: vec![],
+                declared_contracts: vec![],
+                nonces: Default::default(),
+            },
+        };
+        StagedBlocksTable::insert(
+            &tx,
+            &mut CompressionConfig::default().compressor().unwrap(),
+            &staged,
+            &staged_state_update,
+        )
+        .unwrap();
+
+        // A filter which delivered the events of every block.
+        let filter = StarknetEventFilter {
+            from_block: None,
+            to_block: None,
+            contract_address: None,
+            keys: vec![],
+            data_filter: vec![],
+            page_size: 1024,
+            page_number: 0,
+            detect_last_page: false,
+            count_pages: false,
+            only_accepted: false,
+        };
+        let filter =
+            EventFiltersTable::register(&tx, &filter, EventFiltersTable::DEFAULT_MAX_FILTERS)
+                .unwrap();
+        EventFiltersTable::poll(&tx, filter).unwrap().unwrap();
+
+        let tail = blocks[2].number;
+        let count = |table: &str, condition: &str| -> usize {
+            tx.query_row(
+                &format!("SELECT COUNT(1) FROM {table} WHERE {condition}"),
+                [],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        let number = |column: &str| format!("{column} >= {}", tail.get());
+        // Transactions and state updates reference blocks by hash.
+        let hashes = blocks[2..]
+            .iter()
+            .map(|block| format!("x'{}'", hex::encode(block.hash.0.as_be_bytes())))
+            .collect::<Vec<_>>()
+            .join(",");
+        let hashes = format!("block_hash IN ({hashes})");
+
+        let transactions = format!(
+            "starknet_transaction_hash IN (SELECT hash FROM starknet_transactions WHERE {hashes})"
+        );
+
+        let populated = [
+            ("starknet_blocks", number("number")),
+            ("canonical_blocks", number("number")),
+            ("starknet_events", number("block_number")),
+            ("l1_state", number("starknet_block_number")),
+            ("contract_class_index", number("block_number")),
+            ("contract_summaries", number("block_number")),
+            ("storage_updates", number("block_number")),
+            ("raw_blocks", number("number")),
+            ("staged_blocks", number("number")),
+            ("event_filters", number("block_number")),
+            ("l1_handler_origins", transactions),
+            ("starknet_transactions", hashes.clone()),
+            ("starknet_state_updates", hashes),
+        ];
+        for (table, condition) in &populated {
+            assert_ne!(count(table, condition), 0, "{table} before the reorg");
+        }
+
+        Storage::reorg(&tx, tail).unwrap();
+
+        for (table, condition) in &populated {
+            assert_eq!(count(table, condition), 0, "{table} after the reorg");
+        }
+        for table in ["l1_handler_origins", "raw_blocks", "contract_summaries"] {
+            assert_ne!(count(table, "1"), 0, "{table} of earlier blocks");
+        }
+
+        // Earlier blocks are untouched.
+        assert_eq!(
+            StarknetBlocksTable::get_latest_hash_and_number(&tx).unwrap(),
+            Some((blocks[1].hash, blocks[1].number))
+        );
+        assert_eq!(
+            StarknetTransactionsTable::get_highest_block_number(&tx).unwrap(),
+            Some(blocks[1].number)
+        );
+        assert_eq!(
+            StarknetStateUpdatesTable::get_highest_block_number(&tx).unwrap(),
+            Some(blocks[1].number)
+        );
+        assert_eq!(
+            RefsTable::get_l1_l2_head(&tx).unwrap(),
+            Some(blocks[1].number)
+        );
+    }
+
+    #[test]
+    fn concurrent_reads_while_writing() {
+        use crate::core::{EventKey, StarknetTransactionHash};
+        use crate::sequencer::reply::transaction;
+
+        const READERS: usize = 4;
+        const READS: usize = 50;
+        const WRITES: u8 = 100;
+
+        let db_dir = tempfile::TempDir::new().unwrap();
+        let storage =
+            Storage::migrate(db_dir.path().join("stress.sqlite"), JournalMode::WAL).unwrap();
+        let expected = test_utils::insert_test_data(&storage);
+
+        let writer = {
+            let storage = storage.clone();
+            std::thread::spawn(move || {
+                // Blocks with a transaction emitting an event. These events are ordered after the
+                // expected ones, so the readers' first page doesn't change.
+                let (template, template_receipt) =
+                    test_utils::create_transactions_and_receipts()[0].clone();
+                for n in test_utils::NUM_BLOCKS as u8..WRITES {
+                    let mut connection = storage.writer().unwrap();
+                    let tx = connection.transaction().unwrap();
+
+                    let block = StarknetBlock::nth(n);
+                    StarknetBlocksTable::insert(&tx, &block, None).unwrap();
+                    CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
+
+                    let hash = StarknetTransactionHash(StarkHash::from_u64(n as u64));
+                    let transaction = match template.clone() {
+                        transaction::Transaction::Invoke(transaction::InvokeTransaction::V0(
+                            invoke,
+                        )) => transaction::Transaction::Invoke(transaction::InvokeTransaction::V0(
+                            transaction::InvokeTransactionV0 {
+                                transaction_hash: hash,
+                                ..invoke
+                            },
+                        )),
+                        _ => unreachable!("The first test transaction is an invoke"),
+                    };
+                    let receipt = transaction::Receipt {
+                        transaction_hash: hash,
+                        events: vec![transaction::Event {
+                            from_address: crate::core::ContractAddress::new_or_panic(
+                                StarkHash::from_u64(n as u64),
+                            ),
+                            data: vec![],
+                            keys: vec![EventKey(StarkHash::from_u64(n as u64))],
+                        }],
+                        ..template_receipt.clone()
+                    };
+                    StarknetTransactionsTable::upsert(
+                        &tx,
+                        block.hash,
+                        block.number,
+                        &[(transaction, receipt)],
+                    )
+                    .unwrap();
+
+                    tx.commit().unwrap();
+                }
+            })
+        };
+
+        let readers = (0..READERS)
+            .map(|_| {
+                let storage = storage.clone();
+                let expected = expected.clone();
+                std::thread::spawn(move || {
+                    let filter = StarknetEventFilter {
+                        from_block: None,
+                        to_block: None,
+                        contract_address: None,
+                        keys: vec![],
+                        data_filter: vec![],
+                        page_size: expected.len(),
+                        page_number: 0,
+                        detect_last_page: true,
+                        count_pages: false,
+                        only_accepted: false,
+                    };
+
+                    for _ in 0..READS {
+                        let page = storage
+                            .read(|tx| StarknetEventsTable::get_events(tx, &filter))
+                            .unwrap();
+                        assert_eq!(page.events, expected);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        let mut connection = storage.reader().unwrap();
+        let tx = connection.transaction().unwrap();
+        assert_eq!(
+            StarknetBlocksTable::get_latest_number(&tx).unwrap(),
+            Some(StarknetBlockNumber::new_or_panic(WRITES as u64 - 1))
+        );
+    }
+
+    #[test]
+    fn read_retries_busy_storage_errors() {
+        let storage = Storage::in_memory().unwrap();
+
+        let mut attempts = 0;
+        let result = storage.read(|_| {
+            attempts += 1;
+            match attempts {
+                1 => Err(StorageError::Busy).context("Reading"),
+                _ => Ok(attempts),
+            }
+        });
+
+        assert_eq!(result.unwrap(), 2);
+    }
+
+    #[test]
+    fn readers_are_used_concurrently() {
+        const READERS: u32 = 6;
+
+        let db_dir = tempfile::TempDir::new().unwrap();
+        let storage = Storage::migrate(db_dir.path().join("readers.sqlite"), JournalMode::WAL)
+            .unwrap()
+            .with_readers(std::num::NonZeroU32::new(READERS).unwrap())
+            .unwrap();
+        let expected = test_utils::insert_test_data(&storage);
+
+        // Every thread holds its connection until all of them have one, which only succeeds
+        // if the pool hands out all of its connections at once.
+        let barrier = Arc::new(std::sync::Barrier::new(READERS as usize));
+        let readers = (0..READERS)
+            .map(|_| {
+                let storage = storage.clone();
+                let expected = expected.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    let mut connection = storage.reader().unwrap();
+                    barrier.wait();
+
+                    let tx = connection.transaction().unwrap();
+                    let filter = StarknetEventFilter {
+                        from_block: None,
+                        to_block: None,
+                        contract_address: None,
+                        keys: vec![],
+                        data_filter: vec![],
+                        page_size: expected.len(),
+                        page_number: 0,
+                        detect_last_page: true,
+                        count_pages: false,
+                        only_accepted: false,
+                    };
+                    let page = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+                    assert_eq!(page.events, expected);
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn pooled_connections_share_pragmas() {
+        let db_dir = tempfile::TempDir::new().unwrap();
+        let storage =
+            Storage::migrate(db_dir.path().join("pragmas.sqlite"), JournalMode::WAL).unwrap();
+
+        for connection in [
+            storage.connection().unwrap(),
+            storage.reader().unwrap(),
+            storage.writer().unwrap(),
+            storage.auxiliary_writer().unwrap(),
+        ] {
+            let pragma = |name: &str| -> i64 {
+                connection
+                    .pragma_query_value(None, name, |row| row.get(0))
+                    .unwrap()
+            };
+
+            assert_eq!(pragma("foreign_keys"), 1);
+            assert_eq!(pragma("busy_timeout"), BUSY_TIMEOUT.as_millis() as i64);
+            assert_eq!(pragma("journal_size_limit"), JOURNAL_SIZE_LIMIT as i64);
+        }
+
+        let query_only = |connection: PooledConnection| -> i64 {
+            connection
+                .pragma_query_value(None, "query_only", |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(query_only(storage.connection().unwrap()), 1);
+        assert_eq!(query_only(storage.reader().unwrap()), 1);
+        assert_eq!(query_only(storage.writer().unwrap()), 0);
+        assert_eq!(query_only(storage.auxiliary_writer().unwrap()), 0);
+    }
+
+    #[test]
+    fn only_writers_write() {
+        let storage = Storage::in_memory().unwrap();
+        let insert = |connection: &Connection| {
+            connection.execute(
+                "INSERT INTO starknet_versions (version) VALUES ('0.10.1')",
+                [],
+            )
+        };
+
+        insert(&storage.connection().unwrap()).unwrap_err();
+
+        // The auxiliary writer does not wait for the writer to be released.
+        let _writer = storage.writer().unwrap();
+        insert(&storage.auxiliary_writer().unwrap()).unwrap();
+    }
+
+    mod read_only_connection {
+        use super::*;
+
+        fn assert_write_fails(connection: &mut Connection) {
+            let tx = connection.transaction().unwrap();
+            let block = &test_utils::create_blocks()[0];
+
+            let error = StarknetBlocksTable::insert(&tx, block, None).unwrap_err();
+            assert_matches::assert_matches!(
+                error.downcast_ref::<rusqlite::Error>(),
+                Some(rusqlite::Error::SqliteFailure(
+                    rusqlite::ffi::Error {
+                        code: rusqlite::ErrorCode::ReadOnly,
+                        ..
+                    },
+                    _
+                ))
+            );
+        }
+
+        #[test]
+        fn file() {
+            let db_dir = tempfile::TempDir::new().unwrap();
+            let storage =
+                Storage::migrate(db_dir.path().join("ro.sqlite"), JournalMode::WAL).unwrap();
+
+            let mut connection = storage.read_only_connection().unwrap();
+            assert_write_fails(&mut connection);
+        }
+
+        #[test]
+        fn in_memory() {
+            let (storage, events) = test_utils::setup_test_storage();
+
+            let mut connection = storage.read_only_connection().unwrap();
+            assert_write_fails(&mut connection);
+
+            // Queries still work.
+            let tx = connection.transaction().unwrap();
+            let page = StarknetEventsTable::get_events(
+                &tx,
+                &StarknetEventFilter {
+                    from_block: None,
+                    to_block: None,
+                    contract_address: None,
+                    keys: vec![],
+                    data_filter: vec![],
+                    page_size: events.len(),
+                    page_number: 0,
+                    detect_last_page: true,
+                    count_pages: false,
+                    only_accepted: false,
+                },
+            )
+            .unwrap();
+            assert_eq!(page.events, events);
+        }
+    }
+
+    mod merge_from {
+        use super::*;
+        use crate::core::StarknetBlockHash;
+        use crate::rpc::v01::types::reply::StateUpdate;
+
+        /// Creates a storage with the test blocks, transactions and events, as well as a state
+        /// update for each block.
+        fn archive() -> (Storage, Vec<StarknetEmittedEvent>) {
+            let (storage, events) = test_utils::setup_test_storage();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            for (i, block) in test_utils::create_blocks().iter().enumerate() {
+                let mut state_update = StateUpdate::with_block_hash(i as u8);
+                state_update.block_hash = Some(block.hash);
+                StarknetStateUpdatesTable::insert(&tx, block.hash, &state_update).unwrap();
+            }
+            tx.commit().unwrap();
+
+            (storage, events)
+        }
+
+        fn all_events(tx: &rusqlite::Transaction<'_>) -> Vec<StarknetEmittedEvent> {
+            let filter = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                contract_address: None,
+                keys: vec![],
+                data_filter: vec![],
+                page_size: test_utils::NUM_EVENTS + 1,
+                page_number: 0,
+                detect_last_page: true,
+                count_pages: false,
+                only_accepted: false,
+            };
+            StarknetEventsTable::get_events(tx, &filter).unwrap().events
+        }
+
+        #[test]
+        fn disjoint_ranges() {
+            let (archive, expected_events) = archive();
+            let storage = Storage::in_memory().unwrap();
+
+            let second_half = StarknetBlockNumber::new_or_panic(2);
+            let head = StarknetBlockNumber::new_or_panic(test_utils::NUM_BLOCKS as u64 - 1);
+            storage.merge_from(&archive, second_half, head).unwrap();
+            storage
+                .merge_from(&archive, StarknetBlockNumber::GENESIS, second_half - 1)
+                .unwrap();
+
+            let mut archive_connection = archive.connection().unwrap();
+            let archive_tx = archive_connection.transaction().unwrap();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            for block in test_utils::create_blocks() {
+                assert_eq!(
+                    StarknetBlocksTable::get(&tx, block.number.into()).unwrap(),
+                    Some(block.clone())
+                );
+                assert_eq!(
+                    CanonicalBlocksTable::get(&tx, block.number).unwrap(),
+                    Some(block.hash)
+                );
+                assert_eq!(
+                    StarknetTransactionsTable::get_transaction_data_for_block(
+                        &tx,
+                        block.hash.into()
+                    )
+                    .unwrap(),
+                    StarknetTransactionsTable::get_transaction_data_for_block(
+                        &archive_tx,
+                        block.hash.into()
+                    )
+                    .unwrap()
+                );
+                assert_eq!(
+                    StarknetStateUpdatesTable::get(&tx, block.hash).unwrap(),
+                    StarknetStateUpdatesTable::get(&archive_tx, block.hash).unwrap()
+                );
+            }
+            assert_eq!(all_events(&tx), expected_events);
+        }
+
+        #[test]
+        fn existing_rows_are_skipped() {
+            let (archive, expected_events) = archive();
+            let storage = Storage::in_memory().unwrap();
+
+            let head = StarknetBlockNumber::new_or_panic(test_utils::NUM_BLOCKS as u64 - 1);
+            storage
+                .merge_from(&archive, StarknetBlockNumber::GENESIS, head)
+                .unwrap();
+            storage
+                .merge_from(&archive, StarknetBlockNumber::GENESIS, head)
+                .unwrap();
+
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+            assert_eq!(all_events(&tx), expected_events);
+        }
+
+        #[test]
+        fn conflicting_block() {
+            let (archive, _) = archive();
+            let storage = Storage::in_memory().unwrap();
+
+            let mut other = test_utils::create_blocks()[1].clone();
+            other.hash = StarknetBlockHash(stark_hash::StarkHash::from_be_slice(b"other").unwrap());
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+            StarknetBlocksTable::insert(&tx, &other, None).unwrap();
+            tx.commit().unwrap();
+            drop(connection);
+
+            let head = StarknetBlockNumber::new_or_panic(test_utils::NUM_BLOCKS as u64 - 1);
+            let error = storage
+                .merge_from(&archive, StarknetBlockNumber::GENESIS, head)
+                .unwrap_err();
+            assert!(error.to_string().contains("conflicts"), "{error:?}");
+
+            // Nothing was merged.
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+            assert_eq!(
+                StarknetBlocksTable::get(&tx, StarknetBlockNumber::GENESIS.into()).unwrap(),
+                None
+            );
+        }
+
+        #[test]
+        fn missing_source_block() {
+            let (archive, _) = archive();
+            let storage = Storage::in_memory().unwrap();
+
+            let past_head = StarknetBlockNumber::new_or_panic(test_utils::NUM_BLOCKS as u64);
+            storage
+                .merge_from(&archive, StarknetBlockNumber::GENESIS, past_head)
+                .unwrap_err();
+        }
+    }
 }
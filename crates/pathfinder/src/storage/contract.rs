@@ -133,6 +133,30 @@ impl ContractCodeTable {
         }))
     }
 
+    /// Returns the class as it is stored, i.e. with its columns still compressed.
+    pub fn get_compressed(
+        transaction: &Transaction<'_>,
+        hash: ClassHash,
+    ) -> anyhow::Result<Option<CompressedContract>> {
+        transaction
+            .query_row(
+                "SELECT abi, bytecode, definition FROM contract_code WHERE hash = :hash",
+                named_params! {
+                    ":hash": &hash.0.to_be_bytes()
+                },
+                |row| {
+                    Ok(CompressedContract {
+                        abi: row.get("abi")?,
+                        bytecode: row.get("bytecode")?,
+                        definition: row.get("definition")?,
+                        hash,
+                    })
+                },
+            )
+            .optional()
+            .map_err(|e| e.into())
+    }
+
     /// Returns true for each [ClassHash] if the class definition already exists in the table.
     pub fn exists(connection: &Connection, classes: &[ClassHash]) -> anyhow::Result<Vec<bool>> {
         let mut stmt = connection.prepare("select 1 from contract_code where hash = ?")?;
@@ -201,7 +225,7 @@ mod tests {
     #[test]
     fn fails_if_class_hash_missing() {
         let storage = Storage::in_memory().unwrap();
-        let mut conn = storage.connection().unwrap();
+        let mut conn = storage.writer().unwrap();
         let transaction = conn.transaction().unwrap();
 
         let address = ContractAddress::new_or_panic(starkhash!("0abc"));
@@ -213,7 +237,7 @@ mod tests {
     #[test]
     fn get_hash() {
         let storage = Storage::in_memory().unwrap();
-        let mut conn = storage.connection().unwrap();
+        let mut conn = storage.writer().unwrap();
         let transaction = conn.transaction().unwrap();
 
         let address = ContractAddress::new_or_panic(starkhash!("0abc"));
@@ -231,7 +255,7 @@ mod tests {
     #[test]
     fn get_class() {
         let storage = Storage::in_memory().unwrap();
-        let mut conn = storage.connection().unwrap();
+        let mut conn = storage.writer().unwrap();
         let transaction = conn.transaction().unwrap();
 
         let (hash, program, entry_points_by_type) = setup_class(&transaction);
@@ -273,7 +297,7 @@ mod tests {
     #[test]
     fn contracts_exist() {
         let storage = Storage::in_memory().unwrap();
-        let mut connection = storage.connection().unwrap();
+        let mut connection = storage.writer().unwrap();
         let transaction = connection.transaction().unwrap();
 
         let (hash, _, _) = setup_class(&transaction);
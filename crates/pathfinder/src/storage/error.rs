@@ -0,0 +1,120 @@
+//! Contains [StorageError], the error returned by the storage getters.
+use super::EventFilterError;
+
+/// The ways in which reading from storage can fail.
+///
+/// Missing rows are not an error: getters return `None` or an empty list instead.
+///
+/// Converts into [anyhow::Error] like any other error, so callers which do not need to tell
+/// the variants apart can keep using `?` and `context`.
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    /// Another connection holds a lock on the database. The query may succeed if retried.
+    #[error("database is busy")]
+    Busy,
+    /// Stored data could not be decoded.
+    #[error("corrupted data in {table}: {detail}")]
+    Corruption { table: &'static str, detail: String },
+    #[error(transparent)]
+    InvalidFilter(#[from] EventFilterError),
+    #[error(transparent)]
+    Sqlite(rusqlite::Error),
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl StorageError {
+    pub(crate) fn corruption(table: &'static str, detail: impl std::fmt::Display) -> Self {
+        Self::Corruption {
+            table,
+            detail: detail.to_string(),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        match e {
+            rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error {
+                    code: rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked,
+                    ..
+                },
+                _,
+            ) => Self::Busy,
+            e => Self::Sqlite(e),
+        }
+    }
+}
+
+impl From<rusqlite::types::FromSqlError> for StorageError {
+    fn from(e: rusqlite::types::FromSqlError) -> Self {
+        rusqlite::Error::from(e).into()
+    }
+}
+
+/// Errors which went through [anyhow::Context] are classified by their source, so that wrapping a
+/// query error in context does not hide it.
+impl From<anyhow::Error> for StorageError {
+    fn from(e: anyhow::Error) -> Self {
+        let e = match e.downcast::<Self>() {
+            Ok(e) => return e,
+            Err(e) => e,
+        };
+
+        if let Some(filter) = e.downcast_ref::<EventFilterError>() {
+            return Self::InvalidFilter(*filter);
+        }
+
+        match e.downcast_ref::<rusqlite::Error>() {
+            Some(rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error {
+                    code: rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked,
+                    ..
+                },
+                _,
+            )) => Self::Busy,
+            _ => Self::Other(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sqlite_failure(code: std::os::raw::c_int) -> rusqlite::Error {
+        rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(code), None)
+    }
+
+    #[test]
+    fn busy() {
+        let busy = StorageError::from(sqlite_failure(rusqlite::ffi::SQLITE_BUSY));
+        assert!(matches!(busy, StorageError::Busy));
+
+        let locked = StorageError::from(sqlite_failure(rusqlite::ffi::SQLITE_LOCKED));
+        assert!(matches!(locked, StorageError::Busy));
+    }
+
+    #[test]
+    fn classified_through_context() {
+        use anyhow::Context;
+
+        let busy: anyhow::Result<()> =
+            Err(sqlite_failure(rusqlite::ffi::SQLITE_BUSY)).context("Executing query");
+        let busy = StorageError::from(busy.unwrap_err());
+        assert!(matches!(busy, StorageError::Busy));
+
+        let filter: anyhow::Result<()> =
+            Err(EventFilterError::QueryTimeout).context("Querying events");
+        let filter = StorageError::from(filter.unwrap_err());
+        assert!(matches!(
+            filter,
+            StorageError::InvalidFilter(EventFilterError::QueryTimeout)
+        ));
+
+        let other = StorageError::from(sqlite_failure(rusqlite::ffi::SQLITE_CONSTRAINT));
+        let other = StorageError::from(anyhow::Error::from(other));
+        assert!(matches!(other, StorageError::Sqlite(_)));
+    }
+}
@@ -0,0 +1,233 @@
+//! Per-block bloom filters over the keys and emitting contracts of the block's events.
+//!
+//! [StarknetEventsTable::get_events](super::StarknetEventsTable::get_events) scans them to skip
+//! the blocks which cannot contain a matching event before running the precise query. A bloom
+//! filter has no false negatives, so skipping blocks never changes the result.
+//!
+//! Each filter has [EventBloom::BITS] bits, of which every item sets [EventBloom::HASHES]
+//! derived from its Keccak256 hash. This keeps the false positive rate below 1% for blocks with
+//! up to 400 distinct keys and contract addresses, which covers typical blocks, at 512 bytes per
+//! block. Blocks without events have no filter, and neither do the blocks stored before filters
+//! were introduced.
+
+use anyhow::Context;
+use rusqlite::{named_params, OptionalExtension, Transaction};
+use sha3::{Digest, Keccak256};
+use stark_hash::StarkHash;
+
+use super::StorageError;
+use crate::core::{ContractAddress, EventKey, StarknetBlockNumber};
+
+/// A bloom filter over the keys and emitting contracts of a block's events.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct EventBloom(Vec<u8>);
+
+/// The bits set by a single item, see [EventBloom::bits].
+pub(crate) type BloomBits = [usize; EventBloom::HASHES];
+
+impl EventBloom {
+    pub const BITS: usize = 4096;
+    pub const HASHES: usize = 5;
+    const BYTES: usize = Self::BITS / 8;
+
+    pub fn new() -> Self {
+        Self(vec![0; Self::BYTES])
+    }
+
+    /// Returns [None] if `bytes` is not the size of a filter.
+    pub fn from_bytes(bytes: Vec<u8>) -> Option<Self> {
+        (bytes.len() == Self::BYTES).then_some(Self(bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Adds an event's contract address and keys.
+    pub fn insert_event(&mut self, from_address: &ContractAddress, keys: &[EventKey]) {
+        self.insert(from_address.get());
+        keys.iter().for_each(|key| self.insert(&key.0));
+    }
+
+    pub fn insert(&mut self, item: &StarkHash) {
+        for bit in Self::bits(item) {
+            self.0[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    /// Whether an item with these [bits](EventBloom::bits) may have been inserted.
+    pub fn contains(&self, bits: &BloomBits) -> bool {
+        bits.iter()
+            .all(|&bit| self.0[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    /// Adds all items of `other`.
+    pub fn merge(&mut self, other: &Self) {
+        self.0
+            .iter_mut()
+            .zip(&other.0)
+            .for_each(|(byte, other)| *byte |= other);
+    }
+
+    /// The bits set by `item`, each taken from two consecutive bytes of its Keccak256 hash.
+    pub fn bits(item: &StarkHash) -> BloomBits {
+        let hash = Keccak256::digest(item.as_be_bytes());
+
+        let mut bits = [0; Self::HASHES];
+        for (i, bit) in bits.iter_mut().enumerate() {
+            let chunk = u16::from_be_bytes([hash[2 * i], hash[2 * i + 1]]);
+            *bit = usize::from(chunk) % Self::BITS;
+        }
+        bits
+    }
+}
+
+/// Stores an [EventBloom] for each block with events.
+pub(crate) struct EventBloomsTable {}
+
+impl EventBloomsTable {
+    /// Adds the items of `bloom` to the block's filter, creating it if necessary.
+    pub fn merge(
+        tx: &Transaction<'_>,
+        block_number: StarknetBlockNumber,
+        bloom: &EventBloom,
+    ) -> anyhow::Result<()> {
+        let existing: Option<Vec<u8>> = tx
+            .query_row(
+                "SELECT bloom FROM starknet_event_blooms WHERE block_number = ?",
+                [block_number],
+                |row| row.get(0),
+            )
+            .optional()
+            .context("Reading event bloom")?;
+
+        let mut merged = bloom.clone();
+        if let Some(existing) = existing {
+            let existing = EventBloom::from_bytes(existing).context("Invalid event bloom size")?;
+            merged.merge(&existing);
+        }
+
+        tx.execute(
+            "INSERT OR REPLACE INTO starknet_event_blooms (block_number, bloom) VALUES (:block_number, :bloom)",
+            named_params! {
+                ":block_number": block_number,
+                ":bloom": merged.as_bytes(),
+            },
+        )
+        .context("Inserting event bloom")?;
+
+        Ok(())
+    }
+
+    /// Returns the blocks in the range, in ascending order, whose filters may contain an event
+    /// from `contract_address` with any of the `keys`. No contract address or keys match any
+    /// block with events.
+    ///
+    /// Returns [None] as soon as more than `max_candidates` blocks match, without reading the
+    /// remaining filters, and if the range starts before the blocks which have filters, see
+    /// [revision_0038](super::schema::revision_0038).
+    pub fn candidate_blocks(
+        tx: &Transaction<'_>,
+        from_block: Option<StarknetBlockNumber>,
+        to_block: Option<StarknetBlockNumber>,
+        contract_address: Option<&ContractAddress>,
+        keys: &[EventKey],
+        max_candidates: usize,
+    ) -> Result<Option<Vec<StarknetBlockNumber>>, StorageError> {
+        let from_block = from_block.unwrap_or(StarknetBlockNumber::GENESIS);
+
+        let start: StarknetBlockNumber = tx
+            .query_row(
+                "SELECT block_number FROM starknet_event_blooms_start",
+                [],
+                |row| row.get(0),
+            )
+            .context("Reading first block with event blooms")?;
+        if from_block < start {
+            return Ok(None);
+        }
+
+        let address = contract_address.map(|address| EventBloom::bits(address.get()));
+        let keys = keys
+            .iter()
+            .map(|key| EventBloom::bits(&key.0))
+            .collect::<Vec<_>>();
+
+        let mut stmt = tx
+            .prepare(
+                "SELECT block_number, bloom FROM starknet_event_blooms
+                 WHERE block_number BETWEEN :from_block AND :to_block ORDER BY block_number",
+            )
+            .context("Preparing statement")?;
+        let mut rows = stmt
+            .query(named_params! {
+                ":from_block": from_block,
+                ":to_block": to_block.unwrap_or(StarknetBlockNumber::MAX),
+            })
+            .context("Executing query")?;
+
+        let mut candidates = Vec::new();
+        while let Some(row) = rows.next().context("Fetching next event bloom")? {
+            let block_number = row.get("block_number")?;
+            let bloom = row.get_ref("bloom")?.as_blob()?;
+            let bloom = EventBloom::from_bytes(bloom.to_vec()).ok_or_else(|| {
+                StorageError::corruption(
+                    "starknet_event_blooms",
+                    format!("Invalid size of event bloom of block {block_number}"),
+                )
+            })?;
+
+            let address_matches = address.as_ref().map_or(true, |bits| bloom.contains(bits));
+            let keys_match = keys.is_empty() || keys.iter().any(|bits| bloom.contains(bits));
+            if address_matches && keys_match {
+                if candidates.len() == max_candidates {
+                    return Ok(None);
+                }
+                candidates.push(block_number);
+            }
+        }
+
+        Ok(Some(candidates))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(i: u32) -> StarkHash {
+        StarkHash::from_be_slice(&i.to_be_bytes()).unwrap()
+    }
+
+    #[test]
+    fn contains_inserted_items() {
+        let mut bloom = EventBloom::new();
+        (0..100).for_each(|i| bloom.insert(&item(i)));
+
+        assert!((0..100).all(|i| bloom.contains(&EventBloom::bits(&item(i)))));
+    }
+
+    #[test]
+    fn false_positive_rate() {
+        // The documented worst case for typical blocks.
+        let mut bloom = EventBloom::new();
+        (0..400).for_each(|i| bloom.insert(&item(i)));
+
+        let false_positives = (400..100_400)
+            .filter(|&i| bloom.contains(&EventBloom::bits(&item(i))))
+            .count();
+        assert!(false_positives < 1_000, "{false_positives} false positives");
+    }
+
+    #[test]
+    fn merge() {
+        let mut first = EventBloom::new();
+        first.insert(&item(1));
+        let mut second = EventBloom::new();
+        second.insert(&item(2));
+
+        first.merge(&second);
+        assert!(first.contains(&EventBloom::bits(&item(1))));
+        assert!(first.contains(&EventBloom::bits(&item(2))));
+    }
+}
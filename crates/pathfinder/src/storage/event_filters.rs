@@ -0,0 +1,400 @@
+//! Event filters registered by clients which poll for new matching events, in the style of
+//! Ethereum's `eth_newFilter` and `eth_getFilterChanges`.
+//!
+//! Each filter stores the [EventCursor] of the last event delivered for it, so that every poll
+//! only returns events which were not delivered before.
+
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use rusqlite::{named_params, OptionalExtension, Transaction};
+
+use super::{
+    EventCursor, StarknetEmittedEvent, StarknetEventFilter, StarknetEventsTable, StorageError,
+};
+use crate::core::StarknetBlockNumber;
+
+/// Identifies a filter registered with [EventFiltersTable::register].
+///
+/// Identifiers are never reused, even after the filter is removed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct FilterId(pub u64);
+
+/// Stores registered event filters and the last event delivered for each.
+pub struct EventFiltersTable {}
+
+impl EventFiltersTable {
+    /// The default time after which a filter which is not polled is removed, see
+    /// [EventFiltersTable::expire].
+    pub const DEFAULT_MAX_IDLE: Duration = Duration::from_secs(5 * 60);
+
+    /// The default number of filters which may be registered at the same time.
+    pub const DEFAULT_MAX_FILTERS: usize = 1024;
+
+    /// Registers `filter` and returns its identifier. Its first poll starts at the beginning of
+    /// the filter's block range.
+    ///
+    /// The filter's page size limits the number of events returned by each poll, its page number
+    /// is ignored.
+    ///
+    /// Fails with [TooManyFilters](super::EventFilterError::TooManyFilters) if `max_filters`
+    /// filters are already registered.
+    pub fn register(
+        tx: &Transaction<'_>,
+        filter: &StarknetEventFilter,
+        max_filters: usize,
+    ) -> Result<FilterId, StorageError> {
+        StarknetEventsTable::check_filter_limits(
+            &filter.keys,
+            &filter.data_filter,
+            StarknetEventsTable::DEFAULT_MAX_KEYS,
+        )?;
+        if filter.page_size > StarknetEventsTable::PAGE_SIZE_LIMIT {
+            return Err(super::EventFilterError::PageSizeTooBig(
+                StarknetEventsTable::PAGE_SIZE_LIMIT,
+            )
+            .into());
+        }
+        if filter.page_size < 1 {
+            return Err(anyhow::anyhow!("Invalid page size").into());
+        }
+
+        let registered: usize = tx
+            .query_row("SELECT COUNT(1) FROM event_filters", [], |row| row.get(0))
+            .context("Counting event filters")?;
+        if registered >= max_filters {
+            return Err(super::EventFilterError::TooManyFilters(max_filters).into());
+        }
+
+        let keys = serde_json::to_string(&filter.keys).context("Serializing keys")?;
+        let data_filter =
+            serde_json::to_string(&filter.data_filter).context("Serializing data filter")?;
+
+        tx.execute(
+            r"INSERT INTO event_filters (from_block, to_block, contract_address, keys, data_filter, page_size, last_used)
+              VALUES (:from_block, :to_block, :contract_address, :keys, :data_filter, :page_size, :last_used)",
+            named_params! {
+                ":from_block": filter.from_block,
+                ":to_block": filter.to_block,
+                ":contract_address": filter.contract_address,
+                ":keys": keys,
+                ":data_filter": data_filter,
+                ":page_size": filter.page_size,
+                ":last_used": unix_timestamp(),
+            },
+        )
+        .context("Inserting event filter")?;
+
+        Ok(FilterId(tx.last_insert_rowid() as u64))
+    }
+
+    /// Returns the events matching the filter which were not delivered by a previous poll, up to
+    /// the filter's page size, and records them as delivered.
+    ///
+    /// Returns [None] if there is no such filter, e.g. because it was removed or expired.
+    ///
+    /// The delivered events are only recorded once `tx` is committed. Concurrent polls of the
+    /// same filter should use an immediate transaction, so that they cannot read the same cursor.
+    pub fn poll(
+        tx: &Transaction<'_>,
+        id: FilterId,
+    ) -> Result<Option<Vec<StarknetEmittedEvent>>, StorageError> {
+        let stored = tx
+            .query_row(
+                r"SELECT from_block, to_block, contract_address, keys, data_filter, page_size,
+                    block_number, transaction_idx, event_idx
+                  FROM event_filters WHERE id = ?",
+                [id.0],
+                |row| {
+                    let cursor = match row.get("block_number")? {
+                        Some(block_number) => Some(EventCursor {
+                            block_number,
+                            transaction_idx: row.get("transaction_idx")?,
+                            event_idx: row.get("event_idx")?,
+                        }),
+                        None => None,
+                    };
+
+                    Ok((
+                        row.get("from_block")?,
+                        row.get("to_block")?,
+                        row.get("contract_address")?,
+                        row.get::<_, String>("keys")?,
+                        row.get::<_, String>("data_filter")?,
+                        row.get("page_size")?,
+                        cursor,
+                    ))
+                },
+            )
+            .optional()
+            .context("Reading event filter")?;
+
+        let (from_block, to_block, contract_address, keys, data_filter, page_size, cursor) =
+            match stored {
+                Some(stored) => stored,
+                None => return Ok(None),
+            };
+
+        let filter = StarknetEventFilter {
+            from_block,
+            to_block,
+            contract_address,
+            keys: serde_json::from_str(&keys)
+                .map_err(|e| StorageError::corruption("event_filters", e))?,
+            data_filter: serde_json::from_str(&data_filter)
+                .map_err(|e| StorageError::corruption("event_filters", e))?,
+            page_size,
+            page_number: 0,
+            detect_last_page: false,
+            count_pages: false,
+            only_accepted: false,
+        };
+
+        let events = StarknetEventsTable::get_events_after(tx, &filter, cursor, page_size)?;
+        let cursor = events.last().map(|(cursor, _)| *cursor).or(cursor);
+
+        tx.execute(
+            r"UPDATE event_filters SET block_number = :block_number, transaction_idx = :transaction_idx,
+                event_idx = :event_idx, last_used = :last_used
+              WHERE id = :id",
+            named_params! {
+                ":block_number": cursor.map(|c| c.block_number),
+                ":transaction_idx": cursor.map(|c| c.transaction_idx),
+                ":event_idx": cursor.map(|c| c.event_idx),
+                ":last_used": unix_timestamp(),
+                ":id": id.0,
+            },
+        )
+        .context("Updating event filter cursor")?;
+
+        Ok(Some(events.into_iter().map(|(_, event)| event).collect()))
+    }
+
+    /// Removes the filter, returning `false` if there was no such filter.
+    pub fn remove(tx: &Transaction<'_>, id: FilterId) -> anyhow::Result<bool> {
+        let deleted = tx
+            .execute("DELETE FROM event_filters WHERE id = ?", [id.0])
+            .context("Deleting event filter")?;
+
+        Ok(deleted > 0)
+    }
+
+    /// Removes the filters which were neither registered nor polled within the last `max_idle`,
+    /// returning the number of filters removed.
+    pub fn expire(tx: &Transaction<'_>, max_idle: Duration) -> anyhow::Result<usize> {
+        let cutoff = unix_timestamp().saturating_sub(max_idle.as_secs());
+
+        tx.execute("DELETE FROM event_filters WHERE last_used < ?", [cutoff])
+            .context("Deleting expired event filters")
+    }
+
+    /// Rewinds the filters which delivered events from `reorg_tail` onwards, so that their next
+    /// poll starts at `reorg_tail` again.
+    pub fn reorg(tx: &Transaction<'_>, reorg_tail: StarknetBlockNumber) -> anyhow::Result<()> {
+        let cursor = match reorg_tail {
+            StarknetBlockNumber::GENESIS => None,
+            other => Some(EventCursor::end_of_block(other - 1)),
+        };
+
+        tx.execute(
+            r"UPDATE event_filters SET block_number = :block_number, transaction_idx = :transaction_idx,
+                event_idx = :event_idx
+              WHERE block_number >= :reorg_tail",
+            named_params! {
+                ":block_number": cursor.map(|c| c.block_number),
+                ":transaction_idx": cursor.map(|c| c.transaction_idx),
+                ":event_idx": cursor.map(|c| c.event_idx),
+                ":reorg_tail": reorg_tail,
+            },
+        )
+        .context("Rewinding event filter cursors")?;
+
+        Ok(())
+    }
+}
+
+/// Seconds since the unix epoch, for [EventFiltersTable]'s `last_used` column.
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::EventKey;
+    use crate::storage::test_utils;
+
+    fn filter(keys: Vec<EventKey>, page_size: usize) -> StarknetEventFilter {
+        StarknetEventFilter {
+            from_block: None,
+            to_block: None,
+            contract_address: None,
+            keys,
+            data_filter: vec![],
+            page_size,
+            page_number: 0,
+            detect_last_page: false,
+            count_pages: false,
+            only_accepted: false,
+        }
+    }
+
+    fn cursor(tx: &Transaction<'_>, id: FilterId) -> Option<StarknetBlockNumber> {
+        tx.query_row(
+            "SELECT block_number FROM event_filters WHERE id = ?",
+            [id.0],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn filters_advance_independently() {
+        let (storage, events) = test_utils::setup_test_storage();
+        let mut connection = storage.writer().unwrap();
+        let tx = connection.transaction().unwrap();
+
+        // Every event has the same second key, and a unique first key.
+        let common = events[0].keys[1];
+        let all = EventFiltersTable::register(
+            &tx,
+            &filter(vec![events[1].keys[0], common], 10),
+            EventFiltersTable::DEFAULT_MAX_FILTERS,
+        )
+        .unwrap();
+        let some = EventFiltersTable::register(
+            &tx,
+            &filter(vec![events[1].keys[0], events[12].keys[0]], 10),
+            EventFiltersTable::DEFAULT_MAX_FILTERS,
+        )
+        .unwrap();
+        assert_ne!(all, some);
+
+        let polled = EventFiltersTable::poll(&tx, all).unwrap().unwrap();
+        assert_eq!(polled, events[..10]);
+
+        let polled = EventFiltersTable::poll(&tx, some).unwrap().unwrap();
+        assert_eq!(polled, vec![events[1].clone(), events[12].clone()]);
+
+        let polled = EventFiltersTable::poll(&tx, all).unwrap().unwrap();
+        assert_eq!(polled, events[10..20]);
+
+        let polled = EventFiltersTable::poll(&tx, some).unwrap().unwrap();
+        assert_eq!(polled, vec![]);
+
+        let polled = EventFiltersTable::poll(&tx, all).unwrap().unwrap();
+        assert_eq!(polled, events[20..30]);
+    }
+
+    #[test]
+    fn reorg_rewinds_affected_cursors() {
+        let (storage, events) = test_utils::setup_test_storage();
+        let mut connection = storage.writer().unwrap();
+        let tx = connection.transaction().unwrap();
+
+        let common = events[0].keys[1];
+        let behind = EventFiltersTable::register(
+            &tx,
+            &filter(vec![common], 5),
+            EventFiltersTable::DEFAULT_MAX_FILTERS,
+        )
+        .unwrap();
+        let ahead = EventFiltersTable::register(
+            &tx,
+            &filter(vec![common], 25),
+            EventFiltersTable::DEFAULT_MAX_FILTERS,
+        )
+        .unwrap();
+
+        EventFiltersTable::poll(&tx, behind).unwrap().unwrap();
+        EventFiltersTable::poll(&tx, ahead).unwrap().unwrap();
+        assert_eq!(
+            cursor(&tx, behind),
+            Some(StarknetBlockNumber::new_or_panic(0))
+        );
+        assert_eq!(
+            cursor(&tx, ahead),
+            Some(StarknetBlockNumber::new_or_panic(2))
+        );
+
+        EventFiltersTable::reorg(&tx, StarknetBlockNumber::new_or_panic(2)).unwrap();
+        assert_eq!(
+            cursor(&tx, behind),
+            Some(StarknetBlockNumber::new_or_panic(0))
+        );
+        assert_eq!(
+            cursor(&tx, ahead),
+            Some(StarknetBlockNumber::new_or_panic(1))
+        );
+
+        // The events of the reorged blocks are delivered again.
+        let polled = EventFiltersTable::poll(&tx, ahead).unwrap().unwrap();
+        assert_eq!(polled, events[20..]);
+        let polled = EventFiltersTable::poll(&tx, behind).unwrap().unwrap();
+        assert_eq!(polled, events[5..10]);
+
+        EventFiltersTable::reorg(&tx, StarknetBlockNumber::GENESIS).unwrap();
+        assert_eq!(cursor(&tx, behind), None);
+        assert_eq!(cursor(&tx, ahead), None);
+    }
+
+    #[test]
+    fn remove_and_expire() {
+        let (storage, events) = test_utils::setup_test_storage();
+        let mut connection = storage.writer().unwrap();
+        let tx = connection.transaction().unwrap();
+
+        let keys = vec![events[0].keys[1]];
+        let removed = EventFiltersTable::register(
+            &tx,
+            &filter(keys.clone(), 5),
+            EventFiltersTable::DEFAULT_MAX_FILTERS,
+        )
+        .unwrap();
+        let kept = EventFiltersTable::register(
+            &tx,
+            &filter(keys, 5),
+            EventFiltersTable::DEFAULT_MAX_FILTERS,
+        )
+        .unwrap();
+
+        assert!(EventFiltersTable::remove(&tx, removed).unwrap());
+        assert!(!EventFiltersTable::remove(&tx, removed).unwrap());
+        assert_eq!(EventFiltersTable::poll(&tx, removed).unwrap(), None);
+
+        assert_eq!(
+            EventFiltersTable::expire(&tx, Duration::from_secs(60)).unwrap(),
+            0
+        );
+        tx.execute("UPDATE event_filters SET last_used = last_used - 61", [])
+            .unwrap();
+        assert_eq!(
+            EventFiltersTable::expire(&tx, Duration::from_secs(60)).unwrap(),
+            1
+        );
+        assert_eq!(EventFiltersTable::poll(&tx, kept).unwrap(), None);
+    }
+    #[test]
+    fn register_is_capped() {
+        let (storage, events) = test_utils::setup_test_storage();
+        let mut connection = storage.writer().unwrap();
+        let tx = connection.transaction().unwrap();
+
+        let keys = vec![events[0].keys[1]];
+        let first = EventFiltersTable::register(&tx, &filter(keys.clone(), 5), 2).unwrap();
+        EventFiltersTable::register(&tx, &filter(keys.clone(), 5), 2).unwrap();
+
+        let error = EventFiltersTable::register(&tx, &filter(keys.clone(), 5), 2).unwrap_err();
+        assert_matches::assert_matches!(
+            error,
+            StorageError::InvalidFilter(crate::storage::EventFilterError::TooManyFilters(2))
+        );
+
+        // Removing a filter makes room for another.
+        EventFiltersTable::remove(&tx, first).unwrap();
+        EventFiltersTable::register(&tx, &filter(keys, 5), 2).unwrap();
+    }
+}
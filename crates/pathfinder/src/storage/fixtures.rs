@@ -78,11 +78,17 @@ impl StarknetBlock {
         Self {
             number: StarknetBlockNumber::new(n as u64).expect("block number out of range"),
             hash: StarknetBlockHash(hash!(n)),
+            parent_hash: match n {
+                0 => StarknetBlockHash(StarkHash::ZERO),
+                n => StarknetBlockHash(hash!(n - 1)),
+            },
             root: GlobalRoot(hash!(1, n)),
             timestamp: StarknetBlockTimestamp::new(n as u64 + 1000)
                 .expect("block timestamp out of range"),
             gas_price: GasPrice(n as u128 + 2000),
-            sequencer_address: SequencerAddress(hash!(2, n)),
+            sequencer_address: Some(SequencerAddress(hash!(2, n))),
+            transaction_count: 0,
+            event_count: 0,
         }
     }
 }
@@ -94,7 +100,7 @@ where
     F: FnOnce(&Storage, &Transaction<'_>, Vec<StateUpdate>),
 {
     let storage = Storage::in_memory().unwrap();
-    let mut connection = storage.connection().unwrap();
+    let mut connection = storage.writer().unwrap();
     let tx = connection.transaction().unwrap();
 
     f(&storage, &tx, init::with_n_state_updates(&tx, n))
@@ -0,0 +1,116 @@
+//! Metrics related utilities for database writes.
+//!
+//! Recording is a no-op unless a [metrics::Recorder] is installed, which only happens if the
+//! monitoring API is enabled.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+const METRIC_CALLS: &str = "storage_calls_total";
+const METRIC_ROWS: &str = "storage_rows_written_total";
+const METRIC_BYTES: &str = "storage_compressed_bytes_written_total";
+const METRIC_DURATION: &str = "storage_call_duration_seconds";
+const METRIC_TIMESTAMP_REGRESSIONS: &str = "storage_timestamp_regressions_total";
+
+/// The instrumented write paths, used as the `method` label of each metric.
+pub(crate) const INSERT_BLOCK: &str = "insert_block";
+pub(crate) const UPSERT_TRANSACTIONS: &str = "upsert_transactions";
+pub(crate) const INSERT_EVENTS: &str = "insert_events";
+pub(crate) const INSERT_STATE_UPDATE: &str = "insert_state_update";
+pub(crate) const UPSERT_CONTRACT_STATE: &str = "upsert_contract_state";
+const METHODS: [&str; 5] = [
+    INSERT_BLOCK,
+    UPSERT_TRANSACTIONS,
+    INSERT_EVENTS,
+    INSERT_STATE_UPDATE,
+    UPSERT_CONTRACT_STATE,
+];
+
+/// Calls taking at least this many milliseconds are logged, see [set_slow_query_threshold].
+static SLOW_QUERY_THRESHOLD_MS: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Register all storage related metrics
+pub fn register() {
+    METHODS.iter().for_each(|&method| {
+        metrics::register_counter!(METRIC_CALLS, "method" => method);
+        metrics::register_counter!(METRIC_ROWS, "method" => method);
+        metrics::register_counter!(METRIC_BYTES, "method" => method);
+        metrics::register_histogram!(METRIC_DURATION, "method" => method);
+    });
+    metrics::register_counter!(METRIC_TIMESTAMP_REGRESSIONS);
+}
+
+/// Counts a block whose timestamp is before its parent's, see
+/// [TimestampPolicy](super::TimestampPolicy).
+pub(crate) fn record_timestamp_regression() {
+    metrics::increment_counter!(METRIC_TIMESTAMP_REGRESSIONS);
+}
+
+/// Storage writes taking at least `threshold` are logged as warnings, along with the fields
+/// of their tracing span. Disabled by default.
+///
+/// This applies to the whole process.
+pub fn set_slow_query_threshold(threshold: Option<Duration>) {
+    let threshold = threshold
+        .map(|threshold| u64::try_from(threshold.as_millis()).unwrap_or(u64::MAX))
+        .unwrap_or(u64::MAX);
+    SLOW_QUERY_THRESHOLD_MS.store(threshold, Ordering::Relaxed);
+}
+
+/// Times a single storage write, which is recorded once it [finishes](WriteTimer::finish).
+///
+/// Writes which fail are not recorded.
+pub(crate) struct WriteTimer {
+    method: &'static str,
+    started: Instant,
+}
+
+impl WriteTimer {
+    pub fn start(method: &'static str) -> Self {
+        Self {
+            method,
+            started: Instant::now(),
+        }
+    }
+
+    /// Records the call, the number of `rows` it wrote and their size in compressed `bytes`.
+    pub fn finish(self, rows: usize, bytes: usize) {
+        let method = self.method;
+        let elapsed = self.started.elapsed();
+
+        metrics::increment_counter!(METRIC_CALLS, "method" => method);
+        metrics::counter!(METRIC_ROWS, rows as u64, "method" => method);
+        metrics::counter!(METRIC_BYTES, bytes as u64, "method" => method);
+        metrics::histogram!(METRIC_DURATION, elapsed, "method" => method);
+
+        if elapsed.as_millis() >= u128::from(SLOW_QUERY_THRESHOLD_MS.load(Ordering::Relaxed)) {
+            tracing::warn!(method, rows, bytes, ?elapsed, "Slow storage call");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monitoring::metrics::test::{FakeRecorder, RecorderGuard};
+    use crate::storage::{test_utils, Storage};
+
+    #[test]
+    fn counters_increase_on_commit() {
+        let recorder = FakeRecorder::new(&[UPSERT_TRANSACTIONS, INSERT_EVENTS]);
+        let handle = recorder.handle();
+        let _guard = RecorderGuard::lock(recorder);
+
+        let storage = Storage::in_memory().unwrap();
+        test_utils::insert_test_data(&storage);
+
+        // Other tests write to storage concurrently, and are counted as well.
+        let calls = handle.get_counter_value(METRIC_CALLS, UPSERT_TRANSACTIONS);
+        assert!(calls >= test_utils::NUM_BLOCKS as u64);
+        let transactions = handle.get_counter_value(METRIC_ROWS, UPSERT_TRANSACTIONS);
+        assert!(transactions >= test_utils::NUM_TRANSACTIONS as u64);
+        let bytes = handle.get_counter_value(METRIC_BYTES, UPSERT_TRANSACTIONS);
+        assert!(bytes > 0);
+        let events = handle.get_counter_value(METRIC_ROWS, INSERT_EVENTS);
+        assert!(events >= test_utils::NUM_EVENTS as u64);
+    }
+}
@@ -1,3 +1,4 @@
+mod backfill;
 mod revision_0001;
 mod revision_0002;
 mod revision_0003;
@@ -19,6 +20,33 @@ mod revision_0018;
 mod revision_0019;
 mod revision_0020;
 mod revision_0021;
+mod revision_0022;
+mod revision_0023;
+mod revision_0024;
+mod revision_0025;
+mod revision_0026;
+mod revision_0027;
+mod revision_0028;
+mod revision_0029;
+mod revision_0030;
+mod revision_0031;
+mod revision_0032;
+mod revision_0033;
+mod revision_0034;
+mod revision_0035;
+mod revision_0036;
+mod revision_0037;
+mod revision_0038;
+mod revision_0039;
+mod revision_0040;
+mod revision_0041;
+mod revision_0042;
+mod revision_0043;
+mod revision_0044;
+mod revision_0045;
+mod revision_0046;
+mod revision_0047;
+mod revision_0048;
 
 type MigrationFn = fn(&rusqlite::Transaction<'_>) -> anyhow::Result<()>;
 
@@ -47,5 +75,41 @@ pub fn migrations() -> &'static [MigrationFn] {
         revision_0019::migrate,
         revision_0020::migrate,
         revision_0021::migrate,
+        revision_0022::migrate,
+        revision_0023::migrate,
+        revision_0024::migrate,
+        revision_0025::migrate,
+        revision_0026::migrate,
+        revision_0027::migrate,
+        revision_0028::migrate,
+        revision_0029::migrate,
+        revision_0030::migrate,
+        revision_0031::migrate,
+        revision_0032::migrate,
+        revision_0033::migrate,
+        revision_0034::migrate,
+        revision_0035::migrate,
+        revision_0036::migrate,
+        revision_0037::migrate,
+        revision_0038::migrate,
+        revision_0039::migrate,
+        revision_0040::migrate,
+        revision_0041::migrate,
+        revision_0042::migrate,
+        revision_0043::migrate,
+        revision_0044::migrate,
+        revision_0045::migrate,
+        revision_0046::migrate,
+        revision_0047::migrate,
+        revision_0048::migrate,
     ]
 }
+
+/// Applies the migrations preceding schema `revision`, so that the migration to `revision` can be
+/// tested against the schema it upgrades.
+#[cfg(test)]
+pub(crate) fn migrate_before(transaction: &rusqlite::Transaction<'_>, revision: usize) {
+    for migration in &migrations()[..revision - 1] {
+        migration(transaction).unwrap();
+    }
+}
@@ -0,0 +1,287 @@
+//! Shared by the migrations which backfill a new column or table from the compressed blobs of
+//! existing rows.
+
+use anyhow::Context;
+use rusqlite::{named_params, Transaction};
+
+use crate::core::StarknetBlockNumber;
+
+/// The maximum number of rows read into memory at once.
+const BATCH_SIZE: usize = 10_000;
+
+/// Decompresses and deserializes the `column` blob of every row in `starknet_transactions`, and
+/// passes it to `visit` along with the transaction's hash.
+///
+/// Rows are read in batches of increasing rowid, so that memory use is bounded no matter the size
+/// of the table. Rows where `column` is `NULL` are skipped. `task` describes the backfill in the
+/// progress logs.
+pub(crate) fn transactions<T, F>(
+    transaction: &Transaction<'_>,
+    column: &str,
+    task: &str,
+    mut visit: F,
+) -> anyhow::Result<()>
+where
+    T: serde::de::DeserializeOwned,
+    F: FnMut(&[u8], T) -> anyhow::Result<()>,
+{
+    let todo: usize = transaction
+        .query_row("SELECT count(1) FROM starknet_transactions", [], |r| {
+            r.get(0)
+        })
+        .context("Count rows in starknet transactions table")?;
+
+    if todo == 0 {
+        return Ok(());
+    }
+
+    tracing::info!(num_transactions=%todo, "{task}, this may take a while.");
+
+    let mut stmt = transaction
+        .prepare(&format!(
+            "SELECT rowid, hash, {column} FROM starknet_transactions
+            WHERE rowid > :last_rowid ORDER BY rowid LIMIT :batch_size"
+        ))
+        .context("Prepare transaction query")?;
+
+    let mut progress = Progress::new(task, todo);
+    let mut last_rowid = i64::MIN;
+
+    loop {
+        let batch = stmt
+            .query_map(
+                named_params! {":last_rowid": last_rowid, ":batch_size": BATCH_SIZE},
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, Vec<u8>>(1)?,
+                        row.get::<_, Option<Vec<u8>>>(2)?,
+                    ))
+                },
+            )
+            .context("Query transactions")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Read transactions")?;
+
+        last_rowid = match batch.last() {
+            Some((rowid, _, _)) => *rowid,
+            None => return Ok(()),
+        };
+
+        for (_, hash, blob) in &batch {
+            let blob = match blob {
+                Some(blob) => blob,
+                None => continue,
+            };
+            visit(hash, decode(blob, column)?)?;
+        }
+
+        progress.advance(batch.len());
+    }
+}
+
+/// Decompresses and deserializes every row in `starknet_state_updates`, and passes it to `visit`
+/// along with its block's number and hash.
+///
+/// State updates are visited in block order, and read in batches so that memory use is bounded no
+/// matter the size of the table. `task` describes the backfill in the progress logs.
+pub(crate) fn state_updates<T, F>(
+    transaction: &Transaction<'_>,
+    task: &str,
+    mut visit: F,
+) -> anyhow::Result<()>
+where
+    T: serde::de::DeserializeOwned,
+    F: FnMut(StarknetBlockNumber, &[u8], T) -> anyhow::Result<()>,
+{
+    let todo: usize = transaction
+        .query_row("SELECT count(1) FROM starknet_state_updates", [], |r| {
+            r.get(0)
+        })
+        .context("Count rows in starknet state updates table")?;
+
+    if todo == 0 {
+        return Ok(());
+    }
+
+    tracing::info!(num_state_updates=%todo, "{task}, this may take a while.");
+
+    let mut stmt = transaction
+        .prepare(
+            r"SELECT starknet_blocks.number, block_hash, data FROM starknet_state_updates
+    JOIN starknet_blocks ON starknet_blocks.hash = starknet_state_updates.block_hash
+    WHERE starknet_blocks.number >= :next_number
+    ORDER BY starknet_blocks.number LIMIT :batch_size",
+        )
+        .context("Prepare state update query")?;
+
+    let mut progress = Progress::new(task, todo);
+    let mut next_number = StarknetBlockNumber::GENESIS;
+
+    loop {
+        let batch = stmt
+            .query_map(
+                named_params! {":next_number": next_number, ":batch_size": BATCH_SIZE},
+                |row| {
+                    Ok((
+                        row.get::<_, StarknetBlockNumber>(0)?,
+                        row.get::<_, Vec<u8>>(1)?,
+                        row.get::<_, Vec<u8>>(2)?,
+                    ))
+                },
+            )
+            .context("Query state updates")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Read state updates")?;
+
+        next_number = match batch.last() {
+            Some((number, _, _)) => *number + 1,
+            None => return Ok(()),
+        };
+
+        for (number, block_hash, data) in &batch {
+            visit(*number, block_hash, decode(data, "state update")?)?;
+        }
+
+        progress.advance(batch.len());
+    }
+}
+
+fn decode<T: serde::de::DeserializeOwned>(blob: &[u8], what: &str) -> anyhow::Result<T> {
+    let blob = zstd::decode_all(blob).with_context(|| format!("Decompress {what}"))?;
+    serde_json::from_slice(&blob).with_context(|| format!("Deserializing {what}"))
+}
+
+/// Logs the completion of a backfill in steps of roughly 10%.
+struct Progress<'a> {
+    task: &'a str,
+    todo: usize,
+    done: usize,
+    next_log: usize,
+}
+
+impl<'a> Progress<'a> {
+    fn new(task: &'a str, todo: usize) -> Self {
+        Self {
+            task,
+            todo,
+            done: 0,
+            next_log: Self::step(todo),
+        }
+    }
+
+    fn step(todo: usize) -> usize {
+        (todo / 10).max(BATCH_SIZE)
+    }
+
+    fn advance(&mut self, rows: usize) {
+        self.done += rows;
+        if self.done >= self.next_log && self.done < self.todo {
+            tracing::info!(
+                "{} {:.1}% complete",
+                self.task,
+                (100.0 * self.done as f64 / self.todo as f64)
+            );
+            self.next_log = self.done + Self::step(self.todo);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::schema;
+    use rusqlite::Connection;
+
+    #[test]
+    fn transactions_are_visited_across_batches() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        for migration in schema::migrations() {
+            migration(&transaction).unwrap();
+        }
+
+        let count = super::BATCH_SIZE + 2;
+        let receipt = zstd::encode_all(r#"{"value":1}"#.as_bytes(), 10).unwrap();
+        for idx in 0..count {
+            // The last row has no receipt and must be skipped.
+            let receipt = (idx + 1 != count).then(|| receipt.clone());
+            transaction
+                .execute(
+                    "INSERT INTO starknet_transactions (hash, idx, block_hash, tx, receipt) VALUES (?, ?, x'00', x'', ?)",
+                    rusqlite::params![idx.to_be_bytes().to_vec(), idx, receipt],
+                )
+                .unwrap();
+        }
+
+        #[derive(serde::Deserialize)]
+        struct Receipt {
+            value: u8,
+        }
+
+        let mut visited = Vec::new();
+        super::transactions(
+            &transaction,
+            "receipt",
+            "Testing",
+            |hash, receipt: Receipt| {
+                assert_eq!(receipt.value, 1);
+                visited.push(usize::from_be_bytes(hash.try_into().unwrap()));
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(visited, (0..count - 1).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn state_updates_are_visited_in_block_order_across_batches() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        for migration in schema::migrations() {
+            migration(&transaction).unwrap();
+        }
+
+        #[derive(serde::Deserialize)]
+        struct StateUpdate {
+            value: u8,
+        }
+
+        let count = super::BATCH_SIZE + 2;
+        let data = zstd::encode_all(r#"{"value":1}"#.as_bytes(), 10).unwrap();
+        // Inserted in reverse so that the visiting order doesn't follow the rowids.
+        for number in (0..count).rev() {
+            let hash = number.to_be_bytes().to_vec();
+            transaction
+                .execute(
+                    r"INSERT INTO starknet_blocks (number, hash, root, timestamp, gas_price, sequencer_address)
+                    VALUES (?, ?, x'00', 0, x'00', x'00')",
+                    rusqlite::params![number, hash],
+                )
+                .unwrap();
+            transaction
+                .execute(
+                    "INSERT INTO starknet_state_updates (block_hash, data) VALUES (?, ?)",
+                    rusqlite::params![hash, data],
+                )
+                .unwrap();
+        }
+
+        let mut visited = Vec::new();
+        super::state_updates(
+            &transaction,
+            "Testing",
+            |number, hash, state_update: StateUpdate| {
+                assert_eq!(state_update.value, 1);
+                assert_eq!(hash, (number.get() as usize).to_be_bytes());
+                visited.push(number.get() as usize);
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(visited, (0..count).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,19 @@
+use anyhow::Context;
+
+/// Replaces the `starknet_events(from_address)` index with one on `(from_address, block_number)`.
+///
+/// Event queries which filter on a contract address (and optionally a block range) can now be
+/// served from this index alone, instead of scanning all of the contract's events and filtering
+/// or sorting by block number afterwards. The old index is a prefix of the new one and is dropped.
+pub(crate) fn migrate(tx: &rusqlite::Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute(
+        "CREATE INDEX starknet_events_from_address_block_number ON starknet_events(from_address, block_number)",
+        [],
+    )
+    .context("Creating 'starknet_events_from_address_block_number' index")?;
+
+    tx.execute("DROP INDEX starknet_events_from_address", [])
+        .context("Dropping 'starknet_events_from_address' index")?;
+
+    Ok(())
+}
@@ -0,0 +1,13 @@
+use anyhow::Context;
+
+/// Adds an index on `starknet_events(transaction_hash)` so that a transaction's events can be
+/// looked up without scanning the whole table.
+pub(crate) fn migrate(tx: &rusqlite::Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute(
+        "CREATE INDEX starknet_events_transaction_hash ON starknet_events(transaction_hash)",
+        [],
+    )
+    .context("Creating 'starknet_events_transaction_hash' index")?;
+
+    Ok(())
+}
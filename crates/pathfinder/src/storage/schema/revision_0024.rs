@@ -0,0 +1,23 @@
+use anyhow::Context;
+
+/// Adds a `parent_hash` column to `starknet_blocks` and backfills it from the preceding block.
+///
+/// The genesis block keeps the zero default.
+pub(crate) fn migrate(tx: &rusqlite::Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute(
+        r"ALTER TABLE starknet_blocks ADD COLUMN parent_hash
+BLOB NOT NULL DEFAULT X'0000000000000000000000000000000000000000000000000000000000000000'",
+        [],
+    )
+    .context("Adding 'parent_hash' column to 'starknet_blocks'")?;
+
+    tx.execute(
+        r"UPDATE starknet_blocks SET parent_hash = parent.hash
+    FROM starknet_blocks AS parent
+    WHERE parent.number = starknet_blocks.number - 1",
+        [],
+    )
+    .context("Backfilling 'parent_hash' in 'starknet_blocks'")?;
+
+    Ok(())
+}
@@ -0,0 +1,25 @@
+use anyhow::Context;
+
+/// Adds a `transaction_count` column to `starknet_blocks`, so that blocks with missing transactions
+/// can be detected.
+///
+/// Existing blocks are backfilled from their stored transactions, so transactions lost before
+/// this migration are only detected through gaps in their indices.
+pub(crate) fn migrate(tx: &rusqlite::Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute(
+        "ALTER TABLE starknet_blocks ADD COLUMN transaction_count INTEGER NOT NULL DEFAULT 0",
+        [],
+    )
+    .context("Adding 'transaction_count' column to 'starknet_blocks'")?;
+
+    tx.execute(
+        r"UPDATE starknet_blocks SET transaction_count = (
+    SELECT COUNT(1) FROM starknet_transactions
+    WHERE starknet_transactions.block_hash = starknet_blocks.hash
+)",
+        [],
+    )
+    .context("Backfilling 'transaction_count' in 'starknet_blocks'")?;
+
+    Ok(())
+}
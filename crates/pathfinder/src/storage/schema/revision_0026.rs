@@ -0,0 +1,137 @@
+use anyhow::Context;
+use rusqlite::{named_params, Transaction};
+
+/// Adds the `deployed_contracts` table, which indexes the contracts deployed by each state update,
+/// and backfills it from the stored state updates.
+pub(crate) fn migrate(transaction: &Transaction<'_>) -> anyhow::Result<()> {
+    transaction
+        .execute_batch(
+            r"CREATE TABLE deployed_contracts (
+    contract_address BLOB PRIMARY KEY NOT NULL,
+    class_hash BLOB NOT NULL,
+    block_hash BLOB NOT NULL REFERENCES starknet_state_updates(block_hash) ON DELETE CASCADE
+);
+
+CREATE INDEX deployed_contracts_block_hash ON deployed_contracts(block_hash);",
+        )
+        .context("Creating deployed_contracts table")?;
+
+    let mut insert = transaction
+        .prepare(
+            r"INSERT OR IGNORE INTO deployed_contracts ( contract_address,  class_hash,  block_hash)
+                                                VALUES (:contract_address, :class_hash, :block_hash)",
+        )
+        .context("Prepare insert statement")?;
+
+    // State updates are visited in block order, so that only a contract's first deployment is kept.
+    super::backfill::state_updates(
+        transaction,
+        "Indexing deployed contracts of state updates",
+        |_, block_hash, state_update: LightStateUpdate| {
+            for contract in state_update.state_diff.deployed_contracts {
+                insert
+                    .execute(named_params![
+                        ":contract_address": contract.address,
+                        ":class_hash": contract.class_hash,
+                        ":block_hash": block_hash,
+                    ])
+                    .context("Insert deployed contract")?;
+            }
+            Ok(())
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Real state update json has a bunch of fields which we don't need
+#[derive(serde::Deserialize)]
+struct LightStateUpdate {
+    state_diff: LightStateDiff,
+}
+
+#[derive(serde::Deserialize)]
+struct LightStateDiff {
+    deployed_contracts: Vec<LightDeployedContract>,
+}
+
+#[derive(serde::Deserialize)]
+struct LightDeployedContract {
+    address: crate::core::ContractAddress,
+    class_hash: crate::core::ClassHash,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{ClassHash, ContractAddress, StarknetBlockHash};
+    use crate::starkhash;
+    use crate::storage::schema;
+    use rusqlite::Connection;
+
+    const REVISION: usize = 26;
+
+    #[test]
+    fn backfill() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_before(&transaction, REVISION);
+
+        // The same contract is "deployed" in both blocks, only the first deployment is kept.
+        let insert_block = |number: u64, hash_hex: &str, deployed: &str| {
+            let hash = StarknetBlockHash(stark_hash::StarkHash::from_hex_str(hash_hex).unwrap());
+            transaction
+                .execute(
+                    r"INSERT INTO starknet_blocks (number, hash, parent_hash, root, timestamp, gas_price, sequencer_address)
+                    VALUES (?, ?, x'00', x'00', 0, x'00', x'00')",
+                    rusqlite::params![number, hash],
+                )
+                .unwrap();
+            let data = format!(
+                r#"{{"block_hash":"{hash}","new_root":"0x1","old_root":"0x0","state_diff":{{"storage_diffs":[],"declared_contracts":[],"deployed_contracts":{deployed},"nonces":[]}}}}"#,
+                hash = hash_hex,
+            );
+            let data = zstd::encode_all(data.as_bytes(), 10).unwrap();
+            transaction
+                .execute(
+                    "INSERT INTO starknet_state_updates (block_hash, data) VALUES (?, ?)",
+                    rusqlite::params![hash, data],
+                )
+                .unwrap();
+        };
+        insert_block(
+            0,
+            "0xb0",
+            r#"[{"address":"0xa1","class_hash":"0xc1"},{"address":"0xa2","class_hash":"0xc2"}]"#,
+        );
+        insert_block(1, "0xb1", r#"[{"address":"0xa1","class_hash":"0xc3"}]"#);
+
+        super::migrate(&transaction).unwrap();
+
+        let mut stmt = transaction
+            .prepare("SELECT contract_address, class_hash, block_hash FROM deployed_contracts ORDER BY contract_address")
+            .unwrap();
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<Vec<(ContractAddress, ClassHash, StarknetBlockHash)>, _>>()
+            .unwrap();
+
+        let block0 = StarknetBlockHash(starkhash!("b0"));
+        assert_eq!(
+            rows,
+            vec![
+                (
+                    ContractAddress::new_or_panic(starkhash!("a1")),
+                    ClassHash(starkhash!("c1")),
+                    block0
+                ),
+                (
+                    ContractAddress::new_or_panic(starkhash!("a2")),
+                    ClassHash(starkhash!("c2")),
+                    block0
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use anyhow::Context;
+use rusqlite::{named_params, OptionalExtension, Transaction};
+
+/// Adds a `starknet_events.first_key` column holding each event's first key, which by convention
+/// is the event's selector, along with an index on `(first_key, block_number)`.
+///
+/// The keys are also indexed by the `starknet_events_keys` full-text index, but that cannot
+/// tell the position of a key apart.
+pub(crate) fn migrate(tx: &Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute("ALTER TABLE starknet_events ADD COLUMN first_key BLOB", [])
+        .context("Adding 'first_key' column")?;
+
+    let row_count: usize = tx
+        .query_row("SELECT count(1) FROM starknet_events", [], |r| r.get(0))
+        .context("Count rows in starknet_events table")?;
+
+    if row_count > 0 {
+        tracing::info!(
+            %row_count,
+            "Backfilling the first key of events, this may take a while",
+        );
+
+        // The update trigger rebuilds the full-text index entry of each updated row, which is
+        // pointless here as the keys themselves don't change.
+        let update_trigger: Option<String> = tx
+            .query_row(
+                "SELECT sql FROM sqlite_schema WHERE type = 'trigger' AND name = 'starknet_events_au'",
+                [],
+                |r| r.get(0),
+            )
+            .optional()
+            .context("Reading 'starknet_events_au' trigger")?;
+        tx.execute("DROP TRIGGER IF EXISTS starknet_events_au", [])
+            .context("Dropping 'starknet_events_au' trigger")?;
+
+        backfill(tx)?;
+
+        if let Some(update_trigger) = update_trigger {
+            tx.execute(&update_trigger, [])
+                .context("Re-creating 'starknet_events_au' trigger")?;
+        }
+    }
+
+    tx.execute(
+        "CREATE INDEX starknet_events_first_key_block_number ON starknet_events(first_key, block_number)",
+        [],
+    )
+    .context("Creating 'starknet_events_first_key_block_number' index")?;
+
+    Ok(())
+}
+
+fn backfill(tx: &Transaction<'_>) -> anyhow::Result<()> {
+    let mut query = tx
+        .prepare("SELECT id, keys FROM starknet_events")
+        .context("Preparing events query")?;
+    let mut update = tx
+        .prepare("UPDATE starknet_events SET first_key = :first_key WHERE id = :id")
+        .context("Preparing update statement")?;
+
+    let mut rows = query.query([]).context("Executing events query")?;
+    let mut first_key = [0u8; 32];
+
+    while let Some(row) = rows.next().context("Fetching next event")? {
+        let id: i64 = row.get_unwrap("id");
+        let keys = row.get_ref_unwrap("keys").as_str_or_null()?;
+
+        // Keys are stored as space separated base64 strings.
+        let first = match keys.and_then(|keys| keys.split(' ').next()) {
+            Some(first) if !first.is_empty() => first,
+            _ => continue,
+        };
+
+        let used = base64::decode_config_slice(first, base64::STANDARD, &mut first_key)
+            .context("Decoding event key")?;
+        // Keys are 32 bytes wide, but shorter slices are tolerated in the same way as when
+        // reading events.
+        let first_key = stark_hash::StarkHash::from_be_slice(&first_key[..used])
+            .map_err(|e| anyhow::anyhow!("Parsing event key: {e}"))?;
+
+        update
+            .execute(named_params! {
+                ":first_key": first_key.as_be_bytes(),
+                ":id": id,
+            })
+            .context("Updating first key")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::EventKey;
+    use crate::starkhash;
+    use crate::storage::schema;
+    use rusqlite::Connection;
+
+    const REVISION: usize = 27;
+
+    #[test]
+    fn backfill() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_before(&transaction, REVISION);
+
+        transaction
+            .execute(
+                r"INSERT INTO starknet_blocks (number, hash, parent_hash, root, timestamp, gas_price, sequencer_address)
+                VALUES (0, x'00', x'00', x'00', 0, x'00', x'00')",
+                [],
+            )
+            .unwrap();
+        transaction
+            .execute(
+                "INSERT INTO canonical_blocks (number, hash) VALUES (0, x'00')",
+                [],
+            )
+            .unwrap();
+
+        let keys = [
+            // 0x1, 0x2
+            "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAE= AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAI=",
+            // 0x2
+            "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAI=",
+            "",
+        ];
+        for (idx, keys) in keys.iter().enumerate() {
+            transaction
+                .execute(
+                    r"INSERT INTO starknet_events (block_number, idx, transaction_hash, from_address, keys, data)
+                    VALUES (0, ?, x'00', x'00', ?, x'')",
+                    rusqlite::params![idx, keys],
+                )
+                .unwrap();
+        }
+
+        super::migrate(&transaction).unwrap();
+
+        let first_keys = transaction
+            .prepare("SELECT first_key FROM starknet_events ORDER BY idx")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<Vec<Option<EventKey>>, _>>()
+            .unwrap();
+        assert_eq!(
+            first_keys,
+            vec![
+                Some(EventKey(starkhash!("01"))),
+                Some(EventKey(starkhash!("02"))),
+                None
+            ]
+        );
+
+        // The full-text index is untouched and the update trigger is restored.
+        let matches: usize = transaction
+            .query_row(
+                r#"SELECT count(1) FROM starknet_events_keys WHERE keys MATCH '"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAI="'"#,
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(matches, 2);
+
+        let trigger_count: usize = transaction
+            .query_row(
+                "SELECT count(1) FROM sqlite_schema WHERE type = 'trigger' AND name = 'starknet_events_au'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(trigger_count, 1);
+    }
+}
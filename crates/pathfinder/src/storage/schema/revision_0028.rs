@@ -0,0 +1,120 @@
+use anyhow::Context;
+use rusqlite::{named_params, Transaction};
+
+/// Adds the `l1_handler_origins` table, which links L1 handler transactions to the nonce of the
+/// L1 to L2 message they consumed, and backfills it from the stored transaction receipts.
+///
+/// The Ethereum transaction which sent the message is not known at this point, and is left empty.
+pub(crate) fn migrate(transaction: &Transaction<'_>) -> anyhow::Result<()> {
+    transaction
+        .execute_batch(
+            r"CREATE TABLE l1_handler_origins (
+    starknet_transaction_hash BLOB PRIMARY KEY NOT NULL,
+    message_nonce BLOB NOT NULL,
+    ethereum_transaction_hash BLOB
+);
+
+CREATE INDEX l1_handler_origins_message_nonce ON l1_handler_origins(message_nonce);",
+        )
+        .context("Creating l1_handler_origins table")?;
+
+    let mut insert = transaction
+        .prepare(
+            r"INSERT INTO l1_handler_origins ( starknet_transaction_hash,  message_nonce)
+                                      VALUES (:starknet_transaction_hash, :message_nonce)",
+        )
+        .context("Prepare insert statement")?;
+
+    super::backfill::transactions(
+        transaction,
+        "receipt",
+        "Indexing L1 handler origins from transaction receipts",
+        |hash, receipt: LightReceipt| {
+            let nonce = match receipt
+                .l1_to_l2_consumed_message
+                .and_then(|message| message.nonce)
+            {
+                Some(nonce) => nonce,
+                None => return Ok(()),
+            };
+
+            insert
+                .execute(named_params![
+                    ":starknet_transaction_hash": hash,
+                    ":message_nonce": nonce,
+                ])
+                .context("Insert L1 handler origin")?;
+            Ok(())
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Real receipt json has a bunch of fields which we don't need
+#[derive(serde::Deserialize)]
+struct LightReceipt {
+    l1_to_l2_consumed_message: Option<LightMessage>,
+}
+
+#[derive(serde::Deserialize)]
+struct LightMessage {
+    #[serde(default)]
+    nonce: Option<crate::core::L1ToL2MessageNonce>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{L1ToL2MessageNonce, StarknetTransactionHash};
+    use crate::starkhash;
+    use crate::storage::schema;
+    use rusqlite::Connection;
+
+    const REVISION: usize = 28;
+
+    #[test]
+    fn backfill() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_before(&transaction, REVISION);
+
+        let receipts = [
+            // Consumed a message with a nonce.
+            r#"{"l1_to_l2_consumed_message":{"from_address":"0x1","payload":[],"selector":"0x2","to_address":"0x3","nonce":"0x7"}}"#,
+            // Consumed a message from before nonces existed.
+            r#"{"l1_to_l2_consumed_message":{"from_address":"0x1","payload":[],"selector":"0x2","to_address":"0x3"}}"#,
+            // Not an L1 handler.
+            r#"{"l1_to_l2_consumed_message":null}"#,
+        ];
+        for (idx, receipt) in receipts.iter().enumerate() {
+            let receipt = zstd::encode_all(receipt.as_bytes(), 10).unwrap();
+            transaction
+                .execute(
+                    "INSERT INTO starknet_transactions (hash, idx, block_hash, tx, receipt) VALUES (?, ?, x'00', x'', ?)",
+                    rusqlite::params![vec![idx as u8 + 1], idx, receipt],
+                )
+                .unwrap();
+        }
+
+        super::migrate(&transaction).unwrap();
+
+        let mut stmt = transaction
+            .prepare("SELECT starknet_transaction_hash, message_nonce, ethereum_transaction_hash FROM l1_handler_origins")
+            .unwrap();
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<Vec<(StarknetTransactionHash, L1ToL2MessageNonce, Option<Vec<u8>>)>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            rows,
+            vec![(
+                StarknetTransactionHash(starkhash!("01")),
+                L1ToL2MessageNonce(starkhash!("07")),
+                None
+            )]
+        );
+    }
+}
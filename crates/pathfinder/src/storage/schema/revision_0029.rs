@@ -0,0 +1,18 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+/// Adds the `raw_blocks` table, which optionally archives the compressed JSON of blocks exactly as
+/// the sequencer served them.
+pub(crate) fn migrate(transaction: &Transaction<'_>) -> anyhow::Result<()> {
+    transaction
+        .execute_batch(
+            r"CREATE TABLE raw_blocks (
+    number INTEGER PRIMARY KEY NOT NULL,
+    hash BLOB NOT NULL,
+    data BLOB NOT NULL
+);
+
+CREATE INDEX raw_blocks_hash ON raw_blocks(hash);",
+        )
+        .context("Creating raw_blocks table")
+}
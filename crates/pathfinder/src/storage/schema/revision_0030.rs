@@ -0,0 +1,98 @@
+use anyhow::Context;
+use rusqlite::{named_params, Transaction};
+
+/// Adds an `actual_fee` column to `starknet_transactions` so that fees can be aggregated without
+/// decompressing the receipts, and backfills it from the stored receipts.
+///
+/// Fees are stored as 16 byte big-endian blobs, which SQLite orders numerically. Receipts without
+/// a fee are left `NULL`.
+pub(crate) fn migrate(transaction: &Transaction<'_>) -> anyhow::Result<()> {
+    transaction
+        .execute(
+            "ALTER TABLE starknet_transactions ADD COLUMN actual_fee BLOB",
+            [],
+        )
+        .context("Adding 'actual_fee' column to 'starknet_transactions'")?;
+
+    let mut update = transaction
+        .prepare("UPDATE starknet_transactions SET actual_fee = :actual_fee WHERE hash = :hash")
+        .context("Prepare update statement")?;
+
+    super::backfill::transactions(
+        transaction,
+        "receipt",
+        "Extracting fees from transaction receipts",
+        |hash, receipt: LightReceipt| {
+            let fee = match receipt.actual_fee {
+                Some(fee) => fee,
+                None => return Ok(()),
+            };
+
+            update
+                .execute(named_params![
+                    ":hash": hash,
+                    ":actual_fee": fee.0.as_bytes(),
+                ])
+                .context("Update transaction fee")?;
+            Ok(())
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Real receipt json has a bunch of fields which we don't need
+#[serde_with::serde_as]
+#[derive(serde::Deserialize)]
+struct LightReceipt {
+    #[serde_as(as = "Option<crate::rpc::serde::FeeAsHexStr>")]
+    #[serde(default)]
+    actual_fee: Option<crate::core::Fee>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::schema;
+    use rusqlite::Connection;
+
+    const REVISION: usize = 30;
+
+    #[test]
+    fn backfill() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_before(&transaction, REVISION);
+
+        let receipts = [
+            r#"{"actual_fee":"0x1234"}"#,
+            // Receipts from before fees were charged.
+            r#"{"actual_fee":null}"#,
+            r#"{}"#,
+        ];
+        for (idx, receipt) in receipts.iter().enumerate() {
+            let receipt = zstd::encode_all(receipt.as_bytes(), 10).unwrap();
+            transaction
+                .execute(
+                    "INSERT INTO starknet_transactions (hash, idx, block_hash, tx, receipt) VALUES (?, ?, x'00', x'', ?)",
+                    rusqlite::params![vec![idx as u8 + 1], idx, receipt],
+                )
+                .unwrap();
+        }
+
+        super::migrate(&transaction).unwrap();
+
+        let mut stmt = transaction
+            .prepare("SELECT actual_fee FROM starknet_transactions ORDER BY idx")
+            .unwrap();
+        let fees = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<Vec<Option<Vec<u8>>>, _>>()
+            .unwrap();
+
+        let mut expected = [0u8; 16];
+        expected[14..].copy_from_slice(&[0x12, 0x34]);
+        assert_eq!(fees, vec![Some(expected.to_vec()), None, None]);
+    }
+}
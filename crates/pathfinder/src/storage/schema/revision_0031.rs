@@ -0,0 +1,18 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+/// Adds the `reorg_history` table, which keeps a bounded log of the reorgs processed by sync.
+pub(crate) fn migrate(transaction: &Transaction<'_>) -> anyhow::Result<()> {
+    transaction
+        .execute_batch(
+            r"CREATE TABLE reorg_history (
+    id INTEGER PRIMARY KEY NOT NULL,
+    tail INTEGER NOT NULL,
+    old_head INTEGER NOT NULL,
+    old_head_hash BLOB,
+    timestamp INTEGER NOT NULL,
+    source TEXT NOT NULL
+);",
+        )
+        .context("Creating reorg_history table")
+}
@@ -0,0 +1,80 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+/// Adds the `contract_class_index` table, which tracks the class of each contract over time, and
+/// backfills it from the `deployed_contracts` index.
+pub(crate) fn migrate(transaction: &Transaction<'_>) -> anyhow::Result<()> {
+    transaction
+        .execute_batch(
+            r"CREATE TABLE contract_class_index (
+    contract_address BLOB NOT NULL,
+    class_hash BLOB NOT NULL,
+    block_number INTEGER NOT NULL,
+    PRIMARY KEY (contract_address, block_number)
+);
+
+CREATE INDEX contract_class_index_class_hash ON contract_class_index(class_hash);
+CREATE INDEX contract_class_index_block_number ON contract_class_index(block_number);",
+        )
+        .context("Creating contract_class_index table")?;
+
+    transaction
+        .execute(
+            r"INSERT INTO contract_class_index (contract_address, class_hash, block_number)
+    SELECT deployed_contracts.contract_address, deployed_contracts.class_hash, starknet_blocks.number
+    FROM deployed_contracts
+    JOIN starknet_blocks ON starknet_blocks.hash = deployed_contracts.block_hash",
+            [],
+        )
+        .context("Indexing deployed contracts")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::schema;
+    use rusqlite::Connection;
+
+    const REVISION: usize = 32;
+
+    #[test]
+    fn backfills_deployed_contracts() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_before(&transaction, REVISION);
+
+        transaction
+            .execute_batch(
+                r"INSERT INTO starknet_blocks (hash, number, root, timestamp, gas_price, sequencer_address)
+    VALUES (x'01', 0, x'00', 0, x'00', x'00'), (x'02', 1, x'00', 0, x'00', x'00');
+INSERT INTO starknet_state_updates (block_hash, data) VALUES (x'01', x''), (x'02', x'');
+INSERT INTO deployed_contracts (contract_address, class_hash, block_hash)
+    VALUES (x'a0', x'c0', x'01'), (x'a1', x'c0', x'02'), (x'a2', x'c1', x'02');",
+            )
+            .unwrap();
+
+        super::migrate(&transaction).unwrap();
+
+        let mut stmt = transaction
+            .prepare(
+                "SELECT contract_address, class_hash, block_number FROM contract_class_index ORDER BY contract_address",
+            )
+            .unwrap();
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<Vec<(Vec<u8>, Vec<u8>, u64)>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            rows,
+            vec![
+                (vec![0xa0], vec![0xc0], 0),
+                (vec![0xa1], vec![0xc0], 1),
+                (vec![0xa2], vec![0xc1], 1),
+            ]
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::Context;
+use rusqlite::{named_params, Transaction};
+
+use crate::sequencer::reply::transaction::ExecutionStatus;
+
+/// Adds an `execution_status` column to `starknet_transactions` so that transactions can be
+/// filtered by status without decompressing the receipts, and backfills it from the stored
+/// receipts.
+///
+/// The status is `0` for succeeded and `1` for reverted transactions.
+pub(crate) fn migrate(transaction: &Transaction<'_>) -> anyhow::Result<()> {
+    transaction
+        .execute(
+            "ALTER TABLE starknet_transactions ADD COLUMN execution_status INTEGER NOT NULL DEFAULT 0",
+            [],
+        )
+        .context("Adding 'execution_status' column to 'starknet_transactions'")?;
+
+    let mut update = transaction
+        .prepare("UPDATE starknet_transactions SET execution_status = 1 WHERE hash = :hash")
+        .context("Prepare update statement")?;
+
+    super::backfill::transactions(
+        transaction,
+        "receipt",
+        "Extracting execution status from transaction receipts",
+        |hash, receipt: LightReceipt| {
+            // Succeeded is the column's default.
+            if receipt.execution_status == ExecutionStatus::Reverted {
+                update
+                    .execute(named_params![":hash": hash])
+                    .context("Update transaction execution status")?;
+            }
+            Ok(())
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Real receipt json has a bunch of fields which we don't need
+#[derive(serde::Deserialize)]
+struct LightReceipt {
+    #[serde(default)]
+    execution_status: ExecutionStatus,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::schema;
+    use rusqlite::Connection;
+
+    const REVISION: usize = 33;
+
+    #[test]
+    fn backfill() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_before(&transaction, REVISION);
+
+        let receipts = [
+            r#"{"execution_status":"SUCCEEDED"}"#,
+            r#"{"execution_status":"REVERTED"}"#,
+            // Receipts from before transactions could be reverted.
+            r#"{}"#,
+        ];
+        for (idx, receipt) in receipts.iter().enumerate() {
+            let receipt = zstd::encode_all(receipt.as_bytes(), 10).unwrap();
+            transaction
+                .execute(
+                    "INSERT INTO starknet_transactions (hash, idx, block_hash, tx, receipt) VALUES (?, ?, x'00', x'', ?)",
+                    rusqlite::params![vec![idx as u8 + 1], idx, receipt],
+                )
+                .unwrap();
+        }
+
+        super::migrate(&transaction).unwrap();
+
+        let mut stmt = transaction
+            .prepare("SELECT execution_status FROM starknet_transactions ORDER BY idx")
+            .unwrap();
+        let statuses = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<Vec<u8>, _>>()
+            .unwrap();
+
+        assert_eq!(statuses, vec![0, 1, 0]);
+    }
+}
@@ -0,0 +1,13 @@
+use anyhow::Context;
+
+/// Adds an index on `starknet_blocks(timestamp)` so that blocks can be looked up by time without
+/// scanning the whole table.
+pub(crate) fn migrate(tx: &rusqlite::Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute(
+        "CREATE INDEX starknet_blocks_timestamp ON starknet_blocks(timestamp)",
+        [],
+    )
+    .context("Creating 'starknet_blocks_timestamp' index")?;
+
+    Ok(())
+}
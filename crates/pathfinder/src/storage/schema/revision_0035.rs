@@ -0,0 +1,105 @@
+use anyhow::Context;
+use rusqlite::{named_params, Transaction};
+
+/// Adds `n_steps` and `n_memory_holes` columns to `starknet_transactions` so that execution
+/// resources can be aggregated without decompressing the receipts, and backfills them from the
+/// stored receipts.
+///
+/// Receipts without execution resources are left `NULL`.
+pub(crate) fn migrate(transaction: &Transaction<'_>) -> anyhow::Result<()> {
+    transaction
+        .execute_batch(
+            r"ALTER TABLE starknet_transactions ADD COLUMN n_steps INTEGER;
+ALTER TABLE starknet_transactions ADD COLUMN n_memory_holes INTEGER;",
+        )
+        .context("Adding execution resource columns to 'starknet_transactions'")?;
+
+    let mut update = transaction
+        .prepare(
+            "UPDATE starknet_transactions SET n_steps = :n_steps, n_memory_holes = :n_memory_holes WHERE hash = :hash",
+        )
+        .context("Prepare update statement")?;
+
+    super::backfill::transactions(
+        transaction,
+        "receipt",
+        "Extracting execution resources from transaction receipts",
+        |hash, receipt: LightReceipt| {
+            let resources = match receipt.execution_resources {
+                Some(resources) => resources,
+                None => return Ok(()),
+            };
+
+            update
+                .execute(named_params![
+                    ":hash": hash,
+                    ":n_steps": resources.n_steps,
+                    ":n_memory_holes": resources.n_memory_holes,
+                ])
+                .context("Update transaction execution resources")?;
+            Ok(())
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Real receipt json has a bunch of fields which we don't need
+#[derive(serde::Deserialize)]
+struct LightReceipt {
+    #[serde(default)]
+    execution_resources: Option<LightExecutionResources>,
+}
+
+#[derive(serde::Deserialize)]
+struct LightExecutionResources {
+    n_steps: u64,
+    n_memory_holes: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::schema;
+    use rusqlite::Connection;
+
+    const REVISION: usize = 35;
+
+    #[test]
+    fn backfill() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_before(&transaction, REVISION);
+
+        let receipts = [
+            r#"{"execution_resources":{"builtin_instance_counter":{},"n_steps":12,"n_memory_holes":3}}"#,
+            r#"{"execution_resources":null}"#,
+            r#"{}"#,
+        ];
+        for (idx, receipt) in receipts.iter().enumerate() {
+            let receipt = zstd::encode_all(receipt.as_bytes(), 10).unwrap();
+            transaction
+                .execute(
+                    "INSERT INTO starknet_transactions (hash, idx, block_hash, tx, receipt) VALUES (?, ?, x'00', x'', ?)",
+                    rusqlite::params![vec![idx as u8 + 1], idx, receipt],
+                )
+                .unwrap();
+        }
+
+        super::migrate(&transaction).unwrap();
+
+        let mut stmt = transaction
+            .prepare("SELECT n_steps, n_memory_holes FROM starknet_transactions ORDER BY idx")
+            .unwrap();
+        let resources = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<Vec<(Option<u64>, Option<u64>)>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            resources,
+            vec![(Some(12), Some(3)), (None, None), (None, None)]
+        );
+    }
+}
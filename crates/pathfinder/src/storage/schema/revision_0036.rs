@@ -0,0 +1,16 @@
+use anyhow::Context;
+
+/// Adds the `staged_blocks` table, which holds blocks that arrived ahead of the chain head
+/// until they can be inserted in order.
+pub(crate) fn migrate(tx: &rusqlite::Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute(
+        r"CREATE TABLE staged_blocks (
+    number INTEGER PRIMARY KEY,
+    data BLOB NOT NULL
+)",
+        [],
+    )
+    .context("Creating 'staged_blocks' table")?;
+
+    Ok(())
+}
@@ -0,0 +1,25 @@
+use anyhow::Context;
+
+/// Adds the `event_filters` table, which holds the event filters registered for polling along
+/// with the position of the last event delivered to each.
+pub(crate) fn migrate(tx: &rusqlite::Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute(
+        r"CREATE TABLE event_filters (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    from_block INTEGER,
+    to_block INTEGER,
+    contract_address BLOB,
+    keys TEXT NOT NULL,
+    data_filter TEXT NOT NULL,
+    page_size INTEGER NOT NULL,
+    block_number INTEGER,
+    transaction_idx INTEGER,
+    event_idx INTEGER,
+    last_used INTEGER NOT NULL
+)",
+        [],
+    )
+    .context("Creating 'event_filters' table")?;
+
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use anyhow::Context;
+
+/// Adds the `starknet_event_blooms` table, which holds a bloom filter over the keys and emitting
+/// contracts of each block's events.
+///
+/// Existing events are not added to the filters, as that would take another pass over all of
+/// them. Instead the single row `starknet_event_blooms_start` table holds the first block after
+/// them, from which on every block with events has a filter.
+pub(crate) fn migrate(tx: &rusqlite::Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute_batch(
+        r"CREATE TABLE starknet_event_blooms (
+    block_number INTEGER PRIMARY KEY,
+    bloom BLOB NOT NULL,
+    FOREIGN KEY(block_number) REFERENCES canonical_blocks(number) ON DELETE CASCADE
+);
+CREATE TABLE starknet_event_blooms_start (
+    block_number INTEGER NOT NULL
+);
+INSERT INTO starknet_event_blooms_start (block_number)
+    SELECT COALESCE(MAX(block_number) + 1, 0) FROM starknet_events;",
+    )
+    .context("Creating 'starknet_event_blooms' tables")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::schema;
+    use rusqlite::Connection;
+
+    const REVISION: usize = 38;
+
+    #[test]
+    fn start_after_existing_events() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // The events' blocks are not needed.
+        conn.pragma_update(None, "foreign_keys", false).unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_before(&transaction, REVISION);
+
+        for (idx, block_number) in [0, 0, 2].into_iter().enumerate() {
+            transaction
+                .execute(
+                    "INSERT INTO starknet_events (block_number, idx, transaction_hash, from_address, keys, data) VALUES (?, ?, x'00', x'00', '', x'')",
+                    rusqlite::params![block_number, idx],
+                )
+                .unwrap();
+        }
+
+        super::migrate(&transaction).unwrap();
+
+        let blooms: usize = transaction
+            .query_row("SELECT count(1) FROM starknet_event_blooms", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(blooms, 0);
+
+        let start: u64 = transaction
+            .query_row(
+                "SELECT block_number FROM starknet_event_blooms_start",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(start, 3);
+    }
+
+    #[test]
+    fn without_events() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_before(&transaction, REVISION);
+
+        super::migrate(&transaction).unwrap();
+
+        let start: u64 = transaction
+            .query_row(
+                "SELECT block_number FROM starknet_event_blooms_start",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(start, 0);
+    }
+}
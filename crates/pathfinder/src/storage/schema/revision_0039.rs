@@ -0,0 +1,13 @@
+use anyhow::Context;
+
+/// Adds an index on `starknet_blocks(sequencer_address, number)`, which serves listing and
+/// counting the blocks produced by a sequencer in block order.
+pub(crate) fn migrate(tx: &rusqlite::Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute(
+        "CREATE INDEX starknet_blocks_sequencer_address_number ON starknet_blocks(sequencer_address, number)",
+        [],
+    )
+    .context("Creating 'starknet_blocks_sequencer_address_number' index")?;
+
+    Ok(())
+}
@@ -0,0 +1,14 @@
+use anyhow::Context;
+
+/// Adds the `starknet_events.key_bytes` column, which holds an event's keys as concatenated 32 byte
+/// big-endian values. Reading events decodes these instead of the base64 encoded `keys`, which
+/// are still written for the `starknet_events_keys` full-text index.
+///
+/// Existing events are not rewritten: their `key_bytes` is NULL and they keep being decoded from
+/// `keys`.
+pub(crate) fn migrate(tx: &rusqlite::Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute("ALTER TABLE starknet_events ADD COLUMN key_bytes BLOB", [])
+        .context("Adding 'key_bytes' column to 'starknet_events'")?;
+
+    Ok(())
+}
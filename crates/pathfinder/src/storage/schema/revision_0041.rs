@@ -0,0 +1,83 @@
+use anyhow::Context;
+use rusqlite::OptionalExtension;
+
+use crate::consts::{INTEGRATION_GENESIS_HASH, MAINNET_GENESIS_HASH, TESTNET_GENESIS_HASH};
+use crate::core::{Chain, StarknetBlockHash};
+
+/// Adds the single row `meta` table, which binds the database to the Starknet chain it holds,
+/// identified by its chain id.
+///
+/// Existing databases are bound to the chain of their genesis block. Databases without one, or
+/// with an unknown genesis block, are left unbound until the next startup binds them to the
+/// configured chain.
+pub(crate) fn migrate(tx: &rusqlite::Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute(
+        "CREATE TABLE meta (idx INTEGER PRIMARY KEY CHECK (idx = 1), chain_id BLOB NOT NULL)",
+        [],
+    )
+    .context("Creating 'meta' table")?;
+
+    let genesis: Option<StarknetBlockHash> = tx
+        .query_row(
+            "SELECT hash FROM starknet_blocks WHERE number = 0",
+            [],
+            |row| row.get(0),
+        )
+        .optional()
+        .context("Reading genesis block hash")?;
+
+    let chain = match genesis {
+        Some(hash) if hash == TESTNET_GENESIS_HASH => Chain::Testnet,
+        Some(hash) if hash == MAINNET_GENESIS_HASH => Chain::Mainnet,
+        Some(hash) if hash == INTEGRATION_GENESIS_HASH => Chain::Integration,
+        _ => return Ok(()),
+    };
+
+    tx.execute(
+        "INSERT INTO meta (idx, chain_id) VALUES (1, ?)",
+        [chain.starknet_chain_id().as_be_bytes()],
+    )
+    .context("Binding database to its chain")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::consts::MAINNET_GENESIS_HASH;
+    use crate::core::{Chain, StarknetBlockNumber};
+    use crate::storage::{schema, MetaTable};
+    use rusqlite::Connection;
+
+    const REVISION: usize = 41;
+
+    fn migrated(genesis: Option<crate::core::StarknetBlockHash>) -> Option<Chain> {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_before(&transaction, REVISION);
+
+        if let Some(hash) = genesis {
+            transaction
+                .execute(
+                    "INSERT INTO starknet_blocks (number, hash, root, timestamp, gas_price, sequencer_address)
+                     VALUES (?, ?, x'00', 0, x'00', x'00')",
+                    rusqlite::params![StarknetBlockNumber::GENESIS, hash],
+                )
+                .unwrap();
+        }
+
+        super::migrate(&transaction).unwrap();
+        MetaTable::get_chain(&transaction).unwrap()
+    }
+
+    #[test]
+    fn binds_to_genesis_chain() {
+        assert_eq!(migrated(Some(MAINNET_GENESIS_HASH)), Some(Chain::Mainnet));
+    }
+
+    #[test]
+    fn unbound_without_genesis() {
+        assert_eq!(migrated(None), None);
+    }
+}
@@ -0,0 +1,115 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+/// Makes `starknet_blocks.sequencer_address` nullable, so that blocks without a sequencer
+/// address are told apart from a zero address.
+///
+/// Blocks were stored with a zero address when the sequencer did not report one, so existing
+/// zero addresses become NULL.
+pub(crate) fn migrate(tx: &Transaction<'_>) -> anyhow::Result<()> {
+    use rusqlite::config::DbConfig::SQLITE_DBCONFIG_ENABLE_FKEY;
+    let fk_config = tx
+        .db_config(SQLITE_DBCONFIG_ENABLE_FKEY)
+        .context("Reading FK configuration")?;
+    tx.set_db_config(SQLITE_DBCONFIG_ENABLE_FKEY, false)
+        .context("Disabling foreign-key enforcement")?;
+
+    migrate_blocks(tx)?;
+
+    tx.set_db_config(SQLITE_DBCONFIG_ENABLE_FKEY, fk_config)
+        .context("Setting FK enforcement to pre-migration value")?;
+
+    Ok(())
+}
+
+fn migrate_blocks(tx: &Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute(
+        r"CREATE TABLE starknet_blocks_new (
+    hash      BLOB    PRIMARY KEY NOT NULL,
+    number    INTEGER NOT NULL,
+    root      BLOB    NOT NULL,
+    timestamp INTEGER NOT NULL,
+    gas_price BLOB    NOT NULL,
+    sequencer_address BLOB,
+    version_id INTEGER REFERENCES starknet_versions(id),
+    parent_hash BLOB NOT NULL DEFAULT X'0000000000000000000000000000000000000000000000000000000000000000',
+    transaction_count INTEGER NOT NULL DEFAULT 0
+)",
+        [],
+    )
+    .context("Creating new table")?;
+
+    tx.execute(
+        r"INSERT INTO starknet_blocks_new (hash, number, root, timestamp, gas_price, sequencer_address, version_id, parent_hash, transaction_count)
+    SELECT hash, number, root, timestamp, gas_price,
+        NULLIF(sequencer_address, X'0000000000000000000000000000000000000000000000000000000000000000'),
+        version_id, parent_hash, transaction_count
+    FROM starknet_blocks",
+        [],
+    )
+    .context("Copying data")?;
+
+    tx.execute("DROP TABLE starknet_blocks", [])
+        .context("Dropping old table")?;
+
+    tx.execute(
+        "ALTER TABLE starknet_blocks_new RENAME TO starknet_blocks",
+        [],
+    )
+    .context("Renaming table")?;
+
+    for (name, columns) in [
+        ("starknet_blocks_block_number", "number"),
+        ("starknet_blocks_timestamp", "timestamp"),
+        (
+            "starknet_blocks_sequencer_address_number",
+            "sequencer_address, number",
+        ),
+    ] {
+        tx.execute(
+            &format!("CREATE INDEX {name} ON starknet_blocks({columns})"),
+            [],
+        )
+        .with_context(|| format!("Creating {name} index"))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::schema;
+    use rusqlite::Connection;
+
+    const REVISION: usize = 42;
+
+    #[test]
+    fn zero_address_becomes_null() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_before(&transaction, REVISION);
+
+        for (number, address) in [(0, [0u8; 32]), (1, [1u8; 32])] {
+            transaction
+                .execute(
+                    "INSERT INTO starknet_blocks (number, hash, root, timestamp, gas_price, sequencer_address)
+                     VALUES (?, ?, x'00', 0, x'00', ?)",
+                    rusqlite::params![number, [number as u8; 32], address],
+                )
+                .unwrap();
+        }
+
+        super::migrate(&transaction).unwrap();
+
+        let mut stmt = transaction
+            .prepare("SELECT sequencer_address FROM starknet_blocks ORDER BY number")
+            .unwrap();
+        let addresses = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<Vec<Option<Vec<u8>>>, _>>()
+            .unwrap();
+        assert_eq!(addresses, vec![None, Some(vec![1u8; 32])]);
+    }
+}
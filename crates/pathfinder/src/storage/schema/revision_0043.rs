@@ -0,0 +1,112 @@
+use anyhow::Context;
+use rusqlite::{named_params, Transaction};
+
+use crate::core::ClassHash;
+
+/// Adds a `class_hash` column to `starknet_transactions` so that the transaction which declared
+/// or deployed a class can be found without decompressing the transactions, and backfills it from
+/// the stored transactions.
+///
+/// Transactions other than declares and deploys are left `NULL`.
+pub(crate) fn migrate(transaction: &Transaction<'_>) -> anyhow::Result<()> {
+    transaction
+        .execute_batch(
+            r"ALTER TABLE starknet_transactions ADD COLUMN class_hash BLOB;
+CREATE INDEX starknet_transactions_class_hash ON starknet_transactions(class_hash);",
+        )
+        .context("Adding 'class_hash' column to 'starknet_transactions'")?;
+
+    let mut update = transaction
+        .prepare("UPDATE starknet_transactions SET class_hash = :class_hash WHERE hash = :hash")
+        .context("Prepare update statement")?;
+
+    super::backfill::transactions(
+        transaction,
+        "tx",
+        "Extracting class hashes from transactions",
+        |hash, tx: LightTransaction| {
+            let class_hash = match tx {
+                LightTransaction::Declare { class_hash }
+                | LightTransaction::Deploy { class_hash } => class_hash,
+                LightTransaction::Other => return Ok(()),
+            };
+
+            update
+                .execute(named_params![
+                    ":hash": hash,
+                    ":class_hash": class_hash,
+                ])
+                .context("Update transaction class hash")?;
+            Ok(())
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Real transaction json has a bunch of fields which we don't need
+#[derive(serde::Deserialize)]
+#[serde(tag = "type")]
+enum LightTransaction {
+    #[serde(rename = "DECLARE")]
+    Declare { class_hash: ClassHash },
+    #[serde(rename = "DEPLOY")]
+    Deploy { class_hash: ClassHash },
+    #[serde(other)]
+    Other,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::ClassHash;
+    use crate::starkhash;
+    use crate::storage::schema;
+    use rusqlite::Connection;
+
+    const REVISION: usize = 43;
+
+    #[test]
+    fn backfill() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_before(&transaction, REVISION);
+
+        let transactions = [
+            r#"{"type":"DECLARE","class_hash":"0x1","sender_address":"0x2"}"#,
+            r#"{"type":"DEPLOY","class_hash":"0x3","contract_address":"0x4"}"#,
+            r#"{"type":"INVOKE_FUNCTION","contract_address":"0x5"}"#,
+            r#"{"type":"L1_HANDLER","contract_address":"0x6"}"#,
+        ];
+        for (idx, tx) in transactions.iter().enumerate() {
+            let tx = zstd::encode_all(tx.as_bytes(), 10).unwrap();
+            transaction
+                .execute(
+                    "INSERT INTO starknet_transactions (hash, idx, block_hash, tx, receipt) VALUES (?, ?, x'00', ?, x'')",
+                    rusqlite::params![vec![idx as u8 + 1], idx, tx],
+                )
+                .unwrap();
+        }
+
+        super::migrate(&transaction).unwrap();
+
+        let mut stmt = transaction
+            .prepare("SELECT class_hash FROM starknet_transactions ORDER BY idx")
+            .unwrap();
+        let class_hashes = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<Vec<Option<ClassHash>>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            class_hashes,
+            vec![
+                Some(ClassHash(starkhash!("01"))),
+                Some(ClassHash(starkhash!("03"))),
+                None,
+                None
+            ]
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::core::{ClassHash, ContractAddress, ContractNonce};
+use crate::rpc::v01::types::reply::state_update::{DeployedContract, Nonce};
+use crate::storage::ContractSummariesTable;
+
+/// Adds the `contract_summaries` table, which tracks the class and nonce of each contract over
+/// time, and backfills it from the stored state updates.
+pub(crate) fn migrate(transaction: &Transaction<'_>) -> anyhow::Result<()> {
+    transaction
+        .execute_batch(
+            r"CREATE TABLE contract_summaries (
+    contract_address BLOB NOT NULL,
+    block_number INTEGER NOT NULL,
+    class_hash BLOB NOT NULL,
+    nonce BLOB NOT NULL,
+    PRIMARY KEY (contract_address, block_number)
+);
+
+CREATE INDEX contract_summaries_block_number ON contract_summaries(block_number);",
+        )
+        .context("Creating contract_summaries table")?;
+
+    // State updates are visited in block order, as each builds on the contracts' previous state.
+    super::backfill::state_updates(
+        transaction,
+        "Indexing contracts of state updates",
+        |block_number, _, state_update: LightStateUpdate| {
+            let deployed_contracts = state_update
+                .state_diff
+                .deployed_contracts
+                .into_iter()
+                .map(|contract| DeployedContract {
+                    address: contract.address,
+                    class_hash: contract.class_hash,
+                })
+                .collect::<Vec<_>>();
+            let nonces = state_update
+                .state_diff
+                .nonces
+                .into_iter()
+                .map(|nonce| Nonce {
+                    contract_address: nonce.contract_address,
+                    nonce: nonce.nonce,
+                })
+                .collect::<Vec<_>>();
+
+            ContractSummariesTable::apply(transaction, block_number, &deployed_contracts, &nonces)
+                .with_context(|| format!("Indexing contracts of block {block_number}"))
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Real state update json has a bunch of fields which we don't need
+#[derive(serde::Deserialize)]
+struct LightStateUpdate {
+    state_diff: LightStateDiff,
+}
+
+#[derive(serde::Deserialize)]
+struct LightStateDiff {
+    deployed_contracts: Vec<LightDeployedContract>,
+    #[serde(default)]
+    nonces: Vec<LightNonce>,
+}
+
+#[derive(serde::Deserialize)]
+struct LightDeployedContract {
+    address: ContractAddress,
+    class_hash: ClassHash,
+}
+
+#[derive(serde::Deserialize)]
+struct LightNonce {
+    contract_address: ContractAddress,
+    nonce: ContractNonce,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{ClassHash, ContractAddress, ContractNonce, StarknetBlockHash};
+    use crate::starkhash;
+    use crate::storage::schema;
+    use rusqlite::Connection;
+
+    const REVISION: usize = 44;
+
+    #[test]
+    fn backfill() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_before(&transaction, REVISION);
+
+        let insert_block = |number: u64, hash_hex: &str, deployed: &str, nonces: &str| {
+            let hash = StarknetBlockHash(stark_hash::StarkHash::from_hex_str(hash_hex).unwrap());
+            transaction
+                .execute(
+                    r"INSERT INTO starknet_blocks (number, hash, parent_hash, root, timestamp, gas_price)
+                    VALUES (?, ?, x'00', x'00', 0, x'00')",
+                    rusqlite::params![number, hash],
+                )
+                .unwrap();
+            let data = format!(
+                r#"{{"block_hash":"{hash_hex}","new_root":"0x1","old_root":"0x0","state_diff":{{"storage_diffs":[],"declared_contracts":[],"deployed_contracts":{deployed},"nonces":{nonces}}}}}"#,
+            );
+            let data = zstd::encode_all(data.as_bytes(), 10).unwrap();
+            transaction
+                .execute(
+                    "INSERT INTO starknet_state_updates (block_hash, data) VALUES (?, ?)",
+                    rusqlite::params![hash, data],
+                )
+                .unwrap();
+        };
+        // The nonce of the unknown contract 0xa3 is ignored.
+        insert_block(
+            0,
+            "0xb0",
+            r#"[{"address":"0xa1","class_hash":"0xc1"},{"address":"0xa2","class_hash":"0xc2"}]"#,
+            r#"[{"contract_address":"0xa1","nonce":"0x1"}]"#,
+        );
+        insert_block(
+            1,
+            "0xb1",
+            "[]",
+            r#"[{"contract_address":"0xa1","nonce":"0x2"},{"contract_address":"0xa3","nonce":"0x1"}]"#,
+        );
+
+        super::migrate(&transaction).unwrap();
+
+        let mut stmt = transaction
+            .prepare("SELECT contract_address, block_number, class_hash, nonce FROM contract_summaries ORDER BY contract_address, block_number")
+            .unwrap();
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<Result<Vec<(ContractAddress, u64, ClassHash, ContractNonce)>, _>>()
+            .unwrap();
+
+        let a1 = ContractAddress::new_or_panic(starkhash!("a1"));
+        let a2 = ContractAddress::new_or_panic(starkhash!("a2"));
+        assert_eq!(
+            rows,
+            vec![
+                (
+                    a1,
+                    0,
+                    ClassHash(starkhash!("c1")),
+                    ContractNonce(starkhash!("01"))
+                ),
+                (
+                    a1,
+                    1,
+                    ClassHash(starkhash!("c1")),
+                    ContractNonce(starkhash!("02"))
+                ),
+                (a2, 0, ClassHash(starkhash!("c2")), ContractNonce::ZERO),
+            ]
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::Context;
+use rusqlite::{named_params, Transaction};
+
+/// Adds `events_count` and `messages_count` columns to `starknet_transactions` so that the events
+/// emitted and L2 to L1 messages sent by transactions can be counted without decompressing the
+/// receipts, and backfills them.
+///
+/// Event counts are taken from `starknet_events`, message counts from the stored receipts.
+pub(crate) fn migrate(transaction: &Transaction<'_>) -> anyhow::Result<()> {
+    transaction
+        .execute_batch(
+            r"ALTER TABLE starknet_transactions ADD COLUMN events_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE starknet_transactions ADD COLUMN messages_count INTEGER NOT NULL DEFAULT 0;",
+        )
+        .context("Adding count columns to 'starknet_transactions'")?;
+
+    transaction
+        .execute(
+            r"UPDATE starknet_transactions SET events_count = (
+    SELECT COUNT(1) FROM starknet_events WHERE starknet_events.transaction_hash = starknet_transactions.hash
+)",
+            [],
+        )
+        .context("Counting events of transactions")?;
+
+    let mut update = transaction
+        .prepare(
+            "UPDATE starknet_transactions SET messages_count = :messages_count WHERE hash = :hash",
+        )
+        .context("Prepare update statement")?;
+
+    super::backfill::transactions(
+        transaction,
+        "receipt",
+        "Counting messages of transaction receipts",
+        |hash, receipt: LightReceipt| {
+            if receipt.l2_to_l1_messages.is_empty() {
+                return Ok(());
+            }
+
+            update
+                .execute(named_params![
+                    ":hash": hash,
+                    ":messages_count": receipt.l2_to_l1_messages.len(),
+                ])
+                .context("Update transaction message count")?;
+            Ok(())
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Real receipt json has a bunch of fields which we don't need
+#[derive(serde::Deserialize)]
+struct LightReceipt {
+    #[serde(default)]
+    l2_to_l1_messages: Vec<serde::de::IgnoredAny>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::schema;
+    use rusqlite::Connection;
+
+    const REVISION: usize = 45;
+
+    #[test]
+    fn backfill() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // The events' blocks are not needed to count them.
+        conn.pragma_update(None, "foreign_keys", false).unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_before(&transaction, REVISION);
+
+        let receipts = [
+            r#"{"l2_to_l1_messages":[{"from_address":"0x1","payload":[],"to_address":"0x2"},{"from_address":"0x1","payload":[],"to_address":"0x3"}]}"#,
+            r#"{"l2_to_l1_messages":[]}"#,
+            r#"{}"#,
+        ];
+        for (idx, receipt) in receipts.iter().enumerate() {
+            let receipt = zstd::encode_all(receipt.as_bytes(), 10).unwrap();
+            transaction
+                .execute(
+                    "INSERT INTO starknet_transactions (hash, idx, block_hash, tx, receipt) VALUES (?, ?, x'00', x'', ?)",
+                    rusqlite::params![vec![idx as u8 + 1], idx, receipt],
+                )
+                .unwrap();
+        }
+        // Three events of the second transaction.
+        for idx in 0..3 {
+            transaction
+                .execute(
+                    "INSERT INTO starknet_events (block_number, idx, transaction_hash, from_address, keys, data) VALUES (0, ?, x'02', x'00', '', x'')",
+                    [idx],
+                )
+                .unwrap();
+        }
+
+        super::migrate(&transaction).unwrap();
+
+        let mut stmt = transaction
+            .prepare("SELECT events_count, messages_count FROM starknet_transactions ORDER BY idx")
+            .unwrap();
+        let counts = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<Vec<(usize, usize)>, _>>()
+            .unwrap();
+
+        assert_eq!(counts, vec![(0, 2), (3, 0), (0, 0)]);
+    }
+}
@@ -0,0 +1,138 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::core::{ContractAddress, StorageAddress, StorageValue};
+use crate::rpc::v01::types::reply::state_update::StorageDiff;
+use crate::storage::StorageUpdatesTable;
+
+/// Adds the `storage_updates` table, which records every value written to a contract's storage
+/// slot, and backfills it from the stored state updates.
+pub(crate) fn migrate(transaction: &Transaction<'_>) -> anyhow::Result<()> {
+    transaction
+        .execute_batch(
+            r"CREATE TABLE storage_updates (
+    block_number INTEGER NOT NULL,
+    contract_address BLOB NOT NULL,
+    storage_key BLOB NOT NULL,
+    value BLOB NOT NULL,
+    PRIMARY KEY (contract_address, storage_key, block_number)
+);
+
+CREATE INDEX storage_updates_block_number ON storage_updates(block_number);",
+        )
+        .context("Creating storage_updates table")?;
+
+    super::backfill::state_updates(
+        transaction,
+        "Recording storage updates of state updates",
+        |block_number, _, state_update: LightStateUpdate| {
+            let storage_diffs = state_update
+                .state_diff
+                .storage_diffs
+                .into_iter()
+                .map(|diff| StorageDiff {
+                    address: diff.address,
+                    key: diff.key,
+                    value: diff.value,
+                })
+                .collect::<Vec<_>>();
+
+            StorageUpdatesTable::insert(transaction, block_number, &storage_diffs)
+                .with_context(|| format!("Recording storage updates of block {block_number}"))
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Real state update json has a bunch of fields which we don't need
+#[derive(serde::Deserialize)]
+struct LightStateUpdate {
+    state_diff: LightStateDiff,
+}
+
+#[derive(serde::Deserialize)]
+struct LightStateDiff {
+    storage_diffs: Vec<LightStorageDiff>,
+}
+
+#[derive(serde::Deserialize)]
+struct LightStorageDiff {
+    address: ContractAddress,
+    key: StorageAddress,
+    value: StorageValue,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{ContractAddress, StarknetBlockHash, StorageAddress, StorageValue};
+    use crate::starkhash;
+    use crate::storage::schema;
+    use rusqlite::Connection;
+
+    const REVISION: usize = 46;
+
+    #[test]
+    fn backfill() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_before(&transaction, REVISION);
+
+        let insert_block = |number: u64, hash_hex: &str, storage_diffs: &str| {
+            let hash = StarknetBlockHash(stark_hash::StarkHash::from_hex_str(hash_hex).unwrap());
+            transaction
+                .execute(
+                    r"INSERT INTO starknet_blocks (number, hash, parent_hash, root, timestamp, gas_price)
+                    VALUES (?, ?, x'00', x'00', 0, x'00')",
+                    rusqlite::params![number, hash],
+                )
+                .unwrap();
+            let data = format!(
+                r#"{{"block_hash":"{hash_hex}","new_root":"0x1","old_root":"0x0","state_diff":{{"storage_diffs":{storage_diffs},"declared_contracts":[],"deployed_contracts":[],"nonces":[]}}}}"#,
+            );
+            let data = zstd::encode_all(data.as_bytes(), 10).unwrap();
+            transaction
+                .execute(
+                    "INSERT INTO starknet_state_updates (block_hash, data) VALUES (?, ?)",
+                    rusqlite::params![hash, data],
+                )
+                .unwrap();
+        };
+        insert_block(
+            0,
+            "0xb0",
+            r#"[{"address":"0xa1","key":"0x10","value":"0x1"},{"address":"0xa1","key":"0x11","value":"0x2"}]"#,
+        );
+        insert_block(
+            1,
+            "0xb1",
+            r#"[{"address":"0xa1","key":"0x10","value":"0x3"}]"#,
+        );
+
+        super::migrate(&transaction).unwrap();
+
+        let mut stmt = transaction
+            .prepare("SELECT block_number, contract_address, storage_key, value FROM storage_updates ORDER BY storage_key, block_number")
+            .unwrap();
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<Result<Vec<(u64, ContractAddress, StorageAddress, StorageValue)>, _>>()
+            .unwrap();
+
+        let address = ContractAddress::new_or_panic(starkhash!("a1"));
+        let key =
+            |hex| StorageAddress::new_or_panic(stark_hash::StarkHash::from_hex_str(hex).unwrap());
+        assert_eq!(
+            rows,
+            vec![
+                (0, address, key("0x10"), StorageValue(starkhash!("01"))),
+                (1, address, key("0x10"), StorageValue(starkhash!("03"))),
+                (0, address, key("0x11"), StorageValue(starkhash!("02"))),
+            ]
+        );
+    }
+}
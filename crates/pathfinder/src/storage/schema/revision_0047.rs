@@ -0,0 +1,77 @@
+use anyhow::Context;
+
+/// Adds an `event_count` column to `starknet_blocks`, so that block listings don't have to count
+/// the events of each block, and backfills it from `starknet_events`.
+pub(crate) fn migrate(tx: &rusqlite::Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute(
+        "ALTER TABLE starknet_blocks ADD COLUMN event_count INTEGER NOT NULL DEFAULT 0",
+        [],
+    )
+    .context("Adding 'event_count' column to 'starknet_blocks'")?;
+
+    tx.execute(
+        r"WITH counts AS (
+    SELECT block_number, COUNT(1) AS event_count FROM starknet_events GROUP BY block_number
+)
+UPDATE starknet_blocks SET event_count = counts.event_count
+FROM counts WHERE counts.block_number = starknet_blocks.number",
+        [],
+    )
+    .context("Backfilling 'event_count' in 'starknet_blocks'")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::schema;
+    use rusqlite::Connection;
+
+    const REVISION: usize = 47;
+
+    #[test]
+    fn backfill() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_before(&transaction, REVISION);
+
+        for number in 0u8..3 {
+            transaction
+                .execute(
+                    r"INSERT INTO starknet_blocks (number, hash, parent_hash, root, timestamp, gas_price, sequencer_address)
+                    VALUES (?, ?, x'00', x'00', 0, x'00', x'00')",
+                    rusqlite::params![number, vec![number]],
+                )
+                .unwrap();
+            transaction
+                .execute(
+                    "INSERT INTO canonical_blocks (number, hash) VALUES (?, ?)",
+                    rusqlite::params![number, vec![number]],
+                )
+                .unwrap();
+        }
+
+        // Block 0 has two events, block 1 has none and block 2 has one.
+        for (idx, block) in [0, 0, 2].into_iter().enumerate() {
+            transaction
+                .execute(
+                    r"INSERT INTO starknet_events (block_number, idx, transaction_hash, from_address, keys, data)
+                    VALUES (?, ?, x'00', x'00', '', x'')",
+                    rusqlite::params![block, idx],
+                )
+                .unwrap();
+        }
+
+        super::migrate(&transaction).unwrap();
+
+        let counts = transaction
+            .prepare("SELECT event_count FROM starknet_blocks ORDER BY number")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<Vec<usize>, _>>()
+            .unwrap();
+        assert_eq!(counts, vec![2, 0, 1]);
+    }
+}
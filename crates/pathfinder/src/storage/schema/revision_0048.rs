@@ -0,0 +1,66 @@
+use anyhow::Context;
+
+/// Adds the `l1_to_l2_messages` table, which links the nonce of an L1 to L2 message to the
+/// Ethereum transaction which sent it, and drops the `ethereum_transaction_hash` column of
+/// `l1_handler_origins` in its favour.
+///
+/// The messages are synced from L1 independently of the L1 handler transactions which consume
+/// them, so they have to be stored on their own. The dropped column was never filled outside of
+/// tests.
+pub(crate) fn migrate(tx: &rusqlite::Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute_batch(
+        r"CREATE TABLE l1_to_l2_messages (
+    message_nonce BLOB PRIMARY KEY NOT NULL,
+    ethereum_transaction_hash BLOB NOT NULL,
+    ethereum_block_number INTEGER NOT NULL
+);
+
+CREATE INDEX l1_to_l2_messages_ethereum_block_number ON l1_to_l2_messages(ethereum_block_number);
+
+ALTER TABLE l1_handler_origins DROP COLUMN ethereum_transaction_hash;",
+    )
+    .context("Creating l1_to_l2_messages table")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::schema;
+    use rusqlite::Connection;
+
+    const REVISION: usize = 48;
+
+    #[test]
+    fn origins_are_kept() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_before(&transaction, REVISION);
+
+        transaction
+            .execute(
+                r"INSERT INTO l1_handler_origins (starknet_transaction_hash, message_nonce, ethereum_transaction_hash)
+                VALUES (x'01', x'07', x'0abc')",
+                [],
+            )
+            .unwrap();
+
+        super::migrate(&transaction).unwrap();
+
+        let origin: (Vec<u8>, Vec<u8>) = transaction
+            .query_row("SELECT * FROM l1_handler_origins", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(origin, (vec![1], vec![7]));
+        let columns: usize = transaction
+            .query_row(
+                "SELECT COUNT(1) FROM pragma_table_info('l1_handler_origins')",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(columns, 2);
+    }
+}
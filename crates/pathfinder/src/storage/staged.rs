@@ -0,0 +1,435 @@
+//! Blocks which arrived ahead of the chain head, e.g. when block ranges are downloaded in
+//! parallel.
+//!
+//! Staged blocks are kept apart from the canonical tables, so none of the getters can observe
+//! them. Once the gap below them is filled, [StagedBlocksTable::promote_contiguous] inserts them
+//! in order.
+
+use anyhow::Context;
+use rusqlite::{named_params, OptionalExtension, Transaction};
+use stark_hash::StarkHash;
+
+use super::StarknetBlocksTable;
+use crate::core::{StarknetBlockHash, StarknetBlockNumber};
+use crate::rpc::v01::types::reply::StateUpdate;
+use crate::sequencer::reply::Block;
+
+/// A staged block with its state update, stored as compressed JSON.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StagedBlock {
+    block: Block,
+    state_update: StateUpdate,
+}
+
+/// Stores blocks which cannot be inserted yet because blocks below them are missing.
+pub struct StagedBlocksTable {}
+
+impl StagedBlocksTable {
+    /// Stages `block` until [StagedBlocksTable::promote_contiguous] can insert it. Replaces any
+    /// block already staged at the same height.
+    ///
+    /// Fails if the block is not above the current head.
+    pub fn insert(
+        tx: &Transaction<'_>,
+        compressor: &mut zstd::bulk::Compressor<'_>,
+        block: &Block,
+        state_update: &crate::sequencer::reply::StateUpdate,
+    ) -> anyhow::Result<()> {
+        if let Some(head) = StarknetBlocksTable::get_latest_number(tx).context("Reading head")? {
+            anyhow::ensure!(
+                block.block_number > head,
+                "Block {} is not above the head {}",
+                block.block_number,
+                head
+            );
+        }
+
+        let staged = StagedBlock {
+            block: block.clone(),
+            state_update: state_update.clone().into(),
+        };
+        let data = serde_json::to_vec(&staged).context("Serializing staged block")?;
+        let data = compressor
+            .compress(&data)
+            .context("Compressing staged block")?;
+
+        tx.execute(
+            "INSERT OR REPLACE INTO staged_blocks (number, data) VALUES (:number, :data)",
+            named_params! {
+                ":number": block.block_number,
+                ":data": data,
+            },
+        )
+        .context("Inserting staged block")?;
+
+        Ok(())
+    }
+
+    /// Returns the numbers of all staged blocks, in ascending order.
+    pub fn numbers(tx: &Transaction<'_>) -> anyhow::Result<Vec<StarknetBlockNumber>> {
+        let mut stmt = tx
+            .prepare("SELECT number FROM staged_blocks ORDER BY number ASC")
+            .context("Preparing statement")?;
+
+        let numbers = stmt
+            .query_map([], |row| row.get(0))
+            .context("Executing query")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Iterating over rows")?;
+
+        Ok(numbers)
+    }
+
+    /// Inserts the staged blocks which directly extend the head, in order, into the canonical
+    /// tables. Returns the new head, or [None] if no block was promoted.
+    ///
+    /// A staged block whose parent is not the head belongs to a different fork. It is discarded
+    /// along with all staged blocks above it.
+    ///
+    /// The classes referenced by the promoted blocks must already be stored.
+    pub fn promote_contiguous(
+        tx: &Transaction<'_>,
+        compressor: &mut zstd::bulk::Compressor<'_>,
+    ) -> anyhow::Result<Option<StarknetBlockNumber>> {
+        let mut head =
+            StarknetBlocksTable::get_latest_hash_and_number(tx).context("Reading head")?;
+        let mut promoted = None;
+
+        loop {
+            let (parent_hash, next) = match head {
+                Some((hash, number)) => (hash, number + 1),
+                None => (
+                    StarknetBlockHash(StarkHash::ZERO),
+                    StarknetBlockNumber::GENESIS,
+                ),
+            };
+
+            let staged = match Self::take(tx, next)? {
+                Some(staged) => staged,
+                None => break,
+            };
+
+            if staged.block.parent_block_hash != parent_hash {
+                tracing::warn!(
+                    block=%next,
+                    "Staged block does not extend the head, discarding staged blocks from it"
+                );
+                Self::reorg(tx, next).context("Discarding staged blocks")?;
+                break;
+            }
+
+            let block = crate::state::insert_block(
+                tx,
+                compressor,
+                staged.block,
+                staged.state_update.into(),
+            )
+            .with_context(|| format!("Promoting staged block {next}"))?;
+            crate::state::track_l1_l2_head(tx, &block)?;
+
+            head = Some((block.hash, block.number));
+            promoted = Some(block.number);
+        }
+
+        Ok(promoted)
+    }
+
+    /// Removes and returns the block staged at `number`.
+    fn take(
+        tx: &Transaction<'_>,
+        number: StarknetBlockNumber,
+    ) -> anyhow::Result<Option<StagedBlock>> {
+        let data: Option<Vec<u8>> = tx
+            .query_row(
+                "SELECT data FROM staged_blocks WHERE number = ?",
+                [number],
+                |row| row.get(0),
+            )
+            .optional()
+            .context("Reading staged block")?;
+
+        if data.is_some() {
+            tx.execute("DELETE FROM staged_blocks WHERE number = ?", [number])
+                .context("Removing staged block")?;
+        }
+
+        data.map(|data| {
+            let data = zstd::decode_all(data.as_slice())
+                .context("Corruption: invalid compressed column (data)")?;
+            serde_json::from_slice(&data).context("Deserializing staged block")
+        })
+        .transpose()
+    }
+
+    /// Removes all staged blocks from `reorg_tail` onwards.
+    pub fn reorg(tx: &Transaction<'_>, reorg_tail: StarknetBlockNumber) -> anyhow::Result<()> {
+        tx.execute("DELETE FROM staged_blocks WHERE number >= ?", [reorg_tail])
+            .context("Deleting staged blocks")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{
+        ClassHash, ContractAddress, GlobalRoot, StarknetBlockTimestamp, StorageAddress,
+        StorageValue,
+    };
+    use crate::sequencer::reply::state_update::{DeployedContract, StateDiff, StorageDiff};
+    use crate::sequencer::reply::Status;
+    use crate::storage::test_utils;
+    use crate::storage::CompressionConfig;
+    use crate::storage::{
+        CanonicalBlocksTable, ContractCodeTable, StarknetBlocksBlockId, StarknetTransactionsTable,
+        Storage,
+    };
+    use std::collections::HashMap;
+
+    const CHAIN_LENGTH: u64 = 9;
+
+    fn class(i: u8) -> ClassHash {
+        ClassHash(StarkHash::from_be_slice(&[0xc, i]).unwrap())
+    }
+
+    fn contract(i: u8) -> ContractAddress {
+        ContractAddress::new_or_panic(StarkHash::from_be_slice(&[0xa, i]).unwrap())
+    }
+
+    fn hash(i: u8) -> StarknetBlockHash {
+        StarknetBlockHash(StarkHash::from_be_slice(&[0xb, i]).unwrap())
+    }
+
+    fn compressor() -> zstd::bulk::Compressor<'static> {
+        CompressionConfig::default().compressor().unwrap()
+    }
+
+    fn storage_with_classes() -> Storage {
+        let storage = Storage::in_memory().unwrap();
+        let mut connection = storage.writer().unwrap();
+        let tx = connection.transaction().unwrap();
+        for i in 0..CHAIN_LENGTH as u8 {
+            ContractCodeTable::insert(&tx, class(i), b"abi", b"bytecode", &[i; 16]).unwrap();
+        }
+        tx.commit().unwrap();
+        storage
+    }
+
+    /// Builds a chain in which every block deploys a contract with one transaction. The state
+    /// roots are computed by inserting the chain into a scratch database.
+    fn chain() -> Vec<(Block, crate::sequencer::reply::StateUpdate)> {
+        let scratch = storage_with_classes();
+        let mut connection = scratch.writer().unwrap();
+        let mut compressor = scratch.compression().compressor().unwrap();
+        let transactions = test_utils::create_transactions_and_receipts();
+
+        let mut parent_hash = StarknetBlockHash(StarkHash::ZERO);
+        let mut old_root = GlobalRoot(StarkHash::ZERO);
+        (0..CHAIN_LENGTH as u8)
+            .map(|i| {
+                let mut state_update = crate::sequencer::reply::StateUpdate {
+                    block_hash: Some(hash(i)),
+                    new_root: GlobalRoot(StarkHash::ZERO),
+                    old_root,
+                    state_diff: StateDiff {
+                        storage_diffs: HashMap::from([(
+                            contract(i),
+                            vec![StorageDiff {
+                                key: StorageAddress::new_or_panic(StarkHash::from_u128(1)),
+                                value: StorageValue(StarkHash::from_u128(i as u128 + 1)),
+                            }],
+                        )]),
+                        deployed_contracts: vec![DeployedContract {
+                            address: contract(i),
+                            class_hash: class(i),
+                        }],
+                        declared_contracts: vec![],
+                        nonces: HashMap::new(),
+                    },
+                };
+
+                // The state root is computed by applying the update, which is then rolled back.
+                let tx = connection.transaction().unwrap();
+                let root = crate::state::update_starknet_state(&tx, &state_update).unwrap();
+                drop(tx);
+                state_update.new_root = root;
+
+                let (transaction, receipt) = transactions[i as usize].clone();
+                let block = Block {
+                    block_hash: hash(i),
+                    block_number: StarknetBlockNumber::new_or_panic(i as u64),
+                    gas_price: None,
+                    parent_block_hash: parent_hash,
+                    sequencer_address: None,
+                    state_root: root,
+                    status: Status::AcceptedOnL2,
+                    timestamp: StarknetBlockTimestamp::new_or_panic(i as u64),
+                    transaction_receipts: vec![receipt],
+                    transactions: vec![transaction],
+                    starknet_version: None,
+                };
+
+                let tx = connection.transaction().unwrap();
+                crate::state::insert_block(
+                    &tx,
+                    &mut compressor,
+                    block.clone(),
+                    state_update.clone(),
+                )
+                .unwrap();
+                tx.commit().unwrap();
+
+                parent_hash = block.block_hash;
+                old_root = root;
+                (block, state_update)
+            })
+            .collect()
+    }
+
+    fn latest(tx: &Transaction<'_>) -> Option<StarknetBlockNumber> {
+        StarknetBlocksTable::get_latest_number(tx).unwrap()
+    }
+
+    #[test]
+    fn promotes_once_the_gap_is_filled() {
+        let chain = chain();
+        let storage = storage_with_classes();
+        let mut connection = storage.writer().unwrap();
+        let tx = connection.transaction().unwrap();
+
+        let (genesis, genesis_update) = chain[0].clone();
+        crate::state::insert_block(
+            &tx,
+            &mut CompressionConfig::default().compressor().unwrap(),
+            genesis,
+            genesis_update,
+        )
+        .unwrap();
+
+        for (block, state_update) in &chain[5..] {
+            StagedBlocksTable::insert(&tx, &mut compressor(), block, state_update).unwrap();
+        }
+        assert_eq!(
+            StagedBlocksTable::promote_contiguous(&tx, &mut compressor()).unwrap(),
+            None
+        );
+        assert_eq!(latest(&tx), Some(StarknetBlockNumber::GENESIS));
+
+        for (block, state_update) in &chain[1..5] {
+            StagedBlocksTable::insert(&tx, &mut compressor(), block, state_update).unwrap();
+            // Staged blocks are invisible to the getters.
+            assert_eq!(latest(&tx), Some(StarknetBlockNumber::GENESIS));
+            assert_eq!(
+                StarknetBlocksTable::get(&tx, block.block_number.into()).unwrap(),
+                None
+            );
+        }
+
+        assert_eq!(
+            StagedBlocksTable::promote_contiguous(&tx, &mut compressor()).unwrap(),
+            Some(StarknetBlockNumber::new_or_panic(8))
+        );
+        assert_eq!(latest(&tx), Some(StarknetBlockNumber::new_or_panic(8)));
+        assert!(StagedBlocksTable::numbers(&tx).unwrap().is_empty());
+
+        for (block, _) in &chain {
+            let number = block.block_number;
+            let stored = StarknetBlocksTable::get(&tx, number.into())
+                .unwrap()
+                .unwrap();
+            assert_eq!(stored.hash, block.block_hash);
+            assert_eq!(stored.root, block.state_root);
+            assert_eq!(
+                CanonicalBlocksTable::get(&tx, number).unwrap(),
+                Some(block.block_hash)
+            );
+
+            let transactions = StarknetTransactionsTable::get_transaction_data_for_block(
+                &tx,
+                block.block_hash.into(),
+            )
+            .unwrap();
+            assert_eq!(
+                transactions,
+                vec![(
+                    block.transactions[0].clone(),
+                    block.transaction_receipts[0].clone()
+                )]
+            );
+        }
+        assert!(StarknetBlocksTable::get(&tx, StarknetBlocksBlockId::Latest)
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn promotes_from_genesis() {
+        let chain = chain();
+        let storage = storage_with_classes();
+        let mut connection = storage.writer().unwrap();
+        let tx = connection.transaction().unwrap();
+
+        for (block, state_update) in chain[..3].iter().rev() {
+            StagedBlocksTable::insert(&tx, &mut compressor(), block, state_update).unwrap();
+        }
+        assert_eq!(latest(&tx), None);
+
+        assert_eq!(
+            StagedBlocksTable::promote_contiguous(&tx, &mut compressor()).unwrap(),
+            Some(StarknetBlockNumber::new_or_panic(2))
+        );
+        assert_eq!(latest(&tx), Some(StarknetBlockNumber::new_or_panic(2)));
+    }
+
+    #[test]
+    fn fork_is_discarded() {
+        let chain = chain();
+        let storage = storage_with_classes();
+        let mut connection = storage.writer().unwrap();
+        let tx = connection.transaction().unwrap();
+
+        let (mut forked, forked_update) = chain[1].clone();
+        forked.parent_block_hash = hash(0xff);
+        StagedBlocksTable::insert(&tx, &mut compressor(), &chain[0].0, &chain[0].1).unwrap();
+        StagedBlocksTable::insert(&tx, &mut compressor(), &forked, &forked_update).unwrap();
+        StagedBlocksTable::insert(&tx, &mut compressor(), &chain[2].0, &chain[2].1).unwrap();
+
+        assert_eq!(
+            StagedBlocksTable::promote_contiguous(&tx, &mut compressor()).unwrap(),
+            Some(StarknetBlockNumber::GENESIS)
+        );
+        assert_eq!(latest(&tx), Some(StarknetBlockNumber::GENESIS));
+        assert!(StagedBlocksTable::numbers(&tx).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reorg_clears_overlapping_blocks() {
+        let chain = chain();
+        let storage = storage_with_classes();
+        let mut connection = storage.writer().unwrap();
+        let tx = connection.transaction().unwrap();
+
+        for (block, state_update) in &chain[3..6] {
+            StagedBlocksTable::insert(&tx, &mut compressor(), block, state_update).unwrap();
+        }
+
+        StagedBlocksTable::reorg(&tx, StarknetBlockNumber::new_or_panic(4)).unwrap();
+        assert_eq!(
+            StagedBlocksTable::numbers(&tx).unwrap(),
+            vec![StarknetBlockNumber::new_or_panic(3)]
+        );
+    }
+
+    #[test]
+    fn rejects_blocks_at_or_below_head() {
+        let chain = chain();
+        let storage = storage_with_classes();
+        let mut connection = storage.writer().unwrap();
+        let tx = connection.transaction().unwrap();
+
+        StagedBlocksTable::insert(&tx, &mut compressor(), &chain[0].0, &chain[0].1).unwrap();
+        StagedBlocksTable::promote_contiguous(&tx, &mut compressor()).unwrap();
+
+        StagedBlocksTable::insert(&tx, &mut compressor(), &chain[0].0, &chain[0].1).unwrap_err();
+    }
+}
@@ -1,4 +1,7 @@
+use std::collections::HashMap;
+
 use anyhow::Context;
+use bitvec::prelude::BitView;
 use rusqlite::{named_params, params, OptionalExtension, Transaction};
 use stark_hash::StarkHash;
 use web3::types::H256;
@@ -8,14 +11,44 @@ use crate::{
     core::{
         Chain, ClassHash, ContractAddress, ContractNonce, ContractRoot, ContractStateHash,
         EthereumBlockHash, EthereumBlockNumber, EthereumLogIndex, EthereumTransactionHash,
-        EthereumTransactionIndex, EventData, EventKey, GasPrice, GlobalRoot, SequencerAddress,
-        StarknetBlockHash, StarknetBlockNumber, StarknetBlockTimestamp, StarknetTransactionHash,
+        EthereumTransactionIndex, EventData, EventKey, Fee, GasPrice, GlobalRoot,
+        L1ToL2MessageNonce, SequencerAddress, StarknetBlockHash, StarknetBlockNumber,
+        StarknetBlockTimestamp, StarknetTransactionHash, StorageAddress, StorageValue,
     },
     ethereum::{log::StateUpdateLog, BlockOrigin, EthOrigin, TransactionOrigin},
-    rpc::v01::types::reply::StateUpdate,
-    sequencer::reply::transaction,
+    rpc::v01::types::reply::{
+        state_update::{DeployedContract, Nonce, StorageDiff},
+        StateUpdate,
+    },
+    sequencer::reply::{transaction, PendingBlock, Status},
+    state::block_hash::{calculate_transaction_hash_with_signature, transaction_commitment_tree},
+    state::merkle_tree::ProofNode,
+    storage::{
+        event_bloom::{EventBloom, EventBloomsTable},
+        metrics::{self, WriteTimer},
+        Decompressor, EventSender, StorageError,
+    },
 };
 
+/// Decompresses and deserializes a blob read from `table`, which is [corrupted](StorageError::Corruption)
+/// if this fails.
+fn decode_blob<T: serde::de::DeserializeOwned>(
+    data: &[u8],
+    table: &'static str,
+    what: &str,
+) -> Result<T, StorageError> {
+    let data = decompress_blob(data, table, what)?;
+    serde_json::from_slice(&data)
+        .map_err(|e| StorageError::corruption(table, format!("Deserializing {what}: {e}")))
+}
+
+/// Decompresses a blob read from `table`, which is [corrupted](StorageError::Corruption) if this
+/// fails.
+fn decompress_blob(data: &[u8], table: &'static str, what: &str) -> Result<Vec<u8>, StorageError> {
+    zstd::decode_all(data)
+        .map_err(|e| StorageError::corruption(table, format!("Decompressing {what}: {e}")))
+}
+
 /// Contains the [L1 Starknet update logs](StateUpdateLog).
 pub struct L1StateTable {}
 
@@ -214,6 +247,68 @@ impl RefsTable {
     }
 }
 
+/// Binds the database to the Starknet chain it holds, see [Storage::bind_chain](super::Storage::bind_chain).
+pub struct MetaTable {}
+
+/// Returned by [MetaTable::bind_chain] if the database holds another chain than the configured
+/// one.
+#[derive(Copy, Clone, Debug, thiserror::Error, PartialEq, Eq)]
+#[error("database holds {stored} data, but the node is configured for {configured}")]
+pub struct ChainMismatch {
+    pub stored: Chain,
+    pub configured: Chain,
+}
+
+impl MetaTable {
+    /// Returns the chain the database is bound to, if any.
+    pub fn get_chain(tx: &Transaction<'_>) -> Result<Option<Chain>, StorageError> {
+        let chain_id: Option<Vec<u8>> = tx
+            .query_row("SELECT chain_id FROM meta WHERE idx = 1", [], |row| {
+                row.get(0)
+            })
+            .optional()
+            .context("Reading chain id")?;
+
+        chain_id
+            .map(|chain_id| {
+                [Chain::Mainnet, Chain::Testnet, Chain::Integration]
+                    .into_iter()
+                    .find(|chain| chain.starknet_chain_id().as_be_bytes()[..] == chain_id[..])
+                    .ok_or_else(|| {
+                        StorageError::corruption(
+                            "meta",
+                            format!("Unknown chain id 0x{}", hex::encode(&chain_id)),
+                        )
+                    })
+            })
+            .transpose()
+    }
+
+    /// Binds the database to `chain`, failing with [ChainMismatch] if it is already bound to
+    /// another chain or holds another chain's genesis block.
+    pub fn bind_chain(tx: &Transaction<'_>, chain: Chain) -> anyhow::Result<()> {
+        let stored = match Self::get_chain(tx)? {
+            Some(stored) => Some(stored),
+            None => StarknetBlocksTable::get_chain(tx)?,
+        };
+        if let Some(stored) = stored.filter(|&stored| stored != chain) {
+            return Err(ChainMismatch {
+                stored,
+                configured: chain,
+            }
+            .into());
+        }
+
+        tx.execute(
+            "INSERT OR IGNORE INTO meta (idx, chain_id) VALUES (1, ?)",
+            [chain.starknet_chain_id().as_be_bytes()],
+        )
+        .context("Binding database to chain")?;
+
+        Ok(())
+    }
+}
+
 /// Stores all known [StarknetBlocks][StarknetBlock].
 pub struct StarknetBlocksTable {}
 
@@ -226,6 +321,49 @@ impl StarknetBlocksTable {
         block: &StarknetBlock,
         version: Option<&str>,
     ) -> anyhow::Result<()> {
+        Self::insert_with_timestamp_policy(tx, block, version, TimestampPolicy::Allow)
+    }
+
+    /// Same as [StarknetBlocksTable::insert], but first compares the block's timestamp with its
+    /// parent's and handles a timestamp before the parent's according to `policy`.
+    ///
+    /// [TimestampPolicy::Reject] fails with [NonMonotonicTimestamp], without inserting the
+    /// block. The block is not checked if its parent is not stored.
+    pub fn insert_with_timestamp_policy(
+        tx: &Transaction<'_>,
+        block: &StarknetBlock,
+        version: Option<&str>,
+        policy: TimestampPolicy,
+    ) -> anyhow::Result<()> {
+        if policy != TimestampPolicy::Allow {
+            let parent: Option<StarknetBlockTimestamp> = tx
+                .query_row(
+                    "SELECT timestamp FROM starknet_blocks WHERE hash = ?",
+                    [block.parent_hash],
+                    |row| row.get(0),
+                )
+                .optional()
+                .context("Reading parent timestamp")?;
+
+            if let Some(parent) = parent.filter(|parent| parent.get() > block.timestamp.get()) {
+                metrics::record_timestamp_regression();
+                let error = NonMonotonicTimestamp {
+                    parent,
+                    new: block.timestamp,
+                };
+                match policy {
+                    TimestampPolicy::Reject => return Err(error.into()),
+                    TimestampPolicy::WarnOnly => tracing::warn!(
+                        block = %block.number,
+                        parent = parent.get(),
+                        new = block.timestamp.get(),
+                        "Block timestamp is before its parent's"
+                    ),
+                    TimestampPolicy::Allow => {}
+                }
+            }
+        }
+
         let version_id = if let Some(version) = version {
             Some(StarknetVersionsTable::intern(tx, version)?)
         } else {
@@ -233,15 +371,18 @@ impl StarknetBlocksTable {
         };
 
         tx.execute(
-            r"INSERT INTO starknet_blocks ( number,  hash,  root,  timestamp,  gas_price,  sequencer_address,  version_id)
-                                   VALUES (:number, :hash, :root, :timestamp, :gas_price, :sequencer_address, :version_id)",
+            r"INSERT INTO starknet_blocks ( number,  hash,  parent_hash,  root,  timestamp,  gas_price,  sequencer_address,  transaction_count,  event_count,  version_id)
+                                   VALUES (:number, :hash, :parent_hash, :root, :timestamp, :gas_price, :sequencer_address, :transaction_count, :event_count, :version_id)",
             named_params! {
                 ":number": block.number,
                 ":hash": block.hash,
+                ":parent_hash": block.parent_hash,
                 ":root": block.root,
                 ":timestamp": block.timestamp,
                 ":gas_price": &block.gas_price.to_be_bytes(),
                 ":sequencer_address": block.sequencer_address,
+                ":transaction_count": block.transaction_count,
+                ":event_count": block.event_count,
                 ":version_id": version_id,
             },
         )?;
@@ -249,22 +390,51 @@ impl StarknetBlocksTable {
         Ok(())
     }
 
+    /// Returns the block in the inclusive range `from..=to` whose timestamp is furthest before
+    /// its parent's, or [None] if no timestamp in the range goes backwards.
+    ///
+    /// Ties are resolved in favour of the lowest block number.
+    pub fn max_timestamp_regression(
+        tx: &Transaction<'_>,
+        from: StarknetBlockNumber,
+        to: StarknetBlockNumber,
+    ) -> Result<Option<TimestampRegression>, StorageError> {
+        Ok(tx
+            .query_row(
+                r"SELECT child.number, parent.timestamp, child.timestamp FROM starknet_blocks AS child
+    JOIN starknet_blocks AS parent ON parent.number = child.number - 1
+    WHERE child.number BETWEEN ? AND ? AND child.timestamp < parent.timestamp
+    ORDER BY parent.timestamp - child.timestamp DESC, child.number ASC
+    LIMIT 1",
+                [from, to],
+                |row| {
+                    Ok(TimestampRegression {
+                        block: row.get(0)?,
+                        parent: row.get(1)?,
+                        new: row.get(2)?,
+                    })
+                },
+            )
+            .optional()
+            .context("Scanning block timestamps")?)
+    }
+
     /// Returns the requested [StarknetBlock].
     pub fn get(
         tx: &Transaction<'_>,
         block: StarknetBlocksBlockId,
-    ) -> anyhow::Result<Option<StarknetBlock>> {
+    ) -> Result<Option<StarknetBlock>, StorageError> {
         let mut statement = match block {
             StarknetBlocksBlockId::Number(_) => tx.prepare(
-                "SELECT hash, number, root, timestamp, gas_price, sequencer_address
+                "SELECT hash, number, parent_hash, root, timestamp, gas_price, sequencer_address, transaction_count, event_count
                     FROM starknet_blocks WHERE number = ?",
             ),
             StarknetBlocksBlockId::Hash(_) => tx.prepare(
-                "SELECT hash, number, root, timestamp, gas_price, sequencer_address
+                "SELECT hash, number, parent_hash, root, timestamp, gas_price, sequencer_address, transaction_count, event_count
                     FROM starknet_blocks WHERE hash = ?",
             ),
             StarknetBlocksBlockId::Latest => tx.prepare(
-                "SELECT hash, number, root, timestamp, gas_price, sequencer_address
+                "SELECT hash, number, parent_hash, root, timestamp, gas_price, sequencer_address, transaction_count, event_count
                     FROM starknet_blocks ORDER BY number DESC LIMIT 1",
             ),
         }?;
@@ -283,6 +453,8 @@ impl StarknetBlocksTable {
 
                 let hash = row.get_unwrap("hash");
 
+                let parent_hash = row.get_unwrap("parent_hash");
+
                 let root = row.get_unwrap("root");
 
                 let timestamp = row.get_unwrap("timestamp");
@@ -292,13 +464,20 @@ impl StarknetBlocksTable {
 
                 let sequencer_address = row.get_unwrap("sequencer_address");
 
+                let transaction_count = row.get_unwrap("transaction_count");
+
+                let event_count = row.get_unwrap("event_count");
+
                 let block = StarknetBlock {
                     number,
                     hash,
+                    parent_hash,
                     root,
                     timestamp,
                     gas_price,
                     sequencer_address,
+                    transaction_count,
+                    event_count,
                 };
 
                 Ok(Some(block))
@@ -307,11 +486,198 @@ impl StarknetBlocksTable {
         }
     }
 
+    /// Returns the parent of the requested block, found by its `parent_hash` rather than its
+    /// number. Returns `None` for the genesis block, or if the requested block is unknown.
+    pub fn get_parent(
+        tx: &Transaction<'_>,
+        block: StarknetBlocksBlockId,
+    ) -> Result<Option<StarknetBlock>, StorageError> {
+        let parent_hash = match Self::get(tx, block)? {
+            Some(block) if block.number != StarknetBlockNumber::GENESIS => block.parent_hash,
+            _ => return Ok(None),
+        };
+
+        Self::get(tx, parent_hash.into())
+    }
+
+    /// Returns the [StarknetBlockHeader] of the given block, without reading the remaining columns.
+    pub fn get_header(
+        tx: &Transaction<'_>,
+        block: StarknetBlocksBlockId,
+    ) -> Result<Option<StarknetBlockHeader>, StorageError> {
+        fn parse_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<StarknetBlockHeader> {
+            Ok(StarknetBlockHeader {
+                number: row.get("number")?,
+                hash: row.get("hash")?,
+                parent_hash: row.get("parent_hash")?,
+                root: row.get("root")?,
+            })
+        }
+
+        Ok(match block {
+            StarknetBlocksBlockId::Number(number) => tx.query_row(
+                "SELECT number, hash, parent_hash, root FROM starknet_blocks WHERE number = ?",
+                [number],
+                parse_row,
+            ),
+            StarknetBlocksBlockId::Hash(hash) => tx.query_row(
+                "SELECT number, hash, parent_hash, root FROM starknet_blocks WHERE hash = ?",
+                [hash],
+                parse_row,
+            ),
+            StarknetBlocksBlockId::Latest => tx.query_row(
+                "SELECT number, hash, parent_hash, root FROM starknet_blocks ORDER BY number DESC LIMIT 1",
+                [],
+                parse_row,
+            ),
+        }
+        .optional()
+        .context("Querying block header")?)
+    }
+
+    /// Returns the parent hash of the block at the given height.
+    ///
+    /// The genesis block's parent hash is zero.
+    pub fn get_parent_hash(
+        tx: &Transaction<'_>,
+        number: StarknetBlockNumber,
+    ) -> Result<Option<StarknetBlockHash>, StorageError> {
+        Ok(tx
+            .query_row(
+                "SELECT parent_hash FROM starknet_blocks WHERE number = ?",
+                [number],
+                |row| row.get(0),
+            )
+            .optional()
+            .context("Querying parent hash")?)
+    }
+
+    /// Returns the sequencer version of the block at the given height, if one was stored.
+    pub fn get_version(
+        tx: &Transaction<'_>,
+        number: StarknetBlockNumber,
+    ) -> Result<Option<String>, StorageError> {
+        Ok(tx
+            .query_row(
+                r"SELECT starknet_versions.version FROM starknet_blocks
+    INNER JOIN starknet_versions ON starknet_versions.id = starknet_blocks.version_id
+    WHERE starknet_blocks.number = ?",
+                [number],
+                |row| row.get(0),
+            )
+            .optional()
+            .context("Querying block version")?)
+    }
+
+    /// Returns up to `limit` consecutive [BlockListEntries](BlockListEntry), starting at `from`
+    /// and going towards either the head or genesis.
+    pub fn get_headers_with_version(
+        tx: &Transaction<'_>,
+        from: StarknetBlockNumber,
+        limit: usize,
+        descending: bool,
+    ) -> Result<Vec<BlockListEntry>, StorageError> {
+        let query = if descending {
+            r"SELECT number, hash, timestamp, gas_price, sequencer_address, transaction_count, event_count, starknet_versions.version
+    FROM starknet_blocks
+    LEFT JOIN starknet_versions ON starknet_versions.id = starknet_blocks.version_id
+    WHERE number <= :from ORDER BY number DESC LIMIT :limit"
+        } else {
+            r"SELECT number, hash, timestamp, gas_price, sequencer_address, transaction_count, event_count, starknet_versions.version
+    FROM starknet_blocks
+    LEFT JOIN starknet_versions ON starknet_versions.id = starknet_blocks.version_id
+    WHERE number >= :from ORDER BY number ASC LIMIT :limit"
+        };
+
+        let mut stmt = tx.prepare(query).context("Preparing statement")?;
+        let entries = stmt
+            .query_map(
+                named_params! {
+                    ":from": from,
+                    ":limit": limit,
+                },
+                Self::block_list_entry,
+            )
+            .context("Executing query")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Iterating over rows")?;
+
+        Ok(entries)
+    }
+
+    fn block_list_entry(row: &rusqlite::Row<'_>) -> rusqlite::Result<BlockListEntry> {
+        let gas_price = row.get_ref("gas_price")?.as_blob()?;
+        let gas_price = GasPrice::from_be_slice(gas_price).unwrap();
+
+        Ok(BlockListEntry {
+            number: row.get("number")?,
+            hash: row.get("hash")?,
+            timestamp: row.get("timestamp")?,
+            gas_price,
+            sequencer_address: row.get("sequencer_address")?,
+            transaction_count: row.get("transaction_count")?,
+            event_count: row.get("event_count")?,
+            version: row.get("version")?,
+        })
+    }
+
+    /// Returns up to `limit` [BlockListEntries](BlockListEntry) of the blocks produced by
+    /// `sequencer_address`, from the head towards genesis.
+    ///
+    /// Only blocks before `from` are returned, so passing the last number of a previous call
+    /// continues the listing. Blocks without a sequencer address are stored with a zero address,
+    /// and are listed for [SequencerAddress] zero.
+    pub fn get_blocks_by_sequencer(
+        tx: &Transaction<'_>,
+        sequencer_address: SequencerAddress,
+        from: Option<StarknetBlockNumber>,
+        limit: usize,
+    ) -> Result<Vec<BlockListEntry>, StorageError> {
+        let mut stmt = tx
+            .prepare(
+                r"SELECT number, hash, timestamp, gas_price, sequencer_address, transaction_count, event_count, starknet_versions.version
+    FROM starknet_blocks
+    LEFT JOIN starknet_versions ON starknet_versions.id = starknet_blocks.version_id
+    WHERE sequencer_address = :sequencer_address AND (:from IS NULL OR number < :from)
+    ORDER BY number DESC LIMIT :limit",
+            )
+            .context("Preparing statement")?;
+        let entries = stmt
+            .query_map(
+                named_params! {
+                    ":sequencer_address": sequencer_address,
+                    ":from": from,
+                    ":limit": limit,
+                },
+                Self::block_list_entry,
+            )
+            .context("Executing query")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Iterating over rows")?;
+
+        Ok(entries)
+    }
+
+    /// Returns the number of blocks produced by `sequencer_address`, see
+    /// [StarknetBlocksTable::get_blocks_by_sequencer].
+    pub fn count_blocks_by_sequencer(
+        tx: &Transaction<'_>,
+        sequencer_address: SequencerAddress,
+    ) -> Result<u64, StorageError> {
+        Ok(tx
+            .query_row(
+                "SELECT COUNT(1) FROM starknet_blocks WHERE sequencer_address = ?",
+                [sequencer_address],
+                |row| row.get(0),
+            )
+            .context("Counting blocks")?)
+    }
+
     /// Returns the [root](GlobalRoot) of the given block.
     pub fn get_root(
         tx: &Transaction<'_>,
         block: StarknetBlocksBlockId,
-    ) -> anyhow::Result<Option<GlobalRoot>> {
+    ) -> Result<Option<GlobalRoot>, StorageError> {
         match block {
             StarknetBlocksBlockId::Number(number) => tx.query_row(
                 "SELECT root FROM starknet_blocks WHERE number = ?",
@@ -344,7 +710,9 @@ impl StarknetBlocksTable {
     }
 
     /// Returns the [number](StarknetBlockNumber) of the latest block.
-    pub fn get_latest_number(tx: &Transaction<'_>) -> anyhow::Result<Option<StarknetBlockNumber>> {
+    pub fn get_latest_number(
+        tx: &Transaction<'_>,
+    ) -> Result<Option<StarknetBlockNumber>, StorageError> {
         let maybe = tx
             .query_row(
                 "SELECT number FROM starknet_blocks ORDER BY number DESC LIMIT 1",
@@ -355,10 +723,51 @@ impl StarknetBlocksTable {
         Ok(maybe)
     }
 
+    /// Returns the [number](StarknetBlockNumber) of the highest block whose timestamp is at most
+    /// `timestamp`, i.e. the block which was the latest at that time.
+    ///
+    /// Returns `None` if `timestamp` is before the genesis block.
+    pub fn find_by_timestamp(
+        tx: &Transaction<'_>,
+        timestamp: StarknetBlockTimestamp,
+    ) -> Result<Option<StarknetBlockNumber>, StorageError> {
+        Ok(tx.query_row(
+            "SELECT number FROM starknet_blocks WHERE timestamp <= ? ORDER BY timestamp DESC, number DESC LIMIT 1",
+            [timestamp],
+            |row| row.get(0),
+        )
+        .optional()
+        .context("Querying block by timestamp")?)
+    }
+
+    /// Returns the highest stored block whose number is at most `number`, i.e. the requested
+    /// block or the nearest one before it.
+    ///
+    /// Returns `None` if there is no such block, e.g. if `number` is below the oldest block of a
+    /// pruned database.
+    pub fn latest_on_or_before(
+        tx: &Transaction<'_>,
+        number: StarknetBlockNumber,
+    ) -> Result<Option<StarknetBlock>, StorageError> {
+        let nearest: Option<StarknetBlockNumber> = tx
+            .query_row(
+                "SELECT number FROM starknet_blocks WHERE number <= ? ORDER BY number DESC LIMIT 1",
+                [number],
+                |row| row.get(0),
+            )
+            .optional()
+            .context("Querying nearest block number")?;
+
+        match nearest {
+            Some(nearest) => Self::get(tx, nearest.into()),
+            None => Ok(None),
+        }
+    }
+
     /// Returns the [hash](StarknetBlockHash) and [number](StarknetBlockNumber) of the latest block.
     pub fn get_latest_hash_and_number(
         tx: &Transaction<'_>,
-    ) -> anyhow::Result<Option<(StarknetBlockHash, StarknetBlockNumber)>> {
+    ) -> Result<Option<(StarknetBlockHash, StarknetBlockNumber)>, StorageError> {
         let maybe = tx
             .query_row(
                 "SELECT hash, number FROM starknet_blocks ORDER BY number DESC LIMIT 1",
@@ -376,7 +785,7 @@ impl StarknetBlocksTable {
     pub fn get_number(
         tx: &Transaction<'_>,
         hash: StarknetBlockHash,
-    ) -> anyhow::Result<Option<StarknetBlockNumber>> {
+    ) -> Result<Option<StarknetBlockNumber>, StorageError> {
         tx.query_row(
             "SELECT number FROM starknet_blocks WHERE hash = ? LIMIT 1",
             [hash],
@@ -386,8 +795,113 @@ impl StarknetBlocksTable {
         .map_err(|e| e.into())
     }
 
+    /// Resolves any [StarknetBlocksBlockId] to the block's [number](StarknetBlockNumber).
+    ///
+    /// Returns `None` if the block does not exist.
+    pub fn get_number_or_latest(
+        tx: &Transaction<'_>,
+        block: StarknetBlocksBlockId,
+    ) -> Result<Option<StarknetBlockNumber>, StorageError> {
+        match block {
+            StarknetBlocksBlockId::Number(number) => tx
+                .query_row(
+                    "SELECT number FROM starknet_blocks WHERE number = ?",
+                    [number],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(|e| e.into()),
+            StarknetBlocksBlockId::Hash(hash) => Self::get_number(tx, hash),
+            StarknetBlocksBlockId::Latest => Self::get_latest_number(tx),
+        }
+    }
+
+    /// Resolves any [StarknetBlocksBlockId] to the block's [number](StarknetBlockNumber) and
+    /// [hash](StarknetBlockHash) in a single query.
+    ///
+    /// Returns `None` if the block does not exist.
+    pub fn get_number_and_hash(
+        tx: &Transaction<'_>,
+        block: StarknetBlocksBlockId,
+    ) -> Result<Option<(StarknetBlockNumber, StarknetBlockHash)>, StorageError> {
+        let to_pair = |row: &rusqlite::Row<'_>| Ok((row.get(0)?, row.get(1)?));
+        match block {
+            StarknetBlocksBlockId::Number(number) => tx.query_row(
+                "SELECT number, hash FROM starknet_blocks WHERE number = ?",
+                [number],
+                to_pair,
+            ),
+            StarknetBlocksBlockId::Hash(hash) => tx.query_row(
+                "SELECT number, hash FROM starknet_blocks WHERE hash = ?",
+                [hash],
+                to_pair,
+            ),
+            StarknetBlocksBlockId::Latest => tx.query_row(
+                "SELECT number, hash FROM starknet_blocks ORDER BY number DESC LIMIT 1",
+                [],
+                to_pair,
+            ),
+        }
+        .optional()
+        .map_err(|e| e.into())
+    }
+
+    /// Returns the [number](StarknetBlockNumber) and [hash](StarknetBlockHash) of every block
+    /// in the inclusive range `from..=to`, ordered by block number.
+    ///
+    /// Intended for comparing block hashes (e.g. against a peer) without decoding full blocks.
+    pub fn get_hashes_in_range(
+        tx: &Transaction<'_>,
+        from: StarknetBlockNumber,
+        to: StarknetBlockNumber,
+    ) -> Result<Vec<(StarknetBlockNumber, StarknetBlockHash)>, StorageError> {
+        let mut stmt = tx
+            .prepare(
+                "SELECT number, hash FROM starknet_blocks WHERE number BETWEEN ? AND ? ORDER BY number ASC",
+            )
+            .context("Preparing statement")?;
+
+        let hashes = stmt
+            .query_map([from, to], |row| Ok((row.get_unwrap(0), row.get_unwrap(1))))
+            .context("Executing query")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Iterating over rows")?;
+
+        Ok(hashes)
+    }
+
+    /// Returns the [number](StarknetBlockNumber) and [gas price](GasPrice) of every block in the
+    /// inclusive range `from..=to`, ordered by block number.
+    ///
+    /// Intended for plotting gas prices over time without decoding full blocks.
+    pub fn get_gas_prices_in_range(
+        tx: &Transaction<'_>,
+        from: StarknetBlockNumber,
+        to: StarknetBlockNumber,
+    ) -> Result<Vec<(StarknetBlockNumber, GasPrice)>, StorageError> {
+        let mut stmt = tx
+            .prepare(
+                "SELECT number, gas_price FROM starknet_blocks WHERE number BETWEEN ? AND ? ORDER BY number ASC",
+            )
+            .context("Preparing statement")?;
+
+        let mut rows = stmt.query([from, to]).context("Executing query")?;
+
+        let mut prices = Vec::new();
+        while let Some(row) = rows.next().context("Iterating over rows")? {
+            let number: StarknetBlockNumber = row.get_unwrap("number");
+            let gas_price = row.get_ref_unwrap("gas_price").as_blob()?;
+            let gas_price = GasPrice::from_be_slice(gas_price)
+                .with_context(|| format!("Parsing gas price of block {number}"))?;
+
+            prices.push((number, gas_price));
+        }
+
+        Ok(prices)
+    }
+
     /// Returns the [chain](crate::core::Chain) based on genesis block hash stored in the DB.
-    pub fn get_chain(tx: &Transaction<'_>) -> anyhow::Result<Option<Chain>> {
+    pub fn get_chain(tx: &Transaction<'_>) -> Result<Option<Chain>, StorageError> {
         let genesis = Self::get_hash(tx, StarknetBlockNumber::GENESIS.into())
             .context("Read genesis block from database")?;
 
@@ -396,7 +910,7 @@ impl StarknetBlocksTable {
             Some(hash) if hash == TESTNET_GENESIS_HASH => Ok(Some(Chain::Testnet)),
             Some(hash) if hash == MAINNET_GENESIS_HASH => Ok(Some(Chain::Mainnet)),
             Some(hash) if hash == INTEGRATION_GENESIS_HASH => Ok(Some(Chain::Integration)),
-            Some(hash) => Err(anyhow::anyhow!("Unknown genesis block hash {}", hash.0)),
+            Some(hash) => Err(anyhow::anyhow!("Unknown genesis block hash {}", hash.0).into()),
         }
     }
 
@@ -404,7 +918,7 @@ impl StarknetBlocksTable {
     pub fn get_hash(
         tx: &Transaction<'_>,
         block: StarknetBlocksNumberOrLatest,
-    ) -> anyhow::Result<Option<StarknetBlockHash>> {
+    ) -> Result<Option<StarknetBlockHash>, StorageError> {
         match block {
             StarknetBlocksNumberOrLatest::Number(n) => tx.query_row(
                 "SELECT hash FROM starknet_blocks WHERE number = ?",
@@ -422,6 +936,47 @@ impl StarknetBlocksTable {
     }
 }
 
+/// How [StarknetBlocksTable::insert_with_timestamp_policy] handles a block whose timestamp is
+/// before its parent's.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TimestampPolicy {
+    /// Fail with [NonMonotonicTimestamp].
+    Reject,
+    /// Log a warning and insert the block.
+    WarnOnly,
+    /// Insert the block without checking its timestamp.
+    #[default]
+    Allow,
+}
+
+/// A block's timestamp is before its parent's, see [TimestampPolicy::Reject].
+#[derive(Copy, Clone, Debug, thiserror::Error, PartialEq, Eq)]
+#[error(
+    "block timestamp {} is before its parent's timestamp {}",
+    .new.get(),
+    .parent.get()
+)]
+pub struct NonMonotonicTimestamp {
+    pub parent: StarknetBlockTimestamp,
+    pub new: StarknetBlockTimestamp,
+}
+
+/// A block whose timestamp is before its parent's, as found by
+/// [StarknetBlocksTable::max_timestamp_regression].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TimestampRegression {
+    pub block: StarknetBlockNumber,
+    pub parent: StarknetBlockTimestamp,
+    pub new: StarknetBlockTimestamp,
+}
+
+impl TimestampRegression {
+    /// The number of seconds the timestamp went backwards.
+    pub fn seconds(&self) -> u64 {
+        self.parent.get() - self.new.get()
+    }
+}
+
 /// Identifies block in some [StarknetBlocksTable] queries.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StarknetBlocksBlockId {
@@ -471,106 +1026,387 @@ impl TryFrom<StarknetBlocksBlockId> for StarknetBlocksNumberOrLatest {
     }
 }
 
+/// Proves that a transaction is part of its block's transaction commitment, see
+/// [StarknetTransactionsTable::get_inclusion_proof].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TransactionInclusionProof {
+    pub block_hash: StarknetBlockHash,
+    pub transaction_hash: StarknetTransactionHash,
+    pub transaction_index: u64,
+    /// The commitment tree's leaf, i.e. the hash of the transaction and its signature.
+    pub leaf: StarkHash,
+    pub transaction_commitment: StarkHash,
+    /// The commitment tree's nodes, from the root towards the leaf.
+    pub nodes: Vec<ProofNode>,
+}
+
+impl TransactionInclusionProof {
+    /// Checks that the proof links the [leaf](Self::leaf) at the transaction's index to the
+    /// [transaction commitment](Self::transaction_commitment).
+    pub fn verify(&self) -> bool {
+        let key = self.transaction_index.to_be_bytes();
+        ProofNode::verify(
+            &self.nodes,
+            self.transaction_commitment,
+            key.view_bits(),
+            self.leaf,
+        )
+    }
+}
+
+/// Reads a [Fee] stored as a 16 byte big-endian blob.
+fn fee_from_sql(blob: &[u8]) -> anyhow::Result<Fee> {
+    let bytes: [u8; 16] = blob.try_into().context("Fee has invalid length")?;
+    Ok(Fee(bytes.into()))
+}
+
+/// Aggregate statistics over the actual fees of the transactions in a block range, see
+/// [StarknetTransactionsTable::fee_stats].
+///
+/// Transactions without a fee, i.e. from before fees were charged, are excluded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeeStats {
+    pub total: Fee,
+    pub count: usize,
+    pub max: Option<Fee>,
+    pub min: Option<Fee>,
+}
+
+/// The execution resources used by a block's transactions, see
+/// [StarknetTransactionsTable::get_block_execution_summary].
+///
+/// Transactions without execution resources count as zero, but are included in `tx_count`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExecutionSummary {
+    pub total_steps: u64,
+    pub total_memory_holes: u64,
+    pub tx_count: usize,
+}
+
+/// Returned by [StarknetTransactionsTable::upsert_strict] if a transaction is already stored for
+/// another block.
+#[derive(Copy, Clone, Debug, thiserror::Error, PartialEq, Eq)]
+#[error(
+    "transaction {} is already stored for block {}, not {}",
+    .transaction_hash.0,
+    .existing_block.0,
+    .new_block.0
+)]
+pub struct TransactionBlockConflict {
+    pub transaction_hash: StarknetTransactionHash,
+    pub existing_block: StarknetBlockHash,
+    pub new_block: StarknetBlockHash,
+}
+
 /// Stores all known starknet transactions
 pub struct StarknetTransactionsTable {}
 
 impl StarknetTransactionsTable {
-    /// Inserts a Starknet block's transactions and transaction receipts into the [StarknetTransactionsTable].
-    ///
-    /// overwrites existing data if the transaction hash already exists.
+    /// Same as [StarknetTransactionsTable::upsert_with_compressor], using the default
+    /// [CompressionConfig](crate::storage::CompressionConfig) rather than the one of the
+    /// [Storage](crate::storage::Storage).
+    #[cfg(test)]
     pub fn upsert(
         tx: &Transaction<'_>,
         block_hash: StarknetBlockHash,
         block_number: StarknetBlockNumber,
         transaction_data: &[(transaction::Transaction, transaction::Receipt)],
     ) -> anyhow::Result<()> {
-        if transaction_data.is_empty() {
-            return Ok(());
-        }
-
-        let mut compressor = zstd::bulk::Compressor::new(10).context("Create zstd compressor")?;
-        for (i, (transaction, receipt)) in transaction_data.iter().enumerate() {
-            // Serialize and compress transaction data.
-            let tx_data =
-                serde_json::ser::to_vec(&transaction).context("Serialize Starknet transaction")?;
-            let tx_data = compressor
-                .compress(&tx_data)
-                .context("Compress Starknet transaction")?;
+        let mut compressor = crate::storage::CompressionConfig::default().compressor()?;
+        Self::upsert_with_compressor(
+            tx,
+            &mut compressor,
+            block_hash,
+            block_number,
+            transaction_data,
+        )
+    }
 
-            let serialized_receipt = serde_json::ser::to_vec(&receipt)
-                .context("Serialize Starknet transaction receipt")?;
-            let serialized_receipt = compressor
+    /// Same as [StarknetTransactionsTable::upsert_with_compressor] but fails with
+    /// [TransactionBlockConflict], without writing anything, if any of the transactions is
+    /// already stored for another block.
+    ///
+    /// A transaction can only belong to a single block, so a conflict means that two candidate
+    /// blocks claim it. Resyncing the same block is not a conflict.
+    pub fn upsert_strict(
+        tx: &Transaction<'_>,
+        compressor: &mut zstd::bulk::Compressor<'_>,
+        block_hash: StarknetBlockHash,
+        block_number: StarknetBlockNumber,
+        transaction_data: &[(transaction::Transaction, transaction::Receipt)],
+    ) -> anyhow::Result<()> {
+        Self::upsert_impl(
+            tx,
+            compressor,
+            block_hash,
+            block_number,
+            transaction_data,
+            true,
+        )
+    }
+
+    /// Inserts a Starknet block's transactions and transaction receipts into the
+    /// [StarknetTransactionsTable], compressed using the given `compressor`, which allows
+    /// sharing it with other writes of the same block.
+    ///
+    /// Overwrites existing data if the transaction hash already exists.
+    pub fn upsert_with_compressor(
+        tx: &Transaction<'_>,
+        compressor: &mut zstd::bulk::Compressor<'_>,
+        block_hash: StarknetBlockHash,
+        block_number: StarknetBlockNumber,
+        transaction_data: &[(transaction::Transaction, transaction::Receipt)],
+    ) -> anyhow::Result<()> {
+        Self::upsert_impl(
+            tx,
+            compressor,
+            block_hash,
+            block_number,
+            transaction_data,
+            false,
+        )
+    }
+
+    fn upsert_impl(
+        tx: &Transaction<'_>,
+        compressor: &mut zstd::bulk::Compressor<'_>,
+        block_hash: StarknetBlockHash,
+        block_number: StarknetBlockNumber,
+        transaction_data: &[(transaction::Transaction, transaction::Receipt)],
+        strict: bool,
+    ) -> anyhow::Result<()> {
+        if transaction_data.is_empty() {
+            return Ok(());
+        }
+
+        if strict {
+            let mut stmt = tx
+                .prepare_cached("SELECT block_hash FROM starknet_transactions WHERE hash = ?")
+                .context("Preparing statement")?;
+            for (transaction, _) in transaction_data {
+                let existing: Option<StarknetBlockHash> = stmt
+                    .query_row([transaction.hash()], |row| row.get(0))
+                    .optional()
+                    .context("Reading transaction's block")?;
+
+                match existing {
+                    Some(existing) if existing != block_hash => {
+                        return Err(TransactionBlockConflict {
+                            transaction_hash: transaction.hash(),
+                            existing_block: existing,
+                            new_block: block_hash,
+                        }
+                        .into())
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let timer = WriteTimer::start(metrics::UPSERT_TRANSACTIONS);
+        let span = tracing::debug_span!(
+            "upsert_transactions",
+            %block_number,
+            transactions = transaction_data.len(),
+            bytes = tracing::field::Empty,
+        )
+        .entered();
+        let mut bytes = 0;
+
+        for (i, (transaction, receipt)) in transaction_data.iter().enumerate() {
+            // Serialize and compress transaction data.
+            let tx_data =
+                serde_json::ser::to_vec(&transaction).context("Serialize Starknet transaction")?;
+            let tx_data = compressor
+                .compress(&tx_data)
+                .context("Compress Starknet transaction")?;
+
+            let serialized_receipt = serde_json::ser::to_vec(&receipt)
+                .context("Serialize Starknet transaction receipt")?;
+            let serialized_receipt = compressor
                 .compress(&serialized_receipt)
                 .context("Compress Starknet transaction receipt")?;
 
-            tx.execute(r"INSERT OR REPLACE INTO starknet_transactions (hash, idx, block_hash, tx, receipt) VALUES (:hash, :idx, :block_hash, :tx, :receipt)",
+            bytes += tx_data.len() + serialized_receipt.len();
+
+            let execution_status = match receipt.execution_status {
+                transaction::ExecutionStatus::Succeeded => 0,
+                transaction::ExecutionStatus::Reverted => 1,
+            };
+
+            tx.execute(r"INSERT OR REPLACE INTO starknet_transactions (hash, idx, block_hash, tx, receipt, actual_fee, execution_status, n_steps, n_memory_holes, class_hash, events_count, messages_count)
+                VALUES (:hash, :idx, :block_hash, :tx, :receipt, :actual_fee, :execution_status, :n_steps, :n_memory_holes, :class_hash, :events_count, :messages_count)",
                        named_params![
                     ":hash": transaction.hash(),
                     ":idx": i,
                     ":block_hash": block_hash,
                     ":tx": &tx_data,
                     ":receipt": &serialized_receipt,
+                    ":actual_fee": receipt.actual_fee.as_ref().map(|fee| fee.0.as_bytes()),
+                    ":execution_status": execution_status,
+                    ":n_steps": receipt.execution_resources.map(|r| r.n_steps),
+                    ":n_memory_holes": receipt.execution_resources.map(|r| r.n_memory_holes),
+                    ":class_hash": transaction.class_hash(),
+                    ":events_count": receipt.events.len(),
+                    ":messages_count": receipt.l2_to_l1_messages.len(),
                 ]).context("Insert transaction data into transactions table")?;
 
-            // insert events from receipt
-            StarknetEventsTable::insert_events(
-                tx,
-                block_number,
-                receipt.transaction_hash,
-                &receipt.events,
-            )
-            .context("Inserting events")?;
+            if let Some(nonce) = receipt
+                .l1_to_l2_consumed_message
+                .as_ref()
+                .and_then(|message| message.nonce)
+            {
+                L1HandlerOriginsTable::insert(tx, receipt.transaction_hash, nonce)
+                    .context("Inserting L1 handler origin")?;
+            }
         }
 
+        // Insert the events of all receipts at once.
+        let events = transaction_data
+            .iter()
+            .map(|(_, receipt)| (receipt.transaction_hash, receipt.events.as_slice()))
+            .collect::<Vec<_>>();
+        StarknetEventsTable::insert_events_batch(tx, block_number, &events)
+            .context("Inserting events")?;
+
+        span.record("bytes", &bytes);
+        timer.finish(transaction_data.len(), bytes);
+
         Ok(())
     }
 
-    pub fn get_transaction_data_for_block(
+    /// Returns the hashes of the block's reverted transactions, in block order.
+    ///
+    /// Returns an empty list if the block is not known.
+    pub fn get_failed_transactions_for_block(
         tx: &Transaction<'_>,
         block: StarknetBlocksBlockId,
-    ) -> anyhow::Result<Vec<(transaction::Transaction, transaction::Receipt)>> {
-        // Identify block hash
-        let block_hash = match block {
-            StarknetBlocksBlockId::Number(number) => {
-                match StarknetBlocksTable::get(tx, number.into())? {
-                    Some(block) => block.hash,
-                    None => return Ok(Vec::new()),
-                }
-            }
-            StarknetBlocksBlockId::Hash(hash) => hash,
-            StarknetBlocksBlockId::Latest => {
-                match StarknetBlocksTable::get(tx, StarknetBlocksBlockId::Latest)? {
-                    Some(block) => block.hash,
-                    None => return Ok(Vec::new()),
-                }
-            }
-        };
+    ) -> Result<Vec<StarknetTransactionHash>, StorageError> {
+        let (block_condition, params) = Self::block_condition(&block);
+        let mut stmt = tx
+            .prepare_cached(&format!(
+                "SELECT hash FROM starknet_transactions WHERE {block_condition} AND execution_status = 1 ORDER BY idx ASC"
+            ))
+            .context("Preparing statement")?;
+
+        let hashes = stmt
+            .query_map(params.as_slice(), |row| row.get(0))
+            .context("Executing query")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Reading transaction hashes")?;
+
+        Ok(hashes)
+    }
+
+    /// Returns an SQL condition selecting the transactions of `block`, and its named parameters.
+    ///
+    /// Block numbers and `latest` are resolved to a block hash within the same statement, so that
+    /// callers need only a single query.
+    fn block_condition(
+        block: &StarknetBlocksBlockId,
+    ) -> (&'static str, Vec<(&'static str, &dyn rusqlite::ToSql)>) {
+        match block {
+            StarknetBlocksBlockId::Number(number) => (
+                "block_hash = (SELECT hash FROM starknet_blocks WHERE number = :block)",
+                vec![(":block", number)],
+            ),
+            StarknetBlocksBlockId::Hash(hash) => ("block_hash = :block", vec![(":block", hash)]),
+            StarknetBlocksBlockId::Latest => (
+                "block_hash = (SELECT hash FROM starknet_blocks ORDER BY number DESC LIMIT 1)",
+                vec![],
+            ),
+        }
+    }
 
+    /// Returns the hashes of the block's transactions, in block order, without decompressing
+    /// them. Returns an empty list if the block does not exist.
+    pub fn get_transaction_hashes_for_block(
+        tx: &Transaction<'_>,
+        block: StarknetBlocksBlockId,
+    ) -> Result<Vec<StarknetTransactionHash>, StorageError> {
+        let (block_condition, params) = Self::block_condition(&block);
         let mut stmt = tx
-            .prepare(
-                "SELECT tx, receipt FROM starknet_transactions WHERE block_hash = ? ORDER BY idx ASC",
+            .prepare_cached(&format!(
+                "SELECT hash FROM starknet_transactions WHERE {block_condition} ORDER BY idx ASC"
+            ))
+            .context("Preparing statement")?;
+
+        let hashes = stmt
+            .query_map(params.as_slice(), |row| row.get(0))
+            .context("Executing query")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Reading transaction hashes")?;
+
+        Ok(hashes)
+    }
+
+    pub fn get_transaction_data_for_block(
+        tx: &Transaction<'_>,
+        block: StarknetBlocksBlockId,
+    ) -> Result<Vec<(transaction::Transaction, transaction::Receipt)>, StorageError> {
+        let (block_condition, params) = Self::block_condition(&block);
+        let mut stmt = tx
+            .prepare(&format!(
+                "SELECT tx, receipt FROM starknet_transactions WHERE {block_condition} ORDER BY idx ASC"
+            ))
+            .context("Preparing statement")?;
+
+        let rows = stmt.query(params.as_slice()).context("Executing query")?;
+
+        Self::decode_transaction_data(rows)
+    }
+
+    /// Like [get_transaction_data_for_block](Self::get_transaction_data_for_block), but returns
+    /// at most `limit` transactions starting at index `offset`, along with the total number of
+    /// transactions in the block.
+    pub fn get_transaction_data_for_block_paged(
+        tx: &Transaction<'_>,
+        block: StarknetBlocksBlockId,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<(transaction::Transaction, transaction::Receipt)>, usize), StorageError> {
+        let (block_condition, mut params) = Self::block_condition(&block);
+
+        let total: usize = tx
+            .query_row(
+                &format!("SELECT COUNT(*) FROM starknet_transactions WHERE {block_condition}"),
+                params.as_slice(),
+                |row| row.get(0),
             )
+            .context("Counting transactions")?;
+
+        params.push((":offset", &offset));
+        params.push((":limit", &limit));
+        let mut stmt = tx
+            .prepare(&format!(
+                "SELECT tx, receipt FROM starknet_transactions WHERE {block_condition}
+                    ORDER BY idx ASC LIMIT :limit OFFSET :offset"
+            ))
             .context("Preparing statement")?;
 
-        let mut rows = stmt.query([block_hash]).context("Executing query")?;
+        let rows = stmt.query(params.as_slice()).context("Executing query")?;
+        let data = Self::decode_transaction_data(rows)?;
 
+        Ok((data, total))
+    }
+
+    fn decode_transaction_data(
+        mut rows: rusqlite::Rows<'_>,
+    ) -> Result<Vec<(transaction::Transaction, transaction::Receipt)>, StorageError> {
         let mut data = Vec::new();
         while let Some(row) = rows.next()? {
             let receipt = row
                 .get_ref_unwrap("receipt")
                 .as_blob_or_null()?
-                .context("Receipt data missing")?;
-            let receipt = zstd::decode_all(receipt).context("Decompressing transaction receipt")?;
-            let receipt =
-                serde_json::from_slice(&receipt).context("Deserializing transaction receipt")?;
+                .ok_or_else(|| {
+                    StorageError::corruption("starknet_transactions", "Receipt data missing")
+                })?;
+            let receipt = decode_blob(receipt, "starknet_transactions", "transaction receipt")?;
 
-            let transaction = row
-                .get_ref_unwrap("tx")
-                .as_blob_or_null()?
-                .context("Transaction data missing")?;
-            let transaction = zstd::decode_all(transaction).context("Decompressing transaction")?;
-            let transaction =
-                serde_json::from_slice(&transaction).context("Deserializing transaction")?;
+            let transaction = row.get_ref_unwrap("tx").as_blob_or_null()?.ok_or_else(|| {
+                StorageError::corruption("starknet_transactions", "Transaction data missing")
+            })?;
+            let transaction = decode_blob(transaction, "starknet_transactions", "transaction")?;
 
             data.push((transaction, receipt));
         }
@@ -580,7 +1416,7 @@ impl StarknetTransactionsTable {
 
     pub fn get_transactions_for_latest_block(
         sqlite_tx: &Transaction<'_>,
-    ) -> anyhow::Result<Vec<transaction::Transaction>> {
+    ) -> Result<Vec<transaction::Transaction>, StorageError> {
         let mut stmt = sqlite_tx
             .prepare(
                 r"SELECT tx FROM starknet_transactions
@@ -594,13 +1430,10 @@ impl StarknetTransactionsTable {
 
         let mut data = Vec::new();
         while let Some(row) = rows.next()? {
-            let starknet_tx = row
-                .get_ref_unwrap("tx")
-                .as_blob_or_null()?
-                .context("Transaction data missing")?;
-            let starknet_tx = zstd::decode_all(starknet_tx).context("Decompressing transaction")?;
-            let starknet_tx =
-                serde_json::from_slice(&starknet_tx).context("Deserializing transaction")?;
+            let starknet_tx = row.get_ref_unwrap("tx").as_blob_or_null()?.ok_or_else(|| {
+                StorageError::corruption("starknet_transactions", "Transaction data missing")
+            })?;
+            let starknet_tx = decode_blob(starknet_tx, "starknet_transactions", "transaction")?;
 
             data.push(starknet_tx);
         }
@@ -612,31 +1445,16 @@ impl StarknetTransactionsTable {
         tx: &Transaction<'_>,
         block: StarknetBlocksBlockId,
         index: usize,
-    ) -> anyhow::Result<Option<transaction::Transaction>> {
-        // Identify block hash
-        let block_hash = match block {
-            StarknetBlocksBlockId::Number(number) => {
-                match StarknetBlocksTable::get(tx, number.into())? {
-                    Some(block) => block.hash,
-                    None => return Ok(None),
-                }
-            }
-            StarknetBlocksBlockId::Hash(hash) => hash,
-            StarknetBlocksBlockId::Latest => {
-                match StarknetBlocksTable::get(tx, StarknetBlocksBlockId::Latest)? {
-                    Some(block) => block.hash,
-                    None => return Ok(None),
-                }
-            }
-        };
-
+    ) -> Result<Option<transaction::Transaction>, StorageError> {
+        let (block_condition, mut params) = Self::block_condition(&block);
+        params.push((":idx", &index));
         let mut stmt = tx
-            .prepare("SELECT tx FROM starknet_transactions WHERE block_hash = ? AND idx = ?")
+            .prepare(&format!(
+                "SELECT tx FROM starknet_transactions WHERE {block_condition} AND idx = :idx"
+            ))
             .context("Preparing statement")?;
 
-        let mut rows = stmt
-            .query(params![block_hash, index])
-            .context("Executing query")?;
+        let mut rows = stmt.query(params.as_slice()).context("Executing query")?;
 
         let row = match rows.next()? {
             Some(row) => row,
@@ -648,9 +1466,7 @@ impl StarknetTransactionsTable {
             None => return Ok(None),
         };
 
-        let transaction = zstd::decode_all(transaction).context("Decompressing transaction")?;
-        let transaction =
-            serde_json::from_slice(&transaction).context("Deserializing transaction")?;
+        let transaction = decode_blob(transaction, "starknet_transactions", "transaction")?;
 
         Ok(Some(transaction))
     }
@@ -658,7 +1474,7 @@ impl StarknetTransactionsTable {
     pub fn get_receipt(
         tx: &Transaction<'_>,
         transaction: StarknetTransactionHash,
-    ) -> anyhow::Result<Option<(transaction::Receipt, StarknetBlockHash)>> {
+    ) -> Result<Option<(transaction::Receipt, StarknetBlockHash)>, StorageError> {
         let mut stmt = tx
             .prepare("SELECT receipt, block_hash FROM starknet_transactions WHERE hash = ?1")
             .context("Preparing statement")?;
@@ -676,8 +1492,7 @@ impl StarknetTransactionsTable {
             Some(data) => data,
             None => return Ok(None),
         };
-        let receipt = zstd::decode_all(receipt).context("Decompressing transaction")?;
-        let receipt = serde_json::from_slice(&receipt).context("Deserializing transaction")?;
+        let receipt = decode_blob(receipt, "starknet_transactions", "transaction receipt")?;
 
         let block_hash = row.get_unwrap("block_hash");
 
@@ -687,7 +1502,7 @@ impl StarknetTransactionsTable {
     pub fn get_transaction(
         tx: &Transaction<'_>,
         transaction: StarknetTransactionHash,
-    ) -> anyhow::Result<Option<transaction::Transaction>> {
+    ) -> Result<Option<transaction::Transaction>, StorageError> {
         let mut stmt = tx
             .prepare("SELECT tx FROM starknet_transactions WHERE hash = ?1")
             .context("Preparing statement")?;
@@ -700,1977 +1515,11049 @@ impl StarknetTransactionsTable {
         };
 
         let transaction = row.get_ref_unwrap(0).as_blob()?;
-        let transaction = zstd::decode_all(transaction).context("Decompressing transaction")?;
-        let transaction =
-            serde_json::from_slice(&transaction).context("Deserializing transaction")?;
+        let transaction = decode_blob(transaction, "starknet_transactions", "transaction")?;
 
         Ok(Some(transaction))
     }
 
-    pub fn get_transaction_count(
+    /// Returns the transaction which declared the class, or deployed an instance of it, along
+    /// with the hash of its block.
+    ///
+    /// If the class was declared more than once, the earliest transaction of the canonical chain
+    /// is returned.
+    pub fn get_declare_transaction_for_class(
         tx: &Transaction<'_>,
-        block: StarknetBlocksBlockId,
-    ) -> anyhow::Result<usize> {
-        match block {
-            StarknetBlocksBlockId::Number(number) => tx
-                .query_row(
-                    "SELECT COUNT(*) FROM starknet_transactions
-                    JOIN starknet_blocks ON starknet_transactions.block_hash = starknet_blocks.hash
-                    WHERE number = ?1",
-                    [number],
-                    |row| row.get(0),
-                )
-                .context("Counting transactions"),
-            StarknetBlocksBlockId::Hash(hash) => tx
-                .query_row(
-                    "SELECT COUNT(*) FROM starknet_transactions WHERE block_hash = ?1",
-                    [hash],
-                    |row| row.get(0),
-                )
-                .context("Counting transactions"),
-            StarknetBlocksBlockId::Latest => {
-                // First get the latest block
-                let block = match StarknetBlocksTable::get(tx, StarknetBlocksBlockId::Latest)? {
-                    Some(block) => block.number,
-                    None => return Ok(0),
-                };
-
-                Self::get_transaction_count(tx, block.into())
-            }
-        }
-    }
-}
+        class_hash: ClassHash,
+    ) -> Result<Option<(transaction::Transaction, StarknetBlockHash)>, StorageError> {
+        let mut stmt = tx
+            .prepare(
+                r"SELECT starknet_transactions.tx, starknet_transactions.block_hash FROM starknet_transactions
+                JOIN starknet_blocks ON starknet_blocks.hash = starknet_transactions.block_hash
+                WHERE starknet_transactions.class_hash = ?
+                ORDER BY starknet_blocks.number, starknet_transactions.idx
+                LIMIT 1",
+            )
+            .context("Preparing statement")?;
 
-pub struct StarknetEventFilter {
-    pub from_block: Option<StarknetBlockNumber>,
-    pub to_block: Option<StarknetBlockNumber>,
-    pub contract_address: Option<ContractAddress>,
-    pub keys: Vec<EventKey>,
-    pub page_size: usize,
-    pub page_number: usize,
-}
+        let mut rows = stmt.query([class_hash]).context("Executing query")?;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct StarknetEmittedEvent {
-    pub from_address: ContractAddress,
-    pub data: Vec<EventData>,
-    pub keys: Vec<EventKey>,
-    pub block_hash: StarknetBlockHash,
-    pub block_number: StarknetBlockNumber,
-    pub transaction_hash: StarknetTransactionHash,
-}
+        let row = match rows.next()? {
+            Some(row) => row,
+            None => return Ok(None),
+        };
 
-#[derive(Copy, Clone, Debug, thiserror::Error, PartialEq, Eq)]
-pub enum EventFilterError {
-    #[error("requested page size is too big, supported maximum is {0}")]
-    PageSizeTooBig(usize),
-}
+        let transaction = row.get_ref_unwrap(0).as_blob()?;
+        let transaction = decode_blob(transaction, "starknet_transactions", "transaction")?;
+        let block_hash = row.get(1)?;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct PageOfEvents {
-    pub events: Vec<StarknetEmittedEvent>,
-    pub is_last_page: bool,
-}
+        Ok(Some((transaction, block_hash)))
+    }
 
-pub struct StarknetEventsTable {}
+    /// Returns the stored JSON of the transaction, without deserializing it.
+    ///
+    /// The JSON is that of the sequencer's [transaction::Transaction], not of any RPC type, so
+    /// callers serving it over RPC may still need to transform it to comply with the spec.
+    pub fn get_transaction_raw(
+        tx: &Transaction<'_>,
+        transaction: StarknetTransactionHash,
+    ) -> Result<Option<Vec<u8>>, StorageError> {
+        Self::get_raw(tx, "tx", transaction, "transaction")
+    }
 
-impl StarknetEventsTable {
-    pub fn encode_event_data_to_bytes(data: &[EventData], buffer: &mut Vec<u8>) {
-        buffer.extend(data.iter().flat_map(|e| (*e.0.as_be_bytes()).into_iter()))
+    /// Returns the stored JSON of the transaction's receipt, without deserializing it.
+    ///
+    /// As for [get_transaction_raw](Self::get_transaction_raw), the JSON is that of the
+    /// sequencer's [transaction::Receipt].
+    pub fn get_receipt_raw(
+        tx: &Transaction<'_>,
+        transaction: StarknetTransactionHash,
+    ) -> Result<Option<Vec<u8>>, StorageError> {
+        Self::get_raw(tx, "receipt", transaction, "transaction receipt")
     }
 
-    fn encode_event_key_to_base64(key: &EventKey, buf: &mut String) {
-        base64::encode_config_buf(key.0.as_be_bytes(), base64::STANDARD, buf);
+    fn get_raw(
+        tx: &Transaction<'_>,
+        column: &str,
+        transaction: StarknetTransactionHash,
+        what: &str,
+    ) -> Result<Option<Vec<u8>>, StorageError> {
+        let data: Option<Option<Vec<u8>>> = tx
+            .query_row(
+                &format!("SELECT {column} FROM starknet_transactions WHERE hash = ?"),
+                [transaction],
+                |row| row.get(0),
+            )
+            .optional()
+            .context("Querying transaction")?;
+
+        data.flatten()
+            .map(|data| decompress_blob(&data, "starknet_transactions", what))
+            .transpose()
     }
 
-    pub fn event_keys_to_base64_strings(keys: &[EventKey], out: &mut String) {
-        // with padding it seems 44 bytes are needed for each
-        let needed = (keys.len() * (" ".len() + 44)).saturating_sub(" ".len());
-        if let Some(more) = needed.checked_sub(out.capacity() - out.len()) {
-            out.reserve(more);
+    /// Like [get_transaction](Self::get_transaction), but first looks for the transaction in the
+    /// `pending` block, as pending data is not stored.
+    ///
+    /// The transaction's [status](Status) is [Pending](Status::Pending) if it was found in
+    /// the pending block, and otherwise depends on whether its block has been accepted on L1.
+    pub fn get_transaction_including_pending(
+        tx: &Transaction<'_>,
+        transaction: StarknetTransactionHash,
+        pending: Option<&PendingBlock>,
+    ) -> Result<Option<(transaction::Transaction, Status)>, StorageError> {
+        let pending_transaction = pending.and_then(|pending| {
+            pending
+                .transactions
+                .iter()
+                .find(|pending_transaction| pending_transaction.hash() == transaction)
+        });
+        if let Some(pending_transaction) = pending_transaction {
+            return Ok(Some((pending_transaction.clone(), Status::Pending)));
         }
 
-        let _capacity = out.capacity();
+        let stored = match Self::get_transaction(tx, transaction)? {
+            Some(stored) => stored,
+            None => return Ok(None),
+        };
 
-        keys.iter().enumerate().for_each(|(i, x)| {
-            Self::encode_event_key_to_base64(x, out);
+        let block_number: Option<StarknetBlockNumber> = tx
+            .query_row(
+                "SELECT starknet_blocks.number FROM starknet_transactions
+                    JOIN starknet_blocks ON starknet_transactions.block_hash = starknet_blocks.hash
+                    WHERE starknet_transactions.hash = ?",
+                [transaction],
+                |row| row.get(0),
+            )
+            .optional()
+            .context("Querying block number of transaction")?;
+        let l1_l2_head = RefsTable::get_l1_l2_head(tx).context("Reading L1-L2 head")?;
 
-            if i != keys.len() - 1 {
-                out.push(' ');
-            }
-        });
+        let status = match (block_number, l1_l2_head) {
+            (Some(number), Some(head)) if head >= number => Status::AcceptedOnL1,
+            _ => Status::AcceptedOnL2,
+        };
 
-        debug_assert_eq!(_capacity, out.capacity(), "pre-reservation was not enough");
+        Ok(Some((stored, status)))
     }
 
-    pub fn insert_events(
+    /// Counts the transactions in a block per sender, as given by
+    /// [contract_address](transaction::Transaction::contract_address).
+    ///
+    /// Returns an empty map if the block does not exist.
+    pub fn sender_counts(
         tx: &Transaction<'_>,
-        block_number: StarknetBlockNumber,
-        transaction_hash: StarknetTransactionHash,
-        events: &[transaction::Event],
-    ) -> anyhow::Result<()> {
-        let mut stmt = tx.prepare(
-            r"INSERT INTO starknet_events ( block_number,  idx,  transaction_hash,  from_address,  keys,  data)
-                                   VALUES (:block_number, :idx, :transaction_hash, :from_address, :keys, :data)"
-        )?;
+        block: StarknetBlocksBlockId,
+    ) -> Result<HashMap<ContractAddress, usize>, StorageError> {
+        let block_hash = match block {
+            StarknetBlocksBlockId::Hash(hash) => hash,
+            other => match StarknetBlocksTable::get(tx, other)? {
+                Some(block) => block.hash,
+                None => return Ok(HashMap::new()),
+            },
+        };
 
-        let mut keys = String::new();
-        let mut buffer = Vec::new();
+        let mut stmt = tx
+            .prepare("SELECT tx FROM starknet_transactions WHERE block_hash = ?")
+            .context("Preparing statement")?;
 
-        for (idx, event) in events.iter().enumerate() {
-            keys.clear();
-            Self::event_keys_to_base64_strings(&event.keys, &mut keys);
+        let mut rows = stmt.query([block_hash]).context("Executing query")?;
 
-            buffer.clear();
-            Self::encode_event_data_to_bytes(&event.data, &mut buffer);
+        let mut counts = HashMap::new();
+        while let Some(row) = rows.next()? {
+            let transaction = row.get_ref_unwrap("tx").as_blob_or_null()?.ok_or_else(|| {
+                StorageError::corruption("starknet_transactions", "Transaction data missing")
+            })?;
+            let transaction: transaction::Transaction =
+                decode_blob(transaction, "starknet_transactions", "transaction")?;
 
-            stmt.execute(named_params![
-                ":block_number": block_number,
-                ":idx": idx,
-                ":transaction_hash": &transaction_hash,
-                ":from_address": &event.from_address,
-                ":keys": &keys,
-                ":data": &buffer,
-            ])
-            .context("Insert events into events table")?;
+            *counts.entry(transaction.contract_address()).or_default() += 1;
         }
-        Ok(())
+
+        Ok(counts)
     }
 
-    pub(crate) const PAGE_SIZE_LIMIT: usize = 1024;
+    /// Returns the [FeeStats] of the transactions in blocks `from_block..=to_block`.
+    pub fn fee_stats(
+        tx: &Transaction<'_>,
+        from_block: StarknetBlockNumber,
+        to_block: StarknetBlockNumber,
+    ) -> Result<FeeStats, StorageError> {
+        const BLOCK_RANGE: &str = "FROM starknet_transactions
+            JOIN starknet_blocks ON starknet_transactions.block_hash = starknet_blocks.hash
+            WHERE starknet_blocks.number BETWEEN :from_block AND :to_block";
+        let params = named_params! {
+            ":from_block": from_block,
+            ":to_block": to_block,
+        };
 
-    fn event_query<'query, 'arg>(
-        base: &'query str,
-        from_block: Option<&'arg StarknetBlockNumber>,
-        to_block: Option<&'arg StarknetBlockNumber>,
-        contract_address: Option<&'arg ContractAddress>,
-        keys: &'arg [EventKey],
-        key_fts_expression: &'arg mut String,
-    ) -> (
-        std::borrow::Cow<'query, str>,
-        Vec<(&'static str, &'arg dyn rusqlite::ToSql)>,
-    ) {
-        let mut base_query = std::borrow::Cow::Borrowed(base);
-
-        let mut where_statement_parts: Vec<&'static str> = Vec::new();
-        let mut params: Vec<(&str, &dyn rusqlite::ToSql)> = Vec::new();
-
-        // filter on block range
-        match (from_block, to_block) {
-            (Some(from_block), Some(to_block)) => {
-                where_statement_parts.push("block_number BETWEEN :from_block AND :to_block");
-                params.push((":from_block", from_block));
-                params.push((":to_block", to_block));
-            }
-            (Some(from_block), None) => {
-                where_statement_parts.push("block_number >= :from_block");
-                params.push((":from_block", from_block));
-            }
-            (None, Some(to_block)) => {
-                where_statement_parts.push("block_number <= :to_block");
-                params.push((":to_block", to_block));
-            }
-            (None, None) => {}
-        }
-
-        // on contract address
-        if let Some(contract_address) = contract_address {
-            where_statement_parts.push("from_address = :contract_address");
-            params.push((":contract_address", contract_address))
-        }
-
-        // Filter on keys: this is using an FTS5 full-text index (virtual table) on the keys.
-        // The idea is that we convert keys to a space-separated list of Bas64 encoded string
-        // representation and then use the full-text index to find events matching the events.
-        if !keys.is_empty() {
-            let needed =
-                (keys.len() * (" OR ".len() + "\"\"".len() + 44)).saturating_sub(" OR ".len());
-            if let Some(more) = needed.checked_sub(key_fts_expression.capacity()) {
-                key_fts_expression.reserve(more);
-            }
-
-            let _capacity = key_fts_expression.capacity();
+        // Fees are fixed length big-endian blobs, so SQLite's blob ordering is the numeric one.
+        // NULL fees are ignored by the aggregate functions.
+        let (count, max, min) = tx
+            .query_row(
+                &format!(
+                    "SELECT COUNT(actual_fee), MAX(actual_fee), MIN(actual_fee) {BLOCK_RANGE}"
+                ),
+                params,
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get::<_, Option<Vec<u8>>>(1)?,
+                        row.get::<_, Option<Vec<u8>>>(2)?,
+                    ))
+                },
+            )
+            .context("Aggregating transaction fees")?;
+        let max = max.as_deref().map(fee_from_sql).transpose()?;
+        let min = min.as_deref().map(fee_from_sql).transpose()?;
+
+        let total = Self::sum_fees(
+            tx,
+            &format!("SELECT actual_fee {BLOCK_RANGE} AND actual_fee IS NOT NULL"),
+            params,
+        )?;
 
-            keys.iter().enumerate().for_each(|(i, key)| {
-                key_fts_expression.push('"');
-                Self::encode_event_key_to_base64(key, key_fts_expression);
-                key_fts_expression.push('"');
+        Ok(FeeStats {
+            total,
+            count,
+            max,
+            min,
+        })
+    }
 
-                if i != keys.len() - 1 {
-                    key_fts_expression.push_str(" OR ");
-                }
-            });
+    /// Returns the sum of the actual fees of the block's transactions, or [None] if the block
+    /// does not exist.
+    pub fn get_block_total_fee(
+        tx: &Transaction<'_>,
+        block: StarknetBlocksBlockId,
+    ) -> Result<Option<Fee>, StorageError> {
+        let block = match StarknetBlocksTable::get(tx, block)? {
+            Some(block) => block,
+            None => return Ok(None),
+        };
 
-            debug_assert_eq!(
-                _capacity,
-                key_fts_expression.capacity(),
-                "pre-reservation was not enough"
-            );
+        Self::sum_fees(
+            tx,
+            "SELECT actual_fee FROM starknet_transactions
+                WHERE block_hash = :block_hash AND actual_fee IS NOT NULL",
+            named_params! { ":block_hash": block.hash },
+        )
+        .map(Some)
+    }
 
-            base_query.to_mut().push_str(" INNER JOIN starknet_events_keys ON starknet_events.rowid = starknet_events_keys.rowid");
-            where_statement_parts.push("starknet_events_keys.keys MATCH :events_match");
-            params.push((":events_match", &*key_fts_expression));
+    /// Sums the fees selected by `query`. SQLite integers are only 64 bits wide, so the blobs are
+    /// summed here instead.
+    fn sum_fees(
+        tx: &Transaction<'_>,
+        query: &str,
+        params: &[(&str, &dyn rusqlite::ToSql)],
+    ) -> Result<Fee, StorageError> {
+        let mut stmt = tx.prepare_cached(query).context("Preparing fee query")?;
+        let mut rows = stmt.query(params).context("Executing fee query")?;
+
+        let mut total: u128 = 0;
+        while let Some(row) = rows.next().context("Iterate rows")? {
+            let fee = fee_from_sql(row.get_ref_unwrap(0).as_blob()?)?;
+            total = total
+                .checked_add(u128::from_be_bytes(fee.0.to_fixed_bytes()))
+                .context("Total fee overflowed")?;
         }
 
-        if !where_statement_parts.is_empty() {
-            let needed = " WHERE ".len()
-                + where_statement_parts.len() * " AND ".len()
-                + where_statement_parts.iter().map(|x| x.len()).sum::<usize>();
-
-            let q = base_query.to_mut();
-            if let Some(more) = needed.checked_sub(q.capacity() - q.len()) {
-                q.reserve(more);
-            }
-
-            let _capacity = q.capacity();
-
-            q.push_str(" WHERE ");
-
-            let total = where_statement_parts.len();
-            where_statement_parts
-                .into_iter()
-                .enumerate()
-                .for_each(|(i, part)| {
-                    q.push_str(part);
+        Ok(Fee(total.to_be_bytes().into()))
+    }
 
-                    if i != total - 1 {
-                        q.push_str(" AND ");
-                    }
-                });
+    /// Returns the number of transactions in the block, or [None] if the block does not exist.
+    ///
+    /// Blocks looked up by number are answered from their
+    /// [transaction count](StarknetBlock::transaction_count), whereas blocks looked up by hash
+    /// count their stored transactions.
+    pub fn get_transaction_count(
+        tx: &Transaction<'_>,
+        block: StarknetBlocksBlockId,
+    ) -> Result<Option<usize>, StorageError> {
+        // Grouping by block yields no row at all for an unknown block, whereas the LEFT JOIN
+        // still yields a count of 0 for a known block without transactions.
+        const BASE: &str = "SELECT COUNT(starknet_transactions.hash) FROM starknet_blocks
+            LEFT JOIN starknet_transactions ON starknet_transactions.block_hash = starknet_blocks.hash";
 
-            debug_assert_eq!(_capacity, q.capacity(), "pre-reservation was not enough");
+        Ok(match block {
+            StarknetBlocksBlockId::Number(number) => tx.query_row(
+                "SELECT transaction_count FROM starknet_blocks WHERE number = ?",
+                [number],
+                |row| row.get(0),
+            ),
+            StarknetBlocksBlockId::Hash(hash) => tx.query_row(
+                &format!("{BASE} WHERE starknet_blocks.hash = ? GROUP BY starknet_blocks.hash"),
+                [hash],
+                |row| row.get(0),
+            ),
+            StarknetBlocksBlockId::Latest => tx.query_row(
+                "SELECT transaction_count FROM starknet_blocks ORDER BY number DESC LIMIT 1",
+                [],
+                |row| row.get(0),
+            ),
         }
-
-        (base_query, params)
+        .optional()
+        .context("Counting transactions")?)
     }
 
-    pub fn event_count(
+    /// Returns the number of events emitted and L2 to L1 messages sent by the transaction, or
+    /// [None] if the transaction is unknown.
+    pub fn get_counts(
         tx: &Transaction<'_>,
-        from_block: Option<StarknetBlockNumber>,
-        to_block: Option<StarknetBlockNumber>,
-        contract_address: Option<ContractAddress>,
-        keys: Vec<EventKey>,
-    ) -> anyhow::Result<usize> {
-        let mut key_fts_expression = String::new();
-        let (query, params) = Self::event_query(
-            "SELECT COUNT(1) FROM starknet_events",
-            from_block.as_ref(),
-            to_block.as_ref(),
-            contract_address.as_ref(),
-            &keys,
-            &mut key_fts_expression,
-        );
+        transaction: StarknetTransactionHash,
+    ) -> Result<Option<(usize, usize)>, StorageError> {
+        Ok(tx
+            .query_row(
+                "SELECT events_count, messages_count FROM starknet_transactions WHERE hash = ?",
+                [transaction],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .context("Reading transaction counts")?)
+    }
 
-        let count: usize = tx.query_row(&query, params.as_slice(), |row| row.get(0))?;
+    /// Returns the number of events emitted and L2 to L1 messages sent by all of the block's
+    /// transactions, or [None] if the block does not exist.
+    pub fn get_block_counts(
+        tx: &Transaction<'_>,
+        block: StarknetBlocksBlockId,
+    ) -> Result<Option<(usize, usize)>, StorageError> {
+        // As for get_transaction_count, the LEFT JOIN yields zero counts for a known block
+        // without transactions.
+        const BASE: &str = "SELECT COALESCE(SUM(starknet_transactions.events_count), 0),
+                COALESCE(SUM(starknet_transactions.messages_count), 0)
+            FROM starknet_blocks
+            LEFT JOIN starknet_transactions ON starknet_transactions.block_hash = starknet_blocks.hash";
+        let to_counts = |row: &rusqlite::Row<'_>| Ok((row.get(0)?, row.get(1)?));
+
+        Ok(match block {
+            StarknetBlocksBlockId::Number(number) => tx.query_row(
+                &format!("{BASE} WHERE starknet_blocks.number = ? GROUP BY starknet_blocks.hash"),
+                [number],
+                to_counts,
+            ),
+            StarknetBlocksBlockId::Hash(hash) => tx.query_row(
+                &format!("{BASE} WHERE starknet_blocks.hash = ? GROUP BY starknet_blocks.hash"),
+                [hash],
+                to_counts,
+            ),
+            StarknetBlocksBlockId::Latest => tx.query_row(
+                &format!(
+                    "{BASE} WHERE starknet_blocks.number = (SELECT MAX(number) FROM starknet_blocks)
+                    GROUP BY starknet_blocks.hash"
+                ),
+                [],
+                to_counts,
+            ),
+        }
+        .optional()
+        .context("Summing transaction counts")?)
+    }
 
-        Ok(count)
+    /// Returns the number of transactions stored across all blocks.
+    pub fn count_all(tx: &Transaction<'_>) -> anyhow::Result<u64> {
+        tx.query_row("SELECT COUNT(1) FROM starknet_transactions", [], |row| {
+            row.get(0)
+        })
+        .context("Counting transactions")
     }
 
-    pub fn get_events(
+    /// Returns the [ExecutionSummary] of the block's transactions, or [None] if the block does
+    /// not exist.
+    pub fn get_block_execution_summary(
         tx: &Transaction<'_>,
-        filter: &StarknetEventFilter,
-    ) -> anyhow::Result<PageOfEvents> {
-        if filter.page_size > Self::PAGE_SIZE_LIMIT {
-            return Err(EventFilterError::PageSizeTooBig(Self::PAGE_SIZE_LIMIT).into());
-        }
+        block: StarknetBlocksBlockId,
+    ) -> Result<Option<ExecutionSummary>, StorageError> {
+        // As in get_transaction_count, the LEFT JOIN yields a row for a known block without
+        // transactions. SUM ignores NULL resources and is NULL without any transactions.
+        const BASE: &str = "SELECT
+                COUNT(starknet_transactions.hash),
+                COALESCE(SUM(starknet_transactions.n_steps), 0),
+                COALESCE(SUM(starknet_transactions.n_memory_holes), 0)
+            FROM starknet_blocks
+            LEFT JOIN starknet_transactions ON starknet_transactions.block_hash = starknet_blocks.hash";
+
+        let summary = |row: &rusqlite::Row<'_>| {
+            Ok(ExecutionSummary {
+                tx_count: row.get(0)?,
+                total_steps: row.get(1)?,
+                total_memory_holes: row.get(2)?,
+            })
+        };
 
-        if filter.page_size < 1 {
-            anyhow::bail!("Invalid page size");
+        Ok(match block {
+            StarknetBlocksBlockId::Number(number) => tx.query_row(
+                &format!("{BASE} WHERE starknet_blocks.number = ? GROUP BY starknet_blocks.hash"),
+                [number],
+                summary,
+            ),
+            StarknetBlocksBlockId::Hash(hash) => tx.query_row(
+                &format!("{BASE} WHERE starknet_blocks.hash = ? GROUP BY starknet_blocks.hash"),
+                [hash],
+                summary,
+            ),
+            StarknetBlocksBlockId::Latest => tx.query_row(
+                &format!(
+                    "{BASE} WHERE starknet_blocks.number = (SELECT MAX(number) FROM starknet_blocks)
+                    GROUP BY starknet_blocks.hash"
+                ),
+                [],
+                summary,
+            ),
         }
+        .optional()
+        .context("Summing execution resources")?)
+    }
 
-        let base_query = r#"SELECT
-                  block_number,
-                  starknet_blocks.hash as block_hash,
-                  transaction_hash,
-                  starknet_transactions.idx as transaction_idx,
-                  from_address,
-                  data,
-                  starknet_events.keys as keys
-               FROM starknet_events
-               INNER JOIN starknet_transactions ON (starknet_transactions.hash = starknet_events.transaction_hash)
-               INNER JOIN starknet_blocks ON (starknet_blocks.number = starknet_events.block_number)"#;
+    /// Returns the [number](StarknetBlockNumber) of the highest block with stored transactions.
+    pub fn get_highest_block_number(
+        tx: &Transaction<'_>,
+    ) -> Result<Option<StarknetBlockNumber>, StorageError> {
+        Ok(tx
+            .query_row(
+                "SELECT MAX(starknet_blocks.number) FROM starknet_transactions
+                JOIN starknet_blocks ON starknet_transactions.block_hash = starknet_blocks.hash",
+                [],
+                |row| row.get(0),
+            )
+            .context("Querying highest block with transactions")?)
+    }
 
-        let mut key_fts_expression = String::new();
+    /// Deletes the transactions of all blocks where `block number >= reorg_tail`.
+    ///
+    /// Transactions reference their block by hash, so this must be done before the blocks
+    /// themselves are deleted by [StarknetBlocksTable::reorg].
+    pub fn reorg(tx: &Transaction<'_>, reorg_tail: StarknetBlockNumber) -> anyhow::Result<()> {
+        tx.execute(
+            "DELETE FROM starknet_transactions WHERE block_hash IN (SELECT hash FROM starknet_blocks WHERE number >= ?)",
+            [reorg_tail],
+        )?;
+        Ok(())
+    }
 
-        let (mut base_query, mut params) = Self::event_query(
-            base_query,
-            filter.from_block.as_ref(),
-            filter.to_block.as_ref(),
-            filter.contract_address.as_ref(),
-            &filter.keys,
-            &mut key_fts_expression,
-        );
+    /// Returns a proof that the transaction is part of its block's transaction commitment.
+    ///
+    /// Blocks don't store their transaction commitment, so it is recomputed from the block's
+    /// transactions. Returns [None] if the transaction is not stored.
+    pub fn get_inclusion_proof(
+        tx: &Transaction<'_>,
+        transaction: StarknetTransactionHash,
+    ) -> Result<Option<TransactionInclusionProof>, StorageError> {
+        let (block_hash, index): (StarknetBlockHash, u64) = match tx
+            .query_row(
+                "SELECT block_hash, idx FROM starknet_transactions WHERE hash = ?",
+                [transaction],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .context("Querying transaction location")?
+        {
+            Some(location) => location,
+            None => return Ok(None),
+        };
 
-        let offset = filter.page_number * filter.page_size;
+        let transactions = Self::get_transaction_data_for_block(tx, block_hash.into())?
+            .into_iter()
+            .map(|(transaction, _)| transaction)
+            .collect::<Vec<_>>();
+        let leaf = transactions
+            .get(index as usize)
+            .map(calculate_transaction_hash_with_signature)
+            .ok_or_else(|| {
+                StorageError::corruption(
+                    "starknet_transactions",
+                    format!("Transaction index {index} is out of bounds"),
+                )
+            })?;
+
+        let mut tree = transaction_commitment_tree(&transactions)?;
+        let transaction_commitment = tree
+            .commit_mut()
+            .context("Computing transaction commitment")?;
+        let nodes = tree.get_proof(index).context("Computing proof")?;
+
+        Ok(Some(TransactionInclusionProof {
+            block_hash,
+            transaction_hash: transaction,
+            transaction_index: index,
+            leaf,
+            transaction_commitment,
+            nodes,
+        }))
+    }
 
-        // We have to be able to decide if there are more events. We request one extra event
-        // above the requested page size, so that we can decide.
-        let limit = filter.page_size + 1;
-        params.push((":limit", &limit));
-        params.push((":offset", &offset));
+    /// Checks that the `idx` values of the block's transactions form the sequence `0..n`,
+    /// without gaps or duplicates. A block without transactions is trivially contiguous.
+    pub fn verify_idx_contiguity(
+        tx: &Transaction<'_>,
+        block_hash: StarknetBlockHash,
+    ) -> Result<bool, StorageError> {
+        let (count, distinct, min, max): (i64, i64, Option<i64>, Option<i64>) = tx
+            .query_row(
+                "SELECT COUNT(idx), COUNT(DISTINCT idx), MIN(idx), MAX(idx)
+                    FROM starknet_transactions WHERE block_hash = ?",
+                [block_hash],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .context("Querying transaction indices")?;
 
-        base_query.to_mut().push_str(" ORDER BY block_number, transaction_idx, starknet_events.idx LIMIT :limit OFFSET :offset");
+        let contiguous = match (min, max) {
+            (Some(min), Some(max)) => count == distinct && min == 0 && max == count - 1,
+            _ => count == 0,
+        };
 
-        let mut statement = tx.prepare(&base_query).context("Preparing SQL query")?;
-        let mut rows = statement
-            .query(params.as_slice())
-            .context("Executing SQL query")?;
+        Ok(contiguous)
+    }
+}
 
-        let mut is_last_page = true;
-        let mut emitted_events = Vec::new();
-        while let Some(row) = rows.next().context("Fetching next event")? {
-            if emitted_events.len() == filter.page_size {
-                // We already have a full page, and are just fetching the extra event
-                // This means that there are more pages.
-                is_last_page = false;
-            } else {
-                let block_number = row.get_unwrap("block_number");
-                let block_hash = row.get_unwrap("block_hash");
-                let transaction_hash = row.get_unwrap("transaction_hash");
-                let from_address = row.get_unwrap("from_address");
-
-                let data = row.get_ref_unwrap("data").as_blob().unwrap();
-                let data: Vec<_> = data
-                    .chunks_exact(32)
-                    .map(|data| {
-                        let data = StarkHash::from_be_slice(data).unwrap();
-                        EventData(data)
-                    })
-                    .collect();
+/// Filters events stored in the [StarknetEventsTable].
+pub struct StarknetEventFilter {
+    pub from_block: Option<StarknetBlockNumber>,
+    pub to_block: Option<StarknetBlockNumber>,
+    pub contract_address: Option<ContractAddress>,
+    pub keys: Vec<EventKey>,
+    /// Only matches events whose data element at the given position equals the given value.
+    ///
+    /// This is evaluated on each candidate event rather than through an index, so it should be
+    /// combined with another selective filter (block range, contract address or keys) to avoid
+    /// scanning the entire events table.
+    pub data_filter: Vec<(usize, EventData)>,
+    pub page_size: usize,
+    pub page_number: usize,
+    /// Whether to fetch one extra event to decide [PageOfEvents::is_last_page], which should
+    /// normally be `true`.
+    ///
+    /// Callers which paginate using a separate count can disable this to save the work, in which
+    /// case `is_last_page` is `false` unless the block range is empty.
+    pub detect_last_page: bool,
+    /// Whether to also count all matching events to fill in [PageOfEvents::total_pages].
+    ///
+    /// The count scans every matching event rather than a single page, so this should only be
+    /// enabled by callers which need it.
+    pub count_pages: bool,
+    /// Whether to skip the events of transactions which were
+    /// [reverted](transaction::ExecutionStatus::Reverted).
+    pub only_accepted: bool,
+}
 
-                let keys = row.get_ref_unwrap("keys").as_str().unwrap();
+impl StarknetEventFilter {
+    /// Whether `event` matches the filter, with the semantics of the database query: the block
+    /// is in range, the event was emitted by the contract, has any of the keys, and its data
+    /// elements equal the values at the given positions. Paging is not taken into account.
+    ///
+    /// [StarknetEventFilter::only_accepted] is not taken into account either, as the event does
+    /// not record its transaction's status. Callers skip the events of reverted receipts instead.
+    pub fn matches(&self, event: &StarknetEmittedEvent) -> bool {
+        let in_range = self
+            .from_block
+            .map_or(true, |from| event.block_number >= from)
+            && self.to_block.map_or(true, |to| event.block_number <= to);
+        let from_contract = self
+            .contract_address
+            .map_or(true, |address| event.from_address == address);
+        let has_key = self.keys.is_empty() || event.keys.iter().any(|key| self.keys.contains(key));
+        // Positions past the event's data never match, as for the database query.
+        let data_matches = self
+            .data_filter
+            .iter()
+            .all(|(position, value)| event.data.get(*position) == Some(value));
 
-                // no need to allocate a vec for this in loop
-                let mut temp = [0u8; 32];
+        in_range && from_contract && has_key && data_matches
+    }
+}
 
-                let keys: Vec<_> = keys
-                    .split(' ')
-                    .map(|key| {
-                        let used =
-                            base64::decode_config_slice(key, base64::STANDARD, &mut temp).unwrap();
-                        let key = StarkHash::from_be_slice(&temp[..used]).unwrap();
-                        EventKey(key)
-                    })
-                    .collect();
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StarknetEmittedEvent {
+    pub from_address: ContractAddress,
+    pub data: Vec<EventData>,
+    pub keys: Vec<EventKey>,
+    pub block_hash: StarknetBlockHash,
+    pub block_number: StarknetBlockNumber,
+    pub transaction_hash: StarknetTransactionHash,
+}
 
-                let event = StarknetEmittedEvent {
-                    data,
-                    from_address,
-                    keys,
+impl StarknetEmittedEvent {
+    /// The events emitted by the receipts of a block, in the order they are stored.
+    pub(crate) fn from_receipts<'a>(
+        block_hash: StarknetBlockHash,
+        block_number: StarknetBlockNumber,
+        receipts: impl IntoIterator<Item = &'a transaction::Receipt>,
+    ) -> Vec<Self> {
+        receipts
+            .into_iter()
+            .flat_map(|receipt| {
+                receipt.events.iter().map(move |event| Self {
+                    from_address: event.from_address,
+                    data: event.data.clone(),
+                    keys: event.keys.clone(),
                     block_hash,
                     block_number,
-                    transaction_hash,
-                };
-                emitted_events.push(event);
-            }
+                    transaction_hash: receipt.transaction_hash,
+                })
+            })
+            .collect()
+    }
+
+    /// Sends `events` on `sender`. This must only be done once the events' block is committed,
+    /// so that receivers never see events which are rolled back.
+    pub(crate) fn broadcast(sender: &EventSender, events: Vec<Self>) {
+        for event in events {
+            // Only fails if there are no receivers, which is fine.
+            let _ = sender.send(event);
         }
+    }
+}
 
-        Ok(PageOfEvents {
-            events: emitted_events,
-            is_last_page,
-        })
+/// The position of an event in the order returned by the events queries, used to resume a query
+/// after the last event it returned, see [StarknetEventsTable::get_events_after].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct EventCursor {
+    pub block_number: StarknetBlockNumber,
+    pub transaction_idx: u64,
+    pub event_idx: u64,
+}
+
+impl EventCursor {
+    /// A cursor past every event of block `number`.
+    pub fn end_of_block(number: StarknetBlockNumber) -> Self {
+        // Indices are stored as sqlite integers, so this is larger than any stored index.
+        Self {
+            block_number: number,
+            transaction_idx: i64::MAX as u64,
+            event_idx: i64::MAX as u64,
+        }
     }
 }
 
-/// Describes a Starknet block.
-///
-/// While the sequencer version on each block (when present) is stored since starknet 0.9.1, it is
-/// not yet read.
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct StarknetBlock {
-    pub number: StarknetBlockNumber,
-    pub hash: StarknetBlockHash,
-    pub root: GlobalRoot,
-    pub timestamp: StarknetBlockTimestamp,
-    pub gas_price: GasPrice,
-    pub sequencer_address: SequencerAddress,
+#[derive(Copy, Clone, Debug, thiserror::Error, PartialEq, Eq)]
+pub enum EventFilterError {
+    #[error("requested page size is too big, supported maximum is {0}")]
+    PageSizeTooBig(usize),
+    #[error("query exceeded the maximum allowed time")]
+    QueryTimeout,
+    #[error("too many data filters, supported maximum is {0}")]
+    TooManyDataFilters(usize),
+    #[error("too many keys in filter, supported maximum is {0}")]
+    TooManyKeys(usize),
+    #[error("requested page number is too big, supported maximum for this page size is {limit}")]
+    PageNumberTooBig { limit: usize },
+    #[error("invalid block range, from block {from} is after to block {to}")]
+    InvalidBlockRange {
+        from: StarknetBlockNumber,
+        to: StarknetBlockNumber,
+    },
+    #[error("too many event filters registered, supported maximum is {0}")]
+    TooManyFilters(usize),
 }
 
-/// StarknetVersionsTable tracks `starknet_versions` table, which just interns the version
-/// metadata on each block.
-///
-/// It was decided to go with interned approach, as we couldn't be sure that a semantic version
-/// string format is followed. Semantic version strings may have been cheaper to just store
-/// in-line.
-///
-/// Introduced in `revision_0014`.
-struct StarknetVersionsTable;
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PageOfEvents {
+    pub events: Vec<StarknetEmittedEvent>,
+    pub is_last_page: bool,
+    /// The number of pages of matching events, if [StarknetEventFilter::count_pages] was set.
+    pub total_pages: Option<usize>,
+}
 
-impl StarknetVersionsTable {
-    /// Interns, or makes sure there's a unique row for each version.
-    ///
-    /// These are not deleted automatically nor is a need expected due to multiple blocks being
-    /// generated with a single starknet version.
-    fn intern(transaction: &Transaction<'_>, version: &str) -> anyhow::Result<i64> {
-        let id: Option<i64> = transaction
-            .query_row(
-                "SELECT id FROM starknet_versions WHERE version = ?",
-                &[version],
-                |r| Ok(r.get_unwrap(0)),
-            )
-            .optional()
-            .context("Querying for an existing starknet_version")?;
+/// Aggregate statistics over the events in a block range, see [StarknetEventsTable::stats].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EventStats {
+    pub total: usize,
+    /// The contracts which emitted the most events, by descending event count.
+    pub by_contract: Vec<(ContractAddress, usize)>,
+    pub blocks_with_events: usize,
+}
 
-        let id = if let Some(id) = id {
-            id
-        } else {
-            // sqlite "autoincrement" for integer primary keys works like this: we leave it out of
-            // the insert, even though it's not null, it will get max(id)+1 assigned, which we can
-            // read back with last_insert_rowid
-            let rows = transaction
-                .execute(
-                    "INSERT INTO starknet_versions(version) VALUES (?)",
-                    [version],
-                )
-                .context("Inserting unique starknet_version")?;
+/// The output formats of [StarknetEventsTable::export].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Comma separated values, preceded by a header row.
+    Csv,
+    /// One JSON object per line.
+    JsonLines,
+}
 
-            anyhow::ensure!(rows == 1, "Unexpected number of rows inserted: {rows}");
+pub struct StarknetEventsTable {}
 
-            transaction.last_insert_rowid()
-        };
+/// An events SQL query and its named parameters, as built by [StarknetEventsTable::event_query].
+type EventQuery<'query, 'arg> = (
+    std::borrow::Cow<'query, str>,
+    Vec<(&'static str, &'arg dyn rusqlite::ToSql)>,
+);
+
+/// The conditions an event has to meet to be selected by [StarknetEventsTable::event_query].
+#[derive(Default)]
+struct EventConditions<'a> {
+    from_block: Option<&'a StarknetBlockNumber>,
+    to_block: Option<&'a StarknetBlockNumber>,
+    contract_address: Option<&'a ContractAddress>,
+    keys: &'a [EventKey],
+    data_filter: &'a [(usize, EventData)],
+    only_accepted: bool,
+}
 
-        Ok(id)
+impl<'a> From<&'a StarknetEventFilter> for EventConditions<'a> {
+    fn from(filter: &'a StarknetEventFilter) -> Self {
+        Self {
+            from_block: filter.from_block.as_ref(),
+            to_block: filter.to_block.as_ref(),
+            contract_address: filter.contract_address.as_ref(),
+            keys: &filter.keys,
+            data_filter: &filter.data_filter,
+            only_accepted: filter.only_accepted,
+        }
     }
 }
 
-/// Stores the contract state hash along with its preimage. This is useful to
-/// map between the global state tree and the contracts tree.
-///
-/// Specifically it stores
-///
-/// - [contract state hash](ContractStateHash)
-/// - [class hash](ClassHash)
-/// - [contract root](ContractRoot)
-pub struct ContractsStateTable {}
-
-impl ContractsStateTable {
-    /// Insert a state hash into the table, overwrites the data if the hash already exists.
-    pub fn upsert(
-        transaction: &Transaction<'_>,
-        state_hash: ContractStateHash,
-        hash: ClassHash,
-        root: ContractRoot,
-        nonce: ContractNonce,
-    ) -> anyhow::Result<()> {
-        transaction.execute(
-            "INSERT OR IGNORE INTO contract_states (state_hash, hash, root, nonce) VALUES (:state_hash, :hash, :root, :nonce)",
-            named_params! {
-                ":state_hash": state_hash,
-                ":hash": hash,
-                ":root": root,
-                ":nonce": nonce,
-            },
-        )?;
-        Ok(())
+impl StarknetEventsTable {
+    /// Returns the events of the pending block which match the filter, in order.
+    ///
+    /// Pending events are not stored, so this applies [StarknetEventFilter::matches] in memory.
+    /// As with [get_events](Self::get_events), the events' block number must be in range, and
+    /// the caller pages the result.
+    pub fn filter_pending(
+        pending_events: &[StarknetEmittedEvent],
+        filter: &StarknetEventFilter,
+    ) -> Vec<StarknetEmittedEvent> {
+        pending_events
+            .iter()
+            .filter(|event| filter.matches(event))
+            .cloned()
+            .collect()
     }
 
-    /// Gets the root associated with the given state hash, or [None]
-    /// if it does not exist.
-    pub fn get_root(
-        transaction: &Transaction<'_>,
-        state_hash: ContractStateHash,
-    ) -> anyhow::Result<Option<ContractRoot>> {
-        transaction
-            .query_row(
-                "SELECT root FROM contract_states WHERE state_hash = :state_hash",
-                named_params! {
-                    ":state_hash": state_hash
-                },
-                |row| row.get("root"),
-            )
-            .optional()
-            .map_err(|e| e.into())
-    }
+    /// Marks event data encoded along with its element count.
+    ///
+    /// Legacy event data is a bare sequence of 32 byte elements. Elements are field elements, so
+    /// their first byte is at most `0x08` and legacy data never starts with this byte.
+    const EVENT_DATA_V1: u8 = 0x80;
 
-    /// Gets the nonce associated with the given state hash, or [None]
-    /// if it does not exist.
-    pub fn get_nonce(
-        transaction: &Transaction<'_>,
-        state_hash: ContractStateHash,
-    ) -> anyhow::Result<Option<ContractNonce>> {
-        transaction
-            .query_row(
-                "SELECT nonce FROM contract_states WHERE state_hash = :state_hash",
-                named_params! {
-                    ":state_hash": state_hash
-                },
-                |row| row.get("nonce"),
-            )
-            .optional()
-            .map_err(|e| e.into())
+    /// Encodes event data as [Self::EVENT_DATA_V1], followed by the number of elements as a
+    /// big-endian `u32` and the elements as consecutive 32 byte chunks.
+    ///
+    /// The count lets [Self::decode_event_data] detect truncated data.
+    pub fn encode_event_data_to_bytes(data: &[EventData], buffer: &mut Vec<u8>) {
+        buffer.push(Self::EVENT_DATA_V1);
+        buffer.extend((data.len() as u32).to_be_bytes());
+        buffer.extend(data.iter().flat_map(|e| (*e.0.as_be_bytes()).into_iter()))
     }
 
-    /// Gets the root and nonce associated with the given state hash, or [None]
-    /// if it does not exist.
-    pub fn get_root_and_nonce(
-        transaction: &Transaction<'_>,
-        state_hash: ContractStateHash,
-    ) -> anyhow::Result<Option<(ContractRoot, ContractNonce)>> {
-        transaction
-            .query_row(
-                "SELECT root, nonce FROM contract_states WHERE state_hash = :state_hash",
-                named_params! {
-                    ":state_hash": state_hash
-                },
-                |row| {
-                    let root = row.get("root")?;
-                    let nonce = row.get("nonce")?;
+    pub fn encode_event_keys_to_bytes(keys: &[EventKey], buffer: &mut Vec<u8>) {
+        buffer.extend(keys.iter().flat_map(|e| (*e.0.as_be_bytes()).into_iter()))
+    }
 
-                    Ok((root, nonce))
-                },
-            )
-            .optional()
-            .map_err(|e| e.into())
+    fn encode_event_key_to_base64(key: &EventKey, buf: &mut String) {
+        base64::encode_config_buf(key.0.as_be_bytes(), base64::STANDARD, buf);
     }
-}
 
-/// Stores all known [Starknet state updates][crate::rpc::v01::types::reply::StateUpdate].
-pub struct StarknetStateUpdatesTable {}
+    pub fn event_keys_to_base64_strings(keys: &[EventKey], out: &mut String) {
+        // with padding it seems 44 bytes are needed for each
+        let needed = (keys.len() * (" ".len() + 44)).saturating_sub(" ".len());
+        if let Some(more) = needed.checked_sub(out.capacity() - out.len()) {
+            out.reserve(more);
+        }
 
-impl StarknetStateUpdatesTable {
-    /// Inserts a StarkNet state update accociated with a particular block into the [StarknetStateUpdatesTable].
-    ///
-    /// Overwrites existing data if the block hash already exists.
-    pub fn insert(
-        tx: &Transaction<'_>,
-        block_hash: StarknetBlockHash,
-        state_update: &StateUpdate,
-    ) -> anyhow::Result<()> {
-        let serialized =
-            serde_json::to_vec(&state_update).context("Serialize Starknet state update")?;
+        let _capacity = out.capacity();
 
-        let mut compressor = zstd::bulk::Compressor::new(10).context("Create zstd compressor")?;
-        let compressed = compressor
-            .compress(&serialized)
-            .context("Compress Starknet state update")?;
+        keys.iter().enumerate().for_each(|(i, x)| {
+            Self::encode_event_key_to_base64(x, out);
 
-        tx.execute(
-            r"INSERT INTO starknet_state_updates (block_hash, data) VALUES (:block_hash, :data)",
-            named_params![":block_hash": block_hash, ":data": &compressed,],
-        )
-        .context("Insert state update data into state updates table")?;
+            if i != keys.len() - 1 {
+                out.push(' ');
+            }
+        });
 
-        Ok(())
+        debug_assert_eq!(_capacity, out.capacity(), "pre-reservation was not enough");
     }
 
-    /// Gets a StarkNet state update for block.
-    pub fn get(
+    /// Inserts the events emitted by a transaction.
+    ///
+    /// Events are not broadcast here, as `tx` may still be rolled back. Writers send them once
+    /// committed, see [StarknetEmittedEvent::from_receipts].
+    pub fn insert_events(
         tx: &Transaction<'_>,
-        block_hash: StarknetBlockHash,
-    ) -> anyhow::Result<Option<StateUpdate>> {
-        let mut stmt = tx
-            .prepare("SELECT data FROM starknet_state_updates WHERE block_hash = ?1")
-            .context("Preparing statement")?;
-
-        let mut rows = stmt.query([block_hash]).context("Executing query")?;
-
-        let row = match rows.next()? {
-            Some(row) => row,
-            None => return Ok(None),
-        };
-
-        let state_update = row.get_ref_unwrap(0).as_blob()?;
-        let state_update = zstd::decode_all(state_update).context("Decompressing state update")?;
-        let state_update =
-            serde_json::from_slice(&state_update).context("Deserializing state update")?;
-
-        Ok(Some(state_update))
+        block_number: StarknetBlockNumber,
+        transaction_hash: StarknetTransactionHash,
+        events: &[transaction::Event],
+    ) -> anyhow::Result<()> {
+        Self::insert_events_batch(tx, block_number, &[(transaction_hash, events)])
     }
-}
-
-/// Stores the canonical StarkNet block chain.
-pub struct CanonicalBlocksTable {}
 
-impl CanonicalBlocksTable {
-    pub fn insert(
+    /// Same as [StarknetEventsTable::insert_events] for the events of multiple transactions of
+    /// the same block, which only prepares the statement once.
+    pub fn insert_events_batch(
         tx: &Transaction<'_>,
-        number: StarknetBlockNumber,
-        hash: StarknetBlockHash,
+        block_number: StarknetBlockNumber,
+        entries: &[(StarknetTransactionHash, &[transaction::Event])],
     ) -> anyhow::Result<()> {
-        let rows_changed = tx.execute(
-            "INSERT INTO canonical_blocks(number, hash) values(?,?)",
-            params![number, hash],
+        let timer = WriteTimer::start(metrics::INSERT_EVENTS);
+        let span = tracing::debug_span!(
+            "insert_events",
+            %block_number,
+            transactions = entries.len(),
+            events = tracing::field::Empty,
+        )
+        .entered();
+        let mut count = 0;
+
+        let mut stmt = tx.prepare(
+            r"INSERT INTO starknet_events ( block_number,  idx,  transaction_hash,  from_address,  keys,  key_bytes,  first_key,  data)
+                                   VALUES (:block_number, :idx, :transaction_hash, :from_address, :keys, :key_bytes, :first_key, :data)"
         )?;
-        assert_eq!(rows_changed, 1);
+
+        let mut keys = String::new();
+        let mut key_bytes = Vec::new();
+        let mut buffer = Vec::new();
+        let mut bloom = EventBloom::new();
+
+        for &(transaction_hash, events) in entries {
+            for (idx, event) in events.iter().enumerate() {
+                bloom.insert_event(&event.from_address, &event.keys);
+
+                keys.clear();
+                Self::event_keys_to_base64_strings(&event.keys, &mut keys);
+
+                key_bytes.clear();
+                Self::encode_event_keys_to_bytes(&event.keys, &mut key_bytes);
+
+                buffer.clear();
+                Self::encode_event_data_to_bytes(&event.data, &mut buffer);
+
+                stmt.execute(named_params![
+                    ":block_number": block_number,
+                    ":idx": idx,
+                    ":transaction_hash": &transaction_hash,
+                    ":from_address": &event.from_address,
+                    ":keys": &keys,
+                    ":key_bytes": &key_bytes,
+                    ":first_key": event.keys.first(),
+                    ":data": &buffer,
+                ])
+                .context("Insert events into events table")?;
+                count += 1;
+            }
+        }
+
+        if count > 0 {
+            EventBloomsTable::merge(tx, block_number, &bloom)
+                .context("Updating block's event bloom")?;
+        }
+
+        span.record("events", &count);
+        // Events are stored uncompressed.
+        timer.finish(count, 0);
 
         Ok(())
     }
 
-    /// Removes all rows where `number >= reorg_tail`.
+    /// Deletes all events from __head down-to reorg_tail__
+    /// i.e. it deletes all rows where `block number >= reorg_tail`.
+    ///
+    /// The matching `starknet_events_keys` full-text index rows are removed by the table's
+    /// delete trigger. The blocks' event blooms are deleted as well.
     pub fn reorg(tx: &Transaction<'_>, reorg_tail: StarknetBlockNumber) -> anyhow::Result<()> {
         tx.execute(
-            "DELETE FROM canonical_blocks WHERE number >= ?",
+            "DELETE FROM starknet_events WHERE block_number >= ?",
             [reorg_tail],
-        )?;
+        )
+        .context("Deleting events")?;
+        tx.execute(
+            "DELETE FROM starknet_event_blooms WHERE block_number >= ?",
+            [reorg_tail],
+        )
+        .context("Deleting event blooms")?;
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::storage::Storage;
+    pub(crate) const PAGE_SIZE_LIMIT: usize = 1024;
 
-    mod contracts {
-        use super::*;
-        use crate::starkhash;
+    /// Above this many candidate blocks from the [EventBloom]s, listing them in the events query
+    /// costs more than it saves.
+    const MAX_BLOOM_CANDIDATES: usize = 10_000;
+
+    /// The default maximum number of keys in a filter. Each key adds a term to the FTS `MATCH`
+    /// expression, which becomes slow and eventually exceeds sqlite's expression depth limit.
+    pub const DEFAULT_MAX_KEYS: usize = 256;
+
+    /// The default maximum number of events [StarknetEventsTable::get_events] skips to reach
+    /// the requested page. Sqlite has to step through every skipped event, so large offsets
+    /// are expensive.
+    pub const DEFAULT_MAX_OFFSET: usize = 1_000_000;
+
+    /// The maximum number of [StarknetEventFilter::data_filter] entries. Data predicates are not
+    /// indexed and are evaluated on every candidate event, so each one adds to the query's cost.
+    pub const MAX_DATA_FILTERS: usize = 8;
+
+    /// Fails if the filter has more keys or data filters than are supported by
+    /// [StarknetEventsTable::event_query].
+    pub(crate) fn check_filter_limits(
+        keys: &[EventKey],
+        data_filter: &[(usize, EventData)],
+        max_keys: usize,
+    ) -> Result<(), EventFilterError> {
+        if data_filter.len() > Self::MAX_DATA_FILTERS {
+            return Err(EventFilterError::TooManyDataFilters(Self::MAX_DATA_FILTERS));
+        }
 
-        #[test]
-        fn get() {
-            let storage = Storage::in_memory().unwrap();
-            let mut connection = storage.connection().unwrap();
-            let transaction = connection.transaction().unwrap();
+        if keys.len() > max_keys {
+            return Err(EventFilterError::TooManyKeys(max_keys));
+        }
 
-            let state_hash = ContractStateHash(starkhash!("0abc"));
-            let hash = ClassHash(starkhash!("0123"));
-            let root = ContractRoot(starkhash!("0def"));
-            let nonce = ContractNonce(starkhash!("0456"));
+        Ok(())
+    }
 
-            ContractsStateTable::upsert(&transaction, state_hash, hash, root, nonce).unwrap();
+    fn event_query<'query, 'arg>(
+        base: &'query str,
+        conditions: EventConditions<'arg>,
+        max_keys: usize,
+        after: Option<&'arg EventCursor>,
+        blocks: Option<&[StarknetBlockNumber]>,
+        key_fts_expression: &'arg mut String,
+    ) -> Result<EventQuery<'query, 'arg>, EventFilterError> {
+        let EventConditions {
+            from_block,
+            to_block,
+            contract_address,
+            keys,
+            data_filter,
+            only_accepted,
+        } = conditions;
+        Self::check_filter_limits(keys, data_filter, max_keys)?;
 
-            let result = ContractsStateTable::get_root(&transaction, state_hash).unwrap();
-            assert_eq!(result, Some(root));
+        let mut base_query = std::borrow::Cow::Borrowed(base);
 
-            let result = ContractsStateTable::get_nonce(&transaction, state_hash).unwrap();
-            assert_eq!(result, Some(nonce));
+        let mut where_statement_parts: Vec<std::borrow::Cow<'static, str>> = Vec::new();
+        let mut params: Vec<(&str, &dyn rusqlite::ToSql)> = Vec::new();
 
-            let result = ContractsStateTable::get_root_and_nonce(&transaction, state_hash).unwrap();
-            assert_eq!(result, Some((root, nonce)));
+        // filter on block range
+        match (from_block, to_block) {
+            (Some(from_block), Some(to_block)) => {
+                where_statement_parts.push("block_number BETWEEN :from_block AND :to_block".into());
+                params.push((":from_block", from_block));
+                params.push((":to_block", to_block));
+            }
+            (Some(from_block), None) => {
+                where_statement_parts.push("block_number >= :from_block".into());
+                params.push((":from_block", from_block));
+            }
+            (None, Some(to_block)) => {
+                where_statement_parts.push("block_number <= :to_block".into());
+                params.push((":to_block", to_block));
+            }
+            (None, None) => {}
+        }
+
+        // on contract address
+        if let Some(contract_address) = contract_address {
+            where_statement_parts.push("from_address = :contract_address".into());
+            params.push((":contract_address", contract_address))
+        }
+
+        // past a previously returned event, requires the transactions join of EVENT_SELECT
+        if let Some(after) = after {
+            where_statement_parts.push("(block_number, starknet_transactions.idx, starknet_events.idx) > (:after_block, :after_transaction, :after_event)".into());
+            params.push((":after_block", &after.block_number));
+            params.push((":after_transaction", &after.transaction_idx));
+            params.push((":after_event", &after.event_idx));
+        }
+
+        // in the given blocks, which are integers and can be inlined
+        if let Some(blocks) = blocks {
+            let blocks = blocks
+                .iter()
+                .map(|block| block.get().to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            where_statement_parts.push(format!("block_number IN ({blocks})").into());
+        }
+
+        // on data elements, whose positions and values are integers and hashes and can be inlined.
+        //
+        // Event data is stored as consecutive 32 byte chunks after a 5 byte header (see
+        // [Self::encode_event_data_to_bytes]), or without a header in the legacy encoding. The
+        // header length is therefore the data's length modulo 32, and the element at `position`
+        // is the 32 byte substring starting after it at `position * 32`. Events with fewer data
+        // elements yield a shorter substring, which never matches.
+        for (position, value) in data_filter {
+            where_statement_parts.push(
+                format!(
+                    "substr(data, length(data) % 32 + {position} * 32 + 1, 32) = x'{}'",
+                    hex::encode(value.0.as_be_bytes())
+                )
+                .into(),
+            );
+        }
+
+        // Filter on keys: this is using an FTS5 full-text index (virtual table) on the keys.
+        // The idea is that we convert keys to a space-separated list of Bas64 encoded string
+        // representation and then use the full-text index to find events matching the events.
+        // Without keys there is nothing to match, and the virtual table is not joined at all.
+        if !keys.is_empty() {
+            // Duplicate keys match the same events, they would only bloat the expression.
+            let mut seen = std::collections::HashSet::with_capacity(keys.len());
+            let keys = keys
+                .iter()
+                .filter(|key| seen.insert(*key))
+                .collect::<Vec<_>>();
+
+            let needed =
+                (keys.len() * (" OR ".len() + "\"\"".len() + 44)).saturating_sub(" OR ".len());
+            if let Some(more) = needed.checked_sub(key_fts_expression.capacity()) {
+                key_fts_expression.reserve(more);
+            }
+
+            let _capacity = key_fts_expression.capacity();
+
+            keys.iter().enumerate().for_each(|(i, key)| {
+                key_fts_expression.push('"');
+                Self::encode_event_key_to_base64(key, key_fts_expression);
+                key_fts_expression.push('"');
+
+                if i != keys.len() - 1 {
+                    key_fts_expression.push_str(" OR ");
+                }
+            });
+
+            debug_assert_eq!(
+                _capacity,
+                key_fts_expression.capacity(),
+                "pre-reservation was not enough"
+            );
+
+            // A CROSS JOIN makes SQLite look up the keys of the events in the given blocks,
+            // instead of scanning all events matching the keys.
+            let join = if blocks.is_some() {
+                " CROSS JOIN starknet_events_keys ON starknet_events.rowid = starknet_events_keys.rowid"
+            } else {
+                " INNER JOIN starknet_events_keys ON starknet_events.rowid = starknet_events_keys.rowid"
+            };
+            base_query.to_mut().push_str(join);
+            where_statement_parts.push("starknet_events_keys.keys MATCH :events_match".into());
+            params.push((":events_match", &*key_fts_expression));
+        }
+
+        // on the execution status of the emitting transaction. The base query may already join
+        // the transactions, so this joins them again under another name.
+        if only_accepted {
+            base_query.to_mut().push_str(
+                " INNER JOIN starknet_transactions AS emitting ON emitting.hash = starknet_events.transaction_hash",
+            );
+            // Succeeded transactions are stored with status 0, see StarknetTransactionsTable::upsert_impl.
+            where_statement_parts.push("emitting.execution_status = 0".into());
+        }
+
+        if !where_statement_parts.is_empty() {
+            let needed = " WHERE ".len()
+                + where_statement_parts.len() * " AND ".len()
+                + where_statement_parts.iter().map(|x| x.len()).sum::<usize>();
+
+            let q = base_query.to_mut();
+            if let Some(more) = needed.checked_sub(q.capacity() - q.len()) {
+                q.reserve(more);
+            }
+
+            let _capacity = q.capacity();
+
+            q.push_str(" WHERE ");
+
+            let total = where_statement_parts.len();
+            where_statement_parts
+                .into_iter()
+                .enumerate()
+                .for_each(|(i, part)| {
+                    q.push_str(&part);
+
+                    if i != total - 1 {
+                        q.push_str(" AND ");
+                    }
+                });
+
+            debug_assert_eq!(_capacity, q.capacity(), "pre-reservation was not enough");
+        }
+
+        Ok((base_query, params))
+    }
+
+    /// Returns the number of events stored across all blocks.
+    pub fn count_all(tx: &Transaction<'_>) -> anyhow::Result<u64> {
+        tx.query_row("SELECT COUNT(1) FROM starknet_events", [], |row| row.get(0))
+            .context("Counting events")
+    }
+
+    /// Counts the events matching the filter, failing with [EventFilterError::TooManyKeys] if
+    /// there are more than `max_keys` keys, and with [EventFilterError::InvalidBlockRange] if
+    /// `from_block` is after `to_block`.
+    ///
+    /// The filter's paging fields are ignored.
+    pub fn event_count(
+        tx: &Transaction<'_>,
+        filter: &StarknetEventFilter,
+        max_keys: usize,
+    ) -> Result<usize, StorageError> {
+        Self::check_block_range(filter.from_block, filter.to_block)?;
+
+        let mut key_fts_expression = String::new();
+        let (query, params) = Self::event_query(
+            "SELECT COUNT(1) FROM starknet_events",
+            filter.into(),
+            max_keys,
+            None,
+            None,
+            &mut key_fts_expression,
+        )?;
+
+        let count: usize = tx.query_row(&query, params.as_slice(), |row| row.get(0))?;
+
+        Ok(count)
+    }
+
+    /// Counts the matching events in each block of the range, with the same filter semantics as
+    /// [event_count](Self::event_count).
+    ///
+    /// Only blocks with at least one matching event are included, in ascending block order.
+    pub fn event_count_by_block(
+        tx: &Transaction<'_>,
+        from_block: Option<StarknetBlockNumber>,
+        to_block: Option<StarknetBlockNumber>,
+        contract_address: Option<ContractAddress>,
+        keys: Vec<EventKey>,
+    ) -> Result<Vec<(StarknetBlockNumber, usize)>, StorageError> {
+        let mut key_fts_expression = String::new();
+        let (query, params) = Self::event_query(
+            "SELECT block_number, COUNT(1) FROM starknet_events",
+            EventConditions {
+                from_block: from_block.as_ref(),
+                to_block: to_block.as_ref(),
+                contract_address: contract_address.as_ref(),
+                keys: &keys,
+                ..Default::default()
+            },
+            Self::DEFAULT_MAX_KEYS,
+            None,
+            None,
+            &mut key_fts_expression,
+        )?;
+        let query = query.into_owned() + " GROUP BY block_number ORDER BY block_number ASC";
+
+        let mut stmt = tx.prepare(&query).context("Preparing statement")?;
+        let counts = stmt
+            .query_map(params.as_slice(), |row| Ok((row.get(0)?, row.get(1)?)))
+            .context("Executing query")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Counting events per block")?;
+
+        Ok(counts)
+    }
+
+    /// Computes [EventStats] for the events in the block range, with the same semantics as the
+    /// block range of [get_events](Self::get_events).
+    ///
+    /// Only the `top_contracts` contracts emitting the most events are listed, ties are broken
+    /// by contract address.
+    pub fn stats(
+        tx: &Transaction<'_>,
+        from_block: Option<StarknetBlockNumber>,
+        to_block: Option<StarknetBlockNumber>,
+        top_contracts: usize,
+    ) -> Result<EventStats, StorageError> {
+        let mut key_fts_expression = String::new();
+        let (query, params) = Self::event_query(
+            "SELECT COUNT(1), COUNT(DISTINCT block_number) FROM starknet_events",
+            EventConditions {
+                from_block: from_block.as_ref(),
+                to_block: to_block.as_ref(),
+                ..Default::default()
+            },
+            Self::DEFAULT_MAX_KEYS,
+            None,
+            None,
+            &mut key_fts_expression,
+        )?;
+
+        let (total, blocks_with_events) = tx
+            .query_row(&query, params.as_slice(), |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .context("Counting events")?;
+
+        let (query, mut params) = Self::event_query(
+            "SELECT from_address, COUNT(1) AS count FROM starknet_events",
+            EventConditions {
+                from_block: from_block.as_ref(),
+                to_block: to_block.as_ref(),
+                ..Default::default()
+            },
+            Self::DEFAULT_MAX_KEYS,
+            None,
+            None,
+            &mut key_fts_expression,
+        )?;
+        let query = query.into_owned()
+            + " GROUP BY from_address ORDER BY count DESC, from_address ASC LIMIT :limit";
+        params.push((":limit", &top_contracts));
+
+        let mut stmt = tx.prepare(&query).context("Preparing statement")?;
+        let by_contract = stmt
+            .query_map(params.as_slice(), |row| Ok((row.get(0)?, row.get(1)?)))
+            .context("Executing query")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Counting events per contract")?;
+
+        Ok(EventStats {
+            total,
+            by_contract,
+            blocks_with_events,
+        })
+    }
+
+    /// Decodes an event selected by [StarknetEventsTable::EVENT_SELECT].
+    fn event_from_row(row: &rusqlite::Row<'_>) -> Result<StarknetEmittedEvent, StorageError> {
+        let block_number: StarknetBlockNumber = row.get("block_number")?;
+        let transaction_hash: StarknetTransactionHash = row.get("transaction_hash")?;
+        let from_address = row.get("from_address")?;
+
+        // The joined rows are missing if the block was only partially written, in which case the
+        // event cannot be returned, but must not silently vanish from a page either.
+        let block_hash = row.get::<_, Option<_>>("block_hash")?.ok_or_else(|| {
+            StorageError::corruption(
+                "starknet_blocks",
+                format!("Block {block_number} of an event is missing"),
+            )
+        })?;
+        if row.get_ref("transaction_idx")?.data_type() == rusqlite::types::Type::Null {
+            return Err(StorageError::corruption(
+                "starknet_transactions",
+                format!(
+                    "Transaction {} of an event in block {block_number} is missing",
+                    transaction_hash.0
+                ),
+            ));
+        }
+
+        let context = || {
+            format!(
+                "Decoding event of transaction {:?} in block {}",
+                transaction_hash, block_number
+            )
+        };
+
+        let data = row.get_ref("data")?.as_blob()?;
+        let data = Self::decode_event_data(data).map_err(|e| {
+            StorageError::corruption(
+                "starknet_events",
+                format!("{}: invalid event data: {e:#}", context()),
+            )
+        })?;
+
+        let keys = match row.get_ref("key_bytes")?.as_blob_or_null()? {
+            Some(keys) => Self::decode_hashes(keys, EventKey).map_err(|e| {
+                StorageError::corruption(
+                    "starknet_events",
+                    format!("{}: invalid event key: {e}", context()),
+                )
+            })?,
+            // Events stored before the key_bytes column only have their base64 encoded keys.
+            None => {
+                let keys = row.get_ref("keys")?.as_str()?;
+                Self::decode_event_keys(keys).map_err(|e| {
+                    StorageError::corruption("starknet_events", format!("{}: {e:#}", context()))
+                })?
+            }
+        };
+
+        Ok(StarknetEmittedEvent {
+            data,
+            from_address,
+            keys,
+            block_hash,
+            block_number,
+            transaction_hash,
+        })
+    }
+
+    /// Decodes keys encoded by [StarknetEventsTable::event_keys_to_base64_strings].
+    fn decode_event_keys(keys: &str) -> anyhow::Result<Vec<EventKey>> {
+        // Events without keys are stored as an empty string, which would otherwise split into a
+        // single empty key.
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Each key takes 44 characters and a separator.
+        let mut decoded = Vec::with_capacity(keys.len() / 45 + 1);
+        for key in keys.split(' ') {
+            let key = match Self::decode_base64_key(key.as_bytes()) {
+                Some(bytes) => StarkHash::from_be_bytes(bytes),
+                None => {
+                    // Not a 32 byte key, which the encoder never writes, so the slow path only
+                    // needs to produce the right error. 33 bytes is the most 44 characters of
+                    // base64 can decode to.
+                    anyhow::ensure!(key.len() <= 44, "Invalid event key length: {}", key.len());
+                    let mut temp = [0u8; 33];
+                    let used = base64::decode_config_slice(key, base64::STANDARD, &mut temp)
+                        .with_context(|| format!("Invalid base64 event key: {key:?}"))?;
+                    StarkHash::from_be_slice(&temp[..used])
+                }
+            }
+            .with_context(|| format!("Invalid event key: {key:?}"))?;
+            decoded.push(EventKey(key));
+        }
+
+        Ok(decoded)
+    }
+
+    /// Decodes a 32 byte value encoded as 44 characters of padded standard base64, returning
+    /// [None] for any other input.
+    fn decode_base64_key(key: &[u8]) -> Option<[u8; 32]> {
+        const INVALID: u8 = 0xff;
+        const TABLE: [u8; 256] = {
+            let alphabet = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+            let mut table = [INVALID; 256];
+            let mut i = 0;
+            while i < alphabet.len() {
+                table[alphabet[i] as usize] = i as u8;
+                i += 1;
+            }
+            table
+        };
+
+        if key.len() != 44 || key[43] != b'=' {
+            return None;
+        }
+
+        let mut sextets = [0u8; 43];
+        for (sextet, &c) in sextets.iter_mut().zip(key) {
+            *sextet = TABLE[c as usize];
+            if *sextet == INVALID {
+                return None;
+            }
+        }
+
+        let mut bytes = [0u8; 32];
+        for (chunk, out) in sextets.chunks_exact(4).zip(bytes.chunks_exact_mut(3)) {
+            out[0] = chunk[0] << 2 | chunk[1] >> 4;
+            out[1] = chunk[1] << 4 | chunk[2] >> 2;
+            out[2] = chunk[2] << 6 | chunk[3];
+        }
+        // The last three characters encode two bytes, the remaining bits must be zero.
+        let last = &sextets[40..];
+        if last[2] & 0b11 != 0 {
+            return None;
+        }
+        bytes[30] = last[0] << 2 | last[1] >> 4;
+        bytes[31] = last[1] << 4 | last[2] >> 2;
+
+        Some(bytes)
+    }
+
+    /// Decodes event data encoded by [StarknetEventsTable::encode_event_data_to_bytes], or in the
+    /// legacy encoding without an element count.
+    fn decode_event_data(bytes: &[u8]) -> anyhow::Result<Vec<EventData>> {
+        let elements = match bytes.split_first() {
+            Some((&Self::EVENT_DATA_V1, rest)) => {
+                anyhow::ensure!(rest.len() >= 4, "Truncated element count");
+                let (count, elements) = rest.split_at(4);
+                let count = u32::from_be_bytes(count.try_into().expect("Count is 4 bytes"));
+                anyhow::ensure!(
+                    elements.len() % 32 == 0 && elements.len() / 32 == count as usize,
+                    "Expected {count} elements but found {} bytes",
+                    elements.len()
+                );
+                elements
+            }
+            _ => bytes,
+        };
+
+        Ok(Self::decode_hashes(elements, EventData)?)
+    }
+
+    /// Decodes consecutive 32 byte values, as encoded by
+    /// [StarknetEventsTable::encode_event_keys_to_bytes].
+    fn decode_hashes<T>(
+        bytes: &[u8],
+        f: impl Fn(StarkHash) -> T,
+    ) -> Result<Vec<T>, stark_hash::OverflowError> {
+        let mut decoded = Vec::with_capacity(bytes.len() / 32);
+        for chunk in bytes.chunks_exact(32) {
+            let chunk = chunk.try_into().expect("Chunks are 32 bytes");
+            decoded.push(f(StarkHash::from_be_bytes(chunk)?));
+        }
+
+        Ok(decoded)
+    }
+
+    /// Returns the distinct [block numbers](StarknetBlockNumber) in which the given contract
+    /// emitted events, in ascending order.
+    pub fn blocks_for_contract(
+        tx: &Transaction<'_>,
+        address: ContractAddress,
+    ) -> Result<Vec<StarknetBlockNumber>, StorageError> {
+        let mut stmt = tx
+            .prepare(
+                "SELECT DISTINCT block_number FROM starknet_events WHERE from_address = ? ORDER BY block_number ASC",
+            )
+            .context("Preparing statement")?;
+
+        let blocks = stmt
+            .query_map([address], |row| row.get(0))
+            .context("Executing query")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Iterating over rows")?;
+
+        Ok(blocks)
+    }
+
+    /// Appended to [StarknetEventsTable::EVENT_SELECT] by
+    /// [StarknetEventsTable::get_events_for_transaction], which relies on the transaction hash
+    /// index.
+    const TRANSACTION_EVENTS_WHERE: &'static str =
+        " WHERE transaction_hash = ? ORDER BY starknet_events.idx";
+
+    /// Returns all events emitted by the given transaction, in the order they were emitted.
+    ///
+    /// Returns an empty [Vec] if the transaction is unknown or emitted no events.
+    pub fn get_events_for_transaction(
+        tx: &Transaction<'_>,
+        transaction: StarknetTransactionHash,
+    ) -> Result<Vec<StarknetEmittedEvent>, StorageError> {
+        let mut stmt = tx
+            .prepare(&format!(
+                "{}{}",
+                Self::EVENT_SELECT,
+                Self::TRANSACTION_EVENTS_WHERE
+            ))
+            .context("Preparing statement")?;
+
+        let mut rows = stmt.query([transaction]).context("Executing query")?;
+
+        let mut events = Vec::new();
+        while let Some(row) = rows.next().context("Fetching next event")? {
+            events.push(Self::event_from_row(row)?);
         }
+
+        Ok(events)
     }
 
-    mod refs {
-        use super::*;
+    /// Writes all events matching the filter to `writer` in the given format, ordered as for
+    /// [get_events](Self::get_events), and returns the number of events written.
+    ///
+    /// The filter's paging fields are ignored. Events are written as they are read, so memory
+    /// use does not grow with the number of events. Each event is written with several calls,
+    /// so `writer` should be buffered.
+    ///
+    /// Hashes, keys and data are written as `0x` prefixed, zero padded hex. Keys and data are
+    /// space separated lists.
+    pub fn export(
+        tx: &Transaction<'_>,
+        filter: &StarknetEventFilter,
+        mut writer: impl std::io::Write,
+        format: ExportFormat,
+    ) -> Result<usize, StorageError> {
+        use std::fmt::Write;
+
+        let mut key_fts_expression = String::new();
+
+        let (mut query, params) = Self::event_query(
+            Self::EVENT_SELECT,
+            filter.into(),
+            Self::DEFAULT_MAX_KEYS,
+            None,
+            None,
+            &mut key_fts_expression,
+        )?;
+
+        query
+            .to_mut()
+            .push_str(" ORDER BY block_number, transaction_idx, starknet_events.idx");
+
+        let mut stmt = tx.prepare(&query).context("Preparing statement")?;
+        let mut rows = stmt.query(params.as_slice()).context("Executing query")?;
+
+        if format == ExportFormat::Csv {
+            writeln!(
+                writer,
+                "block_number,block_hash,tx_hash,tx_index,event_index,from_address,keys,data"
+            )
+            .context("Writing header")?;
+        }
+
+        // Re-used across events.
+        let mut keys = String::new();
+        let mut data = String::new();
+        let hex_list = |out: &mut String, values: &mut dyn Iterator<Item = &StarkHash>| {
+            out.clear();
+            for (i, value) in values.enumerate() {
+                if i > 0 {
+                    out.push(' ');
+                }
+                write!(out, "0x{value:x}").expect("Writing to a String cannot fail");
+            }
+        };
+
+        let mut count = 0;
+        while let Some(row) = rows.next().context("Fetching next event")? {
+            let event = Self::event_from_row(row)?;
+            let transaction_idx: usize = row.get("transaction_idx")?;
+            let event_idx: usize = row.get("event_idx")?;
+
+            hex_list(&mut keys, &mut event.keys.iter().map(|key| &key.0));
+            hex_list(&mut data, &mut event.data.iter().map(|data| &data.0));
+
+            // None of the values need quoting or escaping.
+            match format {
+                ExportFormat::Csv => writeln!(
+                    writer,
+                    "{},0x{:x},0x{:x},{},{},0x{:x},{},{}",
+                    event.block_number.get(),
+                    event.block_hash.0,
+                    event.transaction_hash.0,
+                    transaction_idx,
+                    event_idx,
+                    event.from_address.get(),
+                    keys,
+                    data,
+                ),
+                ExportFormat::JsonLines => writeln!(
+                    writer,
+                    r#"{{"block_number":{},"block_hash":"0x{:x}","tx_hash":"0x{:x}","tx_index":{},"event_index":{},"from_address":"0x{:x}","keys":"{}","data":"{}"}}"#,
+                    event.block_number.get(),
+                    event.block_hash.0,
+                    event.transaction_hash.0,
+                    transaction_idx,
+                    event_idx,
+                    event.from_address.get(),
+                    keys,
+                    data,
+                ),
+            }
+            .context("Writing event")?;
+
+            count += 1;
+        }
+
+        writer.flush().context("Flushing writer")?;
+
+        Ok(count)
+    }
+
+    /// Number of SQLite virtual machine instructions between deadline checks in
+    /// [StarknetEventsTable::with_timeout].
+    const TIMEOUT_CHECK_INTERVAL: i32 = 1000;
+
+    /// The default `timeout` used for event queries served over RPC, see
+    /// [StarknetEventsTable::with_timeout].
+    pub const DEFAULT_QUERY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+    /// Same as [StarknetEventsTable::get_events] but aborts the query with
+    /// [EventFilterError::QueryTimeout] if it runs for longer than `timeout`.
+    pub fn get_events_with_timeout(
+        tx: &Transaction<'_>,
+        filter: &StarknetEventFilter,
+        timeout: std::time::Duration,
+    ) -> Result<PageOfEvents, StorageError> {
+        Self::with_timeout(tx, timeout, || Self::get_events(tx, filter))
+    }
+
+    /// Runs the queries performed by `f` on `tx`, aborting them with
+    /// [EventFilterError::QueryTimeout] once they have run for longer than `timeout` in total.
+    pub fn with_timeout<T, E>(
+        tx: &Transaction<'_>,
+        timeout: std::time::Duration,
+        f: impl FnOnce() -> Result<T, E>,
+    ) -> Result<T, StorageError>
+    where
+        StorageError: From<E>,
+    {
+        let started_at = std::time::Instant::now();
+        tx.progress_handler(
+            Self::TIMEOUT_CHECK_INTERVAL,
+            Some(move || started_at.elapsed() > timeout),
+        );
+
+        let result = f();
+
+        tx.progress_handler(0, None::<fn() -> bool>);
+
+        result.map_err(|e| {
+            let e = StorageError::from(e);
+            let sqlite_error = match &e {
+                StorageError::Sqlite(sqlite_error) => Some(sqlite_error),
+                StorageError::Other(other) => other.downcast_ref::<rusqlite::Error>(),
+                _ => None,
+            };
+            match sqlite_error {
+                Some(rusqlite::Error::SqliteFailure(
+                    rusqlite::ffi::Error {
+                        code: rusqlite::ErrorCode::OperationInterrupted,
+                        ..
+                    },
+                    _,
+                )) => EventFilterError::QueryTimeout.into(),
+                _ => e,
+            }
+        })
+    }
+
+    /// Selects the columns decoded by [StarknetEventsTable::event_from_row], along with the
+    /// transaction and event indices used for ordering.
+    ///
+    /// The transaction and block are left joined, so that the same events match as for
+    /// [StarknetEventsTable::event_count] and an event whose rows are missing fails decoding
+    /// instead of dropping out of the results.
+    const EVENT_SELECT: &'static str = r#"SELECT
+                  block_number,
+                  starknet_blocks.hash as block_hash,
+                  transaction_hash,
+                  starknet_transactions.idx as transaction_idx,
+                  starknet_events.idx as event_idx,
+                  from_address,
+                  data,
+                  starknet_events.keys as keys,
+                  key_bytes
+               FROM starknet_events
+               LEFT JOIN starknet_transactions ON (starknet_transactions.hash = starknet_events.transaction_hash)
+               LEFT JOIN starknet_blocks ON (starknet_blocks.number = starknet_events.block_number)"#;
+
+    /// Returns the earliest event matching the filter, if any.
+    ///
+    /// The filter's paging fields are ignored.
+    pub fn first_matching(
+        tx: &Transaction<'_>,
+        filter: &StarknetEventFilter,
+    ) -> Result<Option<StarknetEmittedEvent>, StorageError> {
+        let mut key_fts_expression = String::new();
+
+        let (mut query, params) = Self::event_query(
+            Self::EVENT_SELECT,
+            filter.into(),
+            Self::DEFAULT_MAX_KEYS,
+            None,
+            None,
+            &mut key_fts_expression,
+        )?;
+
+        query
+            .to_mut()
+            .push_str(" ORDER BY block_number, transaction_idx, starknet_events.idx LIMIT 1");
+
+        tx.query_row(&query, params.as_slice(), |row| {
+            Ok(Self::event_from_row(row))
+        })
+        .optional()
+        .context("Querying first matching event")?
+        .transpose()
+    }
+
+    /// Returns a page of the events matching the filter.
+    ///
+    /// Filters on contract address or keys first look up the blocks which may contain matching
+    /// events in the [EventBloom]s, and only query the events of those blocks.
+    ///
+    /// Fails with [EventFilterError::InvalidBlockRange] if the filter's `from_block` is after its
+    /// `to_block`.
+    pub fn get_events(
+        tx: &Transaction<'_>,
+        filter: &StarknetEventFilter,
+    ) -> Result<PageOfEvents, StorageError> {
+        Self::get_events_with_max_offset(tx, filter, Self::DEFAULT_MAX_OFFSET)
+    }
+
+    /// Same as [StarknetEventsTable::get_events] but fails with
+    /// [EventFilterError::PageNumberTooBig] if the requested page starts past `max_offset`
+    /// events, instead of [StarknetEventsTable::DEFAULT_MAX_OFFSET].
+    pub fn get_events_with_max_offset(
+        tx: &Transaction<'_>,
+        filter: &StarknetEventFilter,
+        max_offset: usize,
+    ) -> Result<PageOfEvents, StorageError> {
+        Self::get_events_with_limits(tx, filter, max_offset, Self::DEFAULT_MAX_KEYS)
+    }
+
+    /// Same as [StarknetEventsTable::get_events_with_max_offset] but fails with
+    /// [EventFilterError::TooManyKeys] if the filter has more than `max_keys` keys, instead of
+    /// [StarknetEventsTable::DEFAULT_MAX_KEYS].
+    pub fn get_events_with_limits(
+        tx: &Transaction<'_>,
+        filter: &StarknetEventFilter,
+        max_offset: usize,
+        max_keys: usize,
+    ) -> Result<PageOfEvents, StorageError> {
+        let span = tracing::debug_span!(
+            "events_query",
+            keys = filter.keys.len(),
+            contract_address = filter.contract_address.is_some(),
+            from_block = filter.from_block.is_some(),
+            to_block = filter.to_block.is_some(),
+        );
+        let _g = span.enter();
+        let started_at = std::time::Instant::now();
+
+        let result = Self::get_events_page(tx, filter, max_offset, max_keys, true);
+
+        tracing::debug!(elapsed = ?started_at.elapsed(), "Events query finished");
+        result
+    }
+
+    /// Reads a page of events. If `use_blooms` is set, filters on contract address or keys are
+    /// only evaluated in the blocks whose [EventBloom] may contain a match.
+    fn get_events_page(
+        tx: &Transaction<'_>,
+        filter: &StarknetEventFilter,
+        max_offset: usize,
+        max_keys: usize,
+        use_blooms: bool,
+    ) -> Result<PageOfEvents, StorageError> {
+        let offset = Self::page_offset(filter.page_size, filter.page_number, max_offset)?;
+
+        Self::check_block_range(filter.from_block, filter.to_block)?;
+
+        let candidates =
+            if use_blooms && (filter.contract_address.is_some() || !filter.keys.is_empty()) {
+                Self::check_filter_limits(&filter.keys, &filter.data_filter, max_keys)?;
+
+                let candidates = EventBloomsTable::candidate_blocks(
+                    tx,
+                    filter.from_block,
+                    filter.to_block,
+                    filter.contract_address.as_ref(),
+                    &filter.keys,
+                    Self::MAX_BLOOM_CANDIDATES,
+                )?;
+
+                if candidates.as_ref().map_or(false, Vec::is_empty) {
+                    return Ok(PageOfEvents {
+                        events: vec![],
+                        is_last_page: filter.detect_last_page,
+                        total_pages: filter.count_pages.then_some(0),
+                    });
+                }
+
+                candidates
+            } else {
+                None
+            };
+
+        let mut key_fts_expression = String::new();
+
+        let (mut base_query, mut params) = Self::event_query(
+            Self::EVENT_SELECT,
+            filter.into(),
+            max_keys,
+            None,
+            candidates.as_deref(),
+            &mut key_fts_expression,
+        )?;
+
+        // We have to be able to decide if there are more events. We request one extra event
+        // above the requested page size, so that we can decide.
+        let limit = filter.page_size + usize::from(filter.detect_last_page);
+        params.push((":limit", &limit));
+        params.push((":offset", &offset));
+
+        base_query.to_mut().push_str(" ORDER BY block_number, transaction_idx, starknet_events.idx LIMIT :limit OFFSET :offset");
+
+        let mut statement = tx.prepare(&base_query).context("Preparing SQL query")?;
+        let rows = statement
+            .query(params.as_slice())
+            .context("Executing SQL query")?;
+
+        let mut page = Self::collect_page(rows, filter.page_size, filter.detect_last_page)?;
+
+        if filter.count_pages {
+            let total = Self::event_count(tx, filter, max_keys).context("Counting events")?;
+            page.total_pages = Some((total + filter.page_size - 1) / filter.page_size);
+        }
+
+        Ok(page)
+    }
+
+    /// Returns the events whose first key is `selector`, ordered by block number, transaction
+    /// index and event index.
+    ///
+    /// By convention an event's first key is the selector of its name, e.g. `Transfer`. Unlike
+    /// [StarknetEventFilter::keys], which matches a key in any position, only the first key is
+    /// considered. Paging behaves as in [StarknetEventsTable::get_events].
+    pub fn get_by_selector(
+        tx: &Transaction<'_>,
+        selector: EventKey,
+        from_block: Option<StarknetBlockNumber>,
+        to_block: Option<StarknetBlockNumber>,
+        page_size: usize,
+        page_number: usize,
+    ) -> Result<PageOfEvents, StorageError> {
+        let offset = Self::page_offset(page_size, page_number, Self::DEFAULT_MAX_OFFSET)?;
+
+        // One extra event is requested to decide whether there are more pages.
+        let limit = page_size + 1;
+        let from_block = from_block.unwrap_or(StarknetBlockNumber::GENESIS);
+        let to_block = to_block.unwrap_or(StarknetBlockNumber::MAX);
+
+        let query = format!(
+            "{} WHERE starknet_events.first_key = :selector AND block_number BETWEEN :from_block AND :to_block
+               ORDER BY block_number, transaction_idx, starknet_events.idx LIMIT :limit OFFSET :offset",
+            Self::EVENT_SELECT
+        );
+
+        let mut statement = tx.prepare(&query).context("Preparing SQL query")?;
+        let rows = statement
+            .query(named_params! {
+                ":selector": selector,
+                ":from_block": from_block,
+                ":to_block": to_block,
+                ":limit": limit,
+                ":offset": offset,
+            })
+            .context("Executing SQL query")?;
+
+        Self::collect_page(rows, page_size, true)
+    }
+
+    /// Returns up to `limit` events matching the filter which come after the `after` cursor, or
+    /// from the start if there is none, along with their cursors.
+    ///
+    /// Unlike [StarknetEventsTable::get_events], the cost does not grow with the number of
+    /// events already returned. The filter's paging fields are ignored. Filters on contract
+    /// address or keys only query the blocks from the cursor's on whose [EventBloom] may match.
+    pub fn get_events_after(
+        tx: &Transaction<'_>,
+        filter: &StarknetEventFilter,
+        after: Option<EventCursor>,
+        limit: usize,
+    ) -> Result<Vec<(EventCursor, StarknetEmittedEvent)>, StorageError> {
+        if limit > Self::PAGE_SIZE_LIMIT {
+            return Err(EventFilterError::PageSizeTooBig(Self::PAGE_SIZE_LIMIT).into());
+        }
+
+        let candidates = if filter.contract_address.is_some() || !filter.keys.is_empty() {
+            Self::check_filter_limits(&filter.keys, &filter.data_filter, Self::DEFAULT_MAX_KEYS)?;
+
+            // Blocks before the cursor's have been returned already.
+            let from_block = match (filter.from_block, after.as_ref()) {
+                (Some(from_block), Some(after)) if from_block > after.block_number => {
+                    Some(from_block)
+                }
+                (from_block, None) => from_block,
+                (_, Some(after)) => Some(after.block_number),
+            };
+
+            let candidates = EventBloomsTable::candidate_blocks(
+                tx,
+                from_block,
+                filter.to_block,
+                filter.contract_address.as_ref(),
+                &filter.keys,
+                Self::MAX_BLOOM_CANDIDATES,
+            )?;
+
+            if candidates.as_ref().map_or(false, Vec::is_empty) {
+                return Ok(vec![]);
+            }
+
+            candidates
+        } else {
+            None
+        };
+
+        let mut key_fts_expression = String::new();
+
+        let (mut query, mut params) = Self::event_query(
+            Self::EVENT_SELECT,
+            filter.into(),
+            Self::DEFAULT_MAX_KEYS,
+            after.as_ref(),
+            candidates.as_deref(),
+            &mut key_fts_expression,
+        )?;
+
+        params.push((":limit", &limit));
+        query
+            .to_mut()
+            .push_str(" ORDER BY block_number, transaction_idx, starknet_events.idx LIMIT :limit");
+
+        let mut statement = tx.prepare(&query).context("Preparing SQL query")?;
+        let mut rows = statement
+            .query(params.as_slice())
+            .context("Executing SQL query")?;
+
+        let mut events = Vec::new();
+        while let Some(row) = rows.next().context("Fetching next event")? {
+            let event = Self::event_from_row(row)?;
+            let cursor = EventCursor {
+                block_number: event.block_number,
+                transaction_idx: row.get("transaction_idx")?,
+                event_idx: row.get("event_idx")?,
+            };
+            events.push((cursor, event));
+        }
+
+        Ok(events)
+    }
+
+    /// Fails with [EventFilterError::InvalidBlockRange] if both bounds are given and `from_block`
+    /// is after `to_block`, which would never match any event.
+    fn check_block_range(
+        from_block: Option<StarknetBlockNumber>,
+        to_block: Option<StarknetBlockNumber>,
+    ) -> Result<(), EventFilterError> {
+        match (from_block, to_block) {
+            (Some(from), Some(to)) if from > to => {
+                Err(EventFilterError::InvalidBlockRange { from, to })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Validates the paging parameters and returns the number of events to skip.
+    fn page_offset(
+        page_size: usize,
+        page_number: usize,
+        max_offset: usize,
+    ) -> anyhow::Result<usize> {
+        if page_size > Self::PAGE_SIZE_LIMIT {
+            return Err(EventFilterError::PageSizeTooBig(Self::PAGE_SIZE_LIMIT).into());
+        }
+
+        if page_size < 1 {
+            anyhow::bail!("Invalid page size");
+        }
+
+        match page_number.checked_mul(page_size) {
+            Some(offset) if offset <= max_offset => Ok(offset),
+            _ => Err(EventFilterError::PageNumberTooBig {
+                limit: max_offset / page_size,
+            }
+            .into()),
+        }
+    }
+
+    /// Reads a page of events from rows selected by [StarknetEventsTable::EVENT_SELECT] with a
+    /// limit of `page_size + 1`, or `page_size` if the last page is not to be detected.
+    fn collect_page(
+        mut rows: rusqlite::Rows<'_>,
+        page_size: usize,
+        detect_last_page: bool,
+    ) -> Result<PageOfEvents, StorageError> {
+        let mut is_last_page = detect_last_page;
+        let mut emitted_events = Vec::with_capacity(page_size);
+        while let Some(row) = rows.next().context("Fetching next event")? {
+            if emitted_events.len() == page_size {
+                // We already have a full page, and are just fetching the extra event
+                // This means that there are more pages.
+                is_last_page = false;
+            } else {
+                emitted_events.push(Self::event_from_row(row)?);
+            }
+        }
+
+        Ok(PageOfEvents {
+            events: emitted_events,
+            is_last_page,
+            total_pages: None,
+        })
+    }
+}
+
+/// Describes a Starknet block.
+///
+/// While the sequencer version on each block (when present) is stored since starknet 0.9.1, it is
+/// not part of this struct, see [StarknetBlocksTable::get_version].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StarknetBlock {
+    pub number: StarknetBlockNumber,
+    pub hash: StarknetBlockHash,
+    pub parent_hash: StarknetBlockHash,
+    pub root: GlobalRoot,
+    pub timestamp: StarknetBlockTimestamp,
+    pub gas_price: GasPrice,
+    /// [None] if the sequencer did not report one, as for early blocks.
+    pub sequencer_address: Option<SequencerAddress>,
+    /// The number of transactions in the block, which lets missing transactions be detected.
+    pub transaction_count: usize,
+    /// The number of events emitted by the block's transactions.
+    pub event_count: usize,
+}
+
+/// The subset of [StarknetBlock] needed to link blocks together, e.g. when checking for reorgs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StarknetBlockHeader {
+    pub number: StarknetBlockNumber,
+    pub hash: StarknetBlockHash,
+    pub parent_hash: StarknetBlockHash,
+    pub root: GlobalRoot,
+}
+
+impl From<&StarknetBlock> for StarknetBlockHeader {
+    fn from(block: &StarknetBlock) -> Self {
+        Self {
+            number: block.number,
+            hash: block.hash,
+            parent_hash: block.parent_hash,
+            root: block.root,
+        }
+    }
+}
+
+/// A block summary as shown in a block list, see [StarknetBlocksTable::get_headers_with_version].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockListEntry {
+    pub number: StarknetBlockNumber,
+    pub hash: StarknetBlockHash,
+    pub timestamp: StarknetBlockTimestamp,
+    pub gas_price: GasPrice,
+    pub sequencer_address: Option<SequencerAddress>,
+    pub transaction_count: usize,
+    pub event_count: usize,
+    /// The sequencer version, which is only stored since starknet 0.9.1.
+    pub version: Option<String>,
+}
+
+/// StarknetVersionsTable tracks `starknet_versions` table, which just interns the version
+/// metadata on each block.
+///
+/// It was decided to go with interned approach, as we couldn't be sure that a semantic version
+/// string format is followed. Semantic version strings may have been cheaper to just store
+/// in-line.
+///
+/// Introduced in `revision_0014`.
+pub struct StarknetVersionsTable;
+
+impl StarknetVersionsTable {
+    /// Interns, or makes sure there's a unique row for each version.
+    ///
+    /// These are not deleted automatically, as many blocks share a single starknet version, see
+    /// [StarknetVersionsTable::prune_unreferenced].
+    fn intern(transaction: &Transaction<'_>, version: &str) -> anyhow::Result<i64> {
+        let id: Option<i64> = transaction
+            .query_row(
+                "SELECT id FROM starknet_versions WHERE version = ?",
+                &[version],
+                |r| Ok(r.get_unwrap(0)),
+            )
+            .optional()
+            .context("Querying for an existing starknet_version")?;
+
+        let id = if let Some(id) = id {
+            id
+        } else {
+            // sqlite "autoincrement" for integer primary keys works like this: we leave it out of
+            // the insert, even though it's not null, it will get max(id)+1 assigned, which we can
+            // read back with last_insert_rowid
+            let rows = transaction
+                .execute(
+                    "INSERT INTO starknet_versions(version) VALUES (?)",
+                    [version],
+                )
+                .context("Inserting unique starknet_version")?;
+
+            anyhow::ensure!(rows == 1, "Unexpected number of rows inserted: {rows}");
+
+            transaction.last_insert_rowid()
+        };
+
+        Ok(id)
+    }
+
+    /// Deletes the versions which no block references, which is the case once all blocks of a
+    /// version were reorged away. Returns the number of deleted versions.
+    pub fn prune_unreferenced(tx: &Transaction<'_>) -> anyhow::Result<usize> {
+        tx.execute(
+            "DELETE FROM starknet_versions WHERE id NOT IN
+                (SELECT version_id FROM starknet_blocks WHERE version_id IS NOT NULL)",
+            [],
+        )
+        .context("Deleting unreferenced versions")
+    }
+}
+
+/// The outcome of [backfill_block_versions].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VersionBackfill {
+    /// The number of blocks whose version was stored.
+    pub updated: usize,
+    /// The number of blocks left without a version, as `fetch` returned none.
+    pub unavailable: usize,
+}
+
+/// Stores the sequencer version of the blocks in `range` which have none, as returned by `fetch`.
+///
+/// Blocks synced before versions were stored (`revision_0014`) have none. Only those are fetched,
+/// so covering a long range with several calls, each committed separately, lets an interrupted
+/// backfill resume without fetching the committed blocks again.
+pub fn backfill_block_versions(
+    tx: &Transaction<'_>,
+    fetch: impl Fn(StarknetBlockNumber) -> Option<String>,
+    range: std::ops::RangeInclusive<StarknetBlockNumber>,
+) -> anyhow::Result<VersionBackfill> {
+    let blocks = tx
+        .prepare(
+            "SELECT number FROM starknet_blocks
+                WHERE number BETWEEN ? AND ? AND version_id IS NULL ORDER BY number",
+        )
+        .context("Preparing statement")?
+        .query_map([range.start(), range.end()], |row| row.get(0))
+        .context("Querying blocks without version")?
+        .collect::<Result<Vec<StarknetBlockNumber>, _>>()
+        .context("Iterating over rows")?;
+
+    let mut update = tx
+        .prepare("UPDATE starknet_blocks SET version_id = ? WHERE number = ?")
+        .context("Preparing statement")?;
+    let mut backfill = VersionBackfill::default();
+    for number in blocks {
+        match fetch(number) {
+            Some(version) => {
+                let version_id = StarknetVersionsTable::intern(tx, &version)?;
+                update
+                    .execute(params![version_id, number])
+                    .context("Updating block version")?;
+                backfill.updated += 1;
+            }
+            None => backfill.unavailable += 1,
+        }
+    }
+
+    Ok(backfill)
+}
+
+/// Stores the contract state hash along with its preimage. This is useful to
+/// map between the global state tree and the contracts tree.
+///
+/// Specifically it stores
+///
+/// - [contract state hash](ContractStateHash)
+/// - [class hash](ClassHash)
+/// - [contract root](ContractRoot)
+pub struct ContractsStateTable {}
+
+impl ContractsStateTable {
+    /// Insert a state hash into the table, overwrites the data if the hash already exists.
+    pub fn upsert(
+        transaction: &Transaction<'_>,
+        state_hash: ContractStateHash,
+        hash: ClassHash,
+        root: ContractRoot,
+        nonce: ContractNonce,
+    ) -> anyhow::Result<()> {
+        let timer = WriteTimer::start(metrics::UPSERT_CONTRACT_STATE);
+        let _span = tracing::trace_span!("upsert_contract_state", ?state_hash).entered();
+
+        let rows = transaction.execute(
+            "INSERT OR IGNORE INTO contract_states (state_hash, hash, root, nonce) VALUES (:state_hash, :hash, :root, :nonce)",
+            named_params! {
+                ":state_hash": state_hash,
+                ":hash": hash,
+                ":root": root,
+                ":nonce": nonce,
+            },
+        )?;
+
+        timer.finish(rows, 0);
+
+        Ok(())
+    }
+
+    /// Inserts many state hashes at once, see [ContractsStateTable::upsert].
+    ///
+    /// The statement is only prepared once, which makes this cheaper than calling
+    /// [upsert](ContractsStateTable::upsert) for each row.
+    pub fn upsert_many(
+        transaction: &Transaction<'_>,
+        rows: &[(ContractStateHash, ClassHash, ContractRoot, ContractNonce)],
+    ) -> anyhow::Result<()> {
+        let timer = WriteTimer::start(metrics::UPSERT_CONTRACT_STATE);
+
+        let mut stmt = transaction
+            .prepare(
+                "INSERT OR IGNORE INTO contract_states (state_hash, hash, root, nonce) VALUES (:state_hash, :hash, :root, :nonce)",
+            )
+            .context("Preparing statement")?;
+
+        let mut inserted = 0;
+        for (state_hash, hash, root, nonce) in rows {
+            inserted += stmt
+                .execute(named_params! {
+                    ":state_hash": state_hash,
+                    ":hash": hash,
+                    ":root": root,
+                    ":nonce": nonce,
+                })
+                .context("Inserting contract state")?;
+        }
+
+        timer.finish(inserted, 0);
+
+        Ok(())
+    }
+
+    /// Gets the root associated with the given state hash, or [None]
+    /// if it does not exist.
+    pub fn get_root(
+        transaction: &Transaction<'_>,
+        state_hash: ContractStateHash,
+    ) -> Result<Option<ContractRoot>, StorageError> {
+        transaction
+            .query_row(
+                "SELECT root FROM contract_states WHERE state_hash = :state_hash",
+                named_params! {
+                    ":state_hash": state_hash
+                },
+                |row| row.get("root"),
+            )
+            .optional()
+            .map_err(|e| e.into())
+    }
+
+    /// Gets the nonce associated with the given state hash, or [None]
+    /// if it does not exist.
+    pub fn get_nonce(
+        transaction: &Transaction<'_>,
+        state_hash: ContractStateHash,
+    ) -> Result<Option<ContractNonce>, StorageError> {
+        transaction
+            .query_row(
+                "SELECT nonce FROM contract_states WHERE state_hash = :state_hash",
+                named_params! {
+                    ":state_hash": state_hash
+                },
+                |row| row.get("nonce"),
+            )
+            .optional()
+            .map_err(|e| e.into())
+    }
+
+    /// Gets the root and nonce associated with the given state hash, or [None]
+    /// if it does not exist.
+    pub fn get_root_and_nonce(
+        transaction: &Transaction<'_>,
+        state_hash: ContractStateHash,
+    ) -> Result<Option<(ContractRoot, ContractNonce)>, StorageError> {
+        transaction
+            .query_row(
+                "SELECT root, nonce FROM contract_states WHERE state_hash = :state_hash",
+                named_params! {
+                    ":state_hash": state_hash
+                },
+                |row| {
+                    let root = row.get("root")?;
+                    let nonce = row.get("nonce")?;
+
+                    Ok((root, nonce))
+                },
+            )
+            .optional()
+            .map_err(|e| e.into())
+    }
+}
+
+/// Stores all known [Starknet state updates][crate::rpc::v01::types::reply::StateUpdate].
+pub struct StarknetStateUpdatesTable {}
+
+impl StarknetStateUpdatesTable {
+    /// Same as [StarknetStateUpdatesTable::insert_with_compressor], using the default
+    /// [CompressionConfig](crate::storage::CompressionConfig) rather than the one of the
+    /// [Storage](crate::storage::Storage).
+    #[cfg(test)]
+    pub fn insert(
+        tx: &Transaction<'_>,
+        block_hash: StarknetBlockHash,
+        state_update: &StateUpdate,
+    ) -> anyhow::Result<()> {
+        let mut compressor = crate::storage::CompressionConfig::default().compressor()?;
+        Self::insert_with_compressor(tx, &mut compressor, block_hash, state_update)
+    }
+
+    /// Inserts a StarkNet state update accociated with a particular block into the
+    /// [StarknetStateUpdatesTable], compressed using the given `compressor`, which allows
+    /// sharing it with other writes of the same block.
+    ///
+    /// Overwrites existing data if the block hash already exists.
+    pub fn insert_with_compressor(
+        tx: &Transaction<'_>,
+        compressor: &mut zstd::bulk::Compressor<'_>,
+        block_hash: StarknetBlockHash,
+        state_update: &StateUpdate,
+    ) -> anyhow::Result<()> {
+        let timer = WriteTimer::start(metrics::INSERT_STATE_UPDATE);
+        let span = tracing::debug_span!(
+            "insert_state_update",
+            ?block_hash,
+            deployed_contracts = state_update.state_diff.deployed_contracts.len(),
+            bytes = tracing::field::Empty,
+        )
+        .entered();
+
+        let serialized =
+            serde_json::to_vec(&state_update).context("Serialize Starknet state update")?;
+
+        let compressed = compressor
+            .compress(&serialized)
+            .context("Compress Starknet state update")?;
+        span.record("bytes", &compressed.len());
+
+        tx.execute(
+            r"INSERT INTO starknet_state_updates (block_hash, data) VALUES (:block_hash, :data)",
+            named_params![":block_hash": block_hash, ":data": &compressed,],
+        )
+        .context("Insert state update data into state updates table")?;
+
+        DeployedContractsTable::insert(tx, block_hash, &state_update.state_diff.deployed_contracts)
+            .context("Index deployed contracts")?;
+
+        timer.finish(1, compressed.len());
+
+        Ok(())
+    }
+
+    /// Inserts a state update as returned by [StarknetStateUpdatesTable::get_raw], i.e. zstd
+    /// compressed JSON, without recompressing it.
+    ///
+    /// Overwrites existing data if the block hash already exists.
+    pub fn insert_raw(
+        tx: &Transaction<'_>,
+        block_hash: StarknetBlockHash,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        let state_update: StateUpdate = decode_blob(data, "starknet_state_updates", "state update")
+            .context("Decoding state update")?;
+
+        tx.execute(
+            r"INSERT INTO starknet_state_updates (block_hash, data) VALUES (:block_hash, :data)",
+            named_params![":block_hash": block_hash, ":data": data],
+        )
+        .context("Insert state update data into state updates table")?;
+
+        DeployedContractsTable::insert(tx, block_hash, &state_update.state_diff.deployed_contracts)
+            .context("Index deployed contracts")?;
+
+        Ok(())
+    }
+
+    /// Gets a StarkNet state update for block.
+    pub fn get(
+        tx: &Transaction<'_>,
+        block_hash: StarknetBlockHash,
+    ) -> Result<Option<StateUpdate>, StorageError> {
+        let mut stmt = tx
+            .prepare("SELECT data FROM starknet_state_updates WHERE block_hash = ?1")
+            .context("Preparing statement")?;
+
+        let mut rows = stmt.query([block_hash]).context("Executing query")?;
+
+        let row = match rows.next()? {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let state_update = row.get_ref_unwrap(0).as_blob()?;
+        let state_update = decode_blob(state_update, "starknet_state_updates", "state update")?;
+
+        Ok(Some(state_update))
+    }
+
+    /// Returns the state update of `block_hash` as stored, i.e. zstd compressed JSON.
+    pub fn get_raw(
+        tx: &Transaction<'_>,
+        block_hash: StarknetBlockHash,
+    ) -> Result<Option<Vec<u8>>, StorageError> {
+        Ok(tx
+            .query_row(
+                "SELECT data FROM starknet_state_updates WHERE block_hash = ?",
+                [block_hash],
+                |row| row.get(0),
+            )
+            .optional()
+            .context("Querying state update")?)
+    }
+
+    /// Returns the state update of the latest block as stored, i.e. zstd compressed JSON.
+    ///
+    /// Returns [None] if there are no blocks, or the latest block has no state update.
+    pub fn get_latest_raw(tx: &Transaction<'_>) -> Result<Option<Vec<u8>>, StorageError> {
+        Ok(tx
+            .query_row(
+                r"SELECT data FROM starknet_state_updates WHERE block_hash = (
+    SELECT hash FROM starknet_blocks ORDER BY number DESC LIMIT 1
+)",
+                [],
+                |row| row.get(0),
+            )
+            .optional()
+            .context("Querying latest state update")?)
+    }
+
+    /// Returns the [number](StarknetBlockNumber) of the highest block with a stored state update.
+    pub fn get_highest_block_number(
+        tx: &Transaction<'_>,
+    ) -> Result<Option<StarknetBlockNumber>, StorageError> {
+        Ok(tx
+            .query_row(
+                "SELECT MAX(starknet_blocks.number) FROM starknet_state_updates
+                JOIN starknet_blocks ON starknet_state_updates.block_hash = starknet_blocks.hash",
+                [],
+                |row| row.get(0),
+            )
+            .context("Querying highest block with a state update")?)
+    }
+
+    /// Deletes the state updates of all blocks where `block number >= reorg_tail`.
+    ///
+    /// These are also deleted along with their block, but this allows deleting them first.
+    pub fn reorg(tx: &Transaction<'_>, reorg_tail: StarknetBlockNumber) -> anyhow::Result<()> {
+        tx.execute(
+            "DELETE FROM starknet_state_updates WHERE block_hash IN (SELECT hash FROM starknet_blocks WHERE number >= ?)",
+            [reorg_tail],
+        )?;
+        Ok(())
+    }
+}
+
+/// Indexes the contracts deployed in each [state update](StarknetStateUpdatesTable), so that a
+/// contract's deployment can be found without scanning all state updates.
+///
+/// Only a contract's first deployment is kept. Rows are deleted along with their state update,
+/// i.e. when their block is removed by a reorg.
+pub struct DeployedContractsTable {}
+
+impl DeployedContractsTable {
+    /// Records the contracts deployed in the given block. This is done by
+    /// [StarknetStateUpdatesTable::insert].
+    ///
+    /// Contracts which are already recorded keep their original deployment.
+    pub fn insert(
+        tx: &Transaction<'_>,
+        block_hash: StarknetBlockHash,
+        contracts: &[DeployedContract],
+    ) -> anyhow::Result<()> {
+        let mut stmt = tx
+            .prepare_cached(
+                r"INSERT OR IGNORE INTO deployed_contracts ( contract_address,  class_hash,  block_hash)
+                                                    VALUES (:contract_address, :class_hash, :block_hash)",
+            )
+            .context("Preparing statement")?;
+
+        for contract in contracts {
+            stmt.execute(named_params! {
+                ":contract_address": contract.address,
+                ":class_hash": contract.class_hash,
+                ":block_hash": block_hash,
+            })
+            .context("Inserting deployed contract")?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the block in which the contract was first deployed, along with the deployed class.
+    pub fn get_deployment(
+        tx: &Transaction<'_>,
+        contract: ContractAddress,
+    ) -> anyhow::Result<Option<(StarknetBlockNumber, ClassHash)>> {
+        tx.query_row(
+            r"SELECT starknet_blocks.number, deployed_contracts.class_hash FROM deployed_contracts
+    JOIN starknet_blocks ON starknet_blocks.hash = deployed_contracts.block_hash
+    WHERE deployed_contracts.contract_address = ?",
+            [contract],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .context("Querying contract deployment")
+    }
+
+    /// Returns the contracts first deployed in the given block, in state update order.
+    pub fn get_contracts_deployed_in_block(
+        tx: &Transaction<'_>,
+        number: StarknetBlockNumber,
+    ) -> anyhow::Result<Vec<(ContractAddress, ClassHash)>> {
+        let mut stmt = tx
+            .prepare(
+                r"SELECT deployed_contracts.contract_address, deployed_contracts.class_hash FROM deployed_contracts
+    JOIN starknet_blocks ON starknet_blocks.hash = deployed_contracts.block_hash
+    WHERE starknet_blocks.number = ?
+    ORDER BY deployed_contracts.rowid",
+            )
+            .context("Preparing statement")?;
+
+        let contracts = stmt
+            .query_map([number], |row| Ok((row.get(0)?, row.get(1)?)))
+            .context("Executing query")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Iterating over rows")?;
+
+        Ok(contracts)
+    }
+}
+
+/// Tracks the class of each contract over time, so that the contracts currently using a class
+/// can be found.
+///
+/// A contract has a row for every block in which its class was set, i.e. its deployment and any
+/// later class replacement. Its current class is the one with the highest block number, which lets
+/// [ContractClassIndexTable::reorg] roll back replacements by deleting rows.
+pub struct ContractClassIndexTable {}
+
+impl ContractClassIndexTable {
+    /// The number of contracts returned per page by [ContractClassIndexTable::get_contracts_by_class].
+    pub const PAGE_SIZE: usize = 1000;
+
+    /// Sets the class of the contract as of the given block. This is done by sync for deployed
+    /// contracts and class replacements.
+    pub fn insert(
+        tx: &Transaction<'_>,
+        block: StarknetBlockNumber,
+        contract: ContractAddress,
+        class_hash: ClassHash,
+    ) -> anyhow::Result<()> {
+        tx.prepare_cached(
+            r"INSERT OR REPLACE INTO contract_class_index ( contract_address,  class_hash,  block_number)
+                                                    VALUES (:contract_address, :class_hash, :block_number)",
+        )
+        .context("Preparing statement")?
+        .execute(named_params! {
+            ":contract_address": contract,
+            ":class_hash": class_hash,
+            ":block_number": block,
+        })
+        .context("Inserting contract class")?;
+
+        Ok(())
+    }
+
+    /// Returns the current class of the contract.
+    pub fn get_class(
+        tx: &Transaction<'_>,
+        contract: ContractAddress,
+    ) -> anyhow::Result<Option<ClassHash>> {
+        tx.query_row(
+            r"SELECT class_hash FROM contract_class_index WHERE contract_address = ?
+    ORDER BY block_number DESC LIMIT 1",
+            [contract],
+            |row| row.get(0),
+        )
+        .optional()
+        .context("Querying contract class")
+    }
+
+    /// Returns a page of the contracts whose current class is `class_hash`, ordered by address.
+    ///
+    /// Pages hold [PAGE_SIZE](Self::PAGE_SIZE) contracts and are numbered from zero.
+    pub fn get_contracts_by_class(
+        tx: &Transaction<'_>,
+        class_hash: ClassHash,
+        page: usize,
+    ) -> anyhow::Result<Vec<ContractAddress>> {
+        let mut stmt = tx
+            .prepare_cached(
+                r"SELECT contract_address FROM contract_class_index AS current
+    WHERE class_hash = :class_hash AND block_number = (
+        SELECT MAX(block_number) FROM contract_class_index
+        WHERE contract_address = current.contract_address
+    )
+    ORDER BY contract_address
+    LIMIT :limit OFFSET :offset",
+            )
+            .context("Preparing statement")?;
+
+        let offset = page.saturating_mul(Self::PAGE_SIZE);
+        let contracts = stmt
+            .query_map(
+                named_params! {
+                    ":class_hash": class_hash,
+                    ":limit": Self::PAGE_SIZE,
+                    ":offset": offset,
+                },
+                |row| row.get(0),
+            )
+            .context("Executing query")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Iterating over rows")?;
+
+        Ok(contracts)
+    }
+
+    /// Returns the number of contracts whose current class is `class_hash`.
+    pub fn count_contracts_by_class(
+        tx: &Transaction<'_>,
+        class_hash: ClassHash,
+    ) -> anyhow::Result<usize> {
+        tx.query_row(
+            r"SELECT COUNT(1) FROM contract_class_index AS current
+    WHERE class_hash = ? AND block_number = (
+        SELECT MAX(block_number) FROM contract_class_index
+        WHERE contract_address = current.contract_address
+    )",
+            [class_hash],
+            |row| row.get(0),
+        )
+        .context("Counting contracts")
+    }
+
+    /// Removes all rows where `block_number >= reorg_tail`, which restores each contract's class
+    /// as of the block before `reorg_tail`.
+    pub fn reorg(tx: &Transaction<'_>, reorg_tail: StarknetBlockNumber) -> anyhow::Result<()> {
+        tx.execute(
+            "DELETE FROM contract_class_index WHERE block_number >= ?",
+            [reorg_tail],
+        )
+        .context("Deleting contract classes")?;
+        Ok(())
+    }
+}
+
+/// A deployed contract along with its current class and nonce, as listed by
+/// [ContractSummariesTable::list_contracts].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContractSummary {
+    pub address: ContractAddress,
+    pub class_hash: ClassHash,
+    pub nonce: ContractNonce,
+    /// The block in which the contract was first deployed.
+    pub deployed_at_block: StarknetBlockNumber,
+}
+
+/// Tracks the class and nonce of every deployed contract, so that contracts can be listed by
+/// address.
+///
+/// As for [ContractClassIndexTable], a contract has a row for every block which deployed it or
+/// updated its nonce, holding its class and nonce as of that block. Its current state is the row
+/// with the highest block number and its deployment the one with the lowest, which lets
+/// [ContractSummariesTable::reorg] roll back to the canonical chain by deleting rows.
+pub struct ContractSummariesTable {}
+
+impl ContractSummariesTable {
+    /// Applies the contracts deployed and the nonces updated by the block's state update. This is
+    /// done by sync along with the rest of the state update.
+    ///
+    /// Nonce updates of contracts which were never deployed are ignored, as their class is
+    /// unknown.
+    pub fn apply(
+        tx: &Transaction<'_>,
+        block: StarknetBlockNumber,
+        deployed_contracts: &[DeployedContract],
+        nonces: &[Nonce],
+    ) -> anyhow::Result<()> {
+        let mut updates = std::collections::BTreeMap::new();
+        for contract in deployed_contracts {
+            let nonce = Self::get_current(tx, contract.address)?
+                .map(|(_, nonce)| nonce)
+                .unwrap_or(ContractNonce::ZERO);
+            updates.insert(contract.address, (contract.class_hash, nonce));
+        }
+        for nonce in nonces {
+            let class_hash = match updates.get(&nonce.contract_address) {
+                Some((class_hash, _)) => Some(*class_hash),
+                None => {
+                    Self::get_current(tx, nonce.contract_address)?.map(|(class_hash, _)| class_hash)
+                }
+            };
+            if let Some(class_hash) = class_hash {
+                updates.insert(nonce.contract_address, (class_hash, nonce.nonce));
+            }
+        }
+
+        let mut stmt = tx
+            .prepare_cached(
+                r"INSERT OR REPLACE INTO contract_summaries ( contract_address,  block_number,  class_hash,  nonce)
+                                                     VALUES (:contract_address, :block_number, :class_hash, :nonce)",
+            )
+            .context("Preparing statement")?;
+        for (address, (class_hash, nonce)) in updates {
+            stmt.execute(named_params! {
+                ":contract_address": address,
+                ":block_number": block,
+                ":class_hash": class_hash,
+                ":nonce": nonce,
+            })
+            .context("Inserting contract summary")?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the current class and nonce of the contract.
+    fn get_current(
+        tx: &Transaction<'_>,
+        contract: ContractAddress,
+    ) -> anyhow::Result<Option<(ClassHash, ContractNonce)>> {
+        tx.prepare_cached(
+            r"SELECT class_hash, nonce FROM contract_summaries WHERE contract_address = ?
+    ORDER BY block_number DESC LIMIT 1",
+        )
+        .context("Preparing statement")?
+        .query_row([contract], |row| Ok((row.get(0)?, row.get(1)?)))
+        .optional()
+        .context("Querying contract summary")
+    }
+
+    /// Returns up to `limit` contracts ordered by address, starting after `start_after`.
+    ///
+    /// Pass the address of the last contract of a page to get the next page. Unlike an offset,
+    /// this is cheap for any page and stays gap-free if contracts are deployed in between.
+    pub fn list_contracts(
+        tx: &Transaction<'_>,
+        start_after: Option<ContractAddress>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<ContractSummary>> {
+        let start_condition = match start_after {
+            Some(_) => "AND current.contract_address > :start_after",
+            None => "",
+        };
+        let mut stmt = tx
+            .prepare_cached(&format!(
+                r"SELECT current.contract_address, current.class_hash, current.nonce,
+        (SELECT MIN(block_number) FROM contract_summaries
+         WHERE contract_address = current.contract_address) AS deployed_at_block
+    FROM contract_summaries AS current
+    WHERE current.block_number = (
+        SELECT MAX(block_number) FROM contract_summaries
+        WHERE contract_address = current.contract_address
+    ) {start_condition}
+    ORDER BY current.contract_address
+    LIMIT :limit"
+            ))
+            .context("Preparing statement")?;
+
+        let mut params = named_params! { ":limit": limit }.to_vec();
+        if let Some(start_after) = &start_after {
+            params.push((":start_after", start_after));
+        }
+
+        let contracts = stmt
+            .query_map(params.as_slice(), |row| {
+                Ok(ContractSummary {
+                    address: row.get(0)?,
+                    class_hash: row.get(1)?,
+                    nonce: row.get(2)?,
+                    deployed_at_block: row.get(3)?,
+                })
+            })
+            .context("Executing query")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Iterating over rows")?;
+
+        Ok(contracts)
+    }
+
+    /// Returns the number of deployed contracts.
+    pub fn count_contracts(tx: &Transaction<'_>) -> anyhow::Result<usize> {
+        tx.query_row(
+            "SELECT COUNT(DISTINCT contract_address) FROM contract_summaries",
+            [],
+            |row| row.get(0),
+        )
+        .context("Counting contracts")
+    }
+
+    /// Removes all rows where `block_number >= reorg_tail`, which restores each contract's class
+    /// and nonce as of the block before `reorg_tail`.
+    pub fn reorg(tx: &Transaction<'_>, reorg_tail: StarknetBlockNumber) -> anyhow::Result<()> {
+        tx.execute(
+            "DELETE FROM contract_summaries WHERE block_number >= ?",
+            [reorg_tail],
+        )
+        .context("Deleting contract summaries")?;
+        Ok(())
+    }
+}
+
+/// Records every value written to a contract's storage slot, so that the storage of any block
+/// can be read without walking the state trie.
+///
+/// A slot has a row for every block which wrote it. Its value as of a block is the one of the
+/// latest row at or before that block, which lets [StorageUpdatesTable::reorg] roll back by
+/// deleting rows.
+pub struct StorageUpdatesTable {}
+
+impl StorageUpdatesTable {
+    /// Records the storage diffs of the block's state update. This is done by sync along with
+    /// the rest of the state update.
+    pub fn insert(
+        tx: &Transaction<'_>,
+        block: StarknetBlockNumber,
+        storage_diffs: &[StorageDiff],
+    ) -> anyhow::Result<()> {
+        let mut stmt = tx
+            .prepare_cached(
+                r"INSERT OR REPLACE INTO storage_updates ( block_number,  contract_address,  storage_key,  value)
+                                                  VALUES (:block_number, :contract_address, :storage_key, :value)",
+            )
+            .context("Preparing statement")?;
+
+        for diff in storage_diffs {
+            stmt.execute(named_params! {
+                ":block_number": block,
+                ":contract_address": diff.address,
+                ":storage_key": diff.key,
+                ":value": diff.value,
+            })
+            .context("Inserting storage update")?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the value of the contract's storage slot as of the given block, or [None] if the
+    /// slot was not written up to that block.
+    pub fn get_storage_at_block(
+        tx: &Transaction<'_>,
+        contract_address: ContractAddress,
+        key: StorageAddress,
+        block: StarknetBlockNumber,
+    ) -> anyhow::Result<Option<StorageValue>> {
+        tx.prepare_cached(
+            r"SELECT value FROM storage_updates
+    WHERE contract_address = :contract_address AND storage_key = :storage_key AND block_number <= :block_number
+    ORDER BY block_number DESC LIMIT 1",
+        )
+        .context("Preparing statement")?
+        .query_row(
+            named_params! {
+                ":contract_address": contract_address,
+                ":storage_key": key,
+                ":block_number": block,
+            },
+            |row| row.get(0),
+        )
+        .optional()
+        .context("Querying storage value")
+    }
+
+    /// Removes all rows where `block_number >= reorg_tail`, which restores each slot's value as
+    /// of the block before `reorg_tail`.
+    pub fn reorg(tx: &Transaction<'_>, reorg_tail: StarknetBlockNumber) -> anyhow::Result<()> {
+        tx.execute(
+            "DELETE FROM storage_updates WHERE block_number >= ?",
+            [reorg_tail],
+        )
+        .context("Deleting storage updates")?;
+        Ok(())
+    }
+}
+
+/// Links an L1 handler transaction to the L1 to L2 message it consumed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct L1HandlerOrigin {
+    pub starknet_transaction_hash: StarknetTransactionHash,
+    pub message_nonce: L1ToL2MessageNonce,
+    /// The Ethereum transaction which sent the message, if it has been
+    /// [attached](L1HandlerOriginsTable::attach_ethereum_origin).
+    pub ethereum_transaction_hash: Option<EthereumTransactionHash>,
+}
+
+/// Indexes the L1 handler transactions by the nonce of the L1 to L2 message they consumed, and
+/// links them to the Ethereum transaction which sent the message.
+///
+/// Messages sent before nonces were introduced cannot be identified and are not indexed.
+pub struct L1HandlerOriginsTable {}
+
+impl L1HandlerOriginsTable {
+    /// Records the message consumed by an L1 handler transaction. This is done by
+    /// [StarknetTransactionsTable::upsert].
+    pub fn insert(
+        tx: &Transaction<'_>,
+        transaction: StarknetTransactionHash,
+        nonce: L1ToL2MessageNonce,
+    ) -> anyhow::Result<()> {
+        tx.execute(
+            r"INSERT INTO l1_handler_origins ( starknet_transaction_hash,  message_nonce)
+                                      VALUES (:starknet_transaction_hash, :message_nonce)
+    ON CONFLICT(starknet_transaction_hash) DO UPDATE SET message_nonce = excluded.message_nonce",
+            named_params! {
+                ":starknet_transaction_hash": transaction,
+                ":message_nonce": nonce,
+            },
+        )
+        .context("Inserting L1 handler origin")?;
+
+        Ok(())
+    }
+
+    /// Records the Ethereum transaction which sent the message with the given nonce. This is done
+    /// by L1 sync, independently of the L1 handler transaction which consumes the message.
+    ///
+    /// Returns `false` if no L1 handler transaction consumed the message (yet).
+    pub fn attach_ethereum_origin(
+        tx: &Transaction<'_>,
+        nonce: L1ToL2MessageNonce,
+        ethereum_transaction: EthereumTransactionHash,
+        ethereum_block: EthereumBlockNumber,
+    ) -> anyhow::Result<bool> {
+        tx.execute(
+            r"INSERT INTO l1_to_l2_messages ( message_nonce,  ethereum_transaction_hash,  ethereum_block_number)
+                                     VALUES (:message_nonce, :ethereum_transaction_hash, :ethereum_block_number)
+    ON CONFLICT(message_nonce) DO UPDATE SET
+        ethereum_transaction_hash = excluded.ethereum_transaction_hash,
+        ethereum_block_number = excluded.ethereum_block_number",
+            named_params! {
+                ":message_nonce": nonce,
+                ":ethereum_transaction_hash": &ethereum_transaction.0[..],
+                ":ethereum_block_number": ethereum_block.0,
+            },
+        )
+        .context("Inserting L1 to L2 message")?;
+
+        let consumed = tx
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM l1_handler_origins WHERE message_nonce = ?)",
+                [nonce],
+                |row| row.get(0),
+            )
+            .context("Querying L1 handler origin")?;
+
+        Ok(consumed)
+    }
+
+    /// Returns the L1 handler transaction which consumed the message with the given nonce.
+    ///
+    /// Should several transactions have consumed it, the earliest one is returned.
+    pub fn get_l1_handler_by_message(
+        tx: &Transaction<'_>,
+        nonce: L1ToL2MessageNonce,
+    ) -> anyhow::Result<Option<L1HandlerOrigin>> {
+        Self::get(tx, "message_nonce", &nonce)
+    }
+
+    /// Returns the message consumed by the given L1 handler transaction.
+    pub fn get_by_starknet_transaction(
+        tx: &Transaction<'_>,
+        transaction: StarknetTransactionHash,
+    ) -> anyhow::Result<Option<L1HandlerOrigin>> {
+        Self::get(tx, "starknet_transaction_hash", &transaction)
+    }
+
+    fn get(
+        tx: &Transaction<'_>,
+        column: &str,
+        value: &dyn rusqlite::ToSql,
+    ) -> anyhow::Result<Option<L1HandlerOrigin>> {
+        let mut stmt = tx
+            .prepare_cached(&format!(
+                r"SELECT starknet_transaction_hash, l1_handler_origins.message_nonce, ethereum_transaction_hash
+    FROM l1_handler_origins
+    JOIN starknet_transactions ON starknet_transactions.hash = l1_handler_origins.starknet_transaction_hash
+    JOIN starknet_blocks ON starknet_blocks.hash = starknet_transactions.block_hash
+    LEFT JOIN l1_to_l2_messages ON l1_to_l2_messages.message_nonce = l1_handler_origins.message_nonce
+    WHERE l1_handler_origins.{column} = ?
+    ORDER BY starknet_blocks.number, starknet_transactions.idx
+    LIMIT 1"
+            ))
+            .context("Preparing statement")?;
+
+        let mut rows = stmt.query([value]).context("Executing query")?;
+        let row = match rows.next()? {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let ethereum_transaction_hash = row
+            .get_ref_unwrap("ethereum_transaction_hash")
+            .as_blob_or_null()?
+            .map(|hash| {
+                let hash: [u8; 32] = hash
+                    .try_into()
+                    .context("Ethereum transaction hash has invalid length")?;
+                anyhow::Ok(EthereumTransactionHash(H256(hash)))
+            })
+            .transpose()?;
+
+        Ok(Some(L1HandlerOrigin {
+            starknet_transaction_hash: row.get_unwrap("starknet_transaction_hash"),
+            message_nonce: row.get_unwrap("message_nonce"),
+            ethereum_transaction_hash,
+        }))
+    }
+
+    /// Removes the messages sent in Ethereum blocks `>= reorg_tail`, which are no longer part
+    /// of the L1 chain.
+    pub fn ethereum_reorg(
+        tx: &Transaction<'_>,
+        reorg_tail: EthereumBlockNumber,
+    ) -> anyhow::Result<()> {
+        tx.execute(
+            "DELETE FROM l1_to_l2_messages WHERE ethereum_block_number >= ?",
+            [reorg_tail.0],
+        )
+        .context("Deleting L1 to L2 messages")?;
+        Ok(())
+    }
+
+    /// Removes the origins of the transactions of blocks `>= reorg_tail`.
+    ///
+    /// Must be done before [StarknetTransactionsTable::reorg], as the transactions are used to
+    /// find the blocks of the origins.
+    pub fn reorg(tx: &Transaction<'_>, reorg_tail: StarknetBlockNumber) -> anyhow::Result<()> {
+        tx.execute(
+            r"DELETE FROM l1_handler_origins WHERE starknet_transaction_hash IN (
+    SELECT starknet_transactions.hash FROM starknet_transactions
+    JOIN starknet_blocks ON starknet_transactions.block_hash = starknet_blocks.hash
+    WHERE starknet_blocks.number >= ?
+)",
+            [reorg_tail],
+        )
+        .context("Deleting L1 handler origins")?;
+        Ok(())
+    }
+}
+
+/// Stores the canonical StarkNet block chain.
+pub struct CanonicalBlocksTable {}
+
+impl CanonicalBlocksTable {
+    pub fn insert(
+        tx: &Transaction<'_>,
+        number: StarknetBlockNumber,
+        hash: StarknetBlockHash,
+    ) -> anyhow::Result<()> {
+        let rows_changed = tx.execute(
+            "INSERT INTO canonical_blocks(number, hash) values(?,?)",
+            params![number, hash],
+        )?;
+        assert_eq!(rows_changed, 1);
+
+        Ok(())
+    }
+
+    /// Returns the hash of the canonical block at the given height.
+    pub fn get(
+        tx: &Transaction<'_>,
+        number: StarknetBlockNumber,
+    ) -> anyhow::Result<Option<StarknetBlockHash>> {
+        tx.query_row(
+            "SELECT hash FROM canonical_blocks WHERE number = ?",
+            [number],
+            |row| row.get(0),
+        )
+        .optional()
+        .context("Querying canonical block hash")
+    }
+
+    /// Returns true if the block with the given number and hash is part of the canonical chain,
+    /// i.e. it has not been orphaned by a reorg.
+    pub fn contains(
+        tx: &Transaction<'_>,
+        number: StarknetBlockNumber,
+        hash: StarknetBlockHash,
+    ) -> anyhow::Result<bool> {
+        tx.query_row(
+            "SELECT EXISTS(SELECT 1 FROM canonical_blocks WHERE number = ? AND hash = ?)",
+            params![number, hash],
+            |row| row.get(0),
+        )
+        .context("Querying canonical block")
+    }
+
+    /// Removes all rows where `number >= reorg_tail`.
+    pub fn reorg(tx: &Transaction<'_>, reorg_tail: StarknetBlockNumber) -> anyhow::Result<()> {
+        tx.execute(
+            "DELETE FROM canonical_blocks WHERE number >= ?",
+            [reorg_tail],
+        )?;
+        Ok(())
+    }
+}
+
+/// Archives the JSON of blocks exactly as the sequencer served them, to allow replaying or
+/// debugging blocks which were parsed incorrectly.
+///
+/// This is only populated if enabled in the configuration, see [crate::state::archive].
+pub struct RawBlocksTable {}
+
+impl RawBlocksTable {
+    /// Compresses and stores the JSON of the given block, replacing any block previously archived
+    /// at the same height.
+    ///
+    /// Does nothing and returns `false` if this block is already archived.
+    pub fn insert(
+        tx: &Transaction<'_>,
+        number: StarknetBlockNumber,
+        hash: StarknetBlockHash,
+        json: &[u8],
+        compressor: &mut zstd::bulk::Compressor<'_>,
+    ) -> anyhow::Result<bool> {
+        let exists: bool = tx
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM raw_blocks WHERE number = ? AND hash = ?)",
+                params![number, hash],
+                |row| row.get(0),
+            )
+            .context("Querying raw block")?;
+        if exists {
+            return Ok(false);
+        }
+
+        let data = compressor.compress(json).context("Compressing raw block")?;
+        tx.execute(
+            "INSERT OR REPLACE INTO raw_blocks (number, hash, data) VALUES (?, ?, ?)",
+            params![number, hash, &data],
+        )
+        .context("Inserting raw block")?;
+
+        Ok(true)
+    }
+
+    /// Returns the decompressed JSON of the given block, if it was archived.
+    pub fn get(
+        tx: &Transaction<'_>,
+        block: StarknetBlocksBlockId,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let data: Option<Vec<u8>> = match block {
+            StarknetBlocksBlockId::Number(number) => tx.query_row(
+                "SELECT data FROM raw_blocks WHERE number = ?",
+                [number],
+                |row| row.get(0),
+            ),
+            StarknetBlocksBlockId::Hash(hash) => tx.query_row(
+                "SELECT data FROM raw_blocks WHERE hash = ?",
+                [hash],
+                |row| row.get(0),
+            ),
+            StarknetBlocksBlockId::Latest => tx.query_row(
+                "SELECT data FROM raw_blocks ORDER BY number DESC LIMIT 1",
+                [],
+                |row| row.get(0),
+            ),
+        }
+        .optional()
+        .context("Querying raw block")?;
+
+        data.map(|data| zstd::decode_all(data.as_slice()).context("Decompressing raw block"))
+            .transpose()
+    }
+
+    /// Removes all archived blocks where `number < before`, returning how many were removed.
+    pub fn prune(tx: &Transaction<'_>, before: StarknetBlockNumber) -> anyhow::Result<usize> {
+        tx.execute("DELETE FROM raw_blocks WHERE number < ?", [before])
+            .context("Pruning raw blocks")
+    }
+
+    /// Removes all rows where `number >= reorg_tail`.
+    pub fn reorg(tx: &Transaction<'_>, reorg_tail: StarknetBlockNumber) -> anyhow::Result<()> {
+        tx.execute("DELETE FROM raw_blocks WHERE number >= ?", [reorg_tail])
+            .context("Deleting raw blocks")?;
+        Ok(())
+    }
+}
+
+/// Which sync source triggered a reorg recorded in the [ReorgHistoryTable].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReorgSource {
+    /// The Starknet state updates on Ethereum were reorganized.
+    L1,
+    /// The sequencer's chain was reorganized.
+    L2,
+}
+
+impl ReorgSource {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ReorgSource::L1 => "L1",
+            ReorgSource::L2 => "L2",
+        }
+    }
+}
+
+/// A reorg as recorded in the [ReorgHistoryTable].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReorgRecord {
+    /// The first block which was removed.
+    pub tail: StarknetBlockNumber,
+    /// The head before the reorg.
+    pub old_head: StarknetBlockNumber,
+    /// The hash of the L2 block at `old_head`, if it was known.
+    pub old_head_hash: Option<StarknetBlockHash>,
+    /// Seconds since the unix epoch at which the reorg was processed.
+    pub timestamp: u64,
+    pub source: ReorgSource,
+}
+
+impl ReorgRecord {
+    /// The number of blocks which were removed, zero if `old_head` is below `tail`.
+    pub fn depth(&self) -> u64 {
+        (self.old_head.get() + 1).saturating_sub(self.tail.get())
+    }
+}
+
+/// Keeps a log of the most recent reorgs, since the reorg itself deletes all other evidence of
+/// the removed blocks.
+pub struct ReorgHistoryTable {}
+
+impl ReorgHistoryTable {
+    /// The maximum number of reorgs kept, older entries are pruned on insert.
+    pub const MAX_ENTRIES: usize = 1000;
+
+    /// Records a reorg removing blocks `tail..=old_head`. This should be called before the
+    /// tables are reorged, so that `old_head_hash` can still be looked up.
+    pub fn record_reorg(
+        tx: &Transaction<'_>,
+        tail: StarknetBlockNumber,
+        old_head: StarknetBlockNumber,
+        old_head_hash: Option<StarknetBlockHash>,
+        timestamp: u64,
+        source: ReorgSource,
+    ) -> anyhow::Result<()> {
+        tx.execute(
+            r"INSERT INTO reorg_history (tail, old_head, old_head_hash, timestamp, source)
+                VALUES (:tail, :old_head, :old_head_hash, :timestamp, :source)",
+            named_params! {
+                ":tail": tail,
+                ":old_head": old_head,
+                ":old_head_hash": old_head_hash,
+                ":timestamp": timestamp,
+                ":source": source.as_str(),
+            },
+        )
+        .context("Inserting reorg")?;
+
+        tx.execute(
+            "DELETE FROM reorg_history WHERE id <= (SELECT MAX(id) FROM reorg_history) - ?",
+            [Self::MAX_ENTRIES],
+        )
+        .context("Pruning reorg history")?;
+
+        Ok(())
+    }
+
+    /// Returns up to `limit` of the most recent reorgs, newest first.
+    pub fn get_recent_reorgs(
+        tx: &Transaction<'_>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<ReorgRecord>> {
+        let mut stmt = tx
+            .prepare_cached(
+                r"SELECT tail, old_head, old_head_hash, timestamp, source FROM reorg_history
+                    ORDER BY id DESC LIMIT ?",
+            )
+            .context("Preparing statement")?;
+
+        let records = stmt
+            .query_map([limit], |row| {
+                let source = match row.get_ref_unwrap(4).as_str()? {
+                    "L1" => ReorgSource::L1,
+                    "L2" => ReorgSource::L2,
+                    other => {
+                        return Err(rusqlite::Error::FromSqlConversionFailure(
+                            4,
+                            rusqlite::types::Type::Text,
+                            anyhow::anyhow!("Unknown reorg source: {other}").into(),
+                        ))
+                    }
+                };
+
+                Ok(ReorgRecord {
+                    tail: row.get(0)?,
+                    old_head: row.get(1)?,
+                    old_head_hash: row.get(2)?,
+                    timestamp: row.get(3)?,
+                    source,
+                })
+            })
+            .context("Executing query")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Reading reorg history")?;
+
+        Ok(records)
+    }
+}
+
+/// Inconsistencies between the block, canonical chain and transaction tables and the L1-L2 head,
+/// as found by [consistency_check].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ConsistencyReport {
+    /// Blocks in the [StarknetBlocksTable] without a matching [CanonicalBlocksTable] entry.
+    pub not_canonical: Vec<StarknetBlockNumber>,
+    /// [CanonicalBlocksTable] entries without a matching block in the [StarknetBlocksTable].
+    pub missing_blocks: Vec<StarknetBlockNumber>,
+    /// Blocks whose stored transactions don't match their
+    /// [transaction count](StarknetBlock::transaction_count), or whose transaction indices have
+    /// gaps.
+    pub missing_transactions: Vec<StarknetBlockNumber>,
+    /// The L1-L2 head, if it is past the latest L2 block.
+    pub l1_l2_head_ahead: Option<StarknetBlockNumber>,
+}
+
+impl ConsistencyReport {
+    pub fn is_consistent(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// The first L2 block which is not fully consistent.
+    pub fn first_inconsistent_block(&self) -> Option<StarknetBlockNumber> {
+        self.not_canonical
+            .iter()
+            .chain(&self.missing_blocks)
+            .chain(&self.missing_transactions)
+            .min_by_key(|number| number.get())
+            .copied()
+    }
+
+    /// Truncates L2 back to the last fully consistent block, i.e. the block before
+    /// [ConsistencyReport::first_inconsistent_block], the same way an L2 reorg would.
+    ///
+    /// The L1-L2 head is then limited to the remaining L2 head. The [L1StateTable] is left as
+    /// is, as L1 being ahead of L2 is expected while L2 sync is catching up.
+    pub fn repair(&self, tx: &Transaction<'_>) -> anyhow::Result<()> {
+        if let Some(tail) = self.first_inconsistent_block() {
+            CanonicalBlocksTable::reorg(tx, tail).context("Delete canonical blocks")?;
+            StarknetBlocksTable::reorg(tx, tail).context("Delete L2 blocks")?;
+        }
+
+        let l2_head = StarknetBlocksTable::get_latest_number(tx).context("Query L2 head")?;
+
+        let l1_l2_head = RefsTable::get_l1_l2_head(tx).context("Query L1-L2 head")?;
+        if l1_l2_head > l2_head {
+            RefsTable::set_l1_l2_head(tx, l2_head).context("Update L1-L2 head")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Cross-checks the [StarknetBlocksTable], [CanonicalBlocksTable], [StarknetTransactionsTable]
+/// and L1-L2 head against each other.
+///
+/// The transaction counts of blocks stored before they were recorded were backfilled from the
+/// stored transactions. For these, only gaps in the transaction indices reveal missing
+/// transactions, not missing trailing ones.
+pub fn consistency_check(tx: &Transaction<'_>) -> anyhow::Result<ConsistencyReport> {
+    let block_numbers = |query: &str| -> anyhow::Result<Vec<StarknetBlockNumber>> {
+        let mut stmt = tx.prepare(query).context("Preparing statement")?;
+        let numbers = stmt
+            .query_map([], |row| row.get(0))
+            .context("Executing query")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Iterating over rows")?;
+        Ok(numbers)
+    };
+
+    let not_canonical = block_numbers(
+        r"SELECT number FROM starknet_blocks WHERE NOT EXISTS (
+    SELECT 1 FROM canonical_blocks
+    WHERE canonical_blocks.number = starknet_blocks.number AND canonical_blocks.hash = starknet_blocks.hash
+) ORDER BY number",
+    )
+    .context("Querying non-canonical blocks")?;
+
+    let missing_blocks = block_numbers(
+        r"SELECT number FROM canonical_blocks WHERE NOT EXISTS (
+    SELECT 1 FROM starknet_blocks
+    WHERE starknet_blocks.number = canonical_blocks.number AND starknet_blocks.hash = canonical_blocks.hash
+) ORDER BY number",
+    )
+    .context("Querying missing blocks")?;
+
+    let missing_transactions = block_numbers(
+        r"SELECT number FROM starknet_blocks
+LEFT JOIN (
+    SELECT block_hash, COUNT(1) AS count, MAX(idx) + 1 AS end
+    FROM starknet_transactions GROUP BY block_hash
+) AS stored ON stored.block_hash = starknet_blocks.hash
+WHERE transaction_count != COALESCE(stored.count, 0) OR stored.count != stored.end
+ORDER BY number",
+    )
+    .context("Querying blocks with missing transactions")?;
+
+    let l2_head = StarknetBlocksTable::get_latest_number(tx).context("Query L2 head")?;
+
+    let l1_l2_head = RefsTable::get_l1_l2_head(tx).context("Query L1-L2 head")?;
+    let l1_l2_head_ahead = l1_l2_head.filter(|_| l1_l2_head > l2_head);
+
+    Ok(ConsistencyReport {
+        not_canonical,
+        missing_blocks,
+        missing_transactions,
+        l1_l2_head_ahead,
+    })
+}
+
+/// The number of rows an L2 reorg from a given block would delete, as computed by
+/// [reorg_preview].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReorgPreview {
+    pub blocks: usize,
+    pub canonical_blocks: usize,
+    pub transactions: usize,
+    pub events: usize,
+    /// Rows of the [ContractClassIndexTable].
+    pub contract_classes: usize,
+    /// Rows of the [StagedBlocksTable](super::StagedBlocksTable).
+    pub staged_blocks: usize,
+}
+
+/// Counts the rows which an L2 reorg of all blocks from `reorg_tail` onwards would delete,
+/// without modifying anything.
+///
+/// Transactions are deleted along with their blocks through a foreign key cascade.
+pub fn reorg_preview(
+    tx: &Transaction<'_>,
+    reorg_tail: StarknetBlockNumber,
+) -> anyhow::Result<ReorgPreview> {
+    let count = |query: &str| -> anyhow::Result<usize> {
+        tx.query_row(query, [reorg_tail], |row| row.get(0))
+            .with_context(|| format!("Counting rows: {query}"))
+    };
+
+    Ok(ReorgPreview {
+        blocks: count("SELECT COUNT(1) FROM starknet_blocks WHERE number >= ?")?,
+        canonical_blocks: count("SELECT COUNT(1) FROM canonical_blocks WHERE number >= ?")?,
+        transactions: count(
+            r"SELECT COUNT(1) FROM starknet_transactions
+    JOIN starknet_blocks ON starknet_transactions.block_hash = starknet_blocks.hash
+    WHERE starknet_blocks.number >= ?",
+        )?,
+        events: count("SELECT COUNT(1) FROM starknet_events WHERE block_number >= ?")?,
+        contract_classes: count(
+            "SELECT COUNT(1) FROM contract_class_index WHERE block_number >= ?",
+        )?,
+        staged_blocks: count("SELECT COUNT(1) FROM staged_blocks WHERE number >= ?")?,
+    })
+}
+
+/// A block together with all of its transactions and their receipts, as read by
+/// [get_block_with_receipts].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockWithReceipts {
+    pub block: StarknetBlock,
+    /// The sequencer version, which is only stored since starknet 0.9.1.
+    pub version: Option<String>,
+    /// True if the block is at or below the L1-L2 head.
+    pub accepted_on_l1: bool,
+    /// The block's transactions with their receipts and index, in block order.
+    pub transactions: Vec<(transaction::Transaction, transaction::Receipt, usize)>,
+}
+
+/// Reads the block, its version and all of its transactions and receipts in a single query.
+///
+/// Returns `None` if the block does not exist.
+pub fn get_block_with_receipts(
+    tx: &Transaction<'_>,
+    block: StarknetBlocksBlockId,
+) -> anyhow::Result<Option<BlockWithReceipts>> {
+    let (block_condition, params): (_, Vec<&dyn rusqlite::ToSql>) = match &block {
+        StarknetBlocksBlockId::Number(number) => ("starknet_blocks.number = ?", vec![number]),
+        StarknetBlocksBlockId::Hash(hash) => ("starknet_blocks.hash = ?", vec![hash]),
+        StarknetBlocksBlockId::Latest => (
+            "starknet_blocks.number = (SELECT MAX(number) FROM starknet_blocks)",
+            vec![],
+        ),
+    };
+
+    // The transactions are left joined so that blocks without any still produce a row.
+    let mut stmt = tx
+        .prepare(&format!(
+            r"SELECT starknet_blocks.number, starknet_blocks.hash, parent_hash, root, timestamp, gas_price,
+        sequencer_address, transaction_count, event_count, starknet_versions.version,
+        starknet_transactions.idx, starknet_transactions.tx, starknet_transactions.receipt
+    FROM starknet_blocks
+    LEFT JOIN starknet_versions ON starknet_versions.id = starknet_blocks.version_id
+    LEFT JOIN starknet_transactions ON starknet_transactions.block_hash = starknet_blocks.hash
+    WHERE {block_condition}
+    ORDER BY starknet_transactions.idx ASC"
+        ))
+        .context("Preparing statement")?;
+
+    let mut rows = stmt.query(params.as_slice()).context("Executing query")?;
+
+    let mut decompressor = Decompressor::new()?;
+    let mut result: Option<BlockWithReceipts> = None;
+    while let Some(row) = rows.next().context("Iterating over rows")? {
+        let data = match result.as_mut() {
+            Some(data) => data,
+            None => {
+                let gas_price = row.get_ref_unwrap("gas_price").as_blob()?;
+                let gas_price =
+                    GasPrice::from_be_slice(gas_price).context("Parsing gas price bytes")?;
+
+                let block = StarknetBlock {
+                    number: row.get("number")?,
+                    hash: row.get("hash")?,
+                    parent_hash: row.get("parent_hash")?,
+                    root: row.get("root")?,
+                    timestamp: row.get("timestamp")?,
+                    gas_price,
+                    sequencer_address: row.get("sequencer_address")?,
+                    transaction_count: row.get("transaction_count")?,
+                    event_count: row.get("event_count")?,
+                };
+
+                result.insert(BlockWithReceipts {
+                    block,
+                    version: row.get("version")?,
+                    accepted_on_l1: false,
+                    transactions: Vec::new(),
+                })
+            }
+        };
+
+        let idx = match row.get::<_, Option<usize>>("idx")? {
+            Some(idx) => idx,
+            None => continue,
+        };
+
+        let transaction = row
+            .get_ref_unwrap("tx")
+            .as_blob_or_null()?
+            .context("Transaction data missing")?;
+        let transaction = decompressor
+            .decompress(transaction)
+            .context("Decompressing transaction")?;
+        let transaction =
+            serde_json::from_slice(&transaction).context("Deserializing transaction")?;
+
+        let receipt = row
+            .get_ref_unwrap("receipt")
+            .as_blob_or_null()?
+            .context("Receipt data missing")?;
+        let receipt = decompressor
+            .decompress(receipt)
+            .context("Decompressing transaction receipt")?;
+        let receipt =
+            serde_json::from_slice(&receipt).context("Deserializing transaction receipt")?;
+
+        data.transactions.push((transaction, receipt, idx));
+    }
+
+    let mut result = match result {
+        Some(result) => result,
+        None => return Ok(None),
+    };
+
+    let l1_l2_head = RefsTable::get_l1_l2_head(tx).context("Reading L1-L2 head")?;
+    result.accepted_on_l1 = matches!(l1_l2_head, Some(head) if head >= result.block.number);
+
+    Ok(Some(result))
+}
+
+/// The highest L2 block which has been confirmed on L1, see [get_l1_confirmed_head].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct L1ConfirmedHead {
+    pub block: StarknetBlock,
+    /// The L1 state update which confirmed [L1ConfirmedHead::block].
+    pub proof: StateUpdateLog,
+}
+
+/// Returns the block at the [L1-L2 head](RefsTable::get_l1_l2_head), together with the
+/// [L1 state update](L1StateTable) which confirmed it.
+///
+/// Returns `None` if there is no L1-L2 head yet, or if the head's block or L1 state update is
+/// missing. The latter is an inconsistency, and is logged as such.
+pub fn get_l1_confirmed_head(tx: &Transaction<'_>) -> anyhow::Result<Option<L1ConfirmedHead>> {
+    let head = match RefsTable::get_l1_l2_head(tx).context("Reading L1-L2 head")? {
+        Some(head) => head,
+        None => return Ok(None),
+    };
+
+    let block = StarknetBlocksTable::get(tx, head.into()).context("Reading head block")?;
+    let proof = L1StateTable::get(tx, head.into()).context("Reading head L1 state update")?;
+
+    match (block, proof) {
+        (Some(block), Some(proof)) => Ok(Some(L1ConfirmedHead { block, proof })),
+        (block, proof) => {
+            tracing::warn!(
+                %head,
+                block_missing=block.is_none(),
+                l1_state_missing=proof.is_none(),
+                "L1-L2 head is missing from storage"
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// Recomputes the global root of the given block from the leaves of its global state tree, and
+/// compares it against the root stored with the block, see [StarknetBlocksTable::get_root].
+///
+/// The contract state hash of each leaf is recomputed from its preimage in the
+/// [ContractsStateTable] as well. Contract storage trees are not walked.
+///
+/// Fails if the block does not exist.
+pub fn verify_block_root(
+    tx: &Transaction<'_>,
+    number: StarknetBlockNumber,
+) -> anyhow::Result<bool> {
+    use crate::state::merkle_node::Node;
+    use crate::state::merkle_tree::{MerkleTree, Visit};
+    use crate::state::state_tree::GlobalStateTree;
+    use std::ops::ControlFlow;
+
+    let root = StarknetBlocksTable::get_root(tx, number.into())
+        .context("Reading block root")?
+        .with_context(|| format!("Block {number} not found"))?;
+
+    let tree = GlobalStateTree::load(tx, root).context("Loading global state tree")?;
+    let mut leaves = Vec::new();
+    tree.dfs::<(), _>(&mut |node, path| {
+        if let Node::Leaf(value) = node {
+            leaves.push((path.to_bitvec(), ContractStateHash(*value)));
+        }
+        ControlFlow::Continue(Visit::ContinueDeeper)
+    })
+    .context("Walking global state tree")?;
+
+    let mut stmt = tx
+        .prepare_cached("SELECT hash, root, nonce FROM contract_states WHERE state_hash = ?")
+        .context("Preparing statement")?;
+
+    let mut recomputed = MerkleTree::empty((), 251);
+    for (path, state_hash) in &leaves {
+        let preimage = stmt
+            .query_row([state_hash], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .optional()
+            .context("Reading contract state")?;
+
+        let (class_hash, contract_root, nonce) = match preimage {
+            Some(preimage) => preimage,
+            None => return Ok(false),
+        };
+        if crate::state::calculate_contract_state_hash(class_hash, contract_root, nonce)
+            != *state_hash
+        {
+            return Ok(false);
+        }
+
+        recomputed.set(path, state_hash.0)?;
+    }
+    let recomputed = recomputed.commit().context("Computing global root")?;
+
+    Ok(GlobalRoot(recomputed) == root)
+}
+
+/// Compares the root stored with the given block against the root L1 reports for it, see
+/// [L1StateTable::get_root].
+///
+/// Returns `None` if either of the roots is not known.
+pub fn roots_agree(
+    tx: &Transaction<'_>,
+    number: StarknetBlockNumber,
+) -> anyhow::Result<Option<bool>> {
+    let l2_root = StarknetBlocksTable::get_root(tx, number.into()).context("Reading block root")?;
+    let l1_root = L1StateTable::get_root(tx, number.into()).context("Reading L1 root")?;
+
+    Ok(l2_root
+        .zip(l1_root)
+        .map(|(l2_root, l1_root)| l2_root == l1_root))
+}
+
+/// Integrity checks on relationships which are not enforced by foreign keys.
+pub struct StorageIntegrity {}
+
+impl StorageIntegrity {
+    /// Returns the hashes of transactions which have events in the [StarknetEventsTable], but
+    /// no row in the [StarknetTransactionsTable]. Such events are not returned by
+    /// [StarknetEventsTable::get_events].
+    ///
+    /// Each hash is listed once, in event insertion order.
+    pub fn find_orphan_events(
+        tx: &Transaction<'_>,
+    ) -> anyhow::Result<Vec<StarknetTransactionHash>> {
+        let mut stmt = tx
+            .prepare(
+                r"SELECT transaction_hash FROM starknet_events WHERE NOT EXISTS (
+    SELECT 1 FROM starknet_transactions WHERE starknet_transactions.hash = starknet_events.transaction_hash
+) GROUP BY transaction_hash ORDER BY MIN(id)",
+            )
+            .context("Preparing statement")?;
+
+        let hashes = stmt
+            .query_map([], |row| row.get(0))
+            .context("Executing query")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Iterating over rows")?;
+
+        Ok(hashes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{CompressionConfig, Storage};
+
+    mod contracts {
+        use super::*;
+        use crate::starkhash;
+
+        #[test]
+        fn get() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let transaction = connection.transaction().unwrap();
+
+            let state_hash = ContractStateHash(starkhash!("0abc"));
+            let hash = ClassHash(starkhash!("0123"));
+            let root = ContractRoot(starkhash!("0def"));
+            let nonce = ContractNonce(starkhash!("0456"));
+
+            ContractsStateTable::upsert(&transaction, state_hash, hash, root, nonce).unwrap();
+
+            let result = ContractsStateTable::get_root(&transaction, state_hash).unwrap();
+            assert_eq!(result, Some(root));
+
+            let result = ContractsStateTable::get_nonce(&transaction, state_hash).unwrap();
+            assert_eq!(result, Some(nonce));
+
+            let result = ContractsStateTable::get_root_and_nonce(&transaction, state_hash).unwrap();
+            assert_eq!(result, Some((root, nonce)));
+        }
+
+        #[test]
+        fn upsert_many() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let transaction = connection.transaction().unwrap();
+
+            // An existing row is not overwritten.
+            let existing = ContractStateHash(StarkHash::from_u128(7));
+            let existing_root = ContractRoot(starkhash!("0def"));
+            let existing_nonce = ContractNonce(starkhash!("0456"));
+            ContractsStateTable::upsert(
+                &transaction,
+                existing,
+                ClassHash(starkhash!("0123")),
+                existing_root,
+                existing_nonce,
+            )
+            .unwrap();
+
+            let rows = (0..500u128)
+                .map(|i| {
+                    (
+                        ContractStateHash(StarkHash::from_u128(i)),
+                        ClassHash(StarkHash::from_u128(i + 1000)),
+                        ContractRoot(StarkHash::from_u128(i + 2000)),
+                        ContractNonce(StarkHash::from_u128(i + 3000)),
+                    )
+                })
+                .collect::<Vec<_>>();
+            ContractsStateTable::upsert_many(&transaction, &rows).unwrap();
+
+            let count: usize = transaction
+                .query_row("SELECT COUNT(*) FROM contract_states", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(count, 500);
+
+            for (state_hash, _, root, nonce) in rows.iter().step_by(50) {
+                let result =
+                    ContractsStateTable::get_root_and_nonce(&transaction, *state_hash).unwrap();
+                assert_eq!(result, Some((*root, *nonce)));
+            }
+
+            let result = ContractsStateTable::get_root_and_nonce(&transaction, existing).unwrap();
+            assert_eq!(result, Some((existing_root, existing_nonce)));
+        }
+    }
+
+    mod refs {
+        use super::*;
+
+        mod l1_l2_head {
+            use super::*;
+
+            #[test]
+            fn fresh_is_none() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let l1_l2_head = RefsTable::get_l1_l2_head(&tx).unwrap();
+                assert_eq!(l1_l2_head, None);
+            }
+
+            #[test]
+            fn set_get() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let expected = Some(StarknetBlockNumber::new_or_panic(22));
+                RefsTable::set_l1_l2_head(&tx, expected).unwrap();
+                assert_eq!(expected, RefsTable::get_l1_l2_head(&tx).unwrap());
+
+                let expected = Some(StarknetBlockNumber::new_or_panic(25));
+                RefsTable::set_l1_l2_head(&tx, expected).unwrap();
+                assert_eq!(expected, RefsTable::get_l1_l2_head(&tx).unwrap());
+
+                RefsTable::set_l1_l2_head(&tx, None).unwrap();
+                assert_eq!(None, RefsTable::get_l1_l2_head(&tx).unwrap());
+            }
+        }
+    }
+
+    mod l1_state_table {
+        use super::*;
+
+        /// Creates a set of consecutive [StateUpdateLog]s starting from L2 genesis,
+        /// with arbitrary other values.
+        fn create_updates() -> [StateUpdateLog; 3] {
+            (0..3)
+                .map(|i| StateUpdateLog {
+                    origin: EthOrigin {
+                        block: BlockOrigin {
+                            hash: EthereumBlockHash(H256::from_low_u64_le(i + 33)),
+                            number: EthereumBlockNumber(i + 12_000),
+                        },
+                        transaction: TransactionOrigin {
+                            hash: EthereumTransactionHash(H256::from_low_u64_le(i + 999)),
+                            index: EthereumTransactionIndex(i + 20_000),
+                        },
+                        log_index: EthereumLogIndex(i + 500),
+                    },
+                    global_root: GlobalRoot(
+                        StarkHash::from_hex_str(&"3".repeat(i as usize + 1)).unwrap(),
+                    ),
+                    block_number: StarknetBlockNumber::GENESIS + i,
+                })
+                .collect::<Vec<_>>()
+                .try_into()
+                .unwrap()
+        }
+
+        mod get {
+            use super::*;
+
+            #[test]
+            fn none() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let updates = create_updates();
+                for update in &updates {
+                    L1StateTable::upsert(&tx, update).unwrap();
+                }
+
+                let non_existent = updates.last().unwrap().block_number + 1;
+                assert_eq!(L1StateTable::get(&tx, non_existent.into()).unwrap(), None);
+            }
+
+            #[test]
+            fn some() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let updates = create_updates();
+                for update in &updates {
+                    L1StateTable::upsert(&tx, update).unwrap();
+                }
+
+                for (idx, update) in updates.iter().enumerate() {
+                    assert_eq!(
+                        L1StateTable::get(&tx, update.block_number.into())
+                            .unwrap()
+                            .as_ref(),
+                        Some(update),
+                        "Update {}",
+                        idx
+                    );
+                }
+            }
+
+            mod latest {
+                use super::*;
+
+                #[test]
+                fn none() {
+                    let storage = Storage::in_memory().unwrap();
+                    let mut connection = storage.connection().unwrap();
+                    let tx = connection.transaction().unwrap();
+
+                    assert_eq!(
+                        L1StateTable::get(&tx, L1TableBlockId::Latest).unwrap(),
+                        None
+                    );
+                }
+
+                #[test]
+                fn some() {
+                    let storage = Storage::in_memory().unwrap();
+                    let mut connection = storage.writer().unwrap();
+                    let tx = connection.transaction().unwrap();
+
+                    let updates = create_updates();
+                    for update in &updates {
+                        L1StateTable::upsert(&tx, update).unwrap();
+                    }
+
+                    assert_eq!(
+                        L1StateTable::get(&tx, L1TableBlockId::Latest)
+                            .unwrap()
+                            .as_ref(),
+                        updates.last()
+                    );
+                }
+            }
+        }
+
+        mod get_root {
+            use super::*;
+
+            #[test]
+            fn none() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let updates = create_updates();
+                for update in &updates {
+                    L1StateTable::upsert(&tx, update).unwrap();
+                }
+
+                let non_existent = updates.last().unwrap().block_number + 1;
+                assert_eq!(
+                    L1StateTable::get_root(&tx, non_existent.into()).unwrap(),
+                    None
+                );
+            }
+
+            #[test]
+            fn some() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let updates = create_updates();
+                for update in &updates {
+                    L1StateTable::upsert(&tx, update).unwrap();
+                }
+
+                for (idx, update) in updates.iter().enumerate() {
+                    assert_eq!(
+                        L1StateTable::get_root(&tx, update.block_number.into()).unwrap(),
+                        Some(update.global_root),
+                        "Update {}",
+                        idx
+                    );
+                }
+            }
+
+            mod latest {
+                use super::*;
+
+                #[test]
+                fn none() {
+                    let storage = Storage::in_memory().unwrap();
+                    let mut connection = storage.connection().unwrap();
+                    let tx = connection.transaction().unwrap();
+
+                    assert_eq!(
+                        L1StateTable::get_root(&tx, L1TableBlockId::Latest).unwrap(),
+                        None
+                    );
+                }
+
+                #[test]
+                fn some() {
+                    let storage = Storage::in_memory().unwrap();
+                    let mut connection = storage.writer().unwrap();
+                    let tx = connection.transaction().unwrap();
+
+                    let updates = create_updates();
+                    for update in &updates {
+                        L1StateTable::upsert(&tx, update).unwrap();
+                    }
+
+                    assert_eq!(
+                        L1StateTable::get_root(&tx, L1TableBlockId::Latest).unwrap(),
+                        Some(updates.last().unwrap().global_root)
+                    );
+                }
+            }
+        }
+
+        mod reorg {
+            use super::*;
+
+            #[test]
+            fn full() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let updates = create_updates();
+                for update in &updates {
+                    L1StateTable::upsert(&tx, update).unwrap();
+                }
+
+                L1StateTable::reorg(&tx, StarknetBlockNumber::GENESIS).unwrap();
+
+                assert_eq!(
+                    L1StateTable::get(&tx, L1TableBlockId::Latest).unwrap(),
+                    None
+                );
+            }
+
+            #[test]
+            fn partial() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let updates = create_updates();
+                for update in &updates {
+                    L1StateTable::upsert(&tx, update).unwrap();
+                }
+
+                let reorg_tail = updates[1].block_number;
+                L1StateTable::reorg(&tx, reorg_tail).unwrap();
+
+                assert_eq!(
+                    L1StateTable::get(&tx, L1TableBlockId::Latest)
+                        .unwrap()
+                        .as_ref(),
+                    Some(&updates[0])
+                );
+            }
+        }
+    }
+
+    mod starknet_blocks {
+        use super::*;
+        use crate::storage::test_utils;
+
+        fn create_blocks() -> [StarknetBlock; test_utils::NUM_BLOCKS] {
+            test_utils::create_blocks()
+        }
+
+        fn with_default_blocks<F>(f: F)
+        where
+            F: FnOnce(&Transaction<'_>, [StarknetBlock; test_utils::NUM_BLOCKS]),
+        {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let blocks = create_blocks();
+            for block in &blocks {
+                StarknetBlocksTable::insert(&tx, block, None).unwrap();
+            }
+
+            f(&tx, blocks)
+        }
+
+        mod get_parent {
+            use super::*;
+
+            #[test]
+            fn walks_back_to_genesis() {
+                with_default_blocks(|tx, blocks| {
+                    let mut current = StarknetBlocksTable::get(tx, StarknetBlocksBlockId::Latest)
+                        .unwrap()
+                        .unwrap();
+                    let mut walked = vec![current.clone()];
+
+                    while let Some(parent) =
+                        StarknetBlocksTable::get_parent(tx, current.hash.into()).unwrap()
+                    {
+                        assert_eq!(parent.hash, current.parent_hash);
+                        walked.push(parent.clone());
+                        current = parent;
+                    }
+
+                    walked.reverse();
+                    assert_eq!(walked, blocks);
+                })
+            }
+
+            #[test]
+            fn unknown_block() {
+                with_default_blocks(|tx, blocks| {
+                    let non_existent = blocks.last().unwrap().number + 1;
+                    assert_eq!(
+                        StarknetBlocksTable::get_parent(tx, non_existent.into()).unwrap(),
+                        None
+                    );
+                })
+            }
+        }
+
+        mod get {
+            use super::*;
+
+            #[test]
+            fn sequencer_address() {
+                with_default_blocks(|tx, blocks| {
+                    let genesis = StarknetBlocksTable::get(tx, blocks[0].number.into())
+                        .unwrap()
+                        .unwrap();
+                    assert_eq!(genesis.sequencer_address, None);
+                    let stored: Option<Vec<u8>> = tx
+                        .query_row(
+                            "SELECT sequencer_address FROM starknet_blocks WHERE number = 0",
+                            [],
+                            |row| row.get(0),
+                        )
+                        .unwrap();
+                    assert_eq!(stored, None);
+
+                    let block = StarknetBlocksTable::get(tx, blocks[1].number.into())
+                        .unwrap()
+                        .unwrap();
+                    assert!(block.sequencer_address.is_some());
+                    assert_eq!(block.sequencer_address, blocks[1].sequencer_address);
+                })
+            }
+
+            mod by_number {
+                use super::*;
+
+                #[test]
+                fn some() {
+                    with_default_blocks(|tx, blocks| {
+                        for block in blocks {
+                            let result = StarknetBlocksTable::get(tx, block.number.into())
+                                .unwrap()
+                                .unwrap();
+
+                            assert_eq!(result, block);
+                        }
+                    })
+                }
+
+                #[test]
+                fn none() {
+                    with_default_blocks(|tx, blocks| {
+                        let non_existent = blocks.last().unwrap().number + 1;
+                        assert_eq!(
+                            StarknetBlocksTable::get(tx, non_existent.into()).unwrap(),
+                            None
+                        );
+                    });
+                }
+            }
+
+            mod by_hash {
+                use super::*;
+
+                #[test]
+                fn some() {
+                    with_default_blocks(|tx, blocks| {
+                        for block in blocks {
+                            let result = StarknetBlocksTable::get(tx, block.hash.into())
+                                .unwrap()
+                                .unwrap();
+
+                            assert_eq!(result, block);
+                        }
+                    });
+                }
+
+                #[test]
+                fn none() {
+                    with_default_blocks(|tx, _blocks| {
+                        let non_existent =
+                            StarknetBlockHash(StarkHash::from_hex_str(&"b".repeat(10)).unwrap());
+                        assert_eq!(
+                            StarknetBlocksTable::get(tx, non_existent.into()).unwrap(),
+                            None
+                        );
+                    });
+                }
+            }
+
+            mod latest {
+                use super::*;
+
+                #[test]
+                fn some() {
+                    with_default_blocks(|tx, blocks| {
+                        let expected = blocks.last().unwrap();
+
+                        let latest = StarknetBlocksTable::get(tx, StarknetBlocksBlockId::Latest)
+                            .unwrap()
+                            .unwrap();
+                        assert_eq!(&latest, expected);
+                    })
+                }
+
+                #[test]
+                fn none() {
+                    let storage = Storage::in_memory().unwrap();
+                    let mut connection = storage.connection().unwrap();
+                    let tx = connection.transaction().unwrap();
+
+                    let latest =
+                        StarknetBlocksTable::get(&tx, StarknetBlocksBlockId::Latest).unwrap();
+                    assert_eq!(latest, None);
+                }
+            }
+
+            mod number_by_hash {
+                use super::*;
+
+                #[test]
+                fn some() {
+                    with_default_blocks(|tx, blocks| {
+                        for block in blocks {
+                            let result = StarknetBlocksTable::get_number(tx, block.hash)
+                                .unwrap()
+                                .unwrap();
+
+                            assert_eq!(result, block.number);
+                        }
+                    });
+                }
+
+                #[test]
+                fn none() {
+                    with_default_blocks(|tx, _blocks| {
+                        let non_existent =
+                            StarknetBlockHash(StarkHash::from_hex_str(&"b".repeat(10)).unwrap());
+                        assert_eq!(
+                            StarknetBlocksTable::get_number(tx, non_existent).unwrap(),
+                            None
+                        );
+                    });
+                }
+            }
+        }
+
+        mod get_root {
+            use super::*;
+
+            mod by_number {
+                use super::*;
+
+                #[test]
+                fn some() {
+                    with_default_blocks(|tx, blocks| {
+                        for block in blocks {
+                            let root = StarknetBlocksTable::get_root(tx, block.number.into())
+                                .unwrap()
+                                .unwrap();
+
+                            assert_eq!(root, block.root);
+                        }
+                    })
+                }
+
+                #[test]
+                fn none() {
+                    with_default_blocks(|tx, blocks| {
+                        let non_existent = blocks.last().unwrap().number + 1;
+                        assert_eq!(
+                            StarknetBlocksTable::get_root(tx, non_existent.into()).unwrap(),
+                            None
+                        );
+                    })
+                }
+            }
+
+            mod by_hash {
+                use super::*;
+
+                #[test]
+                fn some() {
+                    with_default_blocks(|tx, blocks| {
+                        for block in blocks {
+                            let root = StarknetBlocksTable::get_root(tx, block.hash.into())
+                                .unwrap()
+                                .unwrap();
+
+                            assert_eq!(root, block.root);
+                        }
+                    })
+                }
+
+                #[test]
+                fn none() {
+                    with_default_blocks(|tx, _blocks| {
+                        let non_existent =
+                            StarknetBlockHash(StarkHash::from_hex_str(&"b".repeat(10)).unwrap());
+                        assert_eq!(
+                            StarknetBlocksTable::get_root(tx, non_existent.into()).unwrap(),
+                            None
+                        );
+                    })
+                }
+            }
+
+            mod latest {
+                use super::*;
+
+                #[test]
+                fn some() {
+                    with_default_blocks(|tx, blocks| {
+                        let expected = blocks.last().map(|block| block.root).unwrap();
+
+                        let latest =
+                            StarknetBlocksTable::get_root(tx, StarknetBlocksBlockId::Latest)
+                                .unwrap()
+                                .unwrap();
+                        assert_eq!(latest, expected);
+                    })
+                }
+
+                #[test]
+                fn none() {
+                    let storage = Storage::in_memory().unwrap();
+                    let mut connection = storage.connection().unwrap();
+                    let tx = connection.transaction().unwrap();
+
+                    let latest_root =
+                        StarknetBlocksTable::get_root(&tx, StarknetBlocksBlockId::Latest).unwrap();
+                    assert_eq!(latest_root, None);
+                }
+            }
+        }
+
+        mod get_header {
+            use super::*;
+
+            #[test]
+            fn matches_get() {
+                with_default_blocks(|tx, blocks| {
+                    for block in blocks {
+                        let full = StarknetBlocksTable::get(tx, block.number.into())
+                            .unwrap()
+                            .unwrap();
+                        let header = StarknetBlocksTable::get_header(tx, block.hash.into())
+                            .unwrap()
+                            .unwrap();
+
+                        assert_eq!(header, StarknetBlockHeader::from(&full));
+                    }
+                })
+            }
+
+            #[test]
+            fn latest() {
+                with_default_blocks(|tx, blocks| {
+                    let expected = StarknetBlockHeader::from(blocks.last().unwrap());
+
+                    let latest = StarknetBlocksTable::get_header(tx, StarknetBlocksBlockId::Latest)
+                        .unwrap()
+                        .unwrap();
+                    assert_eq!(latest, expected);
+                })
+            }
+
+            #[test]
+            fn none() {
+                with_default_blocks(|tx, blocks| {
+                    let non_existent = blocks.last().unwrap().number + 1;
+                    assert_eq!(
+                        StarknetBlocksTable::get_header(tx, non_existent.into()).unwrap(),
+                        None
+                    );
+                })
+            }
+        }
+
+        mod find_by_timestamp {
+            use super::*;
+
+            /// Inserts the default blocks with timestamps 500, 510, 520, ...
+            fn with_spaced_blocks<F>(f: F)
+            where
+                F: FnOnce(&Transaction<'_>, [StarknetBlock; test_utils::NUM_BLOCKS]),
+            {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let mut blocks = create_blocks();
+                for (i, block) in blocks.iter_mut().enumerate() {
+                    block.timestamp = StarknetBlockTimestamp::new_or_panic(500 + 10 * i as u64);
+                    StarknetBlocksTable::insert(&tx, block, None).unwrap();
+                }
+
+                f(&tx, blocks)
+            }
+
+            #[test]
+            fn before_genesis() {
+                with_spaced_blocks(|tx, _blocks| {
+                    let timestamp = StarknetBlockTimestamp::new_or_panic(499);
+                    let result = StarknetBlocksTable::find_by_timestamp(tx, timestamp).unwrap();
+                    assert_eq!(result, None);
+                })
+            }
+
+            #[test]
+            fn exactly_on_block() {
+                with_spaced_blocks(|tx, blocks| {
+                    for block in blocks {
+                        let result =
+                            StarknetBlocksTable::find_by_timestamp(tx, block.timestamp).unwrap();
+                        assert_eq!(result, Some(block.number));
+                    }
+                })
+            }
+
+            #[test]
+            fn between_blocks() {
+                with_spaced_blocks(|tx, blocks| {
+                    let timestamp = StarknetBlockTimestamp::new_or_panic(515);
+                    let result = StarknetBlocksTable::find_by_timestamp(tx, timestamp).unwrap();
+                    assert_eq!(result, Some(blocks[1].number));
+
+                    let timestamp = StarknetBlockTimestamp::new_or_panic(u32::MAX as u64);
+                    let result = StarknetBlocksTable::find_by_timestamp(tx, timestamp).unwrap();
+                    assert_eq!(result, Some(blocks.last().unwrap().number));
+                })
+            }
+        }
+
+        mod latest_on_or_before {
+            use super::*;
+
+            /// Inserts blocks 0 to 6 and prunes everything below block 2.
+            fn with_pruned_blocks<F>(f: F)
+            where
+                F: FnOnce(&Transaction<'_>, Vec<StarknetBlock>),
+            {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let blocks = (0..=6).map(StarknetBlock::nth).collect::<Vec<_>>();
+                for block in &blocks {
+                    StarknetBlocksTable::insert(&tx, block, None).unwrap();
+                }
+                tx.execute("DELETE FROM starknet_blocks WHERE number < 2", [])
+                    .unwrap();
+
+                f(&tx, blocks)
+            }
+
+            #[test]
+            fn below_pruned() {
+                with_pruned_blocks(|tx, _blocks| {
+                    let result = StarknetBlocksTable::latest_on_or_before(
+                        tx,
+                        StarknetBlockNumber::new_or_panic(1),
+                    )
+                    .unwrap();
+                    assert_eq!(result, None);
+                })
+            }
+
+            #[test]
+            fn stored() {
+                with_pruned_blocks(|tx, blocks| {
+                    for block in &blocks[2..] {
+                        let result =
+                            StarknetBlocksTable::latest_on_or_before(tx, block.number).unwrap();
+                        assert_eq!(result.as_ref(), Some(block));
+                    }
+                })
+            }
+
+            #[test]
+            fn past_head() {
+                with_pruned_blocks(|tx, blocks| {
+                    let result =
+                        StarknetBlocksTable::latest_on_or_before(tx, StarknetBlockNumber::MAX)
+                            .unwrap();
+                    assert_eq!(result.as_ref(), blocks.last());
+                })
+            }
+        }
+
+        mod get_parent_hash {
+            use super::*;
+
+            #[test]
+            fn genesis_is_zero() {
+                with_default_blocks(|tx, _blocks| {
+                    let parent_hash =
+                        StarknetBlocksTable::get_parent_hash(tx, StarknetBlockNumber::GENESIS)
+                            .unwrap()
+                            .unwrap();
+                    assert_eq!(parent_hash, StarknetBlockHash(StarkHash::ZERO));
+                })
+            }
+
+            #[test]
+            fn links_to_previous_block() {
+                with_default_blocks(|tx, blocks| {
+                    for pair in blocks.windows(2) {
+                        let parent_hash = StarknetBlocksTable::get_parent_hash(tx, pair[1].number)
+                            .unwrap()
+                            .unwrap();
+                        assert_eq!(parent_hash, pair[0].hash);
+                    }
+                })
+            }
+
+            #[test]
+            fn none() {
+                with_default_blocks(|tx, blocks| {
+                    let non_existent = blocks.last().unwrap().number + 1;
+                    assert_eq!(
+                        StarknetBlocksTable::get_parent_hash(tx, non_existent).unwrap(),
+                        None
+                    );
+                })
+            }
+        }
+
+        mod get_headers_with_version {
+            use super::*;
+
+            /// Inserts the default blocks, with a version for all but the genesis block.
+            fn with_versioned_blocks<F>(f: F)
+            where
+                F: FnOnce(&Transaction<'_>, [StarknetBlock; test_utils::NUM_BLOCKS]),
+            {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let blocks = create_blocks();
+                for block in &blocks {
+                    StarknetBlocksTable::insert(&tx, block, version(block).as_deref()).unwrap();
+                }
+
+                f(&tx, blocks)
+            }
+
+            fn version(block: &StarknetBlock) -> Option<String> {
+                (block.number != StarknetBlockNumber::GENESIS)
+                    .then(|| format!("0.9.{}", block.number.get()))
+            }
+
+            fn expected(block: &StarknetBlock) -> BlockListEntry {
+                BlockListEntry {
+                    number: block.number,
+                    hash: block.hash,
+                    timestamp: block.timestamp,
+                    gas_price: block.gas_price,
+                    sequencer_address: block.sequencer_address,
+                    transaction_count: block.transaction_count,
+                    event_count: block.event_count,
+                    version: version(block),
+                }
+            }
+
+            #[test]
+            fn ascending() {
+                with_versioned_blocks(|tx, blocks| {
+                    let entries = StarknetBlocksTable::get_headers_with_version(
+                        tx,
+                        StarknetBlockNumber::GENESIS,
+                        3,
+                        false,
+                    )
+                    .unwrap();
+                    let expected = blocks[..3].iter().map(expected).collect::<Vec<_>>();
+                    assert_eq!(entries, expected);
+                })
+            }
+
+            #[test]
+            fn descending() {
+                with_versioned_blocks(|tx, blocks| {
+                    let entries = StarknetBlocksTable::get_headers_with_version(
+                        tx,
+                        blocks.last().unwrap().number,
+                        3,
+                        true,
+                    )
+                    .unwrap();
+                    let expected = blocks[1..].iter().rev().map(expected).collect::<Vec<_>>();
+                    assert_eq!(entries, expected);
+                })
+            }
+
+            #[test]
+            fn null_version() {
+                with_versioned_blocks(|tx, blocks| {
+                    let entries = StarknetBlocksTable::get_headers_with_version(
+                        tx,
+                        blocks[1].number,
+                        10,
+                        true,
+                    )
+                    .unwrap();
+                    let expected = blocks[..2].iter().rev().map(expected).collect::<Vec<_>>();
+                    assert_eq!(entries, expected);
+                    assert_eq!(entries.last().unwrap().version, None);
+                })
+            }
+
+            #[test]
+            fn none() {
+                with_versioned_blocks(|tx, blocks| {
+                    let non_existent = blocks.last().unwrap().number + 1;
+                    let entries =
+                        StarknetBlocksTable::get_headers_with_version(tx, non_existent, 10, false)
+                            .unwrap();
+                    assert_eq!(entries, vec![]);
+                })
+            }
+        }
+
+        mod get_blocks_by_sequencer {
+            use super::*;
+            use crate::starkhash;
+
+            const FIRST: SequencerAddress = SequencerAddress(starkhash!("0a"));
+            const SECOND: SequencerAddress = SequencerAddress(starkhash!("0b"));
+            const ZERO: SequencerAddress = SequencerAddress(StarkHash::ZERO);
+
+            /// Inserts a genesis block without a sequencer address, followed by blocks which
+            /// alternate between two sequencers.
+            fn with_sequenced_blocks<F>(f: F)
+            where
+                F: FnOnce(&Transaction<'_>),
+            {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                for n in 0..6 {
+                    let block = StarknetBlock {
+                        sequencer_address: match n {
+                            0 => None,
+                            n if n % 2 == 1 => Some(FIRST),
+                            _ => Some(SECOND),
+                        },
+                        ..StarknetBlock::nth(n)
+                    };
+                    StarknetBlocksTable::insert(&tx, &block, None).unwrap();
+                }
+
+                f(&tx)
+            }
+
+            fn numbers(
+                tx: &Transaction<'_>,
+                sequencer_address: SequencerAddress,
+                from: Option<u64>,
+                limit: usize,
+            ) -> Vec<u64> {
+                let from = from.map(StarknetBlockNumber::new_or_panic);
+                StarknetBlocksTable::get_blocks_by_sequencer(tx, sequencer_address, from, limit)
+                    .unwrap()
+                    .into_iter()
+                    .map(|entry| {
+                        assert_eq!(entry.sequencer_address, Some(sequencer_address));
+                        entry.number.get()
+                    })
+                    .collect()
+            }
+
+            #[test]
+            fn descending_with_continuation() {
+                with_sequenced_blocks(|tx| {
+                    assert_eq!(numbers(tx, FIRST, None, 2), vec![5, 3]);
+                    assert_eq!(numbers(tx, FIRST, Some(3), 2), vec![1]);
+                    assert_eq!(numbers(tx, FIRST, Some(1), 2), Vec::<u64>::new());
+
+                    assert_eq!(numbers(tx, SECOND, None, 10), vec![4, 2]);
+                })
+            }
+
+            #[test]
+            fn missing_address_is_not_zero() {
+                with_sequenced_blocks(|tx| {
+                    assert_eq!(numbers(tx, ZERO, None, 10), Vec::<u64>::new());
+                })
+            }
+
+            #[test]
+            fn count() {
+                with_sequenced_blocks(|tx| {
+                    for (sequencer_address, expected) in [
+                        (FIRST, 3),
+                        (SECOND, 2),
+                        (ZERO, 0),
+                        (SequencerAddress(starkhash!("0c")), 0),
+                    ] {
+                        let count =
+                            StarknetBlocksTable::count_blocks_by_sequencer(tx, sequencer_address)
+                                .unwrap();
+                        assert_eq!(count, expected);
+                    }
+                })
+            }
+        }
+
+        mod reorg {
+            use super::*;
+
+            #[test]
+            fn full() {
+                with_default_blocks(|tx, _blocks| {
+                    // reorg to genesis expected to wipe the blocks
+                    StarknetBlocksTable::reorg(tx, StarknetBlockNumber::GENESIS).unwrap();
+
+                    assert_eq!(
+                        StarknetBlocksTable::get(tx, StarknetBlocksBlockId::Latest).unwrap(),
+                        None
+                    );
+                })
+            }
+
+            #[test]
+            fn partial() {
+                with_default_blocks(|tx, blocks| {
+                    let reorg_tail = blocks[1].number;
+                    StarknetBlocksTable::reorg(tx, reorg_tail).unwrap();
+
+                    let expected = StarknetBlock {
+                        number: blocks[0].number,
+                        hash: blocks[0].hash,
+                        parent_hash: blocks[0].parent_hash,
+                        root: blocks[0].root,
+                        timestamp: blocks[0].timestamp,
+                        gas_price: blocks[0].gas_price,
+                        sequencer_address: blocks[0].sequencer_address,
+                        transaction_count: blocks[0].transaction_count,
+                        event_count: blocks[0].event_count,
+                    };
+
+                    assert_eq!(
+                        StarknetBlocksTable::get(tx, StarknetBlocksBlockId::Latest).unwrap(),
+                        Some(expected)
+                    );
+                })
+            }
+        }
+
+        mod interned_version {
+            use super::super::Storage;
+            use super::StarknetBlocksTable;
+
+            #[test]
+            fn duplicate_versions_interned() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let blocks = super::create_blocks();
+                let versions = ["0.9.1", "0.9.1"]
+                    .into_iter()
+                    .chain(std::iter::repeat("0.9.2"));
+
+                let mut inserted = 0;
+
+                for (block, version) in blocks.iter().zip(versions) {
+                    StarknetBlocksTable::insert(&tx, block, Some(version)).unwrap();
+                    inserted += 1;
+                }
+
+                let rows = tx.prepare("select version_id, count(1) from starknet_blocks group by version_id order by version_id")
+                    .unwrap()
+                    .query([])
+                    .unwrap()
+                    .mapped(|r| Ok((r.get::<_, Option<i64>>(0)?, r.get::<_, i64>(1)?)))
+                    .collect::<Result<Vec<(Option<i64>, i64)>, _>>()
+                    .unwrap();
+
+                // there should be two of 0.9.1
+                assert_eq!(rows.first(), Some(&(Some(1), 2)));
+
+                // there should be a few for 0.9.2 (initially the create_rows returned 3 => 1)
+                assert_eq!(rows.last(), Some(&(Some(2), inserted - 2)));
+
+                // we should not have any nulls
+                assert_eq!(rows.len(), 2, "nulls were not expected in {rows:?}");
+            }
+
+            #[test]
+            fn prune_unreferenced() {
+                use super::{StarknetBlockNumber, StarknetVersionsTable};
+
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let blocks = super::create_blocks();
+                let versions = ["0.9.0", "0.9.0", "0.9.1", "0.9.1"];
+                for (block, version) in blocks.iter().zip(versions) {
+                    StarknetBlocksTable::insert(&tx, block, Some(version)).unwrap();
+                }
+                assert_eq!(StarknetVersionsTable::prune_unreferenced(&tx).unwrap(), 0);
+
+                StarknetBlocksTable::reorg(&tx, blocks[2].number).unwrap();
+                assert_eq!(StarknetVersionsTable::prune_unreferenced(&tx).unwrap(), 1);
+
+                let remaining = tx
+                    .query_row("SELECT COUNT(1) FROM starknet_versions", [], |row| {
+                        row.get::<_, usize>(0)
+                    })
+                    .unwrap();
+                assert_eq!(remaining, 1);
+                let version =
+                    StarknetBlocksTable::get_version(&tx, StarknetBlockNumber::GENESIS).unwrap();
+                assert_eq!(version.as_deref(), Some("0.9.0"));
+            }
+
+            #[test]
+            fn backfill_resumes() {
+                use super::{backfill_block_versions, StarknetBlock, StarknetBlockNumber};
+                use std::cell::RefCell;
+
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+
+                let tx = connection.transaction().unwrap();
+                for n in 0..6 {
+                    StarknetBlocksTable::insert(&tx, &StarknetBlock::nth(n), None).unwrap();
+                }
+                tx.commit().unwrap();
+
+                // The sequencer does not know the version of block 4.
+                let fetched = RefCell::new(Vec::new());
+                let fetch = |number: StarknetBlockNumber| {
+                    fetched.borrow_mut().push(number.get());
+                    (number.get() != 4).then(|| format!("0.{}", number.get() / 2))
+                };
+                let range = |from, to| {
+                    StarknetBlockNumber::new_or_panic(from)..=StarknetBlockNumber::new_or_panic(to)
+                };
+
+                let tx = connection.transaction().unwrap();
+                let backfill = backfill_block_versions(&tx, fetch, range(0, 2)).unwrap();
+                assert_eq!(backfill.updated, 3);
+                tx.commit().unwrap();
+
+                // Interrupted before committing.
+                let tx = connection.transaction().unwrap();
+                backfill_block_versions(&tx, fetch, range(3, 5)).unwrap();
+                drop(tx);
+
+                fetched.borrow_mut().clear();
+                let tx = connection.transaction().unwrap();
+                let backfill = backfill_block_versions(&tx, fetch, range(0, 5)).unwrap();
+                assert_eq!(fetched.borrow().as_slice(), &[3, 4, 5]);
+                assert_eq!(backfill.updated, 2);
+                assert_eq!(backfill.unavailable, 1);
+
+                let versions = (0..6)
+                    .map(|n| {
+                        StarknetBlocksTable::get_version(&tx, StarknetBlockNumber::new_or_panic(n))
+                            .unwrap()
+                    })
+                    .collect::<Vec<_>>();
+                let expected = ["0.0", "0.0", "0.1", "0.1", "", "0.2"]
+                    .map(|version| (!version.is_empty()).then(|| version.to_owned()));
+                assert_eq!(versions, expected);
+            }
+        }
+
+        mod get_latest_number {
+            use super::*;
+
+            #[test]
+            fn some() {
+                with_default_blocks(|tx, blocks| {
+                    let latest = blocks.last().unwrap().number;
+                    assert_eq!(
+                        StarknetBlocksTable::get_latest_number(tx).unwrap(),
+                        Some(latest)
+                    );
+                });
+            }
+
+            #[test]
+            fn none() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                assert_eq!(StarknetBlocksTable::get_latest_number(&tx).unwrap(), None);
+            }
+        }
+
+        mod get_latest_hash_and_number {
+            use super::*;
+
+            #[test]
+            fn some() {
+                with_default_blocks(|tx, blocks| {
+                    let latest = blocks.last().unwrap();
+                    assert_eq!(
+                        StarknetBlocksTable::get_latest_hash_and_number(tx).unwrap(),
+                        Some((latest.hash, latest.number))
+                    );
+                });
+            }
+
+            #[test]
+            fn none() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                assert_eq!(
+                    StarknetBlocksTable::get_latest_hash_and_number(&tx).unwrap(),
+                    None
+                );
+            }
+        }
+
+        mod get_number_or_latest {
+            use super::*;
+
+            #[test]
+            fn by_number() {
+                with_default_blocks(|tx, blocks| {
+                    for block in &blocks {
+                        let result =
+                            StarknetBlocksTable::get_number_or_latest(tx, block.number.into())
+                                .unwrap();
+                        assert_eq!(result, Some(block.number));
+                    }
+
+                    let non_existent = blocks.last().unwrap().number + 1;
+                    let result =
+                        StarknetBlocksTable::get_number_or_latest(tx, non_existent.into()).unwrap();
+                    assert_eq!(result, None);
+                })
+            }
+
+            #[test]
+            fn by_hash() {
+                with_default_blocks(|tx, blocks| {
+                    for block in &blocks {
+                        let result =
+                            StarknetBlocksTable::get_number_or_latest(tx, block.hash.into())
+                                .unwrap();
+                        assert_eq!(result, Some(block.number));
+                    }
+
+                    let non_existent = StarknetBlockHash(StarkHash::from_hex_str("b").unwrap());
+                    let result =
+                        StarknetBlocksTable::get_number_or_latest(tx, non_existent.into()).unwrap();
+                    assert_eq!(result, None);
+                })
+            }
+
+            #[test]
+            fn latest() {
+                with_default_blocks(|tx, blocks| {
+                    let result = StarknetBlocksTable::get_number_or_latest(
+                        tx,
+                        StarknetBlocksBlockId::Latest,
+                    )
+                    .unwrap();
+                    assert_eq!(result, Some(blocks.last().unwrap().number));
+                });
+
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let result =
+                    StarknetBlocksTable::get_number_or_latest(&tx, StarknetBlocksBlockId::Latest)
+                        .unwrap();
+                assert_eq!(result, None);
+            }
+        }
+
+        mod timestamps {
+            use super::*;
+
+            /// The default blocks with the given timestamps.
+            fn blocks_with_timestamps(timestamps: &[u64]) -> Vec<StarknetBlock> {
+                create_blocks()
+                    .into_iter()
+                    .zip(timestamps)
+                    .map(|(block, &timestamp)| StarknetBlock {
+                        timestamp: StarknetBlockTimestamp::new_or_panic(timestamp),
+                        ..block
+                    })
+                    .collect()
+            }
+
+            fn insert(
+                tx: &Transaction<'_>,
+                block: &StarknetBlock,
+                policy: TimestampPolicy,
+            ) -> anyhow::Result<()> {
+                StarknetBlocksTable::insert_with_timestamp_policy(tx, block, None, policy)
+            }
+
+            #[test]
+            fn reject() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let blocks = blocks_with_timestamps(&[10, 5, 10]);
+                insert(&tx, &blocks[0], TimestampPolicy::Reject).unwrap();
+
+                let error = insert(&tx, &blocks[1], TimestampPolicy::Reject).unwrap_err();
+                assert_eq!(
+                    error.downcast_ref::<NonMonotonicTimestamp>(),
+                    Some(&NonMonotonicTimestamp {
+                        parent: StarknetBlockTimestamp::new_or_panic(10),
+                        new: StarknetBlockTimestamp::new_or_panic(5),
+                    })
+                );
+                assert_eq!(
+                    StarknetBlocksTable::get(&tx, blocks[1].number.into()).unwrap(),
+                    None
+                );
+
+                // Equal timestamps are not a regression.
+                let mut equal = blocks[1].clone();
+                equal.timestamp = blocks[0].timestamp;
+                insert(&tx, &equal, TimestampPolicy::Reject).unwrap();
+            }
+
+            #[test]
+            fn warn_only_and_allow() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let blocks = blocks_with_timestamps(&[10, 5, 1]);
+                insert(&tx, &blocks[0], TimestampPolicy::Reject).unwrap();
+                insert(&tx, &blocks[1], TimestampPolicy::WarnOnly).unwrap();
+                insert(&tx, &blocks[2], TimestampPolicy::Allow).unwrap();
+
+                assert_eq!(
+                    StarknetBlocksTable::get(&tx, StarknetBlocksBlockId::Latest).unwrap(),
+                    Some(blocks[2].clone())
+                );
+                assert_eq!(TimestampPolicy::default(), TimestampPolicy::Allow);
+            }
+
+            #[test]
+            fn max_regression() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let blocks = blocks_with_timestamps(&[10, 5, 20, 8]);
+                for block in &blocks {
+                    insert(&tx, block, TimestampPolicy::Allow).unwrap();
+                }
+
+                let number = |n| StarknetBlockNumber::new_or_panic(n);
+                let worst =
+                    StarknetBlocksTable::max_timestamp_regression(&tx, number(0), number(3))
+                        .unwrap()
+                        .unwrap();
+                assert_eq!(
+                    worst,
+                    TimestampRegression {
+                        block: number(3),
+                        parent: StarknetBlockTimestamp::new_or_panic(20),
+                        new: StarknetBlockTimestamp::new_or_panic(8),
+                    }
+                );
+                assert_eq!(worst.seconds(), 12);
+
+                let worst =
+                    StarknetBlocksTable::max_timestamp_regression(&tx, number(0), number(2))
+                        .unwrap()
+                        .unwrap();
+                assert_eq!(worst.block, number(1));
+
+                assert_eq!(
+                    StarknetBlocksTable::max_timestamp_regression(&tx, number(2), number(2))
+                        .unwrap(),
+                    None
+                );
+            }
+        }
+
+        mod get_number_and_hash {
+            use super::*;
+
+            #[test]
+            fn by_number() {
+                with_default_blocks(|tx, blocks| {
+                    for block in &blocks {
+                        let result =
+                            StarknetBlocksTable::get_number_and_hash(tx, block.number.into())
+                                .unwrap();
+                        assert_eq!(result, Some((block.number, block.hash)));
+                    }
+
+                    let non_existent = blocks.last().unwrap().number + 1;
+                    let result =
+                        StarknetBlocksTable::get_number_and_hash(tx, non_existent.into()).unwrap();
+                    assert_eq!(result, None);
+                })
+            }
+
+            #[test]
+            fn by_hash() {
+                with_default_blocks(|tx, blocks| {
+                    for block in &blocks {
+                        let result =
+                            StarknetBlocksTable::get_number_and_hash(tx, block.hash.into())
+                                .unwrap();
+                        assert_eq!(result, Some((block.number, block.hash)));
+                    }
+
+                    let non_existent = StarknetBlockHash(StarkHash::from_hex_str("b").unwrap());
+                    let result =
+                        StarknetBlocksTable::get_number_and_hash(tx, non_existent.into()).unwrap();
+                    assert_eq!(result, None);
+                })
+            }
+
+            #[test]
+            fn latest() {
+                with_default_blocks(|tx, blocks| {
+                    let latest = blocks.last().unwrap();
+                    let result =
+                        StarknetBlocksTable::get_number_and_hash(tx, StarknetBlocksBlockId::Latest)
+                            .unwrap();
+                    assert_eq!(result, Some((latest.number, latest.hash)));
+                });
+
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let result =
+                    StarknetBlocksTable::get_number_and_hash(&tx, StarknetBlocksBlockId::Latest)
+                        .unwrap();
+                assert_eq!(result, None);
+            }
+        }
+
+        mod get_hashes_in_range {
+            use super::*;
+
+            #[test]
+            fn sub_range() {
+                with_default_blocks(|tx, blocks| {
+                    let expected = blocks[1..3]
+                        .iter()
+                        .map(|block| (block.number, block.hash))
+                        .collect::<Vec<_>>();
+
+                    let hashes = StarknetBlocksTable::get_hashes_in_range(
+                        tx,
+                        blocks[1].number,
+                        blocks[2].number,
+                    )
+                    .unwrap();
+                    assert_eq!(hashes, expected);
+                })
+            }
+
+            #[test]
+            fn beyond_latest() {
+                with_default_blocks(|tx, blocks| {
+                    let latest = blocks.last().unwrap();
+
+                    let hashes = StarknetBlocksTable::get_hashes_in_range(
+                        tx,
+                        latest.number,
+                        latest.number + 10,
+                    )
+                    .unwrap();
+                    assert_eq!(hashes, vec![(latest.number, latest.hash)]);
+
+                    let hashes = StarknetBlocksTable::get_hashes_in_range(
+                        tx,
+                        latest.number + 1,
+                        latest.number + 10,
+                    )
+                    .unwrap();
+                    assert_eq!(hashes, vec![]);
+                })
+            }
+        }
+
+        mod get_gas_prices_in_range {
+            use super::*;
+
+            #[test]
+            fn all() {
+                with_default_blocks(|tx, blocks| {
+                    let expected = blocks
+                        .iter()
+                        .map(|block| (block.number, block.gas_price))
+                        .collect::<Vec<_>>();
+
+                    let prices = StarknetBlocksTable::get_gas_prices_in_range(
+                        tx,
+                        StarknetBlockNumber::GENESIS,
+                        blocks.last().unwrap().number + 10,
+                    )
+                    .unwrap();
+                    assert_eq!(prices, expected);
+                })
+            }
+
+            #[test]
+            fn sub_range() {
+                with_default_blocks(|tx, blocks| {
+                    let prices = StarknetBlocksTable::get_gas_prices_in_range(
+                        tx,
+                        blocks[1].number,
+                        blocks[2].number,
+                    )
+                    .unwrap();
+                    assert_eq!(
+                        prices,
+                        vec![
+                            (blocks[1].number, blocks[1].gas_price),
+                            (blocks[2].number, blocks[2].gas_price)
+                        ]
+                    );
+                })
+            }
+
+            #[test]
+            fn invalid_blob() {
+                with_default_blocks(|tx, blocks| {
+                    tx.execute(
+                        "UPDATE starknet_blocks SET gas_price = zeroblob(17) WHERE number = ?",
+                        [blocks[1].number],
+                    )
+                    .unwrap();
+
+                    StarknetBlocksTable::get_gas_prices_in_range(
+                        tx,
+                        blocks[0].number,
+                        blocks[2].number,
+                    )
+                    .unwrap_err();
+                })
+            }
+        }
+
+        mod get_hash {
+            use super::*;
+
+            mod by_number {
+                use super::*;
+
+                #[test]
+                fn some() {
+                    with_default_blocks(|tx, blocks| {
+                        for block in blocks {
+                            let result = StarknetBlocksTable::get_hash(tx, block.number.into())
+                                .unwrap()
+                                .unwrap();
+
+                            assert_eq!(result, block.hash);
+                        }
+                    })
+                }
+
+                #[test]
+                fn none() {
+                    with_default_blocks(|tx, blocks| {
+                        let non_existent = blocks.last().unwrap().number + 1;
+                        assert_eq!(
+                            StarknetBlocksTable::get(tx, non_existent.into()).unwrap(),
+                            None
+                        );
+                    });
+                }
+            }
+
+            mod latest {
+                use super::*;
+
+                #[test]
+                fn some() {
+                    with_default_blocks(|tx, blocks| {
+                        let expected = blocks.last().unwrap().hash;
+
+                        let latest =
+                            StarknetBlocksTable::get_hash(tx, StarknetBlocksNumberOrLatest::Latest)
+                                .unwrap()
+                                .unwrap();
+                        assert_eq!(latest, expected);
+                    })
+                }
+
+                #[test]
+                fn none() {
+                    let storage = Storage::in_memory().unwrap();
+                    let mut connection = storage.connection().unwrap();
+                    let tx = connection.transaction().unwrap();
+
+                    let latest =
+                        StarknetBlocksTable::get(&tx, StarknetBlocksBlockId::Latest).unwrap();
+                    assert_eq!(latest, None);
+                }
+            }
+        }
+
+        #[test]
+        fn header_counts_match_stored_rows() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let headers = StarknetBlocksTable::get_headers_with_version(
+                &tx,
+                StarknetBlockNumber::GENESIS,
+                test_utils::NUM_BLOCKS,
+                false,
+            )
+            .unwrap();
+            assert_eq!(headers.len(), test_utils::NUM_BLOCKS);
+
+            for header in headers {
+                let transactions: usize = tx
+                    .query_row(
+                        "SELECT COUNT(1) FROM starknet_transactions WHERE block_hash = ?",
+                        [header.hash],
+                        |row| row.get(0),
+                    )
+                    .unwrap();
+                let events: usize = tx
+                    .query_row(
+                        "SELECT COUNT(1) FROM starknet_events WHERE block_number = ?",
+                        [header.number],
+                        |row| row.get(0),
+                    )
+                    .unwrap();
+                assert_eq!(header.transaction_count, transactions);
+                assert_eq!(header.event_count, events);
+                assert_ne!(events, 0);
+
+                let block = StarknetBlocksTable::get(&tx, header.number.into())
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(block.transaction_count, transactions);
+                assert_eq!(block.event_count, events);
+            }
+        }
+    }
+
+    mod starknet_events {
+        use web3::types::H128;
+
+        use super::*;
+
+        use crate::core::{EntryPoint, EventData, Fee};
+        use crate::sequencer::reply::transaction;
+        use crate::starkhash;
+        use crate::storage::test_utils;
+
+        fn filter_error(error: StorageError) -> EventFilterError {
+            match error {
+                StorageError::InvalidFilter(e) => e,
+                other => panic!("Expected a filter error, got {other:?}"),
+            }
+        }
+
+        #[test]
+        fn event_data_serialization() {
+            let data = [
+                EventData(starkhash!("01")),
+                EventData(starkhash!("02")),
+                EventData(starkhash!("03")),
+            ];
+
+            let mut buffer = Vec::new();
+            StarknetEventsTable::encode_event_data_to_bytes(&data, &mut buffer);
+
+            assert_eq!(
+                &buffer,
+                &[
+                    0x80u8, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0,
+                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3
+                ]
+            );
+        }
+
+        #[test]
+        fn event_keys_to_base64_strings() {
+            let event = transaction::Event {
+                from_address: ContractAddress::new_or_panic(starkhash!(
+                    "06fbd460228d843b7fbef670ff15607bf72e19fa94de21e29811ada167b4ca39"
+                )),
+                data: vec![],
+                keys: vec![
+                    EventKey(starkhash!("901823")),
+                    EventKey(starkhash!("901824")),
+                    EventKey(starkhash!("901825")),
+                ],
+            };
+
+            let mut buf = String::new();
+            StarknetEventsTable::event_keys_to_base64_strings(&event.keys, &mut buf);
+            assert_eq!(buf.capacity(), buf.len());
+            assert_eq!(
+                buf,
+                "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAACQGCM= AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAACQGCQ= AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAACQGCU="
+            );
+        }
+
+        #[test]
+        fn base64_key_decoding() {
+            let keys = [
+                StarkHash::ZERO,
+                starkhash!("01"),
+                starkhash!("deadbeef"),
+                starkhash!("06fbd460228d843b7fbef670ff15607bf72e19fa94de21e29811ada167b4ca39"),
+            ];
+            for key in keys {
+                let mut encoded = String::new();
+                StarknetEventsTable::encode_event_key_to_base64(&EventKey(key), &mut encoded);
+
+                let decoded = StarknetEventsTable::decode_base64_key(encoded.as_bytes());
+                assert_eq!(decoded, Some(*key.as_be_bytes()), "{encoded}");
+            }
+
+            let valid = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAACQGCM=";
+            assert!(StarknetEventsTable::decode_base64_key(valid.as_bytes()).is_some());
+            for invalid in [
+                &valid[1..],
+                "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAACQGCMA",
+                "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAACQGC!=",
+                // Sets bits past the 32nd byte.
+                "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAACQGCN=",
+            ] {
+                assert_eq!(
+                    StarknetEventsTable::decode_base64_key(invalid.as_bytes()),
+                    None,
+                    "{invalid}"
+                );
+            }
+        }
+
+        #[test]
+        fn keys_without_key_bytes() {
+            let (storage, events) = test_utils::setup_test_storage();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            // As stored before the key_bytes column, for every other event.
+            tx.execute(
+                "UPDATE starknet_events SET key_bytes = NULL WHERE id % 2 = 0",
+                [],
+            )
+            .unwrap();
+
+            let filter = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                contract_address: None,
+                keys: vec![],
+                data_filter: vec![],
+                page_size: test_utils::NUM_EVENTS,
+                page_number: 0,
+                detect_last_page: true,
+                count_pages: false,
+                only_accepted: false,
+            };
+            let page = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            assert_eq!(page.events, events);
+
+            for event in &events {
+                let stored =
+                    StarknetEventsTable::get_events_for_transaction(&tx, event.transaction_hash)
+                        .unwrap();
+                assert_eq!(stored, vec![event.clone()]);
+            }
+        }
+
+        /// Attaches a single event to the declare transaction of the genesis block, which has no
+        /// events of its own, and returns the transaction's hash.
+        fn insert_declare_event(
+            tx: &Transaction<'_>,
+            event: transaction::Event,
+        ) -> StarknetTransactionHash {
+            let (declare, _) = &test_utils::create_transactions_and_receipts()[10];
+            let genesis = &test_utils::create_blocks()[0];
+            StarknetEventsTable::insert_events(tx, genesis.number, declare.hash(), &[event])
+                .unwrap();
+            declare.hash()
+        }
+
+        #[test]
+        fn event_without_keys() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let event = transaction::Event {
+                from_address: ContractAddress::new_or_panic(starkhash!("0123")),
+                data: vec![EventData(starkhash!("0456"))],
+                keys: vec![],
+            };
+            let transaction = insert_declare_event(&tx, event.clone());
+
+            let events = StarknetEventsTable::get_events_for_transaction(&tx, transaction).unwrap();
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].keys, vec![]);
+            assert_eq!(events[0].data, event.data);
+        }
+
+        #[test]
+        fn missing_transaction_is_an_error() {
+            let (storage, emitted_events) = test_utils::setup_test_storage();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let event = &emitted_events[0];
+            tx.execute(
+                "DELETE FROM starknet_transactions WHERE hash = ?",
+                [event.transaction_hash],
+            )
+            .unwrap();
+
+            // With and without keys, so that both the full-text index and the plain paths are
+            // covered.
+            for keys in [vec![], vec![event.keys[0]]] {
+                let filter = StarknetEventFilter {
+                    from_block: None,
+                    to_block: None,
+                    contract_address: None,
+                    keys: keys.clone(),
+                    data_filter: vec![],
+                    page_size: test_utils::NUM_EVENTS,
+                    page_number: 0,
+                    detect_last_page: true,
+                    count_pages: false,
+                    only_accepted: false,
+                };
+
+                let error = StarknetEventsTable::get_events(&tx, &filter).unwrap_err();
+                assert!(
+                    matches!(
+                        error,
+                        StorageError::Corruption {
+                            table: "starknet_transactions",
+                            ..
+                        }
+                    ),
+                    "{error:?}"
+                );
+
+                // The event is still counted, as get_events fails rather than skipping it.
+                let expected = emitted_events
+                    .iter()
+                    .filter(|e| keys.iter().all(|key| e.keys.contains(key)))
+                    .count();
+                let count = StarknetEventsTable::event_count(
+                    &tx,
+                    &StarknetEventFilter {
+                        from_block: None,
+                        to_block: None,
+                        contract_address: None,
+                        keys,
+                        data_filter: vec![],
+                        page_size: 0,
+                        page_number: 0,
+                        detect_last_page: false,
+                        count_pages: false,
+                        only_accepted: false,
+                    },
+                    StarknetEventsTable::DEFAULT_MAX_KEYS,
+                )
+                .unwrap();
+                assert_eq!(count, expected);
+            }
+
+            let error =
+                StarknetEventsTable::get_events_for_transaction(&tx, event.transaction_hash)
+                    .unwrap_err();
+            assert!(
+                matches!(
+                    error,
+                    StorageError::Corruption {
+                        table: "starknet_transactions",
+                        ..
+                    }
+                ),
+                "{error:?}"
+            );
+        }
+
+        #[test]
+        fn corrupted_keys_are_an_error() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let event = transaction::Event {
+                from_address: ContractAddress::new_or_panic(starkhash!("0123")),
+                data: vec![],
+                keys: vec![EventKey(starkhash!("0789"))],
+            };
+            let transaction = insert_declare_event(&tx, event);
+
+            // Keys in the old format, and a key which overflows.
+            let corruptions = [
+                ("not base64!", None),
+                ("AAAA AAAA====", None),
+                (&"A".repeat(100), None),
+                ("", Some(vec![0xffu8; 32])),
+            ];
+            for (keys, key_bytes) in corruptions {
+                tx.execute(
+                    "UPDATE starknet_events SET keys = ?, key_bytes = ? WHERE transaction_hash = ?",
+                    rusqlite::params![keys, key_bytes, transaction],
+                )
+                .unwrap();
+
+                let error =
+                    StarknetEventsTable::get_events_for_transaction(&tx, transaction).unwrap_err();
+                assert!(
+                    format!("{error:#}").contains("Decoding event of transaction"),
+                    "{error:#}"
+                );
+
+                let filter = StarknetEventFilter {
+                    from_block: None,
+                    to_block: None,
+                    contract_address: None,
+                    keys: vec![],
+                    data_filter: vec![],
+                    page_size: test_utils::NUM_EVENTS + 1,
+                    page_number: 0,
+                    detect_last_page: true,
+                    count_pages: false,
+                    only_accepted: false,
+                };
+                StarknetEventsTable::get_events(&tx, &filter).unwrap_err();
+            }
+        }
+
+        #[test]
+        fn truncated_data_is_an_error() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let event = transaction::Event {
+                from_address: ContractAddress::new_or_panic(starkhash!("0123")),
+                data: vec![EventData(starkhash!("01")), EventData(starkhash!("02"))],
+                keys: vec![],
+            };
+            let transaction = insert_declare_event(&tx, event);
+
+            // Truncated by a whole element, by part of an element and into the header.
+            for length in [37, 68, 3] {
+                tx.execute(
+                    "UPDATE starknet_events SET data = substr(data, 1, ?) WHERE transaction_hash = ?",
+                    rusqlite::params![length, transaction],
+                )
+                .unwrap();
+
+                let error =
+                    StarknetEventsTable::get_events_for_transaction(&tx, transaction).unwrap_err();
+                assert!(
+                    format!("{error:#}").contains("invalid event data"),
+                    "{error:#}"
+                );
+            }
+        }
+
+        #[test]
+        fn legacy_data() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let data = vec![EventData(starkhash!("01")), EventData(starkhash!("02"))];
+            let event = transaction::Event {
+                from_address: ContractAddress::new_or_panic(starkhash!("0123")),
+                data: data.clone(),
+                keys: vec![],
+            };
+            let transaction = insert_declare_event(&tx, event);
+
+            // Drop the header, leaving the bare elements.
+            tx.execute(
+                "UPDATE starknet_events SET data = substr(data, 6) WHERE transaction_hash = ?",
+                [transaction],
+            )
+            .unwrap();
+
+            let events = StarknetEventsTable::get_events_for_transaction(&tx, transaction).unwrap();
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].data, data);
+        }
+
+        #[test]
+        fn get_events_with_fully_specified_filter() {
+            let (storage, emitted_events) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let expected_event = &emitted_events[1];
+            let filter = StarknetEventFilter {
+                from_block: Some(expected_event.block_number),
+                to_block: Some(expected_event.block_number),
+                contract_address: Some(expected_event.from_address),
+                // we're using a key which is present in _all_ events
+                keys: vec![EventKey(starkhash!("deadbeef"))],
+                data_filter: vec![],
+                page_size: test_utils::NUM_EVENTS,
+                page_number: 0,
+                detect_last_page: true,
+                count_pages: false,
+                only_accepted: false,
+            };
+
+            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            assert_eq!(
+                events,
+                PageOfEvents {
+                    events: vec![expected_event.clone()],
+                    is_last_page: true,
+                    total_pages: None,
+                }
+            );
+        }
+
+        #[test]
+        fn events_are_ordered() {
+            // This is a regression test where events were incorrectly ordered by transaction hash
+            // instead of transaction index.
+            //
+            // Events should be ordered by block number, transaction index, event index.
+            use crate::core::StarknetTransactionHash;
+            use crate::sequencer::reply::transaction::Event;
+
+            // All events we are storing, arbitrarily use from_address to distinguish them.
+            let expected_events = (0u8..5)
+                .map(|idx| Event {
+                    data: Vec::new(),
+                    keys: Vec::new(),
+                    from_address: ContractAddress::new_or_panic(
+                        StarkHash::from_be_slice(&idx.to_be_bytes()).unwrap(),
+                    ),
+                })
+                .collect::<Vec<_>>();
+
+            let block = StarknetBlock {
+                number: StarknetBlockNumber::GENESIS,
+                hash: StarknetBlockHash(starkhash!("1234")),
+                parent_hash: StarknetBlockHash(StarkHash::ZERO),
+                root: GlobalRoot(starkhash!("1234")),
+                timestamp: StarknetBlockTimestamp::new_or_panic(0),
+                gas_price: GasPrice(0),
+                sequencer_address: Some(SequencerAddress(starkhash!("1234"))),
+                transaction_count: 2,
+                event_count: 0,
+            };
+
+            // Note: hashes are reverse ordered to trigger the sorting bug.
+            let transactions = vec![
+                transaction::Transaction::Invoke(transaction::InvokeTransaction::V0(
+                    transaction::InvokeTransactionV0 {
+                        calldata: vec![],
+                        // Only required because event insert rejects if this is None
+                        contract_address: ContractAddress::new_or_panic(StarkHash::ZERO),
+                        entry_point_type: transaction::EntryPointType::External,
+                        entry_point_selector: EntryPoint(StarkHash::ZERO),
+                        max_fee: Fee(H128::zero()),
+                        signature: vec![],
+                        transaction_hash: StarknetTransactionHash(starkhash!("0F")),
+                    },
+                )),
+                transaction::Transaction::Invoke(transaction::InvokeTransaction::V0(
+                    transaction::InvokeTransactionV0 {
+                        calldata: vec![],
+                        // Only required because event insert rejects if this is None
+                        contract_address: ContractAddress::new_or_panic(StarkHash::ZERO),
+                        entry_point_type: transaction::EntryPointType::External,
+                        entry_point_selector: EntryPoint(StarkHash::ZERO),
+                        max_fee: Fee(H128::zero()),
+                        signature: vec![],
+                        transaction_hash: StarknetTransactionHash(starkhash!("01")),
+                    },
+                )),
+            ];
+
+            let receipts = vec![
+                transaction::Receipt {
+                    actual_fee: None,
+                    events: expected_events[..3].to_vec(),
+                    execution_resources: Some(transaction::ExecutionResources {
+                        builtin_instance_counter:
+                            transaction::execution_resources::BuiltinInstanceCounter::Empty(
+                                transaction::execution_resources::EmptyBuiltinInstanceCounter {},
+                            ),
+                        n_steps: 0,
+                        n_memory_holes: 0,
+                    }),
+                    execution_status: transaction::ExecutionStatus::Succeeded,
+                    l1_to_l2_consumed_message: None,
+                    l2_to_l1_messages: Vec::new(),
+                    transaction_hash: transactions[0].hash(),
+                    transaction_index: crate::core::StarknetTransactionIndex::new_or_panic(0),
+                },
+                transaction::Receipt {
+                    actual_fee: None,
+                    events: expected_events[3..].to_vec(),
+                    execution_resources: Some(transaction::ExecutionResources {
+                        builtin_instance_counter:
+                            transaction::execution_resources::BuiltinInstanceCounter::Empty(
+                                transaction::execution_resources::EmptyBuiltinInstanceCounter {},
+                            ),
+                        n_steps: 0,
+                        n_memory_holes: 0,
+                    }),
+                    execution_status: transaction::ExecutionStatus::Succeeded,
+                    l1_to_l2_consumed_message: None,
+                    l2_to_l1_messages: Vec::new(),
+                    transaction_hash: transactions[1].hash(),
+                    transaction_index: crate::core::StarknetTransactionIndex::new_or_panic(1),
+                },
+            ];
+
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            StarknetBlocksTable::insert(&tx, &block, None).unwrap();
+            CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
+            StarknetTransactionsTable::upsert(
+                &tx,
+                block.hash,
+                block.number,
+                &vec![
+                    (transactions[0].clone(), receipts[0].clone()),
+                    (transactions[1].clone(), receipts[1].clone()),
+                ],
+            )
+            .unwrap();
+
+            let addresses = StarknetEventsTable::get_events(
+                &tx,
+                &StarknetEventFilter {
+                    from_block: None,
+                    to_block: None,
+                    contract_address: None,
+                    keys: vec![],
+                    data_filter: vec![],
+                    page_size: 1024,
+                    page_number: 0,
+                    detect_last_page: true,
+                    count_pages: false,
+                    only_accepted: false,
+                },
+            )
+            .unwrap()
+            .events
+            .iter()
+            .map(|e| e.from_address)
+            .collect::<Vec<_>>();
+
+            let expected = expected_events
+                .iter()
+                .map(|e| e.from_address)
+                .collect::<Vec<_>>();
+
+            assert_eq!(addresses, expected);
+        }
+
+        #[test]
+        fn get_events_by_block() {
+            let (storage, emitted_events) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            const BLOCK_NUMBER: usize = 2;
+            let filter = StarknetEventFilter {
+                from_block: Some(StarknetBlockNumber::new_or_panic(BLOCK_NUMBER as u64)),
+                to_block: Some(StarknetBlockNumber::new_or_panic(BLOCK_NUMBER as u64)),
+                contract_address: None,
+                keys: vec![],
+                data_filter: vec![],
+                page_size: test_utils::NUM_EVENTS,
+                page_number: 0,
+                detect_last_page: true,
+                count_pages: false,
+                only_accepted: false,
+            };
+
+            let expected_events = &emitted_events[test_utils::EVENTS_PER_BLOCK * BLOCK_NUMBER
+                ..test_utils::EVENTS_PER_BLOCK * (BLOCK_NUMBER + 1)];
+            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            assert_eq!(
+                events,
+                PageOfEvents {
+                    events: expected_events.to_vec(),
+                    is_last_page: true,
+                    total_pages: None,
+                }
+            );
+        }
+
+        #[test]
+        fn get_events_up_to_block() {
+            let (storage, emitted_events) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            const UNTIL_BLOCK_NUMBER: usize = 2;
+            let filter = StarknetEventFilter {
+                from_block: None,
+                to_block: Some(StarknetBlockNumber::new_or_panic(UNTIL_BLOCK_NUMBER as u64)),
+                contract_address: None,
+                keys: vec![],
+                data_filter: vec![],
+                page_size: test_utils::NUM_EVENTS,
+                page_number: 0,
+                detect_last_page: true,
+                count_pages: false,
+                only_accepted: false,
+            };
+
+            let expected_events =
+                &emitted_events[..test_utils::EVENTS_PER_BLOCK * (UNTIL_BLOCK_NUMBER + 1)];
+            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            assert_eq!(
+                events,
+                PageOfEvents {
+                    events: expected_events.to_vec(),
+                    is_last_page: true,
+                    total_pages: None,
+                }
+            );
+        }
+
+        #[test]
+        fn get_events_from_block_onwards() {
+            let (storage, emitted_events) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            const FROM_BLOCK_NUMBER: usize = 2;
+            let filter = StarknetEventFilter {
+                from_block: Some(StarknetBlockNumber::new_or_panic(FROM_BLOCK_NUMBER as u64)),
+                to_block: None,
+                contract_address: None,
+                keys: vec![],
+                data_filter: vec![],
+                page_size: test_utils::NUM_EVENTS,
+                page_number: 0,
+                detect_last_page: true,
+                count_pages: false,
+                only_accepted: false,
+            };
+
+            let expected_events =
+                &emitted_events[test_utils::EVENTS_PER_BLOCK * FROM_BLOCK_NUMBER..];
+            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            assert_eq!(
+                events,
+                PageOfEvents {
+                    events: expected_events.to_vec(),
+                    is_last_page: true,
+                    total_pages: None,
+                }
+            );
+        }
+
+        #[test]
+        fn get_events_from_contract() {
+            let (storage, emitted_events) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let expected_event = &emitted_events[33];
+
+            let filter = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                contract_address: Some(expected_event.from_address),
+                keys: vec![],
+                data_filter: vec![],
+                page_size: test_utils::NUM_EVENTS,
+                page_number: 0,
+                detect_last_page: true,
+                count_pages: false,
+                only_accepted: false,
+            };
+
+            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            assert_eq!(
+                events,
+                PageOfEvents {
+                    events: vec![expected_event.clone()],
+                    is_last_page: true,
+                    total_pages: None,
+                }
+            );
+        }
+
+        #[test]
+        fn get_events_by_key() {
+            let (storage, emitted_events) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let expected_event = &emitted_events[27];
+            let filter = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                contract_address: None,
+                keys: vec![expected_event.keys[0]],
+                data_filter: vec![],
+                page_size: test_utils::NUM_EVENTS,
+                page_number: 0,
+                detect_last_page: true,
+                count_pages: false,
+                only_accepted: false,
+            };
+
+            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            assert_eq!(
+                events,
+                PageOfEvents {
+                    events: vec![expected_event.clone()],
+                    is_last_page: true,
+                    total_pages: None,
+                }
+            );
+        }
+
+        #[test]
+        fn get_events_with_no_filter() {
+            let (storage, emitted_events) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let filter = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                contract_address: None,
+                keys: vec![],
+                data_filter: vec![],
+                page_size: test_utils::NUM_EVENTS,
+                page_number: 0,
+                detect_last_page: true,
+                count_pages: false,
+                only_accepted: false,
+            };
+
+            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            assert_eq!(
+                events,
+                PageOfEvents {
+                    events: emitted_events,
+                    is_last_page: true,
+                    total_pages: None,
+                }
+            );
+        }
+
+        #[test]
+        fn get_events_with_no_filter_and_paging() {
+            let (storage, emitted_events) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let filter = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                contract_address: None,
+                keys: vec![],
+                data_filter: vec![],
+                page_size: 10,
+                page_number: 0,
+                detect_last_page: true,
+                count_pages: false,
+                only_accepted: false,
+            };
+            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            assert_eq!(
+                events,
+                PageOfEvents {
+                    events: emitted_events[..10].to_vec(),
+                    is_last_page: false,
+                    total_pages: None,
+                }
+            );
+
+            let filter = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                contract_address: None,
+                keys: vec![],
+                data_filter: vec![],
+                page_size: 10,
+                page_number: 1,
+                detect_last_page: true,
+                count_pages: false,
+                only_accepted: false,
+            };
+            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            assert_eq!(
+                events,
+                PageOfEvents {
+                    events: emitted_events[10..20].to_vec(),
+                    is_last_page: false,
+                    total_pages: None,
+                }
+            );
+
+            let filter = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                contract_address: None,
+                keys: vec![],
+                data_filter: vec![],
+                page_size: 10,
+                page_number: 3,
+                detect_last_page: true,
+                count_pages: false,
+                only_accepted: false,
+            };
+            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            assert_eq!(
+                events,
+                PageOfEvents {
+                    events: emitted_events[30..40].to_vec(),
+                    is_last_page: true,
+                    total_pages: None,
+                }
+            );
+        }
+
+        #[test]
+        fn get_events_with_no_filter_and_nonexistent_page() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            const PAGE_SIZE: usize = 10;
+            let filter = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                contract_address: None,
+                keys: vec![],
+                data_filter: vec![],
+                page_size: PAGE_SIZE,
+                // one page _after_ the last one
+                page_number: test_utils::NUM_BLOCKS * test_utils::EVENTS_PER_BLOCK / PAGE_SIZE,
+                detect_last_page: true,
+                count_pages: false,
+                only_accepted: false,
+            };
+            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            assert_eq!(
+                events,
+                PageOfEvents {
+                    events: vec![],
+                    is_last_page: true,
+                    total_pages: None,
+                }
+            );
+        }
+
+        #[test]
+        fn count_all() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+            assert_eq!(StarknetEventsTable::count_all(&tx).unwrap(), 0);
+            drop(tx);
+
+            test_utils::insert_test_data(&storage);
+            let tx = connection.transaction().unwrap();
+            assert_eq!(
+                StarknetEventsTable::count_all(&tx).unwrap(),
+                test_utils::NUM_EVENTS as u64
+            );
+        }
+
+        #[test]
+        fn get_events_with_inverted_block_range() {
+            let (storage, events) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let filter = StarknetEventFilter {
+                from_block: Some(StarknetBlockNumber::new_or_panic(2)),
+                to_block: Some(StarknetBlockNumber::new_or_panic(1)),
+                contract_address: None,
+                keys: vec![],
+                data_filter: vec![],
+                page_size: 10,
+                page_number: 0,
+                detect_last_page: true,
+                count_pages: false,
+                only_accepted: false,
+            };
+            let expected = EventFilterError::InvalidBlockRange {
+                from: StarknetBlockNumber::new_or_panic(2),
+                to: StarknetBlockNumber::new_or_panic(1),
+            };
+
+            let error = StarknetEventsTable::get_events(&tx, &filter).unwrap_err();
+            assert_eq!(filter_error(error), expected);
+
+            let error = StarknetEventsTable::event_count(
+                &tx,
+                &filter,
+                StarknetEventsTable::DEFAULT_MAX_KEYS,
+            )
+            .unwrap_err();
+            assert_eq!(filter_error(error), expected);
+
+            // A single bound is never inverted.
+            let from_only = StarknetEventFilter {
+                to_block: None,
+                ..filter
+            };
+            let page = StarknetEventsTable::get_events(&tx, &from_only).unwrap();
+            let from = test_utils::EVENTS_PER_BLOCK * 2;
+            assert_eq!(page.events, events[from..from + 10]);
+        }
+
+        mod blooms {
+            use super::*;
+
+            /// Returns the page read with the event blooms, after checking that it is the same
+            /// as the one read without them.
+            fn get_events_page(tx: &Transaction<'_>, filter: &StarknetEventFilter) -> PageOfEvents {
+                let read = |use_blooms| {
+                    StarknetEventsTable::get_events_page(
+                        tx,
+                        filter,
+                        StarknetEventsTable::DEFAULT_MAX_OFFSET,
+                        StarknetEventsTable::DEFAULT_MAX_KEYS,
+                        use_blooms,
+                    )
+                    .unwrap()
+                };
+
+                let page = read(true);
+                assert_eq!(page, read(false));
+                page
+            }
+
+            fn filter(
+                contract_address: Option<ContractAddress>,
+                keys: Vec<EventKey>,
+            ) -> StarknetEventFilter {
+                StarknetEventFilter {
+                    from_block: None,
+                    to_block: None,
+                    contract_address,
+                    keys,
+                    data_filter: vec![],
+                    page_size: test_utils::NUM_EVENTS,
+                    page_number: 0,
+                    detect_last_page: true,
+                    count_pages: true,
+                    only_accepted: false,
+                }
+            }
+
+            #[test]
+            fn same_results_as_without_blooms() {
+                let (storage, events) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                for event in &events {
+                    let by_key = get_events_page(&tx, &filter(None, vec![event.keys[0]]));
+                    assert_eq!(by_key.events, vec![event.clone()]);
+
+                    let by_address =
+                        get_events_page(&tx, &filter(Some(event.from_address), vec![]));
+                    assert_eq!(by_address.events, vec![event.clone()]);
+                }
+
+                // Both match, but not in the same event.
+                let mismatched = filter(Some(events[0].from_address), vec![events[1].keys[0]]);
+                assert_eq!(get_events_page(&tx, &mismatched).events, vec![]);
+
+                // Matches no block.
+                let unknown = filter(None, vec![EventKey(starkhash!("0bad"))]);
+                let page = get_events_page(&tx, &unknown);
+                assert_eq!(page.events, vec![]);
+                assert_eq!(page.total_pages, Some(0));
+
+                let expected =
+                    &events[test_utils::EVENTS_PER_BLOCK..3 * test_utils::EVENTS_PER_BLOCK];
+                for (page_number, chunk) in expected.chunks(7).enumerate() {
+                    let common = StarknetEventFilter {
+                        from_block: Some(StarknetBlockNumber::new_or_panic(1)),
+                        to_block: Some(StarknetBlockNumber::new_or_panic(2)),
+                        page_size: 7,
+                        page_number,
+                        ..filter(None, vec![EventKey(starkhash!("deadbeef"))])
+                    };
+                    assert_eq!(get_events_page(&tx, &common).events, chunk);
+                }
+            }
+
+            #[test]
+            fn candidates_are_capped() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let candidates = |max_candidates| {
+                    EventBloomsTable::candidate_blocks(
+                        &tx,
+                        None,
+                        None,
+                        None,
+                        &[EventKey(starkhash!("deadbeef"))],
+                        max_candidates,
+                    )
+                    .unwrap()
+                };
+
+                let all = (0..test_utils::NUM_BLOCKS as u64)
+                    .map(StarknetBlockNumber::new_or_panic)
+                    .collect::<Vec<_>>();
+                assert_eq!(candidates(test_utils::NUM_BLOCKS), Some(all));
+                assert_eq!(candidates(test_utils::NUM_BLOCKS - 1), None);
+            }
+
+            #[test]
+            fn candidate_scan_stops_at_cap() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                // Reading the last block's bloom fails, so it must not be read once the cap is
+                // exceeded.
+                let last = StarknetBlockNumber::new_or_panic(test_utils::NUM_BLOCKS as u64 - 1);
+                tx.execute(
+                    "UPDATE starknet_event_blooms SET bloom = x'00' WHERE block_number = ?",
+                    [last],
+                )
+                .unwrap();
+
+                let candidates = |max_candidates| {
+                    EventBloomsTable::candidate_blocks(
+                        &tx,
+                        None,
+                        None,
+                        None,
+                        &[EventKey(starkhash!("deadbeef"))],
+                        max_candidates,
+                    )
+                };
+
+                assert_matches::assert_matches!(
+                    candidates(test_utils::NUM_BLOCKS),
+                    Err(StorageError::Corruption { .. })
+                );
+                assert_eq!(candidates(test_utils::NUM_BLOCKS - 3).unwrap(), None);
+            }
+
+            #[test]
+            fn blocks_before_start_are_not_filtered() {
+                let (storage, events) = test_utils::setup_test_storage();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                // As if the events of the first two blocks were stored before the blooms.
+                tx.execute(
+                    "UPDATE starknet_event_blooms_start SET block_number = 2",
+                    [],
+                )
+                .unwrap();
+
+                let candidates = |from_block| {
+                    EventBloomsTable::candidate_blocks(
+                        &tx,
+                        from_block,
+                        None,
+                        Some(&events[0].from_address),
+                        &[],
+                        StarknetEventsTable::MAX_BLOOM_CANDIDATES,
+                    )
+                    .unwrap()
+                };
+
+                assert_eq!(candidates(None), None);
+                assert_eq!(candidates(Some(StarknetBlockNumber::new_or_panic(1))), None);
+                assert_eq!(
+                    candidates(Some(StarknetBlockNumber::new_or_panic(2))),
+                    Some(vec![])
+                );
+
+                // The first block's events are still found.
+                let page = get_events_page(&tx, &filter(Some(events[0].from_address), vec![]));
+                assert_eq!(page.events, vec![events[0].clone()]);
+            }
+
+            #[test]
+            fn get_events_after_starts_at_cursor() {
+                let (storage, events) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let filter = filter(None, vec![EventKey(starkhash!("deadbeef"))]);
+                let cursor_of = |event: &StarknetEmittedEvent| {
+                    StarknetEventsTable::get_events_after(
+                        &tx,
+                        &filter,
+                        None,
+                        StarknetEventsTable::PAGE_SIZE_LIMIT,
+                    )
+                    .unwrap()
+                    .into_iter()
+                    .find(|(_, candidate)| candidate == event)
+                    .map(|(cursor, _)| cursor)
+                    .unwrap()
+                };
+
+                let cursor = cursor_of(&events[test_utils::EVENTS_PER_BLOCK + 1]);
+                let rest = StarknetEventsTable::get_events_after(
+                    &tx,
+                    &filter,
+                    Some(cursor),
+                    StarknetEventsTable::PAGE_SIZE_LIMIT,
+                )
+                .unwrap()
+                .into_iter()
+                .map(|(_, event)| event)
+                .collect::<Vec<_>>();
+                assert_eq!(rest, events[test_utils::EVENTS_PER_BLOCK + 2..]);
+            }
+
+            /// A synthetic benchmark, with a contract emitting events in half of the blocks and
+            /// a key emitted in the other half. Only the block in the middle has events matching
+            /// both.
+            #[test]
+            fn fewer_rows_examined() {
+                const BLOCKS: u8 = 200;
+                const EVENTS_PER_BLOCK: u64 = 50;
+
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let contract = ContractAddress::new_or_panic(starkhash!("0c0ffee0"));
+                let key = EventKey(starkhash!("deadbeef"));
+                let (declare, receipt) = &test_utils::create_transactions_and_receipts()[10];
+                for n in 0..BLOCKS {
+                    let block = StarknetBlock::nth(n);
+                    StarknetBlocksTable::insert(&tx, &block, None).unwrap();
+                    CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
+
+                    let hash = StarknetTransactionHash(StarkHash::from_u64(n as u64 + 1));
+                    let mut declare = declare.clone();
+                    match &mut declare {
+                        transaction::Transaction::Declare(declare) => {
+                            declare.transaction_hash = hash
+                        }
+                        _ => unreachable!("The fixture's 10th transaction is a declare"),
+                    }
+                    let events = (0..EVENTS_PER_BLOCK)
+                        .map(|i| {
+                            let other = StarkHash::from_u64(u64::from(n) * EVENTS_PER_BLOCK + i);
+                            transaction::Event {
+                                from_address: if n >= BLOCKS / 2 {
+                                    contract
+                                } else {
+                                    ContractAddress::new_or_panic(other)
+                                },
+                                data: vec![],
+                                keys: vec![if n <= BLOCKS / 2 {
+                                    key
+                                } else {
+                                    EventKey(other)
+                                }],
+                            }
+                        })
+                        .collect();
+                    let receipt = transaction::Receipt {
+                        transaction_hash: hash,
+                        events,
+                        ..receipt.clone()
+                    };
+                    StarknetTransactionsTable::upsert(
+                        &tx,
+                        block.hash,
+                        block.number,
+                        &[(declare, receipt)],
+                    )
+                    .unwrap();
+                }
+
+                let steps = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
+                let counter = steps.clone();
+                tx.progress_handler(
+                    1,
+                    Some(move || {
+                        counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        false
+                    }),
+                );
+                let steps_taken = |use_blooms| {
+                    steps.store(0, std::sync::atomic::Ordering::Relaxed);
+                    let page = StarknetEventsTable::get_events_page(
+                        &tx,
+                        &StarknetEventFilter {
+                            page_size: EVENTS_PER_BLOCK as usize,
+                            count_pages: false,
+                            ..filter(Some(contract), vec![key])
+                        },
+                        StarknetEventsTable::DEFAULT_MAX_OFFSET,
+                        StarknetEventsTable::DEFAULT_MAX_KEYS,
+                        use_blooms,
+                    )
+                    .unwrap();
+                    assert_eq!(page.events.len(), EVENTS_PER_BLOCK as usize);
+                    steps.load(std::sync::atomic::Ordering::Relaxed)
+                };
+
+                let with_blooms = steps_taken(true);
+                let without_blooms = steps_taken(false);
+                assert!(
+                    with_blooms * 2 < without_blooms,
+                    "{with_blooms} steps with blooms, {without_blooms} without"
+                );
+            }
+        }
+
+        mod reorg {
+            use super::*;
+
+            /// The number of `starknet_events_keys` rows matching `key`.
+            fn indexed_key_count(tx: &Transaction<'_>, key: &EventKey) -> usize {
+                let mut fts_key = String::new();
+                StarknetEventsTable::encode_event_key_to_base64(key, &mut fts_key);
+                tx.query_row(
+                    "SELECT COUNT(1) FROM starknet_events_keys WHERE keys MATCH ?",
+                    [format!("\"{fts_key}\"")],
+                    |row| row.get(0),
+                )
+                .unwrap()
+            }
+
+            #[test]
+            fn removes_events_and_index() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let keys = (0..3u8)
+                    .map(|n| {
+                        let block = StarknetBlock::nth(n);
+                        StarknetBlocksTable::insert(&tx, &block, None).unwrap();
+                        CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
+
+                        let key = EventKey(StarkHash::from_u64(0xbeef + n as u64));
+                        let event = transaction::Event {
+                            from_address: ContractAddress::new_or_panic(starkhash!("0123")),
+                            data: vec![],
+                            keys: vec![key],
+                        };
+                        StarknetEventsTable::insert_events(
+                            &tx,
+                            block.number,
+                            StarknetTransactionHash(StarkHash::from_u64(n as u64)),
+                            &[event],
+                        )
+                        .unwrap();
+                        key
+                    })
+                    .collect::<Vec<_>>();
+
+                StarknetEventsTable::reorg(&tx, StarknetBlockNumber::new_or_panic(1)).unwrap();
+
+                let remaining: Vec<StarknetBlockNumber> = tx
+                    .prepare("SELECT block_number FROM starknet_events")
+                    .unwrap()
+                    .query_map([], |row| row.get(0))
+                    .unwrap()
+                    .collect::<Result<_, _>>()
+                    .unwrap();
+                assert_eq!(remaining, vec![StarknetBlockNumber::GENESIS]);
+
+                assert_eq!(indexed_key_count(&tx, &keys[0]), 1);
+                assert_eq!(indexed_key_count(&tx, &keys[1]), 0);
+                assert_eq!(indexed_key_count(&tx, &keys[2]), 0);
+
+                let blooms: usize = tx
+                    .query_row("SELECT COUNT(1) FROM starknet_event_blooms", [], |row| {
+                        row.get(0)
+                    })
+                    .unwrap();
+                assert_eq!(blooms, 1);
+            }
+        }
+
+        mod get_by_selector {
+            use super::*;
+
+            const TRANSFER: EventKey = EventKey(starkhash!(
+                "99cd8bde557814842a3121e8ddfd433a539b8c9f14bf31ebf108d12e6196e9"
+            ));
+            const APPROVAL: EventKey = EventKey(starkhash!(
+                "0134692b230b9e1ffa39098904722134159652b09c5bc41d88d6698779d228ff"
+            ));
+
+            /// Sets up the default test storage, with the events of the first transactions
+            /// replaced by `Transfer` and `Approval` events.
+            ///
+            /// The `Approval` event has the `Transfer` selector as its second key, so it matches
+            /// the `Transfer` selector in any position but not as the first key.
+            fn setup() -> (Storage, Vec<StarknetEmittedEvent>) {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let blocks = test_utils::create_blocks();
+                let mut transactions_and_receipts = test_utils::create_transactions_and_receipts();
+
+                let selectors = [
+                    vec![TRANSFER, EventKey(starkhash!("01"))],
+                    vec![APPROVAL, TRANSFER],
+                    vec![TRANSFER],
+                ];
+                // One event in each of the first two blocks, and a third in the second block.
+                let indices = [
+                    0,
+                    test_utils::TRANSACTIONS_PER_BLOCK,
+                    test_utils::TRANSACTIONS_PER_BLOCK + 1,
+                ];
+                for (i, keys) in indices.into_iter().zip(selectors) {
+                    transactions_and_receipts[i].1.events[0].keys = keys;
+                }
+
+                for (i, block) in blocks.iter().enumerate() {
+                    StarknetBlocksTable::insert(&tx, block, None).unwrap();
+                    CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
+                    StarknetTransactionsTable::upsert(
+                        &tx,
+                        block.hash,
+                        block.number,
+                        &transactions_and_receipts[i * test_utils::TRANSACTIONS_PER_BLOCK
+                            ..(i + 1) * test_utils::TRANSACTIONS_PER_BLOCK],
+                    )
+                    .unwrap();
+                }
+                tx.commit().unwrap();
+
+                let events = test_utils::extract_events(&blocks, &transactions_and_receipts);
+                let events = indices
+                    .into_iter()
+                    .map(|i| {
+                        let hash = transactions_and_receipts[i].0.hash();
+                        events
+                            .iter()
+                            .find(|e| e.transaction_hash == hash)
+                            .unwrap()
+                            .clone()
+                    })
+                    .collect();
+
+                (storage, events)
+            }
+
+            #[test]
+            fn transfer_and_approval() {
+                let (storage, events) = setup();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let transfers =
+                    StarknetEventsTable::get_by_selector(&tx, TRANSFER, None, None, 10, 0).unwrap();
+                assert_eq!(
+                    transfers,
+                    PageOfEvents {
+                        events: vec![events[0].clone(), events[2].clone()],
+                        is_last_page: true,
+                        total_pages: None,
+                    }
+                );
+
+                let approvals =
+                    StarknetEventsTable::get_by_selector(&tx, APPROVAL, None, None, 10, 0).unwrap();
+                assert_eq!(
+                    approvals,
+                    PageOfEvents {
+                        events: vec![events[1].clone()],
+                        is_last_page: true,
+                        total_pages: None,
+                    }
+                );
+
+                // The key filter matches the selector in any position, including the approval.
+                let any_position = StarknetEventsTable::get_events(
+                    &tx,
+                    &StarknetEventFilter {
+                        from_block: None,
+                        to_block: None,
+                        contract_address: None,
+                        keys: vec![TRANSFER],
+                        data_filter: vec![],
+                        page_size: 10,
+                        page_number: 0,
+                        detect_last_page: true,
+                        count_pages: false,
+                        only_accepted: false,
+                    },
+                )
+                .unwrap();
+                assert_eq!(any_position.events, events);
+            }
+
+            #[test]
+            fn block_range_and_paging() {
+                let (storage, events) = setup();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let block = Some(StarknetBlockNumber::new_or_panic(1));
+                let page = StarknetEventsTable::get_by_selector(&tx, TRANSFER, block, block, 10, 0)
+                    .unwrap();
+                assert_eq!(page.events, vec![events[2].clone()]);
+
+                let page =
+                    StarknetEventsTable::get_by_selector(&tx, TRANSFER, None, None, 1, 0).unwrap();
+                assert_eq!(
+                    page,
+                    PageOfEvents {
+                        events: vec![events[0].clone()],
+                        is_last_page: false,
+                        total_pages: None,
+                    }
+                );
+
+                let page =
+                    StarknetEventsTable::get_by_selector(&tx, TRANSFER, None, None, 1, 1).unwrap();
+                assert_eq!(
+                    page,
+                    PageOfEvents {
+                        events: vec![events[2].clone()],
+                        is_last_page: true,
+                        total_pages: None,
+                    }
+                );
+            }
+        }
+
+        mod get_events_with_max_offset {
+            use super::*;
+
+            fn filter(page_size: usize, page_number: usize) -> StarknetEventFilter {
+                StarknetEventFilter {
+                    from_block: None,
+                    to_block: None,
+                    contract_address: None,
+                    keys: vec![],
+                    data_filter: vec![],
+                    page_size,
+                    page_number,
+                    detect_last_page: true,
+                    count_pages: false,
+                    only_accepted: false,
+                }
+            }
+
+            #[test]
+            fn overflow() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let result = StarknetEventsTable::get_events(&tx, &filter(10, usize::MAX));
+                assert_eq!(
+                    filter_error(result.unwrap_err()),
+                    EventFilterError::PageNumberTooBig {
+                        limit: StarknetEventsTable::DEFAULT_MAX_OFFSET / 10
+                    }
+                );
+            }
+
+            #[test]
+            fn configurable_cap() {
+                let (storage, emitted_events) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                // The page starting exactly at the cap is still served.
+                let events =
+                    StarknetEventsTable::get_events_with_max_offset(&tx, &filter(10, 3), 30)
+                        .unwrap();
+                assert_eq!(events.events, emitted_events[30..40].to_vec());
+
+                let result =
+                    StarknetEventsTable::get_events_with_max_offset(&tx, &filter(10, 4), 30);
+                assert_eq!(
+                    filter_error(result.unwrap_err()),
+                    EventFilterError::PageNumberTooBig { limit: 3 }
+                );
+            }
+        }
+
+        #[test]
+        fn get_events_with_total_pages() {
+            let (storage, emitted_events) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let filter = |page_size| StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                contract_address: None,
+                keys: vec![],
+                data_filter: vec![],
+                page_size,
+                page_number: 0,
+                detect_last_page: true,
+                count_pages: true,
+                only_accepted: false,
+            };
+
+            for (page_size, expected) in [(1, 40), (3, 14), (10, 4), (39, 2), (40, 1), (41, 1)] {
+                let events = StarknetEventsTable::get_events(&tx, &filter(page_size)).unwrap();
+                assert_eq!(events.total_pages, Some(expected), "page size {page_size}");
+            }
+
+            // The count applies the filter, not just the page.
+            let filter = StarknetEventFilter {
+                contract_address: Some(emitted_events[1].from_address),
+                ..filter(10)
+            };
+            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            assert_eq!(events.total_pages, Some(1));
+
+            // Block range without events.
+            let filter = StarknetEventFilter {
+                from_block: Some(StarknetBlockNumber::new_or_panic(
+                    test_utils::NUM_BLOCKS as u64,
+                )),
+                to_block: None,
+                ..filter
+            };
+            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            assert_eq!(events.total_pages, Some(0));
+        }
+
+        #[test]
+        fn get_events_without_last_page_detection() {
+            let (storage, emitted_events) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let filter = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                contract_address: None,
+                keys: vec![],
+                data_filter: vec![],
+                page_size: 10,
+                page_number: 0,
+                detect_last_page: false,
+                count_pages: false,
+                only_accepted: false,
+            };
+            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            assert_eq!(
+                events,
+                PageOfEvents {
+                    events: emitted_events[..10].to_vec(),
+                    is_last_page: false,
+                    total_pages: None,
+                }
+            );
+
+            // Even the actual last page is not detected.
+            let filter = StarknetEventFilter {
+                page_size: test_utils::NUM_EVENTS,
+                ..filter
+            };
+            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            assert_eq!(
+                events,
+                PageOfEvents {
+                    events: emitted_events,
+                    is_last_page: false,
+                    total_pages: None,
+                }
+            );
+        }
+
+        #[test]
+        fn get_events_with_invalid_page_size() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let filter = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                contract_address: None,
+                keys: vec![],
+                data_filter: vec![],
+                page_size: 0,
+                page_number: 0,
+                detect_last_page: true,
+                count_pages: false,
+                only_accepted: false,
+            };
+            let result = StarknetEventsTable::get_events(&tx, &filter);
+            assert!(result.is_err());
+            assert_eq!(result.unwrap_err().to_string(), "Invalid page size");
+
+            let filter = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                contract_address: None,
+                keys: vec![],
+                data_filter: vec![],
+                page_size: StarknetEventsTable::PAGE_SIZE_LIMIT + 1,
+                page_number: 0,
+                detect_last_page: true,
+                count_pages: false,
+                only_accepted: false,
+            };
+            let result = StarknetEventsTable::get_events(&tx, &filter);
+            assert!(result.is_err());
+            assert_eq!(
+                filter_error(result.unwrap_err()),
+                EventFilterError::PageSizeTooBig(StarknetEventsTable::PAGE_SIZE_LIMIT)
+            );
+        }
+
+        mod get_events_with_timeout {
+            use super::*;
+
+            use std::time::Duration;
+
+            /// Attaches a large number of events to a declare transaction (which has no events
+            /// of its own) so that scanning them takes a noticeable amount of work.
+            fn setup_large_storage() -> Storage {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let (declare, _) = &test_utils::create_transactions_and_receipts()[10];
+                let events = (0..20_000u32)
+                    .map(|i| transaction::Event {
+                        from_address: ContractAddress::new_or_panic(starkhash!("0123")),
+                        data: vec![EventData(StarkHash::from_u64(i as u64))],
+                        keys: vec![EventKey(StarkHash::from_u64(i as u64))],
+                    })
+                    .collect::<Vec<_>>();
+                let genesis = &test_utils::create_blocks()[0];
+                StarknetEventsTable::insert_events(&tx, genesis.number, declare.hash(), &events)
+                    .unwrap();
+
+                tx.commit().unwrap();
+                storage
+            }
+
+            fn last_page_filter() -> StarknetEventFilter {
+                StarknetEventFilter {
+                    from_block: None,
+                    to_block: None,
+                    contract_address: None,
+                    keys: vec![],
+                    data_filter: vec![],
+                    page_size: StarknetEventsTable::PAGE_SIZE_LIMIT,
+                    page_number: 20_000 / StarknetEventsTable::PAGE_SIZE_LIMIT,
+                    detect_last_page: true,
+                    count_pages: false,
+                    only_accepted: false,
+                }
+            }
+
+            #[test]
+            fn interrupted() {
+                let storage = setup_large_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let result = StarknetEventsTable::get_events_with_timeout(
+                    &tx,
+                    &last_page_filter(),
+                    Duration::from_nanos(1),
+                );
+                assert_eq!(
+                    filter_error(result.unwrap_err()),
+                    EventFilterError::QueryTimeout
+                );
+
+                // The timeout must not leak into subsequent queries.
+                StarknetEventsTable::get_events(&tx, &last_page_filter()).unwrap();
+            }
+
+            #[test]
+            fn within_limit() {
+                let storage = setup_large_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let expected = StarknetEventsTable::get_events(&tx, &last_page_filter()).unwrap();
+                let page = StarknetEventsTable::get_events_with_timeout(
+                    &tx,
+                    &last_page_filter(),
+                    Duration::from_secs(60),
+                )
+                .unwrap();
+                assert_eq!(page, expected);
+            }
+        }
+
+        #[test]
+        fn get_events_by_key_with_paging() {
+            let (storage, emitted_events) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let expected_events = &emitted_events[27..32];
+            let keys_for_expected_events: Vec<_> =
+                expected_events.iter().map(|e| e.keys[0]).collect();
+
+            let filter = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                contract_address: None,
+                keys: keys_for_expected_events.clone(),
+                data_filter: vec![],
+                page_size: 2,
+                page_number: 0,
+                detect_last_page: true,
+                count_pages: false,
+                only_accepted: false,
+            };
+            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            assert_eq!(
+                events,
+                PageOfEvents {
+                    events: expected_events[..2].to_vec(),
+                    is_last_page: false,
+                    total_pages: None,
+                }
+            );
+
+            let filter = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                contract_address: None,
+                keys: keys_for_expected_events.clone(),
+                data_filter: vec![],
+                page_size: 2,
+                page_number: 1,
+                detect_last_page: true,
+                count_pages: false,
+                only_accepted: false,
+            };
+            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            assert_eq!(
+                events,
+                PageOfEvents {
+                    events: expected_events[2..4].to_vec(),
+                    is_last_page: false,
+                    total_pages: None,
+                }
+            );
+
+            let filter = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                contract_address: None,
+                keys: keys_for_expected_events,
+                data_filter: vec![],
+                page_size: 2,
+                page_number: 2,
+                detect_last_page: true,
+                count_pages: false,
+                only_accepted: false,
+            };
+            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            assert_eq!(
+                events,
+                PageOfEvents {
+                    events: expected_events[4..].to_vec(),
+                    is_last_page: true,
+                    total_pages: None,
+                }
+            );
+        }
+
+        #[test]
+        fn event_count_by_block() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let block = Some(StarknetBlockNumber::new_or_panic(2));
+
+            let count = StarknetEventsTable::event_count(
+                &tx,
+                &StarknetEventFilter {
+                    from_block: block,
+                    to_block: block,
+                    contract_address: None,
+                    keys: vec![],
+                    data_filter: vec![],
+                    page_size: 0,
+                    page_number: 0,
+                    detect_last_page: false,
+                    count_pages: false,
+                    only_accepted: false,
+                },
+                StarknetEventsTable::DEFAULT_MAX_KEYS,
+            )
+            .unwrap();
+            assert_eq!(count, test_utils::EVENTS_PER_BLOCK);
+        }
+
+        #[test]
+        fn event_count_from_contract() {
+            let (storage, events) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let addr = events[0].from_address;
+            let expected = events
+                .iter()
+                .filter(|event| event.from_address == addr)
+                .count();
+
+            let count = StarknetEventsTable::event_count(
+                &tx,
+                &StarknetEventFilter {
+                    from_block: Some(StarknetBlockNumber::GENESIS),
+                    to_block: Some(StarknetBlockNumber::MAX),
+                    contract_address: Some(addr),
+                    keys: vec![],
+                    data_filter: vec![],
+                    page_size: 0,
+                    page_number: 0,
+                    detect_last_page: false,
+                    count_pages: false,
+                    only_accepted: false,
+                },
+                StarknetEventsTable::DEFAULT_MAX_KEYS,
+            )
+            .unwrap();
+            assert_eq!(count, expected);
+        }
+
+        #[test]
+        fn event_count_by_key() {
+            let (storage, emitted_events) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let key = emitted_events[27].keys[0];
+            let expected = emitted_events
+                .iter()
+                .filter(|event| event.keys.contains(&key))
+                .count();
+
+            let count = StarknetEventsTable::event_count(
+                &tx,
+                &StarknetEventFilter {
+                    from_block: Some(StarknetBlockNumber::GENESIS),
+                    to_block: Some(StarknetBlockNumber::MAX),
+                    contract_address: None,
+                    keys: vec![key],
+                    data_filter: vec![],
+                    page_size: 0,
+                    page_number: 0,
+                    detect_last_page: false,
+                    count_pages: false,
+                    only_accepted: false,
+                },
+                StarknetEventsTable::DEFAULT_MAX_KEYS,
+            )
+            .unwrap();
+            assert_eq!(count, expected);
+        }
+
+        #[test]
+        fn event_count_by_block_sums_to_event_count() {
+            let (storage, emitted_events) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let key = emitted_events[27].keys[0];
+            let from_block = Some(StarknetBlockNumber::new_or_panic(1));
+            let to_block = Some(StarknetBlockNumber::MAX);
+
+            for keys in [vec![], vec![key]] {
+                let buckets = StarknetEventsTable::event_count_by_block(
+                    &tx,
+                    from_block,
+                    to_block,
+                    None,
+                    keys.clone(),
+                )
+                .unwrap();
+                let total = StarknetEventsTable::event_count(
+                    &tx,
+                    &StarknetEventFilter {
+                        from_block,
+                        to_block,
+                        contract_address: None,
+                        keys: keys.clone(),
+                        data_filter: vec![],
+                        page_size: 0,
+                        page_number: 0,
+                        detect_last_page: false,
+                        count_pages: false,
+                        only_accepted: false,
+                    },
+                    StarknetEventsTable::DEFAULT_MAX_KEYS,
+                )
+                .unwrap();
+
+                assert!(buckets.iter().all(|(_, count)| *count > 0));
+                assert!(buckets.windows(2).all(|pair| pair[0].0 < pair[1].0));
+                assert_eq!(buckets.iter().map(|(_, count)| count).sum::<usize>(), total);
+            }
+
+            let buckets =
+                StarknetEventsTable::event_count_by_block(&tx, None, None, None, vec![]).unwrap();
+            let expected = (0..test_utils::NUM_BLOCKS as u64)
+                .map(|n| {
+                    (
+                        StarknetBlockNumber::new_or_panic(n),
+                        test_utils::EVENTS_PER_BLOCK,
+                    )
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(buckets, expected);
+
+            let buckets =
+                StarknetEventsTable::event_count_by_block(&tx, None, None, None, vec![key])
+                    .unwrap();
+            let mut expected = Vec::<(StarknetBlockNumber, usize)>::new();
+            for event in emitted_events.iter().filter(|e| e.keys.contains(&key)) {
+                match expected.last_mut() {
+                    Some((block, count)) if *block == event.block_number => *count += 1,
+                    _ => expected.push((event.block_number, 1)),
+                }
+            }
+            assert_eq!(buckets, expected);
+        }
+
+        mod stats {
+            use super::*;
+
+            #[test]
+            fn totals_match_event_count() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let ranges = [
+                    (None, None, test_utils::NUM_BLOCKS),
+                    (
+                        Some(StarknetBlockNumber::new_or_panic(1)),
+                        Some(StarknetBlockNumber::new_or_panic(2)),
+                        2,
+                    ),
+                    (Some(StarknetBlockNumber::new_or_panic(3)), None, 1),
+                ];
+                for (from_block, to_block, blocks_with_events) in ranges {
+                    let stats = StarknetEventsTable::stats(
+                        &tx,
+                        from_block,
+                        to_block,
+                        test_utils::NUM_EVENTS,
+                    )
+                    .unwrap();
+
+                    let count = StarknetEventsTable::event_count(
+                        &tx,
+                        &StarknetEventFilter {
+                            from_block,
+                            to_block,
+                            contract_address: None,
+                            keys: vec![],
+                            data_filter: vec![],
+                            page_size: 0,
+                            page_number: 0,
+                            detect_last_page: false,
+                            count_pages: false,
+                            only_accepted: false,
+                        },
+                        StarknetEventsTable::DEFAULT_MAX_KEYS,
+                    )
+                    .unwrap();
+                    assert_eq!(stats.total, count);
+                    assert_eq!(
+                        stats
+                            .by_contract
+                            .iter()
+                            .map(|(_, count)| count)
+                            .sum::<usize>(),
+                        stats.total
+                    );
+                    assert_eq!(stats.blocks_with_events, blocks_with_events);
+                }
+            }
+
+            #[test]
+            fn top_contracts() {
+                let (storage, emitted_events) = test_utils::setup_test_storage();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                // Emit two more events from one contract, and one more from another.
+                let noisiest = &emitted_events[13];
+                let runner_up = &emitted_events[0];
+                for (emitted, extra) in [(noisiest, 2), (runner_up, 1)] {
+                    let event = transaction::Event {
+                        data: emitted.data.clone(),
+                        from_address: emitted.from_address,
+                        keys: emitted.keys.clone(),
+                    };
+                    StarknetEventsTable::insert_events(
+                        &tx,
+                        emitted.block_number,
+                        emitted.transaction_hash,
+                        &vec![event; extra],
+                    )
+                    .unwrap();
+                }
+
+                let stats = StarknetEventsTable::stats(&tx, None, None, 2).unwrap();
+                assert_eq!(
+                    stats.by_contract,
+                    vec![(noisiest.from_address, 3), (runner_up.from_address, 2)]
+                );
+                assert_eq!(stats.total, test_utils::NUM_EVENTS + 3);
+                assert_eq!(stats.blocks_with_events, test_utils::NUM_BLOCKS);
+            }
+        }
+
+        mod blocks_for_contract {
+            use super::*;
+
+            #[test]
+            fn some() {
+                let (storage, emitted_events) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let address = emitted_events[13].from_address;
+                let mut expected = emitted_events
+                    .iter()
+                    .filter(|event| event.from_address == address)
+                    .map(|event| event.block_number)
+                    .collect::<Vec<_>>();
+                expected.dedup();
+
+                let blocks = StarknetEventsTable::blocks_for_contract(&tx, address).unwrap();
+                assert_eq!(blocks, expected);
+            }
+
+            #[test]
+            fn multiple_blocks() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let blocks = test_utils::create_blocks();
+                let address = ContractAddress::new_or_panic(starkhash!("1234"));
+                let event = transaction::Event {
+                    from_address: address,
+                    data: vec![],
+                    keys: vec![],
+                };
+                // The contract emits events in blocks 0 and 2, twice in the latter.
+                let activity = [(0, vec![event.clone()]), (2, vec![event.clone(), event])];
+
+                for block in &blocks {
+                    StarknetBlocksTable::insert(&tx, block, None).unwrap();
+                    CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
+                }
+                for (i, (block_idx, events)) in activity.iter().enumerate() {
+                    StarknetEventsTable::insert_events(
+                        &tx,
+                        blocks[*block_idx].number,
+                        StarknetTransactionHash(StarkHash::from_u128(i as u128)),
+                        events,
+                    )
+                    .unwrap();
+                }
+
+                let result = StarknetEventsTable::blocks_for_contract(&tx, address).unwrap();
+                assert_eq!(result, vec![blocks[0].number, blocks[2].number]);
+            }
+
+            #[test]
+            fn none() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let address = ContractAddress::new_or_panic(starkhash!("0badbeef"));
+                let blocks = StarknetEventsTable::blocks_for_contract(&tx, address).unwrap();
+                assert!(blocks.is_empty());
+            }
+        }
+
+        mod first_matching {
+            use super::*;
+
+            #[test]
+            fn returns_earliest_match() {
+                let (storage, emitted_events) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let expected_event = &emitted_events[test_utils::EVENTS_PER_BLOCK];
+                let filter = StarknetEventFilter {
+                    from_block: Some(expected_event.block_number),
+                    to_block: None,
+                    contract_address: None,
+                    keys: vec![],
+                    data_filter: vec![],
+                    page_size: 1,
+                    page_number: 0,
+                    detect_last_page: true,
+                    count_pages: false,
+                    only_accepted: false,
+                };
+
+                let event = StarknetEventsTable::first_matching(&tx, &filter).unwrap();
+                assert_eq!(event.as_ref(), Some(expected_event));
+            }
+
+            #[test]
+            fn none() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let past_head = StarknetBlockNumber::GENESIS + test_utils::NUM_BLOCKS as u64;
+                let filter = StarknetEventFilter {
+                    from_block: Some(past_head),
+                    to_block: None,
+                    contract_address: None,
+                    keys: vec![],
+                    data_filter: vec![],
+                    page_size: 1,
+                    page_number: 0,
+                    detect_last_page: true,
+                    count_pages: false,
+                    only_accepted: false,
+                };
+
+                let event = StarknetEventsTable::first_matching(&tx, &filter).unwrap();
+                assert_eq!(event, None);
+            }
+        }
+
+        mod data_filter {
+            use super::*;
+
+            fn data(values: &[u8]) -> Vec<EventData> {
+                values
+                    .iter()
+                    .map(|v| EventData(StarkHash::from_be_slice(&[*v]).unwrap()))
+                    .collect()
+            }
+
+            /// Stores a block with a single transaction emitting transfer-like events with the
+            /// given data from two contracts, and returns the emitted events.
+            fn setup(storage: &Storage) -> Vec<StarknetEmittedEvent> {
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let block = test_utils::create_blocks()[0].clone();
+                let (transaction, mut receipt) =
+                    test_utils::create_transactions_and_receipts()[0].clone();
+                receipt.events = [
+                    ("01", data(&[1, 2, 3])),
+                    ("01", data(&[1, 5, 3])),
+                    ("02", data(&[4, 2, 3])),
+                    ("01", data(&[1])),
+                ]
+                .into_iter()
+                .map(|(address, data)| transaction::Event {
+                    from_address: ContractAddress::new_or_panic(
+                        StarkHash::from_hex_str(address).unwrap(),
+                    ),
+                    data,
+                    keys: vec![EventKey(starkhash!("deadbeef"))],
+                })
+                .collect();
+
+                StarknetBlocksTable::insert(&tx, &block, None).unwrap();
+                CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
+                StarknetTransactionsTable::upsert(
+                    &tx,
+                    block.hash,
+                    block.number,
+                    &[(transaction.clone(), receipt.clone())],
+                )
+                .unwrap();
+                tx.commit().unwrap();
+
+                receipt
+                    .events
+                    .into_iter()
+                    .map(|event| StarknetEmittedEvent {
+                        from_address: event.from_address,
+                        data: event.data,
+                        keys: event.keys,
+                        block_hash: block.hash,
+                        block_number: block.number,
+                        transaction_hash: transaction.hash(),
+                    })
+                    .collect()
+            }
+
+            fn filter(data_filter: Vec<(usize, EventData)>) -> StarknetEventFilter {
+                StarknetEventFilter {
+                    from_block: None,
+                    to_block: None,
+                    contract_address: None,
+                    keys: vec![],
+                    data_filter,
+                    page_size: 10,
+                    page_number: 0,
+                    detect_last_page: true,
+                    count_pages: false,
+                    only_accepted: false,
+                }
+            }
+
+            fn matching(filter: &StarknetEventFilter) -> Vec<StarknetEmittedEvent> {
+                let storage = Storage::in_memory().unwrap();
+                setup(&storage);
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                StarknetEventsTable::get_events(&tx, filter).unwrap().events
+            }
+
+            #[test]
+            fn position_0() {
+                let storage = Storage::in_memory().unwrap();
+                let events = setup(&storage);
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let filter = filter(vec![(0, data(&[1])[0])]);
+                let result = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+                assert_eq!(
+                    result.events,
+                    vec![events[0].clone(), events[1].clone(), events[3].clone()]
+                );
+            }
+
+            #[test]
+            fn position_2() {
+                let storage = Storage::in_memory().unwrap();
+                let events = setup(&storage);
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let filter = filter(vec![(2, data(&[3])[0])]);
+                let result = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+                assert_eq!(result.events, events[..3].to_vec());
+            }
+
+            #[test]
+            fn legacy_data() {
+                let storage = Storage::in_memory().unwrap();
+                let events = setup(&storage);
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                // Mix legacy data without the header with data in the current encoding.
+                tx.execute(
+                    "UPDATE starknet_events SET data = substr(data, 6) WHERE idx % 2 = 0",
+                    [],
+                )
+                .unwrap();
+
+                let filter = filter(vec![(2, data(&[3])[0])]);
+                let result = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+                assert_eq!(result.events, events[..3].to_vec());
+            }
+
+            #[test]
+            fn wrong_value() {
+                let events = matching(&filter(vec![(1, data(&[7])[0])]));
+                assert!(events.is_empty());
+            }
+
+            #[test]
+            fn shorter_data_never_matches() {
+                // The last event only has a single data element; even a zero value must not
+                // match the missing elements.
+                let events = matching(&filter(vec![(1, EventData(StarkHash::ZERO))]));
+                assert!(events.is_empty());
+
+                let events = matching(&filter(vec![(3, EventData(StarkHash::ZERO))]));
+                assert!(events.is_empty());
+            }
+
+            #[test]
+            fn composes_with_other_filters() {
+                let storage = Storage::in_memory().unwrap();
+                let events = setup(&storage);
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let data_filter = vec![(1, data(&[2])[0]), (2, data(&[3])[0])];
+                let address = events[0].from_address;
+                let filter = StarknetEventFilter {
+                    from_block: Some(StarknetBlockNumber::GENESIS),
+                    to_block: Some(StarknetBlockNumber::GENESIS),
+                    contract_address: Some(address),
+                    keys: vec![EventKey(starkhash!("deadbeef"))],
+                    data_filter: data_filter.clone(),
+                    page_size: 1,
+                    page_number: 0,
+                    detect_last_page: true,
+                    count_pages: false,
+                    only_accepted: false,
+                };
+
+                let result = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+                assert_eq!(
+                    result,
+                    PageOfEvents {
+                        events: vec![events[0].clone()],
+                        is_last_page: true,
+                        total_pages: None,
+                    }
+                );
+
+                let count = StarknetEventsTable::event_count(
+                    &tx,
+                    &filter,
+                    StarknetEventsTable::DEFAULT_MAX_KEYS,
+                )
+                .unwrap();
+                assert_eq!(count, 1);
+
+                // Without the address filter the other contract's transfer matches as well.
+                let count = StarknetEventsTable::event_count(
+                    &tx,
+                    &StarknetEventFilter {
+                        from_block: None,
+                        to_block: None,
+                        contract_address: None,
+                        keys: vec![],
+                        ..filter
+                    },
+                    StarknetEventsTable::DEFAULT_MAX_KEYS,
+                )
+                .unwrap();
+                assert_eq!(count, 2);
+            }
+
+            #[test]
+            fn filter_pending_matches_query() {
+                let storage = Storage::in_memory().unwrap();
+                let events = setup(&storage);
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let address = events[0].from_address;
+                let other_block = StarknetBlockNumber::new_or_panic(1);
+                let filters = [
+                    filter(vec![]),
+                    filter(vec![(0, data(&[1])[0])]),
+                    filter(vec![(1, data(&[2])[0]), (2, data(&[3])[0])]),
+                    filter(vec![(1, EventData(StarkHash::ZERO))]),
+                    StarknetEventFilter {
+                        contract_address: Some(address),
+                        keys: vec![EventKey(starkhash!("deadbeef")), EventKey(starkhash!("01"))],
+                        ..filter(vec![(2, data(&[3])[0])])
+                    },
+                    StarknetEventFilter {
+                        keys: vec![EventKey(starkhash!("01"))],
+                        ..filter(vec![])
+                    },
+                    StarknetEventFilter {
+                        from_block: Some(other_block),
+                        ..filter(vec![])
+                    },
+                ];
+
+                for filter in &filters {
+                    let expected = StarknetEventsTable::get_events(&tx, filter).unwrap().events;
+                    assert_eq!(
+                        StarknetEventsTable::filter_pending(&events, filter),
+                        expected
+                    );
+                }
+            }
+
+            #[test]
+            fn too_many_filters() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let max = StarknetEventsTable::MAX_DATA_FILTERS;
+                let filter = filter(vec![(0, EventData(StarkHash::ZERO)); max + 1]);
+                let result = StarknetEventsTable::get_events(&tx, &filter);
+                assert_eq!(
+                    filter_error(result.unwrap_err()),
+                    EventFilterError::TooManyDataFilters(max)
+                );
+            }
+        }
+
+        mod too_many_keys {
+            use super::*;
+
+            fn keys(count: usize) -> Vec<EventKey> {
+                (0..count)
+                    .map(|i| EventKey(StarkHash::from_u128(i as u128)))
+                    .collect()
+            }
+
+            #[test]
+            fn get_events() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let filter = StarknetEventFilter {
+                    from_block: None,
+                    to_block: None,
+                    contract_address: None,
+                    keys: keys(StarknetEventsTable::DEFAULT_MAX_KEYS + 1),
+                    data_filter: vec![],
+                    page_size: 10,
+                    page_number: 0,
+                    detect_last_page: true,
+                    count_pages: false,
+                    only_accepted: false,
+                };
+                let result = StarknetEventsTable::get_events(&tx, &filter);
+                assert_eq!(
+                    filter_error(result.unwrap_err()),
+                    EventFilterError::TooManyKeys(StarknetEventsTable::DEFAULT_MAX_KEYS)
+                );
+
+                // Exactly at the limit is fine.
+                let filter = StarknetEventFilter {
+                    keys: keys(StarknetEventsTable::DEFAULT_MAX_KEYS),
+                    ..filter
+                };
+                let page = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+                assert!(page.events.is_empty());
+            }
+
+            #[test]
+            fn duplicates_are_ignored() {
+                let (storage, events) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let keys = vec![events[3].keys[0], events[5].keys[0]];
+                let filter = StarknetEventFilter {
+                    from_block: None,
+                    to_block: None,
+                    contract_address: None,
+                    keys: keys.clone(),
+                    data_filter: vec![],
+                    page_size: test_utils::NUM_EVENTS,
+                    page_number: 0,
+                    detect_last_page: true,
+                    count_pages: false,
+                    only_accepted: false,
+                };
+                let expected = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+                assert_eq!(expected.events, vec![events[3].clone(), events[5].clone()]);
+
+                let duplicated = StarknetEventFilter {
+                    keys: keys.iter().chain(&keys).chain(&keys).copied().collect(),
+                    ..filter
+                };
+                let page = StarknetEventsTable::get_events(&tx, &duplicated).unwrap();
+                assert_eq!(page, expected);
+
+                let mut key_fts_expression = String::new();
+                StarknetEventsTable::event_query(
+                    "",
+                    EventConditions {
+                        keys: &duplicated.keys,
+                        ..Default::default()
+                    },
+                    StarknetEventsTable::DEFAULT_MAX_KEYS,
+                    None,
+                    None,
+                    &mut key_fts_expression,
+                )
+                .unwrap();
+                assert_eq!(key_fts_expression.matches(" OR ").count(), 1);
+            }
+
+            #[test]
+            fn event_count() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let result = StarknetEventsTable::event_count(
+                    &tx,
+                    &StarknetEventFilter {
+                        from_block: None,
+                        to_block: None,
+                        contract_address: None,
+                        keys: keys(StarknetEventsTable::DEFAULT_MAX_KEYS + 1),
+                        data_filter: vec![],
+                        page_size: 0,
+                        page_number: 0,
+                        detect_last_page: false,
+                        count_pages: false,
+                        only_accepted: false,
+                    },
+                    StarknetEventsTable::DEFAULT_MAX_KEYS,
+                );
+                assert_eq!(
+                    filter_error(result.unwrap_err()),
+                    EventFilterError::TooManyKeys(StarknetEventsTable::DEFAULT_MAX_KEYS)
+                );
+            }
+        }
+
+        #[test]
+        fn batch_matches_per_transaction() {
+            let block = test_utils::create_blocks()[0].clone();
+            let transactions = test_utils::create_transactions_and_receipts();
+            let receipts = transactions[..test_utils::TRANSACTIONS_PER_BLOCK]
+                .iter()
+                .map(|(_, receipt)| receipt)
+                .collect::<Vec<_>>();
+
+            let stored_rows = |insert: &dyn Fn(&Transaction<'_>)| {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+                StarknetBlocksTable::insert(&tx, &block, None).unwrap();
+                CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
+
+                insert(&tx);
+
+                let mut stmt = tx
+                    .prepare(
+                        "SELECT id, block_number, idx, transaction_hash, from_address, keys, first_key, data
+                        FROM starknet_events ORDER BY id",
+                    )
+                    .unwrap();
+                let rows = stmt
+                    .query_map([], |row| {
+                        (0..8)
+                            .map(|i| row.get::<_, rusqlite::types::Value>(i))
+                            .collect::<Result<Vec<_>, _>>()
+                    })
+                    .unwrap()
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
+                rows
+            };
+
+            let per_transaction = stored_rows(&|tx| {
+                for receipt in &receipts {
+                    StarknetEventsTable::insert_events(
+                        tx,
+                        block.number,
+                        receipt.transaction_hash,
+                        &receipt.events,
+                    )
+                    .unwrap();
+                }
+            });
+
+            let batch = stored_rows(&|tx| {
+                let entries = receipts
+                    .iter()
+                    .map(|receipt| (receipt.transaction_hash, receipt.events.as_slice()))
+                    .collect::<Vec<_>>();
+                StarknetEventsTable::insert_events_batch(tx, block.number, &entries).unwrap();
+            });
+
+            assert_eq!(per_transaction.len(), test_utils::EVENTS_PER_BLOCK);
+            assert_eq!(batch, per_transaction);
+        }
+
+        mod broadcast {
+            use super::*;
+
+            use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+
+            /// Inserts `count` events in the genesis block, broadcasting them on `storage`'s
+            /// event channel once committed.
+            fn insert_events(storage: &Storage, count: u8) -> Vec<StarknetEmittedEvent> {
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+                let events = insert_uncommitted(&tx, count);
+                tx.commit().unwrap();
+
+                if let Some(sender) = storage.event_sender() {
+                    StarknetEmittedEvent::broadcast(sender, events.clone());
+                }
+
+                events
+            }
+
+            /// Inserts `count` events in the genesis block without committing `tx`.
+            fn insert_uncommitted(tx: &Transaction<'_>, count: u8) -> Vec<StarknetEmittedEvent> {
+                let block = test_utils::create_blocks()[0].clone();
+                StarknetBlocksTable::insert(tx, &block, None).unwrap();
+                CanonicalBlocksTable::insert(tx, block.number, block.hash).unwrap();
+
+                let transaction_hash = StarknetTransactionHash(starkhash!("0abc"));
+                let events = (0..count)
+                    .map(|i| transaction::Event {
+                        from_address: ContractAddress::new_or_panic(starkhash!("1234")),
+                        data: vec![EventData(StarkHash::from_be_slice(&[i]).unwrap())],
+                        keys: vec![EventKey(starkhash!("deadbeef"))],
+                    })
+                    .collect::<Vec<_>>();
+                StarknetEventsTable::insert_events(tx, block.number, transaction_hash, &events)
+                    .unwrap();
+
+                events
+                    .into_iter()
+                    .map(|event| StarknetEmittedEvent {
+                        from_address: event.from_address,
+                        data: event.data,
+                        keys: event.keys,
+                        block_hash: block.hash,
+                        block_number: block.number,
+                        transaction_hash,
+                    })
+                    .collect()
+            }
+
+            #[test]
+            fn disabled_by_default() {
+                let storage = Storage::in_memory().unwrap();
+                assert!(storage.subscribe_events().is_none());
+
+                insert_events(&storage, 1);
+            }
+
+            #[test]
+            fn inserted_event_is_received() {
+                let storage = Storage::in_memory().unwrap().with_event_broadcast(16);
+                let mut rx = storage.subscribe_events().unwrap();
+
+                let expected = insert_events(&storage, 2);
+
+                assert_eq!(rx.try_recv().unwrap(), expected[0]);
+                assert_eq!(rx.try_recv().unwrap(), expected[1]);
+                assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+            }
+
+            #[test]
+            fn rolled_back_events_are_not_received() {
+                let storage = Storage::in_memory().unwrap().with_event_broadcast(16);
+                let mut rx = storage.subscribe_events().unwrap();
+
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+                insert_uncommitted(&tx, 2);
+                tx.rollback().unwrap();
+
+                assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+            }
+
+            #[tokio::test]
+            async fn lagging_receiver_does_not_block_writer() {
+                let storage = Storage::in_memory().unwrap().with_event_broadcast(1);
+                let mut rx = storage.subscribe_events().unwrap();
+
+                let expected = insert_events(&storage, 3);
+
+                assert_eq!(rx.recv().await, Err(RecvError::Lagged(2)));
+                assert_eq!(rx.recv().await.unwrap(), expected[2]);
+            }
+        }
+
+        mod export {
+            use super::*;
+
+            /// Counts the calls made to the underlying writer.
+            #[derive(Default)]
+            struct CountingWriter {
+                bytes: Vec<u8>,
+                writes: usize,
+                largest_write: usize,
+            }
+
+            impl std::io::Write for CountingWriter {
+                fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                    self.writes += 1;
+                    self.largest_write = self.largest_write.max(buf.len());
+                    self.bytes.extend_from_slice(buf);
+                    Ok(buf.len())
+                }
+
+                fn flush(&mut self) -> std::io::Result<()> {
+                    Ok(())
+                }
+            }
+
+            fn no_filter() -> StarknetEventFilter {
+                StarknetEventFilter {
+                    from_block: None,
+                    to_block: None,
+                    contract_address: None,
+                    keys: vec![],
+                    data_filter: vec![],
+                    page_size: 0,
+                    page_number: 0,
+                    detect_last_page: false,
+                    count_pages: false,
+                    only_accepted: false,
+                }
+            }
+
+            fn hex_list(values: impl Iterator<Item = StarkHash>) -> String {
+                values
+                    .map(|value| format!("0x{value:x}"))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            }
+
+            #[test]
+            fn csv() {
+                let (storage, emitted_events) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let mut out = Vec::new();
+                let count =
+                    StarknetEventsTable::export(&tx, &no_filter(), &mut out, ExportFormat::Csv)
+                        .unwrap();
+                assert_eq!(count, test_utils::NUM_EVENTS);
+
+                let out = String::from_utf8(out).unwrap();
+                let mut lines = out.lines();
+                assert_eq!(
+                    lines.next().unwrap(),
+                    "block_number,block_hash,tx_hash,tx_index,event_index,from_address,keys,data"
+                );
+                let rows = lines
+                    .map(|line| line.split(',').collect::<Vec<_>>())
+                    .collect::<Vec<_>>();
+                assert_eq!(rows.len(), test_utils::NUM_EVENTS);
+                assert!(rows.iter().all(|row| row.len() == 8));
+
+                for (row, expected) in rows.iter().zip(&emitted_events) {
+                    assert_eq!(row[0], expected.block_number.get().to_string());
+                    assert_eq!(row[1], format!("0x{:x}", expected.block_hash.0));
+                    assert_eq!(row[2], format!("0x{:x}", expected.transaction_hash.0));
+                    assert_eq!(row[5], format!("0x{:x}", expected.from_address.get()));
+                    assert_eq!(row[6], hex_list(expected.keys.iter().map(|k| k.0)));
+                    assert_eq!(row[7], hex_list(expected.data.iter().map(|d| d.0)));
+                }
+
+                // Each test transaction emits a single event.
+                let last = rows.last().unwrap();
+                assert_eq!(
+                    last[3],
+                    (test_utils::EVENTS_PER_BLOCK - 1).to_string(),
+                    "tx_index"
+                );
+                assert_eq!(last[4], "0", "event_index");
+            }
+
+            #[test]
+            fn json_lines() {
+                let (storage, emitted_events) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let mut out = Vec::new();
+                let count = StarknetEventsTable::export(
+                    &tx,
+                    &no_filter(),
+                    &mut out,
+                    ExportFormat::JsonLines,
+                )
+                .unwrap();
+                assert_eq!(count, test_utils::NUM_EVENTS);
+
+                let rows = out
+                    .split(|&b| b == b'\n')
+                    .filter(|line| !line.is_empty())
+                    .map(|line| serde_json::from_slice::<serde_json::Value>(line).unwrap())
+                    .collect::<Vec<_>>();
+                assert_eq!(rows.len(), test_utils::NUM_EVENTS);
+
+                let first = &emitted_events[0];
+                assert_eq!(
+                    rows[0],
+                    serde_json::json!({
+                        "block_number": 0,
+                        "block_hash": format!("0x{:x}", first.block_hash.0),
+                        "tx_hash": format!("0x{:x}", first.transaction_hash.0),
+                        "tx_index": 0,
+                        "event_index": 0,
+                        "from_address": format!("0x{:x}", first.from_address.get()),
+                        "keys": hex_list(first.keys.iter().map(|k| k.0)),
+                        "data": hex_list(first.data.iter().map(|d| d.0)),
+                    })
+                );
+
+                let last = &emitted_events[test_utils::NUM_EVENTS - 1];
+                assert_eq!(
+                    rows[test_utils::NUM_EVENTS - 1]["block_number"],
+                    last.block_number.get()
+                );
+                assert_eq!(
+                    rows[test_utils::NUM_EVENTS - 1]["tx_hash"],
+                    format!("0x{:x}", last.transaction_hash.0)
+                );
+            }
+
+            #[test]
+            fn filtered() {
+                let (storage, emitted_events) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let expected = &emitted_events[test_utils::EVENTS_PER_BLOCK..];
+                let filter = StarknetEventFilter {
+                    from_block: Some(expected[0].block_number),
+                    contract_address: Some(expected[0].from_address),
+                    ..no_filter()
+                };
+
+                let mut out = Vec::new();
+                let count =
+                    StarknetEventsTable::export(&tx, &filter, &mut out, ExportFormat::JsonLines)
+                        .unwrap();
+                assert_eq!(count, 1);
+
+                let row: serde_json::Value = serde_json::from_slice(&out).unwrap();
+                assert_eq!(
+                    row["tx_hash"],
+                    format!("0x{:x}", expected[0].transaction_hash.0)
+                );
+            }
+
+            #[test]
+            fn streams_events() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let mut writer = CountingWriter::default();
+                StarknetEventsTable::export(&tx, &no_filter(), &mut writer, ExportFormat::Csv)
+                    .unwrap();
+
+                // Events are written one at a time, instead of being collected and written at
+                // once, so no single write holds more than one row.
+                assert!(writer.writes > test_utils::NUM_EVENTS);
+                let longest_line = writer.bytes.split(|&b| b == b'\n').map(<[u8]>::len).max();
+                assert!(writer.largest_write <= longest_line.unwrap());
+            }
+        }
+
+        mod get_events_for_transaction {
+            use super::*;
+
+            #[test]
+            fn multiple_events() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let block = test_utils::create_blocks()[0].clone();
+                let (transaction, mut receipt) =
+                    test_utils::create_transactions_and_receipts()[0].clone();
+                receipt.events = (0u8..3)
+                    .map(|i| transaction::Event {
+                        from_address: ContractAddress::new_or_panic(
+                            StarkHash::from_be_slice(&[i]).unwrap(),
+                        ),
+                        data: vec![EventData(StarkHash::from_be_slice(&[i, i]).unwrap())],
+                        keys: vec![EventKey(starkhash!("deadbeef"))],
+                    })
+                    .collect();
+
+                StarknetBlocksTable::insert(&tx, &block, None).unwrap();
+                CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
+                StarknetTransactionsTable::upsert(
+                    &tx,
+                    block.hash,
+                    block.number,
+                    &[(transaction.clone(), receipt.clone())],
+                )
+                .unwrap();
+
+                let expected = receipt
+                    .events
+                    .into_iter()
+                    .map(|event| StarknetEmittedEvent {
+                        from_address: event.from_address,
+                        data: event.data,
+                        keys: event.keys,
+                        block_hash: block.hash,
+                        block_number: block.number,
+                        transaction_hash: transaction.hash(),
+                    })
+                    .collect::<Vec<_>>();
+
+                let events =
+                    StarknetEventsTable::get_events_for_transaction(&tx, transaction.hash())
+                        .unwrap();
+                assert_eq!(events, expected);
+            }
+
+            #[test]
+            fn no_events() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                // Declare transactions in the test set do not emit any events.
+                let (declare, receipt) = &test_utils::create_transactions_and_receipts()[10];
+                assert_matches::assert_matches!(declare, transaction::Transaction::Declare(_));
+                assert!(receipt.events.is_empty());
+
+                let events =
+                    StarknetEventsTable::get_events_for_transaction(&tx, declare.hash()).unwrap();
+                assert!(events.is_empty());
+            }
+
+            #[test]
+            fn unknown_transaction() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let unknown = StarknetTransactionHash(starkhash!("0badbeef"));
+                let events = StarknetEventsTable::get_events_for_transaction(&tx, unknown).unwrap();
+                assert!(events.is_empty());
+            }
+
+            #[test]
+            fn uses_index() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let plan = tx
+                    .prepare(&format!(
+                        "EXPLAIN QUERY PLAN {}{}",
+                        StarknetEventsTable::EVENT_SELECT,
+                        StarknetEventsTable::TRANSACTION_EVENTS_WHERE
+                    ))
+                    .unwrap()
+                    .query_map([StarknetTransactionHash(starkhash!("01"))], |row| {
+                        row.get::<_, String>("detail")
+                    })
+                    .unwrap()
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
+
+                assert!(
+                    plan.iter()
+                        .any(|detail| detail.contains("starknet_events_transaction_hash")),
+                    "transaction hash lookup does not use the index: {plan:?}"
+                );
+            }
+        }
+
+        #[test]
+        fn no_keys_join_without_keys() {
+            let address = ContractAddress::new_or_panic(starkhash!("0123"));
+            let key = EventKey(starkhash!("deadbeef"));
+
+            for (contract_address, keys) in [
+                (None, &[][..]),
+                (Some(&address), &[]),
+                (None, &[key][..]),
+                (Some(&address), &[key]),
+            ] {
+                let mut key_fts_expression = String::new();
+                let (query, _) = StarknetEventsTable::event_query(
+                    StarknetEventsTable::EVENT_SELECT,
+                    EventConditions {
+                        contract_address,
+                        keys,
+                        ..Default::default()
+                    },
+                    StarknetEventsTable::DEFAULT_MAX_KEYS,
+                    None,
+                    None,
+                    &mut key_fts_expression,
+                )
+                .unwrap();
+
+                assert_eq!(
+                    query.contains("starknet_events_keys"),
+                    !keys.is_empty(),
+                    "{query}"
+                );
+            }
+        }
+
+        #[test]
+        fn contract_address_filter_uses_index() {
+            let (storage, events) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let addr = events[0].from_address;
+            let mut key_fts_expression = String::new();
+            let (query, params) = StarknetEventsTable::event_query(
+                "EXPLAIN QUERY PLAN SELECT COUNT(1) FROM starknet_events",
+                EventConditions {
+                    contract_address: Some(&addr),
+                    ..Default::default()
+                },
+                StarknetEventsTable::DEFAULT_MAX_KEYS,
+                None,
+                None,
+                &mut key_fts_expression,
+            )
+            .unwrap();
+
+            let plan = tx
+                .prepare(&query)
+                .unwrap()
+                .query_map(params.as_slice(), |row| row.get::<_, String>("detail"))
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+            assert!(
+                plan.iter()
+                    .any(|detail| detail.contains("starknet_events_from_address_block_number")),
+                "contract address filter does not use the index: {plan:?}"
+            );
+            assert!(
+                !plan.iter().any(|detail| detail.starts_with("SCAN")),
+                "contract address filter scans a table: {plan:?}"
+            );
+        }
+    }
+
+    mod starknet_transactions {
+        use super::*;
+        use crate::storage::test_utils;
+
+        #[test]
+        fn corrupted_transaction() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let (transaction, _) = &test_utils::create_transactions_and_receipts()[0];
+            tx.execute(
+                "UPDATE starknet_transactions SET tx = X'0BAD' WHERE hash = ?",
+                [transaction.hash()],
+            )
+            .unwrap();
+
+            let error =
+                StarknetTransactionsTable::get_transaction(&tx, transaction.hash()).unwrap_err();
+            assert!(
+                matches!(
+                    error,
+                    StorageError::Corruption {
+                        table: "starknet_transactions",
+                        ..
+                    }
+                ),
+                "{error:?}"
+            );
+        }
+
+        mod counts {
+            use super::*;
+
+            fn message() -> transaction::L2ToL1Message {
+                transaction::L2ToL1Message {
+                    from_address: ContractAddress::new_or_panic(crate::starkhash!("01")),
+                    payload: vec![],
+                    to_address: crate::core::EthereumAddress(web3::types::H160::zero()),
+                }
+            }
+
+            fn event() -> transaction::Event {
+                transaction::Event {
+                    from_address: ContractAddress::new_or_panic(crate::starkhash!("01")),
+                    data: vec![],
+                    keys: vec![],
+                }
+            }
+
+            /// Stores the genesis block with two transactions, the first without events or
+            /// messages and the second with the given numbers of each.
+            fn insert(
+                tx: &Transaction<'_>,
+                events: usize,
+                messages: usize,
+            ) -> [StarknetTransactionHash; 2] {
+                let block = &test_utils::create_blocks()[0];
+                let mut transactions = test_utils::create_transactions_and_receipts()[..2].to_vec();
+                for (_, receipt) in &mut transactions {
+                    receipt.events.clear();
+                    receipt.l2_to_l1_messages.clear();
+                }
+                transactions[1].1.events = vec![event(); events];
+                transactions[1].1.l2_to_l1_messages = vec![message(); messages];
+
+                StarknetTransactionsTable::upsert(tx, block.hash, block.number, &transactions)
+                    .unwrap();
+                [transactions[0].0.hash(), transactions[1].0.hash()]
+            }
+
+            #[test]
+            fn transactions() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+                let block = &test_utils::create_blocks()[0];
+                StarknetBlocksTable::insert(&tx, block, None).unwrap();
+                CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
+
+                let [empty, full] = insert(&tx, 2, 3);
+                assert_eq!(
+                    StarknetTransactionsTable::get_counts(&tx, empty).unwrap(),
+                    Some((0, 0))
+                );
+                assert_eq!(
+                    StarknetTransactionsTable::get_counts(&tx, full).unwrap(),
+                    Some((2, 3))
+                );
+                assert_eq!(
+                    StarknetTransactionsTable::get_block_counts(&tx, block.number.into()).unwrap(),
+                    Some((2, 3))
+                );
+
+                let unknown = StarknetTransactionHash(crate::starkhash_bytes!(b"unknown"));
+                assert_eq!(
+                    StarknetTransactionsTable::get_counts(&tx, unknown).unwrap(),
+                    None
+                );
+            }
+
+            #[test]
+            fn overwritten() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+                let block = &test_utils::create_blocks()[0];
+                StarknetBlocksTable::insert(&tx, block, None).unwrap();
+                CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
+
+                insert(&tx, 2, 3);
+                let [_, full] = insert(&tx, 1, 4);
+                assert_eq!(
+                    StarknetTransactionsTable::get_counts(&tx, full).unwrap(),
+                    Some((1, 4))
+                );
+                assert_eq!(
+                    StarknetTransactionsTable::get_block_counts(&tx, block.hash.into()).unwrap(),
+                    Some((1, 4))
+                );
+            }
+
+            #[test]
+            fn blocks() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+                let blocks = test_utils::create_blocks();
+                for block in &blocks[..2] {
+                    StarknetBlocksTable::insert(&tx, block, None).unwrap();
+                    CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
+                }
+                insert(&tx, 2, 3);
+
+                // The latest block has no transactions.
+                assert_eq!(
+                    StarknetTransactionsTable::get_block_counts(&tx, StarknetBlocksBlockId::Latest)
+                        .unwrap(),
+                    Some((0, 0))
+                );
+                assert_eq!(
+                    StarknetTransactionsTable::get_block_counts(&tx, blocks[2].number.into())
+                        .unwrap(),
+                    None
+                );
+            }
+        }
+
+        mod get_declare_transaction_for_class {
+            use super::*;
+
+            #[test]
+            fn earliest_declare() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let blocks = test_utils::create_blocks();
+                let transactions = test_utils::create_transactions_and_receipts();
+                let (declare, receipt) = transactions[test_utils::TRANSACTIONS_PER_BLOCK..]
+                    .iter()
+                    .find(|(t, _)| matches!(t, transaction::Transaction::Declare(_)))
+                    .unwrap();
+                let class_hash = declare.class_hash().unwrap();
+
+                // A duplicate submission of the same class in a later block.
+                let duplicate_hash = StarknetTransactionHash(crate::starkhash_bytes!(b"duplicate"));
+                let mut duplicate = declare.clone();
+                match &mut duplicate {
+                    transaction::Transaction::Declare(t) => t.transaction_hash = duplicate_hash,
+                    _ => unreachable!(),
+                }
+                let mut duplicate_receipt = receipt.clone();
+                duplicate_receipt.transaction_hash = duplicate_hash;
+                duplicate_receipt.events = vec![];
+                StarknetTransactionsTable::upsert(
+                    &tx,
+                    blocks[3].hash,
+                    blocks[3].number,
+                    &[(duplicate, duplicate_receipt)],
+                )
+                .unwrap();
+
+                let result =
+                    StarknetTransactionsTable::get_declare_transaction_for_class(&tx, class_hash)
+                        .unwrap();
+                assert_eq!(result, Some((declare.clone(), blocks[1].hash)));
+
+                let unknown = ClassHash(crate::starkhash_bytes!(b"unknown"));
+                let result =
+                    StarknetTransactionsTable::get_declare_transaction_for_class(&tx, unknown)
+                        .unwrap();
+                assert_eq!(result, None);
+            }
+
+            #[test]
+            fn other_transactions_have_no_class() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                for (transaction, _) in test_utils::create_transactions_and_receipts() {
+                    let class_hash: Option<ClassHash> = tx
+                        .query_row(
+                            "SELECT class_hash FROM starknet_transactions WHERE hash = ?",
+                            [transaction.hash()],
+                            |row| row.get(0),
+                        )
+                        .unwrap();
+                    assert_eq!(class_hash, transaction.class_hash());
+                    match transaction {
+                        transaction::Transaction::Invoke(_)
+                        | transaction::Transaction::L1Handler(_) => assert_eq!(class_hash, None),
+                        _ => assert!(class_hash.is_some()),
+                    }
+                }
+            }
+        }
+
+        mod raw {
+            use super::*;
+
+            #[test]
+            fn matches_typed() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let hash = test_utils::create_transactions_and_receipts()[0].0.hash();
+
+                let raw = StarknetTransactionsTable::get_transaction_raw(&tx, hash)
+                    .unwrap()
+                    .unwrap();
+                let typed = StarknetTransactionsTable::get_transaction(&tx, hash)
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(
+                    serde_json::from_slice::<transaction::Transaction>(&raw).unwrap(),
+                    typed
+                );
+
+                let raw = StarknetTransactionsTable::get_receipt_raw(&tx, hash)
+                    .unwrap()
+                    .unwrap();
+                let (typed, _) = StarknetTransactionsTable::get_receipt(&tx, hash)
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(
+                    serde_json::from_slice::<transaction::Receipt>(&raw).unwrap(),
+                    typed
+                );
+            }
+
+            #[test]
+            fn missing() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let hash = StarknetTransactionHash(crate::starkhash_bytes!(b"missing"));
+                assert_eq!(
+                    StarknetTransactionsTable::get_transaction_raw(&tx, hash).unwrap(),
+                    None
+                );
+                assert_eq!(
+                    StarknetTransactionsTable::get_receipt_raw(&tx, hash).unwrap(),
+                    None
+                );
+            }
+
+            #[test]
+            fn corrupted() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let hash = test_utils::create_transactions_and_receipts()[0].0.hash();
+                tx.execute(
+                    "UPDATE starknet_transactions SET tx = X'0BAD', receipt = X'0BAD' WHERE hash = ?",
+                    [hash],
+                )
+                .unwrap();
+
+                for error in [
+                    StarknetTransactionsTable::get_transaction_raw(&tx, hash).unwrap_err(),
+                    StarknetTransactionsTable::get_receipt_raw(&tx, hash).unwrap_err(),
+                ] {
+                    assert!(
+                        matches!(
+                            error,
+                            StorageError::Corruption {
+                                table: "starknet_transactions",
+                                ..
+                            }
+                        ),
+                        "{error:?}"
+                    );
+                }
+            }
+        }
+
+        mod upsert_strict {
+            use super::*;
+
+            fn stored_block(
+                tx: &Transaction<'_>,
+                hash: StarknetTransactionHash,
+            ) -> StarknetBlockHash {
+                tx.query_row(
+                    "SELECT block_hash FROM starknet_transactions WHERE hash = ?",
+                    [hash],
+                    |row| row.get(0),
+                )
+                .unwrap()
+            }
+
+            #[test]
+            fn conflict() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let blocks = test_utils::create_blocks();
+                let transactions = &test_utils::create_transactions_and_receipts()
+                    [test_utils::TRANSACTIONS_PER_BLOCK..2 * test_utils::TRANSACTIONS_PER_BLOCK];
+                let hash = transactions[0].0.hash();
+
+                let error = StarknetTransactionsTable::upsert_strict(
+                    &tx,
+                    &mut CompressionConfig::default().compressor().unwrap(),
+                    blocks[0].hash,
+                    blocks[0].number,
+                    transactions,
+                )
+                .unwrap_err();
+                assert_eq!(
+                    error.downcast_ref::<TransactionBlockConflict>(),
+                    Some(&TransactionBlockConflict {
+                        transaction_hash: hash,
+                        existing_block: blocks[1].hash,
+                        new_block: blocks[0].hash,
+                    })
+                );
+                assert_eq!(stored_block(&tx, hash), blocks[1].hash);
+
+                // The default mode overwrites.
+                StarknetTransactionsTable::upsert(
+                    &tx,
+                    blocks[0].hash,
+                    blocks[0].number,
+                    transactions,
+                )
+                .unwrap();
+                assert_eq!(stored_block(&tx, hash), blocks[0].hash);
+            }
+
+            #[test]
+            fn same_block() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let block = &test_utils::create_blocks()[0];
+                let transactions = &test_utils::create_transactions_and_receipts()
+                    [..test_utils::TRANSACTIONS_PER_BLOCK];
+                // Unlike transactions, events are not replaced.
+                StarknetEventsTable::reorg(&tx, block.number).unwrap();
+
+                StarknetTransactionsTable::upsert_strict(
+                    &tx,
+                    &mut CompressionConfig::default().compressor().unwrap(),
+                    block.hash,
+                    block.number,
+                    transactions,
+                )
+                .unwrap();
+                assert_eq!(stored_block(&tx, transactions[0].0.hash()), block.hash);
+            }
+        }
+
+        mod verify_idx_contiguity {
+            use super::*;
+
+            #[test]
+            fn contiguous() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                for block in test_utils::create_blocks() {
+                    assert!(
+                        StarknetTransactionsTable::verify_idx_contiguity(&tx, block.hash).unwrap()
+                    );
+                }
+            }
+
+            #[test]
+            fn gap() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let block = &test_utils::create_blocks()[1];
+                tx.execute(
+                    "DELETE FROM starknet_transactions WHERE block_hash = ? AND idx = 3",
+                    [block.hash],
+                )
+                .unwrap();
+
+                assert!(
+                    !StarknetTransactionsTable::verify_idx_contiguity(&tx, block.hash).unwrap()
+                );
+            }
+
+            #[test]
+            fn duplicate() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let block = &test_utils::create_blocks()[1];
+                tx.execute(
+                    "UPDATE starknet_transactions SET idx = 0 WHERE block_hash = ? AND idx = 1",
+                    [block.hash],
+                )
+                .unwrap();
+
+                assert!(
+                    !StarknetTransactionsTable::verify_idx_contiguity(&tx, block.hash).unwrap()
+                );
+            }
+
+            #[test]
+            fn no_transactions() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let block = &test_utils::create_blocks()[0];
+                assert!(StarknetTransactionsTable::verify_idx_contiguity(&tx, block.hash).unwrap());
+            }
+        }
+
+        mod sender_counts {
+            use super::*;
+            use crate::starkhash;
+
+            #[test]
+            fn two_senders() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let block = &test_utils::create_blocks()[0];
+                StarknetBlocksTable::insert(&tx, block, None).unwrap();
+                CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
+
+                let invoke_sender = ContractAddress::new_or_panic(starkhash!("11"));
+                let declare_sender = ContractAddress::new_or_panic(starkhash!("22"));
+
+                // Keep only the invoke and declare transactions of the first block, and
+                // attribute them to one sender per transaction type.
+                let transaction_data = test_utils::create_transactions_and_receipts()
+                    [..test_utils::TRANSACTIONS_PER_BLOCK]
+                    .iter()
+                    .cloned()
+                    .filter_map(|(transaction, receipt)| {
+                        let transaction = match transaction {
+                            transaction::Transaction::Invoke(
+                                transaction::InvokeTransaction::V0(t),
+                            ) => transaction::Transaction::Invoke(
+                                transaction::InvokeTransaction::V0(
+                                    transaction::InvokeTransactionV0 {
+                                        contract_address: invoke_sender,
+                                        ..t
+                                    },
+                                ),
+                            ),
+                            transaction::Transaction::Declare(t) => {
+                                transaction::Transaction::Declare(transaction::DeclareTransaction {
+                                    sender_address: declare_sender,
+                                    ..t
+                                })
+                            }
+                            _ => return None,
+                        };
+                        Some((transaction, receipt))
+                    })
+                    .collect::<Vec<_>>();
+                StarknetTransactionsTable::upsert(&tx, block.hash, block.number, &transaction_data)
+                    .unwrap();
+
+                let expected = HashMap::from([(invoke_sender, 5), (declare_sender, 5)]);
+
+                let counts =
+                    StarknetTransactionsTable::sender_counts(&tx, block.number.into()).unwrap();
+                assert_eq!(counts, expected);
+
+                let counts =
+                    StarknetTransactionsTable::sender_counts(&tx, block.hash.into()).unwrap();
+                assert_eq!(counts, expected);
+
+                let counts =
+                    StarknetTransactionsTable::sender_counts(&tx, StarknetBlocksBlockId::Latest)
+                        .unwrap();
+                assert_eq!(counts, expected);
+            }
+
+            #[test]
+            fn missing_block() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let counts = StarknetTransactionsTable::sender_counts(
+                    &tx,
+                    StarknetBlockNumber::GENESIS.into(),
+                )
+                .unwrap();
+                assert!(counts.is_empty());
+            }
+        }
+
+        mod get_transaction_including_pending {
+            use super::*;
+            use crate::core::{GasPrice, SequencerAddress, StarknetBlockTimestamp};
+
+            /// Stores the first block, and puts the second block's transactions in a pending block.
+            fn setup() -> (Storage, PendingBlock) {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let block = &test_utils::create_blocks()[0];
+                let transactions = test_utils::create_transactions_and_receipts();
+                StarknetBlocksTable::insert(&tx, block, None).unwrap();
+                CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
+                StarknetTransactionsTable::upsert(
+                    &tx,
+                    block.hash,
+                    block.number,
+                    &transactions[..test_utils::TRANSACTIONS_PER_BLOCK],
+                )
+                .unwrap();
+                tx.commit().unwrap();
+
+                let (pending_transactions, pending_receipts) = transactions
+                    [test_utils::TRANSACTIONS_PER_BLOCK..2 * test_utils::TRANSACTIONS_PER_BLOCK]
+                    .iter()
+                    .cloned()
+                    .unzip();
+                let pending = PendingBlock {
+                    gas_price: GasPrice::ZERO,
+                    parent_hash: block.hash,
+                    sequencer_address: SequencerAddress(StarkHash::ZERO),
+                    status: Status::Pending,
+                    timestamp: StarknetBlockTimestamp::new_or_panic(0),
+                    transaction_receipts: pending_receipts,
+                    transactions: pending_transactions,
+                    starknet_version: None,
+                };
+
+                (storage, pending)
+            }
+
+            #[test]
+            fn pending_only() {
+                let (storage, pending) = setup();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let expected = pending.transactions[3].clone();
+                let hash = expected.hash();
+
+                let result = StarknetTransactionsTable::get_transaction_including_pending(
+                    &tx,
+                    hash,
+                    Some(&pending),
+                )
+                .unwrap();
+                assert_eq!(result, Some((expected, Status::Pending)));
+
+                assert_eq!(
+                    StarknetTransactionsTable::get_transaction(&tx, hash).unwrap(),
+                    None
+                );
+                let result =
+                    StarknetTransactionsTable::get_transaction_including_pending(&tx, hash, None)
+                        .unwrap();
+                assert_eq!(result, None);
+            }
+
+            #[test]
+            fn stored() {
+                let (storage, pending) = setup();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let expected = test_utils::create_transactions_and_receipts()[3].0.clone();
+                let hash = expected.hash();
+
+                let result = StarknetTransactionsTable::get_transaction_including_pending(
+                    &tx,
+                    hash,
+                    Some(&pending),
+                )
+                .unwrap();
+                assert_eq!(result, Some((expected.clone(), Status::AcceptedOnL2)));
+
+                RefsTable::set_l1_l2_head(&tx, Some(StarknetBlockNumber::GENESIS)).unwrap();
+                let result = StarknetTransactionsTable::get_transaction_including_pending(
+                    &tx,
+                    hash,
+                    Some(&pending),
+                )
+                .unwrap();
+                assert_eq!(result, Some((expected, Status::AcceptedOnL1)));
+            }
+        }
+
+        mod fees {
+            use super::*;
+            use web3::types::H128;
+
+            fn fee(value: u128) -> Fee {
+                Fee(H128::from(value.to_be_bytes()))
+            }
+
+            /// Block 0 pays fees of 10 and 20 and has a transaction without a fee, block 1 pays 5
+            /// and also has one without a fee, and block 2 has no transactions.
+            fn setup() -> Storage {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let blocks = test_utils::create_blocks();
+                for block in &blocks[..3] {
+                    StarknetBlocksTable::insert(&tx, block, None).unwrap();
+                    CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
+                }
+
+                let mut data = test_utils::create_transactions_and_receipts();
+                let fees = [Some(10), Some(20), None, Some(5), None];
+                for ((_, receipt), value) in data.iter_mut().zip(fees) {
+                    receipt.actual_fee = value.map(fee);
+                }
+
+                StarknetTransactionsTable::upsert(
+                    &tx,
+                    blocks[0].hash,
+                    blocks[0].number,
+                    &data[..3],
+                )
+                .unwrap();
+                StarknetTransactionsTable::upsert(
+                    &tx,
+                    blocks[1].hash,
+                    blocks[1].number,
+                    &data[3..5],
+                )
+                .unwrap();
+
+                tx.commit().unwrap();
+                drop(connection);
+                storage
+            }
+
+            #[test]
+            fn fee_stats() {
+                let storage = setup();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let stats = StarknetTransactionsTable::fee_stats(
+                    &tx,
+                    StarknetBlockNumber::GENESIS,
+                    StarknetBlockNumber::new_or_panic(2),
+                )
+                .unwrap();
+                assert_eq!(
+                    stats,
+                    FeeStats {
+                        total: fee(35),
+                        count: 3,
+                        max: Some(fee(20)),
+                        min: Some(fee(5)),
+                    }
+                );
 
-        mod l1_l2_head {
-            use super::*;
+                let stats = StarknetTransactionsTable::fee_stats(
+                    &tx,
+                    StarknetBlockNumber::new_or_panic(1),
+                    StarknetBlockNumber::new_or_panic(1),
+                )
+                .unwrap();
+                assert_eq!(
+                    stats,
+                    FeeStats {
+                        total: fee(5),
+                        count: 1,
+                        max: Some(fee(5)),
+                        min: Some(fee(5)),
+                    }
+                );
+            }
 
             #[test]
-            fn fresh_is_none() {
-                let storage = Storage::in_memory().unwrap();
+            fn fee_stats_without_fees() {
+                let storage = setup();
                 let mut connection = storage.connection().unwrap();
                 let tx = connection.transaction().unwrap();
 
-                let l1_l2_head = RefsTable::get_l1_l2_head(&tx).unwrap();
-                assert_eq!(l1_l2_head, None);
+                let stats = StarknetTransactionsTable::fee_stats(
+                    &tx,
+                    StarknetBlockNumber::new_or_panic(2),
+                    StarknetBlockNumber::new_or_panic(10),
+                )
+                .unwrap();
+                assert_eq!(
+                    stats,
+                    FeeStats {
+                        total: fee(0),
+                        count: 0,
+                        max: None,
+                        min: None,
+                    }
+                );
             }
 
             #[test]
-            fn set_get() {
+            fn fee_stats_exceeding_i64() {
                 let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let block = &test_utils::create_blocks()[0];
+                StarknetBlocksTable::insert(&tx, block, None).unwrap();
+                CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
+                let mut data = test_utils::create_transactions_and_receipts();
+                for (_, receipt) in &mut data[..2] {
+                    receipt.actual_fee = Some(fee(u64::MAX as u128));
+                }
+                data[2].1.actual_fee = Some(fee(1));
+                StarknetTransactionsTable::upsert(&tx, block.hash, block.number, &data[..3])
+                    .unwrap();
+
+                let stats =
+                    StarknetTransactionsTable::fee_stats(&tx, block.number, block.number).unwrap();
+                assert_eq!(stats.total, fee(2 * u64::MAX as u128 + 1));
+                assert_eq!(stats.max, Some(fee(u64::MAX as u128)));
+                assert_eq!(stats.min, Some(fee(1)));
+            }
+
+            #[test]
+            fn get_block_total_fee() {
+                let storage = setup();
                 let mut connection = storage.connection().unwrap();
                 let tx = connection.transaction().unwrap();
+                let blocks = test_utils::create_blocks();
 
-                let expected = Some(StarknetBlockNumber::new_or_panic(22));
-                RefsTable::set_l1_l2_head(&tx, expected).unwrap();
-                assert_eq!(expected, RefsTable::get_l1_l2_head(&tx).unwrap());
+                for (block, expected) in blocks[..3].iter().zip([30, 5, 0]) {
+                    let total =
+                        StarknetTransactionsTable::get_block_total_fee(&tx, block.hash.into())
+                            .unwrap();
+                    assert_eq!(total, Some(fee(expected)));
+                }
 
-                let expected = Some(StarknetBlockNumber::new_or_panic(25));
-                RefsTable::set_l1_l2_head(&tx, expected).unwrap();
-                assert_eq!(expected, RefsTable::get_l1_l2_head(&tx).unwrap());
+                let latest = StarknetTransactionsTable::get_block_total_fee(
+                    &tx,
+                    StarknetBlocksBlockId::Latest,
+                )
+                .unwrap();
+                assert_eq!(latest, Some(fee(0)));
 
-                RefsTable::set_l1_l2_head(&tx, None).unwrap();
-                assert_eq!(None, RefsTable::get_l1_l2_head(&tx).unwrap());
+                let unknown = StarknetTransactionsTable::get_block_total_fee(
+                    &tx,
+                    StarknetBlockNumber::new_or_panic(3).into(),
+                )
+                .unwrap();
+                assert_eq!(unknown, None);
             }
         }
-    }
 
-    mod l1_state_table {
-        use super::*;
+        #[test]
+        fn get_transaction_data_for_block_paged() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
 
-        /// Creates a set of consecutive [StateUpdateLog]s starting from L2 genesis,
-        /// with arbitrary other values.
-        fn create_updates() -> [StateUpdateLog; 3] {
-            (0..3)
-                .map(|i| StateUpdateLog {
-                    origin: EthOrigin {
-                        block: BlockOrigin {
-                            hash: EthereumBlockHash(H256::from_low_u64_le(i + 33)),
-                            number: EthereumBlockNumber(i + 12_000),
-                        },
-                        transaction: TransactionOrigin {
-                            hash: EthereumTransactionHash(H256::from_low_u64_le(i + 999)),
-                            index: EthereumTransactionIndex(i + 20_000),
-                        },
-                        log_index: EthereumLogIndex(i + 500),
-                    },
-                    global_root: GlobalRoot(
-                        StarkHash::from_hex_str(&"3".repeat(i as usize + 1)).unwrap(),
-                    ),
-                    block_number: StarknetBlockNumber::GENESIS + i,
-                })
-                .collect::<Vec<_>>()
-                .try_into()
-                .unwrap()
+            let block = &test_utils::create_blocks()[0];
+            tx.execute(
+                "DELETE FROM starknet_transactions WHERE block_hash = ? AND idx >= 5",
+                [block.hash],
+            )
+            .unwrap();
+            let expected =
+                StarknetTransactionsTable::get_transaction_data_for_block(&tx, block.number.into())
+                    .unwrap();
+            assert_eq!(expected.len(), 5);
+
+            let mut pages = Vec::new();
+            for offset in (0..6).step_by(2) {
+                let (page, total) =
+                    StarknetTransactionsTable::get_transaction_data_for_block_paged(
+                        &tx,
+                        block.hash.into(),
+                        offset,
+                        2,
+                    )
+                    .unwrap();
+                assert_eq!(total, 5);
+                pages.push(page);
+            }
+
+            assert_eq!(pages.iter().map(Vec::len).collect::<Vec<_>>(), [2, 2, 1]);
+            assert_eq!(pages.concat(), expected);
         }
 
-        mod get {
+        mod get_transaction_hashes_for_block {
             use super::*;
 
             #[test]
-            fn none() {
-                let storage = Storage::in_memory().unwrap();
+            fn insertion_order() {
+                let (storage, _) = test_utils::setup_test_storage();
                 let mut connection = storage.connection().unwrap();
                 let tx = connection.transaction().unwrap();
 
-                let updates = create_updates();
-                for update in &updates {
-                    L1StateTable::upsert(&tx, update).unwrap();
+                let transactions = test_utils::create_transactions_and_receipts();
+                let blocks = test_utils::create_blocks();
+                for (block, chunk) in blocks
+                    .iter()
+                    .zip(transactions.chunks(test_utils::TRANSACTIONS_PER_BLOCK))
+                {
+                    let expected = chunk.iter().map(|(tx, _)| tx.hash()).collect::<Vec<_>>();
+
+                    for id in [block.number.into(), block.hash.into()] {
+                        let hashes =
+                            StarknetTransactionsTable::get_transaction_hashes_for_block(&tx, id)
+                                .unwrap();
+                        assert_eq!(hashes, expected);
+                    }
                 }
 
-                let non_existent = updates.last().unwrap().block_number + 1;
-                assert_eq!(L1StateTable::get(&tx, non_existent.into()).unwrap(), None);
+                let latest = StarknetTransactionsTable::get_transaction_hashes_for_block(
+                    &tx,
+                    StarknetBlocksBlockId::Latest,
+                )
+                .unwrap();
+                let expected = transactions
+                    [transactions.len() - test_utils::TRANSACTIONS_PER_BLOCK..]
+                    .iter()
+                    .map(|(tx, _)| tx.hash())
+                    .collect::<Vec<_>>();
+                assert_eq!(latest, expected);
             }
 
             #[test]
-            fn some() {
-                let storage = Storage::in_memory().unwrap();
+            fn missing_block() {
+                let (storage, _) = test_utils::setup_test_storage();
                 let mut connection = storage.connection().unwrap();
                 let tx = connection.transaction().unwrap();
 
-                let updates = create_updates();
-                for update in &updates {
-                    L1StateTable::upsert(&tx, update).unwrap();
+                for id in [
+                    StarknetBlockNumber::new_or_panic(test_utils::NUM_BLOCKS as u64).into(),
+                    StarknetBlockHash(StarkHash::from_u128(0xdead)).into(),
+                ] {
+                    let hashes =
+                        StarknetTransactionsTable::get_transaction_hashes_for_block(&tx, id)
+                            .unwrap();
+                    assert_eq!(hashes, vec![]);
                 }
 
-                for (idx, update) in updates.iter().enumerate() {
-                    assert_eq!(
-                        L1StateTable::get(&tx, update.block_number.into())
-                            .unwrap()
-                            .as_ref(),
-                        Some(update),
-                        "Update {}",
-                        idx
-                    );
-                }
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+                let hashes = StarknetTransactionsTable::get_transaction_hashes_for_block(
+                    &tx,
+                    StarknetBlocksBlockId::Latest,
+                )
+                .unwrap();
+                assert_eq!(hashes, vec![]);
             }
+        }
 
-            mod latest {
-                use super::*;
+        mod get_failed_transactions_for_block {
+            use super::*;
 
-                #[test]
-                fn none() {
-                    let storage = Storage::in_memory().unwrap();
-                    let mut connection = storage.connection().unwrap();
-                    let tx = connection.transaction().unwrap();
+            #[test]
+            fn mixed_statuses() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
 
-                    assert_eq!(
-                        L1StateTable::get(&tx, L1TableBlockId::Latest).unwrap(),
-                        None
-                    );
+                let block = test_utils::create_blocks()[0].clone();
+                StarknetBlocksTable::insert(&tx, &block, None).unwrap();
+                CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
+
+                let mut data = test_utils::create_transactions_and_receipts()
+                    [..test_utils::TRANSACTIONS_PER_BLOCK]
+                    .to_vec();
+                for i in [1, 4, 5] {
+                    data[i].1.execution_status = transaction::ExecutionStatus::Reverted;
+                }
+                StarknetTransactionsTable::upsert(&tx, block.hash, block.number, &data).unwrap();
+
+                let expected = [1, 4, 5].map(|i| data[i].0.hash()).to_vec();
+                for id in [
+                    block.number.into(),
+                    block.hash.into(),
+                    StarknetBlocksBlockId::Latest,
+                ] {
+                    let failed =
+                        StarknetTransactionsTable::get_failed_transactions_for_block(&tx, id)
+                            .unwrap();
+                    assert_eq!(failed, expected);
                 }
 
-                #[test]
-                fn some() {
-                    let storage = Storage::in_memory().unwrap();
-                    let mut connection = storage.connection().unwrap();
-                    let tx = connection.transaction().unwrap();
+                // The status survives the receipt's round trip.
+                let stored = StarknetTransactionsTable::get_transaction_data_for_block(
+                    &tx,
+                    block.hash.into(),
+                )
+                .unwrap();
+                assert_eq!(stored, data);
+            }
 
-                    let updates = create_updates();
-                    for update in &updates {
-                        L1StateTable::upsert(&tx, update).unwrap();
-                    }
+            #[test]
+            fn all_succeeded() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
 
-                    assert_eq!(
-                        L1StateTable::get(&tx, L1TableBlockId::Latest)
-                            .unwrap()
-                            .as_ref(),
-                        updates.last()
-                    );
-                }
+                let failed = StarknetTransactionsTable::get_failed_transactions_for_block(
+                    &tx,
+                    StarknetBlocksBlockId::Latest,
+                )
+                .unwrap();
+                assert_eq!(failed, vec![]);
             }
         }
 
-        mod get_root {
+        mod block_resolution {
             use super::*;
 
             #[test]
-            fn none() {
-                let storage = Storage::in_memory().unwrap();
+            fn by_number_hash_and_latest() {
+                let (storage, _) = test_utils::setup_test_storage();
                 let mut connection = storage.connection().unwrap();
                 let tx = connection.transaction().unwrap();
 
-                let updates = create_updates();
-                for update in &updates {
-                    L1StateTable::upsert(&tx, update).unwrap();
+                let transactions = test_utils::create_transactions_and_receipts();
+                let blocks = test_utils::create_blocks();
+                let chunks = transactions
+                    .chunks(test_utils::TRANSACTIONS_PER_BLOCK)
+                    .collect::<Vec<_>>();
+
+                let latest = blocks.last().unwrap();
+                let cases = blocks
+                    .iter()
+                    .zip(&chunks)
+                    .flat_map(|(block, chunk)| {
+                        [
+                            (StarknetBlocksBlockId::from(block.number), *chunk),
+                            (StarknetBlocksBlockId::from(block.hash), *chunk),
+                        ]
+                    })
+                    .chain(std::iter::once((
+                        StarknetBlocksBlockId::Latest,
+                        *chunks.last().unwrap(),
+                    )));
+
+                for (id, chunk) in cases {
+                    let data =
+                        StarknetTransactionsTable::get_transaction_data_for_block(&tx, id).unwrap();
+                    assert_eq!(data, chunk, "{id:?}");
+
+                    let transaction =
+                        StarknetTransactionsTable::get_transaction_at_block(&tx, id, 1).unwrap();
+                    assert_eq!(transaction, Some(chunk[1].0.clone()), "{id:?}");
                 }
 
-                let non_existent = updates.last().unwrap().block_number + 1;
-                assert_eq!(
-                    L1StateTable::get_root(&tx, non_existent.into()).unwrap(),
-                    None
-                );
+                let past_end = StarknetTransactionsTable::get_transaction_at_block(
+                    &tx,
+                    latest.hash.into(),
+                    test_utils::TRANSACTIONS_PER_BLOCK,
+                )
+                .unwrap();
+                assert_eq!(past_end, None);
             }
 
             #[test]
-            fn some() {
-                let storage = Storage::in_memory().unwrap();
+            fn missing_block() {
+                let (storage, _) = test_utils::setup_test_storage();
                 let mut connection = storage.connection().unwrap();
                 let tx = connection.transaction().unwrap();
 
-                let updates = create_updates();
-                for update in &updates {
-                    L1StateTable::upsert(&tx, update).unwrap();
+                for id in [
+                    StarknetBlockNumber::new_or_panic(test_utils::NUM_BLOCKS as u64).into(),
+                    StarknetBlockHash(StarkHash::from_u128(0xdead)).into(),
+                ] {
+                    let data =
+                        StarknetTransactionsTable::get_transaction_data_for_block(&tx, id).unwrap();
+                    assert_eq!(data, vec![]);
+                    let transaction =
+                        StarknetTransactionsTable::get_transaction_at_block(&tx, id, 0).unwrap();
+                    assert_eq!(transaction, None);
                 }
 
-                for (idx, update) in updates.iter().enumerate() {
-                    assert_eq!(
-                        L1StateTable::get_root(&tx, update.block_number.into()).unwrap(),
-                        Some(update.global_root),
-                        "Update {}",
-                        idx
-                    );
-                }
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+                let data = StarknetTransactionsTable::get_transaction_data_for_block(
+                    &tx,
+                    StarknetBlocksBlockId::Latest,
+                )
+                .unwrap();
+                assert_eq!(data, vec![]);
+                let transaction = StarknetTransactionsTable::get_transaction_at_block(
+                    &tx,
+                    StarknetBlocksBlockId::Latest,
+                    0,
+                )
+                .unwrap();
+                assert_eq!(transaction, None);
             }
+        }
 
-            mod latest {
-                use super::*;
+        mod get_block_execution_summary {
+            use super::*;
 
-                #[test]
-                fn none() {
-                    let storage = Storage::in_memory().unwrap();
-                    let mut connection = storage.connection().unwrap();
-                    let tx = connection.transaction().unwrap();
+            #[test]
+            fn mixed_resources() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
 
-                    assert_eq!(
-                        L1StateTable::get_root(&tx, L1TableBlockId::Latest).unwrap(),
-                        None
-                    );
+                let block = test_utils::create_blocks()[0].clone();
+                StarknetBlocksTable::insert(&tx, &block, None).unwrap();
+                CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
+
+                let mut data = test_utils::create_transactions_and_receipts()
+                    [..test_utils::TRANSACTIONS_PER_BLOCK]
+                    .to_vec();
+                for (_, receipt) in data.iter_mut().step_by(2) {
+                    receipt.execution_resources = None;
                 }
+                StarknetTransactionsTable::upsert(&tx, block.hash, block.number, &data).unwrap();
+
+                let resources = data
+                    .iter()
+                    .filter_map(|(_, receipt)| receipt.execution_resources)
+                    .collect::<Vec<_>>();
+                assert!(!resources.is_empty() && resources.len() < data.len());
+                let expected = ExecutionSummary {
+                    total_steps: resources.iter().map(|r| r.n_steps).sum(),
+                    total_memory_holes: resources.iter().map(|r| r.n_memory_holes).sum(),
+                    tx_count: data.len(),
+                };
 
-                #[test]
-                fn some() {
-                    let storage = Storage::in_memory().unwrap();
-                    let mut connection = storage.connection().unwrap();
-                    let tx = connection.transaction().unwrap();
+                for id in [
+                    block.number.into(),
+                    block.hash.into(),
+                    StarknetBlocksBlockId::Latest,
+                ] {
+                    let summary =
+                        StarknetTransactionsTable::get_block_execution_summary(&tx, id).unwrap();
+                    assert_eq!(summary, Some(expected.clone()));
+                }
+            }
 
-                    let updates = create_updates();
-                    for update in &updates {
-                        L1StateTable::upsert(&tx, update).unwrap();
-                    }
+            #[test]
+            fn known_empty_block() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
 
-                    assert_eq!(
-                        L1StateTable::get_root(&tx, L1TableBlockId::Latest).unwrap(),
-                        Some(updates.last().unwrap().global_root)
-                    );
+                let block = &test_utils::create_blocks()[0];
+                StarknetBlocksTable::insert(&tx, block, None).unwrap();
+
+                let summary =
+                    StarknetTransactionsTable::get_block_execution_summary(&tx, block.hash.into())
+                        .unwrap();
+                assert_eq!(
+                    summary,
+                    Some(ExecutionSummary {
+                        total_steps: 0,
+                        total_memory_holes: 0,
+                        tx_count: 0,
+                    })
+                );
+            }
+
+            #[test]
+            fn unknown_block() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                for id in [
+                    StarknetBlockNumber::new_or_panic(test_utils::NUM_BLOCKS as u64).into(),
+                    StarknetBlockHash(StarkHash::from_u128(0xdead)).into(),
+                ] {
+                    let summary =
+                        StarknetTransactionsTable::get_block_execution_summary(&tx, id).unwrap();
+                    assert_eq!(summary, None);
                 }
+
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+                let summary = StarknetTransactionsTable::get_block_execution_summary(
+                    &tx,
+                    StarknetBlocksBlockId::Latest,
+                )
+                .unwrap();
+                assert_eq!(summary, None);
             }
         }
 
-        mod reorg {
+        mod get_transaction_count {
             use super::*;
 
             #[test]
-            fn full() {
-                let storage = Storage::in_memory().unwrap();
+            fn known_blocks() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                for block in test_utils::create_blocks() {
+                    let expected = Some(test_utils::TRANSACTIONS_PER_BLOCK);
+                    let count =
+                        StarknetTransactionsTable::get_transaction_count(&tx, block.number.into())
+                            .unwrap();
+                    assert_eq!(count, expected);
+                    let count =
+                        StarknetTransactionsTable::get_transaction_count(&tx, block.hash.into())
+                            .unwrap();
+                    assert_eq!(count, expected);
+                }
+
+                let count = StarknetTransactionsTable::get_transaction_count(
+                    &tx,
+                    StarknetBlocksBlockId::Latest,
+                )
+                .unwrap();
+                assert_eq!(count, Some(test_utils::TRANSACTIONS_PER_BLOCK));
+            }
+
+            #[test]
+            fn known_empty_block() {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.writer().unwrap();
+                let tx = connection.transaction().unwrap();
+
+                let block = StarknetBlock {
+                    transaction_count: 0,
+                    event_count: 0,
+                    ..test_utils::create_blocks()[0].clone()
+                };
+                StarknetBlocksTable::insert(&tx, &block, None).unwrap();
+
+                for id in [
+                    block.number.into(),
+                    block.hash.into(),
+                    StarknetBlocksBlockId::Latest,
+                ] {
+                    let count = StarknetTransactionsTable::get_transaction_count(&tx, id).unwrap();
+                    assert_eq!(count, Some(0));
+                }
+            }
+
+            #[test]
+            fn unknown_block() {
+                let (storage, _) = test_utils::setup_test_storage();
                 let mut connection = storage.connection().unwrap();
                 let tx = connection.transaction().unwrap();
 
-                let updates = create_updates();
-                for update in &updates {
-                    L1StateTable::upsert(&tx, update).unwrap();
-                }
-
-                L1StateTable::reorg(&tx, StarknetBlockNumber::GENESIS).unwrap();
+                let count = StarknetTransactionsTable::get_transaction_count(
+                    &tx,
+                    StarknetBlockHash(StarkHash::from_u128(0xdead)).into(),
+                )
+                .unwrap();
+                assert_eq!(count, None);
 
-                assert_eq!(
-                    L1StateTable::get(&tx, L1TableBlockId::Latest).unwrap(),
-                    None
-                );
+                let count = StarknetTransactionsTable::get_transaction_count(
+                    &tx,
+                    StarknetBlockNumber::new_or_panic(test_utils::NUM_BLOCKS as u64).into(),
+                )
+                .unwrap();
+                assert_eq!(count, None);
             }
 
             #[test]
-            fn partial() {
+            fn empty_database() {
                 let storage = Storage::in_memory().unwrap();
                 let mut connection = storage.connection().unwrap();
                 let tx = connection.transaction().unwrap();
 
-                let updates = create_updates();
-                for update in &updates {
-                    L1StateTable::upsert(&tx, update).unwrap();
-                }
-
-                let reorg_tail = updates[1].block_number;
-                L1StateTable::reorg(&tx, reorg_tail).unwrap();
-
-                assert_eq!(
-                    L1StateTable::get(&tx, L1TableBlockId::Latest)
-                        .unwrap()
-                        .as_ref(),
-                    Some(&updates[0])
-                );
+                let count = StarknetTransactionsTable::get_transaction_count(
+                    &tx,
+                    StarknetBlocksBlockId::Latest,
+                )
+                .unwrap();
+                assert_eq!(count, None);
             }
         }
-    }
-
-    mod starknet_blocks {
-        use super::*;
-        use crate::storage::test_utils;
-
-        fn create_blocks() -> [StarknetBlock; test_utils::NUM_BLOCKS] {
-            test_utils::create_blocks()
-        }
 
-        fn with_default_blocks<F>(f: F)
-        where
-            F: FnOnce(&Transaction<'_>, [StarknetBlock; test_utils::NUM_BLOCKS]),
-        {
+        #[test]
+        fn count_all() {
             let storage = Storage::in_memory().unwrap();
             let mut connection = storage.connection().unwrap();
             let tx = connection.transaction().unwrap();
+            assert_eq!(StarknetTransactionsTable::count_all(&tx).unwrap(), 0);
+            drop(tx);
 
-            let blocks = create_blocks();
-            for block in &blocks {
-                StarknetBlocksTable::insert(&tx, block, None).unwrap();
-            }
-
-            f(&tx, blocks)
+            test_utils::insert_test_data(&storage);
+            let tx = connection.transaction().unwrap();
+            assert_eq!(
+                StarknetTransactionsTable::count_all(&tx).unwrap(),
+                test_utils::NUM_TRANSACTIONS as u64
+            );
         }
 
-        mod get {
+        mod get_inclusion_proof {
             use super::*;
+            use crate::state::block_hash::transaction_commitment_tree;
 
-            mod by_number {
-                use super::*;
-
-                #[test]
-                fn some() {
-                    with_default_blocks(|tx, blocks| {
-                        for block in blocks {
-                            let result = StarknetBlocksTable::get(tx, block.number.into())
-                                .unwrap()
-                                .unwrap();
-
-                            assert_eq!(result, block);
-                        }
-                    })
-                }
-
-                #[test]
-                fn none() {
-                    with_default_blocks(|tx, blocks| {
-                        let non_existent = blocks.last().unwrap().number + 1;
-                        assert_eq!(
-                            StarknetBlocksTable::get(tx, non_existent.into()).unwrap(),
-                            None
-                        );
-                    });
-                }
-            }
+            #[test]
+            fn verifies_against_commitment() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
 
-            mod by_hash {
-                use super::*;
+                let block = &test_utils::create_blocks()[1];
+                let transactions = test_utils::create_transactions_and_receipts()
+                    [test_utils::TRANSACTIONS_PER_BLOCK..2 * test_utils::TRANSACTIONS_PER_BLOCK]
+                    .iter()
+                    .map(|(transaction, _)| transaction.clone())
+                    .collect::<Vec<_>>();
+                let commitment = transaction_commitment_tree(&transactions)
+                    .unwrap()
+                    .commit()
+                    .unwrap();
 
-                #[test]
-                fn some() {
-                    with_default_blocks(|tx, blocks| {
-                        for block in blocks {
-                            let result = StarknetBlocksTable::get(tx, block.hash.into())
-                                .unwrap()
-                                .unwrap();
+                for (index, transaction) in transactions.iter().enumerate() {
+                    let proof =
+                        StarknetTransactionsTable::get_inclusion_proof(&tx, transaction.hash())
+                            .unwrap()
+                            .unwrap();
 
-                            assert_eq!(result, block);
-                        }
-                    });
-                }
+                    assert_eq!(proof.block_hash, block.hash);
+                    assert_eq!(proof.transaction_index, index as u64);
+                    assert_eq!(proof.transaction_commitment, commitment);
+                    assert!(proof.verify());
 
-                #[test]
-                fn none() {
-                    with_default_blocks(|tx, _blocks| {
-                        let non_existent =
-                            StarknetBlockHash(StarkHash::from_hex_str(&"b".repeat(10)).unwrap());
-                        assert_eq!(
-                            StarknetBlocksTable::get(tx, non_existent.into()).unwrap(),
-                            None
-                        );
-                    });
+                    let tampered = TransactionInclusionProof {
+                        leaf: proof.leaf + StarkHash::from_u128(1),
+                        ..proof
+                    };
+                    assert!(!tampered.verify());
                 }
             }
 
-            mod latest {
-                use super::*;
-
-                #[test]
-                fn some() {
-                    with_default_blocks(|tx, blocks| {
-                        let expected = blocks.last().unwrap();
-
-                        let latest = StarknetBlocksTable::get(tx, StarknetBlocksBlockId::Latest)
-                            .unwrap()
-                            .unwrap();
-                        assert_eq!(&latest, expected);
-                    })
-                }
-
-                #[test]
-                fn none() {
-                    let storage = Storage::in_memory().unwrap();
-                    let mut connection = storage.connection().unwrap();
-                    let tx = connection.transaction().unwrap();
+            #[test]
+            fn missing_transaction() {
+                let (storage, _) = test_utils::setup_test_storage();
+                let mut connection = storage.connection().unwrap();
+                let tx = connection.transaction().unwrap();
 
-                    let latest =
-                        StarknetBlocksTable::get(&tx, StarknetBlocksBlockId::Latest).unwrap();
-                    assert_eq!(latest, None);
-                }
+                let proof = StarknetTransactionsTable::get_inclusion_proof(
+                    &tx,
+                    StarknetTransactionHash(StarkHash::from_u128(0xdead)),
+                )
+                .unwrap();
+                assert_eq!(proof, None);
             }
+        }
 
-            mod number_by_hash {
-                use super::*;
+        #[test]
+        fn compression_levels_interoperate() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
 
-                #[test]
-                fn some() {
-                    with_default_blocks(|tx, blocks| {
-                        for block in blocks {
-                            let result = StarknetBlocksTable::get_number(tx, block.hash)
-                                .unwrap()
-                                .unwrap();
+            let blocks = test_utils::create_blocks();
+            let transactions_and_receipts = test_utils::create_transactions_and_receipts();
+            let levels = [1, 3, 10, 19];
+            assert_eq!(levels.len(), blocks.len());
 
-                            assert_eq!(result, block.number);
-                        }
-                    });
+            for (i, (block, level)) in blocks.iter().zip(levels).enumerate() {
+                let mut compressor = CompressionConfig {
+                    level,
+                    ..Default::default()
                 }
+                .compressor()
+                .unwrap();
+                StarknetBlocksTable::insert(&tx, block, None).unwrap();
+                CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
+                StarknetTransactionsTable::upsert_with_compressor(
+                    &tx,
+                    &mut compressor,
+                    block.hash,
+                    block.number,
+                    &transactions_and_receipts[i * test_utils::TRANSACTIONS_PER_BLOCK
+                        ..(i + 1) * test_utils::TRANSACTIONS_PER_BLOCK],
+                )
+                .unwrap();
+            }
 
-                #[test]
-                fn none() {
-                    with_default_blocks(|tx, _blocks| {
-                        let non_existent =
-                            StarknetBlockHash(StarkHash::from_hex_str(&"b".repeat(10)).unwrap());
-                        assert_eq!(
-                            StarknetBlocksTable::get_number(tx, non_existent).unwrap(),
-                            None
-                        );
-                    });
-                }
+            for (i, block) in blocks.iter().enumerate() {
+                let data = StarknetTransactionsTable::get_transaction_data_for_block(
+                    &tx,
+                    block.hash.into(),
+                )
+                .unwrap();
+                assert_eq!(
+                    data,
+                    transactions_and_receipts[i * test_utils::TRANSACTIONS_PER_BLOCK
+                        ..(i + 1) * test_utils::TRANSACTIONS_PER_BLOCK]
+                );
             }
         }
+    }
 
-        mod get_root {
-            use super::*;
+    mod reorg_history {
+        use super::*;
 
-            mod by_number {
-                use super::*;
+        #[test]
+        fn newest_first() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
 
-                #[test]
-                fn some() {
-                    with_default_blocks(|tx, blocks| {
-                        for block in blocks {
-                            let root = StarknetBlocksTable::get_root(tx, block.number.into())
-                                .unwrap()
-                                .unwrap();
+            let hash = StarknetBlockHash(StarkHash::from_u64(0xabc));
+            ReorgHistoryTable::record_reorg(
+                &tx,
+                StarknetBlockNumber::new_or_panic(3),
+                StarknetBlockNumber::new_or_panic(5),
+                Some(hash),
+                100,
+                ReorgSource::L2,
+            )
+            .unwrap();
+            ReorgHistoryTable::record_reorg(
+                &tx,
+                StarknetBlockNumber::GENESIS,
+                StarknetBlockNumber::GENESIS,
+                None,
+                200,
+                ReorgSource::L1,
+            )
+            .unwrap();
 
-                            assert_eq!(root, block.root);
-                        }
-                    })
-                }
+            let reorgs = ReorgHistoryTable::get_recent_reorgs(&tx, 10).unwrap();
+            assert_eq!(
+                reorgs,
+                vec![
+                    ReorgRecord {
+                        tail: StarknetBlockNumber::GENESIS,
+                        old_head: StarknetBlockNumber::GENESIS,
+                        old_head_hash: None,
+                        timestamp: 200,
+                        source: ReorgSource::L1,
+                    },
+                    ReorgRecord {
+                        tail: StarknetBlockNumber::new_or_panic(3),
+                        old_head: StarknetBlockNumber::new_or_panic(5),
+                        old_head_hash: Some(hash),
+                        timestamp: 100,
+                        source: ReorgSource::L2,
+                    },
+                ]
+            );
+            assert_eq!(reorgs[0].depth(), 1);
+            assert_eq!(reorgs[1].depth(), 3);
+            let below_tail = ReorgRecord {
+                old_head: StarknetBlockNumber::new_or_panic(2),
+                ..reorgs[1].clone()
+            };
+            assert_eq!(below_tail.depth(), 0);
 
-                #[test]
-                fn none() {
-                    with_default_blocks(|tx, blocks| {
-                        let non_existent = blocks.last().unwrap().number + 1;
-                        assert_eq!(
-                            StarknetBlocksTable::get_root(tx, non_existent.into()).unwrap(),
-                            None
-                        );
-                    })
-                }
+            let reorgs = ReorgHistoryTable::get_recent_reorgs(&tx, 1).unwrap();
+            assert_eq!(reorgs.len(), 1);
+            assert_eq!(reorgs[0].timestamp, 200);
+        }
+
+        #[test]
+        fn pruned_past_max_entries() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let total = ReorgHistoryTable::MAX_ENTRIES as u64 + 5;
+            for i in 0..total {
+                ReorgHistoryTable::record_reorg(
+                    &tx,
+                    StarknetBlockNumber::GENESIS,
+                    StarknetBlockNumber::new_or_panic(i),
+                    None,
+                    i,
+                    ReorgSource::L2,
+                )
+                .unwrap();
             }
 
-            mod by_hash {
-                use super::*;
+            let reorgs = ReorgHistoryTable::get_recent_reorgs(&tx, total as usize).unwrap();
+            assert_eq!(reorgs.len(), ReorgHistoryTable::MAX_ENTRIES);
+            assert_eq!(reorgs.first().unwrap().timestamp, total - 1);
+            assert_eq!(reorgs.last().unwrap().timestamp, 5);
+        }
+    }
 
-                #[test]
-                fn some() {
-                    with_default_blocks(|tx, blocks| {
-                        for block in blocks {
-                            let root = StarknetBlocksTable::get_root(tx, block.hash.into())
-                                .unwrap()
-                                .unwrap();
+    mod contract_class_index {
+        use super::*;
 
-                            assert_eq!(root, block.root);
-                        }
-                    })
-                }
+        fn address(n: u8) -> ContractAddress {
+            ContractAddress::new_or_panic(StarkHash::from_u64(0xa0 + n as u64))
+        }
 
-                #[test]
-                fn none() {
-                    with_default_blocks(|tx, _blocks| {
-                        let non_existent =
-                            StarknetBlockHash(StarkHash::from_hex_str(&"b".repeat(10)).unwrap());
-                        assert_eq!(
-                            StarknetBlocksTable::get_root(tx, non_existent.into()).unwrap(),
-                            None
-                        );
-                    })
-                }
+        fn class(n: u8) -> ClassHash {
+            ClassHash(StarkHash::from_u64(0xc0 + n as u64))
+        }
+
+        fn counts(tx: &Transaction<'_>) -> (usize, usize) {
+            (
+                ContractClassIndexTable::count_contracts_by_class(tx, class(0)).unwrap(),
+                ContractClassIndexTable::count_contracts_by_class(tx, class(1)).unwrap(),
+            )
+        }
+
+        #[test]
+        fn replace_and_reorg() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let block0 = StarknetBlockNumber::GENESIS;
+            let block1 = StarknetBlockNumber::new_or_panic(1);
+
+            for (n, class) in [(0, class(0)), (1, class(0)), (2, class(1))] {
+                ContractClassIndexTable::insert(&tx, block0, address(n), class).unwrap();
             }
+            assert_eq!(counts(&tx), (2, 1));
+            assert_eq!(
+                ContractClassIndexTable::get_contracts_by_class(&tx, class(0), 0).unwrap(),
+                vec![address(0), address(1)]
+            );
 
-            mod latest {
-                use super::*;
+            ContractClassIndexTable::insert(&tx, block1, address(1), class(1)).unwrap();
+            assert_eq!(counts(&tx), (1, 2));
+            assert_eq!(
+                ContractClassIndexTable::get_contracts_by_class(&tx, class(1), 0).unwrap(),
+                vec![address(1), address(2)]
+            );
+            assert_eq!(
+                ContractClassIndexTable::get_class(&tx, address(1)).unwrap(),
+                Some(class(1))
+            );
 
-                #[test]
-                fn some() {
-                    with_default_blocks(|tx, blocks| {
-                        let expected = blocks.last().map(|block| block.root).unwrap();
+            ContractClassIndexTable::reorg(&tx, block1).unwrap();
+            assert_eq!(counts(&tx), (2, 1));
+            assert_eq!(
+                ContractClassIndexTable::get_class(&tx, address(1)).unwrap(),
+                Some(class(0))
+            );
 
-                        let latest =
-                            StarknetBlocksTable::get_root(tx, StarknetBlocksBlockId::Latest)
-                                .unwrap()
-                                .unwrap();
-                        assert_eq!(latest, expected);
-                    })
-                }
+            ContractClassIndexTable::reorg(&tx, block0).unwrap();
+            assert_eq!(counts(&tx), (0, 0));
+            assert_eq!(
+                ContractClassIndexTable::get_class(&tx, address(1)).unwrap(),
+                None
+            );
+        }
 
-                #[test]
-                fn none() {
-                    let storage = Storage::in_memory().unwrap();
-                    let mut connection = storage.connection().unwrap();
-                    let tx = connection.transaction().unwrap();
+        #[test]
+        fn pages() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
 
-                    let latest_root =
-                        StarknetBlocksTable::get_root(&tx, StarknetBlocksBlockId::Latest).unwrap();
-                    assert_eq!(latest_root, None);
-                }
+            let total = ContractClassIndexTable::PAGE_SIZE + 3;
+            for n in 0..total {
+                let address = ContractAddress::new_or_panic(StarkHash::from_u64(n as u64 + 1));
+                ContractClassIndexTable::insert(
+                    &tx,
+                    StarknetBlockNumber::GENESIS,
+                    address,
+                    class(0),
+                )
+                .unwrap();
             }
+
+            let first = ContractClassIndexTable::get_contracts_by_class(&tx, class(0), 0).unwrap();
+            let second = ContractClassIndexTable::get_contracts_by_class(&tx, class(0), 1).unwrap();
+            let third = ContractClassIndexTable::get_contracts_by_class(&tx, class(0), 2).unwrap();
+            assert_eq!(first.len(), ContractClassIndexTable::PAGE_SIZE);
+            assert_eq!(second.len(), 3);
+            assert!(third.is_empty());
+            assert!(first.last().unwrap() < second.first().unwrap());
         }
+    }
 
-        mod reorg {
-            use super::*;
+    mod contract_summaries {
+        use super::*;
 
-            #[test]
-            fn full() {
-                with_default_blocks(|tx, _blocks| {
-                    // reorg to genesis expected to wipe the blocks
-                    StarknetBlocksTable::reorg(tx, StarknetBlockNumber::GENESIS).unwrap();
+        fn contract(n: u64) -> DeployedContract {
+            DeployedContract {
+                address: ContractAddress::new_or_panic(StarkHash::from_u64(0xa0 + n)),
+                class_hash: ClassHash(StarkHash::from_u64(0xc0 + n % 2)),
+            }
+        }
 
-                    assert_eq!(
-                        StarknetBlocksTable::get(tx, StarknetBlocksBlockId::Latest).unwrap(),
-                        None
-                    );
-                })
+        fn nonce(contract: &DeployedContract, n: u64) -> Nonce {
+            Nonce {
+                contract_address: contract.address,
+                nonce: ContractNonce(StarkHash::from_u64(n)),
             }
+        }
 
-            #[test]
-            fn partial() {
-                with_default_blocks(|tx, blocks| {
-                    let reorg_tail = blocks[1].number;
-                    StarknetBlocksTable::reorg(tx, reorg_tail).unwrap();
+        #[test]
+        fn pages() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
 
-                    let expected = StarknetBlock {
-                        number: blocks[0].number,
-                        hash: blocks[0].hash,
-                        root: blocks[0].root,
-                        timestamp: blocks[0].timestamp,
-                        gas_price: blocks[0].gas_price,
-                        sequencer_address: blocks[0].sequencer_address,
+            // Deployed out of address order, some with nonce updates in a later block.
+            let contracts = (0..7).rev().map(contract).collect::<Vec<_>>();
+            let block0 = StarknetBlockNumber::GENESIS;
+            let block1 = StarknetBlockNumber::new_or_panic(1);
+            ContractSummariesTable::apply(&tx, block0, &contracts[..4], &[]).unwrap();
+            ContractSummariesTable::apply(
+                &tx,
+                block1,
+                &contracts[4..],
+                &[nonce(&contracts[0], 1), nonce(&contracts[5], 2)],
+            )
+            .unwrap();
+            assert_eq!(ContractSummariesTable::count_contracts(&tx).unwrap(), 7);
+
+            let mut listed = Vec::new();
+            let mut start_after = None;
+            let mut pages = 0;
+            loop {
+                let page = ContractSummariesTable::list_contracts(&tx, start_after, 3).unwrap();
+                if page.is_empty() {
+                    break;
+                }
+                start_after = Some(page.last().unwrap().address);
+                listed.extend(page);
+                pages += 1;
+            }
+            assert_eq!(pages, 3);
+
+            let expected = (0..7)
+                .map(|n| {
+                    let c = contract(n);
+                    let (deployed_at_block, nonce) = match n {
+                        6 => (block0, 1),
+                        1 => (block1, 2),
+                        n if n >= 3 => (block0, 0),
+                        _ => (block1, 0),
                     };
-
-                    assert_eq!(
-                        StarknetBlocksTable::get(tx, StarknetBlocksBlockId::Latest).unwrap(),
-                        Some(expected)
-                    );
+                    ContractSummary {
+                        address: c.address,
+                        class_hash: c.class_hash,
+                        nonce: ContractNonce(StarkHash::from_u64(nonce)),
+                        deployed_at_block,
+                    }
                 })
-            }
+                .collect::<Vec<_>>();
+            assert_eq!(listed, expected);
         }
 
-        mod interned_version {
-            use super::super::Storage;
-            use super::StarknetBlocksTable;
+        #[test]
+        fn reorg() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
 
-            #[test]
-            fn duplicate_versions_interned() {
-                let storage = Storage::in_memory().unwrap();
-                let mut connection = storage.connection().unwrap();
-                let tx = connection.transaction().unwrap();
+            let block0 = StarknetBlockNumber::GENESIS;
+            let block1 = StarknetBlockNumber::new_or_panic(1);
+            ContractSummariesTable::apply(&tx, block0, &[contract(0)], &[]).unwrap();
+            ContractSummariesTable::apply(&tx, block1, &[contract(1)], &[nonce(&contract(0), 1)])
+                .unwrap();
+            assert_eq!(ContractSummariesTable::count_contracts(&tx).unwrap(), 2);
 
-                let blocks = super::create_blocks();
-                let versions = ["0.9.1", "0.9.1"]
-                    .into_iter()
-                    .chain(std::iter::repeat("0.9.2"));
+            ContractSummariesTable::reorg(&tx, block1).unwrap();
+            assert_eq!(ContractSummariesTable::count_contracts(&tx).unwrap(), 1);
+            assert_eq!(
+                ContractSummariesTable::list_contracts(&tx, None, 10).unwrap(),
+                vec![ContractSummary {
+                    address: contract(0).address,
+                    class_hash: contract(0).class_hash,
+                    nonce: ContractNonce::ZERO,
+                    deployed_at_block: block0,
+                }]
+            );
+        }
 
-                let mut inserted = 0;
+        #[test]
+        fn nonce_of_unknown_contract_is_ignored() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
 
-                for (block, version) in blocks.iter().zip(versions) {
-                    StarknetBlocksTable::insert(&tx, block, Some(version)).unwrap();
-                    inserted += 1;
-                }
+            ContractSummariesTable::apply(
+                &tx,
+                StarknetBlockNumber::GENESIS,
+                &[],
+                &[nonce(&contract(0), 1)],
+            )
+            .unwrap();
+            assert_eq!(ContractSummariesTable::count_contracts(&tx).unwrap(), 0);
+        }
+    }
 
-                let rows = tx.prepare("select version_id, count(1) from starknet_blocks group by version_id order by version_id")
-                    .unwrap()
-                    .query([])
-                    .unwrap()
-                    .mapped(|r| Ok((r.get::<_, Option<i64>>(0)?, r.get::<_, i64>(1)?)))
-                    .collect::<Result<Vec<(Option<i64>, i64)>, _>>()
-                    .unwrap();
+    mod storage_updates {
+        use super::*;
 
-                // there should be two of 0.9.1
-                assert_eq!(rows.first(), Some(&(Some(1), 2)));
+        #[test]
+        fn value_changes() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
 
-                // there should be a few for 0.9.2 (initially the create_rows returned 3 => 1)
-                assert_eq!(rows.last(), Some(&(Some(2), inserted - 2)));
+            let address = ContractAddress::new_or_panic(StarkHash::from_u64(0xa0));
+            let key = StorageAddress::new_or_panic(StarkHash::from_u64(0x10));
+            let other_key = StorageAddress::new_or_panic(StarkHash::from_u64(0x11));
+            let diff = |key, value| StorageDiff {
+                address,
+                key,
+                value: StorageValue(StarkHash::from_u64(value)),
+            };
+            let value = |n| Some(StorageValue(StarkHash::from_u64(n)));
 
-                // we should not have any nulls
-                assert_eq!(rows.len(), 2, "nulls were not expected in {rows:?}");
-            }
+            let block = |n| StarknetBlockNumber::new_or_panic(n);
+            StorageUpdatesTable::insert(&tx, block(1), &[diff(key, 1), diff(other_key, 7)])
+                .unwrap();
+            StorageUpdatesTable::insert(&tx, block(3), &[diff(key, 2)]).unwrap();
+
+            let get = |key, n| {
+                StorageUpdatesTable::get_storage_at_block(&tx, address, key, block(n)).unwrap()
+            };
+            assert_eq!(get(key, 0), None);
+            assert_eq!(get(key, 1), value(1));
+            assert_eq!(get(key, 2), value(1));
+            assert_eq!(get(key, 3), value(2));
+            assert_eq!(get(key, 10), value(2));
+            assert_eq!(get(other_key, 10), value(7));
+
+            StorageUpdatesTable::reorg(&tx, block(3)).unwrap();
+            assert_eq!(get(key, 10), value(1));
+            assert_eq!(get(other_key, 10), value(7));
         }
+    }
 
-        mod get_latest_number {
-            use super::*;
+    mod deployed_contracts {
+        use super::*;
+        use crate::starkhash;
 
-            #[test]
-            fn some() {
-                with_default_blocks(|tx, blocks| {
-                    let latest = blocks.last().unwrap().number;
-                    assert_eq!(
-                        StarknetBlocksTable::get_latest_number(tx).unwrap(),
-                        Some(latest)
-                    );
-                });
+        fn contract(n: u8) -> DeployedContract {
+            DeployedContract {
+                address: ContractAddress::new_or_panic(StarkHash::from_u64(0xa0 + n as u64)),
+                class_hash: ClassHash(StarkHash::from_u64(0xc0 + n as u64)),
             }
+        }
 
-            #[test]
-            fn none() {
-                let storage = Storage::in_memory().unwrap();
-                let mut connection = storage.connection().unwrap();
-                let tx = connection.transaction().unwrap();
+        /// Inserts blocks `0..=2` with the given contracts deployed in each.
+        fn with_deployments<F>(deployments: [Vec<DeployedContract>; 3], f: F)
+        where
+            F: FnOnce(&Transaction<'_>),
+        {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
 
-                assert_eq!(StarknetBlocksTable::get_latest_number(&tx).unwrap(), None);
+            for (n, deployed_contracts) in deployments.into_iter().enumerate() {
+                let n = n as u8;
+                StarknetBlocksTable::insert(&tx, &StarknetBlock::nth(n), None).unwrap();
+                let mut update = StateUpdate::with_block_hash(n);
+                update.state_diff.deployed_contracts = deployed_contracts;
+                StarknetStateUpdatesTable::insert(&tx, update.block_hash.unwrap(), &update)
+                    .unwrap();
             }
-        }
 
-        mod get_latest_hash_and_number {
-            use super::*;
+            f(&tx)
+        }
 
-            #[test]
-            fn some() {
-                with_default_blocks(|tx, blocks| {
-                    let latest = blocks.last().unwrap();
-                    assert_eq!(
-                        StarknetBlocksTable::get_latest_hash_and_number(tx).unwrap(),
-                        Some((latest.hash, latest.number))
-                    );
-                });
-            }
+        #[test]
+        fn reorg() {
+            with_deployments([vec![], vec![], vec![contract(1)]], |tx| {
+                let block = StarknetBlockNumber::new_or_panic(2);
+                let expected = Some((block, contract(1).class_hash));
+                let deployment =
+                    DeployedContractsTable::get_deployment(tx, contract(1).address).unwrap();
+                assert_eq!(deployment, expected);
+
+                StarknetBlocksTable::reorg(tx, block).unwrap();
+
+                let deployment =
+                    DeployedContractsTable::get_deployment(tx, contract(1).address).unwrap();
+                assert_eq!(deployment, None);
+                let deployed =
+                    DeployedContractsTable::get_contracts_deployed_in_block(tx, block).unwrap();
+                assert_eq!(deployed, vec![]);
+            })
+        }
 
-            #[test]
-            fn none() {
-                let storage = Storage::in_memory().unwrap();
-                let mut connection = storage.connection().unwrap();
-                let tx = connection.transaction().unwrap();
+        #[test]
+        fn multiple_in_one_block() {
+            let contracts = vec![contract(1), contract(2), contract(3)];
+            with_deployments([vec![], contracts.clone(), vec![]], |tx| {
+                let block = StarknetBlockNumber::new_or_panic(1);
+                let deployed =
+                    DeployedContractsTable::get_contracts_deployed_in_block(tx, block).unwrap();
+                let expected = contracts
+                    .iter()
+                    .map(|c| (c.address, c.class_hash))
+                    .collect::<Vec<_>>();
+                assert_eq!(deployed, expected);
+
+                for c in &contracts {
+                    let deployment = DeployedContractsTable::get_deployment(tx, c.address).unwrap();
+                    assert_eq!(deployment, Some((block, c.class_hash)));
+                }
+            })
+        }
 
+        #[test]
+        fn redeployment_keeps_original() {
+            let redeployed = DeployedContract {
+                class_hash: ClassHash(starkhash!("ff")),
+                ..contract(1)
+            };
+            with_deployments([vec![], vec![contract(1)], vec![redeployed]], |tx| {
+                let deployment =
+                    DeployedContractsTable::get_deployment(tx, contract(1).address).unwrap();
                 assert_eq!(
-                    StarknetBlocksTable::get_latest_hash_and_number(&tx).unwrap(),
-                    None
+                    deployment,
+                    Some((StarknetBlockNumber::new_or_panic(1), contract(1).class_hash))
                 );
-            }
+
+                let deployed = DeployedContractsTable::get_contracts_deployed_in_block(
+                    tx,
+                    StarknetBlockNumber::new_or_panic(2),
+                )
+                .unwrap();
+                assert_eq!(deployed, vec![]);
+            })
         }
 
-        mod get_hash {
-            use super::*;
+        #[test]
+        fn none() {
+            with_deployments([vec![contract(1)], vec![], vec![]], |tx| {
+                let deployment =
+                    DeployedContractsTable::get_deployment(tx, contract(2).address).unwrap();
+                assert_eq!(deployment, None);
+            })
+        }
+    }
 
-            mod by_number {
-                use super::*;
+    mod canonical_blocks {
+        use super::*;
+        use crate::storage::test_utils;
 
-                #[test]
-                fn some() {
-                    with_default_blocks(|tx, blocks| {
-                        for block in blocks {
-                            let result = StarknetBlocksTable::get_hash(tx, block.number.into())
-                                .unwrap()
-                                .unwrap();
+        #[test]
+        fn contains() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
 
-                            assert_eq!(result, block.hash);
-                        }
-                    })
-                }
+            let blocks = test_utils::create_blocks();
+            for block in &blocks[..2] {
+                StarknetBlocksTable::insert(&tx, block, None).unwrap();
+                CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
+            }
+            let (genesis, orphan) = (&blocks[0], &blocks[1]);
 
-                #[test]
-                fn none() {
-                    with_default_blocks(|tx, blocks| {
-                        let non_existent = blocks.last().unwrap().number + 1;
-                        assert_eq!(
-                            StarknetBlocksTable::get(tx, non_existent.into()).unwrap(),
-                            None
-                        );
-                    });
-                }
+            assert!(CanonicalBlocksTable::contains(&tx, genesis.number, genesis.hash).unwrap());
+            assert!(CanonicalBlocksTable::contains(&tx, orphan.number, orphan.hash).unwrap());
+            // The hash must match the number.
+            assert!(!CanonicalBlocksTable::contains(&tx, orphan.number, genesis.hash).unwrap());
+
+            CanonicalBlocksTable::reorg(&tx, orphan.number).unwrap();
+
+            assert!(CanonicalBlocksTable::contains(&tx, genesis.number, genesis.hash).unwrap());
+            assert!(!CanonicalBlocksTable::contains(&tx, orphan.number, orphan.hash).unwrap());
+            assert_eq!(CanonicalBlocksTable::get(&tx, orphan.number).unwrap(), None);
+            // The orphaned block itself is still stored.
+            assert!(StarknetBlocksTable::get(&tx, orphan.hash.into())
+                .unwrap()
+                .is_some());
+        }
+    }
+
+    mod raw_blocks {
+        use super::*;
+        use crate::starkhash;
+
+        fn compressor() -> zstd::bulk::Compressor<'static> {
+            CompressionConfig::default().compressor().unwrap()
+        }
+
+        #[test]
+        fn round_trip() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+            let mut compressor = compressor();
+
+            let blocks = [
+                (
+                    StarknetBlockNumber::new_or_panic(0),
+                    StarknetBlockHash(starkhash!("0abc")),
+                    br#"{"block_number":0}"#.as_slice(),
+                ),
+                (
+                    StarknetBlockNumber::new_or_panic(1),
+                    StarknetBlockHash(starkhash!("0def")),
+                    br#"{"block_number":1}"#.as_slice(),
+                ),
+            ];
+            for (number, hash, json) in blocks {
+                assert!(RawBlocksTable::insert(&tx, number, hash, json, &mut compressor).unwrap());
+            }
+
+            for (number, hash, json) in blocks {
+                let by_number = RawBlocksTable::get(&tx, number.into()).unwrap();
+                assert_eq!(by_number.as_deref(), Some(json));
+                let by_hash = RawBlocksTable::get(&tx, hash.into()).unwrap();
+                assert_eq!(by_hash.as_deref(), Some(json));
             }
+            let latest = RawBlocksTable::get(&tx, StarknetBlocksBlockId::Latest).unwrap();
+            assert_eq!(latest.as_deref(), Some(blocks[1].2));
+
+            let missing = StarknetBlockNumber::new_or_panic(2);
+            assert_eq!(RawBlocksTable::get(&tx, missing.into()).unwrap(), None);
+        }
+
+        #[test]
+        fn insert_replaces_reorged_block() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+            let mut compressor = compressor();
 
-            mod latest {
-                use super::*;
+            let number = StarknetBlockNumber::GENESIS;
+            let original = StarknetBlockHash(starkhash!("0abc"));
+            let reorged = StarknetBlockHash(starkhash!("0def"));
 
-                #[test]
-                fn some() {
-                    with_default_blocks(|tx, blocks| {
-                        let expected = blocks.last().unwrap().hash;
+            assert!(RawBlocksTable::insert(&tx, number, original, b"1", &mut compressor).unwrap());
+            // The same block is not archived twice.
+            assert!(!RawBlocksTable::insert(&tx, number, original, b"1", &mut compressor).unwrap());
+            assert!(RawBlocksTable::insert(&tx, number, reorged, b"2", &mut compressor).unwrap());
 
-                        let latest =
-                            StarknetBlocksTable::get_hash(tx, StarknetBlocksNumberOrLatest::Latest)
-                                .unwrap()
-                                .unwrap();
-                        assert_eq!(latest, expected);
-                    })
-                }
+            assert_eq!(RawBlocksTable::get(&tx, original.into()).unwrap(), None);
+            assert_eq!(
+                RawBlocksTable::get(&tx, number.into()).unwrap().as_deref(),
+                Some(b"2".as_slice())
+            );
+        }
 
-                #[test]
-                fn none() {
-                    let storage = Storage::in_memory().unwrap();
-                    let mut connection = storage.connection().unwrap();
-                    let tx = connection.transaction().unwrap();
+        #[test]
+        fn prune() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+            let mut compressor = compressor();
 
-                    let latest =
-                        StarknetBlocksTable::get(&tx, StarknetBlocksBlockId::Latest).unwrap();
-                    assert_eq!(latest, None);
-                }
+            for i in 0..5u64 {
+                let number = StarknetBlockNumber::new_or_panic(i);
+                let hash = StarknetBlockHash(StarkHash::from_u128(i as u128));
+                RawBlocksTable::insert(&tx, number, hash, b"{}", &mut compressor).unwrap();
+            }
+
+            let pruned = RawBlocksTable::prune(&tx, StarknetBlockNumber::new_or_panic(3)).unwrap();
+            assert_eq!(pruned, 3);
+
+            for i in 0..5u64 {
+                let number = StarknetBlockNumber::new_or_panic(i);
+                let archived = RawBlocksTable::get(&tx, number.into()).unwrap();
+                assert_eq!(archived.is_some(), i >= 3, "block {i}");
             }
         }
     }
 
-    mod starknet_events {
-        use web3::types::H128;
-
+    mod l1_handler_origins {
         use super::*;
-
-        use crate::core::{EntryPoint, EventData, Fee};
-        use crate::sequencer::reply::transaction;
+        use crate::core::{EntryPoint, EthereumAddress};
         use crate::starkhash;
         use crate::storage::test_utils;
+        use web3::types::H160;
 
-        #[test]
-        fn event_data_serialization() {
-            let data = [
-                EventData(starkhash!("01")),
-                EventData(starkhash!("02")),
-                EventData(starkhash!("03")),
-            ];
+        /// Stores the first block, whose first transaction consumed the message with `nonce`.
+        fn setup(nonce: L1ToL2MessageNonce) -> (Storage, StarknetTransactionHash) {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
 
-            let mut buffer = Vec::new();
-            StarknetEventsTable::encode_event_data_to_bytes(&data, &mut buffer);
+            let transaction = insert_block(&tx, 0, nonce);
+            tx.commit().unwrap();
 
-            assert_eq!(
-                &buffer,
-                &[
-                    0u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                    0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3
-                ]
-            );
+            (storage, transaction)
+        }
+
+        /// Stores the block `index` of [test_utils::create_blocks], whose first transaction
+        /// consumed the message with `nonce`, and returns that transaction's hash.
+        fn insert_block(
+            tx: &Transaction<'_>,
+            index: usize,
+            nonce: L1ToL2MessageNonce,
+        ) -> StarknetTransactionHash {
+            let block = &test_utils::create_blocks()[index];
+            StarknetBlocksTable::insert(tx, block, None).unwrap();
+            CanonicalBlocksTable::insert(tx, block.number, block.hash).unwrap();
+
+            let transactions = index * test_utils::TRANSACTIONS_PER_BLOCK
+                ..(index + 1) * test_utils::TRANSACTIONS_PER_BLOCK;
+            let mut transaction_data =
+                test_utils::create_transactions_and_receipts()[transactions].to_vec();
+            transaction_data[0].1.l1_to_l2_consumed_message = Some(transaction::L1ToL2Message {
+                from_address: EthereumAddress(H160::from_low_u64_be(1)),
+                payload: Vec::new(),
+                selector: EntryPoint(starkhash!("02")),
+                to_address: ContractAddress::new_or_panic(starkhash!("03")),
+                nonce: Some(nonce),
+            });
+            StarknetTransactionsTable::upsert(tx, block.hash, block.number, &transaction_data)
+                .unwrap();
+
+            transaction_data[0].0.hash()
         }
 
         #[test]
-        fn event_keys_to_base64_strings() {
-            let event = transaction::Event {
-                from_address: ContractAddress::new_or_panic(starkhash!(
-                    "06fbd460228d843b7fbef670ff15607bf72e19fa94de21e29811ada167b4ca39"
-                )),
-                data: vec![],
-                keys: vec![
-                    EventKey(starkhash!("901823")),
-                    EventKey(starkhash!("901824")),
-                    EventKey(starkhash!("901825")),
-                ],
+        fn insert_then_attach() {
+            let nonce = L1ToL2MessageNonce(starkhash!("07"));
+            let (storage, transaction) = setup(nonce);
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let mut expected = L1HandlerOrigin {
+                starknet_transaction_hash: transaction,
+                message_nonce: nonce,
+                ethereum_transaction_hash: None,
             };
+            let origin = L1HandlerOriginsTable::get_by_starknet_transaction(&tx, transaction)
+                .unwrap()
+                .unwrap();
+            assert_eq!(origin, expected);
 
-            let mut buf = String::new();
-            StarknetEventsTable::event_keys_to_base64_strings(&event.keys, &mut buf);
-            assert_eq!(buf.capacity(), buf.len());
-            assert_eq!(
-                buf,
-                "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAACQGCM= AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAACQGCQ= AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAACQGCU="
-            );
+            let ethereum_transaction = EthereumTransactionHash(H256::from_low_u64_be(0xabc));
+            assert!(L1HandlerOriginsTable::attach_ethereum_origin(
+                &tx,
+                nonce,
+                ethereum_transaction,
+                EthereumBlockNumber(10)
+            )
+            .unwrap());
+            expected.ethereum_transaction_hash = Some(ethereum_transaction);
+
+            let origin = L1HandlerOriginsTable::get_by_starknet_transaction(&tx, transaction)
+                .unwrap()
+                .unwrap();
+            assert_eq!(origin, expected);
+            let origin = L1HandlerOriginsTable::get_l1_handler_by_message(&tx, nonce)
+                .unwrap()
+                .unwrap();
+            assert_eq!(origin, expected);
+
+            // Storing the transactions again keeps the attached origin.
+            let block = &test_utils::create_blocks()[0];
+            let transaction_data =
+                StarknetTransactionsTable::get_transaction_data_for_block(&tx, block.hash.into())
+                    .unwrap();
+            StarknetTransactionsTable::upsert(&tx, block.hash, block.number, &transaction_data)
+                .unwrap();
+            let origin = L1HandlerOriginsTable::get_l1_handler_by_message(&tx, nonce)
+                .unwrap()
+                .unwrap();
+            assert_eq!(origin, expected);
         }
 
         #[test]
-        fn get_events_with_fully_specified_filter() {
-            let (storage, emitted_events) = test_utils::setup_test_storage();
-            let mut connection = storage.connection().unwrap();
+        fn unknown() {
+            let nonce = L1ToL2MessageNonce(starkhash!("07"));
+            let (storage, _) = setup(nonce);
+            let mut connection = storage.writer().unwrap();
             let tx = connection.transaction().unwrap();
 
-            let expected_event = &emitted_events[1];
-            let filter = StarknetEventFilter {
-                from_block: Some(expected_event.block_number),
-                to_block: Some(expected_event.block_number),
-                contract_address: Some(expected_event.from_address),
-                // we're using a key which is present in _all_ events
-                keys: vec![EventKey(starkhash!("deadbeef"))],
-                page_size: test_utils::NUM_EVENTS,
-                page_number: 0,
-            };
+            let other_nonce = L1ToL2MessageNonce(starkhash!("08"));
+            assert!(!L1HandlerOriginsTable::attach_ethereum_origin(
+                &tx,
+                other_nonce,
+                EthereumTransactionHash(H256::from_low_u64_be(0xabc)),
+                EthereumBlockNumber(10)
+            )
+            .unwrap());
+            assert_eq!(
+                L1HandlerOriginsTable::get_l1_handler_by_message(&tx, other_nonce).unwrap(),
+                None
+            );
 
-            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            // Only the L1 handler transaction is indexed.
+            let other_transaction = test_utils::create_transactions_and_receipts()[1].0.hash();
             assert_eq!(
-                events,
-                PageOfEvents {
-                    events: vec![expected_event.clone()],
-                    is_last_page: true,
-                }
+                L1HandlerOriginsTable::get_by_starknet_transaction(&tx, other_transaction).unwrap(),
+                None
             );
         }
 
         #[test]
-        fn events_are_ordered() {
-            // This is a regression test where events were incorrectly ordered by transaction hash
-            // instead of transaction index.
-            //
-            // Events should be ordered by block number, transaction index, event index.
-            use crate::core::StarknetTransactionHash;
-            use crate::sequencer::reply::transaction::Event;
+        fn attach_before_insert() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
 
-            // All events we are storing, arbitrarily use from_address to distinguish them.
-            let expected_events = (0u8..5)
-                .map(|idx| Event {
-                    data: Vec::new(),
-                    keys: Vec::new(),
-                    from_address: ContractAddress::new_or_panic(
-                        StarkHash::from_be_slice(&idx.to_be_bytes()).unwrap(),
-                    ),
+            // L1 sync usually sees the message before L2 sync stores the transaction.
+            let nonce = L1ToL2MessageNonce(starkhash!("07"));
+            let ethereum_transaction = EthereumTransactionHash(H256::from_low_u64_be(0xabc));
+            assert!(!L1HandlerOriginsTable::attach_ethereum_origin(
+                &tx,
+                nonce,
+                ethereum_transaction,
+                EthereumBlockNumber(10)
+            )
+            .unwrap());
+
+            let transaction = insert_block(&tx, 0, nonce);
+            let origin = L1HandlerOriginsTable::get_by_starknet_transaction(&tx, transaction)
+                .unwrap()
+                .unwrap();
+            assert_eq!(origin.ethereum_transaction_hash, Some(ethereum_transaction));
+
+            // Messages of Ethereum blocks which were reorged away are detached again.
+            L1HandlerOriginsTable::ethereum_reorg(&tx, EthereumBlockNumber(11)).unwrap();
+            let origin = L1HandlerOriginsTable::get_by_starknet_transaction(&tx, transaction)
+                .unwrap()
+                .unwrap();
+            assert_eq!(origin.ethereum_transaction_hash, Some(ethereum_transaction));
+
+            L1HandlerOriginsTable::ethereum_reorg(&tx, EthereumBlockNumber(10)).unwrap();
+            let origin = L1HandlerOriginsTable::get_by_starknet_transaction(&tx, transaction)
+                .unwrap()
+                .unwrap();
+            assert_eq!(origin.ethereum_transaction_hash, None);
+        }
+
+        #[test]
+        fn duplicate_nonce_returns_earliest() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            // The later block is stored first, so that insertion order doesn't decide.
+            let nonce = L1ToL2MessageNonce(starkhash!("07"));
+            insert_block(&tx, 1, nonce);
+            let earliest = insert_block(&tx, 0, nonce);
+
+            let origin = L1HandlerOriginsTable::get_l1_handler_by_message(&tx, nonce)
+                .unwrap()
+                .unwrap();
+            assert_eq!(origin.starknet_transaction_hash, earliest);
+        }
+    }
+
+    mod starknet_updates {
+        use super::*;
+        use crate::storage::fixtures::with_n_state_updates;
+
+        #[test]
+        fn compression_levels_interoperate() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let updates = [1, 10, 19]
+                .into_iter()
+                .enumerate()
+                .map(|(n, level)| {
+                    let n = n as u8;
+                    StarknetBlocksTable::insert(&tx, &StarknetBlock::nth(n), None).unwrap();
+                    let update = StateUpdate::with_block_hash(n);
+                    let mut compressor = CompressionConfig {
+                        level,
+                        ..Default::default()
+                    }
+                    .compressor()
+                    .unwrap();
+                    StarknetStateUpdatesTable::insert_with_compressor(
+                        &tx,
+                        &mut compressor,
+                        update.block_hash.unwrap(),
+                        &update,
+                    )
+                    .unwrap();
+                    update
                 })
                 .collect::<Vec<_>>();
 
-            let block = StarknetBlock {
-                number: StarknetBlockNumber::GENESIS,
-                hash: StarknetBlockHash(starkhash!("1234")),
-                root: GlobalRoot(starkhash!("1234")),
-                timestamp: StarknetBlockTimestamp::new_or_panic(0),
-                gas_price: GasPrice(0),
-                sequencer_address: SequencerAddress(starkhash!("1234")),
-            };
+            for expected in updates {
+                let actual = StarknetStateUpdatesTable::get(&tx, expected.block_hash.unwrap())
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(actual, expected);
+            }
+        }
 
-            // Note: hashes are reverse ordered to trigger the sorting bug.
-            let transactions = vec![
-                transaction::Transaction::Invoke(transaction::InvokeTransaction::V0(
-                    transaction::InvokeTransactionV0 {
-                        calldata: vec![],
-                        // Only required because event insert rejects if this is None
-                        contract_address: ContractAddress::new_or_panic(StarkHash::ZERO),
-                        entry_point_type: transaction::EntryPointType::External,
-                        entry_point_selector: EntryPoint(StarkHash::ZERO),
-                        max_fee: Fee(H128::zero()),
-                        signature: vec![],
-                        transaction_hash: StarknetTransactionHash(starkhash!("0F")),
-                    },
-                )),
-                transaction::Transaction::Invoke(transaction::InvokeTransaction::V0(
-                    transaction::InvokeTransactionV0 {
-                        calldata: vec![],
-                        // Only required because event insert rejects if this is None
-                        contract_address: ContractAddress::new_or_panic(StarkHash::ZERO),
-                        entry_point_type: transaction::EntryPointType::External,
-                        entry_point_selector: EntryPoint(StarkHash::ZERO),
-                        max_fee: Fee(H128::zero()),
-                        signature: vec![],
-                        transaction_hash: StarknetTransactionHash(starkhash!("01")),
-                    },
-                )),
-            ];
+        mod get {
+            use super::*;
 
-            let receipts = vec![
-                transaction::Receipt {
-                    actual_fee: None,
-                    events: expected_events[..3].to_vec(),
-                    execution_resources: Some(transaction::ExecutionResources {
-                        builtin_instance_counter:
-                            transaction::execution_resources::BuiltinInstanceCounter::Empty(
-                                transaction::execution_resources::EmptyBuiltinInstanceCounter {},
-                            ),
-                        n_steps: 0,
-                        n_memory_holes: 0,
-                    }),
-                    l1_to_l2_consumed_message: None,
-                    l2_to_l1_messages: Vec::new(),
-                    transaction_hash: transactions[0].hash(),
-                    transaction_index: crate::core::StarknetTransactionIndex::new_or_panic(0),
-                },
-                transaction::Receipt {
-                    actual_fee: None,
-                    events: expected_events[3..].to_vec(),
-                    execution_resources: Some(transaction::ExecutionResources {
-                        builtin_instance_counter:
-                            transaction::execution_resources::BuiltinInstanceCounter::Empty(
-                                transaction::execution_resources::EmptyBuiltinInstanceCounter {},
-                            ),
-                        n_steps: 0,
-                        n_memory_holes: 0,
-                    }),
-                    l1_to_l2_consumed_message: None,
-                    l2_to_l1_messages: Vec::new(),
-                    transaction_hash: transactions[1].hash(),
-                    transaction_index: crate::core::StarknetTransactionIndex::new_or_panic(1),
-                },
-            ];
+            #[test]
+            fn some() {
+                with_n_state_updates(1, |_, tx, state_updates| {
+                    for expected in state_updates {
+                        let actual =
+                            StarknetStateUpdatesTable::get(tx, expected.block_hash.unwrap())
+                                .unwrap()
+                                .unwrap();
+                        assert_eq!(actual, expected);
+                    }
+                })
+            }
+
+            #[test]
+            fn none() {
+                use crate::starkhash;
+                with_n_state_updates(1, |_, tx, _| {
+                    let non_existent = StarknetBlockHash(starkhash!("ff"));
+                    let actual = StarknetStateUpdatesTable::get(tx, non_existent).unwrap();
+                    assert!(actual.is_none());
+                })
+            }
+        }
 
-            let storage = Storage::in_memory().unwrap();
-            let mut connection = storage.connection().unwrap();
+        mod get_latest_raw {
+            use super::*;
+
+            #[test]
+            fn matches_get() {
+                with_n_state_updates(3, |_, tx, state_updates| {
+                    let raw = StarknetStateUpdatesTable::get_latest_raw(tx)
+                        .unwrap()
+                        .unwrap();
+                    let raw = zstd::decode_all(raw.as_slice()).unwrap();
+                    let actual: StateUpdate = serde_json::from_slice(&raw).unwrap();
+
+                    let latest = state_updates.last().unwrap();
+                    let expected = StarknetStateUpdatesTable::get(tx, latest.block_hash.unwrap())
+                        .unwrap()
+                        .unwrap();
+                    assert_eq!(actual, expected);
+                    assert_eq!(&actual, latest);
+                })
+            }
+
+            #[test]
+            fn latest_block_without_update() {
+                with_n_state_updates(1, |_, tx, _| {
+                    StarknetBlocksTable::insert(tx, &StarknetBlock::nth(1), None).unwrap();
+                    let actual = StarknetStateUpdatesTable::get_latest_raw(tx).unwrap();
+                    assert_eq!(actual, None);
+                })
+            }
+
+            #[test]
+            fn empty() {
+                with_n_state_updates(0, |_, tx, _| {
+                    let actual = StarknetStateUpdatesTable::get_latest_raw(tx).unwrap();
+                    assert_eq!(actual, None);
+                })
+            }
+        }
+    }
+
+    mod reorg_preview {
+        use super::*;
+        use crate::starkhash;
+        use crate::storage::{test_utils, ContractCodeTable, StagedBlocksTable};
+
+        fn counts(tx: &Transaction<'_>) -> ReorgPreview {
+            reorg_preview(tx, StarknetBlockNumber::GENESIS).unwrap()
+        }
+
+        #[test]
+        fn matches_deleted_rows() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.writer().unwrap();
             let tx = connection.transaction().unwrap();
 
-            StarknetBlocksTable::insert(&tx, &block, None).unwrap();
-            CanonicalBlocksTable::insert(&tx, block.number, block.hash).unwrap();
-            StarknetTransactionsTable::upsert(
+            let block = StarknetBlocksTable::get(&tx, StarknetBlockNumber::new_or_panic(1).into())
+                .unwrap()
+                .unwrap();
+            ContractCodeTable::insert(&tx, ClassHash(starkhash!("c1")), b"", b"", b"").unwrap();
+            ContractClassIndexTable::insert(
                 &tx,
-                block.hash,
                 block.number,
-                &vec![
-                    (transactions[0].clone(), receipts[0].clone()),
-                    (transactions[1].clone(), receipts[1].clone()),
-                ],
+                ContractAddress::new_or_panic(starkhash!("a1")),
+                ClassHash(starkhash!("c1")),
             )
             .unwrap();
+            for number in [3, 7] {
+                tx.execute(
+                    "INSERT INTO staged_blocks (number, data) VALUES (?, x'00')",
+                    [number],
+                )
+                .unwrap();
+            }
 
-            let addresses = StarknetEventsTable::get_events(
-                &tx,
-                &StarknetEventFilter {
-                    from_block: None,
-                    to_block: None,
-                    contract_address: None,
-                    keys: vec![],
-                    page_size: 1024,
-                    page_number: 0,
-                },
-            )
-            .unwrap()
-            .events
-            .iter()
-            .map(|e| e.from_address)
-            .collect::<Vec<_>>();
+            let tail = StarknetBlockNumber::new_or_panic(1);
+            let before = counts(&tx);
+            let preview = reorg_preview(&tx, tail).unwrap();
+
+            // Previewing doesn't delete anything.
+            assert_eq!(counts(&tx), before);
+
+            StarknetEventsTable::reorg(&tx, tail).unwrap();
+            CanonicalBlocksTable::reorg(&tx, tail).unwrap();
+            ContractClassIndexTable::reorg(&tx, tail).unwrap();
+            StarknetBlocksTable::reorg(&tx, tail).unwrap();
+            StagedBlocksTable::reorg(&tx, tail).unwrap();
+
+            let after = counts(&tx);
+            let deleted = ReorgPreview {
+                blocks: before.blocks - after.blocks,
+                canonical_blocks: before.canonical_blocks - after.canonical_blocks,
+                transactions: before.transactions - after.transactions,
+                events: before.events - after.events,
+                contract_classes: before.contract_classes - after.contract_classes,
+                staged_blocks: before.staged_blocks - after.staged_blocks,
+            };
+            assert_eq!(preview, deleted);
+            assert_eq!(
+                preview,
+                ReorgPreview {
+                    blocks: test_utils::NUM_BLOCKS - 1,
+                    canonical_blocks: test_utils::NUM_BLOCKS - 1,
+                    transactions: (test_utils::NUM_BLOCKS - 1) * test_utils::TRANSACTIONS_PER_BLOCK,
+                    events: (test_utils::NUM_BLOCKS - 1) * test_utils::EVENTS_PER_BLOCK,
+                    contract_classes: 1,
+                    staged_blocks: 2,
+                }
+            );
+        }
 
-            let expected = expected_events
+        #[test]
+        fn past_the_head() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let preview = reorg_preview(&tx, StarknetBlockNumber::new_or_panic(100)).unwrap();
+            assert_eq!(preview, ReorgPreview::default());
+        }
+    }
+
+    mod get_block_with_receipts {
+        use super::*;
+        use crate::starkhash;
+        use crate::storage::test_utils;
+
+        #[test]
+        fn many_transactions() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let blocks = test_utils::create_blocks();
+            let transactions = test_utils::create_transactions_and_receipts();
+            let expected = transactions[test_utils::TRANSACTIONS_PER_BLOCK..]
                 .iter()
-                .map(|e| e.from_address)
+                .take(test_utils::TRANSACTIONS_PER_BLOCK)
+                .cloned()
+                .enumerate()
+                .map(|(idx, (transaction, receipt))| (transaction, receipt, idx))
                 .collect::<Vec<_>>();
 
-            assert_eq!(addresses, expected);
+            for block_id in [blocks[1].number.into(), blocks[1].hash.into()] {
+                let result = get_block_with_receipts(&tx, block_id).unwrap().unwrap();
+                assert_eq!(result.block, blocks[1]);
+                assert_eq!(result.transactions, expected);
+                assert!(!result.accepted_on_l1);
+            }
+
+            let latest = get_block_with_receipts(&tx, StarknetBlocksBlockId::Latest)
+                .unwrap()
+                .unwrap();
+            assert_eq!(latest.block, blocks[test_utils::NUM_BLOCKS - 1]);
         }
 
         #[test]
-        fn get_events_by_block() {
-            let (storage, emitted_events) = test_utils::setup_test_storage();
-            let mut connection = storage.connection().unwrap();
+        fn no_transactions() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.writer().unwrap();
             let tx = connection.transaction().unwrap();
 
-            const BLOCK_NUMBER: usize = 2;
-            let filter = StarknetEventFilter {
-                from_block: Some(StarknetBlockNumber::new_or_panic(BLOCK_NUMBER as u64)),
-                to_block: Some(StarknetBlockNumber::new_or_panic(BLOCK_NUMBER as u64)),
-                contract_address: None,
-                keys: vec![],
-                page_size: test_utils::NUM_EVENTS,
-                page_number: 0,
+            let latest = StarknetBlocksTable::get(&tx, StarknetBlocksBlockId::Latest)
+                .unwrap()
+                .unwrap();
+            let block = StarknetBlock {
+                number: latest.number + 1,
+                hash: StarknetBlockHash(starkhash!("0abcdef0")),
+                parent_hash: latest.hash,
+                transaction_count: 0,
+                event_count: 0,
+                ..latest
             };
+            StarknetBlocksTable::insert(&tx, &block, Some("0.10.1")).unwrap();
 
-            let expected_events = &emitted_events[test_utils::EVENTS_PER_BLOCK * BLOCK_NUMBER
-                ..test_utils::EVENTS_PER_BLOCK * (BLOCK_NUMBER + 1)];
-            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            let result = get_block_with_receipts(&tx, block.number.into())
+                .unwrap()
+                .unwrap();
             assert_eq!(
-                events,
-                PageOfEvents {
-                    events: expected_events.to_vec(),
-                    is_last_page: true,
+                result,
+                BlockWithReceipts {
+                    block,
+                    version: Some("0.10.1".to_owned()),
+                    accepted_on_l1: false,
+                    transactions: Vec::new(),
                 }
             );
         }
 
         #[test]
-        fn get_events_up_to_block() {
-            let (storage, emitted_events) = test_utils::setup_test_storage();
-            let mut connection = storage.connection().unwrap();
+        fn status() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.writer().unwrap();
             let tx = connection.transaction().unwrap();
 
-            const UNTIL_BLOCK_NUMBER: usize = 2;
-            let filter = StarknetEventFilter {
-                from_block: None,
-                to_block: Some(StarknetBlockNumber::new_or_panic(UNTIL_BLOCK_NUMBER as u64)),
-                contract_address: None,
-                keys: vec![],
-                page_size: test_utils::NUM_EVENTS,
-                page_number: 0,
-            };
+            RefsTable::set_l1_l2_head(&tx, Some(StarknetBlockNumber::new_or_panic(1))).unwrap();
 
-            let expected_events =
-                &emitted_events[..test_utils::EVENTS_PER_BLOCK * (UNTIL_BLOCK_NUMBER + 1)];
-            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
-            assert_eq!(
-                events,
-                PageOfEvents {
-                    events: expected_events.to_vec(),
-                    is_last_page: true,
-                }
-            );
+            let accepted_on_l1 = |number| {
+                get_block_with_receipts(&tx, StarknetBlockNumber::new_or_panic(number).into())
+                    .unwrap()
+                    .unwrap()
+                    .accepted_on_l1
+            };
+            assert!(accepted_on_l1(0));
+            assert!(accepted_on_l1(1));
+            assert!(!accepted_on_l1(2));
         }
 
         #[test]
-        fn get_events_from_block_onwards() {
-            let (storage, emitted_events) = test_utils::setup_test_storage();
+        fn unknown_block() {
+            let (storage, _) = test_utils::setup_test_storage();
             let mut connection = storage.connection().unwrap();
             let tx = connection.transaction().unwrap();
 
-            const FROM_BLOCK_NUMBER: usize = 2;
-            let filter = StarknetEventFilter {
-                from_block: Some(StarknetBlockNumber::new_or_panic(FROM_BLOCK_NUMBER as u64)),
-                to_block: None,
-                contract_address: None,
-                keys: vec![],
-                page_size: test_utils::NUM_EVENTS,
-                page_number: 0,
-            };
+            let result =
+                get_block_with_receipts(&tx, StarknetBlockNumber::new_or_panic(100).into())
+                    .unwrap();
+            assert_eq!(result, None);
 
-            let expected_events =
-                &emitted_events[test_utils::EVENTS_PER_BLOCK * FROM_BLOCK_NUMBER..];
-            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
-            assert_eq!(
-                events,
-                PageOfEvents {
-                    events: expected_events.to_vec(),
-                    is_last_page: true,
-                }
-            );
+            let result =
+                get_block_with_receipts(&tx, StarknetBlockHash(starkhash!("dead")).into()).unwrap();
+            assert_eq!(result, None);
+
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
+            let result = get_block_with_receipts(&tx, StarknetBlocksBlockId::Latest).unwrap();
+            assert_eq!(result, None);
+        }
+    }
+
+    mod consistency_check {
+        use super::*;
+        use crate::storage::test_utils;
+
+        fn latest(tx: &Transaction<'_>) -> Option<StarknetBlockNumber> {
+            StarknetBlocksTable::get_latest_number(tx).unwrap()
         }
 
         #[test]
-        fn get_events_from_contract() {
-            let (storage, emitted_events) = test_utils::setup_test_storage();
+        fn consistent() {
+            let (storage, _) = test_utils::setup_test_storage();
             let mut connection = storage.connection().unwrap();
             let tx = connection.transaction().unwrap();
 
-            let expected_event = &emitted_events[33];
+            let report = consistency_check(&tx).unwrap();
+            assert!(report.is_consistent());
+            assert_eq!(report.first_inconsistent_block(), None);
 
-            let filter = StarknetEventFilter {
-                from_block: None,
-                to_block: None,
-                contract_address: Some(expected_event.from_address),
-                keys: vec![],
-                page_size: test_utils::NUM_EVENTS,
-                page_number: 0,
-            };
+            report.repair(&tx).unwrap();
+            assert_eq!(latest(&tx), Some(StarknetBlockNumber::new_or_panic(3)));
+        }
 
-            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
-            assert_eq!(
-                events,
-                PageOfEvents {
-                    events: vec![expected_event.clone()],
-                    is_last_page: true,
-                }
-            );
+        #[test]
+        fn not_canonical() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            tx.execute("DELETE FROM canonical_blocks WHERE number = 2", [])
+                .unwrap();
+
+            let report = consistency_check(&tx).unwrap();
+            let expected = StarknetBlockNumber::new_or_panic(2);
+            assert_eq!(report.not_canonical, vec![expected]);
+            assert_eq!(report.first_inconsistent_block(), Some(expected));
+
+            report.repair(&tx).unwrap();
+            assert_eq!(latest(&tx), Some(StarknetBlockNumber::new_or_panic(1)));
+            assert!(consistency_check(&tx).unwrap().is_consistent());
         }
 
         #[test]
-        fn get_events_by_key() {
-            let (storage, emitted_events) = test_utils::setup_test_storage();
-            let mut connection = storage.connection().unwrap();
+        fn missing_block() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.writer().unwrap();
+            connection.execute("PRAGMA foreign_keys = OFF", []).unwrap();
             let tx = connection.transaction().unwrap();
 
-            let expected_event = &emitted_events[27];
-            let filter = StarknetEventFilter {
-                from_block: None,
-                to_block: None,
-                contract_address: None,
-                keys: vec![expected_event.keys[0]],
-                page_size: test_utils::NUM_EVENTS,
-                page_number: 0,
-            };
+            tx.execute("DELETE FROM starknet_blocks WHERE number = 1", [])
+                .unwrap();
 
-            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
-            assert_eq!(
-                events,
-                PageOfEvents {
-                    events: vec![expected_event.clone()],
-                    is_last_page: true,
-                }
-            );
+            let report = consistency_check(&tx).unwrap();
+            let expected = StarknetBlockNumber::new_or_panic(1);
+            assert_eq!(report.missing_blocks, vec![expected]);
+            assert_eq!(report.first_inconsistent_block(), Some(expected));
+
+            report.repair(&tx).unwrap();
+            assert_eq!(latest(&tx), Some(StarknetBlockNumber::GENESIS));
+            assert!(consistency_check(&tx).unwrap().is_consistent());
         }
 
         #[test]
-        fn get_events_with_no_filter() {
-            let (storage, emitted_events) = test_utils::setup_test_storage();
-            let mut connection = storage.connection().unwrap();
+        fn missing_transactions() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.writer().unwrap();
             let tx = connection.transaction().unwrap();
 
-            let filter = StarknetEventFilter {
-                from_block: None,
-                to_block: None,
-                contract_address: None,
-                keys: vec![],
-                page_size: test_utils::NUM_EVENTS,
-                page_number: 0,
-            };
+            let block = StarknetBlocksTable::get(&tx, StarknetBlockNumber::new_or_panic(1).into())
+                .unwrap()
+                .unwrap();
+            tx.execute(
+                "DELETE FROM starknet_transactions WHERE block_hash = ?",
+                [block.hash],
+            )
+            .unwrap();
 
-            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
-            assert_eq!(
-                events,
-                PageOfEvents {
-                    events: emitted_events,
-                    is_last_page: true,
-                }
-            );
+            let report = consistency_check(&tx).unwrap();
+            assert_eq!(report.missing_transactions, vec![block.number]);
+            assert_eq!(report.first_inconsistent_block(), Some(block.number));
+
+            report.repair(&tx).unwrap();
+            assert_eq!(latest(&tx), Some(StarknetBlockNumber::GENESIS));
+            assert!(consistency_check(&tx).unwrap().is_consistent());
         }
 
         #[test]
-        fn get_events_with_no_filter_and_paging() {
-            let (storage, emitted_events) = test_utils::setup_test_storage();
-            let mut connection = storage.connection().unwrap();
+        fn transaction_gap() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            // The count doesn't reveal the missing transaction, as if it had been backfilled.
+            let block = StarknetBlocksTable::get(&tx, StarknetBlockNumber::new_or_panic(2).into())
+                .unwrap()
+                .unwrap();
+            tx.execute(
+                "DELETE FROM starknet_transactions WHERE block_hash = ? AND idx = 0",
+                [block.hash],
+            )
+            .unwrap();
+            tx.execute(
+                "UPDATE starknet_blocks SET transaction_count = transaction_count - 1 WHERE hash = ?",
+                [block.hash],
+            )
+            .unwrap();
+
+            let report = consistency_check(&tx).unwrap();
+            assert_eq!(report.missing_transactions, vec![block.number]);
+        }
+
+        #[test]
+        fn l1_l2_head_ahead() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.writer().unwrap();
             let tx = connection.transaction().unwrap();
 
-            let filter = StarknetEventFilter {
-                from_block: None,
-                to_block: None,
-                contract_address: None,
-                keys: vec![],
-                page_size: 10,
-                page_number: 0,
-            };
-            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
-            assert_eq!(
-                events,
-                PageOfEvents {
-                    events: emitted_events[..10].to_vec(),
-                    is_last_page: false,
-                }
-            );
+            let head = StarknetBlockNumber::new_or_panic(10);
+            RefsTable::set_l1_l2_head(&tx, Some(head)).unwrap();
 
-            let filter = StarknetEventFilter {
-                from_block: None,
-                to_block: None,
-                contract_address: None,
-                keys: vec![],
-                page_size: 10,
-                page_number: 1,
-            };
-            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
-            assert_eq!(
-                events,
-                PageOfEvents {
-                    events: emitted_events[10..20].to_vec(),
-                    is_last_page: false,
-                }
-            );
+            let report = consistency_check(&tx).unwrap();
+            assert_eq!(report.l1_l2_head_ahead, Some(head));
+            assert_eq!(report.first_inconsistent_block(), None);
 
-            let filter = StarknetEventFilter {
-                from_block: None,
-                to_block: None,
-                contract_address: None,
-                keys: vec![],
-                page_size: 10,
-                page_number: 3,
-            };
-            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            report.repair(&tx).unwrap();
             assert_eq!(
-                events,
-                PageOfEvents {
-                    events: emitted_events[30..40].to_vec(),
-                    is_last_page: true,
-                }
+                RefsTable::get_l1_l2_head(&tx).unwrap(),
+                Some(StarknetBlockNumber::new_or_panic(3))
             );
+            assert!(consistency_check(&tx).unwrap().is_consistent());
         }
 
         #[test]
-        fn get_events_with_no_filter_and_nonexistent_page() {
+        fn l1_state_ahead_is_consistent() {
             let (storage, _) = test_utils::setup_test_storage();
-            let mut connection = storage.connection().unwrap();
+            let mut connection = storage.writer().unwrap();
             let tx = connection.transaction().unwrap();
 
-            const PAGE_SIZE: usize = 10;
-            let filter = StarknetEventFilter {
-                from_block: None,
-                to_block: None,
-                contract_address: None,
-                keys: vec![],
-                page_size: PAGE_SIZE,
-                // one page _after_ the last one
-                page_number: test_utils::NUM_BLOCKS * test_utils::EVENTS_PER_BLOCK / PAGE_SIZE,
+            let update = StateUpdateLog {
+                origin: EthOrigin {
+                    block: BlockOrigin {
+                        hash: EthereumBlockHash(H256::from_low_u64_le(33)),
+                        number: EthereumBlockNumber(12_000),
+                    },
+                    transaction: TransactionOrigin {
+                        hash: EthereumTransactionHash(H256::from_low_u64_le(999)),
+                        index: EthereumTransactionIndex(20_000),
+                    },
+                    log_index: EthereumLogIndex(500),
+                },
+                global_root: GlobalRoot(StarkHash::from_hex_str("3").unwrap()),
+                block_number: StarknetBlockNumber::new_or_panic(10),
             };
-            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
+            L1StateTable::upsert(&tx, &update).unwrap();
+
+            // L1 is ahead of L2 while L2 sync is catching up.
+            let report = consistency_check(&tx).unwrap();
+            assert!(report.is_consistent());
+
+            // Repairing an inconsistent L2 keeps the L1 state.
+            tx.execute("DELETE FROM canonical_blocks WHERE number = 2", [])
+                .unwrap();
+            consistency_check(&tx).unwrap().repair(&tx).unwrap();
             assert_eq!(
-                events,
-                PageOfEvents {
-                    events: vec![],
-                    is_last_page: true,
-                }
+                L1StateTable::get(&tx, L1TableBlockId::Latest).unwrap(),
+                Some(update)
             );
         }
+    }
+
+    mod get_l1_confirmed_head {
+        use super::*;
+        use crate::storage::test_utils;
+
+        fn update(block: &StarknetBlock) -> StateUpdateLog {
+            StateUpdateLog {
+                origin: EthOrigin {
+                    block: BlockOrigin {
+                        hash: EthereumBlockHash(H256::from_low_u64_le(33)),
+                        number: EthereumBlockNumber(12_000),
+                    },
+                    transaction: TransactionOrigin {
+                        hash: EthereumTransactionHash(H256::from_low_u64_le(999)),
+                        index: EthereumTransactionIndex(20_000),
+                    },
+                    log_index: EthereumLogIndex(500),
+                },
+                global_root: block.root,
+                block_number: block.number,
+            }
+        }
 
         #[test]
-        fn get_events_with_invalid_page_size() {
+        fn head() {
             let (storage, _) = test_utils::setup_test_storage();
-            let mut connection = storage.connection().unwrap();
+            let mut connection = storage.writer().unwrap();
             let tx = connection.transaction().unwrap();
 
-            let filter = StarknetEventFilter {
-                from_block: None,
-                to_block: None,
-                contract_address: None,
-                keys: vec![],
-                page_size: 0,
-                page_number: 0,
-            };
-            let result = StarknetEventsTable::get_events(&tx, &filter);
-            assert!(result.is_err());
-            assert_eq!(result.unwrap_err().to_string(), "Invalid page size");
+            let number = StarknetBlockNumber::new_or_panic(2);
+            let block = StarknetBlocksTable::get(&tx, number.into())
+                .unwrap()
+                .unwrap();
+            let proof = update(&block);
+            L1StateTable::upsert(&tx, &proof).unwrap();
+            RefsTable::set_l1_l2_head(&tx, Some(number)).unwrap();
 
-            let filter = StarknetEventFilter {
-                from_block: None,
-                to_block: None,
-                contract_address: None,
-                keys: vec![],
-                page_size: StarknetEventsTable::PAGE_SIZE_LIMIT + 1,
-                page_number: 0,
-            };
-            let result = StarknetEventsTable::get_events(&tx, &filter);
-            assert!(result.is_err());
-            assert_eq!(
-                result.unwrap_err().downcast::<EventFilterError>().unwrap(),
-                EventFilterError::PageSizeTooBig(StarknetEventsTable::PAGE_SIZE_LIMIT)
-            );
+            let head = get_l1_confirmed_head(&tx).unwrap();
+            assert_eq!(head, Some(L1ConfirmedHead { block, proof }));
         }
 
         #[test]
-        fn get_events_by_key_with_paging() {
-            let (storage, emitted_events) = test_utils::setup_test_storage();
+        fn no_head() {
+            let (storage, _) = test_utils::setup_test_storage();
             let mut connection = storage.connection().unwrap();
             let tx = connection.transaction().unwrap();
 
-            let expected_events = &emitted_events[27..32];
-            let keys_for_expected_events: Vec<_> =
-                expected_events.iter().map(|e| e.keys[0]).collect();
+            assert_eq!(get_l1_confirmed_head(&tx).unwrap(), None);
+        }
 
-            let filter = StarknetEventFilter {
-                from_block: None,
-                to_block: None,
-                contract_address: None,
-                keys: keys_for_expected_events.clone(),
-                page_size: 2,
-                page_number: 0,
-            };
-            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
-            assert_eq!(
-                events,
-                PageOfEvents {
-                    events: expected_events[..2].to_vec(),
-                    is_last_page: false,
-                }
-            );
+        #[test]
+        fn missing_rows() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
 
-            let filter = StarknetEventFilter {
-                from_block: None,
-                to_block: None,
-                contract_address: None,
-                keys: keys_for_expected_events.clone(),
-                page_size: 2,
-                page_number: 1,
-            };
-            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
-            assert_eq!(
-                events,
-                PageOfEvents {
-                    events: expected_events[2..4].to_vec(),
-                    is_last_page: false,
-                }
-            );
+            // The L1 state update is missing.
+            let number = StarknetBlockNumber::new_or_panic(2);
+            RefsTable::set_l1_l2_head(&tx, Some(number)).unwrap();
+            assert_eq!(get_l1_confirmed_head(&tx).unwrap(), None);
 
-            let filter = StarknetEventFilter {
-                from_block: None,
-                to_block: None,
-                contract_address: None,
-                keys: keys_for_expected_events,
-                page_size: 2,
-                page_number: 2,
+            // The block is missing.
+            let block = StarknetBlock {
+                number: StarknetBlockNumber::new_or_panic(100),
+                ..StarknetBlocksTable::get(&tx, number.into())
+                    .unwrap()
+                    .unwrap()
             };
-            let events = StarknetEventsTable::get_events(&tx, &filter).unwrap();
-            assert_eq!(
-                events,
-                PageOfEvents {
-                    events: expected_events[4..].to_vec(),
-                    is_last_page: true,
-                }
-            );
+            L1StateTable::upsert(&tx, &update(&block)).unwrap();
+            RefsTable::set_l1_l2_head(&tx, Some(block.number)).unwrap();
+            assert_eq!(get_l1_confirmed_head(&tx).unwrap(), None);
+        }
+    }
+
+    mod verify_block_root {
+        use super::*;
+        use crate::state::state_tree::GlobalStateTree;
+
+        fn update(block_number: StarknetBlockNumber, global_root: GlobalRoot) -> StateUpdateLog {
+            StateUpdateLog {
+                origin: EthOrigin {
+                    block: BlockOrigin {
+                        hash: EthereumBlockHash(H256::from_low_u64_le(33)),
+                        number: EthereumBlockNumber(12_000 + block_number.get()),
+                    },
+                    transaction: TransactionOrigin {
+                        hash: EthereumTransactionHash(H256::from_low_u64_le(999)),
+                        index: EthereumTransactionIndex(20_000),
+                    },
+                    log_index: EthereumLogIndex(500),
+                },
+                global_root,
+                block_number,
+            }
         }
 
         #[test]
-        fn event_count_by_block() {
-            let (storage, _) = test_utils::setup_test_storage();
+        fn fixture_roots_verify() {
+            let storage = crate::rpc::tests::setup_storage();
             let mut connection = storage.connection().unwrap();
             let tx = connection.transaction().unwrap();
 
-            let block = Some(StarknetBlockNumber::new_or_panic(2));
-
-            let count = StarknetEventsTable::event_count(&tx, block, block, None, vec![]).unwrap();
-            assert_eq!(count, test_utils::EVENTS_PER_BLOCK);
+            for number in 0..=2 {
+                let number = StarknetBlockNumber::new_or_panic(number);
+                assert!(verify_block_root(&tx, number).unwrap(), "block {number}");
+            }
         }
 
         #[test]
-        fn event_count_from_contract() {
-            let (storage, events) = test_utils::setup_test_storage();
-            let mut connection = storage.connection().unwrap();
+        fn tampered_contract_state() {
+            let storage = crate::rpc::tests::setup_storage();
+            let mut connection = storage.writer().unwrap();
             let tx = connection.transaction().unwrap();
 
-            let addr = events[0].from_address;
-            let expected = events
-                .iter()
-                .filter(|event| event.from_address == addr)
-                .count();
-
-            let count = StarknetEventsTable::event_count(
-                &tx,
-                Some(StarknetBlockNumber::GENESIS),
-                Some(StarknetBlockNumber::MAX),
-                Some(addr),
-                vec![],
+            let latest = StarknetBlockNumber::new_or_panic(2);
+            let root = StarknetBlocksTable::get_root(&tx, latest.into())
+                .unwrap()
+                .unwrap();
+            let contract = ContractAddress::new_or_panic(crate::starkhash_bytes!(b"contract 0"));
+            let state_hash = GlobalStateTree::load(&tx, root)
+                .unwrap()
+                .get(contract)
+                .unwrap();
+
+            tx.execute(
+                "UPDATE contract_states SET nonce = ? WHERE state_hash = ?",
+                params![
+                    ContractNonce(StarkHash::from_hex_str("bad").unwrap()),
+                    state_hash
+                ],
             )
             .unwrap();
-            assert_eq!(count, expected);
+
+            assert!(!verify_block_root(&tx, latest).unwrap());
         }
 
         #[test]
-        fn event_count_by_key() {
-            let (storage, emitted_events) = test_utils::setup_test_storage();
+        fn unknown_block() {
+            let storage = crate::rpc::tests::setup_storage();
             let mut connection = storage.connection().unwrap();
             let tx = connection.transaction().unwrap();
 
-            let key = emitted_events[27].keys[0];
-            let expected = emitted_events
-                .iter()
-                .filter(|event| event.keys.contains(&key))
-                .count();
+            verify_block_root(&tx, StarknetBlockNumber::new_or_panic(3)).unwrap_err();
+        }
 
-            let count = StarknetEventsTable::event_count(
-                &tx,
-                Some(StarknetBlockNumber::GENESIS),
-                Some(StarknetBlockNumber::MAX),
-                None,
-                vec![key],
-            )
-            .unwrap();
-            assert_eq!(count, expected);
+        #[test]
+        fn l1_roots() {
+            let storage = crate::rpc::tests::setup_storage();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let genesis = StarknetBlockNumber::GENESIS;
+            let block1 = StarknetBlockNumber::new_or_panic(1);
+            let block2 = StarknetBlockNumber::new_or_panic(2);
+
+            let root1 = StarknetBlocksTable::get_root(&tx, block1.into())
+                .unwrap()
+                .unwrap();
+            L1StateTable::upsert(&tx, &update(block1, root1)).unwrap();
+            let different = GlobalRoot(StarkHash::from_hex_str("3").unwrap());
+            L1StateTable::upsert(&tx, &update(block2, different)).unwrap();
+
+            assert_eq!(roots_agree(&tx, genesis).unwrap(), None);
+            assert_eq!(roots_agree(&tx, block1).unwrap(), Some(true));
+            assert_eq!(roots_agree(&tx, block2).unwrap(), Some(false));
+            assert_eq!(
+                roots_agree(&tx, StarknetBlockNumber::new_or_panic(3)).unwrap(),
+                None
+            );
         }
     }
 
-    mod starknet_updates {
+    mod storage_integrity {
         use super::*;
-        use crate::storage::fixtures::with_n_state_updates;
+        use crate::storage::test_utils;
 
-        mod get {
-            use super::*;
+        #[test]
+        fn no_orphans() {
+            let (storage, _) = test_utils::setup_test_storage();
+            let mut connection = storage.connection().unwrap();
+            let tx = connection.transaction().unwrap();
 
-            #[test]
-            fn some() {
-                with_n_state_updates(1, |_, tx, state_updates| {
-                    for expected in state_updates {
-                        let actual =
-                            StarknetStateUpdatesTable::get(tx, expected.block_hash.unwrap())
-                                .unwrap()
-                                .unwrap();
-                        assert_eq!(actual, expected);
-                    }
-                })
-            }
+            let orphans = StorageIntegrity::find_orphan_events(&tx).unwrap();
+            assert!(orphans.is_empty());
+        }
 
-            #[test]
-            fn none() {
-                use crate::starkhash;
-                with_n_state_updates(1, |_, tx, _| {
-                    let non_existent = StarknetBlockHash(starkhash!("ff"));
-                    let actual = StarknetStateUpdatesTable::get(tx, non_existent).unwrap();
-                    assert!(actual.is_none());
-                })
-            }
+        #[test]
+        fn deleted_transaction() {
+            let (storage, events) = test_utils::setup_test_storage();
+            let mut connection = storage.writer().unwrap();
+            let tx = connection.transaction().unwrap();
+
+            let orphan = events[3].transaction_hash;
+            tx.execute("DELETE FROM starknet_transactions WHERE hash = ?", [orphan])
+                .unwrap();
+
+            let orphans = StorageIntegrity::find_orphan_events(&tx).unwrap();
+            assert_eq!(orphans, vec![orphan]);
         }
     }
 }
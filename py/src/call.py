@@ -19,7 +19,7 @@ except ModuleNotFoundError:
 
 
 # used from tests, and the query which asserts that the schema is of expected version.
-EXPECTED_SCHEMA_REVISION = 21
+EXPECTED_SCHEMA_REVISION = 48
 EXPECTED_CAIRO_VERSION = "0.10.0"
 SUPPORTED_COMMANDS = frozenset(["call", "estimate_fee"])
 
@@ -519,7 +519,10 @@ def resolve_block(connection, at_block, forced_gas_price):
         # allow caller to override any; see rust side's GasPriceSource for more rationale
         gas_price = forced_gas_price
 
-    sequencer_address = int.from_bytes(sequencer_address, "big")
+    # blocks without a sequencer address are executed as if it were zero
+    sequencer_address = (
+        0 if sequencer_address is None else int.from_bytes(sequencer_address, "big")
+    )
 
     return (
         BlockInfo(